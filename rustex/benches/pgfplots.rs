@@ -0,0 +1,35 @@
+//! Times compiling `test/pgfplots.tex` (plots with thousands of points). Run with
+//! `cargo bench --bench pgfplots`; requires a TeX installation, like the tests.
+
+use path_dedot::*;
+use rustex_lib::engine::{RusTeXEngine, RusTeXEngineT, Settings};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tex_engine::utils::PWD;
+
+const RUNS: u32 = 5;
+
+fn main() {
+    let testpath: PathBuf = PWD
+        .join("../test/pgfplots.tex")
+        .parse_dot()
+        .unwrap()
+        .to_path_buf();
+    RusTeXEngine::initialize(false);
+    let mut times = Vec::new();
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let ret = RusTeXEngine::do_file(testpath.to_str().unwrap(), Settings::default());
+        times.push(start.elapsed());
+        assert!(ret.error.is_none(), "{:?}", ret.error.map(|(e, _)| e));
+    }
+    times.sort();
+    let total: Duration = times.iter().sum();
+    println!(
+        "pgfplots.tex: median {:?}, mean {:?}, min {:?}, max {:?} ({RUNS} runs)",
+        times[times.len() / 2],
+        total / RUNS,
+        times[0],
+        times[times.len() - 1]
+    );
+}
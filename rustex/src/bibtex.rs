@@ -0,0 +1,500 @@
+//! A small BibTeX replacement: reads the `\citation`/`\bibdata`/`\bibstyle` entries LaTeX
+//! writes to the `.aux` files, resolves them against `.bib` databases and generates a `.bbl`
+//! file (a `thebibliography` environment) for the next pass, so that `\cite` produces resolved
+//! references instead of question marks. Entries are formatted roughly like the `plain`
+//! style (numeric labels); `unsrt` keeps citation order, all other styles sort by author.
+//!
+//! `.bbl` files generated here start with [`BBL_HEADER`] and are regenerated in every pass;
+//! any other existing `.bbl` (e.g. generated by BibTeX or biber) takes precedence.
+//! Documents using biblatex (recognizable by `\abx@aux@...` entries or the `biblatex`
+//! bibliography style) are left alone, since biblatex expects the `.bbl` format of biber.
+
+use crate::engine::files::{AuxFiles, RusTeXFileSystem};
+use std::fmt::Write;
+use std::path::Path;
+
+/// The first line of every `.bbl` file generated by [`make_bbl`].
+pub const BBL_HEADER: &str = "% generated by RusTeX";
+
+/// Adds `<jobname>.bbl` to `aux` if the `.aux` files request a bibliography and there is no
+/// `.bbl` yet that was not generated by us, neither in `aux` nor on disk. The `.bib` files
+/// are looked up like TeX input files.
+pub(crate) fn resolve(aux: &mut AuxFiles, fs: &RusTeXFileSystem, jobname: &str) {
+    let bbl = fs.inner.kpse.pwd.join(format!("{jobname}.bbl"));
+    let existing = match aux.get(&bbl) {
+        Some(s) => Some(s.to_string()),
+        None => std::fs::read_to_string(&bbl).ok(),
+    };
+    if existing.is_some_and(|s| !s.starts_with(BBL_HEADER)) {
+        return;
+    }
+    let cites = Citations::from_aux(
+        aux.files
+            .iter()
+            .filter(|(p, _)| p.extension().is_some_and(|e| e == "aux"))
+            .map(|(_, s)| s.as_str()),
+    );
+    if cites.is_empty() || cites.biblatex {
+        return;
+    }
+    let mut entries = Vec::new();
+    for db in &cites.databases {
+        let name = if Path::new(db).extension().is_some_and(|e| e == "bib") {
+            db.clone()
+        } else {
+            format!("{db}.bib")
+        };
+        let found = fs.inner.kpse.kpsewhich(&name);
+        if let Ok(src) = std::fs::read_to_string(&found.path) {
+            entries.extend(parse_bib(&src));
+        } else if let Some(src) = aux.get(&found.path) {
+            entries.extend(parse_bib(src));
+        }
+    }
+    aux.insert(bbl, make_bbl(&cites, &entries));
+}
+
+/// A single entry of a `.bib` file. Field names are lowercase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BibEntry {
+    pub kind: String,
+    pub key: String,
+    pub fields: Vec<(String, String)>,
+}
+impl BibEntry {
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == field)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// The bibliography-related data of the `.aux` files of a document.
+#[derive(Debug, Clone, Default)]
+pub struct Citations {
+    /// cited keys in order of first citation; `*` means "all entries"
+    pub keys: Vec<String>,
+    /// the `.bib` files (without extension)
+    pub databases: Vec<String>,
+    pub style: Option<String>,
+    /// whether the document uses biblatex, whose `.bbl` files we can not generate
+    pub biblatex: bool,
+}
+impl Citations {
+    /// Collects `\citation`, `\bibdata` and `\bibstyle` lines from the given `.aux` contents.
+    pub fn from_aux<'a, I: IntoIterator<Item = &'a str>>(aux: I) -> Self {
+        let mut ret = Self::default();
+        for src in aux {
+            for line in src.lines() {
+                if let Some(arg) = command_arg(line, "\\citation") {
+                    for k in arg.split(',').map(str::trim) {
+                        if !k.is_empty() && !ret.keys.iter().any(|e| e == k) {
+                            ret.keys.push(k.to_string());
+                        }
+                    }
+                } else if let Some(arg) = command_arg(line, "\\bibdata") {
+                    ret.databases
+                        .extend(arg.split(',').map(|s| s.trim().to_string()));
+                } else if let Some(arg) = command_arg(line, "\\bibstyle") {
+                    ret.biblatex |= arg.trim() == "biblatex";
+                    ret.style = Some(arg.trim().to_string());
+                } else if line.trim_start().starts_with("\\abx@aux@") {
+                    ret.biblatex = true;
+                }
+            }
+        }
+        ret
+    }
+    /// Whether the document asks for a bibliography at all.
+    pub fn is_empty(&self) -> bool {
+        self.databases.is_empty()
+    }
+}
+
+pub(crate) fn command_arg<'a>(line: &'a str, cmd: &str) -> Option<&'a str> {
+    line.trim()
+        .strip_prefix(cmd)?
+        .strip_prefix('{')?
+        .rsplit_once('}')
+        .map(|(a, _)| a)
+}
+
+/// Parses the entries of a `.bib` file, expanding `@string` abbreviations and `#`
+/// concatenation. Malformed entries are skipped.
+pub fn parse_bib(src: &str) -> Vec<BibEntry> {
+    let mut strings: Vec<(String, String)> = MONTHS
+        .iter()
+        .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+        .collect();
+    let mut ret = Vec::new();
+    let mut p = Parser { s: src, pos: 0 };
+    while let Some(i) = p.rest().find('@') {
+        p.pos += i + 1;
+        let kind = p.ident().to_ascii_lowercase();
+        p.ws();
+        let close = match p.peek() {
+            Some('{') => '}',
+            Some('(') => ')',
+            _ => continue,
+        };
+        p.pos += 1;
+        match kind.as_str() {
+            "comment" | "preamble" => {
+                p.pos -= 1;
+                p.skip_group();
+            }
+            "string" => {
+                p.ws();
+                let name = p.ident().to_ascii_lowercase();
+                p.ws();
+                if p.peek() == Some('=') {
+                    p.pos += 1;
+                    let v = p.value(&strings);
+                    strings.push((name, v));
+                }
+            }
+            _ => {
+                p.ws();
+                let key_len = p
+                    .rest()
+                    .find(|c: char| c == ',' || c == close || c.is_whitespace())
+                    .unwrap_or(p.rest().len());
+                let key = p.rest()[..key_len].to_string();
+                p.pos += key_len;
+                let mut fields = Vec::new();
+                loop {
+                    p.ws();
+                    match p.peek() {
+                        Some(',') => p.pos += 1,
+                        Some(c) if c == close => {
+                            p.pos += 1;
+                            break;
+                        }
+                        None => break,
+                        _ => (),
+                    }
+                    p.ws();
+                    let name = p.ident().to_ascii_lowercase();
+                    if name.is_empty() {
+                        if p.peek() == Some(close) {
+                            continue;
+                        }
+                        break;
+                    }
+                    p.ws();
+                    if p.peek() != Some('=') {
+                        break;
+                    }
+                    p.pos += 1;
+                    let v = p.value(&strings);
+                    fields.push((name, v.split_whitespace().collect::<Vec<_>>().join(" ")));
+                }
+                if !key.is_empty() {
+                    ret.push(BibEntry { kind, key, fields });
+                }
+            }
+        }
+    }
+    ret
+}
+
+const MONTHS: [(&str, &str); 12] = [
+    ("jan", "January"),
+    ("feb", "February"),
+    ("mar", "March"),
+    ("apr", "April"),
+    ("may", "May"),
+    ("jun", "June"),
+    ("jul", "July"),
+    ("aug", "August"),
+    ("sep", "September"),
+    ("oct", "October"),
+    ("nov", "November"),
+    ("dec", "December"),
+];
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.s[self.pos..]
+    }
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+    fn ws(&mut self) {
+        let r = self.rest();
+        self.pos += r.len() - r.trim_start().len();
+    }
+    fn ident(&mut self) -> &str {
+        let start = self.pos;
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_alphanumeric() || "_-:.+/".contains(c)))
+            .unwrap_or(self.rest().len());
+        self.pos += len;
+        &self.s[start..start + len]
+    }
+    /// Skips a braced or parenthesized group starting at the current position.
+    fn skip_group(&mut self) {
+        let mut depth = 0;
+        for (i, c) in self.rest().char_indices() {
+            match c {
+                '{' | '(' => depth += 1,
+                '}' | ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos += i + 1;
+                        return;
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.pos = self.s.len();
+    }
+    /// A field value: `{...}`, `"..."`, a number or a `@string` name, possibly
+    /// concatenated with `#`.
+    fn value(&mut self, strings: &[(String, String)]) -> String {
+        let mut ret = String::new();
+        loop {
+            self.ws();
+            match self.peek() {
+                Some('{') => {
+                    let start = self.pos + 1;
+                    self.skip_braces();
+                    ret.push_str(&self.s[start..self.pos.saturating_sub(1).max(start)]);
+                }
+                Some('"') => {
+                    self.pos += 1;
+                    let start = self.pos;
+                    let mut depth = 0;
+                    let mut end = self.s.len();
+                    for (i, c) in self.rest().char_indices() {
+                        match c {
+                            '{' => depth += 1,
+                            '}' => depth -= 1,
+                            '"' if depth == 0 => {
+                                end = start + i;
+                                break;
+                            }
+                            _ => (),
+                        }
+                    }
+                    ret.push_str(&self.s[start..end]);
+                    self.pos = (end + 1).min(self.s.len());
+                }
+                Some(_) => {
+                    let id = self.ident().to_ascii_lowercase();
+                    if id.chars().all(|c| c.is_ascii_digit()) {
+                        ret.push_str(&id);
+                    } else if let Some((_, v)) = strings.iter().rev().find(|(k, _)| *k == id) {
+                        ret.push_str(v);
+                    }
+                }
+                None => break,
+            }
+            self.ws();
+            if self.peek() == Some('#') {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        ret
+    }
+    fn skip_braces(&mut self) {
+        let mut depth = 0;
+        for (i, c) in self.rest().char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos += i + 1;
+                        return;
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.pos = self.s.len();
+    }
+}
+
+/// Generates the contents of a `.bbl` file for the given citations, starting with
+/// [`BBL_HEADER`]. Keys that are not found in `entries` are left out (LaTeX will warn about
+/// undefined citations).
+pub fn make_bbl(cites: &Citations, entries: &[BibEntry]) -> String {
+    let mut used: Vec<&BibEntry> = if cites.keys.iter().any(|k| k == "*") {
+        entries.iter().collect()
+    } else {
+        cites
+            .keys
+            .iter()
+            .filter_map(|k| entries.iter().find(|e| e.key.eq_ignore_ascii_case(k)))
+            .collect()
+    };
+    if cites.style.as_deref() != Some("unsrt") {
+        used.sort_by_cached_key(|e| {
+            (
+                e.get("author")
+                    .or_else(|| e.get("editor"))
+                    .map(|a| last_names(a).to_lowercase())
+                    .unwrap_or_default(),
+                e.get("year").unwrap_or_default().to_string(),
+                e.get("title").unwrap_or_default().to_lowercase(),
+            )
+        });
+    }
+    let mut ret = format!("{BBL_HEADER}\n");
+    let _ = writeln!(ret, "\\begin{{thebibliography}}{{{}}}\n", used.len().max(1));
+    for e in used {
+        let _ = writeln!(ret, "\\bibitem{{{}}}\n{}\n", e.key, format_entry(e));
+    }
+    ret.push_str("\\end{thebibliography}\n");
+    ret
+}
+
+/// "Last, First" or "First Last" → "First Last".
+fn display_name(name: &str) -> String {
+    match name.split_once(',') {
+        Some((last, first)) if !first.trim().is_empty() => {
+            format!("{} {}", first.trim(), last.trim())
+        }
+        Some((last, _)) => last.trim().to_string(),
+        None => name.trim().to_string(),
+    }
+}
+
+fn last_names(names: &str) -> String {
+    names
+        .split(" and ")
+        .map(|n| match n.split_once(',') {
+            Some((last, _)) => last.trim().to_string(),
+            None => n.split_whitespace().last().unwrap_or_default().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn format_names(names: &str) -> String {
+    let names: Vec<_> = names.split(" and ").map(display_name).collect();
+    match names.as_slice() {
+        [] => String::new(),
+        [a] => a.clone(),
+        [a, b] => format!("{a} and {b}"),
+        [init @ .., last] => format!("{}, and {last}", init.join(", ")),
+    }
+}
+
+fn format_entry(e: &BibEntry) -> String {
+    let mut blocks = Vec::new();
+    if let Some(a) = e.get("author") {
+        blocks.push(format_names(a));
+    } else if let Some(ed) = e.get("editor") {
+        blocks.push(format!(
+            "{}, editor{}",
+            format_names(ed),
+            if ed.contains(" and ") { "s" } else { "" }
+        ));
+    }
+    let title = e.get("title");
+    let year = e.get("year").map(|y| match e.get("month") {
+        Some(m) => format!("{m} {y}"),
+        None => y.to_string(),
+    });
+    let pages = e
+        .get("pages")
+        .map(|p| p.replace("--", "-").replace('-', "--"));
+    let mut last = Vec::new();
+    match e.kind.as_str() {
+        "article" => {
+            blocks.extend(title.map(str::to_string));
+            let mut j = e
+                .get("journal")
+                .map(|j| format!("{{\\em {j}}}"))
+                .unwrap_or_default();
+            if let Some(v) = e.get("volume") {
+                let _ = write!(j, ", {v}");
+                if let Some(n) = e.get("number") {
+                    let _ = write!(j, "({n})");
+                }
+                if let Some(p) = &pages {
+                    let _ = write!(j, ":{p}");
+                }
+            } else if let Some(p) = &pages {
+                let _ = write!(j, ", pages {p}");
+            }
+            last.push(j);
+        }
+        "book" | "booklet" | "manual" | "proceedings" => {
+            blocks.extend(title.map(|t| format!("{{\\em {t}}}")));
+            last.extend(
+                e.get("publisher")
+                    .or_else(|| e.get("organization"))
+                    .map(str::to_string),
+            );
+            last.extend(e.get("address").map(str::to_string));
+        }
+        "inproceedings" | "incollection" | "conference" | "inbook" => {
+            blocks.extend(title.map(str::to_string));
+            let mut b = e
+                .get("booktitle")
+                .map(|b| format!("In {{\\em {b}}}"))
+                .unwrap_or_default();
+            if let Some(p) = &pages {
+                let _ = write!(b, ", pages {p}");
+            }
+            last.push(b);
+            last.extend(e.get("publisher").map(str::to_string));
+        }
+        "phdthesis" | "mastersthesis" => {
+            blocks.extend(title.map(str::to_string));
+            last.push(
+                if e.kind == "phdthesis" {
+                    "PhD thesis"
+                } else {
+                    "Master's thesis"
+                }
+                .to_string(),
+            );
+            last.extend(e.get("school").map(str::to_string));
+        }
+        "techreport" => {
+            blocks.extend(title.map(str::to_string));
+            let mut t = "Technical Report".to_string();
+            if let Some(n) = e.get("number") {
+                let _ = write!(t, " {n}");
+            }
+            last.push(t);
+            last.extend(e.get("institution").map(str::to_string));
+        }
+        _ => {
+            blocks.extend(title.map(str::to_string));
+            last.extend(e.get("howpublished").map(str::to_string));
+        }
+    }
+    last.extend(year);
+    let last: Vec<_> = last.into_iter().filter(|s| !s.is_empty()).collect();
+    if !last.is_empty() {
+        blocks.push(last.join(", "));
+    }
+    if let Some(n) = e.get("note") {
+        blocks.push(n.to_string());
+    }
+    blocks
+        .into_iter()
+        .map(|b| {
+            if b.ends_with(['.', '?', '!']) {
+                b
+            } else {
+                b + "."
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n\\newblock ")
+}
@@ -0,0 +1,226 @@
+//! Experimental DVI output (see [`Settings::dvi`](crate::engine::Settings::dvi)): the shipped out
+//! pages, laid out as for the [`pdf`](crate::pdf) backend (see [`placement`]), as a DVI file -
+//! so that RusTeX's layout can be inspected with the usual DVI tools (`dvisvgm`, `dviout`,
+//! `dvitype`, ...) and compared with the DVI files of TeX or e-TeX runs.
+//!
+//! Since RusTeX only knows TFM fonts, plain DVI suffices and there is no XDV output. Glyphs are
+//! placed individually (with `put1` rather than `set_char`), and links, images and specials are
+//! not written at all.
+
+use crate::engine::state::RusTeXState;
+use crate::engine::{Font, Refs, Types};
+use crate::placement::{self, Canvas, ONE_INCH};
+use tex_engine::engine::filesystem::kpathsea::Kpathsea;
+use tex_engine::engine::fontsystem::Font as FontT;
+use tex_engine::engine::state::State;
+use tex_engine::tex::nodes::boxes::TeXBox;
+use tex_engine::tex::nodes::NodeTrait;
+
+const PUT1: u8 = 133;
+const PUT_RULE: u8 = 137;
+const BOP: u8 = 139;
+const EOP: u8 = 140;
+const RIGHT4: u8 = 146;
+const DOWN4: u8 = 160;
+const FNT_NUM_0: u8 = 171;
+const FNT1: u8 = 235;
+const FNT_DEF1: u8 = 243;
+const PRE: u8 = 247;
+const POST: u8 = 248;
+const POST_POST: u8 = 249;
+const ID: u8 = 2;
+/// DVI units are sp; `NUM / DEN` converts them to 10^-7 m
+const NUM: u32 = 25_400_000;
+const DEN: u32 = 473_628_672;
+
+/// Collects the shipped out pages during a run; see the [module documentation](self).
+pub(crate) struct DviWriter {
+    out: Vec<u8>,
+    /// the offset of the last `bop`, or `-1`
+    last_bop: i32,
+    pages: u16,
+    fonts: Vec<DviFont>,
+    max_height: i32,
+    max_width: i32,
+}
+
+struct DviFont {
+    /// the name of the `.tfm` file
+    name: String,
+    at: i32,
+    checksum: u32,
+    design_size: i32,
+}
+
+impl Default for DviWriter {
+    fn default() -> Self {
+        let comment = b" RusTeX output";
+        let mut out = vec![PRE, ID];
+        out.extend_from_slice(&NUM.to_be_bytes());
+        out.extend_from_slice(&DEN.to_be_bytes());
+        out.extend_from_slice(&1000u32.to_be_bytes());
+        out.push(comment.len() as u8);
+        out.extend_from_slice(comment);
+        Self {
+            out,
+            last_bop: -1,
+            pages: 0,
+            fonts: Vec::new(),
+            max_height: 0,
+            max_width: 0,
+        }
+    }
+}
+
+impl DviWriter {
+    /// Records a page shipped out with `\shipout`.
+    pub(crate) fn add_page(&mut self, engine: Refs, page: &TeXBox<Types>) {
+        let state: &RusTeXState = &*engine.state;
+        let bop = self.out.len() as i32;
+        self.out.push(BOP);
+        for i in 0..10 {
+            self.out
+                .extend_from_slice(&state.get_int_register(i).to_be_bytes());
+        }
+        self.out.extend_from_slice(&self.last_bop.to_be_bytes());
+        self.last_bop = bop;
+        let mut writer = PageWriter {
+            out: &mut self.out,
+            fonts: &mut self.fonts,
+            kpse: &engine.filesystem.inner.kpse,
+            h: 0,
+            v: 0,
+            font: None,
+        };
+        placement::place(state, page, &mut writer);
+        self.out.push(EOP);
+        self.pages = self.pages.saturating_add(1);
+        self.max_height = self.max_height.max(page.height().0 + page.depth().0);
+        self.max_width = self.max_width.max(page.width().0);
+    }
+
+    /// The DVI file.
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        let post = self.out.len() as i32;
+        self.out.push(POST);
+        self.out.extend_from_slice(&self.last_bop.to_be_bytes());
+        self.out.extend_from_slice(&NUM.to_be_bytes());
+        self.out.extend_from_slice(&DEN.to_be_bytes());
+        self.out.extend_from_slice(&1000u32.to_be_bytes());
+        self.out.extend_from_slice(&self.max_height.to_be_bytes());
+        self.out.extend_from_slice(&self.max_width.to_be_bytes());
+        // we never `push`
+        self.out.extend_from_slice(&0u16.to_be_bytes());
+        self.out.extend_from_slice(&self.pages.to_be_bytes());
+        for (i, f) in self.fonts.iter().enumerate() {
+            f.define(i as u8, &mut self.out);
+        }
+        self.out.push(POST_POST);
+        self.out.extend_from_slice(&post.to_be_bytes());
+        self.out.push(ID);
+        // at least four 223s, up to a multiple of four bytes
+        let padding = 4 + (4 - (self.out.len() + 4) % 4) % 4;
+        self.out.extend(std::iter::repeat_n(223, padding));
+        self.out
+    }
+}
+
+impl DviFont {
+    fn define(&self, k: u8, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[FNT_DEF1, k]);
+        out.extend_from_slice(&self.checksum.to_be_bytes());
+        out.extend_from_slice(&self.at.to_be_bytes());
+        out.extend_from_slice(&self.design_size.to_be_bytes());
+        out.extend_from_slice(&[0, self.name.len() as u8]);
+        out.extend_from_slice(self.name.as_bytes());
+    }
+}
+
+/// The [`Canvas`] for one page; DVI coordinates are relative to TeX's origin.
+struct PageWriter<'a> {
+    out: &'a mut Vec<u8>,
+    fonts: &'a mut Vec<DviFont>,
+    kpse: &'a Kpathsea,
+    h: i32,
+    v: i32,
+    font: Option<usize>,
+}
+
+impl PageWriter<'_> {
+    fn move_to(&mut self, x: i32, y: i32) {
+        let (h, v) = (x - ONE_INCH, y - ONE_INCH);
+        if h != self.h {
+            self.out.push(RIGHT4);
+            self.out.extend_from_slice(&(h - self.h).to_be_bytes());
+            self.h = h;
+        }
+        if v != self.v {
+            self.out.push(DOWN4);
+            self.out.extend_from_slice(&(v - self.v).to_be_bytes());
+            self.v = v;
+        }
+    }
+
+    /// Selects the font, defining it first if it is new. DVI font numbers only go up to 255; if
+    /// a document uses more fonts (at different sizes), the later ones are dropped and this
+    /// returns `false`.
+    fn select(&mut self, font: &Font) -> bool {
+        let name = font.filename();
+        let at = font.get_at().0;
+        let idx = match self.fonts.iter().position(|f| f.name == name && f.at == at) {
+            Some(i) => i,
+            None if self.fonts.len() < 256 => {
+                // the checksum and design size are the first two words of the TFM header
+                let res = self.kpse.kpsewhich(format!("{name}.tfm"));
+                let header = std::fs::read(res.path)
+                    .ok()
+                    .and_then(|tfm| tfm.get(24..32).map(<[u8]>::to_vec))
+                    .unwrap_or_default();
+                let word = |i: usize| {
+                    header
+                        .get(i..i + 4)
+                        .map_or(0, |w| u32::from_be_bytes([w[0], w[1], w[2], w[3]]))
+                };
+                let design_size = match (word(4) as i32) >> 4 {
+                    0 => at,
+                    d => d,
+                };
+                let f = DviFont {
+                    name: name.to_string(),
+                    at,
+                    checksum: word(0),
+                    design_size,
+                };
+                f.define(self.fonts.len() as u8, self.out);
+                self.fonts.push(f);
+                self.fonts.len() - 1
+            }
+            None => return false,
+        };
+        if self.font != Some(idx) {
+            match idx {
+                0..64 => self.out.push(FNT_NUM_0 + idx as u8),
+                _ => self.out.extend_from_slice(&[FNT1, idx as u8]),
+            }
+            self.font = Some(idx);
+        }
+        true
+    }
+}
+
+impl Canvas for PageWriter<'_> {
+    fn glyph(&mut self, x: i32, baseline: i32, font: &Font, char: u8) {
+        if self.select(font) {
+            self.move_to(x, baseline);
+            self.out.extend_from_slice(&[PUT1, char]);
+        }
+    }
+
+    fn rule(&mut self, x: i32, top: i32, width: i32, height: i32) {
+        // rules are placed by their bottom left corner
+        self.move_to(x, top + height);
+        self.out.push(PUT_RULE);
+        self.out.extend_from_slice(&height.to_be_bytes());
+        self.out.extend_from_slice(&width.to_be_bytes());
+    }
+}
@@ -0,0 +1,188 @@
+//! Line breaking for Chinese, Japanese and Korean: CJK text has no spaces to break lines at, so
+//! before a paragraph is broken into lines, a zero-width glue is inserted between any two CJK
+//! characters (as far as the `CJK` package has not already inserted its `\CJKglue`), except where
+//! the prohibition rules (*kinsoku shori*) forbid a break: before closing punctuation, small kana
+//! and the like, and after opening brackets. There, a `\penalty10000` is inserted in front of the
+//! glue instead. CJK text is never hyphenated.
+//!
+//! Characters are recognized by their glyphs, so this works for the Unicode subfonts of the `CJK`
+//! package (see [`FontInfoStore`](tex_glyphs::encodings::FontInfoStore)) as well as for any other
+//! font with a known encoding.
+
+use crate::engine::{Refs, Types};
+use tex_engine::engine::fontsystem::Font as FontT;
+use tex_engine::tex::nodes::horizontal::HNode;
+use tex_engine::tex::numerics::Skip;
+
+/// Whether `c` is an ideograph, kana, hangul or CJK punctuation.
+pub fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x11FF     // Hangul Jamo
+        | 0x2E80..=0x2FDF   // radicals
+        | 0x3000..=0x303F   // CJK symbols and punctuation
+        | 0x3040..=0x30FF   // Hiragana, Katakana
+        | 0x3100..=0x312F   // Bopomofo
+        | 0x3130..=0x318F   // Hangul compatibility Jamo
+        | 0x31F0..=0x31FF   // Katakana phonetic extensions
+        | 0x3400..=0x4DBF   // CJK extension A
+        | 0x4E00..=0x9FFF   // CJK unified ideographs
+        | 0xAC00..=0xD7AF   // Hangul syllables
+        | 0xF900..=0xFAFF   // CJK compatibility ideographs
+        | 0xFE30..=0xFE4F   // CJK compatibility forms
+        | 0xFF00..=0xFFEF   // halfwidth and fullwidth forms
+        | 0x20000..=0x3134F // CJK extensions B to G
+    )
+}
+
+/// Characters a line must not start with.
+pub fn no_break_before(c: char) -> bool {
+    matches!(
+        c,
+        '、' | '。'
+            | '，'
+            | '．'
+            | '：'
+            | '；'
+            | '？'
+            | '！'
+            | '）'
+            | '］'
+            | '｝'
+            | '〕'
+            | '〉'
+            | '》'
+            | '」'
+            | '』'
+            | '】'
+            | '〙'
+            | '〗'
+            | '〟'
+            | '’'
+            | '”'
+            | '｠'
+            | '»'
+            | 'ー'
+            | '‐'
+            | '゠'
+            | '–'
+            | '〜'
+            | '～'
+            | '…'
+            | '‥'
+            | '・'
+            | '･'
+            | 'ゝ'
+            | 'ゞ'
+            | 'ヽ'
+            | 'ヾ'
+            | '々'
+            | '〻'
+            | 'ぁ'
+            | 'ぃ'
+            | 'ぅ'
+            | 'ぇ'
+            | 'ぉ'
+            | 'っ'
+            | 'ゃ'
+            | 'ゅ'
+            | 'ょ'
+            | 'ゎ'
+            | 'ゕ'
+            | 'ゖ'
+            | 'ァ'
+            | 'ィ'
+            | 'ゥ'
+            | 'ェ'
+            | 'ォ'
+            | 'ッ'
+            | 'ャ'
+            | 'ュ'
+            | 'ョ'
+            | 'ヮ'
+            | 'ヵ'
+            | 'ヶ'
+            | ','
+            | '.'
+            | ':'
+            | ';'
+            | '?'
+            | '!'
+            | ')'
+            | ']'
+            | '}'
+    )
+}
+
+/// Characters a line must not end with.
+pub fn no_break_after(c: char) -> bool {
+    matches!(
+        c,
+        '（' | '［'
+            | '｛'
+            | '〔'
+            | '〈'
+            | '《'
+            | '「'
+            | '『'
+            | '【'
+            | '〘'
+            | '〖'
+            | '〝'
+            | '‘'
+            | '“'
+            | '｟'
+            | '«'
+            | '('
+            | '['
+            | '{'
+    )
+}
+
+/// Inserts the break points (and prohibitions) described in the [module documentation](self)
+/// into the contents of a paragraph.
+pub(crate) fn insert_breakpoints(engine: Refs, children: Vec<HNode<Types>>) -> Vec<HNode<Types>> {
+    let mut ret = Vec::with_capacity(children.len());
+    // the last character, and the index in `ret` after it
+    let mut last: Option<(char, usize)> = None;
+    for node in children {
+        match &node {
+            HNode::Char { char, font } => {
+                let glyph = engine
+                    .fontsystem
+                    .glyphmaps
+                    .get_glyphlist(font.filename())
+                    .get(*char);
+                let c = if glyph.is_defined() {
+                    glyph.to_string().chars().next()
+                } else {
+                    None
+                };
+                if let (Some((prev, idx)), Some(c)) = (last, c) {
+                    if is_cjk(prev) || is_cjk(c) {
+                        let forbidden = no_break_after(prev) || no_break_before(c);
+                        let glue = ret[idx..].iter().position(|n| {
+                            matches!(n, HNode::Space | HNode::HSkip(_) | HNode::Hss | HNode::HFil)
+                        });
+                        let penalty = ret[idx..].iter().any(|n| matches!(n, HNode::Penalty(_)));
+                        match glue {
+                            Some(i) if forbidden && !penalty => {
+                                ret.insert(idx + i, HNode::Penalty(10000))
+                            }
+                            Some(_) => (),
+                            None if forbidden || !(is_cjk(prev) && is_cjk(c)) => (),
+                            None => ret.push(HNode::HSkip(Skip::default())),
+                        }
+                    }
+                }
+                ret.push(node);
+                last = c.map(|c| (c, ret.len()));
+            }
+            HNode::HKern(_) | HNode::Penalty(_) | HNode::Space | HNode::HSkip(_) => ret.push(node),
+            _ => {
+                last = None;
+                ret.push(node);
+            }
+        }
+    }
+    ret
+}
@@ -1,11 +1,16 @@
-use crate::engine::extension::CSS;
-use crate::engine::nodes::RusTeXNode;
+use crate::engine::extension::{Script, CSS};
+use crate::engine::locale::Locale;
+use crate::engine::nodes::{BoxVisibility, RusTeXNode};
 use crate::engine::stomach::RusTeXStomach;
 use crate::engine::{register_command, Refs, Res, Types};
 use crate::utils::{VecMap, VecSet};
+use std::fmt::Write;
 use tex_engine::add_node;
-use tex_engine::commands::primitives::{register_simple_expandable, register_unexpandable};
+use tex_engine::commands::primitives::{
+    register_expandable, register_simple_expandable, register_unexpandable,
+};
 use tex_engine::commands::{CommandScope, PrimitiveCommand};
+use tex_engine::engine::filesystem::FileSystem;
 use tex_engine::engine::mouth::Mouth;
 use tex_engine::engine::state::State;
 use tex_engine::engine::stomach::Stomach;
@@ -17,6 +22,7 @@ use tex_engine::tex::nodes::math::{
 };
 use tex_engine::tex::nodes::vertical::VNode;
 use tex_engine::tex::nodes::{ListTarget, NodeList};
+use tex_engine::tex::tokens::token_lists::Otherize;
 use tex_engine::tex::tokens::CompactToken;
 use tex_engine::utils::errors::TeXError;
 
@@ -41,6 +47,8 @@ pub fn register_primitives_postinit(engine: &mut DefaultEngine<Types>) {
                      true, false
     );*/
     crate::engine::pgf::register_pgf(engine);
+    tex_engine::commands::luatex::register_catcode_table_primitives(engine);
+    tex_engine::commands::xetex::register_interchar_primitives(engine);
     register_unexpandable(
         engine,
         "rustex@annotateHTML",
@@ -87,6 +95,8 @@ pub fn register_primitives_postinit(engine: &mut DefaultEngine<Types>) {
         CommandScope::Any,
         invisible_end,
     );
+    register_unexpandable(engine, "rustex@phantom", CommandScope::Any, phantom);
+    register_unexpandable(engine, "rustex@smash", CommandScope::Any, smash);
     register_unexpandable(
         engine,
         "rustex@@underbrace",
@@ -99,6 +109,97 @@ pub fn register_primitives_postinit(engine: &mut DefaultEngine<Types>) {
         CommandScope::MathOnly,
         overbrace,
     );
+    engine.state.register_primitive(
+        &mut engine.aux,
+        "ifrustex",
+        PrimitiveCommand::Conditional(tex_engine::commands::tex::iftrue),
+    );
+    register_expandable(engine, "rustexversion", rustex_version);
+    register_expandable(engine, "rustexformatdate", format_date);
+    register_expandable(engine, "rustexformatnumber", format_number);
+    register_unexpandable(engine, "rustexHTML", CommandScope::Any, raw_html);
+    register_unexpandable(engine, "rustex@rawhtml", CommandScope::Any, raw_html);
+    register_unexpandable(engine, "rustex@cssrule", CommandScope::Any, css_rule);
+    register_unexpandable(engine, "rustex@annotateData", CommandScope::Any, data_begin);
+    register_unexpandable(
+        engine,
+        "rustex@requireScript",
+        CommandScope::Any,
+        script_link,
+    );
+    register_unexpandable(
+        engine,
+        "rustex@scriptLiteral",
+        CommandScope::Any,
+        script_literal,
+    );
+    register_unexpandable(
+        engine,
+        "rustex@sectionStart",
+        CommandScope::Any,
+        section_start,
+    );
+    register_unexpandable(
+        engine,
+        "rustex@lineNumbers",
+        CommandScope::Any,
+        line_numbers,
+    );
+    register_unexpandable(
+        engine,
+        "rustex@documentLanguage",
+        CommandScope::Any,
+        document_language,
+    );
+    register_unexpandable(engine, "rustex@language", CommandScope::Any, language);
+    register_unexpandable(
+        engine,
+        "rustex@languageEnd",
+        CommandScope::Any,
+        language_end,
+    );
+    register_unexpandable(engine, "rustex@floatBegin", CommandScope::Any, float_begin);
+    register_unexpandable(engine, "rustex@floatEnd", CommandScope::Any, float_end);
+    register_unexpandable(engine, "rustex@caption", CommandScope::Any, caption);
+    engine.state.register_primitive(
+        &mut engine.aux,
+        "ifrustex@sidenotes",
+        PrimitiveCommand::Conditional(if_sidenotes),
+    );
+    engine.state.register_primitive(
+        &mut engine.aux,
+        "ifrustex@animations",
+        PrimitiveCommand::Conditional(if_animations),
+    );
+    engine.state.register_primitive(
+        &mut engine.aux,
+        "ifrustex@verbatim",
+        PrimitiveCommand::Conditional(if_verbatim),
+    );
+    engine.state.register_primitive(
+        &mut engine.aux,
+        "ifrustex@rotatelandscape",
+        PrimitiveCommand::Conditional(if_rotate_landscape),
+    );
+    register_unexpandable(engine, "rustex@stexModule", CommandScope::Any, stex_module);
+    register_unexpandable(
+        engine,
+        "rustex@beamerFrame",
+        CommandScope::Any,
+        beamer_frame,
+    );
+    register_unexpandable(
+        engine,
+        "rustex@stexModuleEnd",
+        CommandScope::Any,
+        stex_module_end,
+    );
+    register_unexpandable(
+        engine,
+        "rustex@stexDependency",
+        CommandScope::Any,
+        stex_dependency,
+    );
     // if@rustex
     // rustex@directHTML
 }
@@ -110,6 +211,285 @@ fn html_literal(engine: Refs, token: CompactToken) -> Res<()> {
     add_node!(RusTeXStomach;engine, VNode::Custom(node),HNode::Custom(node),MathNode::Custom(node));
     Ok(())
 }
+fn rustex_version(_engine: Refs, exp: &mut Vec<CompactToken>, _token: CompactToken) -> Res<()> {
+    let mut fi = |t| exp.push(t);
+    let mut f = Otherize::new(&mut fi);
+    write!(f, "{}", env!("CARGO_PKG_VERSION"))?;
+    Ok(())
+}
+/// Reads a braced language name and returns its [`Locale`]; if empty, the one given by
+/// [`Settings::locale`](crate::engine::Settings::locale) is used.
+fn read_locale(engine: Refs, token: &CompactToken) -> Res<&'static Locale> {
+    let mut lang = String::new();
+    engine.read_braced_string(true, true, token, &mut lang)?;
+    if lang.trim().is_empty() {
+        if let Some(l) = &engine.aux.extension.locale {
+            lang.clone_from(l);
+        }
+    }
+    Ok(Locale::get_or_default(&lang))
+}
+/// Tokenizes `s` under the current catcodes (so that non-ASCII characters go through
+/// `inputenc`), without the trailing `\endlinechar`.
+fn push_localized(engine: Refs, exp: &mut Vec<CompactToken>, s: &str) -> Res<()> {
+    let mut tks = engine.tokenize_str(s)?;
+    while tks
+        .last()
+        .is_some_and(|t| t.command_code() == CommandCode::Space)
+    {
+        tks.pop();
+    }
+    exp.extend(tks);
+    Ok(())
+}
+/// `\rustexformatdate{<language>}{<year>}{<month>}{<day>}`
+fn format_date(engine: Refs, exp: &mut Vec<CompactToken>, token: CompactToken) -> Res<()> {
+    let locale = read_locale(engine, &token)?;
+    let mut nums = [0i64; 3];
+    for n in &mut nums {
+        let mut s = String::new();
+        engine.read_braced_string(true, true, &token, &mut s)?;
+        *n = s.trim().parse().unwrap_or(0);
+    }
+    let [year, month, day] = nums;
+    let date = u8::try_from(month)
+        .ok()
+        .zip(u8::try_from(day).ok())
+        .and_then(|(m, d)| locale.format_date(year, m, d));
+    match date {
+        Some(date) => push_localized(engine, exp, &date),
+        None => engine.general_error(format!("Invalid date: {year}-{month}-{day}")),
+    }
+}
+/// `\rustexformatnumber{<language>}{<number>}`
+fn format_number(engine: Refs, exp: &mut Vec<CompactToken>, token: CompactToken) -> Res<()> {
+    let locale = read_locale(engine, &token)?;
+    let mut num = String::new();
+    engine.read_braced_string(true, true, &token, &mut num)?;
+    match locale.format_number(&num) {
+        Some(num) => push_localized(engine, exp, &num),
+        None => engine.general_error(format!("Not a number: {num}")),
+    }
+}
+/// Like `\rustex@HTMLLiteral`, but subject to the [`RawHTMLPolicy`](crate::engine::sanitize::RawHTMLPolicy)
+/// of the current run.
+fn raw_html(engine: Refs, token: CompactToken) -> Res<()> {
+    let mut lit = String::new();
+    engine.read_braced_string(true, true, &token, &mut lit)?;
+    let Some(lit) = engine.aux.extension.raw_content.html.apply(&lit) else {
+        return Ok(());
+    };
+    let node = RusTeXNode::Literal(lit);
+    add_node!(RusTeXStomach;engine, VNode::Custom(node),HNode::Custom(node),MathNode::Custom(node));
+    Ok(())
+}
+/// Inserts a `<style>` element at the current point, subject to the
+/// [`RawCSSPolicy`](crate::engine::sanitize::RawCSSPolicy) of the current run.
+fn css_rule(engine: Refs, token: CompactToken) -> Res<()> {
+    let mut rule = String::new();
+    engine.read_braced_string(true, true, &token, &mut rule)?;
+    let Some(rule) = engine.aux.extension.raw_content.css.apply(&rule) else {
+        return Ok(());
+    };
+    let node = RusTeXNode::Literal(format!("<style>{rule}</style>"));
+    add_node!(RusTeXStomach;engine, VNode::Custom(node),HNode::Custom(node),MathNode::Custom(node));
+    Ok(())
+}
+/// `\rustex@lineNumbers<0 or 1>`: whether the lines of the following paragraphs start with an
+/// anchor `line-<n>`, numbered by lineno's counter `linenumber` (see `patches.tex`).
+fn line_numbers(engine: Refs, token: CompactToken) -> Res<()> {
+    let on: i64 = engine.read_int(false, &token)?.into();
+    engine.aux.extension.line_numbers = on != 0;
+    Ok(())
+}
+/// `\rustex@sectionStart<level>`, inserted in front of sectional commands by `patches.tex`;
+/// records where the section starts in the source and inserts a placeholder for its anchor
+/// (see [`sections`](crate::shipout::sections)).
+fn section_start(engine: Refs, token: CompactToken) -> Res<()> {
+    let level: i64 = engine.read_int(false, &token)?.into();
+    let sref = engine.mouth.start_ref();
+    let Some(sections) = &mut engine.aux.extension.sections else {
+        return Ok(());
+    };
+    let node = RusTeXNode::Literal(crate::shipout::sections::placeholder(sections.len()));
+    sections.push(crate::shipout::sections::SectionStart {
+        level: level as i32,
+        sref,
+    });
+    add_node!(RusTeXStomach;engine, VNode::Custom(node),HNode::Custom(node),MathNode::Custom(node));
+    Ok(())
+}
+/// `\rustex@documentLanguage{<language>}`, at `\begin{document}`: the `lang` of the `<html>`
+/// element (unless set with `\rustex@annotateTop`); language switches before are ignored. If
+/// empty, the one given by [`Settings::locale`](crate::engine::Settings::locale) is used.
+fn document_language(engine: Refs, token: CompactToken) -> Res<()> {
+    let mut name = String::new();
+    engine.read_braced_string(true, true, &token, &mut name)?;
+    if name.trim().is_empty() {
+        if let Some(l) = &engine.aux.extension.locale {
+            name.clone_from(l);
+        }
+    }
+    let tag = crate::engine::locale::language_tag(&name).unwrap_or_else(|| "en".to_string());
+    let ext = &mut engine.aux.extension;
+    if ext.top.get("lang").is_none() {
+        ext.top.insert("lang".to_string(), tag.clone());
+    }
+    ext.languages = Some(vec![(tag, false)]);
+    Ok(())
+}
+/// `\rustex@language{<language>}`, whenever `babel` or `polyglossia` switch languages; wraps
+/// what follows in an element with the `lang` attribute, up to the matching
+/// `\rustex@languageEnd` (which `patches.tex` inserts with `\aftergroup`).
+fn language(engine: Refs, token: CompactToken) -> Res<()> {
+    let mut name = String::new();
+    engine.read_braced_string(true, true, &token, &mut name)?;
+    let Some(languages) = &mut engine.aux.extension.languages else {
+        return Ok(());
+    };
+    let tag = crate::engine::locale::language_tag(&name);
+    let current = languages.last().map(|(t, _)| t.as_str());
+    let Some(tag) = tag.filter(|t| Some(t.as_str()) != current) else {
+        let current = current.unwrap_or_default().to_string();
+        languages.push((current, false));
+        return Ok(());
+    };
+    languages.push((tag.clone(), true));
+    let mut attrs = VecMap::default();
+    attrs.insert("lang".to_string(), tag);
+    let node = RusTeXNode::AnnotBegin {
+        attrs,
+        styles: VecMap::default(),
+        start: engine.mouth.start_ref(),
+        classes: VecSet::default(),
+        tag: None,
+    };
+    add_node!(RusTeXStomach;engine, VNode::Custom(node),HNode::Custom(node),MathNode::Custom(node));
+    Ok(())
+}
+fn language_end(engine: Refs, token: CompactToken) -> Res<()> {
+    let Some(languages) = &mut engine.aux.extension.languages else {
+        return Ok(());
+    };
+    // the document language stays
+    if languages.len() > 1 && languages.pop().is_some_and(|(_, opened)| opened) {
+        annot_end(engine, token)?;
+    }
+    Ok(())
+}
+/// `\rustex@floatBegin{<type>}`, inserted at the beginning of the box of a float by
+/// `patches.tex`; opens its `<figure>` (see [`captions`](crate::shipout::captions)).
+fn float_begin(engine: Refs, token: CompactToken) -> Res<()> {
+    let start = engine.mouth.start_ref();
+    let mut kind = String::new();
+    engine.read_braced_string(true, true, &token, &mut kind)?;
+    let id = engine.aux.extension.captions.float_begin(kind.trim());
+    let mut attrs = VecMap::default();
+    attrs.insert("id".to_string(), id);
+    attrs.insert("data-float-type".to_string(), kind.trim().to_string());
+    let mut classes = VecSet::default();
+    classes.insert("rustex-float".to_string());
+    let node = RusTeXNode::AnnotBegin {
+        attrs,
+        styles: VecMap::default(),
+        start,
+        classes,
+        tag: Some("figure".to_string()),
+    };
+    add_node!(RusTeXStomach;engine, VNode::Custom(node),HNode::Custom(node),MathNode::Custom(node));
+    Ok(())
+}
+fn float_end(engine: Refs, token: CompactToken) -> Res<()> {
+    engine.aux.extension.captions.float_end();
+    annot_end(engine, token)
+}
+/// `\ifrustex@sidenotes`: whether `\marginpar`s become sidenotes (see
+/// [`Settings::sidenotes`](crate::engine::Settings::sidenotes)).
+fn if_sidenotes(engine: Refs, _token: CompactToken) -> Res<bool> {
+    Ok(engine.aux.extension.sidenotes)
+}
+/// `\ifrustex@animations`: whether the animations of the `animate` package show all frames
+/// (see [`Settings::animations`](crate::engine::Settings::animations)).
+fn if_animations(engine: Refs, _token: CompactToken) -> Res<bool> {
+    Ok(engine.aux.extension.animations)
+}
+/// `\ifrustex@verbatim`: whether verbatim environments and listings become `<pre>` blocks
+/// (see [`Settings::verbatim`](crate::engine::Settings::verbatim)).
+fn if_verbatim(engine: Refs, _token: CompactToken) -> Res<bool> {
+    Ok(engine.aux.extension.verbatim)
+}
+/// `\ifrustex@rotatelandscape`: whether landscape pages stay rotated
+/// (see [`Settings::rotate_landscape`](crate::engine::Settings::rotate_landscape)).
+fn if_rotate_landscape(engine: Refs, _token: CompactToken) -> Res<bool> {
+    Ok(engine.aux.extension.rotate_landscape)
+}
+/// `\rustex@caption{<short caption>}{<caption>}`
+fn caption(engine: Refs, token: CompactToken) -> Res<()> {
+    let mut short = String::new();
+    engine.read_braced_string(true, false, &token, &mut short)?;
+    let mut long = String::new();
+    engine.read_braced_string(true, false, &token, &mut long)?;
+    engine.aux.extension.captions.caption(&short, &long);
+    Ok(())
+}
+/// `\rustex@beamerFrame{<frame>}{<slide>}`: the next page shipped out is slide `<slide>` of
+/// `beamer` frame `<frame>`, and gets wrapped in a `<section data-frame="<frame>">`.
+fn beamer_frame(engine: Refs, token: CompactToken) -> Res<()> {
+    let mut frame = String::new();
+    engine.read_braced_string(true, true, &token, &mut frame)?;
+    let mut slide = String::new();
+    engine.read_braced_string(true, true, &token, &mut slide)?;
+    match (frame.trim().parse(), slide.trim().parse()) {
+        (Ok(frame), Ok(slide)) => {
+            engine.aux.extension.frame = Some((frame, slide));
+            Ok(())
+        }
+        _ => Err(TeXError::General(format!(
+            "Invalid beamer frame number: {frame}/{slide}"
+        ))),
+    }
+}
+fn stex_position(engine: Refs) -> (String, usize) {
+    let sr = engine.mouth.current_sourceref();
+    (engine.filesystem.ref_str(sr.file).to_string(), sr.line)
+}
+/// `\rustex@stexModule{<name>}`, at the beginning of an `smodule` (see [`stex`](crate::stex)).
+fn stex_module(engine: Refs, token: CompactToken) -> Res<()> {
+    let mut name = String::new();
+    engine.read_braced_string(true, true, &token, &mut name)?;
+    let (file, line) = stex_position(engine);
+    if let Some(deps) = &mut engine.aux.extension.stex {
+        deps.open_module(name, file, line);
+    }
+    Ok(())
+}
+fn stex_module_end(engine: Refs, _token: CompactToken) -> Res<()> {
+    if let Some(deps) = &mut engine.aux.extension.stex {
+        deps.close_module();
+    }
+    Ok(())
+}
+/// `\rustex@stexDependency{<kind>}{<archive>}{<target>}`, where `<kind>` is `import`, `use`,
+/// `require`, `declaration` or `reference` and `<archive>` may be empty.
+fn stex_dependency(engine: Refs, token: CompactToken) -> Res<()> {
+    let mut kind = String::new();
+    let mut archive = String::new();
+    let mut target = String::new();
+    engine.read_braced_string(true, true, &token, &mut kind)?;
+    engine.read_braced_string(true, true, &token, &mut archive)?;
+    engine.read_braced_string(true, true, &token, &mut target)?;
+    let Some(kind) = crate::stex::DependencyKind::from_name(kind.trim()) else {
+        return Err(TeXError::General(format!(
+            "Invalid sTeX dependency kind: {kind}"
+        )));
+    };
+    let (file, line) = stex_position(engine);
+    if let Some(deps) = &mut engine.aux.extension.stex {
+        let archive = Some(archive.trim().to_string()).filter(|a| !a.is_empty());
+        deps.add(kind, archive, target.trim().to_string(), file, line);
+    }
+    Ok(())
+}
 fn css_link(engine: Refs, token: CompactToken) -> Res<()> {
     let mut file = String::new();
     engine.read_braced_string(true, true, &token, &mut file)?;
@@ -286,6 +666,16 @@ fn invisible_end(engine: Refs, _token: CompactToken) -> Res<()> {
     add_node!(RusTeXStomach;engine, VNode::Custom(node),HNode::Custom(node),MathNode::Custom(node));
     Ok(())
 }
+fn phantom(engine: Refs, _token: CompactToken) -> Res<()> {
+    let node = RusTeXNode::BoxVisibility(BoxVisibility::Phantom);
+    add_node!(RusTeXStomach;engine, VNode::Custom(node),HNode::Custom(node),MathNode::Custom(node));
+    Ok(())
+}
+fn smash(engine: Refs, _token: CompactToken) -> Res<()> {
+    let node = RusTeXNode::BoxVisibility(BoxVisibility::Smash);
+    add_node!(RusTeXStomach;engine, VNode::Custom(node),HNode::Custom(node),MathNode::Custom(node));
+    Ok(())
+}
 fn annot_begin(engine: Refs, token: CompactToken) -> Res<()> {
     let start = engine.mouth.start_ref();
     let mut str = String::new();
@@ -301,6 +691,51 @@ fn annot_begin(engine: Refs, token: CompactToken) -> Res<()> {
     add_node!(RusTeXStomach;engine, VNode::Custom(node),HNode::Custom(node),MathNode::Custom(node));
     Ok(())
 }
+/// Like `\rustex@annotateHTML`, but all attributes are prefixed with `data-`
+/// (unless they already are); closed by `\rustex@annotateHTMLEnd`.
+fn data_begin(engine: Refs, token: CompactToken) -> Res<()> {
+    let start = engine.mouth.start_ref();
+    let mut str = String::new();
+    engine.read_braced_string(true, true, &token, &mut str)?;
+    let (data, styles, classes) = parse_annotations(&str)?;
+    let mut attrs = VecMap::default();
+    for (k, v) in data.into_iter() {
+        if k.starts_with("data-") {
+            attrs.insert(k, v);
+        } else {
+            attrs.insert(format!("data-{k}"), v);
+        }
+    }
+    let node = RusTeXNode::AnnotBegin {
+        attrs,
+        styles,
+        start,
+        classes,
+        tag: None,
+    };
+    add_node!(RusTeXStomach;engine, VNode::Custom(node),HNode::Custom(node),MathNode::Custom(node));
+    Ok(())
+}
+fn script_link(engine: Refs, token: CompactToken) -> Res<()> {
+    let mut file = String::new();
+    engine.read_braced_string(true, true, &token, &mut file)?;
+    if engine.aux.extension.raw_content.scripts {
+        engine.aux.extension.scripts.insert(Script::File(file));
+    }
+    Ok(())
+}
+fn script_literal(engine: Refs, token: CompactToken) -> Res<()> {
+    let mut literal = String::new();
+    engine.read_braced_string(true, true, &token, &mut literal)?;
+    if engine.aux.extension.raw_content.scripts {
+        engine
+            .aux
+            .extension
+            .scripts
+            .insert(Script::Literal(literal));
+    }
+    Ok(())
+}
 fn annot_end(engine: Refs, _token: CompactToken) -> Res<()> {
     let node = RusTeXNode::AnnotEnd(engine.mouth.current_sourceref());
     add_node!(RusTeXStomach;engine, VNode::Custom(node),HNode::Custom(node),MathNode::Custom(node));
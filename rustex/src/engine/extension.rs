@@ -1,4 +1,7 @@
+use crate::engine::nodes::ExternalNodeHandlers;
+use crate::engine::sanitize::RawContentPolicy;
 use crate::engine::{CSName, Types};
+use crate::shipout::sections::SectionStart;
 use crate::shipout::state::ShipoutState;
 use crate::utils::{VecMap, VecSet};
 use pdfium_render::prelude::Pdfium;
@@ -6,7 +9,8 @@ use tex_engine::commands::Macro;
 use tex_engine::engine::utils::memory::MemoryManager;
 use tex_engine::engine::EngineExtension;
 use tex_engine::pdflatex::nodes::{
-    MinimalPDFExtension, PDFAnnot, PDFColor, PDFExtension, PDFObj, PDFXForm, PDFXImage,
+    MinimalPDFExtension, PDFAnnot, PDFColor, PDFExtension, PDFObj, PDFPositions, PDFRandom,
+    PDFXForm, PDFXImage,
 };
 use tex_engine::prelude::CSHandler;
 use tex_engine::tex::catcodes::DEFAULT_SCHEME_U8;
@@ -19,6 +23,13 @@ pub enum CSS {
     Literal(String),
 }
 
+/// A script asset required by the document; included once in the `<head>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Script {
+    File(String),
+    Literal(String),
+}
+
 pub struct RusTeXExtension {
     pdf: MinimalPDFExtension<Types>,
     pub(crate) state: ShipoutState,
@@ -29,12 +40,63 @@ pub struct RusTeXExtension {
     pub(crate) evenfoot: CSName,
     pub(crate) mkboth: CSName,
     pub(crate) specialpage: CSName,
+    /// lineno's counter `\c@linenumber`
+    pub(crate) linenumber: CSName,
+    /// whether lines get anchors (see `\rustex@lineNumbers`)
+    pub(crate) line_numbers: bool,
     pub(crate) gobbletwo: Macro<CompactToken>,
     pub(crate) empty: Macro<CompactToken>,
     pub(crate) namespaces: VecMap<String, String>,
     pub(crate) metas: Vec<VecMap<String, String>>,
     pub(crate) top: VecMap<String, String>,
     pub(crate) css: VecSet<CSS>,
+    pub(crate) scripts: VecSet<Script>,
+    pub(crate) raw_content: RawContentPolicy,
+    /// `None` unless [`Settings::section_spans`](crate::engine::Settings::section_spans) is set
+    pub(crate) sections: Option<Vec<SectionStart>>,
+    /// see [`Settings::split_pages`](crate::engine::Settings::split_pages)
+    pub(crate) page_markers: bool,
+    /// see [`Settings::page_geometry`](crate::engine::Settings::page_geometry)
+    pub(crate) page_geometry: crate::shipout::layout::PageGeometry,
+    /// see [`Settings::sidenotes`](crate::engine::Settings::sidenotes)
+    pub(crate) sidenotes: bool,
+    /// see [`Settings::animations`](crate::engine::Settings::animations)
+    pub(crate) animations: bool,
+    /// see [`Settings::verbatim`](crate::engine::Settings::verbatim)
+    pub(crate) verbatim: bool,
+    /// see [`Settings::highlighter`](crate::engine::Settings::highlighter); only during a run
+    pub(crate) highlighter: Option<Box<dyn crate::shipout::verbatim::Highlighter>>,
+    /// see [`Settings::rotate_landscape`](crate::engine::Settings::rotate_landscape)
+    pub(crate) rotate_landscape: bool,
+    /// see [`Settings::headers`](crate::engine::Settings::headers)
+    pub(crate) running_heads: crate::shipout::pages::RunningHeads,
+    /// see [`Settings::decoration_filter`](crate::engine::Settings::decoration_filter); only
+    /// during a run
+    pub(crate) decoration_filter: Option<Box<dyn crate::shipout::decorations::DecorationFilter>>,
+    /// see [`Settings::smart_punctuation`](crate::engine::Settings::smart_punctuation)
+    pub(crate) smart_punctuation: bool,
+    /// see [`Settings::locale`](crate::engine::Settings::locale)
+    pub(crate) locale: Option<String>,
+    /// the languages switched to with `\rustex@language` (with whether a `lang` annotation was
+    /// opened), or `None` before the document language is known
+    pub(crate) languages: Option<Vec<(String, bool)>>,
+    /// see [`Settings::stex_dependencies`](crate::engine::Settings::stex_dependencies)
+    pub(crate) stex: Option<crate::stex::StexDependencies>,
+    /// the `beamer` frame and slide number of the next page, set by `\rustex@beamerFrame`
+    pub(crate) frame: Option<(usize, usize)>,
+    /// what the [`StatisticsOutput`](crate::statistics::StatisticsOutput) collected during the
+    /// last run
+    pub(crate) statistics: Option<crate::statistics::Collected>,
+    /// see [`Settings::pdf`](crate::engine::Settings::pdf)
+    pub(crate) pdf_output: Option<crate::pdf::PdfWriter>,
+    /// see [`Settings::dvi`](crate::engine::Settings::dvi)
+    pub(crate) dvi_output: Option<crate::dvi::DviWriter>,
+    pub(crate) captions: crate::shipout::captions::Captions,
+    pub(crate) saved_positions: crate::shipout::positions::SavedPositions,
+    /// the SVG path pgf is currently constructing (see [`pgf`](crate::engine::pgf))
+    pub(crate) pgf_path: String,
+    /// how [`ExternalNode`](crate::engine::nodes::ExternalNode)s are turned into HTML
+    pub external_nodes: ExternalNodeHandlers,
 }
 impl RusTeXExtension {
     pub(crate) fn push(&mut self) {
@@ -58,6 +120,8 @@ impl EngineExtension<Types> for RusTeXExtension {
             evenfoot: memory.cs_interner_mut().cs_from_str("@evenfoot"),
             mkboth: memory.cs_interner_mut().cs_from_str("@mkboth"),
             specialpage: memory.cs_interner_mut().cs_from_str("if@specialpage"),
+            linenumber: memory.cs_interner_mut().cs_from_str("c@linenumber"),
+            line_numbers: false,
             gobbletwo: Macro::new::<_, _, Types>(
                 memory.cs_interner_mut(),
                 &DEFAULT_SCHEME_U8,
@@ -71,6 +135,30 @@ impl EngineExtension<Types> for RusTeXExtension {
             metas: vec![],
             top: VecMap::default(),
             css: VecSet::default(),
+            scripts: VecSet::default(),
+            raw_content: RawContentPolicy::default(),
+            sections: None,
+            page_markers: false,
+            page_geometry: crate::shipout::layout::PageGeometry::default(),
+            sidenotes: false,
+            animations: false,
+            verbatim: false,
+            highlighter: None,
+            rotate_landscape: false,
+            running_heads: crate::shipout::pages::RunningHeads::default(),
+            decoration_filter: None,
+            smart_punctuation: false,
+            locale: None,
+            languages: None,
+            stex: None,
+            frame: None,
+            statistics: None,
+            pdf_output: None,
+            dvi_output: None,
+            captions: crate::shipout::captions::Captions::default(),
+            saved_positions: crate::shipout::positions::SavedPositions::default(),
+            pgf_path: String::new(),
+            external_nodes: ExternalNodeHandlers::default(),
         };
         ret.gobbletwo.long = true;
         ret
@@ -85,6 +173,14 @@ impl PDFExtension<Types> for RusTeXExtension {
         self.pdf.elapsed()
     }
 
+    fn random(&mut self) -> &mut PDFRandom {
+        self.pdf.random()
+    }
+
+    fn positions(&mut self) -> &mut PDFPositions {
+        self.pdf.positions()
+    }
+
     fn colorstacks(&mut self) -> &mut Vec<Vec<PDFColor>> {
         self.pdf.colorstacks()
     }
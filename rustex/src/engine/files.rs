@@ -1,5 +1,8 @@
-use std::path::PathBuf;
+use crate::sink::{relative_name, DirectorySink, OutputSink};
+use path_dedot::ParseDot;
+use std::path::{Path, PathBuf};
 use tex_engine::engine::filesystem::{File, FileSystem, NoOutputFileSystem, VirtualFile};
+use tex_engine::engine::utils::outputs::Outputs;
 use tex_engine::engine::{EngineAux, EngineTypes};
 use tex_engine::prelude::CSName;
 use tex_engine::tex::characters::{StringLineSource, TextLine};
@@ -8,12 +11,220 @@ use tex_engine::utils::Ptr;
 
 static PGFSYS: &str = include_str!("../resources/pgfsys.def");
 static RUSTEX_DEFS: &str = include_str!("../resources/patches.tex");
+static RUSTEX_STY: &str = include_str!("../resources/rustex.sty");
+
+/// Which commands `\write18` may run. The command is split into arguments as by a shell (see
+/// [`split_command`]) and its first word compared against the whitelist; everything else is
+/// refused (and logged).
+#[derive(Clone, Debug, Default)]
+pub struct ShellEscape {
+    pub whitelist: Vec<String>,
+}
+impl ShellEscape {
+    pub fn allows(&self, command: &str) -> bool {
+        split_command(command).is_ok_and(|args| {
+            args.first()
+                .is_some_and(|prog| self.whitelist.iter().any(|w| w == prog))
+        })
+    }
+}
+
+/// Splits a `\write18` command into arguments like a POSIX shell does, but without running one:
+/// words may be quoted with `'...'` or `"..."` and characters escaped with `\`. Since the
+/// command is run directly, anything a shell would interpret beyond that (pipes, redirections,
+/// command separators, substitutions, globs) is rejected with an error message, unless quoted.
+pub fn split_command(command: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if c == '\n' {
+                    return Err("multiple commands are not supported".to_string());
+                }
+                args.extend(current.take());
+            }
+            '\'' => {
+                let word = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let word = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => (),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unterminated quote".to_string()),
+                        },
+                        Some(c @ ('$' | '`')) => {
+                            return Err(format!("unsupported shell syntax: {c}"))
+                        }
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated quote".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => (),
+                Some(c) => current.get_or_insert_with(String::new).push(c),
+                None => return Err("trailing backslash".to_string()),
+            },
+            '|' | '&' | ';' | '<' | '>' | '(' | ')' | '$' | '`' | '*' | '?' | '[' => {
+                return Err(format!("unsupported shell syntax: {c}"))
+            }
+            // comments and home directories, but only at the start of a word
+            '#' | '~' if current.is_none() => return Err(format!("unsupported shell syntax: {c}")),
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(current);
+    Ok(args)
+}
+
+/// A single `\write18` invocation, whether it was run or not.
+#[derive(Clone, Debug)]
+pub struct ShellInvocation {
+    pub command: String,
+    pub allowed: bool,
+    /// exit code; `None` if the command was refused, failed to start or was killed
+    pub status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Auxiliary files (`.aux`, `.toc`, `.lof`, ...) written during a run. Feeding them into the next run (via
+/// [`Settings::aux_files`](crate::engine::Settings::aux_files)) carries over the state LaTeX
+/// checkpoints in them; in particular, chapters excluded by `\includeonly` keep the counters
+/// and labels of the last run in which they were included.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuxFiles {
+    pub files: Vec<(PathBuf, String)>,
+}
+impl AuxFiles {
+    /// The extensions of the files that are kept.
+    pub const EXTENSIONS: [&'static str; 10] = [
+        "aux", "toc", "lof", "lot", "out", "nav", "snm", "bbl", "idx", "ind",
+    ];
+
+    pub fn get(&self, path: &Path) -> Option<&str> {
+        let path = Self::canonical(path);
+        self.files
+            .iter()
+            .find(|(p, _)| *p == path)
+            .map(|(_, s)| s.as_str())
+    }
+    /// Adds or replaces a file.
+    pub fn insert(&mut self, path: PathBuf, content: String) {
+        let path = Self::canonical(&path);
+        match self.files.iter_mut().find(|(p, _)| *p == path) {
+            Some((_, c)) => *c = content,
+            None => self.files.push((path, content)),
+        }
+    }
+    /// Reads all auxiliary files in `dir`, e.g. from a previous pdflatex run or
+    /// [`write`](Self::write).
+    ///
+    /// #### Errors
+    /// If the directory can not be read
+    pub fn read_dir(dir: &Path) -> std::io::Result<Self> {
+        let mut ret = Self::default();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| Self::EXTENSIONS.contains(&e))
+            {
+                if let Ok(s) = std::fs::read_to_string(&path) {
+                    ret.insert(path, s);
+                }
+            }
+        }
+        Ok(ret)
+    }
+    /// Writes all files to the physical file system; see [`write_to`](Self::write_to).
+    ///
+    /// #### Errors
+    /// If a file is not in `dir` or can not be written
+    pub fn write(&self, dir: &Path) -> std::io::Result<()> {
+        let dir = Self::canonical(dir);
+        self.write_to(&mut DirectorySink::new(&dir), &dir)
+    }
+    /// Writes all files to `sink`, named by their paths relative to `dir` (usually the
+    /// directory of the main file). Files outside of `dir` are refused, so that documents can
+    /// not overwrite arbitrary files.
+    ///
+    /// #### Errors
+    /// If a file is not in `dir` (in which case nothing is written) or writing to the sink
+    /// fails
+    pub fn write_to(&self, sink: &mut dyn OutputSink, dir: &Path) -> std::io::Result<()> {
+        let dir = Self::canonical(dir);
+        let mut names = Vec::with_capacity(self.files.len());
+        for (p, s) in &self.files {
+            let p = Self::canonical(p);
+            if !p.starts_with(&dir) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    format!("{} is not in {}", p.display(), dir.display()),
+                ));
+            }
+            names.push((relative_name(&p, &dir), s));
+        }
+        for (name, s) in names {
+            sink.write_asset(&name, s.as_bytes())?;
+        }
+        Ok(())
+    }
+    /// `path` made absolute, without `.` and `..`, and with symbolic links in its directory
+    /// resolved, so that the same file always has the same path - also if it does not exist
+    /// yet.
+    fn canonical(path: &Path) -> PathBuf {
+        let path = if path.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            path
+        };
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let path = path.parse_dot().map_or(path.clone(), |p| p.to_path_buf());
+        match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => dir
+                .canonicalize()
+                .map_or_else(|_| path.clone(), |d| d.join(name)),
+            _ => path,
+        }
+    }
+}
+
+fn file_content(f: &VirtualFile<u8>) -> String {
+    let mut content = Vec::new();
+    for l in f.source.iter().flat_map(|s| s.iter()) {
+        content.extend_from_slice(l);
+        content.push(b'\n');
+    }
+    String::from_utf8_lossy(&content).into_owned()
+}
 
 #[derive(Clone)]
 pub struct RusTeXFileSystem {
     pub(crate) inner: NoOutputFileSystem<u8>,
     pub(crate) svg: (<VirtualFile<u8> as File>::SourceRefID, Ptr<[TextLine<u8>]>),
     pub(crate) defs: (<VirtualFile<u8> as File>::SourceRefID, Ptr<[TextLine<u8>]>),
+    pub(crate) sty: (<VirtualFile<u8> as File>::SourceRefID, Ptr<[TextLine<u8>]>),
+    pub shell_escape: ShellEscape,
+    pub(crate) shell_log: Vec<ShellInvocation>,
 }
 impl RusTeXFileSystem {
     pub fn new_with_envs<I: IntoIterator<Item = (String, String)>>(pwd: PathBuf, envs: I) -> Self {
@@ -29,6 +240,94 @@ impl RusTeXFileSystem {
     pub fn add_envs<I: IntoIterator<Item = (String, String)>>(&mut self, envs: I) {
         self.inner.envs.extend(envs);
     }
+
+    /// Makes the auxiliary files of a previous run available to `\input`/`\openin`,
+    /// taking precedence over files on disk.
+    pub fn add_aux_files(&mut self, aux: &AuxFiles) {
+        for (p, s) in &aux.files {
+            self.inner.add_file(p.clone(), s);
+        }
+    }
+
+    /// `prev` updated by the auxiliary files written in this run; files that have not been
+    /// written again (e.g. those of excluded `\include`s) are kept as they were.
+    pub(crate) fn collect_aux_files(&self, mut prev: AuxFiles) -> AuxFiles {
+        for f in self.inner.written_files() {
+            let is_aux = f
+                .path
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| AuxFiles::EXTENSIONS.contains(&e));
+            if !is_aux {
+                continue;
+            }
+            prev.insert(f.path.clone(), file_content(f));
+        }
+        prev
+    }
+
+    /// The content of a file written (and closed) in this run, regardless of its extension.
+    pub(crate) fn written_file(&self, path: &Path) -> Option<String> {
+        self.inner
+            .written_files()
+            .find(|f| f.path == path)
+            .map(file_content)
+    }
+
+    fn shell_escape<ET: EngineTypes>(&mut self, command: String, aux: &mut EngineAux<ET>) {
+        let command = command.trim().to_string();
+        let args = match split_command(&command) {
+            Ok(args) if self.shell_escape.allows(&command) => args,
+            r => {
+                aux.outputs
+                    .write_18(format!("runsystem({command})...disabled (restricted)."));
+                self.shell_log.push(ShellInvocation {
+                    command,
+                    allowed: false,
+                    status: None,
+                    stdout: String::new(),
+                    stderr: r.err().unwrap_or_default(),
+                });
+                return;
+            }
+        };
+        let Some((prog, args)) = args.split_first() else {
+            return;
+        };
+        let result = std::process::Command::new(prog)
+            .args(args)
+            .current_dir(&self.inner.kpse.pwd)
+            .output();
+        let invocation = match result {
+            Ok(out) => ShellInvocation {
+                command,
+                allowed: true,
+                status: out.status.code(),
+                stdout: String::from_utf8_lossy(&out.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&out.stderr).into_owned(),
+            },
+            Err(e) => ShellInvocation {
+                command,
+                allowed: true,
+                status: None,
+                stdout: String::new(),
+                stderr: e.to_string(),
+            },
+        };
+        aux.outputs.write_18(format!(
+            "runsystem({})...{}.",
+            invocation.command,
+            if invocation.status == Some(0) {
+                "executed"
+            } else {
+                "failed"
+            }
+        ));
+        if !invocation.stdout.is_empty() {
+            aux.outputs.write_18(&invocation.stdout);
+        }
+        self.shell_log.push(invocation);
+    }
 }
 
 impl FileSystem for RusTeXFileSystem {
@@ -40,6 +339,7 @@ impl FileSystem for RusTeXFileSystem {
             .interner
             .get_or_intern("<TEXINPUTS>/pgfsys-rustex.def");
         let ext_id = inner.interner.get_or_intern("<TEXINPUTS>/rustex_defs.def");
+        let sty_id = inner.interner.get_or_intern("<TEXINPUTS>/rustex.sty");
         Self {
             inner,
             svg: (
@@ -50,6 +350,12 @@ impl FileSystem for RusTeXFileSystem {
                 Some(ext_id),
                 StringLineSource::make_lines(RUSTEX_DEFS.as_bytes().iter().copied()).into(),
             ),
+            sty: (
+                Some(sty_id),
+                StringLineSource::make_lines(RUSTEX_STY.as_bytes().iter().copied()).into(),
+            ),
+            shell_escape: ShellEscape::default(),
+            shell_log: Vec::new(),
         }
     }
     fn ref_str(&self, id: <Self::File as File>::SourceRefID) -> &str {
@@ -74,6 +380,14 @@ impl FileSystem for RusTeXFileSystem {
                 exists: true,
                 source: Some(self.defs.1.clone()),
             }
+        } else if sr == "rustex.sty" || sr.ends_with("/rustex.sty") {
+            VirtualFile {
+                path: self.inner.kpse.pwd.join("rustex.sty"),
+                id: self.sty.0,
+                pipe: false,
+                exists: true,
+                source: Some(self.sty.1.clone()),
+            }
         } else {
             self.inner.get(sr)
         }
@@ -99,6 +413,10 @@ impl FileSystem for RusTeXFileSystem {
         self.inner.close_out(idx)
     }
 
+    fn close_all_out(&mut self) {
+        self.inner.close_all_out()
+    }
+
     fn eof(&self, idx: u8) -> bool {
         self.inner.eof(idx)
     }
@@ -110,7 +428,11 @@ impl FileSystem for RusTeXFileSystem {
         newlinechar: Option<ET::Char>,
         aux: &mut EngineAux<ET>,
     ) {
-        self.inner.write(idx, string, newlinechar, aux)
+        if idx == 18 {
+            self.shell_escape(string.to_string(), aux)
+        } else {
+            self.inner.write(idx, string, newlinechar, aux)
+        }
     }
 
     fn read<ET: EngineTypes<Char = <Self::File as File>::Char>, F: FnMut(ET::Token)>(
@@ -1,8 +1,12 @@
+use crate::shipout::state::FontData;
+use crate::utils::VecSet;
+use std::fmt::{Display, Formatter};
 use tex_engine::engine::filesystem::FileSystem;
 use tex_engine::engine::fontsystem::{Font, FontSystem as FontSystemT};
 use tex_engine::engine::{EngineAux, EngineTypes};
 use tex_engine::tex::numerics::Dim32;
 use tex_engine::tex::tokens::control_sequences::InternedCSName;
+use tex_engine::utils::HMap;
 
 pub(crate) type FontStore = tex_glyphs::encodings::FontInfoStore<String, fn(&str) -> String>;
 
@@ -16,6 +20,31 @@ fn get(s: &str) -> String {
 pub struct Fontsystem {
     fs: tex_engine::engine::fontsystem::TfmFontSystem<i32, Dim32, InternedCSName<u8>>,
     pub glyphmaps: FontStore,
+    /// whether fonts without a glyph list get the one of the fallback font
+    glyph_fallback: bool,
+    /// fonts without a glyph list, and the fallback whose glyph list they were given
+    glyph_substitutes: HMap<Box<str>, String>,
+    substitutions: VecSet<FontSubstitution>,
+}
+impl Fontsystem {
+    /// The number of distinct `.tfm` files loaded, including those preloaded with the format
+    pub fn loaded_fonts(&self) -> usize {
+        self.fs.loaded_files()
+    }
+    /// Sets the font whose metrics are used for fonts without `.tfm` file; if `None`, such
+    /// fonts have no characters. If `glyphs` is set, fonts without glyph list get the one of
+    /// `fallback` as well - which is only correct if they have the same encoding, so by default,
+    /// their characters are reported as [missing](MissingGlyph) instead.
+    pub fn set_fallback(&mut self, fallback: Option<String>, glyphs: bool) {
+        self.fs.fallback = fallback;
+        self.glyph_fallback = glyphs;
+    }
+    /// The substitutions made since the last call, in order.
+    pub(crate) fn take_substitutions(&mut self) -> Vec<FontSubstitution> {
+        std::mem::take(&mut self.substitutions)
+            .into_iter()
+            .collect()
+    }
 }
 impl FontSystemT for Fontsystem {
     type Char = u8;
@@ -27,6 +56,9 @@ impl FontSystemT for Fontsystem {
         Fontsystem {
             fs: tex_engine::engine::fontsystem::TfmFontSystem::new(aux),
             glyphmaps: FontStore::new(get),
+            glyph_fallback: false,
+            glyph_substitutes: HMap::default(),
+            substitutions: VecSet::default(),
         }
     }
 
@@ -36,10 +68,122 @@ impl FontSystemT for Fontsystem {
         macroname: <Self::Font as Font>::CS,
         fs: &mut F,
     ) -> Self::Font {
-        self.fs.new_font(path, macroname, fs)
+        let font = self.fs.new_font(path, macroname, fs);
+        for s in self.fs.take_substitutions() {
+            self.substitutions.insert(FontSubstitution {
+                font: s.requested,
+                kind: SubstitutionKind::Metrics,
+                substitute: s.substitute,
+            });
+        }
+        let Some(fallback) = self.fs.fallback.as_ref().filter(|_| self.glyph_fallback) else {
+            return font;
+        };
+        let name = font.filename();
+        let substitute = match self.glyph_substitutes.get(name) {
+            Some(s) => Some(s.clone()),
+            None if self.glyphmaps.substitute_glyphlist(name, fallback) => {
+                self.glyph_substitutes.insert(name.into(), fallback.clone());
+                Some(fallback.clone())
+            }
+            None => None,
+        };
+        if let Some(substitute) = substitute {
+            self.substitutions.insert(FontSubstitution {
+                font: name.to_string(),
+                kind: SubstitutionKind::Glyphs,
+                substitute: Some(substitute),
+            });
+        }
+        font
     }
 
     fn null(&self) -> Self::Font {
         self.fs.null()
     }
 }
+
+/// What of a font had to be replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubstitutionKind {
+    /// the `.tfm` file could not be found
+    Metrics,
+    /// there is no glyph list (i.e. no known encoding) for the font
+    Glyphs,
+}
+
+/// A font whose metrics or glyphs were replaced by those of the fallback font (see
+/// [`Settings::fallback_font`](crate::engine::Settings::fallback_font)).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontSubstitution {
+    pub font: String,
+    pub kind: SubstitutionKind,
+    /// `None` if the fallback font could not be found either, so that an empty font was used
+    pub substitute: Option<String>,
+}
+
+/// A character that is rendered as a replacement glyph, since it is not in the glyph list of
+/// its font.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingGlyph {
+    pub font: String,
+    /// the name of the glyph, as far as it is known
+    pub glyph: String,
+    pub char: u8,
+}
+
+/// The fonts of a run that had to be substituted, and the glyphs that could not be rendered;
+/// see [`CompilationResult::font_report`](crate::engine::CompilationResult::font_report).
+#[derive(Debug, Clone, Default)]
+pub struct FontReport {
+    pub substitutions: Vec<FontSubstitution>,
+    /// sorted by font
+    pub missing_glyphs: Vec<MissingGlyph>,
+}
+impl FontReport {
+    pub(crate) fn new(
+        substitutions: Vec<FontSubstitution>,
+        font_data: &HMap<Box<str>, FontData>,
+    ) -> Self {
+        let mut missing_glyphs: Vec<_> = font_data
+            .iter()
+            .flat_map(|(font, data)| {
+                data.missing.inner.iter().map(|(glyph, char)| MissingGlyph {
+                    font: font.to_string(),
+                    glyph: glyph.clone(),
+                    char: *char,
+                })
+            })
+            .collect();
+        missing_glyphs.sort_by(|a, b| (&a.font, a.char).cmp(&(&b.font, b.char)));
+        Self {
+            substitutions,
+            missing_glyphs,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.substitutions.is_empty() && self.missing_glyphs.is_empty()
+    }
+}
+impl Display for FontReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for s in &self.substitutions {
+            let what = match s.kind {
+                SubstitutionKind::Metrics => "font metrics",
+                SubstitutionKind::Glyphs => "glyph list",
+            };
+            match &s.substitute {
+                Some(sub) => writeln!(f, "Missing {what} for {}; using {sub}", s.font)?,
+                None => writeln!(f, "Missing {what} for {}; using an empty font", s.font)?,
+            }
+        }
+        for g in &self.missing_glyphs {
+            writeln!(
+                f,
+                "Missing glyph {} (position {}) in font {}",
+                g.glyph, g.char, g.font
+            )?;
+        }
+        Ok(())
+    }
+}
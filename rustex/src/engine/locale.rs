@@ -0,0 +1,412 @@
+//! Locale-dependent formatting of dates and numbers for the primitives `\rustexformatdate` and
+//! `\rustexformatnumber` (and hence `\today` without `babel`/`polyglossia`; see
+//! [`Settings::locale`](crate::engine::Settings::locale)). Languages are identified by their
+//! `babel` name (as in `\languagename`) or ISO 639-1 code.
+//!
+//! [`language_tag`] maps `babel` and `polyglossia` language names to the BCP 47 tags used in the
+//! `lang` attributes of the HTML output.
+
+/// How dates and numbers are written in a language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    /// the `babel` name
+    pub name: &'static str,
+    /// month names as used in dates (i.e. in the genitive, where applicable)
+    pub months: [&'static str; 12],
+    /// `{d}`, `{m}` and `{y}` are replaced by day, month name and year
+    pub date: &'static str,
+    /// used instead of the day for the first of a month, if given (e.g. `1er` in French)
+    pub first_day: Option<&'static str>,
+    pub decimal_separator: char,
+    pub group_separator: char,
+}
+
+const ENGLISH_MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+const NNBSP: char = '\u{202F}';
+
+static LOCALES: &[(&[&str], Locale)] = &[
+    (
+        &["english", "american", "USenglish", "en", "en-US"],
+        Locale {
+            name: "english",
+            months: ENGLISH_MONTHS,
+            date: "{m} {d}, {y}",
+            first_day: None,
+            decimal_separator: '.',
+            group_separator: ',',
+        },
+    ),
+    (
+        &["british", "UKenglish", "australian", "newzealand", "en-GB"],
+        Locale {
+            name: "british",
+            months: ENGLISH_MONTHS,
+            date: "{d} {m} {y}",
+            first_day: None,
+            decimal_separator: '.',
+            group_separator: ',',
+        },
+    ),
+    (
+        &[
+            "ngerman",
+            "german",
+            "naustrian",
+            "austrian",
+            "nswissgerman",
+            "de",
+        ],
+        Locale {
+            name: "ngerman",
+            months: [
+                "Januar",
+                "Februar",
+                "März",
+                "April",
+                "Mai",
+                "Juni",
+                "Juli",
+                "August",
+                "September",
+                "Oktober",
+                "November",
+                "Dezember",
+            ],
+            date: "{d}. {m} {y}",
+            first_day: None,
+            decimal_separator: ',',
+            group_separator: '.',
+        },
+    ),
+    (
+        &["french", "francais", "fr"],
+        Locale {
+            name: "french",
+            months: [
+                "janvier",
+                "février",
+                "mars",
+                "avril",
+                "mai",
+                "juin",
+                "juillet",
+                "août",
+                "septembre",
+                "octobre",
+                "novembre",
+                "décembre",
+            ],
+            date: "{d} {m} {y}",
+            first_day: Some("1er"),
+            decimal_separator: ',',
+            group_separator: NNBSP,
+        },
+    ),
+    (
+        &["spanish", "es"],
+        Locale {
+            name: "spanish",
+            months: [
+                "enero",
+                "febrero",
+                "marzo",
+                "abril",
+                "mayo",
+                "junio",
+                "julio",
+                "agosto",
+                "septiembre",
+                "octubre",
+                "noviembre",
+                "diciembre",
+            ],
+            date: "{d} de {m} de {y}",
+            first_day: None,
+            decimal_separator: ',',
+            group_separator: '.',
+        },
+    ),
+    (
+        &["italian", "it"],
+        Locale {
+            name: "italian",
+            months: [
+                "gennaio",
+                "febbraio",
+                "marzo",
+                "aprile",
+                "maggio",
+                "giugno",
+                "luglio",
+                "agosto",
+                "settembre",
+                "ottobre",
+                "novembre",
+                "dicembre",
+            ],
+            date: "{d} {m} {y}",
+            first_day: Some("1º"),
+            decimal_separator: ',',
+            group_separator: '.',
+        },
+    ),
+    (
+        &[
+            "portuguese",
+            "portuges",
+            "brazil",
+            "brazilian",
+            "pt",
+            "pt-BR",
+        ],
+        Locale {
+            name: "portuguese",
+            months: [
+                "janeiro",
+                "fevereiro",
+                "março",
+                "abril",
+                "maio",
+                "junho",
+                "julho",
+                "agosto",
+                "setembro",
+                "outubro",
+                "novembro",
+                "dezembro",
+            ],
+            date: "{d} de {m} de {y}",
+            first_day: None,
+            decimal_separator: ',',
+            group_separator: '.',
+        },
+    ),
+    (
+        &["dutch", "nl"],
+        Locale {
+            name: "dutch",
+            months: [
+                "januari",
+                "februari",
+                "maart",
+                "april",
+                "mei",
+                "juni",
+                "juli",
+                "augustus",
+                "september",
+                "oktober",
+                "november",
+                "december",
+            ],
+            date: "{d} {m} {y}",
+            first_day: None,
+            decimal_separator: ',',
+            group_separator: '.',
+        },
+    ),
+    (
+        &["polish", "pl"],
+        Locale {
+            name: "polish",
+            months: [
+                "stycznia",
+                "lutego",
+                "marca",
+                "kwietnia",
+                "maja",
+                "czerwca",
+                "lipca",
+                "sierpnia",
+                "września",
+                "października",
+                "listopada",
+                "grudnia",
+            ],
+            date: "{d} {m} {y}",
+            first_day: None,
+            decimal_separator: ',',
+            group_separator: NNBSP,
+        },
+    ),
+    (
+        &["russian", "ru"],
+        Locale {
+            name: "russian",
+            months: [
+                "января",
+                "февраля",
+                "марта",
+                "апреля",
+                "мая",
+                "июня",
+                "июля",
+                "августа",
+                "сентября",
+                "октября",
+                "ноября",
+                "декабря",
+            ],
+            date: "{d} {m} {y} г.",
+            first_day: None,
+            decimal_separator: ',',
+            group_separator: NNBSP,
+        },
+    ),
+];
+
+impl Locale {
+    /// The locale for the given `babel` language name or ISO 639-1 code, if known.
+    pub fn get(language: &str) -> Option<&'static Self> {
+        let language = language.trim();
+        LOCALES
+            .iter()
+            .find(|(names, _)| names.iter().any(|n| n.eq_ignore_ascii_case(language)))
+            .map(|(_, l)| l)
+    }
+    /// The locale for the given language, falling back to English.
+    pub fn get_or_default(language: &str) -> &'static Self {
+        Self::get(language).unwrap_or(&LOCALES[0].1)
+    }
+
+    /// Formats a date; returns `None` if the month or day is out of range.
+    pub fn format_date(&self, year: i64, month: u8, day: u8) -> Option<String> {
+        let name = self.months.get(usize::from(month).checked_sub(1)?)?;
+        if !(1..=31).contains(&day) {
+            return None;
+        }
+        let day = match self.first_day {
+            Some(first) if day == 1 => first.to_string(),
+            _ => day.to_string(),
+        };
+        Some(
+            self.date
+                .replace("{d}", &day)
+                .replace("{m}", name)
+                .replace("{y}", &year.to_string()),
+        )
+    }
+
+    /// Formats a decimal number given in TeX notation (e.g. `-1234567.5`), with the locale's
+    /// decimal separator and digit grouping; returns `None` if `number` is not a number.
+    pub fn format_number(&self, number: &str) -> Option<String> {
+        let number = number.trim();
+        let (sign, digits) = match number.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", number.strip_prefix('+').unwrap_or(number)),
+        };
+        let (int, frac) = match digits.split_once(['.', ',']) {
+            Some((i, f)) => (i, Some(f)),
+            None => (digits, None),
+        };
+        let all_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+        if int.is_empty() && frac.is_none_or(str::is_empty)
+            || !all_digits(int)
+            || !frac.is_none_or(all_digits)
+        {
+            return None;
+        }
+        let int = if int.is_empty() { "0" } else { int };
+        let mut ret = String::from(sign);
+        for (i, c) in int.chars().enumerate() {
+            if i > 0 && (int.len() - i) % 3 == 0 {
+                ret.push(self.group_separator);
+            }
+            ret.push(c);
+        }
+        if let Some(frac) = frac.filter(|f| !f.is_empty()) {
+            ret.push(self.decimal_separator);
+            ret.push_str(frac);
+        }
+        Some(ret)
+    }
+}
+
+static LANGUAGE_TAGS: &[(&[&str], &str)] = &[
+    (&["english"], "en"),
+    (&["american", "USenglish"], "en-US"),
+    (&["british", "UKenglish"], "en-GB"),
+    (&["australian"], "en-AU"),
+    (&["canadian"], "en-CA"),
+    (&["newzealand"], "en-NZ"),
+    (&["german", "ngerman"], "de"),
+    (&["austrian", "naustrian"], "de-AT"),
+    (&["swissgerman", "nswissgerman"], "de-CH"),
+    (&["french", "francais", "acadian", "canadien"], "fr"),
+    (&["spanish"], "es"),
+    (&["catalan"], "ca"),
+    (&["italian"], "it"),
+    (&["portuguese", "portuges"], "pt"),
+    (&["brazilian", "brazil"], "pt-BR"),
+    (&["dutch"], "nl"),
+    (&["danish"], "da"),
+    (&["swedish"], "sv"),
+    (&["norsk", "norwegian", "bokmal"], "nb"),
+    (&["nynorsk"], "nn"),
+    (&["finnish"], "fi"),
+    (&["icelandic"], "is"),
+    (&["polish"], "pl"),
+    (&["czech"], "cs"),
+    (&["slovak"], "sk"),
+    (&["slovene", "slovenian"], "sl"),
+    (&["croatian"], "hr"),
+    (&["serbian"], "sr"),
+    (&["hungarian", "magyar"], "hu"),
+    (&["romanian"], "ro"),
+    (&["bulgarian"], "bg"),
+    (&["russian"], "ru"),
+    (&["ukrainian"], "uk"),
+    (&["greek", "polutonikogreek"], "el"),
+    (&["turkish"], "tr"),
+    (&["hebrew"], "he"),
+    (&["arabic"], "ar"),
+    (&["latin"], "la"),
+    (&["estonian"], "et"),
+    (&["latvian"], "lv"),
+    (&["lithuanian"], "lt"),
+    (&["irish"], "ga"),
+    (&["welsh"], "cy"),
+    (&["basque"], "eu"),
+    (&["galician"], "gl"),
+    (&["esperanto"], "eo"),
+    (&["japanese"], "ja"),
+    (&["chinese"], "zh"),
+    (&["korean"], "ko"),
+    (&["hindi"], "hi"),
+    (&["vietnamese"], "vi"),
+    (&["thai"], "th"),
+    (&["indonesian", "bahasai"], "id"),
+    (&["malay", "bahasam"], "ms"),
+];
+
+/// The BCP 47 tag for a `babel` or `polyglossia` language name; strings that already look like
+/// a tag (e.g. `de-CH`) are returned as they are.
+pub fn language_tag(name: &str) -> Option<String> {
+    let name = name.trim();
+    if let Some((_, tag)) = LANGUAGE_TAGS
+        .iter()
+        .find(|(names, _)| names.iter().any(|n| n.eq_ignore_ascii_case(name)))
+    {
+        return Some((*tag).to_string());
+    }
+    let mut parts = name.split('-');
+    let primary = parts.next()?;
+    ((2..=3).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_lowercase())
+        && parts
+            .all(|p| !p.is_empty() && p.len() <= 8 && p.chars().all(|c| c.is_ascii_alphanumeric())))
+    .then(|| name.to_string())
+}
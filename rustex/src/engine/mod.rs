@@ -1,18 +1,23 @@
-use crate::engine::extension::CSS;
+use crate::engine::extension::{Script, CSS};
 use crate::shipout;
 use crate::shipout::html::{CompilationDisplay, ImageOptions};
+use crate::shipout::merge::MathMergeSettings;
 use crate::shipout::state::{FontData, Shipout, ShipoutNodeV, ShipoutWrapper, Top};
+use crate::sink::{DirectorySink, MemorySink, OutputSink};
+use crate::statistics::{Statistics, StatisticsOutput};
 use crate::utils::{VecMap, VecSet};
 use extension::RusTeXExtension;
 use fonts::Fontsystem;
 use nodes::RusTeXNode;
 use output::RusTeXOutput;
 use state::RusTeXState;
+use std::cell::RefCell;
 use std::fmt::Display;
-use std::io::BufWriter;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use stomach::RusTeXStomach;
+use tex_engine::commands::closures::ClosurePrimitives;
 use tex_engine::commands::{Macro, TeXCommand};
 use tex_engine::engine::filesystem::FileSystem;
 use tex_engine::engine::filesystem::{File, SourceReference, VirtualFile};
@@ -23,11 +28,16 @@ use tex_engine::engine::mouth::DefaultMouth;
 use tex_engine::engine::mouth::Mouth;
 use tex_engine::engine::state::State as OrigState;
 use tex_engine::engine::stomach::Stomach as StomachT;
+use tex_engine::engine::utils::approximations::Approximations;
+use tex_engine::engine::utils::hardening::Hardening;
+use tex_engine::engine::utils::limits::{Limits, ResourceGuard};
 use tex_engine::engine::utils::memory::MemoryManager;
+use tex_engine::engine::utils::progress::{Progress, ProgressHandler, ProgressReporter};
+use tex_engine::engine::utils::trace::{CatcodeTrace, ConditionalTrace, MacroTrace};
 use tex_engine::engine::EngineExtension;
 use tex_engine::engine::TeXEngine;
 use tex_engine::engine::{DefaultEngine, EngineAux, EngineReferences, EngineTypes};
-use tex_engine::pdflatex::nodes::PDFColor;
+use tex_engine::pdflatex::nodes::{PDFColor, PDFExtension, PDFPositions, PDFRandom};
 use tex_engine::pdflatex::PDFTeXEngine;
 use tex_engine::prelude::*;
 use tex_engine::tex;
@@ -37,13 +47,17 @@ use tex_engine::tex::tokens::CompactToken;
 use tex_engine::utils::errors::{ErrorThrower, TeXError, TeXResult};
 use tex_engine::utils::HMap;
 
+pub mod cjk;
 pub mod commands;
 pub(crate) mod extension;
 pub mod files;
 pub mod fonts;
+pub mod locale;
 pub(crate) mod nodes;
 pub mod output;
 pub(crate) mod pgf;
+pub mod sanitize;
+pub(crate) mod shading;
 pub mod state;
 pub mod stomach;
 
@@ -83,6 +97,8 @@ impl EngineTypes for Types {
 thread_local! {
     static MAIN_STATE : Mutex<Option<(RusTeXState,MemoryManager<CompactToken>)>> = const { Mutex::new(None) };
     static FONT_SYSTEM : Mutex<Option<Fontsystem>> = const { Mutex::new(None) };
+    /// the initialization files [`MAIN_STATE`] was built from, in order
+    static FORMATS : Mutex<Vec<String>> = const { Mutex::new(Vec::new()) };
 }
 
 fn get_state(log: bool) -> (RusTeXState, MemoryManager<CompactToken>) {
@@ -96,10 +112,18 @@ fn get_state(log: bool) -> (RusTeXState, MemoryManager<CompactToken>) {
                 if log {
                     engine.aux.outputs = RusTeXOutput::Print(true);
                 }
+                let formats = Arc::new(Mutex::new(Vec::new()));
+                let recorder = formats.clone();
+                engine.aux.progress = ProgressReporter::new(Box::new(move |p: Progress<'_>| {
+                    if let Progress::FormatLoaded(name) = p {
+                        recorder.lock().unwrap().push(name.to_string());
+                    }
+                }));
                 commands::register_primitives_preinit(&mut engine);
                 engine.initialize_pdflatex().unwrap();
                 commands::register_primitives_postinit(&mut engine);
                 engine.init_file("rustex_defs.def").unwrap();
+                FORMATS.with(|f| *f.lock().unwrap() = std::mem::take(&mut formats.lock().unwrap()));
                 *n = Some((engine.state.clone(), engine.aux.memory.clone()));
                 FONT_SYSTEM.with(|f| f.lock().unwrap().replace(engine.fontsystem.clone()));
                 //println!("Initialized in {:?}", start.elapsed());
@@ -115,10 +139,21 @@ fn get_engine(log: bool) -> DefaultEngine<Types> {
     let mut aux = EngineAux {
         outputs: RusTeXOutput::None,
         error_handler: ErrorThrower::new(),
-        start_time: chrono::Local::now(),
+        start_time: chrono::Local::now().fixed_offset(),
+        clock: None,
         extension: Extension::new(&mut memory),
         memory,
         jobname: String::new(),
+        limits: ResourceGuard::default(),
+        progress: ProgressReporter::default(),
+        macro_trace: None,
+        conditional_trace: None,
+        catcode_trace: None,
+        closures: ClosurePrimitives::default(),
+        approximations: Approximations::default(),
+        hardening: Hardening::default(),
+        definitions: None,
+        debugger: None,
     };
     let mut mouth = DefaultMouth::new(&mut aux, &mut state);
     let gullet = DefaultGullet::new(&mut aux, &mut state, &mut mouth);
@@ -138,27 +173,288 @@ pub struct CompilationResult {
     out: Vec<ShipoutNodeV>,
     pub error: Option<(TeXError<Types>, Vec<FileTrace>)>,
     pub font_data: HMap<Box<str>, FontData>,
+    /// the fonts of the last pass that had to be substituted, and the glyphs missing in the
+    /// fonts used
+    pub font_report: fonts::FontReport,
     top_font: Font,
     top_width: i32,
     page_width: i32,
+    page_geometry: shipout::layout::PageGeometry,
+    reflow: bool,
+    fil_cap: Option<i32>,
+    /// one per shipped out page
+    layouts: Vec<shipout::layout::PageLayout>,
     sourcerefs: bool,
     metas: Vec<VecMap<String, String>>,
     top: VecMap<String, String>,
     img: ImageOptions,
     css: VecSet<CSS>,
+    scripts: VecSet<Script>,
     pub font_info: bool,
+    /// all `\write18` invocations, including refused ones
+    pub shell_escapes: Vec<files::ShellInvocation>,
+    /// the auxiliary files after this run, to be passed to the next one
+    pub aux_files: files::AuxFiles,
+    /// whether the auxiliary files changed during the last pass, i.e. cross-references
+    /// may be wrong ("Rerun to get cross-references right")
+    pub rerun_needed: bool,
+    /// the number of passes this result took
+    pub passes: usize,
+    /// the source spans of all sectional units, in document order; empty unless
+    /// [`Settings::section_spans`] (or [`Settings::split_at`]) is set
+    pub sections: Vec<shipout::sections::SectionSource>,
+    /// source positions of the output elements; empty unless [`Settings::sourcerefs`] is set
+    pub source_map: shipout::positions::SourceMap,
+    /// the macro expansions of the last pass, if [`Settings::macro_trace`] is set
+    pub macro_trace: Option<MacroTrace>,
+    /// the branches taken by all conditionals of the last pass, if
+    /// [`Settings::conditional_trace`] is set
+    pub conditional_trace: Option<ConditionalTrace>,
+    /// all changes of category codes in the last pass and the verbatim regions derived from
+    /// them, if [`Settings::catcode_trace`] is set
+    pub catcode_trace: Option<CatcodeTrace>,
+    /// fonts, files and packages used and warnings produced by the last pass, if
+    /// [`Settings::statistics`] is set
+    pub statistics: Option<Statistics>,
+    split_at: Option<shipout::split::SplitUnit>,
+    /// the module structure of the last pass, if [`Settings::stex_dependencies`] is set
+    pub stex_dependencies: Option<crate::stex::StexDependencies>,
+    /// the floats of the last pass with their captions, in document order
+    pub floats: Vec<shipout::captions::Float>,
+    /// the positions recorded with `\pdfsavepos` in the last pass, in document order
+    pub saved_positions: Vec<shipout::positions::SavedPosition>,
+    /// the directory the source references are relative to
+    source_dir: PathBuf,
+    /// the pages as a PDF, if [`Settings::pdf`] is set
+    pub pdf: Option<Vec<u8>>,
+    /// the pages as a DVI file, if [`Settings::dvi`] is set
+    pub dvi: Option<Vec<u8>>,
+    metrics: crate::statistics::Metrics,
+    html_bytes: AtomicUsize,
 }
 impl CompilationResult {
     pub fn write_out(&self, path: &Path) -> std::io::Result<()> {
-        use std::io::Write;
-        let mut f = std::fs::File::create(path)?;
-        let mut f = BufWriter::new(&mut f);
-        write!(f, "{}", self)?;
-        f.flush()
+        let mut sink = DirectorySink::new(path.parent().unwrap_or(Path::new("")));
+        let name = path
+            .file_name()
+            .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+        self.write_to(&mut sink, &name)?;
+        sink.finalize()
     }
-}
-impl Display for CompilationResult {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    /// Writes the HTML as `name` to `sink`, together with the images rendered from PDFs
+    /// (named like the PDF file with `-rustex.png` appended, as referenced by the HTML).
+    ///
+    /// If [`Settings::split_at`] was set, `name` is the index page with the front matter and
+    /// the table of contents, and the units are written as `name` with `-1`, `-2`, ... inserted
+    /// before the extension.
+    ///
+    /// #### Errors
+    /// If writing to the sink fails
+    pub fn write_to(&self, sink: &mut dyn OutputSink, name: &str) -> std::io::Result<()> {
+        let Some(unit) = self.split_at else {
+            return self.write_nodes(sink, name, &self.out);
+        };
+        let (mut front, units, parts) = self.units(name, unit);
+        let literal = |s: String| ShipoutNodeV::Common(shipout::state::Common::Literal(s));
+        front.push(literal(shipout::split::table_of_contents(&units)));
+        self.write_nodes(sink, name, &front)?;
+        for (i, mut part) in parts.into_iter().enumerate() {
+            let prev = i.checked_sub(1).map(|j| &units[j]);
+            let next = units.get(i + 1);
+            let nav = shipout::split::navigation(
+                prev.map(|u| (u.0.as_str(), u.1.as_str())),
+                name,
+                next.map(|u| (u.0.as_str(), u.1.as_str())),
+            );
+            part.insert(0, literal(nav.clone()));
+            part.push(literal(nav));
+            self.write_nodes(sink, &units[i].0, &part)?;
+        }
+        Ok(())
+    }
+    /// Writes the output as an EPUB 3 publication to `path`, with one content document per
+    /// unit of [`Settings::split_at`] (chapters if unset; note that the run needs
+    /// [`Settings::split_at`] or [`Settings::section_spans`] for the output to be chunked at
+    /// all).
+    ///
+    /// #### Errors
+    /// If writing the file fails
+    pub fn write_epub(
+        &self,
+        path: &Path,
+        options: &crate::epub::EpubOptions,
+    ) -> std::io::Result<()> {
+        std::fs::write(path, self.epub(options)?)
+    }
+    /// The EPUB 3 publication (see [`write_epub`](Self::write_epub)), as bytes.
+    ///
+    /// #### Errors
+    /// If compressing the contents fails
+    pub fn epub(&self, options: &crate::epub::EpubOptions) -> std::io::Result<Vec<u8>> {
+        let name = "index.xhtml";
+        let unit = self.split_at.unwrap_or(shipout::split::SplitUnit::Chapter);
+        let (front, units, parts) = self.units(name, unit);
+        let mut sink = MemorySink::default();
+        let mut toc = Vec::new();
+        if !front.is_empty() || parts.is_empty() {
+            self.write_nodes(&mut sink, name, &front)?;
+            toc.push((
+                name.to_string(),
+                crate::shipout::utils::escape_html(&options.title),
+                None,
+            ));
+        }
+        for (unit, part) in units.into_iter().zip(parts) {
+            self.write_nodes(&mut sink, &unit.0, &part)?;
+            toc.push(unit);
+        }
+        crate::epub::package(&sink, &toc, options)
+    }
+    /// The output as plain text or Markdown (see [`shipout::text`]). Headings are only
+    /// recognized if [`Settings::section_spans`] was set.
+    pub fn text(&self, options: &shipout::text::TextOptions) -> String {
+        shipout::text::text(&self.out, &self.sections, &self.source_dir, *options)
+    }
+    /// Splits the output at `unit`: the front matter, and the file name, title and level of
+    /// every unit along with its nodes.
+    #[allow(clippy::type_complexity)]
+    fn units(
+        &self,
+        name: &str,
+        unit: shipout::split::SplitUnit,
+    ) -> (
+        Vec<ShipoutNodeV>,
+        Vec<(String, String, Option<i32>)>,
+        Vec<Vec<ShipoutNodeV>>,
+    ) {
+        let (front, parts) = shipout::split::split(self.out.clone(), &self.sections, unit);
+        let units = parts
+            .iter()
+            .enumerate()
+            .map(|(i, part)| {
+                (
+                    shipout::split::file_name(name, i + 1),
+                    self.title(part),
+                    shipout::split::level(part, &self.sections),
+                )
+            })
+            .collect();
+        (front, units, parts)
+    }
+    /// The text of the first block of a unit (i.e. usually its heading), for navigation.
+    fn title(&self, part: &[ShipoutNodeV]) -> String {
+        let mut title = None;
+        shipout::diff::blocks(part, &mut |n| {
+            if title.is_none() {
+                let mut assets = Vec::new();
+                let html = BlockDisplay {
+                    result: self,
+                    node: n,
+                    assets: RefCell::new(&mut assets),
+                }
+                .to_string();
+                title = Some(shipout::split::plain_text(&html, 80)).filter(|t| !t.is_empty());
+            }
+        });
+        title.unwrap_or_else(|| "…".to_string())
+    }
+    fn write_nodes(
+        &self,
+        sink: &mut dyn OutputSink,
+        name: &str,
+        out: &[ShipoutNodeV],
+    ) -> std::io::Result<()> {
+        let display = PageDisplay {
+            result: self,
+            out,
+            layout: self.layouts.first(),
+            assets: Some(RefCell::new(Vec::new())),
+        };
+        let html = display.to_string();
+        self.html_bytes.fetch_add(html.len(), Ordering::Relaxed);
+        sink.write_page(name, &html)?;
+        for (path, img) in display.assets.map(RefCell::into_inner).unwrap_or_default() {
+            let mut bytes = Vec::new();
+            img.write_to(
+                &mut std::io::Cursor::new(&mut bytes),
+                image::ImageFormat::Png,
+            )
+            .map_err(std::io::Error::other)?;
+            sink.write_asset(&path, &bytes)?;
+        }
+        Ok(())
+    }
+    /// The BCP 47 tag of the document's main language, as given by `babel` or `polyglossia`
+    /// (or [`Settings::locale`]) at `\begin{document}`.
+    pub fn language(&self) -> Option<&str> {
+        self.top.get("lang").map(String::as_str)
+    }
+    /// Resource usage of the run.
+    pub fn metrics(&self) -> crate::statistics::Metrics {
+        crate::statistics::Metrics {
+            html_bytes: self.html_bytes.load(Ordering::Relaxed),
+            ..self.metrics
+        }
+    }
+    /// One standalone HTML document per shipped out page (sharing the head of the full
+    /// document, but with the layout registers of the respective page), in order. Unless
+    /// [`Settings::split_pages`] was set, the whole output is a single page.
+    pub fn pages(&self) -> Vec<String> {
+        shipout::pages::split_pages(self.out.clone())
+            .iter()
+            .enumerate()
+            .map(|(i, out)| {
+                PageDisplay {
+                    result: self,
+                    out,
+                    layout: self.layouts.get(i).or(self.layouts.first()),
+                    assets: None,
+                }
+                .to_string()
+            })
+            .collect()
+    }
+    /// Marks the blocks that changed relative to `old`, an earlier version of the same
+    /// document, for review in rendered form (see [`shipout::diff`]).
+    pub fn mark_changes(&mut self, old: &CompilationResult) -> shipout::diff::DiffSummary {
+        let old = old.block_hashes();
+        let new = self.block_hashes();
+        let alignment = shipout::diff::align(&old, &new);
+        shipout::diff::mark(&mut self.out, &alignment, &mut 0);
+        if let Some(d) = alignment.deleted.last().filter(|d| **d > 0) {
+            self.out.push(shipout::diff::deleted_marker(*d));
+        }
+        self.css.insert(CSS::Literal(DIFF_CSS.into()));
+        alignment.summary()
+    }
+    /// The structure of the output, for comparison with other versions (see [`shipout::ir`]).
+    pub fn ir(&self) -> shipout::ir::IrNode {
+        shipout::ir::document(&self.out)
+    }
+    fn block_hashes(&self) -> Vec<u64> {
+        use std::hash::{Hash, Hasher};
+        let mut ret = Vec::new();
+        shipout::diff::blocks(&self.out, &mut |n| {
+            let mut assets = Vec::new();
+            let html = BlockDisplay {
+                result: self,
+                node: n,
+                assets: RefCell::new(&mut assets),
+            }
+            .to_string();
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            html.hash(&mut hasher);
+            ret.push(hasher.finish());
+        });
+        ret
+    }
+    fn display_nodes(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        out: &[ShipoutNodeV],
+        layout: Option<&shipout::layout::PageLayout>,
+        assets: Option<&mut Vec<(String, image::DynamicImage)>>,
+    ) -> std::fmt::Result {
         let mut dsp = CompilationDisplay {
             color: PDFColor::default(),
             font: self.top_font.clone(),
@@ -171,17 +467,82 @@ impl Display for CompilationResult {
             font_data: &self.font_data,
             image: &self.img,
             font_info: self.font_info,
+            script_level: 0,
+            reflow: self.reflow,
+            fil_cap: self.fil_cap,
+            assets,
             f,
         };
         dsp.display(
             &self.metas,
             &self.top,
             &self.css.inner,
+            &self.scripts.inner,
             self.page_width,
-            &self.out,
+            self.page_geometry,
+            layout,
+            out,
         )
     }
 }
+struct PageDisplay<'a> {
+    result: &'a CompilationResult,
+    out: &'a [ShipoutNodeV],
+    layout: Option<&'a shipout::layout::PageLayout>,
+    assets: Option<RefCell<Vec<(String, image::DynamicImage)>>>,
+}
+impl Display for PageDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.assets {
+            Some(assets) => {
+                self.result
+                    .display_nodes(f, self.out, self.layout, Some(&mut assets.borrow_mut()))
+            }
+            None => self.result.display_nodes(f, self.out, self.layout, None),
+        }
+    }
+}
+/// Renders a single block without source references, as a fingerprint for
+/// [`CompilationResult::mark_changes`].
+struct BlockDisplay<'a, 'b> {
+    result: &'a CompilationResult,
+    node: &'a ShipoutNodeV,
+    assets: RefCell<&'b mut Vec<(String, image::DynamicImage)>>,
+}
+impl Display for BlockDisplay<'_, '_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut assets = self.assets.borrow_mut();
+        CompilationDisplay {
+            color: PDFColor::default(),
+            font: self.result.top_font.clone(),
+            width: self.result.top_width,
+            indent: 0,
+            in_link: false,
+            attrs: VecMap::default(),
+            styles: VecMap::default(),
+            sourcerefs: false,
+            font_data: &self.result.font_data,
+            image: &self.result.img,
+            font_info: self.result.font_info,
+            script_level: 0,
+            reflow: self.result.reflow,
+            fil_cap: self.result.fil_cap,
+            assets: Some(&mut assets),
+            f,
+        }
+        .display_fragment(self.node)
+    }
+}
+const DIFF_CSS: &str = ".rustex-diff-inserted, .rustex-diff-changed { border-left: 3px solid; padding-left: 0.5em; margin-left: calc(-0.5em - 3px); }
+.rustex-diff-inserted { border-left-color: #2da44e; background: #2da44e14; }
+.rustex-diff-changed { border-left-color: #bf8700; background: #bf870014; }
+.rustex-diff-deleted { border-left: 3px solid #cf222e; height: 0.5em; margin-left: calc(-0.5em - 3px); }
+";
+impl Display for CompilationResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.display_nodes(f, &self.out, self.layouts.first(), None)
+    }
+}
 
 pub trait RusTeXEngineT {
     fn initialize(log: bool);
@@ -221,6 +582,135 @@ pub struct Settings {
     pub log: bool,
     pub image_options: ImageOptions,
     pub insert_font_info: bool,
+    /// merge adjacent inline formulas separated only by the given text;
+    /// `None` disables the pass
+    pub merge_math: Option<MathMergeSettings>,
+    /// commands `\write18` is allowed to run
+    pub shell_escape: files::ShellEscape,
+    /// resource limits; exceeding one aborts the run with [`TeXError::LimitExceeded`]
+    pub limits: Limits,
+    /// receives progress reports (loaded formats, opened files, shipped pages) during the run
+    pub progress: Option<Box<dyn ProgressHandler>>,
+    /// what to do with raw HTML and CSS from `\rustexHTML`, `\rustex@rawhtml` and `\rustex@cssrule`
+    pub raw_content: sanitize::RawContentPolicy,
+    /// auxiliary files of a previous run (see [`CompilationResult::aux_files`])
+    pub aux_files: files::AuxFiles,
+    /// [`RusTeXEngineT::do_file`] reruns the document until its auxiliary files no longer
+    /// change, but at most this many times in total (`0` or `1` to never rerun)
+    pub max_passes: usize,
+    /// generate a `.bbl` from the `.bib` files cited in the `.aux` files unless one from
+    /// BibTeX or biber exists (see [`bibtex`](crate::bibtex)); takes effect in the next pass
+    pub bibtex: bool,
+    /// generate `.ind` files from the `.idx` files written by `\makeindex`/`\index` unless
+    /// ones from `makeindex` or `xindy` exist (see [`makeindex`](crate::makeindex)); takes
+    /// effect in the next pass
+    pub makeindex: Option<crate::makeindex::IndexOptions>,
+    /// generate the glossary files of the `glossaries` package (see
+    /// [`glossaries`](crate::glossaries)); takes effect in the next pass
+    pub glossaries: bool,
+    /// mark every sectional unit with the line and byte range of its source (see
+    /// [`CompilationResult::sections`])
+    pub section_spans: bool,
+    /// record page boundaries, so that [`CompilationResult::pages`] can split the output
+    pub split_pages: bool,
+    /// replace `--`, `---`, ``` `` ``` and `''` that the fonts do not turn into ligatures by
+    /// proper Unicode punctuation; monospaced fonts and `verbatim` text are left alone
+    pub smart_punctuation: bool,
+    /// record all macro expansions (of the last pass) in
+    /// [`CompilationResult::macro_trace`]; the given trace is used as the (empty) template,
+    /// e.g. to restrict which macros are recorded
+    pub macro_trace: Option<MacroTrace>,
+    /// record which branch every conditional (of the last pass) took, with its source
+    /// position, in [`CompilationResult::conditional_trace`]
+    pub conditional_trace: bool,
+    /// record every change of a category code (of the last pass) with the source position it
+    /// takes effect at, in [`CompilationResult::catcode_trace`] - e.g. to find out which parts
+    /// of the input were read verbatim
+    pub catcode_trace: bool,
+    /// collect a summary of the fonts, files and packages used and of the warnings and
+    /// unsupported primitives encountered in [`CompilationResult::statistics`]
+    pub statistics: bool,
+    /// fail (listing them) if the engine or the HTML conversion takes any known approximation,
+    /// e.g. a stubbed primitive, a missing glyph or a dropped node
+    pub strict: bool,
+    /// fail with [`TeXError::Internal`] instead of panicking when malformed input breaks an
+    /// invariant of the engine (see [`hardening`](tex_engine::engine::utils::hardening))
+    pub hardened: bool,
+    /// with [`sourcerefs`](Self::sourcerefs), also record where the contents of unpacked
+    /// boxes (`\unhbox` etc.) were unpacked at, as a chain in a `data-rustex-via` attribute
+    pub provenance_via: bool,
+    /// the language (a `babel` name or ISO 639-1 code) used by `\rustexformatdate` and
+    /// `\rustexformatnumber` if none is given - and hence by `\today` if neither `babel` nor
+    /// `polyglossia` is loaded; English if `None`
+    pub locale: Option<String>,
+    /// the font whose metrics are used for fonts without `.tfm` file (see
+    /// [`CompilationResult::font_report`]); `cmr10` if `None`
+    pub fallback_font: Option<String>,
+    /// also use the glyph list of the [`fallback_font`](Self::fallback_font) for fonts with
+    /// unknown encoding, instead of reporting their characters as missing
+    pub fallback_glyphs: bool,
+    /// split the output into one file per unit, with navigation and a table of contents (see
+    /// [`shipout::split`] and [`CompilationResult::write_to`])
+    pub split_at: Option<shipout::split::SplitUnit>,
+    /// record the modules an sTeX document declares and the modules and symbols they depend
+    /// on (see [`CompilationResult::stex_dependencies`])
+    pub stex_dependencies: bool,
+    /// emit formulas as their TeX source, to be rendered in the browser by MathJax or KaTeX
+    /// (see [`shipout::client_math`]), rather than as MathML
+    pub math_renderer: Option<shipout::client_math::MathRenderer>,
+    /// also lay the shipped out pages out as a PDF, in [`CompilationResult::pdf`] (see
+    /// [`pdf`](crate::pdf))
+    pub pdf: bool,
+    /// also write the shipped out pages as a DVI file, in [`CompilationResult::dvi`] (see
+    /// [`dvi`](crate::dvi))
+    pub dvi: bool,
+    /// insert the implicit kerns of the fonts (e.g. between `A` and `V`) as separate kern
+    /// nodes, so that the [`pdf`](Self::pdf) and [`dvi`](Self::dvi) output matches TeX's word
+    /// shapes; they split the HTML text at every kerned pair of characters, and browsers kern
+    /// on their own anyway
+    pub implicit_kerns: bool,
+    /// how the page geometry (`\pdfpagewidth`, `\textwidth`, the margins,...) maps to the
+    /// HTML output
+    pub page_geometry: shipout::layout::PageGeometry,
+    /// emit horizontal dimensions relative to the width of the enclosing box rather than in
+    /// pixels, and paragraphs without hard line breaks, so that the output reflows to narrow
+    /// screens; the geometry is then only used as the maximal width of the text
+    pub reflow: bool,
+    /// render `\marginpar`s as sidenotes, floated into the margin next to the line they are
+    /// in (and inline and collapsible on narrow screens), rather than where LaTeX's output
+    /// routine puts them
+    pub sidenotes: bool,
+    /// render the animations of the `animate` package (`\animategraphics`, `animateinline`)
+    /// as all of their frames, of which CSS shows one after the other at the frame rate, rather
+    /// than only the first frame
+    pub animations: bool,
+    /// render `verbatim` environments, fancyvrb's `Verbatim` (and the environments defined
+    /// from it) and listings' displayed listings as `<pre><code>` elements with the text of
+    /// their lines, preserving every space, rather than as paragraphs of monospaced text
+    pub verbatim: bool,
+    /// highlights the code of the `<pre>` blocks of [`verbatim`](Self::verbatim) during
+    /// shipout; without one, they contain plain text
+    pub highlighter: Option<Box<dyn shipout::verbatim::Highlighter>>,
+    /// keep the pages of `landscape` environments (lscape, pdflscape) rotated by 90 degrees,
+    /// as printed, rather than showing their (wide) contents upright
+    pub rotate_landscape: bool,
+    /// keep the headers and footers the output routine puts on the pages (e.g. fancyhdr's) and
+    /// emit them as `<header>`/`<footer>` elements - hidden by default - whenever they differ
+    /// from the previous page's, rather than dropping them
+    pub headers: bool,
+    /// decides which boxes of the pages - headers and footers, crop marks, line numbers... -
+    /// are kept during shipout, by where they were built
+    pub decoration_filter: Option<Box<dyn shipout::decorations::DecorationFilter>>,
+    /// the maximal height (in pt) of the space that infinitely stretchable vertical glue
+    /// (`\vfil`, `\vfill`,...) takes up in the HTML output - e.g. the bottom of short pages;
+    /// unbounded if `None`
+    pub fil_cap: Option<f32>,
+    /// the seed of `\pdfuniformdeviate` and `\pdfnormaldeviate` (in every pass), for
+    /// reproducible output; derived from the current time if `None`, as in pdfTeX
+    pub random_seed: Option<i32>,
+    /// the time the document is compiled at, for `\year`, `\month`, `\day`, `\time` and
+    /// `\pdfcreationdate`; `SOURCE_DATE_EPOCH` or the current time if `None`
+    pub clock: Option<chrono::DateTime<chrono::FixedOffset>>,
 }
 
 /*pub struct RusTeXEngine {
@@ -242,6 +732,129 @@ pub trait RusTeXEngineExt: sealed::Sealed {
     ) -> CompilationResult;
 }
 
+fn collect_aux_files(engine: &RusTeXEngine, settings: &Settings) -> files::AuxFiles {
+    let mut aux = engine
+        .filesystem
+        .collect_aux_files(settings.aux_files.clone());
+    if settings.bibtex {
+        crate::bibtex::resolve(&mut aux, &engine.filesystem, &engine.aux.jobname);
+    }
+    if let Some(options) = &settings.makeindex {
+        crate::makeindex::resolve(&mut aux, &engine.filesystem, options, &engine.aux.outputs);
+    }
+    if settings.glossaries {
+        crate::glossaries::resolve(&mut aux, &engine.filesystem, &engine.aux.jobname);
+    }
+    aux
+}
+
+/// Reports the initialization files to the progress handler in `settings`. They are only
+/// processed once per thread (see [`get_state`]), before any handler is installed, so the
+/// reports are replayed at the start of each compilation instead.
+fn report_formats(settings: &mut Settings) {
+    if let Some(handler) = &mut settings.progress {
+        FORMATS.with(|f| {
+            for name in f.lock().unwrap().iter() {
+                handler.progress(Progress::FormatLoaded(name));
+            }
+        });
+    }
+}
+
+/// Runs the engine once on `file`, with the per-run parts of `settings` applied.
+fn run_pass(
+    engine: &mut RusTeXEngine,
+    file: &str,
+    settings: &mut Settings,
+) -> Option<TeXError<Types>> {
+    engine.filesystem.shell_escape = settings.shell_escape.clone();
+    engine.aux.limits = ResourceGuard::new(settings.limits.clone());
+    engine.aux.extension.raw_content = settings.raw_content;
+    let split_level = settings.split_at.and_then(shipout::split::SplitUnit::level);
+    engine.aux.extension.sections =
+        (settings.section_spans || split_level.is_some()).then(Vec::new);
+    engine.aux.extension.page_markers =
+        settings.split_pages || settings.split_at == Some(shipout::split::SplitUnit::Frame);
+    engine.aux.extension.page_geometry = settings.page_geometry;
+    engine.aux.extension.sidenotes = settings.sidenotes;
+    engine.aux.extension.animations = settings.animations;
+    engine.aux.extension.verbatim = settings.verbatim;
+    engine.aux.extension.highlighter = settings.highlighter.take();
+    engine.aux.extension.rotate_landscape = settings.rotate_landscape;
+    engine.aux.extension.running_heads = shipout::pages::RunningHeads::new(settings.headers);
+    engine.aux.extension.decoration_filter = settings.decoration_filter.take();
+    engine.aux.clock = settings.clock;
+    if let Some(seed) = settings.random_seed {
+        *engine.aux.extension.random() = PDFRandom::new(seed);
+    }
+    engine.aux.extension.smart_punctuation = settings.smart_punctuation;
+    engine.aux.extension.locale.clone_from(&settings.locale);
+    engine.aux.extension.languages = None;
+    engine.aux.extension.stex = settings
+        .stex_dependencies
+        .then(crate::stex::StexDependencies::default);
+    engine.aux.extension.state.sources = settings.sourcerefs.then(Vec::new);
+    engine.aux.extension.state.tex_sources = settings
+        .math_renderer
+        .map(|_| shipout::client_math::Sources::default());
+    engine.aux.extension.pdf_output = settings.pdf.then(crate::pdf::PdfWriter::default);
+    engine.aux.extension.dvi_output = settings.dvi.then(crate::dvi::DviWriter::default);
+    engine.stomach.data_mut().implicit_kerns = settings.implicit_kerns;
+    engine.aux.extension.captions = shipout::captions::Captions::default();
+    engine.aux.extension.saved_positions = shipout::positions::SavedPositions::default();
+    engine.aux.extension.pgf_path.clear();
+    engine.aux.extension.line_numbers = false;
+    *engine.aux.extension.positions() = PDFPositions::default();
+    engine.aux.extension.state.via =
+        (settings.sourcerefs && settings.provenance_via).then(Vec::new);
+    engine.aux.macro_trace = settings.macro_trace.clone();
+    engine.aux.conditional_trace = settings.conditional_trace.then(ConditionalTrace::new);
+    engine.aux.catcode_trace = settings.catcode_trace.then(CatcodeTrace::new);
+    engine.aux.approximations = Approximations::new(settings.strict);
+    engine.aux.hardening = Hardening::new(settings.hardened);
+    engine.fontsystem.set_fallback(
+        Some(
+            settings
+                .fallback_font
+                .clone()
+                .unwrap_or_else(|| "cmr10".to_string()),
+        ),
+        settings.fallback_glyphs,
+    );
+    engine.fontsystem.take_substitutions();
+    engine.filesystem.add_aux_files(&settings.aux_files);
+    engine.aux.progress = settings
+        .progress
+        .take()
+        .map(ProgressReporter::new)
+        .unwrap_or_default();
+    if settings.statistics {
+        let out = std::mem::replace(&mut engine.aux.outputs, RusTeXOutput::None);
+        engine.aux.outputs = StatisticsOutput::wrap(out);
+    }
+    let res = match engine.do_file_pdf(file, shipout::shipout) {
+        Ok(_) => None,
+        Err(e) => {
+            engine.aux.outputs.errmessage(format!(
+                "{}\n\nat {}",
+                e,
+                engine.mouth.current_sourceref().display(&engine.filesystem)
+            ));
+            Some(e)
+        }
+    };
+    if settings.statistics {
+        let out = std::mem::replace(&mut engine.aux.outputs, RusTeXOutput::None);
+        let (out, collected) = StatisticsOutput::unwrap(out);
+        engine.aux.outputs = out;
+        engine.aux.extension.statistics = collected;
+    }
+    settings.progress = engine.aux.progress.take_handler();
+    settings.highlighter = engine.aux.extension.highlighter.take();
+    settings.decoration_filter = engine.aux.extension.decoration_filter.take();
+    res
+}
+
 impl RusTeXEngineExt for RusTeXEngine {
     fn run_string(&mut self, file: PathBuf, content: &str) -> Option<TeXError<Types>> {
         let s = file.display().to_string();
@@ -326,9 +939,33 @@ impl RusTeXEngineExt for RusTeXEngine {
             s.output = nodes;
             self.aux.extension.state = s;
         }
-        let out = std::mem::take(&mut self.aux.extension.state.output);
-        let css = std::mem::take(&mut self.aux.extension.css);
+        let mut out = std::mem::take(&mut self.aux.extension.state.output);
+        if let Some(merge) = &settings.merge_math {
+            shipout::merge::merge_math(&mut out, merge);
+        }
+        let source_map = match self.aux.extension.state.sources.take() {
+            Some(refs) => shipout::positions::SourceMap::resolve(refs, &self.filesystem),
+            None => shipout::positions::SourceMap::default(),
+        };
+        let sections = match self.aux.extension.sections.take() {
+            Some(starts) => {
+                let sections = shipout::sections::resolve(starts, &self.filesystem);
+                shipout::sections::insert_anchors(
+                    &mut out,
+                    &sections,
+                    &self.filesystem.inner.kpse.pwd,
+                );
+                sections
+            }
+            None => Vec::new(),
+        };
+        let mut css = std::mem::take(&mut self.aux.extension.css);
+        let mut scripts = std::mem::take(&mut self.aux.extension.scripts);
+        if let Some(renderer) = settings.math_renderer {
+            renderer.add_loader(&mut css, &mut scripts);
+        }
         let font_data = std::mem::take(&mut self.aux.extension.state.font_data);
+        let font_report = fonts::FontReport::new(self.fontsystem.take_substitutions(), &font_data);
         let top_font = self
             .aux
             .extension
@@ -350,21 +987,80 @@ impl RusTeXEngineExt for RusTeXEngine {
             .page_width
             .clone()
             .unwrap_or_default();
+        let layouts = std::mem::take(&mut self.aux.extension.state.layouts);
         let top = std::mem::take(&mut self.aux.extension.top);
         let metas = std::mem::take(&mut self.aux.extension.metas);
+        let shell_escapes = std::mem::take(&mut self.filesystem.shell_log);
+        let statistics = self.aux.extension.statistics.take().map(|c| {
+            let error = match &result {
+                Some((TeXError::General(msg), _)) => Some(msg.as_str()),
+                _ => None,
+            };
+            Statistics::new(c, &font_data, error)
+        });
+        let pdf = self
+            .aux
+            .extension
+            .pdf_output
+            .take()
+            .and_then(|pdf| pdf.finish(&self.filesystem).ok());
+        let dvi = self
+            .aux
+            .extension
+            .dvi_output
+            .take()
+            .map(crate::dvi::DviWriter::finish);
+        let metrics = crate::statistics::Metrics {
+            nodes: self.aux.limits.nodes(),
+            peak_list_depth: self.aux.limits.peak_list_depth(),
+            expansions: self.aux.limits.expansions(),
+            fonts_loaded: self.fontsystem.loaded_fonts(),
+            html_bytes: 0,
+        };
+        let aux_files = collect_aux_files(self, &settings);
+        let rerun_needed = aux_files != settings.aux_files;
         CompilationResult {
             out,
             error: result,
             css,
+            scripts,
             font_data,
+            font_report,
             top_font,
             top_width,
             top,
             metas,
             page_width,
+            page_geometry: settings.page_geometry,
+            reflow: settings.reflow,
+            fil_cap: settings.fil_cap.map(|pt| (pt * 65536.0) as i32),
+            layouts,
             sourcerefs: settings.sourcerefs,
             font_info: settings.insert_font_info,
             img: settings.image_options,
+            shell_escapes,
+            aux_files,
+            rerun_needed,
+            passes: 1,
+            sections,
+            source_map,
+            macro_trace: self.aux.macro_trace.take(),
+            conditional_trace: self.aux.conditional_trace.take(),
+            catcode_trace: self
+                .aux
+                .catcode_trace
+                .take()
+                .map(|t| t.map_files(|f| self.filesystem.ref_str(f).to_string())),
+            statistics,
+            split_at: settings.split_at,
+            stex_dependencies: self.aux.extension.stex.take(),
+            floats: std::mem::take(&mut self.aux.extension.captions).into_floats(),
+            saved_positions: std::mem::take(&mut self.aux.extension.saved_positions).into_vec(),
+            source_dir: self.filesystem.inner.kpse.pwd.clone(),
+            pdf,
+            dvi,
+            metrics,
+            html_bytes: AtomicUsize::new(0),
         }
     }
 }
@@ -385,29 +1081,41 @@ impl RusTeXEngineT for RusTeXEngine {
         get_engine(false)
     }
 
-    fn run<S: AsRef<str>>(&mut self, file: S, settings: Settings) -> CompilationResult {
-        let res = match self.do_file_pdf(file.as_ref(), shipout::shipout) {
-            Ok(_) => None,
-            Err(e) => {
-                self.aux.outputs.errmessage(format!(
-                    "{}\n\nat {}",
-                    e,
-                    self.mouth.current_sourceref().display(&self.filesystem)
-                ));
-                Some(e)
-            }
-        };
+    fn run<S: AsRef<str>>(&mut self, file: S, mut settings: Settings) -> CompilationResult {
+        report_formats(&mut settings);
+        let res = run_pass(self, file.as_ref(), &mut settings);
         self.do_result(res, settings)
     }
 
-    fn do_file<S: AsRef<str>>(file: S, settings: Settings) -> CompilationResult {
-        let mut engine = Self::get();
-        engine.stomach.continuous = true;
-        if settings.log {
-            engine.aux.outputs = RusTeXOutput::Print(settings.verbose);
+    fn do_file<S: AsRef<str>>(file: S, mut settings: Settings) -> CompilationResult {
+        let get = |settings: &Settings| {
+            let mut engine = Self::get();
+            engine.stomach.continuous = true;
+            if settings.log {
+                engine.aux.outputs = RusTeXOutput::Print(settings.verbose);
+            }
+            engine
+        };
+        let mut passes = 1;
+        loop {
+            let mut engine = get(&settings);
+            if passes == 1 {
+                report_formats(&mut settings);
+            }
+            let res = run_pass(&mut engine, file.as_ref(), &mut settings);
+            if passes < settings.max_passes && res.is_none() {
+                let aux = collect_aux_files(&engine, &settings);
+                if aux != settings.aux_files {
+                    FONT_SYSTEM.with(|f| f.lock().unwrap().replace(engine.fontsystem));
+                    settings.aux_files = aux;
+                    passes += 1;
+                    continue;
+                }
+            }
+            let mut ret = engine.do_result(res, settings);
+            ret.passes = passes;
+            FONT_SYSTEM.with(|f| f.lock().unwrap().replace(engine.fontsystem));
+            return ret;
         }
-        let ret = <Self as RusTeXEngineT>::run(&mut engine, file, settings);
-        FONT_SYSTEM.with(|f| f.lock().unwrap().replace(engine.fontsystem));
-        ret
     }
 }
@@ -1,13 +1,16 @@
 use crate::engine::state::RusTeXState;
-use crate::engine::{Font, SRef, Types};
+use crate::engine::{Font, Refs, SRef, Types};
 use crate::utils::{VecMap, VecSet};
+use std::any::{Any, TypeId};
 use tex_engine::commands::primitives::PRIMITIVES;
+use tex_engine::engine::mouth::Mouth;
 use tex_engine::engine::state::State;
 use tex_engine::engine::stomach::methods::ParLineSpec;
 use tex_engine::pdflatex::nodes::PDFNode;
 use tex_engine::tex::nodes::boxes::TeXBox;
 use tex_engine::tex::nodes::{CustomNodeTrait, NodeTrait, NodeType};
 use tex_engine::tex::numerics::{Dim32, Skip};
+use tex_engine::utils::HMap;
 
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct LineSkip {
@@ -62,6 +65,8 @@ pub enum RusTeXNode {
         miny: Dim32,
         maxx: Dim32,
         maxy: Dim32,
+        /// see [`Captions`](crate::shipout::captions::Captions)
+        picture: usize,
     },
     PageBegin,
     PageEnd,
@@ -76,19 +81,59 @@ pub enum RusTeXNode {
     InvisibleEnd,
     AnnotEnd(SRef),
     Literal(String),
+    External(ExternalNode),
+    /// the contents of a box spanning `start` to `end` that was unpacked at `via` follow
+    ProvenanceBegin {
+        start: SRef,
+        end: SRef,
+        via: SRef,
+    },
+    ProvenanceEnd,
+    /// the first node of an `\hbox` produced by `\phantom` or `\smash` and friends
+    BoxVisibility(BoxVisibility),
+}
+
+/// How the contents of a box are shown; see [`RusTeXNode::BoxVisibility`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoxVisibility {
+    /// takes up space, but is not shown (`\phantom`, `\hphantom`, `\vphantom`)
+    Phantom,
+    /// is shown, but the box has zero height and/or depth (`\smash`)
+    Smash,
+}
+impl BoxVisibility {
+    /// the CSS class of the box in the output
+    pub fn class(self) -> &'static str {
+        match self {
+            Self::Phantom => "rustex-phantom",
+            Self::Smash => "rustex-smash",
+        }
+    }
+}
+impl CustomNodeTrait<Types> for RusTeXNode {
+    fn provenance(engine: Refs, start: SRef, end: SRef) -> Option<(Self, Self)> {
+        // only needed for source references in the output
+        engine.aux.extension.state.sources.as_ref()?;
+        let via = engine.mouth.current_sourceref();
+        Some((
+            Self::ProvenanceBegin { start, end, via },
+            Self::ProvenanceEnd,
+        ))
+    }
 }
-impl CustomNodeTrait<Types> for RusTeXNode {}
 impl NodeTrait<Types> for RusTeXNode {
     fn height(&self) -> Dim32 {
         match self {
             Self::PDFNode(n) => n.height(),
             Self::PGFSvg { miny, maxy, .. } => *maxy + -*miny,
+            Self::External(n) => n.0.dyn_height(),
             _ => Dim32(0),
         }
     }
     fn depth(&self) -> Dim32 {
         match self {
             Self::PDFNode(n) => n.depth(),
+            Self::External(n) => n.0.dyn_depth(),
             _ => Dim32(0),
         }
     }
@@ -96,15 +141,20 @@ impl NodeTrait<Types> for RusTeXNode {
         match self {
             Self::PDFNode(n) => n.width(),
             Self::PGFSvg { minx, maxx, .. } => *maxx + -*minx,
+            Self::External(n) => n.0.dyn_width(),
             _ => Dim32(0),
         }
     }
     fn nodetype(&self) -> NodeType {
-        NodeType::WhatsIt
+        match self {
+            Self::External(n) => n.0.dyn_nodetype(),
+            _ => NodeType::WhatsIt,
+        }
     }
     fn display_fmt(&self, indent: usize, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::PDFNode(n) => n.display_fmt(indent, f),
+            Self::External(n) => n.0.dyn_display_fmt(indent, f),
             Self::PGFSvg { bx, .. } => {
                 write!(f, "<svg>")?;
                 bx.display_fmt(indent + 2, f)?;
@@ -117,6 +167,7 @@ impl NodeTrait<Types> for RusTeXNode {
     fn opaque(&self) -> bool {
         match self {
             Self::PDFNode(n) => n.opaque(),
+            Self::External(n) => n.0.dyn_opaque(),
             _ => true,
         }
     }
@@ -126,3 +177,124 @@ impl From<PDFNode<Types>> for RusTeXNode {
         Self::PDFNode(value)
     }
 }
+
+/// Object-safe version of [`NodeTrait`], implemented for all node types.
+trait DynNode: Any {
+    fn dyn_height(&self) -> Dim32;
+    fn dyn_depth(&self) -> Dim32;
+    fn dyn_width(&self) -> Dim32;
+    fn dyn_nodetype(&self) -> NodeType;
+    fn dyn_display_fmt(&self, indent: usize, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result;
+    fn dyn_opaque(&self) -> bool;
+    fn clone_box(&self) -> Box<dyn DynNode>;
+    fn as_any(&self) -> &dyn Any;
+    fn type_name(&self) -> &'static str;
+}
+impl<N: NodeTrait<Types> + 'static> DynNode for N {
+    fn dyn_height(&self) -> Dim32 {
+        NodeTrait::height(self)
+    }
+    fn dyn_depth(&self) -> Dim32 {
+        NodeTrait::depth(self)
+    }
+    fn dyn_width(&self) -> Dim32 {
+        NodeTrait::width(self)
+    }
+    fn dyn_nodetype(&self) -> NodeType {
+        NodeTrait::nodetype(self)
+    }
+    fn dyn_display_fmt(&self, indent: usize, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        NodeTrait::display_fmt(self, indent, f)
+    }
+    fn dyn_opaque(&self) -> bool {
+        NodeTrait::opaque(self)
+    }
+    fn clone_box(&self) -> Box<dyn DynNode> {
+        Box::new(self.clone())
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<N>()
+    }
+}
+
+/// A node of a type defined outside of RusTeX, e.g. by an embedder adding semantic annotations
+/// via custom primitives (see [`closures`](tex_engine::commands::closures)). Any
+/// [`NodeTrait`]`<`[`Types`]`>` can be wrapped; since `ExternalNode` implements
+/// [`CustomNodeTrait`], it can then be added to any list via e.g.
+/// `ExternalNode::new(n).into_h()`.
+///
+/// How such a node ends up in the HTML is determined by the handler registered for its type in
+/// the [`ExternalNodeHandlers`] of the engine; nodes without handler are dropped.
+pub struct ExternalNode(Box<dyn DynNode>);
+impl ExternalNode {
+    pub fn new<N: NodeTrait<Types> + 'static>(node: N) -> Self {
+        Self(Box::new(node))
+    }
+    /// The wrapped node, if it is of type `N`.
+    pub fn downcast_ref<N: NodeTrait<Types> + 'static>(&self) -> Option<&N> {
+        self.0.as_any().downcast_ref()
+    }
+    /// The name of the type of the wrapped node.
+    pub fn type_name(&self) -> &'static str {
+        self.0.type_name()
+    }
+}
+impl Clone for ExternalNode {
+    fn clone(&self) -> Self {
+        Self(self.0.clone_box())
+    }
+}
+impl std::fmt::Debug for ExternalNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.dyn_display_fmt(0, f)
+    }
+}
+impl NodeTrait<Types> for ExternalNode {
+    fn height(&self) -> Dim32 {
+        self.0.dyn_height()
+    }
+    fn depth(&self) -> Dim32 {
+        self.0.dyn_depth()
+    }
+    fn width(&self) -> Dim32 {
+        self.0.dyn_width()
+    }
+    fn nodetype(&self) -> NodeType {
+        self.0.dyn_nodetype()
+    }
+    fn display_fmt(&self, indent: usize, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.dyn_display_fmt(indent, f)
+    }
+    fn opaque(&self) -> bool {
+        self.0.dyn_opaque()
+    }
+}
+impl CustomNodeTrait<Types> for ExternalNode {}
+impl From<ExternalNode> for RusTeXNode {
+    fn from(value: ExternalNode) -> Self {
+        Self::External(value)
+    }
+}
+
+type Handler = Box<dyn Fn(&ExternalNode) -> Option<String>>;
+
+/// Turns [`ExternalNode`]s into HTML during shipout, by the type of the wrapped node.
+#[derive(Default)]
+pub struct ExternalNodeHandlers(HMap<TypeId, Handler>);
+impl ExternalNodeHandlers {
+    /// Registers `f` as the handler for nodes of type `N`, replacing the previous one (if any).
+    /// The returned string is inserted verbatim into the HTML output.
+    pub fn register<N: NodeTrait<Types> + 'static>(&mut self, f: impl Fn(&N) -> String + 'static) {
+        self.0.insert(
+            TypeId::of::<N>(),
+            Box::new(move |n| n.downcast_ref::<N>().map(&f)),
+        );
+    }
+    /// The HTML for `node`, or `None` if there is no handler for its type.
+    pub fn render(&self, node: &ExternalNode) -> Option<String> {
+        self.0.get(&node.0.as_any().type_id()).and_then(|f| f(node))
+    }
+}
@@ -1,9 +1,11 @@
 use crate::engine::nodes::RusTeXNode;
+use crate::engine::shading;
 use crate::engine::stomach::RusTeXStomach;
 use crate::engine::{register_command, Refs, Res, Types};
 use crate::utils::VecMap;
+use std::fmt::Write;
 use tex_engine::add_node;
-use tex_engine::commands::{CommandScope, PrimitiveCommand, TeXCommand};
+use tex_engine::commands::{CommandScope, PrimitiveCommand};
 use tex_engine::engine::filesystem::SourceRef;
 use tex_engine::engine::state::State;
 use tex_engine::engine::stomach::Stomach;
@@ -13,6 +15,8 @@ use tex_engine::tex::nodes::boxes::{HBoxInfo, TeXBox, ToOrSpread};
 use tex_engine::tex::nodes::horizontal::HNode;
 use tex_engine::tex::nodes::math::{MathAtom, MathKernel, MathNode, MathNucleus};
 use tex_engine::tex::nodes::vertical::VNode;
+use tex_engine::tex::numerics::Dim32;
+use tex_engine::tex::tokens::token_lists::Otherize;
 use tex_engine::tex::tokens::CompactToken;
 
 pub(crate) fn register_pgf(engine: &mut DefaultEngine<Types>) {
@@ -32,6 +36,12 @@ pub(crate) fn register_pgf(engine: &mut DefaultEngine<Types>) {
         ("rustex!pgf!gend", gend),
         ("rustex!pgf!begin", pgfbegin),
         ("rustex!pgf!end", pgfend),
+        ("rustex!pgf!moveto", pgfmoveto),
+        ("rustex!pgf!lineto", pgflineto),
+        ("rustex!pgf!curveto", pgfcurveto),
+        ("rustex!pgf!rect", pgfrect),
+        ("rustex!pgf!closepath", pgfclosepath),
+        ("rustex!pgf!addtopath", pgfaddtopath),
     ];
     for (s, c) in all {
         engine.state.register_primitive(
@@ -56,6 +66,11 @@ pub(crate) fn register_pgf(engine: &mut DefaultEngine<Types>) {
         "rustex!pgf!flushpath",
         PrimitiveCommand::Expandable(pgfflushpath),
     );
+    engine.state.register_primitive(
+        &mut engine.aux,
+        "rustex!pgf!functionalshading",
+        PrimitiveCommand::Expandable(pgffunctionalshading),
+    );
     register_command(
         engine,
         true,
@@ -107,6 +122,7 @@ fn pgftypesetpicturebox(engine: Refs, tk: CompactToken) -> Res<()> {
         miny,
         maxx,
         maxy,
+        picture: engine.aux.extension.captions.new_picture(),
     };
     match engine.stomach.data_mut().mode() {
         TeXMode::RestrictedHorizontal | TeXMode::Horizontal => {
@@ -133,27 +149,82 @@ fn pgftypesetpicturebox(engine: Refs, tk: CompactToken) -> Res<()> {
 fn pgfliteral(_engine: Refs, _token: CompactToken) -> Res<()> {
     todo!("pgfliteral")
 }
+// Paths are built up in `RusTeXExtension::pgf_path` rather than in a macro: plots may have thousands of points, and appending to a macro copies
+// all of it every time.
+fn path_num(engine: Refs, token: &CompactToken) -> Res<()> {
+    let dim = engine.read_dim(false, token)?;
+    push_num(&mut engine.aux.extension.pgf_path, dim);
+    Ok(())
+}
+/// Numbers in pt, as `\pgf@sys@tonumber` would write them.
+fn push_num(path: &mut String, dim: Dim32) {
+    let s = dim.to_string();
+    path.push_str(s.strip_suffix("pt").unwrap_or(&s));
+    path.push(' ');
+}
+fn path_op(engine: Refs, token: &CompactToken, op: &str, nums: usize) -> Res<()> {
+    engine.aux.extension.pgf_path.push_str(op);
+    engine.aux.extension.pgf_path.push(' ');
+    for _ in 0..nums {
+        path_num(engine, token)?;
+    }
+    Ok(())
+}
+fn pgfmoveto(engine: Refs, token: CompactToken) -> Res<()> {
+    path_op(engine, &token, "M", 2)
+}
+fn pgflineto(engine: Refs, token: CompactToken) -> Res<()> {
+    path_op(engine, &token, "L", 2)
+}
+fn pgfcurveto(engine: Refs, token: CompactToken) -> Res<()> {
+    path_op(engine, &token, "C", 6)
+}
+fn pgfclosepath(engine: Refs, token: CompactToken) -> Res<()> {
+    path_op(engine, &token, "Z", 0)
+}
+fn pgfrect(engine: Refs, token: CompactToken) -> Res<()> {
+    path_op(engine, &token, "M", 2)?;
+    let width = engine.read_dim(false, &token)?;
+    let height = engine.read_dim(false, &token)?;
+    let path = &mut engine.aux.extension.pgf_path;
+    path.push_str("h ");
+    push_num(path, width);
+    path.push_str("v ");
+    push_num(path, height);
+    path.push_str("h ");
+    push_num(path, Dim32(-width.0));
+    path.push_str("Z ");
+    Ok(())
+}
+fn pgfaddtopath(engine: Refs, token: CompactToken) -> Res<()> {
+    let mut s = String::new();
+    engine.read_braced_string(true, true, &token, &mut s)?;
+    engine.aux.extension.pgf_path.push_str(&s);
+    Ok(())
+}
 fn pgfflushpath(engine: Refs, ret: &mut Vec<CompactToken>, _token: CompactToken) -> Res<()> {
-    let path = engine
-        .aux
-        .memory
-        .cs_interner_mut()
-        .cs_from_str("pgf@sys@svgpath");
-    let empty = engine
-        .state
-        .get_command(
-            &engine
-                .aux
-                .memory
-                .cs_interner_mut()
-                .cs_from_str("pgfutil@empty"),
-        )
-        .cloned();
-    match engine.state.get_command(&path) {
-        Some(TeXCommand::Macro(m)) => ret.extend(m.expansion.0.iter().cloned()),
-        _ => todo!("error"),
-    };
-    engine.state.set_command(engine.aux, path, empty, true);
+    let path = std::mem::take(&mut engine.aux.extension.pgf_path);
+    let mut fi = |t| ret.push(t);
+    let mut f = Otherize::new(&mut fi);
+    f.write_str(&path)?;
+    Ok(())
+}
+/// `\rustex!pgf!functionalshading<x0><y0><x1><y1>{<function>}` expands to the shading
+/// as a `data:` URL (or nothing, if the function is not supported).
+fn pgffunctionalshading(engine: Refs, ret: &mut Vec<CompactToken>, token: CompactToken) -> Res<()> {
+    let mut domain = [0.0; 4];
+    for d in &mut domain {
+        let dim = engine.read_dim(false, &token)?;
+        *d = dim.0 as f64 / 65536.0 * 72.0 / 72.27;
+    }
+    let [x0, y0, x1, y1] = domain;
+    let mut code = String::new();
+    engine.read_braced_string(true, true, &token, &mut code)?;
+    if let Some(url) = shading::functional_shading(&code, [x0, x1, y0, y1]) {
+        let mut fi = |t| ret.push(t);
+        let mut f = Otherize::new(&mut fi);
+        f.write_str(&url)?;
+    }
     Ok(())
 }
 fn gbegin(engine: Refs, token: CompactToken) -> Res<()> {
@@ -202,8 +273,14 @@ fn gbegin(engine: Refs, token: CompactToken) -> Res<()> {
             b"href",
             b"fx",
             b"fy",
+            b"x1",
+            b"y1",
+            b"x2",
+            b"y2",
             b"stroke-miterlimit",
             b"patternUnits",
+            b"patternContentUnits",
+            b"preserveAspectRatio",
             b"patternTransform",
             b"markerUnits",
             b"orient",
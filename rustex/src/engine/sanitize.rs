@@ -0,0 +1,162 @@
+//! Policies for raw HTML, CSS and scripts supplied by documents (via `\rustexHTML`,
+//! `\rustex@rawhtml`, `\rustex@cssrule` and `\rustex@requireScript`), so that services compiling untrusted
+//! documents can disable or filter them.
+
+/// The policies applied to raw content of a run.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RawContentPolicy {
+    pub html: RawHTMLPolicy,
+    pub css: RawCSSPolicy,
+    /// whether documents may include scripts (via `\rustex@requireScript` and
+    /// `\rustex@scriptLiteral`); off by default
+    pub scripts: bool,
+}
+
+/// What to do with raw HTML a document wants to insert into the output.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum RawHTMLPolicy {
+    /// drop it entirely
+    Deny,
+    /// keep only known-safe elements, attributes and URL schemes; see [`sanitize_html`]
+    #[default]
+    Sanitize,
+    /// insert it unchanged
+    Allow,
+    /// pass it through a custom filter; `None` drops it
+    Filter(fn(&str) -> Option<String>),
+}
+impl RawHTMLPolicy {
+    /// Applies the policy to a piece of HTML; `None` means it should be dropped.
+    pub fn apply(&self, html: &str) -> Option<String> {
+        match self {
+            Self::Deny => None,
+            Self::Allow => Some(html.to_string()),
+            Self::Sanitize => Some(sanitize_html(html)),
+            Self::Filter(f) => f(html),
+        }
+    }
+}
+
+/// What to do with raw CSS rules a document wants to insert into the output.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum RawCSSPolicy {
+    /// drop them entirely
+    Deny,
+    /// drop rules that load external resources or execute code (`@import`, `url(...)`,
+    /// `image-set(...)`, `expression(...)`, `behavior:`, `javascript:`), also when hidden by
+    /// escapes or comments, and anything that could close the `<style>` element
+    #[default]
+    Sanitize,
+    /// insert them unchanged
+    Allow,
+    /// pass them through a custom filter; `None` drops them
+    Filter(fn(&str) -> Option<String>),
+}
+impl RawCSSPolicy {
+    /// Applies the policy to a CSS rule; `None` means it should be dropped.
+    pub fn apply(&self, css: &str) -> Option<String> {
+        match self {
+            Self::Deny => None,
+            Self::Allow => Some(css.to_string()),
+            Self::Sanitize => sanitize_css(css),
+            Self::Filter(f) => f(css),
+        }
+    }
+}
+
+const FORBIDDEN_CSS: [&str; 5] = [
+    "@import",
+    "url(",
+    "image-set(",
+    "expression(",
+    "javascript:",
+];
+/// forbidden properties; unlike [`FORBIDDEN_CSS`], these only match whole property names, so
+/// that e.g. `scroll-behavior` is allowed
+const FORBIDDEN_PROPERTIES: [&str; 3] = ["behavior:", "-ms-behavior:", "-moz-binding:"];
+
+/// Returns `None` if the CSS contains anything forbidden by [`RawCSSPolicy::Sanitize`];
+/// otherwise the input without `<`, which could otherwise end the surrounding `<style>`.
+/// Escapes and comments are resolved before checking, so that e.g. `u\72l(` and
+/// `@im/**/port` are caught as well.
+pub fn sanitize_css(css: &str) -> Option<String> {
+    let normalized = normalize_css(css);
+    if FORBIDDEN_CSS.iter().any(|f| normalized.contains(f))
+        || FORBIDDEN_PROPERTIES
+            .iter()
+            .any(|p| has_property(&normalized, p))
+    {
+        return None;
+    }
+    Some(css.replace('<', ""))
+}
+
+/// Whether `property` (including the trailing `:`) occurs in the normalized CSS other than as
+/// the suffix of a longer identifier.
+fn has_property(normalized: &str, property: &str) -> bool {
+    normalized.match_indices(property).any(|(i, _)| {
+        !normalized[..i]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    })
+}
+
+/// Lowercases CSS and removes comments, whitespace and escapes (`\72` or `\r` for `r`).
+fn normalize_css(css: &str) -> String {
+    let mut ret = String::with_capacity(css.len());
+    let mut chars = css.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            '\\' => {
+                let mut hex = String::new();
+                while hex.len() < 6 && chars.peek().is_some_and(char::is_ascii_hexdigit) {
+                    hex.extend(chars.next());
+                }
+                if hex.is_empty() {
+                    ret.extend(chars.next());
+                } else {
+                    // a single whitespace character terminates a hex escape
+                    chars.next_if(|c| c.is_whitespace());
+                    ret.push(
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .unwrap_or('\u{FFFD}'),
+                    );
+                }
+            }
+            c => ret.push(c),
+        }
+    }
+    ret.retain(|c| !c.is_whitespace());
+    ret.to_lowercase()
+}
+
+/// Attributes allowed on every element, in addition to [`ammonia`]'s defaults (`lang`, `title`).
+const GENERIC_ATTRIBUTES: [&str; 3] = ["class", "id", "style"];
+
+/// Reduces a piece of HTML to an allowlist of elements, attributes and URL schemes (using
+/// [`ammonia`]); see [`RawHTMLPolicy::Sanitize`]. `style` attributes are subject to
+/// [`sanitize_css`].
+pub fn sanitize_html(html: &str) -> String {
+    ammonia::Builder::default()
+        .add_generic_attributes(GENERIC_ATTRIBUTES)
+        .add_generic_attribute_prefixes(["data-"])
+        .attribute_filter(|_, attr, value| match attr {
+            "style" => sanitize_css(value).map(Into::into),
+            _ => Some(value.into()),
+        })
+        .clean(html)
+        .to_string()
+}
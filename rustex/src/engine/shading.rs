@@ -0,0 +1,302 @@
+//! pgf's functional shadings: the shading is given as a PostScript calculator function (PDF
+//! function type 4) mapping points `x y` (in bp) to `r g b`, which has no SVG counterpart - so we
+//! evaluate it ourselves and embed the result as a PNG.
+
+/// The number of pixels per bp of a shading, and the maximum size in either direction.
+const RESOLUTION: f64 = 1.0;
+const MAX_PIXELS: f64 = 256.0;
+
+#[derive(Clone, Copy, Debug)]
+enum Value {
+    Int(i64),
+    Real(f64),
+    Bool(bool),
+}
+impl Value {
+    fn num(self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(i as f64),
+            Value::Real(f) => Some(f),
+            Value::Bool(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Op<'a> {
+    Push(Value),
+    Proc(Vec<Op<'a>>),
+    Operator(&'a str),
+}
+
+fn parse<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Option<Vec<Op<'a>>> {
+    let mut ret = Vec::new();
+    while let Some(t) = tokens.next() {
+        ret.push(match t {
+            "{" => Op::Proc(parse(tokens)?),
+            "}" => return Some(ret),
+            "true" => Op::Push(Value::Bool(true)),
+            "false" => Op::Push(Value::Bool(false)),
+            _ => match t.parse::<i64>() {
+                Ok(i) => Op::Push(Value::Int(i)),
+                Err(_) => match t.parse::<f64>() {
+                    Ok(f) => Op::Push(Value::Real(f)),
+                    Err(_) => Op::Operator(t),
+                },
+            },
+        })
+    }
+    Some(ret)
+}
+
+/// Evaluates the operators of a calculator function; [`None`] on an error (stack underflow,
+/// type errors, unknown operators).
+fn run(ops: &[Op], stack: &mut Vec<Value>) -> Option<()> {
+    use Value::*;
+    let mut i = 0;
+    while i < ops.len() {
+        match &ops[i] {
+            Op::Push(v) => stack.push(*v),
+            Op::Proc(p) => match (ops.get(i + 1), ops.get(i + 2)) {
+                (Some(Op::Proc(q)), Some(Op::Operator("ifelse"))) => {
+                    let Bool(b) = stack.pop()? else { return None };
+                    run(if b { p } else { q }, stack)?;
+                    i += 2;
+                }
+                (Some(Op::Operator("if")), _) => {
+                    let Bool(b) = stack.pop()? else { return None };
+                    if b {
+                        run(p, stack)?;
+                    }
+                    i += 1;
+                }
+                _ => return None,
+            },
+            Op::Operator(o) => operator(o, stack)?,
+        }
+        i += 1;
+    }
+    Some(())
+}
+
+fn operator(o: &str, stack: &mut Vec<Value>) -> Option<()> {
+    use Value::*;
+    macro_rules! real1 {
+        ($f:expr) => {{
+            let a = stack.pop()?.num()?;
+            stack.push(Real($f(a)))
+        }};
+    }
+    macro_rules! arith {
+        ($int:expr, $real:expr) => {{
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            stack.push(match (a, b) {
+                (Int(a), Int(b)) => $int(a, b)
+                    .map(Int)
+                    .unwrap_or(Real($real(a as f64, b as f64))),
+                _ => Real($real(a.num()?, b.num()?)),
+            })
+        }};
+    }
+    macro_rules! compare {
+        ($op:tt) => {{
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            stack.push(Bool(match (a, b) {
+                (Bool(a), Bool(b)) => a $op b,
+                _ => a.num()? $op b.num()?,
+            }))
+        }};
+    }
+    macro_rules! logic {
+        ($op:tt) => {{
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            stack.push(match (a, b) {
+                (Bool(a), Bool(b)) => Bool(a $op b),
+                (Int(a), Int(b)) => Int(a $op b),
+                _ => return None,
+            })
+        }};
+    }
+    match o {
+        "add" => arith!(i64::checked_add, |a, b| a + b),
+        "sub" => arith!(i64::checked_sub, |a, b| a - b),
+        "mul" => arith!(i64::checked_mul, |a, b| a * b),
+        "div" => {
+            let b = stack.pop()?.num()?;
+            let a = stack.pop()?.num()?;
+            if b == 0.0 {
+                return None;
+            }
+            stack.push(Real(a / b))
+        }
+        "idiv" | "mod" => {
+            let (Int(b), Int(a)) = (stack.pop()?, stack.pop()?) else {
+                return None;
+            };
+            if b == 0 {
+                return None;
+            }
+            stack.push(Int(if o == "idiv" { a / b } else { a % b }))
+        }
+        "neg" => match stack.pop()? {
+            Int(i) => stack.push(Int(i.wrapping_neg())),
+            v => stack.push(Real(-v.num()?)),
+        },
+        "abs" => match stack.pop()? {
+            Int(i) => stack.push(Int(i.wrapping_abs())),
+            v => stack.push(Real(v.num()?.abs())),
+        },
+        "ceiling" | "floor" | "round" | "truncate" => match stack.pop()? {
+            Int(i) => stack.push(Int(i)),
+            v => {
+                let f = v.num()?;
+                stack.push(Real(match o {
+                    "ceiling" => f.ceil(),
+                    "floor" => f.floor(),
+                    // PostScript rounds halves up, not away from zero
+                    "round" => (f + 0.5).floor(),
+                    _ => f.trunc(),
+                }))
+            }
+        },
+        "sqrt" => real1!(f64::sqrt),
+        "sin" => real1!(|a: f64| a.to_radians().sin()),
+        "cos" => real1!(|a: f64| a.to_radians().cos()),
+        "ln" => real1!(f64::ln),
+        "log" => real1!(f64::log10),
+        "exp" => {
+            let b = stack.pop()?.num()?;
+            let a = stack.pop()?.num()?;
+            stack.push(Real(a.powf(b)))
+        }
+        "atan" => {
+            let den = stack.pop()?.num()?;
+            let num = stack.pop()?.num()?;
+            let a = num.atan2(den).to_degrees();
+            stack.push(Real(if a < 0.0 { a + 360.0 } else { a }))
+        }
+        "cvi" => {
+            let f = stack.pop()?.num()?;
+            stack.push(Int(f.trunc() as i64))
+        }
+        "cvr" => {
+            let f = stack.pop()?.num()?;
+            stack.push(Real(f))
+        }
+        "eq" => compare!(==),
+        "ne" => compare!(!=),
+        "gt" => compare!(>),
+        "ge" => compare!(>=),
+        "lt" => compare!(<),
+        "le" => compare!(<=),
+        "and" => logic!(&),
+        "or" => logic!(|),
+        "xor" => logic!(^),
+        "not" => match stack.pop()? {
+            Bool(b) => stack.push(Bool(!b)),
+            Int(i) => stack.push(Int(!i)),
+            Real(_) => return None,
+        },
+        "bitshift" => {
+            let (Int(s), Int(i)) = (stack.pop()?, stack.pop()?) else {
+                return None;
+            };
+            stack.push(Int(if s >= 0 {
+                i.checked_shl(s as u32).unwrap_or(0)
+            } else {
+                i.checked_shr(s.unsigned_abs() as u32).unwrap_or(0)
+            }))
+        }
+        "pop" => {
+            stack.pop()?;
+        }
+        "dup" => stack.push(*stack.last()?),
+        "exch" => {
+            let len = stack.len();
+            if len < 2 {
+                return None;
+            }
+            stack.swap(len - 1, len - 2)
+        }
+        "copy" => {
+            let Int(n) = stack.pop()? else { return None };
+            let len = stack.len();
+            let n = usize::try_from(n).ok().filter(|n| *n <= len)?;
+            stack.extend_from_within(len - n..)
+        }
+        "index" => {
+            let Int(n) = stack.pop()? else { return None };
+            let n = usize::try_from(n).ok().filter(|n| *n < stack.len())?;
+            stack.push(stack[stack.len() - 1 - n])
+        }
+        "roll" => {
+            let (Int(j), Int(n)) = (stack.pop()?, stack.pop()?) else {
+                return None;
+            };
+            let len = stack.len();
+            let n = usize::try_from(n).ok().filter(|n| *n <= len)?;
+            if n > 0 {
+                let j = j.rem_euclid(n as i64) as usize;
+                stack[len - n..].rotate_right(j)
+            }
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+/// Renders the function `code` over `domain` (`[x0, x1, y0, y1]`, in bp) and returns it as a
+/// `data:` URL of a PNG, whose first row is the *top* of the shading (`y1`); [`None`] if the
+/// function can not be evaluated.
+pub(crate) fn functional_shading(code: &str, domain: [f64; 4]) -> Option<String> {
+    let code = code.replace('{', " { ").replace('}', " } ");
+    let ops = parse(&mut code.split_ascii_whitespace())?;
+    let [x0, x1, y0, y1] = domain;
+    let pixels = |d: f64| (d.abs() * RESOLUTION).ceil().clamp(1.0, MAX_PIXELS) as u32;
+    let (width, height) = (pixels(x1 - x0), pixels(y1 - y0));
+    let mut buf = Vec::with_capacity((width * height * 3) as usize);
+    let mut stack = Vec::new();
+    for row in 0..height {
+        let y = y1 - (y1 - y0) * (row as f64 + 0.5) / height as f64;
+        for col in 0..width {
+            let x = x0 + (x1 - x0) * (col as f64 + 0.5) / width as f64;
+            stack.clear();
+            stack.push(Value::Real(x));
+            stack.push(Value::Real(y));
+            run(&ops, &mut stack)?;
+            let rgb = match stack.as_slice() {
+                [.., r, g, b] => [r.num()?, g.num()?, b.num()?],
+                [.., g] => [g.num()?; 3],
+                [] => return None,
+            };
+            buf.extend(rgb.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8));
+        }
+    }
+    let img = image::RgbImage::from_raw(width, height, buf)?;
+    let mut png = Vec::new();
+    img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .ok()?;
+    Some(format!("data:image/png;base64,{}", base64(&png)))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut ret = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(CHARS[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                ret.push('=');
+            }
+        }
+    }
+    ret
+}
@@ -128,6 +128,22 @@ impl State<Types> for RusTeXState {
         self.0.set_catcode(aux, c, cc, globally)
     }
 
+    fn get_catcode_table(&self) -> usize {
+        self.0.get_catcode_table()
+    }
+
+    fn set_catcode_table(&mut self, aux: &EngineAux<Types>, idx: usize, globally: bool) -> bool {
+        self.0.set_catcode_table(aux, idx, globally)
+    }
+
+    fn save_catcode_table(&mut self, idx: usize) {
+        self.0.save_catcode_table(idx)
+    }
+
+    fn init_catcode_table(&mut self, idx: usize) {
+        self.0.init_catcode_table(idx)
+    }
+
     fn get_sfcode(&self, c: u8) -> u16 {
         self.0.get_sfcode(c)
     }
@@ -168,6 +184,29 @@ impl State<Types> for RusTeXState {
         self.0.set_mathcode(aux, c, mathcode, globally)
     }
 
+    fn get_charclass(&self, c: u8) -> u16 {
+        self.0.get_charclass(c)
+    }
+
+    fn set_charclass(&mut self, aux: &EngineAux<Types>, c: u8, class: u16, globally: bool) {
+        self.0.set_charclass(aux, c, class, globally)
+    }
+
+    fn get_interchar_toks(&self, from: u16, to: u16) -> &TokenList<CompactToken> {
+        self.0.get_interchar_toks(from, to)
+    }
+
+    fn set_interchar_toks(
+        &mut self,
+        aux: &EngineAux<Types>,
+        from: u16,
+        to: u16,
+        v: TokenList<CompactToken>,
+        globally: bool,
+    ) {
+        self.0.set_interchar_toks(aux, from, to, v, globally)
+    }
+
     fn get_endline_char(&self) -> Option<u8> {
         self.0.get_endline_char()
     }
@@ -360,6 +399,9 @@ impl State<Types> for RusTeXState {
     fn get_command(&self, name: &CSName) -> Option<&TeXCommand<Types>> {
         self.0.get_command(name)
     }
+    fn iter_commands(&self) -> impl Iterator<Item = (CSName, &TeXCommand<Types>)> {
+        self.0.iter_commands()
+    }
 
     fn set_command(
         &mut self,
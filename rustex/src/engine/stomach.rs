@@ -4,15 +4,17 @@ use crate::engine::{Font, Refs, Res, Types};
 use tex_engine::commands::primitives::PRIMITIVES;
 use tex_engine::commands::{PrimitiveCommand, TeXCommand};
 use tex_engine::engine::filesystem::{File, SourceReference};
+use tex_engine::engine::fontsystem::Font as FontT;
 use tex_engine::engine::mouth::Mouth;
 use tex_engine::engine::state::{GroupType, State};
 use tex_engine::engine::stomach::methods::{
-    insert_afterassignment, split_paragraph_roughly, ParLine, ParLineSpec, SplitResult,
+    insert_afterassignment, space_glue, split_paragraph_roughly, ParLine, ParLineSpec, SplitResult,
 };
 use tex_engine::engine::stomach::{Stomach, StomachData};
 use tex_engine::engine::{EngineAux, EngineReferences, EngineTypes};
+use tex_engine::pdflatex::nodes::{NumOrName, PDFDest, PDFDestType, PDFNode};
 use tex_engine::prelude::*;
-use tex_engine::tex::nodes::boxes::{BoxType, ToOrSpread};
+use tex_engine::tex::nodes::boxes::{BoxType, TeXBox, ToOrSpread};
 use tex_engine::tex::nodes::horizontal::{HNode, HorizontalNodeListType};
 use tex_engine::tex::nodes::math::{MathAtom, MathNode, MathNucleus};
 use tex_engine::tex::nodes::vertical::{VNode, VerticalNodeListType};
@@ -47,6 +49,19 @@ impl Stomach<Types> for RusTeXStomach {
         &mut self.data
     }
 
+    /// A space stays a space (so that the HTML text keeps it); if the space factor, `\spaceskip`
+    /// or `\xspaceskip` make it wider or narrower than the font's interword space, a kern for the
+    /// difference precedes it.
+    fn add_space(engine: Refs) {
+        if let Some(glue) = space_glue(engine) {
+            let diff = glue.base - engine.state.get_current_font().get_dim(1);
+            if diff != Dim32(0) {
+                Self::add_node_h(engine, HNode::HKern(diff));
+            }
+        }
+        Self::add_node_h(engine, HNode::Space)
+    }
+
     fn split_vertical(engine: Refs, nodes: Vec<VNode<Types>>, target: Dim32) -> SplitResult<Types> {
         vsplit(engine, nodes, target)
     }
@@ -69,7 +84,14 @@ impl Stomach<Types> for RusTeXStomach {
     }
 
     fn close_box(engine: &mut EngineReferences<Types>, bt: BoxType) -> Res<()> {
-        let markers = std::mem::take(engine.aux.extension.change_markers.last_mut().unwrap());
+        let Some(markers) = engine.aux.extension.change_markers.last_mut() else {
+            return Err(engine
+                .aux
+                .hardening
+                .violation("box closed outside of a group")
+                .into());
+        };
+        let markers = std::mem::take(markers);
         for _ in 0..markers {
             tex_engine::add_node!(Self;engine,
                             VNode::Custom(RusTeXNode::FontChangeEnd),
@@ -89,6 +111,7 @@ impl Stomach<Types> for RusTeXStomach {
         if children.is_empty() {
             return Ok(());
         }
+        let children = crate::engine::cjk::insert_breakpoints(engine, children);
         let ret = split_paragraph_roughly(engine, specs.clone(), children, sourceref.clone());
         engine.stomach.prevent_shipout = true;
         Self::add_node_v(
@@ -105,7 +128,13 @@ impl Stomach<Types> for RusTeXStomach {
         for line in ret {
             match line {
                 ParLine::Adjust(n) => redo.push(n),
-                ParLine::Line(bx) => Self::add_node_v(engine, VNode::Box(bx))?,
+                ParLine::Line(mut bx) => {
+                    if engine.aux.extension.line_numbers {
+                        number_line(engine, &mut bx)
+                    }
+                    Self::add_node_v(engine, VNode::Box(bx))?
+                }
+                ParLine::Penalty(p) => Self::add_node_v(engine, VNode::Penalty(p))?,
             }
         }
         engine.stomach.prevent_shipout = false;
@@ -237,23 +266,11 @@ pub fn vsplit(engine: Refs, mut nodes: Vec<VNode<Types>>, mut target: Dim32) ->
     data.firstmarks.clear();
     data.splitfirstmarks.clear();
     data.splitbotmarks.clear();
-    let mut in_par = None;
     let mut split = nodes.len();
     let iter = nodes.iter().enumerate();
     for (i, n) in iter {
         match n {
-            VNode::Custom(r @ RusTeXNode::ParagraphBegin { .. }) => {
-                in_par = Some(r.clone());
-            }
-            VNode::Custom(RusTeXNode::ParagraphEnd) => {
-                in_par = None;
-            }
-            VNode::Mark(i, v) => {
-                if !data.firstmarks.contains_key(&i) {
-                    data.firstmarks.insert(*i, v.clone());
-                }
-                data.botmarks.insert(*i, v.clone());
-            }
+            VNode::Custom(_) | VNode::Mark(..) => (),
             VNode::Insert(_, bx) => {
                 target = target - bx.iter().map(|c| c.height() + c.depth()).sum(); // - n.depth() ?
                 if target < Dim32(0) {
@@ -270,6 +287,38 @@ pub fn vsplit(engine: Refs, mut nodes: Vec<VNode<Types>>, mut target: Dim32) ->
             }
         }
     }
+    // a break right after an unbreakable penalty (e.g. a `\widowpenalty` or `\clubpenalty` of
+    // 10000 between two lines) moves up to the last penalty or glue where breaking is allowed
+    if split < nodes.len() && split > 0 {
+        if let VNode::Penalty(p) = nodes[split - 1] {
+            if p >= 10000 {
+                let allowed = (1..split - 1).rev().find(|&i| match &nodes[i] {
+                    VNode::Penalty(p) => *p < 10000,
+                    VNode::VSkip(_) | VNode::VFil | VNode::VFill | VNode::Vss => {
+                        !nodes[i - 1].discardable()
+                    }
+                    _ => false,
+                });
+                if let Some(i) = allowed {
+                    split = i;
+                }
+            }
+        }
+    }
+    let mut in_par = None;
+    for n in &nodes[..split] {
+        match n {
+            VNode::Custom(r @ RusTeXNode::ParagraphBegin { .. }) => in_par = Some(r.clone()),
+            VNode::Custom(RusTeXNode::ParagraphEnd) => in_par = None,
+            VNode::Mark(i, v) => {
+                if !data.firstmarks.contains_key(i) {
+                    data.firstmarks.insert(*i, v.clone());
+                }
+                data.botmarks.insert(*i, v.clone());
+            }
+            _ => (),
+        }
+    }
     let mut rest = nodes.split_off(split);
     if let Some(b) = in_par {
         rest.insert(0, VNode::Custom(b));
@@ -302,6 +351,30 @@ pub fn vsplit(engine: Refs, mut nodes: Vec<VNode<Types>>, mut target: Dim32) ->
     }
 }
 
+/// Starts the paragraph line `bx` with an anchor `line-<n>`, where `<n>` is the value of
+/// lineno's counter `linenumber`, which is then stepped (globally, like `\stepcounter`).
+fn number_line(engine: Refs, bx: &mut TeXBox<Types>) {
+    let Some(&TeXCommand::IntRegister(idx)) =
+        engine.state.get_command(&engine.aux.extension.linenumber)
+    else {
+        return;
+    };
+    let n = engine.state.get_int_register(idx);
+    engine.state.set_int_register(engine.aux, idx, n + 1, true);
+    if let TeXBox::H { children, .. } = bx {
+        let mut nodes = std::mem::take(children).into_vec();
+        nodes.insert(
+            0,
+            HNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFDest(PDFDest {
+                structnum: None,
+                id: NumOrName::Name(format!("line-{n}")),
+                dest: PDFDestType::XYZ { zoom: None },
+            }))),
+        );
+        *children = nodes.into();
+    }
+}
+
 fn do_shipout<F: FnOnce(&mut StomachData<Types>)>(
     engine: &mut EngineReferences<Types>,
     penalty: Option<i32>,
@@ -317,27 +390,29 @@ fn do_shipout<F: FnOnce(&mut StomachData<Types>)>(
             )
         };
     }
-    set_empty!(oddhead);
-    set_empty!(oddfoot);
-    set_empty!(evenhead);
-    set_empty!(evenfoot);
-    engine.state.set_command(
-        &engine.aux,
-        engine.aux.extension.mkboth,
-        Some(TeXCommand::Macro(engine.aux.extension.gobbletwo.clone())),
-        true,
-    );
+    if !engine.aux.extension.running_heads.emit {
+        set_empty!(oddhead);
+        set_empty!(oddfoot);
+        set_empty!(evenhead);
+        set_empty!(evenfoot);
+        engine.state.set_command(
+            &engine.aux,
+            engine.aux.extension.mkboth,
+            Some(TeXCommand::Macro(engine.aux.extension.gobbletwo.clone())),
+            true,
+        );
 
-    let iffalse = TeXCommand::Primitive {
-        cmd: PrimitiveCommand::Conditional(tex_engine::commands::tex::iffalse::<Types>),
-        name: PRIMITIVES.iffalse,
-    };
-    engine.state.set_command(
-        &engine.aux,
-        engine.aux.extension.specialpage,
-        Some(iffalse),
-        true,
-    );
+        let iffalse = TeXCommand::Primitive {
+            cmd: PrimitiveCommand::Conditional(tex_engine::commands::tex::iffalse::<Types>),
+            name: PRIMITIVES.iffalse,
+        };
+        engine.state.set_command(
+            &engine.aux,
+            engine.aux.extension.specialpage,
+            Some(iffalse),
+            true,
+        );
+    }
     let data = engine.stomach.data_mut();
     data.page.insert(0, VNode::Custom(RusTeXNode::PageBegin));
     f(data);
@@ -0,0 +1,583 @@
+//! EPUB 3 output (see [`CompilationResult::write_epub`](crate::engine::CompilationResult::write_epub)):
+//! the HTML shipout, chunked into one XHTML content document per unit (as for
+//! [`Settings::split_at`](crate::engine::Settings::split_at); chapters by default), together with
+//! the package document, a navigation document and all assets, zipped into a single file that
+//! e-readers can open directly.
+
+use crate::shipout::utils::escape_html;
+use crate::sink::MemorySink;
+use crate::RUSTEX_CSS_URL;
+use std::io::Write;
+
+static RUSTEX_CSS: &str = include_str!("resources/rustex.css");
+
+/// Metadata and options for [`CompilationResult::write_epub`](crate::engine::CompilationResult::write_epub).
+#[derive(Debug, Clone)]
+pub struct EpubOptions {
+    pub title: String,
+    /// a BCP 47 language tag
+    pub language: String,
+    /// the unique identifier of the publication; derived from the title if `None`
+    pub identifier: Option<String>,
+    /// no HTTP client is bundled; supply one to embed remote stylesheets (i.e. the web fonts)
+    /// into the publication, rather than linking to them
+    pub fetch: Option<fn(&str) -> Option<Vec<u8>>>,
+    /// the `dcterms:modified` date; `SOURCE_DATE_EPOCH` or the current time if `None`
+    pub modified: Option<chrono::DateTime<chrono::Utc>>,
+}
+impl Default for EpubOptions {
+    fn default() -> Self {
+        Self {
+            title: "Untitled".to_string(),
+            language: "en".to_string(),
+            identifier: None,
+            fetch: None,
+            modified: None,
+        }
+    }
+}
+
+/// A unit of the publication: file name, title and sectioning level, as in the
+/// [table of contents](crate::shipout::split::table_of_contents) of split outputs.
+pub(crate) type Unit = (String, String, Option<i32>);
+
+/// Zips the pages (in reading order) and assets of `sink` into an EPUB container; `toc` lists
+/// the pages to include in the navigation document.
+pub(crate) fn package(
+    sink: &MemorySink,
+    toc: &[Unit],
+    options: &EpubOptions,
+) -> std::io::Result<Vec<u8>> {
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut spine = Vec::new();
+    let mut remote = Remote::default();
+    for (name, html) in &sink.pages {
+        let mut page = xhtml(&html.replace(RUSTEX_CSS_URL, "rustex.css"));
+        if let Some(fetch) = options.fetch {
+            page = remote.embed(&page, fetch, &mut files);
+        }
+        let mut properties = Vec::new();
+        if page.contains("<math") {
+            properties.push("mathml");
+        }
+        if page.contains("<svg") {
+            properties.push("svg");
+        }
+        if page.contains("href=\"http") || page.contains("src=\"http") {
+            properties.push("remote-resources");
+        }
+        if page.contains("<script") {
+            properties.push("scripted");
+        }
+        spine.push((name.clone(), properties.join(" ")));
+        files.push((name.clone(), page.into_bytes()));
+    }
+    files.push(("rustex.css".to_string(), RUSTEX_CSS.as_bytes().to_vec()));
+    for (name, content) in &sink.assets {
+        files.push((name.clone(), content.clone()));
+    }
+
+    let identifier = options.identifier.clone().unwrap_or_else(|| {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        options.title.hash(&mut hasher);
+        format!("urn:rustex:{:016x}", hasher.finish())
+    });
+    let mut opf = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" \
+         unique-identifier=\"uid\" xml:lang=\"{lang}\">\n\
+         <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+         \t<dc:identifier id=\"uid\">{}</dc:identifier>\n\
+         \t<dc:title>{}</dc:title>\n\
+         \t<dc:language>{lang}</dc:language>\n\
+         \t<meta property=\"dcterms:modified\">{}</meta>\n\
+         </metadata>\n<manifest>\n\
+         \t<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" \
+         properties=\"nav\"/>\n",
+        escape_html(&identifier),
+        escape_html(&options.title),
+        options
+            .modified
+            .or_else(tex_engine::engine::source_date_epoch)
+            .unwrap_or_else(chrono::Utc::now)
+            .format("%Y-%m-%dT%H:%M:%SZ"),
+        lang = escape_html(&options.language),
+    );
+    for (i, (name, _)) in files.iter().enumerate() {
+        let properties = spine
+            .iter()
+            .find(|(n, p)| n == name && !p.is_empty())
+            .map(|(_, p)| format!(" properties=\"{p}\""))
+            .unwrap_or_default();
+        opf.push_str(&format!(
+            "\t<item id=\"item{i}\" href=\"{}\" media-type=\"{}\"{properties}/>\n",
+            escape_html(name),
+            media_type(name)
+        ));
+    }
+    opf.push_str("</manifest>\n<spine>\n");
+    for (name, _) in &spine {
+        if let Some(i) = files.iter().position(|(n, _)| n == name) {
+            opf.push_str(&format!("\t<itemref idref=\"item{i}\"/>\n"));
+        }
+    }
+    opf.push_str("</spine>\n</package>\n");
+
+    let mut zip = Zip::default();
+    // the mimetype must come first, and uncompressed
+    zip.add("mimetype", b"application/epub+zip", false)?;
+    zip.add(
+        "META-INF/container.xml",
+        b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+          <container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n\
+          \t<rootfiles><rootfile full-path=\"OEBPS/content.opf\" \
+          media-type=\"application/oebps-package+xml\"/></rootfiles>\n\
+          </container>\n",
+        true,
+    )?;
+    zip.add("OEBPS/content.opf", opf.as_bytes(), true)?;
+    zip.add(
+        "OEBPS/nav.xhtml",
+        navigation(toc, &options.title, &options.language).as_bytes(),
+        true,
+    )?;
+    for (name, content) in &files {
+        let compress = !matches!(
+            name.rsplit_once('.').map(|(_, e)| e),
+            Some("png" | "jpg" | "jpeg" | "gif" | "webp" | "woff" | "woff2")
+        );
+        zip.add(&format!("OEBPS/{name}"), content, compress)?;
+    }
+    zip.finish()
+}
+
+/// The navigation document, with the units nested by their sectioning levels.
+fn navigation(toc: &[Unit], title: &str, language: &str) -> String {
+    let mut ret = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" \
+         lang=\"{lang}\" xml:lang=\"{lang}\">\n<head><meta charset=\"UTF-8\"/><title>{title}</title>\
+         </head>\n<body>\n<nav epub:type=\"toc\" id=\"toc\"><h1>{title}</h1>\n<ol>",
+        lang = escape_html(language),
+        title = escape_html(title)
+    );
+    let min = toc.iter().filter_map(|u| u.2).min().unwrap_or(0);
+    let mut levels = Vec::new();
+    for (i, (file, title, level)) in toc.iter().enumerate() {
+        let level = level.unwrap_or(min);
+        if i > 0 {
+            if levels.last().is_some_and(|l| level > *l) {
+                ret.push_str("<ol>");
+            } else {
+                ret.push_str("</li>");
+                while levels.len() > 1 && levels.last().is_some_and(|l| level < *l) {
+                    levels.pop();
+                    ret.push_str("</ol></li>");
+                }
+                levels.pop();
+            }
+        }
+        levels.push(level);
+        ret.push_str(&format!(
+            "\n<li><a href=\"{}\">{}</a>",
+            escape_html(file),
+            // already escaped
+            title
+        ));
+    }
+    if !toc.is_empty() {
+        ret.push_str("</li>");
+    }
+    for _ in 1..levels.len() {
+        ret.push_str("</ol></li>");
+    }
+    ret.push_str("\n</ol></nav>\n</body>\n</html>\n");
+    ret
+}
+
+fn media_type(name: &str) -> &'static str {
+    match name
+        .rsplit_once('.')
+        .map(|(_, e)| e.to_ascii_lowercase())
+        .as_deref()
+    {
+        Some("xhtml" | "html" | "htm") => "application/xhtml+xml",
+        Some("css") => "text/css",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("js") => "application/javascript",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Remote stylesheets (and the fonts they reference), downloaded into the publication.
+#[derive(Default)]
+struct Remote {
+    /// remote URL -> local name
+    fetched: Vec<(String, Option<String>)>,
+}
+impl Remote {
+    fn embed(
+        &mut self,
+        page: &str,
+        fetch: fn(&str) -> Option<Vec<u8>>,
+        files: &mut Vec<(String, Vec<u8>)>,
+    ) -> String {
+        let mut ret = String::new();
+        let mut rest = page;
+        while let Some(i) = rest.find("<link ") {
+            let end = rest[i..].find('>').map_or(rest.len(), |e| i + e + 1);
+            let tag = &rest[i..end];
+            ret.push_str(&rest[..i]);
+            rest = &rest[end..];
+            let href = tag
+                .split_once("href=\"")
+                .and_then(|(_, h)| h.split_once('"'))
+                .map(|(h, _)| h)
+                .filter(|h| h.starts_with("http://") || h.starts_with("https://"));
+            match href.and_then(|h| self.stylesheet(h, fetch, files)) {
+                Some(local) => ret.push_str(&tag.replace(href.unwrap_or_default(), &local)),
+                None => ret.push_str(tag),
+            }
+        }
+        ret.push_str(rest);
+        ret
+    }
+    fn stylesheet(
+        &mut self,
+        url: &str,
+        fetch: fn(&str) -> Option<Vec<u8>>,
+        files: &mut Vec<(String, Vec<u8>)>,
+    ) -> Option<String> {
+        if let Some((_, local)) = self.fetched.iter().find(|(u, _)| u == url) {
+            return local.clone();
+        }
+        let local = fetch(url)
+            .and_then(|css| String::from_utf8(css).ok())
+            .map(|css| {
+                let css = self.fonts(&css, url, fetch, files);
+                let name = format!("styles/{}.css", self.fetched.len());
+                files.push((name.clone(), css.into_bytes()));
+                name
+            });
+        self.fetched.push((url.to_string(), local.clone()));
+        local
+    }
+    /// Downloads the resources referenced by `url(...)` in a stylesheet and makes the
+    /// references relative to `styles/`.
+    fn fonts(
+        &mut self,
+        css: &str,
+        base: &str,
+        fetch: fn(&str) -> Option<Vec<u8>>,
+        files: &mut Vec<(String, Vec<u8>)>,
+    ) -> String {
+        let mut ret = String::new();
+        let mut rest = css;
+        while let Some(i) = rest.find("url(") {
+            ret.push_str(&rest[..i + 4]);
+            rest = &rest[i + 4..];
+            let Some(end) = rest.find(')') else { break };
+            let target = rest[..end].trim().trim_matches(|c| c == '"' || c == '\'');
+            let local = (!target.starts_with("data:"))
+                .then(|| resolve(base, target))
+                .and_then(|url| {
+                    if let Some((_, local)) = self.fetched.iter().find(|(u, _)| *u == url) {
+                        return local.clone();
+                    }
+                    let file = url.rsplit('/').next().unwrap_or_default();
+                    let file = file.split(['?', '#']).next().unwrap_or_default();
+                    let local = fetch(&url).map(|data| {
+                        let name = format!("fonts/{}-{file}", self.fetched.len());
+                        files.push((name.clone(), data));
+                        name
+                    });
+                    self.fetched.push((url, local.clone()));
+                    local
+                });
+            match local {
+                Some(local) => ret.push_str(&format!("\"../{local}\"")),
+                None => ret.push_str(&rest[..end]),
+            }
+            rest = &rest[end..];
+        }
+        ret.push_str(rest);
+        ret
+    }
+}
+
+/// Resolves a (possibly relative) URL against the URL of the stylesheet it occurs in.
+fn resolve(base: &str, url: &str) -> String {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return url.to_string();
+    }
+    let scheme = base.split_once("//").map_or("https:", |(s, _)| s);
+    if let Some(rest) = url.strip_prefix("//") {
+        return format!("{scheme}//{rest}");
+    }
+    if url.starts_with('/') {
+        let host = base
+            .split_once("//")
+            .map_or(base, |(_, r)| r)
+            .split('/')
+            .next()
+            .unwrap_or_default();
+        return format!("{scheme}//{host}{url}");
+    }
+    match base.rsplit_once('/') {
+        Some((dir, _)) => format!("{dir}/{url}"),
+        None => url.to_string(),
+    }
+}
+
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Turns the HTML produced by RusTeX into well-formed XHTML, as required for EPUB content
+/// documents: void elements are closed, attributes quoted and given values, named character
+/// references other than XML's replaced by numeric ones, and namespaces added to the `html`,
+/// `svg` and `math` elements.
+pub(crate) fn xhtml(html: &str) -> String {
+    let mut ret = String::with_capacity(html.len() + html.len() / 16);
+    let mut rest = html;
+    while let Some(i) = rest.find(['<', '&']) {
+        ret.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with('&') {
+            rest = entity(rest, &mut ret);
+        } else if let Some(r) = rest.strip_prefix("<!--") {
+            let end = r.find("-->").map_or(r.len(), |e| e + 3);
+            ret.push_str("<!--");
+            ret.push_str(&r[..end]);
+            rest = &r[end..];
+        } else if rest.starts_with("<!") || rest.starts_with("</") || rest.starts_with("<?") {
+            let end = rest.find('>').map_or(rest.len(), |e| e + 1);
+            ret.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            let (name, r) = tag(rest, &mut ret);
+            rest = r;
+            if name == "script" || name == "style" {
+                let close = format!("</{name}>");
+                let end = rest.find(&close).unwrap_or(rest.len());
+                let content = &rest[..end];
+                if content.contains(['<', '&']) {
+                    ret.push_str("/*<![CDATA[*/");
+                    ret.push_str(&content.replace("]]>", "]]]]><![CDATA[>"));
+                    ret.push_str("/*]]>*/");
+                } else {
+                    ret.push_str(content);
+                }
+                rest = &rest[end..];
+            }
+        } else {
+            ret.push_str("&lt;");
+            rest = &rest[1..];
+        }
+    }
+    ret.push_str(rest);
+    ret
+}
+
+/// Copies the start tag at the beginning of `s` to `out`; returns its name and the rest.
+fn tag<'a>(s: &'a str, out: &mut String) -> (String, &'a str) {
+    let mut chars = s[1..].char_indices().peekable();
+    let mut name = String::new();
+    while let Some((_, c)) = chars.next_if(|(_, c)| !c.is_whitespace() && *c != '>' && *c != '/') {
+        name.push(c);
+    }
+    let lower = name.to_ascii_lowercase();
+    out.push('<');
+    out.push_str(&name);
+    let mut has_xmlns = false;
+    let mut attrs = String::new();
+    let end;
+    loop {
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        match chars.next() {
+            None => {
+                end = s.len();
+                break;
+            }
+            Some((i, '>')) => {
+                end = i + 2;
+                break;
+            }
+            Some((_, '/')) => (),
+            Some((_, c)) => {
+                let mut key = String::from(c);
+                while let Some((_, c)) =
+                    chars.next_if(|(_, c)| !c.is_whitespace() && !matches!(c, '=' | '>' | '/'))
+                {
+                    key.push(c);
+                }
+                while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+                let mut value = String::new();
+                if chars.next_if(|(_, c)| *c == '=').is_some() {
+                    while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+                    match chars.peek().map(|(_, c)| *c) {
+                        Some(q @ ('"' | '\'')) => {
+                            chars.next();
+                            for (_, c) in chars.by_ref() {
+                                if c == q {
+                                    break;
+                                }
+                                value.push(c);
+                            }
+                        }
+                        _ => {
+                            while let Some((_, c)) =
+                                chars.next_if(|(_, c)| !c.is_whitespace() && *c != '>')
+                            {
+                                value.push(c);
+                            }
+                        }
+                    }
+                } else {
+                    value = key.clone();
+                }
+                has_xmlns |= key == "xmlns";
+                attrs.push(' ');
+                attrs.push_str(&key);
+                attrs.push_str("=\"");
+                let mut v = String::new();
+                let mut r = value.as_str();
+                while let Some(i) = r.find('&') {
+                    v.push_str(&r[..i]);
+                    r = entity(&r[i..], &mut v);
+                }
+                v.push_str(r);
+                attrs.push_str(&v.replace('<', "&lt;").replace('"', "&quot;"));
+                attrs.push('"');
+            }
+        }
+    }
+    if !has_xmlns {
+        match lower.as_str() {
+            "html" => out.push_str(" xmlns=\"http://www.w3.org/1999/xhtml\""),
+            "svg" => out.push_str(
+                " xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\"",
+            ),
+            "math" => out.push_str(" xmlns=\"http://www.w3.org/1998/Math/MathML\""),
+            _ => (),
+        }
+    }
+    out.push_str(&attrs);
+    let self_closing = s[..end].trim_end_matches('>').ends_with('/');
+    if self_closing || VOID_ELEMENTS.contains(&lower.as_str()) {
+        out.push_str("/>");
+    } else {
+        out.push('>');
+    }
+    (lower, &s[end..])
+}
+
+/// Copies the character reference at the beginning of `s` to `out`, replacing named
+/// references unknown to XML by numeric ones (or escaping the `&` if it is none).
+fn entity<'a>(s: &'a str, out: &mut String) -> &'a str {
+    let len = s[1..]
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '#')
+        .map_or(s.len() - 1, |i| i);
+    let name = &s[1..1 + len];
+    if !s[1 + len..].starts_with(';') || name.is_empty() {
+        out.push_str("&amp;");
+        return &s[1..];
+    }
+    let code = match name {
+        "amp" | "lt" | "gt" | "quot" | "apos" => None,
+        n if n.starts_with('#') => None,
+        "nbsp" => Some(160),
+        "shy" => Some(173),
+        "ensp" => Some(8194),
+        "emsp" => Some(8195),
+        "thinsp" => Some(8201),
+        "zwnj" => Some(8204),
+        "zwj" => Some(8205),
+        "ndash" => Some(8211),
+        "mdash" => Some(8212),
+        "hellip" => Some(8230),
+        "copy" => Some(169),
+        _ => {
+            out.push_str("&amp;");
+            return &s[1..];
+        }
+    };
+    match code {
+        Some(c) => out.push_str(&format!("&#{c};")),
+        None => out.push_str(&s[..len + 2]),
+    }
+    &s[len + 2..]
+}
+
+/// A minimal ZIP writer (stored or deflated entries, no timestamps).
+#[derive(Default)]
+struct Zip {
+    out: Vec<u8>,
+    central: Vec<u8>,
+    entries: u16,
+}
+impl Zip {
+    fn add(&mut self, name: &str, data: &[u8], compress: bool) -> std::io::Result<()> {
+        let mut crc = flate2::Crc::new();
+        crc.update(data);
+        let (method, stored): (u16, std::borrow::Cow<[u8]>) = if compress {
+            let mut enc =
+                flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+            enc.write_all(data)?;
+            (8, enc.finish()?.into())
+        } else {
+            (0, data.into())
+        };
+        let offset = self.out.len() as u32;
+        // version, flags (UTF-8 names), method, time, date (1980-01-01), crc, sizes
+        let mut common = Vec::new();
+        common.extend_from_slice(&20u16.to_le_bytes());
+        common.extend_from_slice(&(1u16 << 11).to_le_bytes());
+        common.extend_from_slice(&method.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&0x21u16.to_le_bytes());
+        common.extend_from_slice(&crc.sum().to_le_bytes());
+        common.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+
+        self.out.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        self.out.extend_from_slice(&common);
+        self.out.extend_from_slice(name.as_bytes());
+        self.out.extend_from_slice(&stored);
+
+        self.central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        self.central.extend_from_slice(&20u16.to_le_bytes());
+        self.central.extend_from_slice(&common);
+        // comment length, disk, internal and external attributes
+        self.central.extend_from_slice(&[0; 10]);
+        self.central.extend_from_slice(&offset.to_le_bytes());
+        self.central.extend_from_slice(name.as_bytes());
+        self.entries += 1;
+        Ok(())
+    }
+    fn finish(mut self) -> std::io::Result<Vec<u8>> {
+        let offset = self.out.len() as u32;
+        let size = self.central.len() as u32;
+        self.out.append(&mut self.central);
+        self.out.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        self.out.extend_from_slice(&[0; 4]);
+        self.out.extend_from_slice(&self.entries.to_le_bytes());
+        self.out.extend_from_slice(&self.entries.to_le_bytes());
+        self.out.extend_from_slice(&size.to_le_bytes());
+        self.out.extend_from_slice(&offset.to_le_bytes());
+        self.out.extend_from_slice(&0u16.to_le_bytes());
+        Ok(self.out)
+    }
+}
@@ -0,0 +1,103 @@
+//! Support for the `glossaries` package without `makeglossaries`: the glossaries declared in
+//! the `.aux` files (`\@newglossary`) are sorted with [`makeindex`](crate::makeindex), using
+//! the style file the package writes (`\@istfilename`), and the resulting `.gls`/`.acr`/...
+//! files are made available to the next pass.
+
+use crate::bibtex::command_arg;
+use crate::engine::files::{AuxFiles, RusTeXFileSystem};
+use crate::makeindex::{make_ind, parse_idx, Collation, IndexStyle};
+use std::cmp::Ordering;
+use std::path::Path;
+
+/// The glossary-related data of the `.aux` files of a document.
+#[derive(Debug, Clone, Default)]
+pub struct GlossaryInfo {
+    /// (name, output extension, input extension) of every glossary
+    pub glossaries: Vec<(String, String, String)>,
+    pub style_file: Option<String>,
+    /// `\@glsorder{letter}` ignores spaces when sorting
+    pub letter_order: bool,
+}
+impl GlossaryInfo {
+    /// Collects `\@newglossary`, `\@istfilename` and `\@glsorder` lines from the given
+    /// `.aux` contents.
+    pub fn from_aux<'a, I: IntoIterator<Item = &'a str>>(aux: I) -> Self {
+        let mut ret = Self::default();
+        for src in aux {
+            for line in src.lines() {
+                if let Some(arg) = command_arg(line, "\\@newglossary") {
+                    let mut args = arg.split("}{");
+                    if let (Some(name), Some(_log), Some(out), Some(inp)) =
+                        (args.next(), args.next(), args.next(), args.next())
+                    {
+                        if !ret.glossaries.iter().any(|(n, _, _)| n == name) {
+                            ret.glossaries.push((
+                                name.into(),
+                                out.trim().into(),
+                                inp.trim().into(),
+                            ));
+                        }
+                    }
+                } else if let Some(arg) = command_arg(line, "\\@istfilename") {
+                    ret.style_file = Some(arg.trim().to_string());
+                } else if let Some(arg) = command_arg(line, "\\@glsorder") {
+                    ret.letter_order = arg.trim() == "letter";
+                }
+            }
+        }
+        ret
+    }
+}
+
+fn letter_order(a: &str, b: &str) -> Ordering {
+    let a: String = a.split_whitespace().collect();
+    let b: String = b.split_whitespace().collect();
+    Collation::Makeindex.compare(&a, &b)
+}
+
+/// Reads a file from `aux`, the files written in this run, or the disk, in that order.
+fn read(aux: &AuxFiles, fs: &RusTeXFileSystem, path: &Path) -> Option<String> {
+    aux.get(path)
+        .map(ToString::to_string)
+        .or_else(|| fs.written_file(path))
+        .or_else(|| std::fs::read_to_string(path).ok())
+}
+
+/// Adds the output file of every glossary declared in the `.aux` files to `aux`, generated
+/// from the glossary's input file as written in this run.
+pub(crate) fn resolve(aux: &mut AuxFiles, fs: &RusTeXFileSystem, jobname: &str) {
+    let info = GlossaryInfo::from_aux(
+        aux.files
+            .iter()
+            .filter(|(p, _)| p.extension().is_some_and(|e| e == "aux"))
+            .map(|(_, s)| s.as_str()),
+    );
+    if info.glossaries.is_empty() {
+        return;
+    }
+    let pwd = &fs.inner.kpse.pwd;
+    let style = info
+        .style_file
+        .as_ref()
+        .and_then(|f| read(aux, fs, &pwd.join(f)))
+        .map(|s| IndexStyle::parse(&s))
+        .unwrap_or_default();
+    let collation = if info.letter_order {
+        Collation::Custom(letter_order)
+    } else {
+        Collation::Makeindex
+    };
+    for (_, out, inp) in &info.glossaries {
+        let Some(src) = read(aux, fs, &pwd.join(format!("{jobname}.{inp}"))) else {
+            continue;
+        };
+        let entries = parse_idx(&src, &style);
+        if entries.is_empty() {
+            continue;
+        }
+        aux.insert(
+            pwd.join(format!("{jobname}.{out}")),
+            make_ind(&entries, &style, collation),
+        );
+    }
+}
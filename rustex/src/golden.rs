@@ -0,0 +1,245 @@
+//! Golden tests against pdfTeX: [`compare`] runs a snippet of LaTeX through RusTeX and - if it
+//! is installed - through `pdflatex`, and collects the boxes shown with `\showbox` in either
+//! run, so that they can be compared after [normalization](normalize). Used by the crate's
+//! integration tests, and usable by package authors to check that RusTeX builds the boxes of
+//! their macros like pdfTeX does, e.g.
+//!
+//! ```no_run
+//! use rustex_lib::golden::{compare, hbox};
+//! if let Err(e) = compare(&hbox(r"A\kern2pt B\hfil C")).check() {
+//!     panic!("{e}")
+//! }
+//! ```
+//!
+//! RusTeX's dumps are only an approximation of TeX's (see
+//! [`ShowBox`](tex_engine::tex::nodes::show::ShowBox)); in particular, math lists are not
+//! compared.
+
+use crate::engine::output::{OutputCont, RusTeXOutput};
+use crate::engine::{RusTeXEngine, RusTeXEngineT, Settings};
+use std::any::Any;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The snippet `\setbox0=\hbox{material}\showbox0`.
+pub fn hbox(material: &str) -> String {
+    format!("\\setbox0=\\hbox{{{material}}}\\showbox0")
+}
+/// The snippet `\setbox0=\vbox{material}\showbox0`.
+pub fn vbox(material: &str) -> String {
+    format!("\\setbox0=\\vbox{{{material}}}\\showbox0")
+}
+
+/// The boxes shown by the two engines, in the order they were shown, each starting with the
+/// line `> \boxN=`.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub rustex: Vec<String>,
+    /// the error RusTeX stopped with, if any
+    pub rustex_error: Option<String>,
+    /// `None` if `pdflatex` could not be run
+    pub pdftex: Option<Vec<String>>,
+}
+impl Comparison {
+    /// The indices and normalized RusTeX and pdfTeX versions of the boxes that differ; a box
+    /// shown by only one of the engines is compared with the empty string.
+    pub fn differences(&self) -> Vec<(usize, String, String)> {
+        let Some(pdftex) = &self.pdftex else {
+            return Vec::new();
+        };
+        let get = |v: &[String], i: usize| v.get(i).map(|s| normalize(s)).unwrap_or_default();
+        (0..self.rustex.len().max(pdftex.len()))
+            .map(|i| (i, get(&self.rustex, i), get(pdftex, i)))
+            .filter(|(_, r, p)| r != p)
+            .collect()
+    }
+
+    /// Succeeds if RusTeX did not fail and the boxes are the same as pdfTeX's - or pdfTeX is
+    /// not available; otherwise, the error describes the first differing line of every box.
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(e) = &self.rustex_error {
+            return Err(format!("RusTeX failed: {e}"));
+        }
+        let differences = self.differences();
+        if differences.is_empty() {
+            return Ok(());
+        }
+        let mut ret = String::new();
+        for (i, rustex, pdftex) in differences {
+            let (line, (r, p)) = rustex
+                .lines()
+                .chain(std::iter::repeat(""))
+                .zip(pdftex.lines().chain(std::iter::repeat("")))
+                .enumerate()
+                .find(|(_, (r, p))| r != p)
+                .unwrap_or((0, ("", "")));
+            ret.push_str(&format!(
+                "box {i}, line {}:\n  RusTeX: {r}\n  pdfTeX: {p}\n",
+                line + 1
+            ));
+        }
+        Err(ret)
+    }
+}
+
+/// Makes dumps of the two engines comparable:
+/// - blank lines and trailing whitespace are removed,
+/// - the names of glue parameters are dropped (`\glue(\rightskip) 0.0` becomes `\glue 0.0`),
+///   since RusTeX does not record where glue came from,
+/// - font kerns and explicit kerns are not told apart (`\kern1.0` becomes `\kern 1.0`),
+/// - the contents of math lists, between `\mathon` and `\mathoff`, are dropped,
+/// - glue set ratios are rounded to three decimals.
+pub fn normalize(dump: &str) -> String {
+    let mut ret = String::new();
+    let mut in_math: Option<String> = None;
+    for line in dump.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        let prefix_len = line.len() - line.trim_start_matches(['.', '|']).len();
+        let (prefix, node) = line.split_at(prefix_len);
+        if let Some(p) = &in_math {
+            if p == prefix && node == "\\mathoff" {
+                in_math = None;
+            } else {
+                continue;
+            }
+        } else if node == "\\mathon" {
+            in_math = Some(prefix.to_string());
+        }
+        ret.push_str(prefix);
+        if let Some(rest) = node.strip_prefix("\\glue(") {
+            ret.push_str("\\glue");
+            ret.push_str(rest.split_once(')').map_or(rest, |(_, r)| r));
+        } else if let Some(rest) = node.strip_prefix("\\kern") {
+            ret.push_str("\\kern ");
+            ret.push_str(rest.trim_start());
+        } else if let Some((head, tail)) = node.split_once(", glue set ") {
+            ret.push_str(head);
+            ret.push_str(", glue set ");
+            ret.push_str(&round_glue_set(tail));
+        } else {
+            ret.push_str(node);
+        }
+        ret.push('\n');
+    }
+    ret
+}
+/// `tail` starts with the glue set ratio, e.g. `- 1.2345fil, shifted 2.0`.
+fn round_glue_set(tail: &str) -> String {
+    let (sign, rest) = match tail.strip_prefix("- ") {
+        Some(r) => ("- ", r),
+        None => ("", tail),
+    };
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(rest.len());
+    match rest[..end].parse::<f64>() {
+        Ok(f) => format!("{sign}{f:.3}{}", &rest[end..]),
+        Err(_) => tail.to_string(),
+    }
+}
+
+const PREAMBLE: &str = "\\documentclass{article}\n\\showboxdepth=\\maxdimen\n\\showboxbreadth=\\maxdimen\n\\begin{document}\n";
+const POSTAMBLE: &str = "\n\\end{document}\n";
+
+/// Runs `snippet` (in the body of an `article`) through RusTeX and `pdflatex`.
+pub fn compare(snippet: &str) -> Comparison {
+    static RUN: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "rustex-golden-{}-{}",
+        std::process::id(),
+        RUN.fetch_add(1, Ordering::Relaxed)
+    ));
+    let file = dir.join("golden.tex");
+    let written = std::fs::create_dir_all(&dir)
+        .and_then(|_| std::fs::write(&file, format!("{PREAMBLE}{snippet}{POSTAMBLE}")));
+    let ret = match written {
+        Ok(()) => {
+            let (rustex, rustex_error) = rustex_dumps(&file);
+            Comparison {
+                rustex,
+                rustex_error,
+                pdftex: pdftex_dumps(&dir, &file),
+            }
+        }
+        Err(e) => Comparison {
+            rustex: Vec::new(),
+            rustex_error: Some(format!("Error writing {}: {e}", file.display())),
+            pdftex: None,
+        },
+    };
+    let _ = std::fs::remove_dir_all(&dir);
+    ret
+}
+
+/// Collects the output of `\showbox`.
+#[derive(Default)]
+struct BoxDumps(RefCell<Vec<String>>);
+impl OutputCont for BoxDumps {
+    fn message(&self, _text: String) {}
+    fn errmessage(&self, _text: String) {}
+    fn file_open(&self, _text: String) {}
+    fn file_close(&self, _text: String) {}
+    fn write_18(&self, _text: String) {}
+    fn write_17(&self, _text: String) {}
+    fn write_16(&self, _text: String) {}
+    fn write_neg1(&self, text: String) {
+        if text.starts_with("> \\box") {
+            self.0.borrow_mut().push(text)
+        }
+    }
+    fn write_other(&self, _text: String) {}
+    fn as_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+fn rustex_dumps(file: &Path) -> (Vec<String>, Option<String>) {
+    let mut engine = RusTeXEngine::get();
+    engine.aux.outputs = RusTeXOutput::Cont(Box::<BoxDumps>::default());
+    let result = engine.run(file.display().to_string(), Settings::default());
+    let dumps = match std::mem::replace(&mut engine.aux.outputs, RusTeXOutput::None) {
+        RusTeXOutput::Cont(b) => b
+            .as_any()
+            .downcast::<BoxDumps>()
+            .map(|d| d.0.into_inner())
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    (dumps, result.error.map(|(e, _)| e.to_string()))
+}
+
+fn pdftex_dumps(dir: &Path, file: &Path) -> Option<Vec<String>> {
+    std::process::Command::new("pdflatex")
+        .arg("-interaction=batchmode")
+        .arg(file.file_name()?)
+        .current_dir(dir)
+        // no line breaks in the log
+        .env("max_print_line", "100000")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .ok()?;
+    let log = std::fs::read(PathBuf::from(file).with_extension("log")).ok()?;
+    Some(log_dumps(&String::from_utf8_lossy(&log)))
+}
+
+/// The boxes shown in a TeX log: from a line `> \boxN=` to the next blank line.
+fn log_dumps(log: &str) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut lines = log.lines();
+    while let Some(line) = lines.next() {
+        if line.starts_with("> \\box") {
+            let mut dump = line.to_string();
+            for l in lines.by_ref().take_while(|l| !l.trim().is_empty()) {
+                dump.push('\n');
+                dump.push_str(l);
+            }
+            ret.push(dump);
+        }
+    }
+    ret
+}
@@ -0,0 +1,245 @@
+//! Just enough JSON for the language server protocol and the [IR export](crate::shipout::ir).
+
+use std::fmt::{Display, Formatter, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+impl Json {
+    /// The value of the given key, if this is an object containing it.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Self::Object(v) => v.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+    /// The value at the given path of object keys, e.g. `["textDocument", "uri"]`.
+    pub fn path(&self, path: &[&str]) -> Option<&Json> {
+        path.iter().try_fold(self, |j, k| j.get(k))
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Self::Number(n) if *n >= 0.0 => Some(*n as usize),
+            _ => None,
+        }
+    }
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Self::Array(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Parses a JSON document; returns `None` if it is malformed.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parser = Parser {
+            chars: s.chars().peekable(),
+        };
+        let ret = parser.value()?;
+        parser.ws();
+        parser.chars.peek().is_none().then_some(ret)
+    }
+}
+
+/// `Json::Object` from key/value pairs.
+pub fn object<const N: usize>(entries: [(&str, Json); N]) -> Json {
+    Json::Object(
+        entries
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+    )
+}
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Self::String(s.to_string())
+    }
+}
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Self::String(s)
+    }
+}
+impl From<usize> for Json {
+    fn from(n: usize) -> Self {
+        Self::Number(n as f64)
+    }
+}
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Self::Bool(b)
+    }
+}
+impl From<Vec<Json>> for Json {
+    fn from(v: Vec<Json>) -> Self {
+        Self::Array(v)
+    }
+}
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(o: Option<T>) -> Self {
+        o.map_or(Self::Null, Into::into)
+    }
+}
+
+impl Display for Json {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Self::Number(n) => write!(f, "{n}"),
+            Self::String(s) => {
+                f.write_char('"')?;
+                for c in s.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\r' => f.write_str("\\r")?,
+                        '\t' => f.write_str("\\t")?,
+                        c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                        c => f.write_char(c)?,
+                    }
+                }
+                f.write_char('"')
+            }
+            Self::Array(v) => {
+                f.write_char('[')?;
+                for (i, j) in v.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{j}")?;
+                }
+                f.write_char(']')
+            }
+            Self::Object(v) => {
+                f.write_char('{')?;
+                for (i, (k, j)) in v.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}:{j}", Self::String(k.clone()))?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+impl Parser<'_> {
+    fn ws(&mut self) {
+        while self.chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    }
+    fn keyword(&mut self, kw: &str, value: Json) -> Option<Json> {
+        for c in kw.chars() {
+            self.chars.next_if_eq(&c)?;
+        }
+        Some(value)
+    }
+    fn value(&mut self) -> Option<Json> {
+        self.ws();
+        match *self.chars.peek()? {
+            'n' => self.keyword("null", Json::Null),
+            't' => self.keyword("true", Json::Bool(true)),
+            'f' => self.keyword("false", Json::Bool(false)),
+            '"' => self.string().map(Json::String),
+            '[' => {
+                self.chars.next();
+                let mut ret = Vec::new();
+                self.ws();
+                if self.chars.next_if_eq(&']').is_some() {
+                    return Some(Json::Array(ret));
+                }
+                loop {
+                    ret.push(self.value()?);
+                    self.ws();
+                    match self.chars.next()? {
+                        ',' => (),
+                        ']' => return Some(Json::Array(ret)),
+                        _ => return None,
+                    }
+                }
+            }
+            '{' => {
+                self.chars.next();
+                let mut ret = Vec::new();
+                self.ws();
+                if self.chars.next_if_eq(&'}').is_some() {
+                    return Some(Json::Object(ret));
+                }
+                loop {
+                    self.ws();
+                    let key = self.string()?;
+                    self.ws();
+                    self.chars.next_if_eq(&':')?;
+                    ret.push((key, self.value()?));
+                    self.ws();
+                    match self.chars.next()? {
+                        ',' => (),
+                        '}' => return Some(Json::Object(ret)),
+                        _ => return None,
+                    }
+                }
+            }
+            _ => {
+                let mut s = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+                {
+                    s.push(c);
+                }
+                s.parse().ok().map(Json::Number)
+            }
+        }
+    }
+    fn string(&mut self) -> Option<String> {
+        self.chars.next_if_eq(&'"')?;
+        let mut ret = String::new();
+        loop {
+            match self.chars.next()? {
+                '"' => return Some(ret),
+                '\\' => match self.chars.next()? {
+                    'n' => ret.push('\n'),
+                    'r' => ret.push('\r'),
+                    't' => ret.push('\t'),
+                    'b' => ret.push('\u{8}'),
+                    'f' => ret.push('\u{c}'),
+                    'u' => {
+                        let mut code = self.hex4()?;
+                        if (0xD800..0xDC00).contains(&code) {
+                            self.chars.next_if_eq(&'\\')?;
+                            self.chars.next_if_eq(&'u')?;
+                            let low = self.hex4()?;
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low.checked_sub(0xDC00)?);
+                        }
+                        ret.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    }
+                    c => ret.push(c),
+                },
+                c => ret.push(c),
+            }
+        }
+    }
+    fn hex4(&mut self) -> Option<u32> {
+        (0..4).try_fold(0, |acc, _| {
+            Some(acc * 16 + self.chars.next()?.to_digit(16)?)
+        })
+    }
+}
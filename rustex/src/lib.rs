@@ -1,5 +1,20 @@
+pub mod bibtex;
+pub mod dvi;
 pub mod engine;
+pub mod epub;
+pub mod glossaries;
+pub mod golden;
+pub mod json;
+pub mod makeindex;
+pub mod patgen;
+pub mod pdf;
+mod placement;
+pub mod project;
 pub mod shipout;
+pub mod sink;
+pub mod statistics;
+pub mod stex;
+pub mod thumbnails;
 
 pub mod utils;
 pub use shipout::html::ImageOptions;
@@ -30,4 +45,708 @@ mod tests {
         let out = testpath.with_extension("html");
         ret.write_out(&out).unwrap();
     }
+    // only checks the primitives expl3 needs, see test/expl3.tex
+    #[test]
+    fn test_expl3() {
+        let testpath: PathBuf = PWD
+            .join("../test/expl3.tex")
+            .parse_dot()
+            .unwrap()
+            .to_path_buf();
+        let ret = RusTeXEngine::do_file(testpath.to_str().unwrap(), Settings::default());
+        assert!(ret.error.is_none(), "{:?}", ret.error.map(|(e, _)| e));
+    }
+    #[test]
+    fn sanitize_html() {
+        use crate::engine::sanitize::sanitize_html;
+        for payload in [
+            "<img/onerror=alert(1) src=x>",
+            "<svg/onload=alert(1)>",
+            "<a href=\"jav&#x61;script:alert(1)\">x</a>",
+            "<a href=\"java\tscript:alert(1)\">x</a>",
+            "<a href=\"data:text/html;base64,PHNjcmlwdD4=\">x</a>",
+            "<a href=\"vbscript:msgbox(1)\">x</a>",
+            "<svg><animate attributeName=\"href\" values=\"javascript:alert(1)\"/></svg>",
+            "<svg><set attributeName=\"href\" to=\"javascript:alert(1)\"/></svg>",
+            "<style>body{display:none}</style>",
+            "<link rel=\"stylesheet\" href=\"http://evil/x.css\">",
+            "<meta http-equiv=\"refresh\" content=\"0;url=http://evil\">",
+            "<form action=\"http://evil\"><input name=\"x\"></form>",
+            "<script>alert(1)</script>",
+            "<iframe src=\"http://evil\"></iframe>",
+        ] {
+            let clean = sanitize_html(payload).to_ascii_lowercase();
+            for bad in [
+                "onerror", "onload", "script", "data:", "<svg", "<animate", "<set", "<style",
+                "<link", "<meta", "<form", "<input", "<iframe", "evil",
+            ] {
+                assert!(!clean.contains(bad), "{payload} -> {clean}");
+            }
+        }
+        assert_eq!(
+            sanitize_html("<span class=\"a\" style=\"color:red\">x</span>"),
+            "<span class=\"a\" style=\"color:red\">x</span>"
+        );
+        assert_eq!(
+            sanitize_html("<span style=\"background:url(http://evil/x)\">x</span>"),
+            "<span>x</span>"
+        );
+    }
+    #[test]
+    fn sanitize_css() {
+        use crate::engine::sanitize::sanitize_css;
+        for payload in [
+            ".a{background:u\\72l(http://evil/x)}",
+            ".a{background:\\75 \\72 \\6c (http://evil/x)}",
+            "@\\69mport 'http://evil/x.css';",
+            "@im/**/port 'http://evil/x.css';",
+            ".a{background:URL( http://evil/x)}",
+            ".a{width:expression(alert(1))}",
+            ".a{background:image-set('http://evil/x' 1x)}",
+            ".a{behavior:url(x.htc)}",
+            ".a{color:red; Behavior : x.htc}",
+            ".a{-moz-binding:x}",
+        ] {
+            assert_eq!(sanitize_css(payload), None, "{payload}");
+        }
+        assert_eq!(
+            sanitize_css(".a{color:red}</style>").as_deref(),
+            Some(".a{color:red}/style>")
+        );
+        assert_eq!(
+            sanitize_css(".a::before{content:'\\2014'}").as_deref(),
+            Some(".a::before{content:'\\2014'}")
+        );
+        assert_eq!(
+            sanitize_css("html{scroll-behavior:smooth}").as_deref(),
+            Some("html{scroll-behavior:smooth}")
+        );
+    }
+    #[test]
+    fn parse_bib() {
+        use crate::bibtex::parse_bib;
+        let entries = parse_bib(
+            r#"@string{acm = "ACM Press"}
+            @comment{ignored @article{no, title = {No}} }
+            @Article{knuth84,
+              author = {Knuth, Donald E.},
+              title  = "Literate {Programming}",
+              journal = {The Computer Journal},
+              publisher = acm # { Inc.},
+              year   = 1984,
+              month  = may,
+            }
+            @book(lamport, title = {\LaTeX{}}, author = {Leslie Lamport})
+            @misc{broken, title = }"#,
+        );
+        assert_eq!(entries.len(), 3);
+        let knuth = &entries[0];
+        assert_eq!(
+            (knuth.kind.as_str(), knuth.key.as_str()),
+            ("article", "knuth84")
+        );
+        assert_eq!(knuth.get("author"), Some("Knuth, Donald E."));
+        assert_eq!(knuth.get("title"), Some("Literate {Programming}"));
+        assert_eq!(knuth.get("publisher"), Some("ACM Press Inc."));
+        assert_eq!(knuth.get("year"), Some("1984"));
+        assert_eq!(knuth.get("month"), Some("May"));
+        assert_eq!(entries[1].key, "lamport");
+        assert_eq!(entries[1].get("title"), Some("\\LaTeX{}"));
+        assert_eq!(entries[2].key, "broken");
+    }
+    #[test]
+    fn make_bbl() {
+        use crate::bibtex::{make_bbl, parse_bib, Citations, BBL_HEADER};
+        let entries = parse_bib(
+            "@book{b, author = {Zed, Zoe}, title = {Last}, year = 2000}
+            @book{a, author = {Adam Alpha and Bea Beta}, title = {First}, year = 1999}",
+        );
+        let cites = Citations::from_aux([
+            "\\citation{b}\n\\citation{a,missing}\n\\bibdata{refs}\n\\bibstyle{plain}",
+        ]);
+        assert_eq!(cites.keys, ["b", "a", "missing"]);
+        assert_eq!(cites.databases, ["refs"]);
+        let bbl = make_bbl(&cites, &entries);
+        assert!(bbl.starts_with(BBL_HEADER));
+        assert!(bbl.contains("\\begin{thebibliography}{2}"));
+        assert!(bbl.contains("Adam Alpha and Bea Beta"));
+        assert!(!bbl.contains("missing"));
+        // plain sorts by author, unsrt keeps the order of citation
+        assert!(bbl.find("\\bibitem{a}") < bbl.find("\\bibitem{b}"));
+        let unsrt = Citations {
+            style: Some("unsrt".into()),
+            ..cites
+        };
+        let bbl = make_bbl(&unsrt, &entries);
+        assert!(bbl.find("\\bibitem{b}") < bbl.find("\\bibitem{a}"));
+        assert!(Citations::from_aux(["\\abx@aux@cite{0}{a}"]).biblatex);
+    }
+    #[test]
+    fn aux_files_stay_in_job_dir() {
+        use crate::engine::files::AuxFiles;
+        use crate::sink::MemorySink;
+        let dir = std::env::temp_dir().join("rustex-aux-test");
+        let mut aux = AuxFiles::default();
+        aux.insert(dir.join("sub/../main.aux"), "a".into());
+        aux.insert(dir.join("chapter.aux"), "b".into());
+        assert_eq!(aux.get(&dir.join("./main.aux")), Some("a"));
+        let mut sink = MemorySink::default();
+        aux.write_to(&mut sink, &dir).unwrap();
+        let names: Vec<_> = sink.assets.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["main.aux", "chapter.aux"]);
+
+        aux.insert(dir.join("../evil.aux"), "c".into());
+        let mut sink = MemorySink::default();
+        assert!(aux.write_to(&mut sink, &dir).is_err());
+        assert!(sink.assets.is_empty());
+    }
+    #[test]
+    fn make_ind() {
+        use crate::makeindex::{make_ind, parse_idx, Collation, IndexStyle};
+        let style = IndexStyle::default();
+        let entries = parse_idx(
+            "\\indexentry{beta}{4}\n\\indexentry{alpha!sub}{2}\n\\indexentry{beta}{3}\n\
+            \\indexentry{alpha}{1}\n\\indexentry{beta|textbf}{6}\n\\indexentry{beta}{5}",
+            &style,
+        );
+        assert_eq!(entries.len(), 6);
+        assert_eq!(
+            make_ind(&entries, &style, Collation::Makeindex),
+            "\\begin{theindex}\n\n  \\item alpha, 1\n    \\subitem sub, 2\n\n  \\indexspace\n\
+            \n  \\item beta, 3--5, \\textbf{6}\n\n\\end{theindex}\n"
+        );
+    }
+    #[test]
+    fn format_pages() {
+        use crate::makeindex::{format_pages, parse_idx, IndexStyle};
+        let style = IndexStyle::default();
+        let entries = parse_idx(
+            "\\indexentry{x}{10}\\indexentry{x}{ii}\\indexentry{x}{1}\\indexentry{x}{2}\
+            \\indexentry{x}{4}\\indexentry{x}{4}\\indexentry{x|(}{7}\\indexentry{x}{8}\
+            \\indexentry{x|)}{9}",
+            &style,
+        );
+        let entries: Vec<_> = entries.iter().collect();
+        // roman numbers first, two consecutive pages are no range, duplicates are dropped
+        assert_eq!(format_pages(&entries, &style), "ii, 1, 2, 4, 7--9, 10");
+    }
+    #[test]
+    fn roman() {
+        use crate::makeindex::roman;
+        assert_eq!(roman("xiv"), Some(14));
+        assert_eq!(roman("MCMXCIV"), Some(1994));
+        assert_eq!(roman("iV"), None);
+        assert_eq!(roman("abc"), None);
+        assert_eq!(roman(""), None);
+    }
+    #[test]
+    fn section_source_paths() {
+        use crate::shipout::sections::relative_path;
+        use std::path::Path;
+        let base = Path::new("/doc/main");
+        assert_eq!(
+            relative_path(Path::new("/doc/main/chapters/intro.tex"), base),
+            Path::new("chapters/intro.tex")
+        );
+        assert_eq!(
+            relative_path(Path::new("/doc/shared/macros.tex"), base),
+            Path::new("../shared/macros.tex")
+        );
+    }
+    #[test]
+    fn project_output_collisions() {
+        use crate::project::Project;
+        use crate::sink::MemorySink;
+        let dir = std::env::temp_dir().join("rustex-project-test");
+        for sub in ["root", "a", "b"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        let mut project = Project::default();
+        project.root = dir.join("root");
+        for sub in ["a", "b"] {
+            let doc = dir.join(sub).join("main.tex");
+            std::fs::write(&doc, "\\documentclass{article}").unwrap();
+            project.add_document(doc).unwrap();
+        }
+        let mut sink = MemorySink::default();
+        let out = dir.join("out");
+        let status = project
+            .compile_to(&out, &mut sink, true, Settings::default, None)
+            .unwrap();
+        assert_eq!(status.len(), 2);
+        assert!(status.iter().all(|s| !s.compiled
+            && s.error
+                .as_ref()
+                .is_some_and(|e| e.contains("same output file"))));
+    }
+    #[test]
+    fn patgen() {
+        use crate::patgen::{generate, PatgenOptions, Patterns, Word};
+        let words = Word::parse_list("ta-ble\nca-ble\nfa-ble\nba-con\nbea-con\nta-ble-top\n");
+        assert_eq!(words.len(), 6);
+        assert_eq!(words[0].letters, vec!['t', 'a', 'b', 'l', 'e']);
+        assert_eq!(words[0].hyphens, vec![false, false, true, false, false]);
+        let patterns = generate(&words, &PatgenOptions::default());
+        assert_eq!(patterns.to_tex(&[]), "\\patterns{\na1b\na1c\n}\n");
+        assert_eq!(patterns.hyphenate("Gable"), vec![2]);
+        assert_eq!(patterns.hyphenate("tab"), Vec::<usize>::new());
+        // the hyphen in `tabletop` occurs only once, which is below the threshold
+        let eval = patterns.evaluate(&words);
+        assert_eq!((eval.good, eval.bad, eval.missed), (6, 0, 1));
+        assert_eq!(eval.exceptions, vec![words[5].clone()]);
+        assert!(patterns
+            .to_tex(&eval.exceptions)
+            .ends_with("}\n\\hyphenation{\nta-ble-top\n}\n"));
+        let reparsed = Patterns::parse("a1b a1c", 2, 3);
+        assert_eq!(reparsed.len(), patterns.len());
+        assert_eq!(reparsed.hyphenate("cable"), patterns.hyphenate("cable"));
+    }
+    #[test]
+    fn locale_formatting() {
+        use crate::engine::locale::Locale;
+        let en = Locale::get_or_default("english");
+        let de = Locale::get("ngerman").unwrap();
+        let fr = Locale::get("french").unwrap();
+        assert_eq!(en.format_number("1234567").as_deref(), Some("1,234,567"));
+        assert_eq!(en.format_number("123").as_deref(), Some("123"));
+        assert_eq!(
+            de.format_number("1234567.25").as_deref(),
+            Some("1.234.567,25")
+        );
+        assert_eq!(fr.format_number("12345").as_deref(), Some("12\u{202F}345"));
+        assert_eq!(en.format_number("-1234.5").as_deref(), Some("-1,234.5"));
+        assert_eq!(de.format_number("-1000").as_deref(), Some("-1.000"));
+        assert_eq!(en.format_number("+42").as_deref(), Some("42"));
+        assert_eq!(en.format_number("1,5").as_deref(), Some("1.5"));
+        assert_eq!(de.format_number("1,5").as_deref(), Some("1,5"));
+        assert_eq!(en.format_number(",5").as_deref(), Some("0.5"));
+        assert_eq!(en.format_number("1,234,567"), None);
+        assert_eq!(en.format_number("-"), None);
+        assert_eq!(en.format_number("1a"), None);
+        assert_eq!(en.format_date(2024, 3, 1).as_deref(), Some("March 1, 2024"));
+        assert_eq!(de.format_date(2024, 3, 1).as_deref(), Some("1. März 2024"));
+        assert_eq!(fr.format_date(2024, 3, 1).as_deref(), Some("1er mars 2024"));
+        assert_eq!(fr.format_date(2024, 3, 2).as_deref(), Some("2 mars 2024"));
+        assert_eq!(en.format_date(2024, 13, 1), None);
+        assert_eq!(en.format_date(2024, 0, 1), None);
+        assert_eq!(en.format_date(2024, 1, 32), None);
+    }
+    #[test]
+    fn epub_xhtml() {
+        use crate::epub::xhtml;
+        assert_eq!(
+            xhtml("<p>a<br>b<hr/><img src=a.png alt=x></p>"),
+            "<p>a<br/>b<hr/><img src=\"a.png\" alt=\"x\"/></p>"
+        );
+        assert_eq!(
+            xhtml("<input type='checkbox' checked disabled>"),
+            "<input type=\"checkbox\" checked=\"checked\" disabled=\"disabled\"/>"
+        );
+        assert_eq!(
+            xhtml("a&nbsp;b &foo; c&d &amp; &#8212; a < b"),
+            "a&#160;b &amp;foo; c&amp;d &amp; &#8212; a &lt; b"
+        );
+        assert_eq!(
+            xhtml("<a title=\"x&nbsp;y\" href=\"?a=1&b=2\">"),
+            "<a title=\"x&#160;y\" href=\"?a=1&amp;b=2\">"
+        );
+        assert_eq!(
+            xhtml("<script>if (a < b && c) {}</script><style>.a{color:red}</style>"),
+            "<script>/*<![CDATA[*/if (a < b && c) {}/*]]>*/</script><style>.a{color:red}</style>"
+        );
+        assert_eq!(
+            xhtml("<svg width=\"1\"><path d=\"M0\"/></svg>"),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+             width=\"1\"><path d=\"M0\"/></svg>"
+        );
+        assert_eq!(
+            xhtml("<math display=\"block\"><mi>x</mi></math>"),
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"block\"><mi>x</mi></math>"
+        );
+        assert_eq!(
+            xhtml("<math xmlns=\"http://www.w3.org/1998/Math/MathML\">"),
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\">"
+        );
+    }
+    #[test]
+    fn merge_math() {
+        use crate::shipout::merge::{merge_hlist, MathMergeSettings};
+        use crate::shipout::state::{CharOrStr, Common, ShipoutNodeH, ShipoutNodeM, SourceRef};
+        use tex_engine::pdflatex::nodes::PDFColor;
+        fn math(s: &str) -> ShipoutNodeH {
+            ShipoutNodeH::Math {
+                sref: SourceRef::default(),
+                display: None,
+                fleqn: None,
+                axis_shift: None,
+                children: vec![ShipoutNodeM::Common(Common::Literal(s.into()))],
+                uses_color: false,
+                uses_font: false,
+            }
+        }
+        fn text(s: &str) -> impl Iterator<Item = ShipoutNodeH> + '_ {
+            s.chars().map(|c| match c {
+                ' ' => ShipoutNodeH::Space,
+                c => ShipoutNodeH::Char(CharOrStr::Char(c)),
+            })
+        }
+        fn show(nodes: &[ShipoutNodeM]) -> String {
+            nodes
+                .iter()
+                .map(|n| match n {
+                    ShipoutNodeM::Common(Common::Literal(s)) => {
+                        s.replace("<mo class=\"rustex-math-merged\">", "<mo>")
+                    }
+                    ShipoutNodeM::Common(Common::WithColor { children, .. }) => {
+                        format!("[{}]", show(children))
+                    }
+                    ShipoutNodeM::Space => " ".to_string(),
+                    _ => "?".to_string(),
+                })
+                .collect()
+        }
+        let merged = |nodes: Vec<ShipoutNodeH>| {
+            let mut nodes = nodes;
+            merge_hlist(&mut nodes, &MathMergeSettings::default());
+            match nodes.as_slice() {
+                [ShipoutNodeH::Math { children, .. }] => Some(show(children)),
+                _ => None,
+            }
+        };
+        let list = |a: &str, sep: &str, b: &str| {
+            let mut v = vec![math(a)];
+            v.extend(text(sep));
+            v.push(math(b));
+            v
+        };
+        assert_eq!(merged(list("a", "-", "b")).as_deref(), Some("a<mo>-</mo>b"));
+        assert_eq!(
+            merged(list("a", " = ", "b")).as_deref(),
+            Some("a <mo>=</mo> b")
+        );
+        assert_eq!(merged(list("a", "", "b")).as_deref(), Some("ab"));
+        assert_eq!(merged(list("a", "  ", "b")).as_deref(), Some("a b"));
+        let mut nodes = list("a", " and ", "b");
+        merge_hlist(&mut nodes, &MathMergeSettings::default());
+        assert_eq!(nodes.len(), 7);
+        let mut nodes = list("a", "-", "b");
+        nodes[0] = ShipoutNodeH::Common(Common::WithColor {
+            color: PDFColor::black(),
+            children: vec![math("a")],
+            uses_font: false,
+        });
+        nodes.extend(text("-"));
+        nodes.push(math("c"));
+        assert_eq!(merged(nodes).as_deref(), Some("[a]<mo>-</mo>b<mo>-</mo>c"));
+        let mut nodes = list("a", "-", "b");
+        nodes.insert(
+            1,
+            ShipoutNodeH::Common(Common::WithColor {
+                color: PDFColor::black(),
+                children: vec![math("x"), ShipoutNodeH::Space],
+                uses_font: false,
+            }),
+        );
+        merge_hlist(&mut nodes, &MathMergeSettings::default());
+        assert_eq!(nodes.len(), 4);
+    }
+    #[test]
+    fn shell_escape() {
+        use crate::engine::files::{split_command, ShellEscape};
+        assert_eq!(
+            split_command(
+                "pygmentize -l 'latex' -P stripnl='False' -o \"out dir/x.pyg\" x\\ y.pygtex"
+            ),
+            Ok(vec![
+                "pygmentize".to_string(),
+                "-l".to_string(),
+                "latex".to_string(),
+                "-P".to_string(),
+                "stripnl=False".to_string(),
+                "-o".to_string(),
+                "out dir/x.pyg".to_string(),
+                "x y.pygtex".to_string(),
+            ])
+        );
+        assert_eq!(
+            split_command("echo \"a \\\"b\\\"\" '' c#d"),
+            Ok(vec![
+                "echo".to_string(),
+                "a \"b\"".to_string(),
+                String::new(),
+                "c#d".to_string(),
+            ])
+        );
+        assert_eq!(
+            split_command("echo 'a|b' \"x;y\""),
+            Ok(vec![
+                "echo".to_string(),
+                "a|b".to_string(),
+                "x;y".to_string()
+            ])
+        );
+        for command in [
+            "echo a | sh",
+            "echo a > x",
+            "echo a; rm x",
+            "echo a && rm x",
+            "echo $(rm x)",
+            "echo \"$HOME\"",
+            "echo `rm x`",
+            "rm *.aux",
+            "cat ~/.ssh/id_rsa",
+            "echo 'a",
+            "echo a\nrm x",
+        ] {
+            assert!(split_command(command).is_err(), "{command}");
+        }
+        let policy = ShellEscape {
+            whitelist: vec!["pygmentize".to_string()],
+        };
+        assert!(policy.allows("\"pygmentize\" -o \"x.pyg\" x.tex"));
+        assert!(!policy.allows("pygmentize x.tex; rm x"));
+        assert!(!policy.allows("kpsewhich x"));
+    }
+    #[test]
+    fn pdf_fonts() {
+        use crate::pdf::{glyph_names, pfb_segments, FontMap};
+        let map = FontMap::parse(
+            "% comment\n\
+             cmr10 CMR10 <cmr10.pfb\n\
+             ptmr8r Times-Roman \"TeXBase1Encoding ReEncodeFont\" <8r.enc <utmr8a.pfb\n\
+             ptmro8r Times-Roman \" .167 SlantFont TeXBase1Encoding ReEncodeFont \" <8r.enc \
+             <utmr8a.pfb\n\
+             lmr10 LMRoman10-Regular <[lm-ec.enc <lmr10.otf\n\
+             cmmi10 CMMI10 <cmmi10.pfb\n",
+            |tfm| tfm != "cmmi10",
+        );
+        let entry = |tfm| {
+            map.get(tfm)
+                .map(|e| (e.ps_name.as_str(), e.enc.as_deref(), e.file.as_deref()))
+        };
+        assert_eq!(entry("cmr10"), Some(("CMR10", None, Some("cmr10.pfb"))));
+        assert_eq!(
+            entry("ptmr8r"),
+            Some(("Times-Roman", Some("8r.enc"), Some("utmr8a.pfb")))
+        );
+        assert_eq!(entry("ptmro8r"), entry("ptmr8r"));
+        assert_eq!(
+            entry("lmr10"),
+            Some(("LMRoman10-Regular", Some("lm-ec.enc"), Some("lmr10.otf")))
+        );
+        assert_eq!(entry("cmmi10"), None);
+
+        assert_eq!(
+            glyph_names("% an encoding\n/Enc [ % first\n/.notdef /grave\n/fi/fl ] def"),
+            vec!["/.notdef", "/grave", "/fi/fl"]
+        );
+        assert!(glyph_names("no vector").is_empty());
+
+        fn segment(tp: u8, data: &[u8]) -> Vec<u8> {
+            let mut ret = vec![0x80, tp];
+            ret.extend((data.len() as u32).to_le_bytes());
+            ret.extend(data);
+            ret
+        }
+        let pfb = [
+            segment(1, b"clear1 "),
+            segment(1, b"clear2"),
+            segment(2, &[0, 1]),
+            segment(2, &[2]),
+            segment(1, b"trailer"),
+            vec![0x80, 3],
+        ]
+        .concat();
+        assert_eq!(
+            pfb_segments(&pfb),
+            Some([
+                b"clear1 clear2".to_vec(),
+                vec![0, 1, 2],
+                b"trailer".to_vec()
+            ])
+        );
+        assert_eq!(pfb_segments(&pfb[..pfb.len() - 6]), None);
+        assert_eq!(pfb_segments(&segment(1, b"clear")), None);
+        let binary_after_trailer = [&pfb[..pfb.len() - 2], &segment(2, &[3])].concat();
+        assert_eq!(pfb_segments(&binary_after_trailer), None);
+    }
+    #[test]
+    fn ir_diff() {
+        use crate::shipout::diff::{align, common, DiffSummary};
+        use crate::shipout::ir::{diff, IrChangeKind, IrLocation, IrNode, IrSource};
+        assert_eq!(
+            common(&[1, 2, 3, 4, 5], &[1, 2, 9, 4, 5]),
+            vec![(0, 0), (1, 1), (3, 3), (4, 4)]
+        );
+        assert_eq!(
+            common(&[1, 2, 3, 1], &[3, 1, 2, 1]),
+            vec![(0, 1), (1, 2), (3, 3)]
+        );
+        assert_eq!(
+            align(&[1, 2, 3, 4, 5], &[1, 9, 3, 8, 4]).summary(),
+            DiffSummary {
+                unchanged: 3,
+                changed: 1,
+                inserted: 1,
+                deleted: 1,
+            }
+        );
+
+        let text = |kind: &str, text: &str| IrNode {
+            kind: kind.to_string(),
+            text: text.to_string(),
+            ..IrNode::default()
+        };
+        let root = |children: Vec<IrNode>| IrNode {
+            kind: "vbox".to_string(),
+            children,
+            ..IrNode::default()
+        };
+        let (a, b, d) = (
+            text("paragraph", "a"),
+            text("paragraph", "b"),
+            text("paragraph", "d"),
+        );
+        let mut c = text("hbox", "c");
+        c.attrs.push(("width".to_string(), "10pt".to_string()));
+        let old = root(vec![a.clone(), b.clone(), c.clone(), d.clone()]);
+        let changes = |new: Vec<IrNode>| {
+            diff(&old, &root(new))
+                .into_iter()
+                .map(|c| {
+                    let path = |l: Option<IrLocation>| l.map(|l| l.path);
+                    (c.kind, c.node, path(c.old), path(c.new))
+                })
+                .collect::<Vec<_>>()
+        };
+        let at = |p: &str| Some(p.to_string());
+        assert!(changes(vec![a.clone(), b.clone(), c.clone(), d.clone()]).is_empty());
+        assert_eq!(
+            changes(vec![
+                a.clone(),
+                b.clone(),
+                text("hbox", "x"),
+                c.clone(),
+                d.clone()
+            ]),
+            vec![(
+                IrChangeKind::Inserted,
+                "hbox".to_string(),
+                None,
+                at("/hbox[2]")
+            )]
+        );
+        assert_eq!(
+            changes(vec![a.clone(), c.clone(), d.clone()]),
+            vec![(
+                IrChangeKind::Deleted,
+                "paragraph".to_string(),
+                at("/paragraph[1]"),
+                None
+            )]
+        );
+        assert_eq!(
+            changes(vec![c.clone(), a.clone(), b.clone(), d.clone()]),
+            vec![(
+                IrChangeKind::Moved,
+                "hbox".to_string(),
+                at("/hbox[2]"),
+                at("/hbox[0]")
+            )]
+        );
+        let mut wider = c.clone();
+        wider.attrs[0].1 = "20pt".to_string();
+        assert_eq!(
+            changes(vec![a.clone(), b.clone(), wider, d.clone()]),
+            vec![(
+                IrChangeKind::Changed,
+                "hbox".to_string(),
+                at("/hbox[2]"),
+                at("/hbox[2]")
+            )]
+        );
+        let mut nested = b.clone();
+        nested.text.clear();
+        nested.children = vec![text("text", "x")];
+        let old = root(vec![a.clone(), nested.clone()]);
+        let mut new = nested.clone();
+        new.children[0].text = "y".to_string();
+        let found = diff(&old, &root(vec![a.clone(), new]));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, IrChangeKind::Changed);
+        assert_eq!(
+            found[0].new.as_ref().map(|l| l.path.as_str()),
+            Some("/paragraph[1]/text[0]")
+        );
+
+        let mut tree = root(vec![a, nested, c]);
+        tree.source = Some(IrSource {
+            file: "dir/main \"1\".tex".to_string(),
+            start: (1, 0),
+            end: (20, 5),
+        });
+        tree.children[1].children[0].text = "\u{e4}\\\n\t".to_string();
+        let json = tree.to_json();
+        assert_eq!(IrNode::from_json(&json), Some(tree));
+        assert_eq!(IrNode::from_json("[]"), None);
+        assert_eq!(
+            IrNode::from_json("{\"kind\": \"vbox\", \"attrs\": []}"),
+            None
+        );
+    }
+    #[test]
+    fn progress_events() {
+        use std::sync::{Arc, Mutex};
+        use tex_engine::engine::utils::progress::Progress;
+        let dir = std::env::temp_dir().join("rustex-progress-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("main.tex");
+        std::fs::write(
+            &file,
+            "\\documentclass{article}\n\\begin{document}\nHello\n\\end{document}\n",
+        )
+        .unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let settings = || {
+            let recorder = events.clone();
+            Settings {
+                progress: Some(Box::new(move |p: Progress<'_>| {
+                    let event = match p {
+                        Progress::FormatLoaded(name) => format!("format {name}"),
+                        Progress::FileOpened { path, depth: 0 } => {
+                            format!("main {}", path.display())
+                        }
+                        Progress::FileOpened { .. } => return,
+                        Progress::PageShipped { page, .. } => format!("page {page}"),
+                    };
+                    recorder.lock().unwrap().push(event);
+                })),
+                ..Settings::default()
+            }
+        };
+        // the second run uses the cached format, but still reports it
+        for _ in 0..2 {
+            events.lock().unwrap().clear();
+            let ret = RusTeXEngine::do_file(file.to_str().unwrap(), settings());
+            assert!(ret.error.is_none());
+            let events = events.lock().unwrap();
+            let formats: Vec<_> = events
+                .iter()
+                .take_while(|e| e.starts_with("format "))
+                .collect();
+            assert_eq!(
+                formats,
+                [
+                    "format pdftexconfig.tex",
+                    "format latex.ltx",
+                    "format rustex_defs.def"
+                ]
+            );
+            assert_eq!(
+                events.get(formats.len()),
+                Some(&format!("main {}", file.display()))
+            );
+            assert_eq!(events.last().map(String::as_str), Some("page 1"));
+        }
+    }
 }
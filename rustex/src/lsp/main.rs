@@ -0,0 +1,521 @@
+/*! `rustex-lsp`: a language server for (La)TeX documents, speaking the language server protocol
+over stdin/stdout. Every open document gets a background thread that runs the engine on it
+whenever it changes (or an included file is saved), and publishes:
+
+- the error the run stopped with (if any) and the [approximations](tex_engine::engine::utils::approximations)
+  it took as diagnostics,
+- its sectional units (and the macros defined in it) as document symbols,
+- the definitions of all macros at the end of the run, for hover information.
+
+Positions are counted in characters rather than UTF-16 code units.
+*/
+use rustex_lib::engine::{CompilationResult, RusTeXEngine, RusTeXEngineT, Settings};
+use rustex_lib::json::{object, Json};
+use rustex_lib::shipout::sections::SectionSource;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use tex_engine::engine::filesystem::FileSystem;
+use tex_engine::engine::state::snapshot::{DefinitionSites, StateSnapshot};
+use tex_engine::utils::errors::TeXError;
+use tex_engine::utils::HMap;
+
+const ERROR: usize = 1;
+const WARNING: usize = 2;
+const SYMBOL_MODULE: usize = 2;
+const SYMBOL_NAMESPACE: usize = 3;
+const SYMBOL_FUNCTION: usize = 12;
+
+/// What the last run on a document found out.
+struct Analysis {
+    sections: Vec<SectionSource>,
+    snapshot: StateSnapshot,
+}
+
+struct Document {
+    path: PathBuf,
+    text: String,
+    /// sends the current text to the document's thread
+    worker: Sender<String>,
+    analysis: Option<Analysis>,
+}
+
+type Documents = Arc<Mutex<HMap<String, Document>>>;
+
+fn main() {
+    let docs = Documents::default();
+    let mut stdin = std::io::stdin().lock();
+    let mut shutdown = false;
+    while let Some(msg) = read_message(&mut stdin) {
+        let id = msg.get("id").cloned();
+        let params = msg.get("params").cloned().unwrap_or(Json::Null);
+        let uri = params
+            .path(&["textDocument", "uri"])
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+            .to_string();
+        match msg.get("method").and_then(Json::as_str).unwrap_or_default() {
+            "initialize" => respond(
+                id,
+                object([(
+                    "capabilities",
+                    object([
+                        // full document sync
+                        ("textDocumentSync", 1.into()),
+                        ("hoverProvider", true.into()),
+                        ("documentSymbolProvider", true.into()),
+                    ]),
+                )]),
+            ),
+            "shutdown" => {
+                shutdown = true;
+                respond(id, Json::Null);
+            }
+            "exit" => std::process::exit(i32::from(!shutdown)),
+            "textDocument/didOpen" => {
+                let text = params
+                    .path(&["textDocument", "text"])
+                    .and_then(Json::as_str);
+                open(&docs, uri, text.unwrap_or_default().to_string());
+            }
+            "textDocument/didChange" => {
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Json::as_array)
+                    .and_then(<[Json]>::last)
+                    .and_then(|c| c.get("text"))
+                    .and_then(Json::as_str);
+                if let Some(text) = text {
+                    update(&docs, &uri, Some(text.to_string()));
+                }
+            }
+            // recompile, since an included file may have changed
+            "textDocument/didSave" => update(&docs, &uri, None),
+            "textDocument/didClose" => {
+                // dropping the sender ends the document's thread
+                docs.lock().unwrap().remove(&uri);
+                publish_diagnostics(&uri, Vec::new());
+            }
+            "textDocument/documentSymbol" => {
+                let docs = docs.lock().unwrap();
+                let symbols = docs.get(&uri).map_or_else(Vec::new, document_symbols);
+                respond(id, symbols.into());
+            }
+            "textDocument/hover" => {
+                let line = params.path(&["position", "line"]).and_then(Json::as_usize);
+                let character = params
+                    .path(&["position", "character"])
+                    .and_then(Json::as_usize);
+                let docs = docs.lock().unwrap();
+                let hover = docs
+                    .get(&uri)
+                    .zip(line.zip(character))
+                    .and_then(|(doc, (line, character))| hover(doc, line, character));
+                respond(id, hover.unwrap_or(Json::Null));
+            }
+            _ if id.is_some() && msg.get("method").is_some() => {
+                send(&object([
+                    ("jsonrpc", "2.0".into()),
+                    ("id", id.unwrap_or(Json::Null)),
+                    (
+                        "error",
+                        object([
+                            ("code", Json::Number(-32601.0)),
+                            ("message", "method not found".into()),
+                        ]),
+                    ),
+                ]));
+            }
+            _ => (),
+        }
+    }
+}
+
+fn read_message(input: &mut impl BufRead) -> Option<Json> {
+    loop {
+        let mut length = None;
+        loop {
+            let mut line = String::new();
+            if input.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((k, v)) = line.split_once(':') {
+                if k.trim().eq_ignore_ascii_case("content-length") {
+                    length = v.trim().parse::<usize>().ok();
+                }
+            }
+        }
+        let Some(length) = length else { continue };
+        let mut body = vec![0; length];
+        input.read_exact(&mut body).ok()?;
+        if let Some(msg) = Json::parse(&String::from_utf8_lossy(&body)) {
+            return Some(msg);
+        }
+    }
+}
+
+fn send(msg: &Json) {
+    let body = msg.to_string();
+    let mut out = std::io::stdout().lock();
+    let _ = write!(out, "Content-Length: {}\r\n\r\n{body}", body.len());
+    let _ = out.flush();
+}
+fn respond(id: Option<Json>, result: Json) {
+    send(&object([
+        ("jsonrpc", "2.0".into()),
+        ("id", id.unwrap_or(Json::Null)),
+        ("result", result),
+    ]));
+}
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) {
+    send(&object([
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        (
+            "params",
+            object([("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+        ),
+    ]));
+}
+
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(b) = iter.next() {
+        let hex = |b: Option<u8>| (b? as char).to_digit(16);
+        if b == b'%' {
+            let mut lookahead = iter.clone();
+            if let (Some(h), Some(l)) = (hex(lookahead.next()), hex(lookahead.next())) {
+                bytes.push((h * 16 + l) as u8);
+                iter = lookahead;
+                continue;
+            }
+        }
+        bytes.push(b);
+    }
+    let path = String::from_utf8_lossy(&bytes).into_owned();
+    // file:///C:/... on Windows
+    match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => PathBuf::from(&path[1..]),
+        _ => PathBuf::from(path),
+    }
+}
+
+fn open(docs: &Documents, uri: String, text: String) {
+    let path = uri_to_path(&uri);
+    let worker = spawn_worker(uri.clone(), path.clone(), docs.clone());
+    let _ = worker.send(text.clone());
+    docs.lock().unwrap().insert(
+        uri,
+        Document {
+            path,
+            text,
+            worker,
+            analysis: None,
+        },
+    );
+}
+fn update(docs: &Documents, uri: &str, text: Option<String>) {
+    let mut docs = docs.lock().unwrap();
+    if let Some(doc) = docs.get_mut(uri) {
+        if let Some(text) = text {
+            doc.text = text;
+        }
+        let _ = doc.worker.send(doc.text.clone());
+    }
+}
+
+fn spawn_worker(uri: String, path: PathBuf, docs: Documents) -> Sender<String> {
+    let (sender, receiver) = channel::<String>();
+    let _ = std::thread::Builder::new()
+        .name(format!("rustex-lsp: {uri}"))
+        .spawn(move || {
+            while let Ok(mut text) = receiver.recv() {
+                // only the latest version is of interest
+                while let Ok(newer) = receiver.try_recv() {
+                    text = newer;
+                }
+                let run = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    analyze(&path, &text)
+                }));
+                match run {
+                    Ok((diagnostics, analysis)) => {
+                        publish_diagnostics(&uri, diagnostics);
+                        if let Some(doc) = docs.lock().unwrap().get_mut(&uri) {
+                            doc.analysis = Some(analysis);
+                        }
+                    }
+                    Err(_) => publish_diagnostics(
+                        &uri,
+                        vec![diagnostic((0, 0), ERROR, "RusTeX crashed".to_string())],
+                    ),
+                }
+            }
+        });
+    sender
+}
+
+/// Runs the engine on the (possibly unsaved) `text` of the file at `path`.
+fn analyze(path: &Path, text: &str) -> (Vec<Json>, Analysis) {
+    let mut engine = RusTeXEngine::get();
+    engine.stomach.continuous = true;
+    engine.aux.definitions = Some(DefinitionSites::new());
+    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    engine.filesystem.set_pwd(dir.clone());
+    engine.filesystem.add_file(path.to_path_buf(), text);
+    let settings = Settings {
+        section_spans: true,
+        strict: true,
+        hardened: true,
+        ..Settings::default()
+    };
+    let result: CompilationResult = engine.run(path.display().to_string(), settings);
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+    let mut diagnostics = Vec::new();
+    match &result.error {
+        // the approximations are reported individually below
+        None | Some((TeXError::Strict(_), _)) => (),
+        Some((e, trace)) => {
+            let mut message = e.to_string();
+            // the innermost file is not necessarily the document itself
+            if let Some(t) = trace.first().filter(|t| t.file != canonical) {
+                message.push_str(&format!("\n(in {} l. {})", t.file.display(), t.line));
+            }
+            let at = trace
+                .iter()
+                .find(|t| t.file == canonical)
+                .map_or((0, 0), |t| (t.line as usize, t.col as usize));
+            diagnostics.push(diagnostic(at, ERROR, message));
+        }
+    }
+    for a in engine.aux.approximations.list() {
+        // `at` is displayed as `<file> l. <line> c. <column>`
+        let at =
+            a.at.rsplit_once(" l. ")
+                .and_then(|(file, pos)| {
+                    let file = dir.join(file);
+                    if file.canonicalize().unwrap_or(file) != canonical {
+                        return None;
+                    }
+                    let (line, col) = pos.split_once(" c. ")?;
+                    Some((line.trim().parse().ok()?, col.trim().parse().ok()?))
+                })
+                .unwrap_or((0, 0));
+        diagnostics.push(diagnostic(at, WARNING, format!("{}: {}", a.kind, a.what)));
+    }
+
+    let snapshot = StateSnapshot::new(&engine.state, &engine.aux);
+    (
+        diagnostics,
+        Analysis {
+            sections: result.sections,
+            snapshot,
+        },
+    )
+}
+
+/// A diagnostic from the given (1-based) line and column to the end of the line; `(0, 0)` for
+/// "somewhere in the document".
+fn diagnostic((line, col): (usize, usize), severity: usize, message: String) -> Json {
+    let line = line.saturating_sub(1);
+    object([
+        ("range", range((line, col.saturating_sub(1)), (line + 1, 0))),
+        ("severity", severity.into()),
+        ("source", "RusTeX".into()),
+        ("message", message.into()),
+    ])
+}
+/// A range between two (0-based) positions.
+fn range(start: (usize, usize), end: (usize, usize)) -> Json {
+    let pos = |(line, character): (usize, usize)| {
+        object([("line", line.into()), ("character", character.into())])
+    };
+    object([("start", pos(start)), ("end", pos(end))])
+}
+
+/// Splits a group starting at the beginning of `s` into its content and the rest.
+fn braced(s: &str, open: char, close: char) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Some((&s[1..i], &s[i + 1..]));
+            }
+        }
+    }
+    None
+}
+
+/// The argument of the first command on the line, e.g. `Introduction` for
+/// `\section[Intro]{Introduction}`; the line itself if there is none.
+fn heading_title(line: &str) -> String {
+    let title = line.find('\\').and_then(|i| {
+        let rest = line[i + 1..].trim_start_matches(|c: char| c.is_alphabetic() || c == '@');
+        let mut rest = rest.trim_start_matches('*').trim_start();
+        if rest.starts_with('[') {
+            rest = braced(rest, '[', ']')?.1.trim_start();
+        }
+        rest.starts_with('{')
+            .then(|| braced(rest, '{', '}'))
+            .flatten()
+            .map(|(arg, _)| arg.trim().to_string())
+    });
+    title
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| line.trim().to_string())
+}
+
+struct Symbol {
+    name: String,
+    kind: usize,
+    detail: String,
+    /// 1-based, inclusive
+    lines: (usize, usize),
+    children: Vec<Symbol>,
+}
+impl Symbol {
+    fn to_json(&self) -> Json {
+        let (start, end) = (self.lines.0.saturating_sub(1), self.lines.1);
+        object([
+            ("name", self.name.as_str().into()),
+            ("detail", self.detail.as_str().into()),
+            ("kind", self.kind.into()),
+            ("range", range((start, 0), (end, 0))),
+            ("selectionRange", range((start, 0), (start + 1, 0))),
+            (
+                "children",
+                self.children
+                    .iter()
+                    .map(Symbol::to_json)
+                    .collect::<Vec<_>>()
+                    .into(),
+            ),
+        ])
+    }
+}
+
+fn document_symbols(doc: &Document) -> Vec<Json> {
+    let Some(analysis) = &doc.analysis else {
+        return Vec::new();
+    };
+    let canonical = doc.path.canonicalize().unwrap_or_else(|_| doc.path.clone());
+    let lines: Vec<&str> = doc.text.lines().collect();
+    // sections nest by level; the stack holds the currently open ones
+    let mut top: Vec<Symbol> = Vec::new();
+    let mut stack: Vec<(i32, Symbol)> = Vec::new();
+    let close = |stack: &mut Vec<(i32, Symbol)>, top: &mut Vec<Symbol>| {
+        if let Some((_, s)) = stack.pop() {
+            match stack.last_mut() {
+                Some((_, parent)) => parent.children.push(s),
+                None => top.push(s),
+            }
+        }
+    };
+    for section in analysis.sections.iter().filter(|s| s.file == canonical) {
+        while stack.last().is_some_and(|(l, _)| *l >= section.level) {
+            close(&mut stack, &mut top);
+        }
+        let heading = lines.get(section.lines.0 - 1).copied().unwrap_or_default();
+        stack.push((
+            section.level,
+            Symbol {
+                name: heading_title(heading),
+                kind: if section.level <= 0 {
+                    SYMBOL_MODULE
+                } else {
+                    SYMBOL_NAMESPACE
+                },
+                detail: String::new(),
+                lines: section.lines,
+                children: Vec::new(),
+            },
+        ));
+    }
+    while !stack.is_empty() {
+        close(&mut stack, &mut top);
+    }
+    let dir = doc.path.parent().unwrap_or(Path::new(""));
+    for m in &analysis.snapshot.macros {
+        let Some(site) = &m.defined_at else { continue };
+        let file = dir.join(&site.file);
+        if file.canonicalize().unwrap_or(file) == canonical {
+            top.push(Symbol {
+                name: m.name.clone(),
+                kind: SYMBOL_FUNCTION,
+                detail: m.parameters.clone(),
+                lines: (site.line, site.line),
+                children: Vec::new(),
+            });
+        }
+    }
+    top.iter().map(Symbol::to_json).collect()
+}
+
+/// The control sequence (with backslash) at the given (0-based) position in `text`.
+fn control_sequence_at(text: &str, line: usize, character: usize) -> Option<String> {
+    let chars: Vec<char> = text.lines().nth(line)?.chars().collect();
+    let is_letter = |c: &char| c.is_alphabetic() || *c == '@';
+    let mut start = character.min(chars.len());
+    if chars.get(start) != Some(&'\\') {
+        while start > 0 && is_letter(&chars[start - 1]) {
+            start -= 1;
+        }
+        start = start.checked_sub(1).filter(|i| chars[*i] == '\\')?;
+    }
+    let name: String = chars[start + 1..]
+        .iter()
+        .take_while(|c| is_letter(c))
+        .collect();
+    match name.is_empty() {
+        true => chars.get(start + 1).map(|c| format!("\\{c}")),
+        false => Some(format!("\\{name}")),
+    }
+}
+
+fn hover(doc: &Document, line: usize, character: usize) -> Option<Json> {
+    let snapshot = &doc.analysis.as_ref()?.snapshot;
+    let name = control_sequence_at(&doc.text, line, character)?;
+    let contents = match snapshot.get_macro(&name) {
+        Some(m) => {
+            let mut s = String::from("```latex\n");
+            for (set, prefix) in [
+                (m.protected, "\\protected"),
+                (m.long, "\\long"),
+                (m.outer, "\\outer"),
+            ] {
+                if set {
+                    s.push_str(prefix);
+                }
+            }
+            s.push_str(&format!(
+                "\\def{}{}{{{}}}\n```",
+                m.name, m.parameters, m.body
+            ));
+            if let Some(site) = &m.defined_at {
+                s.push_str(&format!(
+                    "\n\ndefined in `{}`, line {}",
+                    site.file, site.line
+                ));
+            }
+            s
+        }
+        None => {
+            let (_, meaning) = snapshot.commands.iter().find(|(n, _)| *n == name)?;
+            format!("`{name}`: {meaning}")
+        }
+    };
+    Some(object([(
+        "contents",
+        object([("kind", "markdown".into()), ("value", contents.into())]),
+    )]))
+}
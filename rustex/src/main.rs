@@ -3,12 +3,26 @@
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 use rustex_lib::engine::{RusTeXEngine, RusTeXEngineT, Settings};
+use rustex_lib::epub::EpubOptions;
+use rustex_lib::shipout::client_math::MathRenderer;
+use rustex_lib::shipout::layout::PageGeometry;
+use rustex_lib::shipout::merge::MathMergeSettings;
+use rustex_lib::shipout::split::SplitUnit;
+use rustex_lib::shipout::text::{TextFormat, TextMath, TextOptions};
 
 use clap::Parser;
-use rustex_lib::engine::files::RusTeXFileSystem;
+use rustex_lib::engine::files::{AuxFiles, RusTeXFileSystem, ShellEscape};
 use rustex_lib::engine::output::RusTeXOutput;
+use rustex_lib::engine::sanitize::{RawCSSPolicy, RawContentPolicy, RawHTMLPolicy};
 use rustex_lib::engine::Types;
+use rustex_lib::makeindex::IndexOptions;
+use rustex_lib::project::Project;
+use rustex_lib::sink::{CompressingSink, Compression, DirectorySink, OutputSink};
+use rustex_lib::thumbnails::{HeadlessBrowser, ThumbnailOptions, ThumbnailRenderer};
 use std::path::{Path, PathBuf};
+use tex_engine::engine::utils::limits::Limits;
+use tex_engine::engine::utils::progress::Progress;
+use tex_engine::engine::utils::trace::MacroTrace;
 use tex_engine::engine::{DefaultEngine, TeXEngine};
 use tex_engine::pdflatex::commands::register_pdftex_primitives;
 
@@ -159,6 +173,47 @@ fn test() {
             sourcerefs: true,
             image_options: Default::default(),
             insert_font_info: true,
+            merge_math: None,
+            shell_escape: Default::default(),
+            limits: Default::default(),
+            progress: None,
+            raw_content: Default::default(),
+            aux_files: Default::default(),
+            max_passes: 1,
+            bibtex: false,
+            makeindex: None,
+            glossaries: false,
+            section_spans: false,
+            split_pages: false,
+            smart_punctuation: false,
+            macro_trace: None,
+            conditional_trace: false,
+            catcode_trace: false,
+            statistics: false,
+            strict: false,
+            hardened: false,
+            provenance_via: false,
+            locale: None,
+            fallback_font: None,
+            fallback_glyphs: false,
+            split_at: None,
+            stex_dependencies: false,
+            math_renderer: None,
+            pdf: false,
+            dvi: false,
+            implicit_kerns: false,
+            page_geometry: PageGeometry::default(),
+            reflow: false,
+            fil_cap: None,
+            random_seed: None,
+            clock: None,
+            sidenotes: false,
+            animations: false,
+            verbatim: false,
+            highlighter: None,
+            rotate_landscape: false,
+            headers: false,
+            decoration_filter: None,
         },
     );
     ret.write_out(Path::new(
@@ -180,6 +235,47 @@ fn temp_test() {
             sourcerefs: true,
             image_options: Default::default(),
             insert_font_info: true,
+            merge_math: None,
+            shell_escape: Default::default(),
+            limits: Default::default(),
+            progress: None,
+            raw_content: Default::default(),
+            aux_files: Default::default(),
+            max_passes: 1,
+            bibtex: false,
+            makeindex: None,
+            glossaries: false,
+            section_spans: false,
+            split_pages: false,
+            smart_punctuation: false,
+            macro_trace: None,
+            conditional_trace: false,
+            catcode_trace: false,
+            statistics: false,
+            strict: false,
+            hardened: false,
+            provenance_via: false,
+            locale: None,
+            fallback_font: None,
+            fallback_glyphs: false,
+            split_at: None,
+            stex_dependencies: false,
+            math_renderer: None,
+            pdf: false,
+            dvi: false,
+            implicit_kerns: false,
+            page_geometry: PageGeometry::default(),
+            reflow: false,
+            fil_cap: None,
+            random_seed: None,
+            clock: None,
+            sidenotes: false,
+            animations: false,
+            verbatim: false,
+            highlighter: None,
+            rotate_landscape: false,
+            headers: false,
+            decoration_filter: None,
         },
     );
     //let ret = RusTeXEngine::do_file("/home/jazzpirate/work/LaTeX/Papers/17 - Alignment Translation/macros/kwarc/workplan/workplan-template.tex",true,true,true);
@@ -202,6 +298,47 @@ fn thesis() {
             sourcerefs: true,
             image_options: Default::default(),
             insert_font_info: false,
+            merge_math: None,
+            shell_escape: Default::default(),
+            limits: Default::default(),
+            progress: None,
+            raw_content: Default::default(),
+            aux_files: Default::default(),
+            max_passes: 1,
+            bibtex: false,
+            makeindex: None,
+            glossaries: false,
+            section_spans: false,
+            split_pages: false,
+            smart_punctuation: false,
+            macro_trace: None,
+            conditional_trace: false,
+            catcode_trace: false,
+            statistics: false,
+            strict: false,
+            hardened: false,
+            provenance_via: false,
+            locale: None,
+            fallback_font: None,
+            fallback_glyphs: false,
+            split_at: None,
+            stex_dependencies: false,
+            math_renderer: None,
+            pdf: false,
+            dvi: false,
+            implicit_kerns: false,
+            page_geometry: PageGeometry::default(),
+            reflow: false,
+            fil_cap: None,
+            random_seed: None,
+            clock: None,
+            sidenotes: false,
+            animations: false,
+            verbatim: false,
+            highlighter: None,
+            rotate_landscape: false,
+            headers: false,
+            decoration_filter: None,
         },
     );
     ret.write_out(Path::new(
@@ -221,6 +358,47 @@ fn notes() {
             sourcerefs: true,
             image_options: Default::default(),
             insert_font_info: false,
+            merge_math: None,
+            shell_escape: Default::default(),
+            limits: Default::default(),
+            progress: None,
+            raw_content: Default::default(),
+            aux_files: Default::default(),
+            max_passes: 1,
+            bibtex: false,
+            makeindex: None,
+            glossaries: false,
+            section_spans: false,
+            split_pages: false,
+            smart_punctuation: false,
+            macro_trace: None,
+            conditional_trace: false,
+            catcode_trace: false,
+            statistics: false,
+            strict: false,
+            hardened: false,
+            provenance_via: false,
+            locale: None,
+            fallback_font: None,
+            fallback_glyphs: false,
+            split_at: None,
+            stex_dependencies: false,
+            math_renderer: None,
+            pdf: false,
+            dvi: false,
+            implicit_kerns: false,
+            page_geometry: PageGeometry::default(),
+            reflow: false,
+            fil_cap: None,
+            random_seed: None,
+            clock: None,
+            sidenotes: false,
+            animations: false,
+            verbatim: false,
+            highlighter: None,
+            rotate_landscape: false,
+            headers: false,
+            decoration_filter: None,
         },
     );
     //let ret = RusTeXEngine::do_file("/home/jazzpirate/work/MathHub/MiKoMH/CompLog/source/kr/tikz/axioms2.tex",true,true,true);
@@ -291,6 +469,226 @@ struct Parameters {
     #[clap(short, long, default_value_t = false)]
     profile: bool,
 
+    /// abort after this many seconds
+    #[clap(long)]
+    timeout: Option<u64>,
+
+    /// print progress (opened files, shipped pages) to stderr
+    #[clap(long, default_value_t = false)]
+    progress: bool,
+
+    /// read auxiliary files (.aux) from the input directory and write them back afterwards
+    #[clap(long, default_value_t = false)]
+    aux: bool,
+
+    /// rerun until cross-references are stable, at most this many times
+    #[clap(long, default_value_t = 1)]
+    passes: usize,
+
+    /// resolve citations against the .bib files unless there is a .bbl from BibTeX or biber;
+    /// not for biblatex (needs --passes > 1 or --aux)
+    #[clap(long, default_value_t = false)]
+    bibtex: bool,
+
+    /// generate the index from the .idx files (needs --passes > 1 or --aux)
+    #[clap(long, default_value_t = false)]
+    makeindex: bool,
+
+    /// makeindex style file (.ist) for --makeindex
+    #[clap(long)]
+    index_style: Option<String>,
+
+    /// generate the glossaries of the glossaries package (needs --passes > 1 or --aux)
+    #[clap(long, default_value_t = false)]
+    glossaries: bool,
+
+    /// annotate every section with the line and byte range of its source
+    #[clap(long, default_value_t = false)]
+    section_spans: bool,
+
+    /// turn --, ---, `` and '' into Unicode dashes and quotes where the fonts do not
+    #[clap(long, default_value_t = false)]
+    smart_punctuation: bool,
+
+    /// compile this earlier version of the input as well and highlight the blocks that changed
+    /// since then
+    #[clap(long)]
+    diff_against: Option<String>,
+
+    /// render PNG thumbnails of all pages next to the output, using this (Chromium-compatible)
+    /// headless browser
+    #[clap(long)]
+    thumbnails: Option<String>,
+
+    /// width of the thumbnails in pixels
+    #[clap(long, default_value_t = 320)]
+    thumbnail_width: u32,
+
+    /// additionally write gzip-compressed .gz versions of HTML/CSS/SVG outputs
+    #[clap(long, default_value_t = false)]
+    gzip: bool,
+
+    /// additionally write brotli-compressed .br versions of HTML/CSS/SVG outputs
+    #[clap(long, default_value_t = false)]
+    brotli: bool,
+
+    /// what to do with raw HTML, CSS and scripts inserted by the document
+    #[clap(long, default_value = "sanitize", value_parser = ["deny", "sanitize", "allow"])]
+    raw_content: String,
+
+    /// commands \write18 may run (comma separated)
+    #[clap(long, value_delimiter = ',')]
+    shell_escape: Vec<String>,
+
+    /// record all macro expansions and write them to this file (as JSON)
+    #[clap(long)]
+    macro_trace: Option<String>,
+
+    /// write the macro trace in the Chrome trace event format (chrome://tracing, Perfetto)
+    #[clap(long, default_value_t = false)]
+    chrome_trace: bool,
+
+    /// only record expansions of these macros (comma separated, without backslash)
+    #[clap(long, value_delimiter = ',')]
+    trace_only: Vec<String>,
+
+    /// record which branch every conditional took and write them to this file (as JSON)
+    #[clap(long)]
+    if_trace: Option<String>,
+
+    /// record every change of a category code and the regions of the input read verbatim and
+    /// write them to this file (as JSON)
+    #[clap(long)]
+    catcode_trace: Option<String>,
+
+    /// write the structure of the output (paragraphs, boxes, tables, formulas... with their
+    /// source references) to this file (as JSON), for comparison with `RusTeX diff`
+    #[clap(long)]
+    ir: Option<String>,
+
+    /// write a summary of the fonts, files and packages used and of the warnings and
+    /// unsupported primitives encountered to this file (as JSON)
+    #[clap(long)]
+    statistics: Option<String>,
+
+    /// fail instead of producing output if any approximation (stubbed primitive, missing
+    /// glyph, dropped node, reassembled paragraph) was taken
+    #[clap(long)]
+    strict: bool,
+
+    /// report an error instead of crashing if malformed input breaks an internal invariant
+    /// of the engine
+    #[clap(long)]
+    hardened: bool,
+
+    /// with --sourcerefs, record where the contents of unpacked boxes were unpacked at
+    #[clap(long)]
+    provenance_via: bool,
+
+    /// the language for dates and numbers (e.g. `\today`) if the document does not load babel
+    /// or polyglossia, e.g. `ngerman` or `fr`
+    #[clap(long)]
+    locale: Option<String>,
+
+    /// the font used in place of fonts whose metrics can not be found (default: cmr10)
+    #[clap(long)]
+    fallback_font: Option<String>,
+
+    /// also use the glyphs of the fallback font for fonts whose encoding is unknown
+    #[clap(long, default_value_t = false)]
+    fallback_glyphs: bool,
+
+    /// split the output into one file per unit, with navigation and a table of contents in the
+    /// output file: part, chapter, section, subsection or frame
+    #[clap(long)]
+    split: Option<String>,
+
+    /// write the modules declared by an sTeX document and their dependencies as JSON to this
+    /// file
+    #[clap(long)]
+    stex_deps: Option<String>,
+
+    /// additionally write the output as an EPUB 3 publication to this file, with one content
+    /// document per chapter (or per unit given by --split)
+    #[clap(long)]
+    epub: Option<String>,
+
+    /// additionally write the text of the output to this file, as Markdown if it ends in `.md`
+    /// and as plain text otherwise
+    #[clap(long)]
+    plain_text: Option<String>,
+
+    /// in the --plain-text output, give formulas as their TeX source rather than in Unicode
+    #[clap(long)]
+    text_tex_math: bool,
+
+    /// emit formulas as TeX, rendered in the browser by the given library (mathjax or katex)
+    /// instead of as MathML
+    #[clap(long)]
+    math_renderer: Option<String>,
+
+    /// additionally write the pages as laid out by RusTeX to this PDF file (experimental; for
+    /// comparing the layout with pdfTeX's)
+    #[clap(long)]
+    pdf: Option<String>,
+
+    /// additionally write the pages as laid out by RusTeX to this DVI file (experimental)
+    #[clap(long)]
+    dvi: Option<String>,
+
+    /// insert the fonts' implicit kerns as separate nodes, so that the --pdf and --dvi output
+    /// matches pdfTeX's word shapes
+    #[clap(long, default_value_t = false)]
+    implicit_kerns: bool,
+
+    /// how the page geometry maps to the HTML: text (a centered column of the text width),
+    /// pages (one container per page, with its margins) or responsive (ignore the geometry)
+    #[clap(long)]
+    geometry: Option<String>,
+
+    /// emit horizontal dimensions relative to the text width and paragraphs without hard line
+    /// breaks, so that the output adapts to small screens
+    #[clap(long, default_value_t = false)]
+    reflow: bool,
+
+    /// render margin notes (\marginpar) as sidenotes next to the text they belong to
+    #[clap(long, default_value_t = false)]
+    sidenotes: bool,
+
+    /// render the animations of the animate package with all their frames, played by CSS
+    #[clap(long, default_value_t = false)]
+    animations: bool,
+
+    /// render verbatim environments and code listings as <pre> blocks with exact whitespace
+    #[clap(long, default_value_t = false)]
+    verbatim: bool,
+
+    /// keep the pages of landscape environments rotated, as printed
+    #[clap(long, default_value_t = false)]
+    rotate_landscape: bool,
+
+    /// keep the headers and footers of the pages, as hidden <header>/<footer> elements
+    #[clap(long, default_value_t = false)]
+    headers: bool,
+
+    /// the maximal height (in pt) of the space \vfil and friends take up in the HTML
+    #[clap(long)]
+    fil_cap: Option<f32>,
+
+    /// the seed for `\pdfuniformdeviate` and friends, for reproducible output
+    #[clap(long)]
+    random_seed: Option<i32>,
+
+    /// the time to compile the document at (RFC 3339, e.g. `2024-01-31T12:00:00+01:00`, or
+    /// seconds since 1970), overriding SOURCE_DATE_EPOCH
+    #[clap(long)]
+    clock: Option<String>,
+
+    /// merge adjacent inline formulas separated by one of these strings
+    /// (comma separated; empty entries merge formulas separated only by whitespace)
+    #[clap(long, value_delimiter = ',')]
+    merge_math: Option<Vec<String>>,
+
     #[command(subcommand)]
     sub: Option<SubCmd>,
 }
@@ -306,6 +704,57 @@ enum SubCmd {
         #[arg(required = true)]
         path: String,
     },
+    /// compile all documents of a project (given by a manifest file listing one document
+    /// per line, or a directory to scan for documents) into an output directory
+    Project {
+        /// manifest file or directory
+        #[arg(required = true)]
+        source: String,
+        /// output directory
+        #[arg(required = true)]
+        out: String,
+        /// recompile documents even if they are up to date
+        #[clap(long, default_value_t = false)]
+        force: bool,
+        /// render PNG thumbnails of all pages, using this (Chromium-compatible) headless browser
+        #[clap(long)]
+        thumbnails: Option<String>,
+        /// additionally write gzip-compressed .gz versions of HTML/CSS/SVG outputs
+        #[clap(long, default_value_t = false)]
+        gzip: bool,
+        /// additionally write brotli-compressed .br versions of HTML/CSS/SVG outputs
+        #[clap(long, default_value_t = false)]
+        brotli: bool,
+    },
+    /// generate hyphenation patterns from a list of hyphenated words (e.g. `hy-phen-ation`)
+    Patgen {
+        /// the word list
+        #[arg(required = true)]
+        words: String,
+        /// the file to write the patterns to (default: standard output)
+        #[clap(short, long)]
+        out: Option<String>,
+        #[clap(long, default_value_t = 2)]
+        left_hyphen_min: usize,
+        #[clap(long, default_value_t = 3)]
+        right_hyphen_min: usize,
+        /// also write words the patterns get wrong as `\hyphenation` exceptions
+        #[clap(long, default_value_t = false)]
+        exceptions: bool,
+    },
+    /// compare the structures of two outputs written with `--ir` and list the boxes and other
+    /// nodes that moved, changed, were inserted or deleted; exits with status 1 if there are any
+    Diff {
+        /// the structure of the old version
+        #[arg(required = true)]
+        old: String,
+        /// the structure of the new version
+        #[arg(required = true)]
+        new: String,
+        /// print the changes as JSON
+        #[clap(long, default_value_t = false)]
+        json: bool,
+    },
     /// font info
     Font {
         /// name of the font
@@ -323,6 +772,85 @@ enum SubCmd {
     },
 }
 
+fn output_sink(dir: &Path, gzip: bool, brotli: bool) -> Box<dyn OutputSink> {
+    if gzip || brotli {
+        Box::new(CompressingSink::new(
+            DirectorySink::new(dir),
+            Compression {
+                gzip,
+                brotli,
+                ..Compression::default()
+            },
+        ))
+    } else {
+        Box::new(DirectorySink::new(dir))
+    }
+}
+
+/// An RFC 3339 date or a number of seconds since 1970 (in UTC).
+fn parse_clock(s: &str) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    match s.trim().parse::<i64>() {
+        Ok(secs) => chrono::DateTime::from_timestamp(secs, 0).map(|t| t.fixed_offset()),
+        Err(_) => chrono::DateTime::parse_from_rfc3339(s.trim()).ok(),
+    }
+}
+
+fn do_project(
+    source: String,
+    out: String,
+    force: bool,
+    thumbnails: Option<String>,
+    gzip: bool,
+    brotli: bool,
+) {
+    let source = Path::new(&source);
+    let project = if source.is_dir() {
+        Project::scan(source)
+    } else {
+        Project::from_manifest(source)
+    };
+    let project = match project {
+        Ok(p) => p,
+        Err(e) => return eprintln!("Error reading project: {e}"),
+    };
+    let mut browser = thumbnails.map(|command| HeadlessBrowser { command });
+    let options = ThumbnailOptions::default();
+    let renderer = browser
+        .as_mut()
+        .map(|b| (&options, b as &mut dyn ThumbnailRenderer));
+    let out = Path::new(&out);
+    let mut sink = output_sink(out, gzip, brotli);
+    match project.compile_to(out, &mut *sink, force, Settings::default, renderer) {
+        Ok(docs) => {
+            for d in docs {
+                match (d.compiled, d.error) {
+                    (false, _) => println!("{}: up to date", d.document.display()),
+                    (true, None) => println!("{}: done", d.document.display()),
+                    (true, Some(e)) => println!("{}: {e}", d.document.display()),
+                }
+            }
+        }
+        Err(e) => eprintln!("Error writing output: {e}"),
+    }
+}
+
+fn print_progress(p: Progress) {
+    match p {
+        Progress::FormatLoaded(name) => eprintln!("[progress] loaded {name}"),
+        Progress::FileOpened { path, depth } => {
+            eprintln!("[progress] {}{}", "  ".repeat(depth), path.display())
+        }
+        Progress::PageShipped {
+            page,
+            percent: Some(pct),
+        } => eprintln!("[progress] page {page} ({pct}%)"),
+        Progress::PageShipped {
+            page,
+            percent: None,
+        } => eprintln!("[progress] page {page}"),
+    }
+}
+
 fn run() {
     //env_logger::builder().filter_level(log::LevelFilter::Info).try_init();
     let params = Parameters::parse();
@@ -333,6 +861,22 @@ fn run() {
     if let Some(k) = params.sub {
         match k {
             SubCmd::Kpse { log, path } => return kpse(log, path),
+            SubCmd::Project {
+                source,
+                out,
+                force,
+                thumbnails,
+                gzip,
+                brotli,
+            } => return do_project(source, out, force, thumbnails, gzip, brotli),
+            SubCmd::Patgen {
+                words,
+                out,
+                left_hyphen_min,
+                right_hyphen_min,
+                exceptions,
+            } => return patgen(words, out, left_hyphen_min, right_hyphen_min, exceptions),
+            SubCmd::Diff { old, new, json } => return do_diff(old, new, json),
             SubCmd::Font { name } => return do_font(name),
             SubCmd::Glyph {
                 font,
@@ -343,7 +887,30 @@ fn run() {
     }
     match (params.input, params.output) {
         (Some(i), Some(o)) => {
-            let ret = RusTeXEngine::do_file(
+            let aux_files = match Path::new(&i).parent() {
+                Some(dir) if params.aux => AuxFiles::read_dir(dir).unwrap_or_default(),
+                _ => AuxFiles::default(),
+            };
+            let clock = match params.clock.as_deref().map(parse_clock) {
+                Some(None) => return eprintln!("Invalid --clock: expected RFC 3339 or seconds"),
+                Some(Some(c)) => Some(c),
+                None => None,
+            };
+            let old = params.diff_against.as_ref().map(|old| {
+                RusTeXEngine::do_file(
+                    old.as_str(),
+                    Settings {
+                        insert_font_info: params.glyph_debug,
+                        merge_math: params
+                            .merge_math
+                            .clone()
+                            .map(|separators| MathMergeSettings { separators }),
+                        smart_punctuation: params.smart_punctuation,
+                        ..Default::default()
+                    },
+                )
+            });
+            let mut ret = RusTeXEngine::do_file(
                 i.as_str(),
                 Settings {
                     verbose: params.verbose,
@@ -351,9 +918,213 @@ fn run() {
                     sourcerefs: params.sourcerefs,
                     image_options: Default::default(),
                     insert_font_info: params.glyph_debug,
+                    merge_math: params
+                        .merge_math
+                        .map(|separators| MathMergeSettings { separators }),
+                    shell_escape: ShellEscape {
+                        whitelist: params.shell_escape,
+                    },
+                    limits: Limits {
+                        timeout: params.timeout.map(std::time::Duration::from_secs),
+                        ..Default::default()
+                    },
+                    progress: if params.progress {
+                        Some(Box::new(print_progress))
+                    } else {
+                        None
+                    },
+                    raw_content: match params.raw_content.as_str() {
+                        "deny" => RawContentPolicy {
+                            html: RawHTMLPolicy::Deny,
+                            css: RawCSSPolicy::Deny,
+                            scripts: false,
+                        },
+                        "allow" => RawContentPolicy {
+                            html: RawHTMLPolicy::Allow,
+                            css: RawCSSPolicy::Allow,
+                            scripts: true,
+                        },
+                        _ => RawContentPolicy::default(),
+                    },
+                    aux_files,
+                    max_passes: params.passes,
+                    bibtex: params.bibtex,
+                    makeindex: params.makeindex.then(|| IndexOptions {
+                        style: params.index_style,
+                        ..Default::default()
+                    }),
+                    glossaries: params.glossaries,
+                    section_spans: params.section_spans
+                        || params.epub.is_some()
+                        || params.plain_text.is_some(),
+                    split_pages: params.thumbnails.is_some(),
+                    smart_punctuation: params.smart_punctuation,
+                    macro_trace: params
+                        .macro_trace
+                        .as_ref()
+                        .map(|_| MacroTrace::restricted_to(params.trace_only.clone())),
+                    conditional_trace: params.if_trace.is_some(),
+                    catcode_trace: params.catcode_trace.is_some(),
+                    statistics: params.statistics.is_some(),
+                    strict: params.strict,
+                    hardened: params.hardened,
+                    provenance_via: params.provenance_via,
+                    locale: params.locale,
+                    fallback_font: params.fallback_font,
+                    fallback_glyphs: params.fallback_glyphs,
+                    split_at: params.split.as_deref().and_then(SplitUnit::from_name),
+                    stex_dependencies: params.stex_deps.is_some(),
+                    math_renderer: params
+                        .math_renderer
+                        .as_deref()
+                        .and_then(MathRenderer::from_name),
+                    pdf: params.pdf.is_some(),
+                    dvi: params.dvi.is_some(),
+                    implicit_kerns: params.implicit_kerns,
+                    page_geometry: params
+                        .geometry
+                        .as_deref()
+                        .and_then(PageGeometry::from_name)
+                        .unwrap_or_default(),
+                    reflow: params.reflow,
+                    sidenotes: params.sidenotes,
+                    animations: params.animations,
+                    verbatim: params.verbatim,
+                    highlighter: None,
+                    rotate_landscape: params.rotate_landscape,
+                    headers: params.headers,
+                    decoration_filter: None,
+                    fil_cap: params.fil_cap,
+                    random_seed: params.random_seed,
+                    clock,
                 },
             );
-            ret.write_out(Path::new(&o)).unwrap();
+            if ret.rerun_needed {
+                eprintln!(
+                    "Cross-references may have changed; rerun (or use --passes) to get them right."
+                );
+            }
+            if let Some(old) = &old {
+                let diff = ret.mark_changes(old);
+                eprintln!(
+                    "{} blocks changed, {} inserted, {} deleted, {} unchanged",
+                    diff.changed, diff.inserted, diff.deleted, diff.unchanged
+                );
+            }
+            if let (Some(path), Some(trace)) = (&params.macro_trace, &ret.macro_trace) {
+                let json = if params.chrome_trace {
+                    trace.to_chrome_trace()
+                } else {
+                    trace.to_json()
+                };
+                if let Err(e) = std::fs::write(path, json) {
+                    eprintln!("Error writing macro trace: {e}");
+                }
+            }
+            if let (Some(path), Some(trace)) = (&params.if_trace, &ret.conditional_trace) {
+                if let Err(e) = std::fs::write(path, trace.to_json()) {
+                    eprintln!("Error writing conditional trace: {e}");
+                }
+            }
+            if let (Some(path), Some(trace)) = (&params.catcode_trace, &ret.catcode_trace) {
+                if let Err(e) = std::fs::write(path, trace.to_json()) {
+                    eprintln!("Error writing catcode trace: {e}");
+                }
+            }
+            if let Some(path) = &params.ir {
+                if let Err(e) = std::fs::write(path, ret.ir().to_json()) {
+                    eprintln!("Error writing output structure: {e}");
+                }
+            }
+            if let (Some(path), Some(stats)) = (&params.statistics, &ret.statistics) {
+                if let Err(e) = std::fs::write(path, stats.to_json()) {
+                    eprintln!("Error writing statistics: {e}");
+                }
+            }
+            if let (Some(path), Some(deps)) = (&params.stex_deps, &ret.stex_dependencies) {
+                if let Err(e) = std::fs::write(path, deps.to_json()) {
+                    eprintln!("Error writing sTeX dependencies: {e}");
+                }
+            }
+            if let Some(path) = &params.epub {
+                let options = EpubOptions {
+                    title: Path::new(&i)
+                        .file_stem()
+                        .map_or_else(String::new, |s| s.to_string_lossy().into_owned()),
+                    language: ret.language().unwrap_or("en").to_string(),
+                    modified: clock.map(|c| c.to_utc()),
+                    ..Default::default()
+                };
+                if let Err(e) = ret.write_epub(Path::new(path), &options) {
+                    eprintln!("Error writing EPUB: {e}");
+                }
+            }
+            if let Some(path) = &params.plain_text {
+                let options = TextOptions {
+                    format: if path.ends_with(".md") {
+                        TextFormat::Markdown
+                    } else {
+                        TextFormat::Plain
+                    },
+                    math: if params.text_tex_math {
+                        TextMath::TeX
+                    } else {
+                        TextMath::Unicode
+                    },
+                };
+                if let Err(e) = std::fs::write(path, ret.text(&options)) {
+                    eprintln!("Error writing text: {e}");
+                }
+            }
+            if let (Some(path), Some(pdf)) = (&params.pdf, &ret.pdf) {
+                if let Err(e) = std::fs::write(path, pdf) {
+                    eprintln!("Error writing PDF: {e}");
+                }
+            }
+            if let (Some(path), Some(dvi)) = (&params.dvi, &ret.dvi) {
+                if let Err(e) = std::fs::write(path, dvi) {
+                    eprintln!("Error writing DVI: {e}");
+                }
+            }
+            if params.aux {
+                let dir = Path::new(&i).parent().unwrap_or(Path::new(""));
+                if let Err(e) = ret.aux_files.write(dir) {
+                    eprintln!("Error writing auxiliary files: {e}");
+                }
+            }
+            if !ret.font_report.is_empty() {
+                eprint!("{}", ret.font_report);
+            }
+            if params.strict && ret.error.is_some() {
+                std::process::exit(1);
+            }
+            let out = Path::new(&o);
+            let dir = out.parent().unwrap_or(Path::new(""));
+            let mut sink = output_sink(dir, params.gzip, params.brotli);
+            let stem = out
+                .file_stem()
+                .map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+            let name = out
+                .file_name()
+                .map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+            ret.write_to(&mut *sink, &name).unwrap();
+            if let Some(command) = params.thumbnails {
+                let options = ThumbnailOptions {
+                    width: params.thumbnail_width,
+                    ..Default::default()
+                };
+                if let Err(e) = rustex_lib::thumbnails::generate(
+                    &ret,
+                    dir,
+                    &stem,
+                    &options,
+                    &mut HeadlessBrowser { command },
+                    &mut *sink,
+                ) {
+                    eprintln!("Error generating thumbnails: {e}");
+                }
+            }
+            sink.finalize().unwrap();
         }
         _ => {
             println!("No input/output file given. Testing latex.ltx...");
@@ -403,6 +1174,69 @@ fn kpse(log: bool, path: String) {
     }
 }
 
+fn patgen(
+    words: String,
+    out: Option<String>,
+    left_hyphen_min: usize,
+    right_hyphen_min: usize,
+    exceptions: bool,
+) {
+    use rustex_lib::patgen::{generate, PatgenOptions, Word};
+    let words = match std::fs::read_to_string(&words) {
+        Ok(s) => Word::parse_list(&s),
+        Err(e) => return eprintln!("Error reading {words}: {e}"),
+    };
+    let options = PatgenOptions {
+        left_hyphen_min,
+        right_hyphen_min,
+        ..PatgenOptions::default()
+    };
+    let patterns = generate(&words, &options);
+    let eval = patterns.evaluate(&words);
+    eprintln!(
+        "{} patterns; {} hyphens found, {} wrong, {} missed",
+        patterns.len(),
+        eval.good,
+        eval.bad,
+        eval.missed
+    );
+    let tex = patterns.to_tex(if exceptions { &eval.exceptions } else { &[] });
+    match out {
+        Some(out) => {
+            if let Err(e) = std::fs::write(&out, tex) {
+                eprintln!("Error writing {out}: {e}");
+            }
+        }
+        None => print!("{tex}"),
+    }
+}
+
+fn do_diff(old: String, new: String, json: bool) {
+    use rustex_lib::shipout::ir::{changes_to_json, diff, IrNode};
+    let read = |path: &str| match std::fs::read_to_string(path) {
+        Ok(s) => IrNode::from_json(&s).ok_or_else(|| format!("{path} is not an output structure")),
+        Err(e) => Err(format!("Error reading {path}: {e}")),
+    };
+    let (old, new) = match (read(&old), read(&new)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+    let changes = diff(&old, &new);
+    if json {
+        println!("{}", changes_to_json(&changes));
+    } else {
+        for c in &changes {
+            println!("{c}");
+        }
+    }
+    if !changes.is_empty() {
+        std::process::exit(1);
+    }
+}
+
 fn do_font(s: String) {
     let mut store =
         tex_glyphs::FontInfoStore::new(
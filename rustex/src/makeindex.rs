@@ -0,0 +1,632 @@
+//! A small `makeindex` replacement: reads the `\indexentry` lines LaTeX writes to `.idx` files
+//! (via `\makeindex`/`\index`), sorts them and generates the corresponding `.ind` file (a
+//! `theindex` environment) for the next pass, so that `\printindex` works without running
+//! an external indexer.
+//!
+//! The input syntax (`@`, `!`, `|`, `"`, `|(`/`|)`, `|see{...}`) and the output format follow
+//! `makeindex`; both can be adapted with a `makeindex` style file (`.ist`). `.ind` files
+//! generated here start with [`IND_HEADER`] and are regenerated in every pass; any other
+//! existing `.ind` (e.g. generated by `makeindex` or `xindy`) takes precedence.
+//!
+//! `xindy` is only supported as far as its input format is the same as `makeindex`'s: xindy
+//! modules (`.xdy`) are not read, so documents indexed with xindy get the `makeindex` output
+//! format, and language-specific sorting needs a [`Collation::Custom`].
+
+use crate::engine::files::{AuxFiles, RusTeXFileSystem};
+use crate::engine::output::RusTeXOutput;
+use std::cmp::Ordering;
+use std::path::PathBuf;
+use tex_engine::engine::utils::outputs::Outputs;
+
+/// The first line of every `.ind` file generated by [`resolve`].
+pub const IND_HEADER: &str = "% generated by RusTeX";
+
+/// How to generate `.ind` files; see [`Settings::makeindex`](crate::engine::Settings::makeindex).
+#[derive(Debug, Clone, Default)]
+pub struct IndexOptions {
+    /// a `makeindex` style file (`.ist`), looked up like TeX input files
+    pub style: Option<String>,
+    pub collation: Collation,
+}
+
+/// How index keys are compared.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Collation {
+    /// like `makeindex`: symbols before numbers before letters; letters are compared
+    /// case-insensitively, ties broken by case; numbers by value
+    #[default]
+    Makeindex,
+    /// by Unicode code points
+    Ordinal,
+    /// a custom comparison of sort keys
+    Custom(fn(&str, &str) -> Ordering),
+}
+impl Collation {
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            Self::Ordinal => a.cmp(b),
+            Self::Custom(f) => f(a, b),
+            Self::Makeindex => Group::of(a).cmp(&Group::of(b)).then_with(|| {
+                match (a.trim().parse::<i64>(), b.trim().parse::<i64>()) {
+                    (Ok(x), Ok(y)) => x.cmp(&y),
+                    _ => a
+                        .to_lowercase()
+                        .cmp(&b.to_lowercase())
+                        .then_with(|| b.cmp(a)),
+                }
+            }),
+        }
+    }
+}
+
+/// The letter groups of an index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Group {
+    Symbols,
+    Numbers,
+    Letter(char),
+}
+impl Group {
+    fn of(key: &str) -> Self {
+        match key.trim_start().chars().next() {
+            Some(c) if c.is_alphabetic() => Self::Letter(c.to_lowercase().next().unwrap_or(c)),
+            Some(c) if c.is_ascii_digit() => Self::Numbers,
+            _ => Self::Symbols,
+        }
+    }
+}
+
+/// The parts of a `makeindex` style file RusTeX understands. Unknown keys are ignored.
+#[derive(Debug, Clone)]
+pub struct IndexStyle {
+    pub keyword: String,
+    pub actual: char,
+    pub level: char,
+    pub encap: char,
+    pub quote: char,
+    pub escape: char,
+    pub range_open: char,
+    pub range_close: char,
+    pub preamble: String,
+    pub postamble: String,
+    pub group_skip: String,
+    /// `0`: no headings; positive: upper case headings; negative: lower case headings
+    pub headings_flag: i32,
+    pub heading_prefix: String,
+    pub heading_suffix: String,
+    pub symhead: String,
+    pub numhead: String,
+    /// `item_0`, `item_1`, `item_2`
+    pub item: [String; 3],
+    pub item_01: String,
+    pub item_x1: String,
+    pub item_12: String,
+    pub item_x2: String,
+    /// `delim_0`, `delim_1`, `delim_2`
+    pub delim: [String; 3],
+    pub delim_n: String,
+    pub delim_r: String,
+    /// after the page list
+    pub delim_t: String,
+    pub encap_prefix: String,
+    pub encap_infix: String,
+    pub encap_suffix: String,
+}
+impl Default for IndexStyle {
+    fn default() -> Self {
+        Self {
+            keyword: "\\indexentry".into(),
+            actual: '@',
+            level: '!',
+            encap: '|',
+            quote: '"',
+            escape: '\\',
+            range_open: '(',
+            range_close: ')',
+            preamble: "\\begin{theindex}\n".into(),
+            postamble: "\n\n\\end{theindex}\n".into(),
+            group_skip: "\n\n  \\indexspace\n".into(),
+            headings_flag: 0,
+            heading_prefix: String::new(),
+            heading_suffix: String::new(),
+            symhead: "Symbols".into(),
+            numhead: "Numbers".into(),
+            item: [
+                "\n  \\item ".into(),
+                "\n    \\subitem ".into(),
+                "\n      \\subsubitem ".into(),
+            ],
+            item_01: "\n    \\subitem ".into(),
+            item_x1: "\n    \\subitem ".into(),
+            item_12: "\n      \\subsubitem ".into(),
+            item_x2: "\n      \\subsubitem ".into(),
+            delim: [", ".into(), ", ".into(), ", ".into()],
+            delim_n: ", ".into(),
+            delim_r: "--".into(),
+            delim_t: String::new(),
+            encap_prefix: "\\".into(),
+            encap_infix: "{".into(),
+            encap_suffix: "}".into(),
+        }
+    }
+}
+impl IndexStyle {
+    /// Parses a `makeindex` style file, starting from the defaults.
+    pub fn parse(src: &str) -> Self {
+        let mut ret = Self::default();
+        for line in src.lines() {
+            let line = strip_comment(line).trim();
+            let Some((key, value)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+            let value = value.trim();
+            let string = || unquote(value, '"');
+            let chr = || unquote(value, '\'').chars().next();
+            match key {
+                "keyword" => ret.keyword = string(),
+                "preamble" => ret.preamble = string(),
+                "postamble" => ret.postamble = string(),
+                "group_skip" => ret.group_skip = string(),
+                "headings_flag" | "lethead_flag" => ret.headings_flag = value.parse().unwrap_or(0),
+                "heading_prefix" | "lethead_prefix" => ret.heading_prefix = string(),
+                "heading_suffix" | "lethead_suffix" => ret.heading_suffix = string(),
+                "symhead_positive" | "symhead_negative" => ret.symhead = string(),
+                "numhead_positive" | "numhead_negative" => ret.numhead = string(),
+                "item_0" => ret.item[0] = string(),
+                "item_1" => ret.item[1] = string(),
+                "item_2" => ret.item[2] = string(),
+                "item_01" => ret.item_01 = string(),
+                "item_x1" => ret.item_x1 = string(),
+                "item_12" => ret.item_12 = string(),
+                "item_x2" => ret.item_x2 = string(),
+                "delim_0" => ret.delim[0] = string(),
+                "delim_1" => ret.delim[1] = string(),
+                "delim_2" => ret.delim[2] = string(),
+                "delim_n" => ret.delim_n = string(),
+                "delim_r" => ret.delim_r = string(),
+                "delim_t" => ret.delim_t = string(),
+                "encap_prefix" => ret.encap_prefix = string(),
+                "encap_infix" => ret.encap_infix = string(),
+                "encap_suffix" => ret.encap_suffix = string(),
+                "actual" => ret.actual = chr().unwrap_or(ret.actual),
+                "level" => ret.level = chr().unwrap_or(ret.level),
+                "encap" => ret.encap = chr().unwrap_or(ret.encap),
+                "quote" => ret.quote = chr().unwrap_or(ret.quote),
+                "escape" => ret.escape = chr().unwrap_or(ret.escape),
+                "range_open" => ret.range_open = chr().unwrap_or(ret.range_open),
+                "range_close" => ret.range_close = chr().unwrap_or(ret.range_close),
+                _ => (),
+            }
+        }
+        ret
+    }
+}
+
+/// Removes a `%`-comment outside of quoted strings and characters.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if q == c => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '%') => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Strips the delimiters of a style file string or character and resolves its escapes.
+fn unquote(s: &str, delim: char) -> String {
+    let s = s.strip_prefix(delim).unwrap_or(s);
+    let s = s.strip_suffix(delim).unwrap_or(s);
+    let mut ret = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => ret.push('\n'),
+            Some('t') => ret.push('\t'),
+            Some(c) => ret.push(c),
+            None => ret.push('\\'),
+        }
+    }
+    ret
+}
+
+/// A single `\indexentry` of an `.idx` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// (sort key, displayed text) per level
+    pub levels: Vec<(String, String)>,
+    pub page: String,
+    /// e.g. `textbf` or `see{foo}`
+    pub encap: Option<String>,
+    pub range: Option<RangeMark>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeMark {
+    Open,
+    Close,
+}
+
+/// Parses the `\indexentry{...}{page}` lines of an `.idx` file. Malformed entries are skipped.
+pub fn parse_idx(src: &str, style: &IndexStyle) -> Vec<IndexEntry> {
+    let mut ret = Vec::new();
+    let mut rest = src;
+    while let Some(i) = rest.find(style.keyword.as_str()) {
+        rest = &rest[i + style.keyword.len()..];
+        let Some((arg, r)) = braced(rest, style) else {
+            continue;
+        };
+        let Some((page, r)) = braced(r, style) else {
+            continue;
+        };
+        rest = r;
+        if let Some(e) = parse_entry(arg, page.trim(), style) {
+            ret.push(e);
+        }
+    }
+    ret
+}
+
+/// Splits off a braced argument (after optional whitespace), respecting quoted and
+/// escaped braces.
+fn braced<'a>(s: &'a str, style: &IndexStyle) -> Option<(&'a str, &'a str)> {
+    let s = s.trim_start().strip_prefix('{')?;
+    let mut depth = 0;
+    let mut skip = false;
+    for (i, c) in s.char_indices() {
+        if skip {
+            skip = false;
+            continue;
+        }
+        match c {
+            c if c == style.quote || c == style.escape => skip = true,
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some((&s[..i], &s[i + 1..])),
+            '}' => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+fn parse_entry(arg: &str, page: &str, style: &IndexStyle) -> Option<IndexEntry> {
+    let (text, encap) = split_unquoted(arg, style.encap, style);
+    let mut levels = Vec::new();
+    let mut text = text;
+    loop {
+        let (level, rest) = split_unquoted(&text, style.level, style);
+        let (sort, display) = split_unquoted(&level, style.actual, style);
+        let sort = strip_quotes(sort.trim(), style);
+        let display = display.map_or_else(|| sort.clone(), |d| strip_quotes(d.trim(), style));
+        levels.push((sort, display));
+        match rest {
+            Some(r) if levels.len() < 3 => text = r,
+            _ => break,
+        }
+    }
+    if levels.iter().all(|(s, _)| s.is_empty()) {
+        return None;
+    }
+    let (range, encap) = match encap {
+        Some(e) if e.starts_with(style.range_open) => (
+            Some(RangeMark::Open),
+            e[style.range_open.len_utf8()..].to_string(),
+        ),
+        Some(e) if e.starts_with(style.range_close) => (
+            Some(RangeMark::Close),
+            e[style.range_close.len_utf8()..].to_string(),
+        ),
+        Some(e) => (None, e),
+        None => (None, String::new()),
+    };
+    Some(IndexEntry {
+        levels,
+        page: page.to_string(),
+        encap: Some(encap).filter(|e| !e.is_empty()),
+        range,
+    })
+}
+
+/// Splits at the first occurrence of `sep` that is neither quoted nor inside braces.
+fn split_unquoted(s: &str, sep: char, style: &IndexStyle) -> (String, Option<String>) {
+    let mut ret = String::with_capacity(s.len());
+    let mut depth = 0;
+    let mut chars = s.char_indices();
+    let mut prev_escape = false;
+    while let Some((i, c)) = chars.next() {
+        if c == style.quote && !prev_escape {
+            ret.push(c);
+            if let Some((_, n)) = chars.next() {
+                ret.push(n);
+            }
+            continue;
+        }
+        prev_escape = c == style.escape && !prev_escape;
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            c if c == sep && depth == 0 => {
+                return (ret, Some(s[i + c.len_utf8()..].to_string()));
+            }
+            _ => (),
+        }
+        ret.push(c);
+    }
+    (ret, None)
+}
+
+/// Removes quote characters that are not preceded by the escape character.
+fn strip_quotes(s: &str, style: &IndexStyle) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    let mut prev_escape = false;
+    while let Some(c) = chars.next() {
+        if c == style.quote && !prev_escape {
+            ret.extend(chars.next());
+            prev_escape = false;
+            continue;
+        }
+        prev_escape = c == style.escape && !prev_escape;
+        ret.push(c);
+    }
+    ret
+}
+
+/// Generates the contents of an `.ind` file.
+pub fn make_ind(entries: &[IndexEntry], style: &IndexStyle, collation: Collation) -> String {
+    let mut sorted: Vec<&IndexEntry> = entries.iter().collect();
+    // stable, so that the pages of equal keys stay in input order
+    sorted.sort_by(|a, b| {
+        for (x, y) in a.levels.iter().zip(b.levels.iter()) {
+            let o = collation.compare(&x.0, &y.0).then_with(|| x.1.cmp(&y.1));
+            if o != Ordering::Equal {
+                return o;
+            }
+        }
+        a.levels.len().cmp(&b.levels.len())
+    });
+    let mut out = style.preamble.clone();
+    let mut prev: Option<&[(String, String)]> = None;
+    let mut group = None;
+    let mut i = 0;
+    while i < sorted.len() {
+        let levels = &sorted[i].levels;
+        let mut j = i + 1;
+        while j < sorted.len() && sorted[j].levels == *levels {
+            j += 1;
+        }
+        let g = Group::of(&levels[0].0);
+        if group != Some(g) {
+            if group.is_some() {
+                out.push_str(&style.group_skip);
+            }
+            if style.headings_flag != 0 {
+                out.push_str(&style.heading_prefix);
+                out.push_str(&heading(g, style));
+                out.push_str(&style.heading_suffix);
+            }
+            group = Some(g);
+        }
+        // the first level that differs from the previous entry
+        let first = prev.map_or(0, |p| {
+            p.iter()
+                .zip(levels.iter())
+                .take_while(|(a, b)| a == b)
+                .count()
+        });
+        for (l, (_, display)) in levels.iter().enumerate().skip(first) {
+            let item = match l {
+                0 => &style.item[0],
+                l if l > first => {
+                    // the parent was just created without pages
+                    if l == 1 {
+                        &style.item_x1
+                    } else {
+                        &style.item_x2
+                    }
+                }
+                l if prev.is_some_and(|p| p.len() == l) => {
+                    // directly following its parent, which has pages
+                    if l == 1 {
+                        &style.item_01
+                    } else {
+                        &style.item_12
+                    }
+                }
+                l => &style.item[l.min(2)],
+            };
+            out.push_str(item);
+            out.push_str(display);
+        }
+        let pages = format_pages(&sorted[i..j], style);
+        if !pages.is_empty() {
+            out.push_str(&style.delim[(levels.len() - 1).min(2)]);
+            out.push_str(&pages);
+            out.push_str(&style.delim_t);
+        }
+        prev = Some(levels);
+        i = j;
+    }
+    out.push_str(&style.postamble);
+    out
+}
+
+fn heading(g: Group, style: &IndexStyle) -> String {
+    match g {
+        Group::Symbols => style.symhead.clone(),
+        Group::Numbers => style.numhead.clone(),
+        Group::Letter(c) if style.headings_flag > 0 => c.to_uppercase().collect(),
+        Group::Letter(c) => c.to_string(),
+    }
+}
+
+/// Formats the page list of entries with equal keys: explicit ranges, implicit ranges of
+/// three or more consecutive pages, and encapsulators.
+pub(crate) fn format_pages(entries: &[&IndexEntry], style: &IndexStyle) -> String {
+    let mut pages: Vec<&IndexEntry> = entries.to_vec();
+    pages.sort_by_key(|e| page_value(&e.page));
+    let encapped = |page: &str, encap: &Option<String>| match encap {
+        Some(e) => format!(
+            "{}{e}{}{page}{}",
+            style.encap_prefix, style.encap_infix, style.encap_suffix
+        ),
+        None => page.to_string(),
+    };
+    // (first page, last page, encap)
+    let mut items: Vec<(String, String, Option<String>, bool)> = Vec::new();
+    let mut open: Option<&IndexEntry> = None;
+    for e in pages {
+        match (e.range, open) {
+            (Some(RangeMark::Open), None) => open = Some(e),
+            (Some(RangeMark::Close), Some(o)) => {
+                items.push((o.page.clone(), e.page.clone(), o.encap.clone(), true));
+                open = None;
+            }
+            (_, Some(_)) => (),
+            (_, None) => {
+                let value = page_value(&e.page);
+                match items.last_mut() {
+                    Some(last)
+                        if !last.3
+                            && last.2 == e.encap
+                            && value.0 == 1
+                            && page_value(&last.1) == (1, value.1 - 1) =>
+                    {
+                        last.1 = e.page.clone()
+                    }
+                    Some(last) if !last.3 && last.2 == e.encap && last.1 == e.page => (),
+                    _ => items.push((e.page.clone(), e.page.clone(), e.encap.clone(), false)),
+                }
+            }
+        }
+    }
+    if let Some(o) = open {
+        items.push((o.page.clone(), o.page.clone(), o.encap.clone(), true));
+    }
+    let mut ret = Vec::new();
+    for (first, last, encap, explicit) in items {
+        let span = page_value(&last).1 - page_value(&first).1;
+        if first == last {
+            ret.push(encapped(&first, &encap));
+        } else if explicit || span >= 2 {
+            ret.push(encapped(&format!("{first}{}{last}", style.delim_r), &encap));
+        } else {
+            // two consecutive pages are not merged into a range
+            ret.push(encapped(&first, &encap));
+            ret.push(encapped(&last, &encap));
+        }
+    }
+    ret.join(&style.delim_n)
+}
+
+/// Orders pages like `makeindex`'s default `page_precedence` (`rnaRA`): lower case roman,
+/// arabic, lower case letters, upper case roman, upper case letters; anything else last.
+fn page_value(page: &str) -> (u8, i64) {
+    if let Ok(n) = page.parse::<i64>() {
+        return (1, n);
+    }
+    if let Some(n) = roman(page) {
+        return if page.chars().all(|c| c.is_ascii_lowercase()) {
+            (0, n)
+        } else {
+            (3, n)
+        };
+    }
+    let mut chars = page.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_lowercase() => (2, c as i64),
+        (Some(c), None) if c.is_ascii_uppercase() => (4, c as i64),
+        _ => (5, 0),
+    }
+}
+
+pub(crate) fn roman(s: &str) -> Option<i64> {
+    if s.is_empty()
+        || !(s.chars().all(|c| c.is_ascii_lowercase()) || s.chars().all(|c| c.is_ascii_uppercase()))
+    {
+        return None;
+    }
+    let mut ret = 0;
+    let mut prev = 0;
+    for c in s.chars().rev() {
+        let v = match c.to_ascii_lowercase() {
+            'i' => 1,
+            'v' => 5,
+            'x' => 10,
+            'l' => 50,
+            'c' => 100,
+            'd' => 500,
+            'm' => 1000,
+            _ => return None,
+        };
+        if v < prev {
+            ret -= v;
+        } else {
+            ret += v;
+            prev = v;
+        }
+    }
+    Some(ret)
+}
+
+/// Adds an `.ind` file to `aux` for every `.idx` file in `aux`, unless there is an `.ind`
+/// file that was not generated by us, neither in `aux` nor on disk. Warns on `outputs` if
+/// the style file can not be read.
+pub(crate) fn resolve(
+    aux: &mut AuxFiles,
+    fs: &RusTeXFileSystem,
+    options: &IndexOptions,
+    outputs: &RusTeXOutput,
+) {
+    let style = options
+        .style
+        .as_ref()
+        .map(|s| {
+            let name = if s.ends_with(".ist") {
+                s.clone()
+            } else {
+                format!("{s}.ist")
+            };
+            let found = fs.inner.kpse.kpsewhich(&name);
+            match std::fs::read_to_string(&found.path) {
+                Ok(src) => IndexStyle::parse(&src),
+                Err(e) => {
+                    outputs.message(format!(
+                        "Index style file {name} could not be read ({e}); using the default style"
+                    ));
+                    IndexStyle::default()
+                }
+            }
+        })
+        .unwrap_or_default();
+    let idx: Vec<(PathBuf, String)> = aux
+        .files
+        .iter()
+        .filter(|(p, _)| p.extension().is_some_and(|e| e == "idx"))
+        .map(|(p, s)| (p.with_extension("ind"), s))
+        .filter(|(ind, _)| {
+            let existing = match aux.get(ind) {
+                Some(s) => Some(s.to_string()),
+                None => std::fs::read_to_string(ind).ok(),
+            };
+            !existing.is_some_and(|s| !s.starts_with(IND_HEADER))
+        })
+        .map(|(ind, s)| {
+            let body = make_ind(&parse_idx(s, &style), &style, options.collation);
+            (ind, format!("{IND_HEADER}\n{body}"))
+        })
+        .collect();
+    for (p, s) in idx {
+        aux.insert(p, s);
+    }
+}
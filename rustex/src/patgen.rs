@@ -0,0 +1,325 @@
+//! A small `patgen` replacement: generates Liang-style hyphenation patterns from a list of
+//! hyphenated words (e.g. `hy-phen-ation`), so that patterns for less common languages can be
+//! produced without external tools. The result can be written as the argument of `\patterns`
+//! (see [`Patterns::to_tex`]), together with `\hyphenation` exceptions for the words the
+//! patterns still get wrong.
+//!
+//! As in `patgen`, patterns are generated level by level: odd levels add patterns allowing
+//! hyphens that are still missed, even levels add patterns inhibiting wrong ones. At each level,
+//! a candidate pattern is selected if `good * good_weight - bad * bad_weight >= threshold`,
+//! where `good` and `bad` count the positions in the word list at which it would improve resp.
+//! break the hyphenation found by the patterns selected so far.
+
+use std::fmt::Write;
+use tex_engine::utils::HMap;
+
+/// A word from the word list, with its allowed hyphenation points.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    /// lower case
+    pub letters: Vec<char>,
+    /// `hyphens[i]` is `true` if the word may be hyphenated before `letters[i]`
+    pub hyphens: Vec<bool>,
+}
+impl Word {
+    /// Parses a word with hyphenation points marked by `-`, e.g. `hy-phen-ation`.
+    pub fn parse(s: &str) -> Self {
+        let mut letters = Vec::new();
+        let mut hyphens = vec![false];
+        for c in s.chars() {
+            if c == '-' {
+                if let Some(h) = hyphens.last_mut() {
+                    *h = !letters.is_empty();
+                }
+            } else {
+                for c in c.to_lowercase() {
+                    letters.push(c);
+                    hyphens.push(false);
+                }
+            }
+        }
+        hyphens.truncate(letters.len());
+        Self { letters, hyphens }
+    }
+    /// Parses a word list with one or more words per line; `%` starts a comment.
+    pub fn parse_list(s: &str) -> Vec<Self> {
+        s.lines()
+            .map(|l| l.split('%').next().unwrap_or_default())
+            .flat_map(str::split_whitespace)
+            .map(Self::parse)
+            .filter(|w| !w.letters.is_empty())
+            .collect()
+    }
+}
+
+/// The parameters of a single level of pattern generation.
+#[derive(Debug, Clone, Copy)]
+pub struct Level {
+    /// the minimal and maximal length of the patterns to generate (excluding digits)
+    pub min_length: usize,
+    pub max_length: usize,
+    pub good_weight: u32,
+    pub bad_weight: u32,
+    pub threshold: u32,
+}
+
+/// The options for [`generate`].
+#[derive(Debug, Clone)]
+pub struct PatgenOptions {
+    /// one entry per level, starting with level 1
+    pub levels: Vec<Level>,
+    /// no hyphens are inserted before this many letters at the start of a word (cf.
+    /// `\lefthyphenmin`)
+    pub left_hyphen_min: usize,
+    /// ...or after this many letters at the end of a word (cf. `\righthyphenmin`)
+    pub right_hyphen_min: usize,
+}
+impl Default for PatgenOptions {
+    fn default() -> Self {
+        let level = |min_length, max_length, bad_weight, threshold| Level {
+            min_length,
+            max_length,
+            good_weight: 1,
+            bad_weight,
+            threshold,
+        };
+        Self {
+            levels: vec![
+                level(2, 5, 2, 2),
+                level(2, 5, 1, 1),
+                level(3, 6, 3, 2),
+                level(3, 7, 1, 1),
+            ],
+            left_hyphen_min: 2,
+            right_hyphen_min: 3,
+        }
+    }
+}
+
+/// A set of hyphenation patterns.
+#[derive(Debug, Clone)]
+pub struct Patterns {
+    /// the letters of a pattern (with `.` marking word boundaries) and the values between them
+    map: HMap<Vec<char>, Vec<u8>>,
+    max_length: usize,
+    pub left_hyphen_min: usize,
+    pub right_hyphen_min: usize,
+}
+impl Patterns {
+    pub fn new(left_hyphen_min: usize, right_hyphen_min: usize) -> Self {
+        Self {
+            map: HMap::default(),
+            max_length: 0,
+            left_hyphen_min,
+            right_hyphen_min,
+        }
+    }
+    /// Parses patterns in the syntax of `\patterns`, e.g. `.ach4 4b1l hy3ph`.
+    pub fn parse(s: &str, left_hyphen_min: usize, right_hyphen_min: usize) -> Self {
+        let mut ret = Self::new(left_hyphen_min, right_hyphen_min);
+        for p in s.split_whitespace() {
+            let mut letters = Vec::new();
+            let mut values = vec![0];
+            for c in p.chars() {
+                match c.to_digit(10) {
+                    Some(d) => *values.last_mut().unwrap_or(&mut 0) = d as u8,
+                    None => {
+                        letters.push(c);
+                        values.push(0);
+                    }
+                }
+            }
+            ret.insert(letters, values);
+        }
+        ret
+    }
+    fn insert(&mut self, letters: Vec<char>, values: Vec<u8>) {
+        self.max_length = self.max_length.max(letters.len());
+        match self.map.get_mut(&letters) {
+            Some(old) => {
+                for (o, v) in old.iter_mut().zip(values) {
+                    *o = (*o).max(v);
+                }
+            }
+            None => {
+                self.map.insert(letters, values);
+            }
+        }
+    }
+    /// The number of patterns.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// The maximal pattern values between the letters of `.word.`.
+    fn values(&self, letters: &[char]) -> Vec<u8> {
+        let dotted: Vec<char> = std::iter::once('.')
+            .chain(letters.iter().copied())
+            .chain(std::iter::once('.'))
+            .collect();
+        let mut ret = vec![0; dotted.len() + 1];
+        for start in 0..dotted.len() {
+            for end in start + 1..=dotted.len().min(start + self.max_length) {
+                if let Some(values) = self.map.get(&dotted[start..end]) {
+                    for (r, v) in ret[start..].iter_mut().zip(values) {
+                        *r = (*r).max(*v);
+                    }
+                }
+            }
+        }
+        ret
+    }
+    fn hyphenatable(&self, len: usize) -> std::ops::Range<usize> {
+        self.left_hyphen_min.max(1)..(len + 1).saturating_sub(self.right_hyphen_min.max(1))
+    }
+    /// The positions in `word` before which it may be hyphenated according to these patterns.
+    pub fn hyphenate(&self, word: &str) -> Vec<usize> {
+        let letters: Vec<char> = word.chars().flat_map(char::to_lowercase).collect();
+        self.hyphenate_letters(&letters)
+    }
+    fn hyphenate_letters(&self, letters: &[char]) -> Vec<usize> {
+        let values = self.values(letters);
+        self.hyphenatable(letters.len())
+            .filter(|i| values[i + 1] % 2 == 1)
+            .collect()
+    }
+    /// Compares the hyphenation found by these patterns with the word list.
+    pub fn evaluate(&self, words: &[Word]) -> Evaluation {
+        let mut ret = Evaluation::default();
+        for w in words {
+            let found = self.hyphenate_letters(&w.letters);
+            let mut wrong = false;
+            for i in self.hyphenatable(w.letters.len()) {
+                match (w.hyphens[i], found.contains(&i)) {
+                    (true, true) => ret.good += 1,
+                    (false, true) => ret.bad += 1,
+                    (true, false) => ret.missed += 1,
+                    (false, false) => continue,
+                }
+                wrong |= w.hyphens[i] != found.contains(&i);
+            }
+            if wrong {
+                ret.exceptions.push(w.clone());
+            }
+        }
+        ret
+    }
+
+    /// The patterns in the syntax of `\patterns`, sorted and separated by line breaks. If
+    /// `exceptions` is not empty, a `\hyphenation` command for them is appended.
+    pub fn to_tex(&self, exceptions: &[Word]) -> String {
+        let mut patterns: Vec<String> = self
+            .map
+            .iter()
+            .map(|(letters, values)| {
+                let mut s = String::new();
+                for (i, v) in values.iter().enumerate() {
+                    if *v > 0 {
+                        let _ = write!(s, "{v}");
+                    }
+                    if let Some(c) = letters.get(i) {
+                        s.push(*c);
+                    }
+                }
+                s
+            })
+            .collect();
+        patterns.sort_by(|a, b| {
+            let key = |s: &str| {
+                s.chars()
+                    .filter(|c| !c.is_ascii_digit())
+                    .collect::<String>()
+            };
+            key(a).cmp(&key(b)).then_with(|| a.cmp(b))
+        });
+        let mut ret = String::from("\\patterns{\n");
+        for p in patterns {
+            ret.push_str(&p);
+            ret.push('\n');
+        }
+        ret.push_str("}\n");
+        if !exceptions.is_empty() {
+            ret.push_str("\\hyphenation{\n");
+            for w in exceptions {
+                for (i, c) in w.letters.iter().enumerate() {
+                    if w.hyphens[i] {
+                        ret.push('-');
+                    }
+                    ret.push(*c);
+                }
+                ret.push('\n');
+            }
+            ret.push_str("}\n");
+        }
+        ret
+    }
+}
+
+/// How well a set of [`Patterns`] reproduces a word list.
+#[derive(Debug, Clone, Default)]
+pub struct Evaluation {
+    /// the number of hyphens found correctly
+    pub good: usize,
+    /// the number of hyphens found wrongly
+    pub bad: usize,
+    /// the number of hyphens not found
+    pub missed: usize,
+    /// the words hyphenated wrongly, e.g. to be added as `\hyphenation` exceptions
+    pub exceptions: Vec<Word>,
+}
+
+/// Generates patterns for the given word list.
+pub fn generate(words: &[Word], options: &PatgenOptions) -> Patterns {
+    let mut patterns = Patterns::new(options.left_hyphen_min, options.right_hyphen_min);
+    for (i, level) in options.levels.iter().enumerate() {
+        let value = (i + 1) as u8;
+        for length in level.min_length..=level.max_length {
+            let mut counts: HMap<(Vec<char>, usize), (u32, u32)> = HMap::default();
+            for w in words {
+                let current = patterns.values(&w.letters);
+                let dotted: Vec<char> = std::iter::once('.')
+                    .chain(w.letters.iter().copied())
+                    .chain(std::iter::once('.'))
+                    .collect();
+                for pos in patterns.hyphenatable(w.letters.len()) {
+                    // hyphenation points are counted between the letters of `.word.`
+                    let point = pos + 1;
+                    let v = current[point];
+                    if v % 2 == value % 2 {
+                        continue;
+                    }
+                    let good = w.hyphens[pos] == (value % 2 == 1);
+                    for offset in 1..length {
+                        let Some(start) = point.checked_sub(offset) else {
+                            break;
+                        };
+                        if start + length > dotted.len() {
+                            continue;
+                        }
+                        let key = (dotted[start..start + length].to_vec(), offset);
+                        let e = counts.entry(key).or_default();
+                        if good {
+                            e.0 += 1;
+                        } else {
+                            e.1 += 1;
+                        }
+                    }
+                }
+            }
+            for ((letters, offset), (good, bad)) in counts {
+                if good > 0
+                    && (good * level.good_weight).saturating_sub(bad * level.bad_weight)
+                        >= level.threshold
+                {
+                    let mut values = vec![0; letters.len() + 1];
+                    values[offset] = value;
+                    patterns.insert(letters, values);
+                }
+            }
+        }
+    }
+    patterns
+}
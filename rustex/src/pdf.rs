@@ -0,0 +1,616 @@
+//! Experimental PDF output (see [`Settings::pdf`](crate::engine::Settings::pdf)): the shipped out
+//! pages as they come out of the engine - boxes, rules, glyphs and links, placed where RusTeX's
+//! layout put them - so that they can be compared with pdfTeX's output for the same document,
+//! separating layout bugs from bugs in the HTML shipout.
+//!
+//! Only the basics are covered (see [`placement`] for how the pages are laid out): no colors,
+//! images, `\pdfliteral`s or PGF pictures. Fonts are embedded if `pdftex.map` maps them to a
+//! Type 1 (`.pfb`) or OpenType file; otherwise they are only referenced by name.
+
+use crate::engine::files::RusTeXFileSystem;
+use crate::engine::nodes::RusTeXNode;
+use crate::engine::{Font, Refs, Types};
+use crate::placement::{self, Canvas};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io::Write as _;
+use tex_engine::engine::fontsystem::Font as FontT;
+use tex_engine::pdflatex::nodes::{ActionSpec, GotoAction, NumOrName, PDFNode};
+use tex_engine::tex::nodes::boxes::TeXBox;
+
+/// Collects the shipped out pages during a run; see the [module documentation](self).
+#[derive(Default)]
+pub(crate) struct PdfWriter {
+    pages: Vec<Page>,
+    fonts: Vec<FontEntry>,
+    dests: Vec<Dest>,
+}
+
+struct Page {
+    width: i32,
+    height: i32,
+    content: String,
+    links: Vec<Link>,
+}
+
+struct Dest {
+    name: String,
+    page: usize,
+    /// in PDF coordinates
+    x: i32,
+    y: i32,
+}
+
+#[derive(Clone)]
+enum Target {
+    /// the entries of the annotation dictionary, as given by `\pdfstartlink user{...}`
+    Raw(String),
+    Dest(String),
+    Page(usize),
+    File {
+        file: String,
+        dest: String,
+    },
+}
+
+struct Link {
+    /// left, bottom, right, top in PDF coordinates
+    rect: [i32; 4],
+    attr: Option<String>,
+    target: Target,
+}
+
+struct FontEntry {
+    /// the name of the `.tfm` file
+    name: String,
+    /// in thousandths of the font size
+    widths: Vec<i32>,
+}
+
+impl PdfWriter {
+    /// Records a page shipped out with `\shipout`.
+    pub(crate) fn add_page(&mut self, engine: Refs, page: &TeXBox<Types>) {
+        let state = &*engine.state;
+        let (width, height) = placement::page_size(state, page);
+        let mut writer = PageWriter {
+            fonts: &mut self.fonts,
+            dests: &mut self.dests,
+            page: self.pages.len(),
+            height,
+            content: String::new(),
+            links: Vec::new(),
+            link: None,
+        };
+        placement::place(state, page, &mut writer);
+        let PageWriter { content, links, .. } = writer;
+        self.pages.push(Page {
+            width,
+            height,
+            content,
+            links,
+        });
+    }
+
+    /// The PDF file, with the fonts looked up in `fs`.
+    ///
+    /// #### Errors
+    /// if compressing a stream fails
+    pub(crate) fn finish(self, fs: &RusTeXFileSystem) -> std::io::Result<Vec<u8>> {
+        let mut objs = Objects::default();
+        let catalog = objs.reserve();
+        let pages = objs.reserve();
+        let page_ids: Vec<usize> = self.pages.iter().map(|_| objs.reserve()).collect();
+
+        let map = FontMap::load(fs, &self.fonts);
+        let mut fonts = String::new();
+        for (i, font) in self.fonts.iter().enumerate() {
+            let id = font.write(&mut objs, map.get(&font.name), fs)?;
+            let _ = write!(fonts, "/F{i} {id} 0 R ");
+        }
+        let resources = objs.add(format!("<< /Font << {fonts}>> >>"));
+
+        for (i, page) in self.pages.into_iter().enumerate() {
+            let content = objs.add_bytes(stream("", page.content.as_bytes())?);
+            let mut annots = String::new();
+            for link in page.links {
+                let Some(dict) = link.dict(&page_ids) else {
+                    continue;
+                };
+                let _ = write!(annots, "{} 0 R ", objs.add(dict));
+            }
+            objs.set(
+                page_ids[i],
+                format!(
+                    "<< /Type /Page /Parent {pages} 0 R /MediaBox [0 0 {} {}] \
+                     /Resources {resources} 0 R /Contents {content} 0 R /Annots [{annots}] >>",
+                    bp(page.width),
+                    bp(page.height)
+                ),
+            );
+        }
+        let kids: String = page_ids.iter().map(|i| format!("{i} 0 R ")).collect();
+        objs.set(
+            pages,
+            format!(
+                "<< /Type /Pages /Kids [{kids}] /Count {} >>",
+                page_ids.len()
+            ),
+        );
+
+        // the name tree must be sorted; the first destination of a name wins, as in pdfTeX
+        let mut dests = BTreeMap::new();
+        for d in self.dests {
+            dests.entry(d.name).or_insert_with(|| {
+                format!(
+                    "[{} 0 R /XYZ {} {} null]",
+                    page_ids[d.page],
+                    bp(d.x),
+                    bp(d.y)
+                )
+            });
+        }
+        let mut root = format!("<< /Type /Catalog /Pages {pages} 0 R");
+        if !dests.is_empty() {
+            let names: String = dests
+                .iter()
+                .map(|(name, d)| format!("{} {d} ", pdf_string(name)))
+                .collect();
+            let tree = objs.add(format!("<< /Names [{names}] >>"));
+            let _ = write!(root, " /Names << /Dests {tree} 0 R >>");
+        }
+        root.push_str(" >>");
+        objs.set(catalog, root);
+        Ok(objs.serialize(catalog))
+    }
+}
+
+impl Link {
+    fn dict(self, pages: &[usize]) -> Option<String> {
+        let [x0, y0, x1, y1] = self.rect;
+        let mut dict = format!(
+            "<< /Type /Annot /Rect [{} {} {} {}] ",
+            bp(x0),
+            bp(y0),
+            bp(x1),
+            bp(y1)
+        );
+        match self.target {
+            Target::Raw(s) => {
+                if !s.contains("/Subtype") {
+                    dict.push_str("/Subtype /Link ");
+                }
+                dict.push_str(&s);
+            }
+            Target::Dest(name) => {
+                let _ = write!(dict, "/Subtype /Link /Dest {}", pdf_string(&name));
+            }
+            Target::Page(p) => {
+                let _ = write!(dict, "/Subtype /Link /Dest [{} 0 R /Fit]", pages.get(p)?);
+            }
+            Target::File { file, dest } => {
+                let _ = write!(
+                    dict,
+                    "/Subtype /Link /A << /S /GoToR /F {} /D {} >>",
+                    pdf_string(&file),
+                    pdf_string(&dest)
+                );
+            }
+        }
+        match self.attr {
+            Some(attr) => {
+                dict.push(' ');
+                dict.push_str(&attr);
+            }
+            None => dict.push_str(" /Border [0 0 0]"),
+        }
+        dict.push_str(" >>");
+        Some(dict)
+    }
+}
+
+/// The [`Canvas`] for one page; the coordinates are flipped when written.
+struct PageWriter<'a> {
+    fonts: &'a mut Vec<FontEntry>,
+    dests: &'a mut Vec<Dest>,
+    page: usize,
+    height: i32,
+    content: String,
+    links: Vec<Link>,
+    link: Option<OpenLink>,
+}
+
+/// A link between `\pdfstartlink` and `\pdfendlink`; every line it spans gets its own rectangle.
+struct OpenLink {
+    attr: Option<String>,
+    target: Target,
+    height: Option<i32>,
+    depth: Option<i32>,
+    /// the start of the current rectangle: x, baseline, and the height and depth of the line
+    start: Option<(i32, i32, i32, i32)>,
+}
+
+impl PageWriter<'_> {
+    fn font(&mut self, font: &Font) -> usize {
+        let name = font.filename();
+        if let Some(i) = self.fonts.iter().position(|f| f.name == name) {
+            return i;
+        }
+        let at = f64::from(font.get_at().0.max(1));
+        self.fonts.push(FontEntry {
+            name: name.to_string(),
+            widths: (0..=255u8)
+                .map(|c| {
+                    if font.has_char(c) {
+                        (f64::from(font.get_wd(c).0) / at * 1000.0).round() as i32
+                    } else {
+                        0
+                    }
+                })
+                .collect(),
+        });
+        self.fonts.len() - 1
+    }
+
+    /// Closes the rectangle of the current link (if any) at `x`.
+    fn end_link_line(&mut self, x: i32) {
+        let Some(link) = &mut self.link else { return };
+        let Some((x0, baseline, height, depth)) = link.start.take() else {
+            return;
+        };
+        if x > x0 {
+            let height = link.height.unwrap_or(height);
+            let depth = link.depth.unwrap_or(depth);
+            self.links.push(Link {
+                rect: [
+                    x0,
+                    self.height - baseline - depth,
+                    x,
+                    self.height - baseline + height,
+                ],
+                attr: link.attr.clone(),
+                target: link.target.clone(),
+            });
+        }
+    }
+}
+
+impl Canvas for PageWriter<'_> {
+    fn glyph(&mut self, x: i32, baseline: i32, font: &Font, char: u8) {
+        let idx = self.font(font);
+        let _ = writeln!(
+            self.content,
+            "BT /F{idx} {} Tf 1 0 0 1 {} {} Tm <{char:02X}> Tj ET",
+            bp(font.get_at().0),
+            bp(x),
+            bp(self.height - baseline)
+        );
+    }
+
+    fn rule(&mut self, x: i32, top: i32, width: i32, height: i32) {
+        let _ = writeln!(
+            self.content,
+            "{} {} {} {} re f",
+            bp(x),
+            bp(self.height - top - height),
+            bp(width),
+            bp(height)
+        );
+    }
+
+    /// links and destinations
+    fn custom(&mut self, n: &RusTeXNode, x: i32, y: i32, height: i32, depth: i32) {
+        let RusTeXNode::PDFNode(n) = n else { return };
+        match n {
+            PDFNode::PDFStartLink(l) => {
+                let target = match &l.action {
+                    ActionSpec::User(s) => Target::Raw(s.clone()),
+                    ActionSpec::Goto(GotoAction::Current { page: Some(p), .. }) => {
+                        Target::Page(usize::try_from(*p - 1).unwrap_or_default())
+                    }
+                    ActionSpec::Goto(GotoAction::Current { target, .. }) => {
+                        Target::Dest(target.clone().as_name())
+                    }
+                    ActionSpec::Goto(GotoAction::File {
+                        filename, target, ..
+                    }) => Target::File {
+                        file: filename.clone(),
+                        dest: target.clone(),
+                    },
+                    ActionSpec::Thread { .. } => return,
+                };
+                self.link = Some(OpenLink {
+                    attr: l.attr.clone(),
+                    target,
+                    height: l.height.map(|d| d.0),
+                    depth: l.depth.map(|d| d.0),
+                    start: Some((x, y, height, depth)),
+                });
+            }
+            PDFNode::PDFEndLink => {
+                self.end_link_line(x);
+                self.link = None;
+            }
+            PDFNode::PDFDest(d) => {
+                let name = match &d.id {
+                    NumOrName::Name(s) => s.clone(),
+                    NumOrName::Num(i) => format!("NUM_{i}"),
+                };
+                self.dests.push(Dest {
+                    name,
+                    page: self.page,
+                    x,
+                    y: self.height - y + height,
+                });
+            }
+            _ => (),
+        }
+    }
+
+    fn line_start(&mut self, x: i32, baseline: i32, height: i32, depth: i32) {
+        if let Some(link) = &mut self.link {
+            link.start.get_or_insert((x, baseline, height, depth));
+        }
+    }
+
+    fn line_end(&mut self, x: i32) {
+        self.end_link_line(x);
+    }
+}
+
+/// The entries of `pdftex.map` for the fonts used.
+pub(crate) struct FontMap(HashMap<String, MapEntry>);
+pub(crate) struct MapEntry {
+    pub(crate) ps_name: String,
+    pub(crate) enc: Option<String>,
+    pub(crate) file: Option<String>,
+}
+impl FontMap {
+    fn load(fs: &RusTeXFileSystem, fonts: &[FontEntry]) -> Self {
+        let map = fs.inner.kpse.kpsewhich("pdftex.map");
+        let content = std::fs::read_to_string(map.path).unwrap_or_default();
+        Self::parse(&content, |tfm| fonts.iter().any(|f| f.name == tfm))
+    }
+    /// Parses the lines of a map file for the `.tfm` names for which `used` returns `true`.
+    pub(crate) fn parse(content: &str, used: impl Fn(&str) -> bool) -> Self {
+        let mut ret = HashMap::new();
+        for line in content.lines() {
+            let mut words = line.split_whitespace();
+            let Some(tfm) = words.next() else { continue };
+            if tfm.starts_with('%') || !used(tfm) {
+                continue;
+            }
+            let mut entry = MapEntry {
+                ps_name: tfm.to_string(),
+                enc: None,
+                file: None,
+            };
+            let mut rest = line.trim_start()[tfm.len()..].trim_start();
+            let mut named = false;
+            while !rest.is_empty() {
+                if let Some(r) = rest.strip_prefix('"') {
+                    // a PostScript special, e.g. `" .167 SlantFont "`
+                    rest = r.split_once('"').map_or("", |(_, r)| r);
+                } else {
+                    let (word, r) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                    let file = word.trim_start_matches(['<', '[']);
+                    if file.ends_with(".enc") {
+                        entry.enc = Some(file.to_string());
+                    } else if word.starts_with('<') {
+                        entry.file = Some(file.to_string());
+                    } else if !named && !word.starts_with(|c: char| c.is_ascii_digit()) {
+                        entry.ps_name = word.to_string();
+                        named = true;
+                    }
+                    rest = r;
+                }
+                rest = rest.trim_start();
+            }
+            ret.insert(tfm.to_string(), entry);
+        }
+        Self(ret)
+    }
+    pub(crate) fn get(&self, tfm: &str) -> Option<&MapEntry> {
+        self.0.get(tfm)
+    }
+}
+
+impl FontEntry {
+    /// Adds the font (and its file, if found) to `objs`; returns the id of the font dictionary.
+    fn write(
+        &self,
+        objs: &mut Objects,
+        entry: Option<&MapEntry>,
+        fs: &RusTeXFileSystem,
+    ) -> std::io::Result<usize> {
+        let widths: String = self.widths.iter().map(|w| format!("{w} ")).collect();
+        let find = |name: &str| {
+            let res = fs.inner.kpse.kpsewhich(name);
+            if res.exists {
+                std::fs::read(res.path).ok()
+            } else {
+                None
+            }
+        };
+        let ps_name = entry.map_or(self.name.as_str(), |e| e.ps_name.as_str());
+        let mut dict = format!(
+            "<< /Type /Font /Subtype /Type1 /BaseFont /{ps_name} \
+             /FirstChar 0 /LastChar 255 /Widths [{widths}]"
+        );
+        if let Some(enc) = entry.and_then(|e| e.enc.as_deref()).and_then(find) {
+            let names = glyph_names(&String::from_utf8_lossy(&enc));
+            let _ = write!(
+                dict,
+                " /Encoding << /Type /Encoding /Differences [0 {}] >>",
+                names.join(" ")
+            );
+        }
+        let file = entry.and_then(|e| e.file.as_deref());
+        let embedded = match file.and_then(|f| find(f).map(|data| (f, data))) {
+            Some((f, data)) if f.ends_with(".pfb") => pfb_segments(&data).map(|[a, b, c]| {
+                let bbox = font_bbox(&String::from_utf8_lossy(&a));
+                let extra = format!(
+                    "/Length1 {} /Length2 {} /Length3 {}",
+                    a.len(),
+                    b.len(),
+                    c.len()
+                );
+                (stream(&extra, &[a, b, c].concat()), "/FontFile", bbox)
+            }),
+            Some((f, data)) if f.ends_with(".otf") => Some((
+                stream("/Subtype /OpenType", &data),
+                "/FontFile3",
+                [0, -250, 1000, 750],
+            )),
+            _ => None,
+        };
+        if let Some((data, key, [llx, lly, urx, ury])) = embedded {
+            let file = objs.add_bytes(data?);
+            let descriptor = objs.add(format!(
+                "<< /Type /FontDescriptor /FontName /{ps_name} /Flags 4 \
+                 /FontBBox [{llx} {lly} {urx} {ury}] /ItalicAngle 0 /Ascent {ury} \
+                 /Descent {lly} /CapHeight {ury} /StemV 80 {key} {file} 0 R >>"
+            ));
+            let _ = write!(dict, " /FontDescriptor {descriptor} 0 R");
+        }
+        dict.push_str(" >>");
+        Ok(objs.add(dict))
+    }
+}
+
+/// The glyph names of an encoding vector (`/Name [ /a /b ... ] def`), as PDF names.
+pub(crate) fn glyph_names(enc: &str) -> Vec<String> {
+    let without_comments: String = enc
+        .lines()
+        .map(|l| l.split('%').next().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let Some((_, vector)) = without_comments.split_once('[') else {
+        return Vec::new();
+    };
+    let vector = vector.split(']').next().unwrap_or_default();
+    vector
+        .split_whitespace()
+        .filter(|n| n.starts_with('/'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// The cleartext, binary and trailer parts of a `.pfb` file; each may be split across several
+/// consecutive segments of the same type.
+pub(crate) fn pfb_segments(data: &[u8]) -> Option<[Vec<u8>; 3]> {
+    let mut parts: [Vec<u8>; 3] = Default::default();
+    let mut rest = data;
+    let mut i = 0;
+    while let [0x80, tp @ (1 | 2), a, b, c, d, r @ ..] = rest {
+        let len = u32::from_le_bytes([*a, *b, *c, *d]) as usize;
+        let segment = r.get(..len)?;
+        // cleartext, then binary, then the cleartext trailer
+        if (*tp == 2 && i == 0) || (*tp == 1 && i == 1) {
+            i += 1;
+        } else if *tp == 2 && i == 2 {
+            return None;
+        }
+        parts[i].extend_from_slice(segment);
+        rest = &r[len..];
+    }
+    (!parts[0].is_empty() && !parts[1].is_empty()).then_some(parts)
+}
+
+fn font_bbox(cleartext: &str) -> [i32; 4] {
+    let nums: Vec<i32> = cleartext
+        .split_once("/FontBBox")
+        .map(|(_, rest)| {
+            rest.trim_start()
+                .trim_start_matches(['{', '['])
+                .split_whitespace()
+                .take(4)
+                .map_while(|n| n.trim_end_matches(['}', ']']).parse::<f64>().ok())
+                .map(|f| f.round() as i32)
+                .collect()
+        })
+        .unwrap_or_default();
+    match nums[..] {
+        [a, b, c, d] => [a, b, c, d],
+        _ => [0, -250, 1000, 750],
+    }
+}
+
+/// sp as big points
+fn bp(sp: i32) -> String {
+    format!("{:.3}", f64::from(sp) / 65536.0 * 72.0 / 72.27)
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+fn pdf_string(s: &str) -> String {
+    let mut ret = String::from("(");
+    for c in s.chars() {
+        if matches!(c, '(' | ')' | '\\') {
+            ret.push('\\');
+        }
+        ret.push(c);
+    }
+    ret.push(')');
+    ret
+}
+
+/// A compressed stream object, with `extra` entries in its dictionary.
+fn stream(extra: &str, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+    enc.write_all(data)?;
+    let data = enc.finish()?;
+    let mut ret = format!(
+        "<< /Length {} /Filter /FlateDecode {extra} >>\nstream\n",
+        data.len()
+    )
+    .into_bytes();
+    ret.extend_from_slice(&data);
+    ret.extend_from_slice(b"\nendstream");
+    Ok(ret)
+}
+
+/// The objects of the file, numbered from 1.
+#[derive(Default)]
+struct Objects(Vec<Option<Vec<u8>>>);
+impl Objects {
+    fn reserve(&mut self) -> usize {
+        self.0.push(None);
+        self.0.len()
+    }
+    fn set(&mut self, id: usize, obj: String) {
+        self.0[id - 1] = Some(obj.into_bytes());
+    }
+    fn add(&mut self, obj: String) -> usize {
+        self.add_bytes(obj.into_bytes())
+    }
+    fn add_bytes(&mut self, obj: Vec<u8>) -> usize {
+        self.0.push(Some(obj));
+        self.0.len()
+    }
+    fn serialize(self, root: usize) -> Vec<u8> {
+        // 1.6 for OpenType font files
+        let mut out = b"%PDF-1.6\n%\xE2\xE3\xCF\xD3\n".to_vec();
+        let mut offsets = Vec::with_capacity(self.0.len());
+        for (i, obj) in self.0.into_iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            out.extend_from_slice(obj.as_deref().unwrap_or(b"null"));
+            out.extend_from_slice(b"\nendobj\n");
+        }
+        let xref = out.len();
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1);
+        for o in &offsets {
+            let _ = write!(table, "{o:010} 00000 n \n");
+        }
+        let _ = write!(
+            table,
+            "trailer\n<< /Size {} /Root {root} 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            offsets.len() + 1
+        );
+        out.extend_from_slice(table.as_bytes());
+        out
+    }
+}
@@ -0,0 +1,716 @@
+//! Places the contents of shipped out pages at absolute positions, for the [`pdf`](crate::pdf)
+//! and [`dvi`](crate::dvi) backends: a [`Canvas`] is told where every glyph and rule goes.
+//!
+//! Lines are not broken the way TeX would break them, so finite glue is stretched or shrunk at
+//! most as far as it allows, which leaves the last lines of paragraphs ragged. Formulas are set
+//! glyph by glyph along the baseline with scripts raised or lowered; everything else in a
+//! formula only takes up its width. Leaders are glue, filled with copies of their box (aligned
+//! as for `\leaders`, `\cleaders` or `\xleaders`) or with their rule.
+
+use crate::engine::nodes::RusTeXNode;
+use crate::engine::state::RusTeXState;
+use crate::engine::{Font, Types};
+use crate::shipout::positions::SavedPosition;
+use tex_engine::commands::primitives::PRIMITIVES;
+use tex_engine::engine::fontsystem::Font as FontT;
+use tex_engine::engine::state::State;
+use tex_engine::pdflatex::nodes::PDFNode;
+use tex_engine::tex::nodes::boxes::{HBoxInfo, TeXBox, ToOrSpread, VBoxInfo};
+use tex_engine::tex::nodes::horizontal::HNode;
+use tex_engine::tex::nodes::math::{MathFontStyle, MathKernel, MathNode, MathNucleus};
+use tex_engine::tex::nodes::vertical::VNode;
+use tex_engine::tex::nodes::{LeaderBody, LeaderSkip, LeaderType, Leaders, NodeTrait};
+use tex_engine::tex::numerics::{Dim32, Skip, StretchShrink};
+use tex_engine::utils::HMap;
+
+/// 1in in sp; TeX's origin is one inch right of and below the top left corner of the page
+pub(crate) const ONE_INCH: i32 = 4_736_286;
+/// the default thickness of rules (0.4pt)
+const RULE: i32 = 26_214;
+
+type MNode = MathNode<Types, MathFontStyle<Types>>;
+
+/// Receives the glyphs and rules of a page. Coordinates are in sp, measured from the top left
+/// corner of the page downwards.
+pub(crate) trait Canvas {
+    fn glyph(&mut self, x: i32, baseline: i32, font: &Font, char: u8);
+    /// a rule with its top left corner at `(x, top)`; only called with positive dimensions
+    fn rule(&mut self, x: i32, top: i32, width: i32, height: i32);
+    /// A custom node at `(x, y)`; in horizontal lists, `y` is the baseline of the surrounding box
+    /// of the given height and depth, in vertical lists its top (with height and depth `0`).
+    fn custom(&mut self, _node: &RusTeXNode, _x: i32, _y: i32, _height: i32, _depth: i32) {}
+    /// The start of a line of a paragraph at `(x, baseline)`, with the line's height and depth.
+    fn line_start(&mut self, _x: i32, _baseline: i32, _height: i32, _depth: i32) {}
+    /// The end of the line of a paragraph started last, at `x`.
+    fn line_end(&mut self, _x: i32) {}
+}
+
+/// Places the shipped out box `page` on `canvas`, at the position of TeX's origin (one inch from
+/// the top left corner, moved by `\hoffset` and `\voffset`).
+pub(crate) fn place<C: Canvas>(state: &RusTeXState, page: &TeXBox<Types>, canvas: &mut C) {
+    let symbols = state.get_textfont(2);
+    let mut placer = Placer {
+        canvas,
+        sup_shift: symbols.get_dim(12).0,
+        sub_shift: symbols.get_dim(15).0,
+    };
+    let x = ONE_INCH + primitive_dim(state, "hoffset");
+    let top = ONE_INCH + primitive_dim(state, "voffset");
+    placer.tex_box(page, x, top + page.height().0);
+}
+
+/// The width and height of the page `page` is shipped out on: `\pdfpagewidth` and
+/// `\pdfpageheight`, or the size of the box plus 1in on every side if they are not set.
+pub(crate) fn page_size(state: &RusTeXState, page: &TeXBox<Types>) -> (i32, i32) {
+    let mut width = state.get_primitive_dim(PRIMITIVES.pdfpagewidth).0;
+    let mut height = primitive_dim(state, "pdfpageheight");
+    if width <= 0 {
+        width = page.width().0 + 2 * ONE_INCH;
+    }
+    if height <= 0 {
+        height = page.height().0 + page.depth().0 + 2 * ONE_INCH;
+    }
+    (width, height)
+}
+
+/// The positions of the `\pdfsavepos` nodes on the shipped out box `page`, by their number, in
+/// pdfTeX's coordinates (from the bottom left corner of the page).
+pub(crate) fn saved_positions(
+    state: &RusTeXState,
+    page: &TeXBox<Types>,
+    index: usize,
+) -> HMap<usize, SavedPosition> {
+    struct Positions {
+        page: usize,
+        height: i32,
+        found: HMap<usize, SavedPosition>,
+    }
+    impl Positions {
+        fn insert(&mut self, id: usize, x: i32, y: i32) {
+            let pos = SavedPosition {
+                page: self.page,
+                x,
+                y: self.height - y,
+            };
+            self.found.insert(id, pos);
+        }
+    }
+    /// The `\pdfsavepos`es in a pgf picture; the picture's contents are SVG, so they are all
+    /// taken to be at its origin (which is where `remember picture` puts them anyway).
+    fn in_picture(children: &[HNode<Types>], ids: &mut Vec<usize>) {
+        for c in children {
+            match c {
+                HNode::Custom(RusTeXNode::PDFNode(PDFNode::SavePos(id))) => ids.push(*id),
+                HNode::Custom(RusTeXNode::PGFEscape(TeXBox::H { children, .. }))
+                | HNode::Box(TeXBox::H { children, .. }) => in_picture(children, ids),
+                _ => (),
+            }
+        }
+    }
+    impl Canvas for Positions {
+        fn glyph(&mut self, _x: i32, _baseline: i32, _font: &Font, _char: u8) {}
+        fn rule(&mut self, _x: i32, _top: i32, _width: i32, _height: i32) {}
+        fn custom(&mut self, node: &RusTeXNode, x: i32, y: i32, _height: i32, _depth: i32) {
+            match node {
+                RusTeXNode::PDFNode(PDFNode::SavePos(id)) => self.insert(*id, x, y),
+                RusTeXNode::PGFSvg {
+                    bx: TeXBox::H { children, .. },
+                    minx,
+                    miny,
+                    ..
+                } => {
+                    let mut ids = Vec::new();
+                    in_picture(children, &mut ids);
+                    for id in ids {
+                        self.insert(id, x - minx.0, y + miny.0);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    let mut canvas = Positions {
+        page: index,
+        height: page_size(state, page).1,
+        found: HMap::default(),
+    };
+    place(state, page, &mut canvas);
+    canvas.found
+}
+
+/// The value of a dimension primitive that [`PRIMITIVES`](tex_engine::commands::primitives::PRIMITIVES)
+/// has no field for, e.g. `\pdfpageheight`.
+pub(crate) fn primitive_dim(state: &RusTeXState, name: &str) -> i32 {
+    state
+        .primitives()
+        .get_name(name)
+        .map_or(0, |id| state.get_primitive_dim(id).0)
+}
+
+struct Placer<'a, C: Canvas> {
+    canvas: &'a mut C,
+    /// `\fontdimen13` and `\fontdimen16` of `\textfont2`
+    sup_shift: i32,
+    sub_shift: i32,
+}
+
+/// How the glue in a list is set.
+#[derive(Clone, Copy, Default)]
+struct GlueSet {
+    ratio: f64,
+    /// 0 for finite, 1 for fil, 2 for fill, 3 for filll
+    order: u8,
+    shrink: bool,
+}
+impl GlueSet {
+    /// As in TeX, finite glue shrinks at most as far as it allows but may stretch further,
+    /// unless the list was broken `roughly` (a line of a paragraph).
+    fn new(natural: i32, target: Option<i32>, glues: &[Skip<Dim32>], roughly: bool) -> Self {
+        let Some(target) = target else {
+            return Self::default();
+        };
+        let shrink = target < natural;
+        let mut totals = [0f64; 4];
+        for g in glues {
+            if let Some(s) = if shrink { g.shrink } else { g.stretch } {
+                let (o, v) = glue_order(s);
+                totals[o as usize] += v;
+            }
+        }
+        let Some(order) = (0..4u8).rev().find(|o| totals[*o as usize] != 0.0) else {
+            return Self::default();
+        };
+        let mut ratio = f64::from((target - natural).abs()) / totals[order as usize];
+        if order == 0 && (shrink || roughly) {
+            ratio = ratio.min(1.0);
+        }
+        Self {
+            ratio,
+            order,
+            shrink,
+        }
+    }
+    fn apply(self, g: &Skip<Dim32>) -> i32 {
+        let part = if self.shrink { g.shrink } else { g.stretch };
+        let delta = match part.map(glue_order) {
+            Some((o, v)) if o == self.order => (v * self.ratio).round() as i32,
+            _ => 0,
+        };
+        if self.shrink {
+            g.base.0 - delta
+        } else {
+            g.base.0 + delta
+        }
+    }
+}
+
+fn glue_order(s: StretchShrink<Dim32>) -> (u8, f64) {
+    match s {
+        StretchShrink::Dim(d) => (0, f64::from(d.0)),
+        StretchShrink::Fil(i) => (1, f64::from(i)),
+        StretchShrink::Fill(i) => (2, f64::from(i)),
+        StretchShrink::Filll(i) => (3, f64::from(i)),
+    }
+}
+
+fn fil(s: StretchShrink<Dim32>, shrink: bool) -> Skip<Dim32> {
+    Skip {
+        base: Dim32(0),
+        stretch: Some(s),
+        shrink: shrink.then_some(s),
+    }
+}
+
+/// The glue of leaders.
+fn leader_glue(skip: &LeaderSkip<Types>) -> Skip<Dim32> {
+    match skip {
+        LeaderSkip::HSkip(s) | LeaderSkip::VSkip(s) => *s,
+        LeaderSkip::HFil | LeaderSkip::VFil => fil(StretchShrink::Fil(1), false),
+        LeaderSkip::HFill | LeaderSkip::VFill => fil(StretchShrink::Fill(1), false),
+    }
+}
+
+/// Where copies of a leader box of size `size` go in glue of length `len` starting at `pos`, in
+/// a box starting at `start`: `\leaders` align them with the box, `\cleaders` center them and
+/// `\xleaders` spread them out evenly.
+fn leader_positions(tp: LeaderType, size: i32, start: i32, pos: i32, len: i32) -> Vec<i32> {
+    if size <= 0 || len < size {
+        return Vec::new();
+    }
+    let (first, step) = match tp {
+        LeaderType::Normal => (
+            start + (pos - start + size - 1).div_euclid(size) * size,
+            size,
+        ),
+        LeaderType::C => (pos + (len % size) / 2, size),
+        LeaderType::X => {
+            let gap = (len % size) / (len / size + 1);
+            (pos + gap, size + gap)
+        }
+    };
+    (0..)
+        .map(|i| first + i * step)
+        .take_while(|p| *p + size <= pos + len)
+        .collect()
+}
+
+/// Sets the glue of a horizontal box, for backends that do not set glue themselves: leaders
+/// (e.g. the dots between the titles and the page numbers in a table of contents) get the
+/// widths they are set to. If the box is packaged to a width by stretching or shrinking finite
+/// glue, so does all other glue, and every space is followed by a kern by which it is stretched
+/// or shrunk. Glue in paragraph lines is left alone, since those are reflowed anyway; and so is
+/// glue in boxes with infinite glue, where the finite glue keeps its natural width.
+pub(crate) fn set_glue(info: &HBoxInfo<Types>, children: Box<[HNode<Types>]>) -> Vec<HNode<Types>> {
+    let (_, set, _) = hbox_glue_set(info, &children);
+    let justify = set.order == 0 && set.ratio != 0.0 && !matches!(info, HBoxInfo::ParLine { .. });
+    if !justify && !children.iter().any(|c| matches!(c, HNode::Leaders(_))) {
+        return children.into_vec();
+    }
+    let fixed = |width: i32| Skip {
+        base: Dim32(width),
+        stretch: None,
+        shrink: None,
+    };
+    let mut ret = Vec::with_capacity(children.len());
+    let mut font: Option<Font> = None;
+    for c in children.into_vec() {
+        match c {
+            HNode::Leaders(mut l) => {
+                l.skip = LeaderSkip::HSkip(fixed(set.apply(&leader_glue(&l.skip))));
+                ret.push(HNode::Leaders(l));
+            }
+            HNode::HSkip(s) if justify => ret.push(HNode::HSkip(fixed(set.apply(&s)))),
+            HNode::Space if justify => {
+                let delta = h_glue(&c, font.as_ref()).map_or(0, |g| set.apply(&g) - g.base.0);
+                ret.push(c);
+                if delta != 0 {
+                    ret.push(HNode::HKern(Dim32(delta)));
+                }
+            }
+            c => {
+                if let HNode::Char { font: f, .. } | HNode::Accent { font: f, .. } = &c {
+                    font = Some(f.clone());
+                }
+                ret.push(c);
+            }
+        }
+    }
+    ret
+}
+
+/// The glue of a node, if it is glue; spaces get the interword glue of `font`.
+fn h_glue(n: &HNode<Types>, font: Option<&Font>) -> Option<Skip<Dim32>> {
+    Some(match n {
+        HNode::HSkip(s) => *s,
+        HNode::Leaders(l) => leader_glue(&l.skip),
+        HNode::HFil => fil(StretchShrink::Fil(1), false),
+        HNode::HFill => fil(StretchShrink::Fill(1), false),
+        HNode::HFilneg => fil(StretchShrink::Fil(-1), false),
+        HNode::Hss => fil(StretchShrink::Fil(1), true),
+        HNode::Space => match font {
+            Some(f) => Skip {
+                base: f.get_dim(1),
+                stretch: Some(StretchShrink::Dim(f.get_dim(2))),
+                shrink: Some(StretchShrink::Dim(f.get_dim(3))),
+            },
+            None => Skip {
+                base: n.width(),
+                stretch: None,
+                shrink: None,
+            },
+        },
+        _ => return None,
+    })
+}
+
+fn v_glue(n: &VNode<Types>) -> Option<Skip<Dim32>> {
+    Some(match n {
+        VNode::VSkip(s) => *s,
+        VNode::Leaders(l) => leader_glue(&l.skip),
+        VNode::VFil => fil(StretchShrink::Fil(1), false),
+        VNode::VFill => fil(StretchShrink::Fill(1), false),
+        VNode::VFilneg => fil(StretchShrink::Fil(-1), false),
+        VNode::Vss => fil(StretchShrink::Fil(1), true),
+        _ => return None,
+    })
+}
+
+/// The natural width of a horizontal list, with spaces as wide as their font says.
+fn natural_width(children: &[HNode<Types>]) -> i32 {
+    let mut font: Option<&Font> = None;
+    children
+        .iter()
+        .map(|c| {
+            if let HNode::Char { font: f, .. } | HNode::Accent { font: f, .. } = c {
+                font = Some(f);
+            }
+            h_glue(c, font).map_or(c.width().0, |g| g.base.0)
+        })
+        .sum()
+}
+
+fn natural_height(children: &[VNode<Types>]) -> i32 {
+    children
+        .iter()
+        .map(|c| match v_glue(c) {
+            Some(g) => g.base.0,
+            None => c.height().0 + c.depth().0,
+        })
+        .sum()
+}
+
+/// The target size of a box, if it has one: `to`, `spread` or an assigned dimension.
+fn target(scaled: &ToOrSpread<Dim32>, assigned: Option<Dim32>, natural: i32) -> Option<i32> {
+    match (assigned, scaled) {
+        (Some(d), _) => Some(d.0),
+        (_, ToOrSpread::To(d)) => Some(d.0),
+        (_, ToOrSpread::Spread(d)) => Some(natural + d.0),
+        _ => None,
+    }
+}
+
+/// How the glue in a horizontal box is set; also returns the width of the `\leftskip` (of a
+/// paragraph line) and of the whole box.
+fn hbox_glue_set(info: &HBoxInfo<Types>, children: &[HNode<Types>]) -> (i32, GlueSet, i32) {
+    let natural = natural_width(children);
+    let (left, right, target) = match info {
+        HBoxInfo::ParLine { spec, .. } => (
+            Some(spec.leftskip),
+            Some(spec.rightskip),
+            Some(spec.leftskip.base.0 + spec.target.0 + spec.rightskip.base.0),
+        ),
+        HBoxInfo::HBox {
+            scaled,
+            assigned_width,
+            ..
+        } => (None, None, target(scaled, *assigned_width, natural)),
+        HBoxInfo::HAlignCell { to, .. } => (None, None, to.map(|d| d.0)),
+        _ => (None, None, None),
+    };
+    let mut glues: Vec<_> = left.into_iter().chain(right).collect();
+    let mut font = None;
+    for c in children.iter() {
+        if let HNode::Char { font: f, .. } | HNode::Accent { font: f, .. } = c {
+            font = Some(f);
+        }
+        glues.extend(h_glue(c, font));
+    }
+    let natural = natural + left.map_or(0, |s| s.base.0) + right.map_or(0, |s| s.base.0);
+    let line = matches!(info, HBoxInfo::ParLine { .. });
+    let set = GlueSet::new(natural, target, &glues, line);
+    (
+        left.map_or(0, |s| set.apply(&s)),
+        set,
+        target.unwrap_or(natural),
+    )
+}
+
+/// `\moveleft` and `\raise` of a box
+fn offsets(b: &TeXBox<Types>) -> (i32, i32) {
+    match b {
+        TeXBox::H { info, .. } => (
+            info.moved_left().map_or(0, |d| d.0),
+            info.raised().map_or(0, |d| d.0),
+        ),
+        TeXBox::V { info, .. } => (
+            info.moved_left().map_or(0, |d| d.0),
+            info.raised().map_or(0, |d| d.0),
+        ),
+    }
+}
+
+impl<C: Canvas> Placer<'_, C> {
+    fn rule(&mut self, x: i32, top: i32, width: i32, height: i32) {
+        if width > 0 && height > 0 {
+            self.canvas.rule(x, top, width, height);
+        }
+    }
+
+    /// Places a box with its reference point at `(x, baseline)`.
+    fn tex_box(&mut self, b: &TeXBox<Types>, x: i32, baseline: i32) {
+        match b {
+            TeXBox::H { info, children, .. } => {
+                let (left, set, width) = hbox_glue_set(info, children);
+                let x = x + left;
+                let line = matches!(info, HBoxInfo::ParLine { .. });
+                let (height, depth) = (b.height().0, b.depth().0);
+                if line {
+                    self.canvas.line_start(x, baseline, height, depth);
+                }
+                let end = self.hlist(children, x, baseline, (width, height, depth), set);
+                if line {
+                    self.canvas.line_end(end);
+                }
+            }
+            TeXBox::V { info, children, .. } => {
+                let natural = natural_height(children);
+                let target = match info {
+                    VBoxInfo::VBox {
+                        scaled,
+                        assigned_height,
+                        ..
+                    } => target(scaled, *assigned_height, natural),
+                    _ => None,
+                };
+                let glues: Vec<_> = children.iter().filter_map(v_glue).collect();
+                let set = GlueSet::new(natural, target, &glues, false);
+                self.vlist(children, x, baseline - b.height().0, b.width().0, set);
+            }
+        }
+    }
+
+    fn vlist(&mut self, children: &[VNode<Types>], x: i32, top: i32, width: i32, set: GlueSet) {
+        let mut y = top;
+        for c in children {
+            if let Some(g) = v_glue(c) {
+                let len = set.apply(&g);
+                if let VNode::Leaders(l) = c {
+                    self.v_leaders(l, (top, y, len), x, width);
+                }
+                y += len;
+                continue;
+            }
+            match c {
+                VNode::Box(b) => {
+                    let (moved_left, _) = offsets(b);
+                    let height = b.height().0;
+                    self.tex_box(b, x - moved_left, y + height);
+                    y += height + b.depth().0;
+                }
+                VNode::HRule {
+                    width: w,
+                    height,
+                    depth,
+                    ..
+                } => {
+                    let h = height.map_or(RULE, |d| d.0) + depth.map_or(0, |d| d.0);
+                    self.rule(x, y, w.map_or(width, |d| d.0), h);
+                    y += h;
+                }
+                VNode::VKern(d) => y += d.0,
+                VNode::Custom(n) => {
+                    self.canvas.custom(n, x, y, 0, 0);
+                    y += n.height().0 + n.depth().0;
+                }
+                _ => y += c.height().0 + c.depth().0,
+            }
+        }
+    }
+
+    /// Horizontal leaders in the glue `(box start, x, length)`, in a box of the given height and
+    /// depth.
+    fn h_leaders(
+        &mut self,
+        l: &Leaders<Types>,
+        (start, x, len): (i32, i32, i32),
+        baseline: i32,
+        (height, depth): (i32, i32),
+    ) {
+        match &l.body {
+            LeaderBody::Rule {
+                height: h,
+                depth: d,
+                ..
+            } => {
+                let h = h.map_or(height, |d| d.0);
+                let d = d.map_or(depth, |d| d.0);
+                self.rule(x, baseline - h, len, h + d);
+            }
+            LeaderBody::Box(b) => {
+                let (_, raised) = offsets(b);
+                for bx in leader_positions(l.tp, b.width().0, start, x, len) {
+                    self.tex_box(b, bx, baseline - raised);
+                }
+            }
+        }
+    }
+
+    /// Vertical leaders in the glue `(box top, y, length)`, in a box of the given width.
+    fn v_leaders(
+        &mut self,
+        l: &Leaders<Types>,
+        (top, y, len): (i32, i32, i32),
+        x: i32,
+        width: i32,
+    ) {
+        match &l.body {
+            LeaderBody::Rule { width: w, .. } => {
+                self.rule(x, y, w.map_or(width, |d| d.0), len);
+            }
+            LeaderBody::Box(b) => {
+                let (moved_left, _) = offsets(b);
+                let size = b.height().0 + b.depth().0;
+                for by in leader_positions(l.tp, size, top, y, len) {
+                    self.tex_box(b, x - moved_left, by + b.height().0);
+                }
+            }
+        }
+    }
+
+    /// Sets a horizontal list starting at `(x, baseline)` in a box of the given
+    /// (width, height, depth); returns where it ends.
+    fn hlist(
+        &mut self,
+        children: &[HNode<Types>],
+        start: i32,
+        baseline: i32,
+        (width, height, depth): (i32, i32, i32),
+        set: GlueSet,
+    ) -> i32 {
+        let mut x = start;
+        let mut font: Option<&Font> = None;
+        for c in children {
+            if let Some(g) = h_glue(c, font) {
+                let len = set.apply(&g);
+                if let HNode::Leaders(l) = c {
+                    self.h_leaders(l, (start, x, len), baseline, (height, depth));
+                }
+                x += len;
+                continue;
+            }
+            match c {
+                HNode::Char { char, font: f } => {
+                    self.canvas.glyph(x, baseline, f, *char);
+                    x += f.get_wd(*char).0;
+                    font = Some(f);
+                }
+                HNode::Accent {
+                    accent,
+                    char,
+                    font: f,
+                } => {
+                    // as with `\accent`: centered over the character, and raised by the
+                    // difference between its height and the x-height
+                    let wd = f.get_wd(*char).0;
+                    self.canvas.glyph(x, baseline, f, *char);
+                    let raise = (f.get_ht(*char).0 - f.get_dim(4).0).max(0);
+                    let ax = x + (wd - f.get_wd(*accent).0) / 2;
+                    self.canvas.glyph(ax, baseline - raise, f, *accent);
+                    x += wd;
+                    font = Some(f);
+                }
+                HNode::Box(b) => {
+                    let (_, raised) = offsets(b);
+                    self.tex_box(b, x, baseline - raised);
+                    x += b.width().0;
+                }
+                HNode::VRule {
+                    width: w,
+                    height: h,
+                    depth: d,
+                    ..
+                } => {
+                    let w = w.map_or(RULE, |d| d.0);
+                    let h = h.map_or(height, |d| d.0);
+                    let d = d.map_or(depth, |d| d.0);
+                    self.rule(x, baseline - h, w, h + d);
+                    x += w;
+                }
+                HNode::HKern(d) => x += d.0,
+                // discretionaries the paragraph was broken at have been resolved already
+                HNode::Discretionary { nobreak, .. } => {
+                    x = self.hlist(nobreak, x, baseline, (width, height, depth), set)
+                }
+                HNode::MathGroup(g) => {
+                    match &g.display {
+                        // the line of a display also contains the skips above and below
+                        Some((_, below)) => {
+                            let mx = start + (width - g.width().0) / 2;
+                            let b = baseline - g.depth().0 - below.base.0;
+                            self.mlist(&g.children, mx, b);
+                        }
+                        None => {
+                            self.mlist(&g.children, x, baseline);
+                        }
+                    }
+                    x += g.width().0;
+                }
+                HNode::Custom(n) => {
+                    self.canvas.custom(n, x, baseline, height, depth);
+                    x += n.width().0;
+                }
+                _ => x += c.width().0,
+            }
+        }
+        x
+    }
+
+    /// Sets a math list along the baseline; returns where it ends.
+    fn mlist(&mut self, children: &[MNode], x: i32, baseline: i32) -> i32 {
+        let mut x = x;
+        for c in children {
+            match c {
+                MathNode::Atom(a) => {
+                    let end = self.nucleus(&a.nucleus, x, baseline);
+                    if let Some(sup) = &a.sup {
+                        self.mlist(sup, end, baseline - self.sup_shift);
+                    }
+                    if let Some(sub) = &a.sub {
+                        self.mlist(sub, end, baseline + self.sub_shift);
+                    }
+                }
+                MathNode::VRule {
+                    width,
+                    height,
+                    depth,
+                    ..
+                } => {
+                    let h = height.map_or(0, |d| d.0);
+                    let d = depth.map_or(0, |d| d.0);
+                    self.rule(x, baseline - h, width.map_or(RULE, |d| d.0), h + d);
+                }
+                _ => (),
+            }
+            x += c.width().0;
+        }
+        x
+    }
+
+    fn nucleus(&mut self, n: &MathNucleus<Types, MathFontStyle<Types>>, x: i32, b: i32) -> i32 {
+        match n {
+            MathNucleus::Simple { kernel, .. }
+            | MathNucleus::Inner(kernel)
+            | MathNucleus::Overline(kernel)
+            | MathNucleus::Underline(kernel) => self.kernel(kernel, x, b),
+            MathNucleus::LeftRight {
+                left,
+                children,
+                right,
+                ..
+            } => {
+                let mut x = x;
+                if let Some((c, style)) = left {
+                    self.canvas.glyph(x, b, &style.font, *c);
+                    x += style.font.get_wd(*c).0;
+                }
+                x = self.mlist(children, x, b);
+                if let Some((c, style)) = right {
+                    self.canvas.glyph(x, b, &style.font, *c);
+                    x += style.font.get_wd(*c).0;
+                }
+                x
+            }
+            MathNucleus::Middle(c, style) => {
+                self.canvas.glyph(x, b, &style.font, *c);
+                x + style.font.get_wd(*c).0
+            }
+            _ => x + n.width().0,
+        }
+    }
+
+    fn kernel(&mut self, k: &MathKernel<Types, MathFontStyle<Types>>, x: i32, b: i32) -> i32 {
+        match k {
+            MathKernel::Empty => x,
+            MathKernel::Char { char, style } => {
+                self.canvas.glyph(x, b, &style.font, *char);
+                x + style.font.get_wd(*char).0
+            }
+            MathKernel::Box(bx) => {
+                let (_, raised) = offsets(bx);
+                self.tex_box(bx, x, b - raised);
+                x + bx.width().0
+            }
+            MathKernel::List { children, .. } => self.mlist(children, x, b),
+        }
+    }
+}
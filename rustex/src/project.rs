@@ -0,0 +1,463 @@
+//! Compiling several documents that share files (preambles, macro files, images) as one
+//! project: dependencies are collected by scanning the sources for `\input`, `\include`
+//! and `\includegraphics`, only documents whose dependencies changed are recompiled, and
+//! links between documents are rewritten to point to the generated HTML files.
+
+use crate::engine::{RusTeXEngine, RusTeXEngineT, Settings};
+use crate::sink::{relative_name, DirectorySink, MemorySink, OutputSink};
+use crate::thumbnails::{Thumbnail, ThumbnailOptions, ThumbnailRenderer};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tex_engine::utils::HMap;
+
+const TEX_EXTENSIONS: [&str; 2] = ["", "tex"];
+const GRAPHICS_EXTENSIONS: [&str; 6] = ["", "pdf", "png", "jpg", "jpeg", "svg"];
+
+/// A set of documents together with their (transitive, local) dependencies.
+#[derive(Debug, Clone, Default)]
+pub struct Project {
+    /// the directory the output structure mirrors
+    pub root: PathBuf,
+    /// the main files of the project
+    pub documents: Vec<PathBuf>,
+    deps: HMap<PathBuf, Vec<PathBuf>>,
+}
+
+/// The result of compiling a single document of a [`Project`].
+#[derive(Debug, Clone)]
+pub struct DocumentStatus {
+    pub document: PathBuf,
+    pub output: PathBuf,
+    /// `false` if the output was up to date
+    pub compiled: bool,
+    /// the error message, if compilation failed
+    pub error: Option<String>,
+    /// the page thumbnails, if requested (see [`Project::compile_with_thumbnails`])
+    pub thumbnails: Vec<Thumbnail>,
+}
+impl DocumentStatus {
+    fn not_compiled(document: &Path, output: PathBuf, error: Option<String>) -> Self {
+        Self {
+            document: document.to_path_buf(),
+            output,
+            compiled: false,
+            error,
+            thumbnails: Vec::new(),
+        }
+    }
+}
+
+impl Project {
+    /// Reads a manifest: one document per line, relative to the manifest's directory.
+    /// Empty lines and lines starting with `#` are ignored.
+    ///
+    /// #### Errors
+    /// If the manifest or one of the documents can not be read
+    pub fn from_manifest(manifest: &Path) -> std::io::Result<Self> {
+        let root = manifest
+            .parent()
+            .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+        let content = std::fs::read_to_string(manifest)?;
+        let mut ret = Self {
+            root,
+            ..Self::default()
+        };
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let doc = ret.root.join(line);
+            ret.add_document(doc)?;
+        }
+        Ok(ret)
+    }
+
+    /// Collects all `.tex` files below `root` that contain a `\documentclass`.
+    ///
+    /// #### Errors
+    /// If a directory or file can not be read
+    pub fn scan(root: &Path) -> std::io::Result<Self> {
+        let mut ret = Self {
+            root: root.to_path_buf(),
+            ..Self::default()
+        };
+        let mut dirs = vec![root.to_path_buf()];
+        let mut docs = Vec::new();
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if path.extension().is_some_and(|e| e == "tex")
+                    && std::fs::read_to_string(&path)
+                        .is_ok_and(|s| strip_comments(&s).contains("\\documentclass"))
+                {
+                    docs.push(path);
+                }
+            }
+        }
+        docs.sort();
+        for d in docs {
+            ret.add_document(d)?;
+        }
+        Ok(ret)
+    }
+
+    /// Adds a main file to the project and collects its dependencies.
+    ///
+    /// #### Errors
+    /// If the document can not be read
+    pub fn add_document(&mut self, doc: PathBuf) -> std::io::Result<()> {
+        let doc = doc.canonicalize()?;
+        if self.deps.contains_key(&doc) {
+            return Ok(());
+        }
+        let dir = doc.parent().unwrap_or(&self.root).to_path_buf();
+        let mut deps = Vec::new();
+        let mut todo = vec![doc.clone()];
+        while let Some(file) = todo.pop() {
+            let Ok(content) = std::fs::read_to_string(&file) else {
+                continue;
+            };
+            for (graphics, name) in references(&content) {
+                let exts: &[&str] = if graphics {
+                    &GRAPHICS_EXTENSIONS
+                } else {
+                    &TEX_EXTENSIONS
+                };
+                let Some(found) = resolve(&dir, &name, exts) else {
+                    continue;
+                };
+                if found == doc || deps.contains(&found) {
+                    continue;
+                }
+                if !graphics {
+                    todo.push(found.clone());
+                }
+                deps.push(found);
+            }
+        }
+        self.deps.insert(doc.clone(), deps);
+        self.documents.push(doc);
+        Ok(())
+    }
+
+    /// The local files `doc` depends on (not including `doc` itself).
+    pub fn dependencies(&self, doc: &Path) -> &[PathBuf] {
+        self.deps.get(doc).map_or(&[], Vec::as_slice)
+    }
+
+    /// The documents that need to be recompiled if `changed` changes.
+    pub fn affected(&self, changed: &Path) -> Vec<&Path> {
+        let changed = changed
+            .canonicalize()
+            .unwrap_or_else(|_| changed.to_path_buf());
+        self.documents
+            .iter()
+            .filter(|d| **d == changed || self.dependencies(d).contains(&changed))
+            .map(PathBuf::as_path)
+            .collect()
+    }
+
+    /// The path of the HTML file generated for `doc` in `out_dir`. Documents outside of the
+    /// [`root`](Self::root) are placed directly in `out_dir`, so their outputs may collide
+    /// (see [`compile_to`](Self::compile_to)).
+    pub fn output_path(&self, doc: &Path, out_dir: &Path) -> PathBuf {
+        let root = self
+            .root
+            .canonicalize()
+            .unwrap_or_else(|_| self.root.clone());
+        let rel = doc
+            .strip_prefix(&root)
+            .ok()
+            .or_else(|| doc.file_name().map(Path::new))
+            .unwrap_or(doc);
+        out_dir.join(rel).with_extension("html")
+    }
+
+    /// The documents whose output in `out_dir` is missing or older than the document or
+    /// one of its dependencies.
+    pub fn outdated(&self, out_dir: &Path) -> Vec<&Path> {
+        self.documents
+            .iter()
+            .filter(|doc| {
+                let Some(out) = modified(&self.output_path(doc, out_dir)) else {
+                    return true;
+                };
+                std::iter::once(doc.as_path())
+                    .chain(self.dependencies(doc).iter().map(PathBuf::as_path))
+                    .any(|f| modified(f).is_none_or(|m| m > out))
+            })
+            .map(PathBuf::as_path)
+            .collect()
+    }
+
+    /// Compiles all outdated documents (or all, if `force` is set) into `out_dir`, rewriting
+    /// links to other documents of the project, and writes an `index.html` listing them.
+    ///
+    /// #### Errors
+    /// If an output file can not be written
+    pub fn compile<F: FnMut() -> Settings>(
+        &self,
+        out_dir: &Path,
+        force: bool,
+        settings: F,
+    ) -> std::io::Result<Vec<DocumentStatus>> {
+        self.compile_with_thumbnails(out_dir, force, settings, None)
+    }
+
+    /// Like [`compile`](Self::compile), but additionally renders thumbnails of the pages of
+    /// every compiled document next to its output (see [`thumbnails`](crate::thumbnails)).
+    /// A document whose thumbnails fail to render is reported with an error.
+    ///
+    /// #### Errors
+    /// If an output file can not be written
+    pub fn compile_with_thumbnails<F: FnMut() -> Settings>(
+        &self,
+        out_dir: &Path,
+        force: bool,
+        settings: F,
+        thumbnails: Option<(&ThumbnailOptions, &mut dyn ThumbnailRenderer)>,
+    ) -> std::io::Result<Vec<DocumentStatus>> {
+        let mut sink = DirectorySink::new(out_dir);
+        self.compile_to(out_dir, &mut sink, force, settings, thumbnails)
+    }
+
+    /// Like [`compile_with_thumbnails`](Self::compile_with_thumbnails), but writes all
+    /// outputs to `sink` (named relative to `out_dir`) and finalizes it afterwards. `out_dir`
+    /// is still used to determine which documents are up to date and as the working
+    /// directory for rendering thumbnails.
+    ///
+    /// Documents that can not be compiled, since their path is not valid UTF-8 or since
+    /// several documents have the same [output path](Self::output_path), are reported
+    /// with an error.
+    ///
+    /// #### Errors
+    /// If writing to the sink fails
+    pub fn compile_to<F: FnMut() -> Settings>(
+        &self,
+        out_dir: &Path,
+        sink: &mut dyn OutputSink,
+        force: bool,
+        mut settings: F,
+        mut thumbnails: Option<(&ThumbnailOptions, &mut dyn ThumbnailRenderer)>,
+    ) -> std::io::Result<Vec<DocumentStatus>> {
+        let outdated = if force {
+            self.documents.iter().map(PathBuf::as_path).collect()
+        } else {
+            self.outdated(out_dir)
+        };
+        let mut outputs: HMap<PathBuf, Vec<&Path>> = HMap::default();
+        for doc in &self.documents {
+            outputs
+                .entry(self.output_path(doc, out_dir))
+                .or_default()
+                .push(doc);
+        }
+        let mut ret = Vec::with_capacity(self.documents.len());
+        for doc in &self.documents {
+            let output = self.output_path(doc, out_dir);
+            if let Some(others) = outputs.get(&output).filter(|d| d.len() > 1) {
+                let others = others
+                    .iter()
+                    .filter(|d| **d != doc.as_path())
+                    .map(|d| d.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let error = format!("{} has the same output file as {others}", doc.display());
+                ret.push(DocumentStatus::not_compiled(doc, output, Some(error)));
+                continue;
+            }
+            if !outdated.contains(&doc.as_path()) {
+                ret.push(DocumentStatus::not_compiled(doc, output, None));
+                continue;
+            }
+            let Some(s) = doc.to_str() else {
+                let error = format!("{} is not a valid UTF-8 path", doc.display());
+                ret.push(DocumentStatus::not_compiled(doc, output, Some(error)));
+                continue;
+            };
+            let mut settings = settings();
+            settings.split_pages |= thumbnails.is_some();
+            let result = RusTeXEngine::do_file(s, settings);
+            let name = relative_name(&output, out_dir);
+            let mut mem = MemorySink::default();
+            result.write_to(&mut mem, &name)?;
+            for (n, html) in &mem.pages {
+                sink.write_page(n, &self.resolve_links(doc, html))?;
+            }
+            for (n, bytes) in &mem.assets {
+                sink.write_asset(n, bytes)?;
+            }
+            if let Some(deps) = &result.stex_dependencies {
+                let prefix = name.strip_suffix(".html").unwrap_or(&name);
+                sink.write_asset(&format!("{prefix}.deps.json"), deps.to_json().as_bytes())?;
+            }
+            let mut error = result.error.as_ref().map(|(e, _)| e.to_string());
+            let thumbs = match &mut thumbnails {
+                Some((options, renderer)) => {
+                    let prefix = name.strip_suffix(".html").unwrap_or(&name);
+                    let dir = output.parent().unwrap_or(out_dir);
+                    crate::thumbnails::generate(&result, dir, prefix, options, *renderer, sink)
+                        .unwrap_or_else(|e| {
+                            error.get_or_insert_with(|| format!("thumbnails: {e}"));
+                            Vec::new()
+                        })
+                }
+                None => Vec::new(),
+            };
+            ret.push(DocumentStatus {
+                document: doc.clone(),
+                output,
+                compiled: true,
+                error,
+                thumbnails: thumbs,
+            });
+        }
+        self.write_index(out_dir, sink, &ret)?;
+        sink.finalize()?;
+        Ok(ret)
+    }
+
+    /// Rewrites `href`s in the output of `doc` that point to the source or PDF of another
+    /// document of the project, so that they point to its HTML file instead.
+    fn resolve_links(&self, doc: &Path, html: &str) -> String {
+        let dir = doc.parent().unwrap_or(&self.root);
+        let mut ret = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(i) = rest.find("href=\"") {
+            let (pre, post) = rest.split_at(i + 6);
+            ret.push_str(pre);
+            let Some(end) = post.find('"') else {
+                rest = post;
+                break;
+            };
+            let target = &post[..end];
+            let (file, fragment) = target
+                .split_once('#')
+                .map_or((target, None), |(f, r)| (f, Some(r)));
+            let is_doc = !file.contains("://")
+                && Path::new(file)
+                    .extension()
+                    .is_some_and(|e| e == "tex" || e == "pdf" || e == "html")
+                && dir
+                    .join(file)
+                    .with_extension("tex")
+                    .canonicalize()
+                    .is_ok_and(|p| self.deps.contains_key(&p));
+            if is_doc {
+                ret.push_str(&Path::new(file).with_extension("html").to_string_lossy());
+                if let Some(f) = fragment {
+                    ret.push('#');
+                    ret.push_str(f);
+                }
+            } else {
+                ret.push_str(target);
+            }
+            rest = &post[end..];
+        }
+        ret.push_str(rest);
+        ret
+    }
+
+    fn write_index(
+        &self,
+        out_dir: &Path,
+        sink: &mut dyn OutputSink,
+        docs: &[DocumentStatus],
+    ) -> std::io::Result<()> {
+        use std::fmt::Write;
+        let mut s = String::from(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"UTF-8\"><title>Index</title></head><body><ul>\n",
+        );
+        for d in docs {
+            let _ = writeln!(
+                s,
+                "<li><a href=\"{0}\">{0}</a>{1}</li>",
+                relative_name(&d.output, out_dir),
+                if d.error.is_some() { " (errors)" } else { "" }
+            );
+        }
+        s.push_str("</ul></body></html>\n");
+        sink.write_page("index.html", &s)
+    }
+}
+
+fn modified(p: &Path) -> Option<SystemTime> {
+    std::fs::metadata(p).and_then(|m| m.modified()).ok()
+}
+
+fn resolve(dir: &Path, name: &str, exts: &[&str]) -> Option<PathBuf> {
+    let base = dir.join(name);
+    exts.iter()
+        .map(|e| {
+            if e.is_empty() {
+                base.clone()
+            } else {
+                let mut s = base.clone().into_os_string();
+                s.push(".");
+                s.push(e);
+                PathBuf::from(s)
+            }
+        })
+        .find(|p| p.is_file())
+        .and_then(|p| p.canonicalize().ok())
+}
+
+/// Removes `%`-comments (but not escaped `\%`).
+fn strip_comments(s: &str) -> String {
+    s.lines()
+        .map(|l| {
+            let mut escaped = false;
+            let end = l
+                .char_indices()
+                .find(|(_, c)| {
+                    let r = *c == '%' && !escaped;
+                    escaped = *c == '\\' && !escaped;
+                    r
+                })
+                .map_or(l.len(), |(i, _)| i);
+            &l[..end]
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Finds the arguments of `\input`, `\include` and `\includegraphics` in a source file;
+/// the boolean is `true` for graphics.
+fn references(content: &str) -> Vec<(bool, String)> {
+    let stripped = strip_comments(content);
+    let mut ret = Vec::new();
+    let mut rest = stripped.as_str();
+    while let Some(i) = rest.find('\\') {
+        rest = &rest[i + 1..];
+        let name_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let graphics = match &rest[..name_len] {
+            "input" | "include" => false,
+            "includegraphics" => true,
+            _ => continue,
+        };
+        let mut args = rest[name_len..].trim_start();
+        if args.starts_with('*') {
+            args = &args[1..];
+        }
+        if args.starts_with('[') {
+            let Some(e) = args.find(']') else { continue };
+            args = args[e + 1..].trim_start();
+        }
+        let arg = if let Some(a) = args.strip_prefix('{') {
+            a.find('}').map(|e| &a[..e])
+        } else {
+            // `\input file`: the name extends to the next whitespace
+            args.split(|c: char| c.is_whitespace() || c == '\\')
+                .next()
+                .filter(|s| !s.is_empty())
+        };
+        if let Some(arg) = arg.map(str::trim).filter(|a| !a.contains('\\')) {
+            ret.push((graphics, arg.to_string()));
+        }
+    }
+    ret
+}
@@ -0,0 +1,123 @@
+//! Captions of TikZ/PGF pictures: `patches.tex` reports the beginning and end of floats with
+//! `\rustex@floatBegin`/`\rustex@floatEnd` and their captions with `\rustex@caption`. Each picture
+//! in a float gets the caption of the float as the `<title>` (the short caption) and `<desc>`
+//! (the full caption, if it differs) of its `<svg>`, so that screen readers can announce it.
+//!
+//! The floats themselves are kept where they occur in the source (after the paragraph they
+//! are in) rather than moved to the top or bottom of a page, as a `<figure>` with the caption
+//! as `<figcaption>`, and listed in
+//! [`CompilationResult::floats`](crate::engine::CompilationResult::floats).
+
+/// The caption of a picture or float.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caption {
+    /// the short caption (as in the list of figures)
+    pub title: String,
+    /// the full caption, if it differs
+    pub desc: Option<String>,
+}
+
+/// A float (`figure`, `table`,...) of a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Float {
+    /// the float type, e.g. `figure`
+    pub kind: String,
+    /// the `id` of its `<figure>`
+    pub id: String,
+    pub caption: Option<Caption>,
+}
+
+/// The pictures and floats of a run and their captions, by the order in which they were
+/// typeset.
+#[derive(Debug, Default)]
+pub(crate) struct Captions {
+    pictures: Vec<Option<Caption>>,
+    floats: Vec<Float>,
+    in_float: bool,
+    /// the pictures in the current float that precede its caption
+    uncaptioned: Vec<usize>,
+    /// the caption of the current float, if it precedes the pictures
+    pending: Option<Caption>,
+}
+impl Captions {
+    /// Registers a new picture and returns its number.
+    pub(crate) fn new_picture(&mut self) -> usize {
+        let idx = self.pictures.len();
+        self.pictures.push(self.pending.clone());
+        if self.in_float && self.pending.is_none() {
+            self.uncaptioned.push(idx);
+        }
+        idx
+    }
+    /// Registers a new float of the given type and returns the `id` of its `<figure>`.
+    pub(crate) fn float_begin(&mut self, kind: &str) -> String {
+        self.in_float = true;
+        self.uncaptioned.clear();
+        self.pending = None;
+        let id = format!("rustex-float-{}", self.floats.len() + 1);
+        self.floats.push(Float {
+            kind: kind.to_string(),
+            id: id.clone(),
+            caption: None,
+        });
+        id
+    }
+    pub(crate) fn float_end(&mut self) {
+        self.in_float = false;
+        self.uncaptioned.clear();
+        self.pending = None;
+    }
+    /// A `\caption[short]{long}`; only captions of floats are associated with pictures.
+    pub(crate) fn caption(&mut self, short: &str, long: &str) {
+        if !self.in_float {
+            return;
+        }
+        let title = plain_text(short);
+        let desc = Some(plain_text(long)).filter(|d| !d.is_empty() && *d != title);
+        let caption = Caption { title, desc };
+        if let Some(f) = self.floats.last_mut() {
+            f.caption.get_or_insert_with(|| caption.clone());
+        }
+        if self.uncaptioned.is_empty() {
+            self.pending = Some(caption);
+        } else {
+            for i in self.uncaptioned.drain(..) {
+                self.pictures[i] = Some(caption.clone());
+            }
+        }
+    }
+    pub(crate) fn get(&self, picture: usize) -> Option<&Caption> {
+        self.pictures.get(picture).and_then(Option::as_ref)
+    }
+    pub(crate) fn into_floats(self) -> Vec<Float> {
+        self.floats
+    }
+}
+
+/// Drops control sequences and braces from the (partially expanded) caption text.
+fn plain_text(s: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if chars
+                    .next_if(|c| c.is_ascii_alphabetic() || *c == '@')
+                    .is_some()
+                {
+                    while chars
+                        .next_if(|c| c.is_ascii_alphabetic() || *c == '@')
+                        .is_some()
+                    {}
+                } else if let Some(c) = chars.next() {
+                    // `\%`, `\&`,...
+                    ret.push(c);
+                }
+            }
+            '{' | '}' => (),
+            '~' => ret.push(' '),
+            c => ret.push(c),
+        }
+    }
+    ret.split_whitespace().collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,210 @@
+//! Client side math rendering (see [`Settings::math_renderer`](crate::engine::Settings::math_renderer)):
+//! instead of the MathML generated by RusTeX, formulas are emitted as their TeX source in a
+//! `<span class="rustex-tex-math">` (with `\(...\)` or `\[...\]` delimiters), and a loader for
+//! MathJax or KaTeX is added to the head, which renders them in the browser.
+//!
+//! The source of a formula is taken from the input file, between the math shifts (or
+//! `\(`/`\[`, or the `\begin` and `\end` of a math environment) the formula was started and ended
+//! with. Formulas that do not come directly from the input (e.g. from a macro expanding to
+//! `$...$`) keep the MathML output; so do formulas whose source the renderers could not make
+//! sense of anyway, e.g. the cells of an alignment.
+
+use crate::engine::extension::{Script, CSS};
+use crate::engine::files::RusTeXFileSystem;
+use crate::engine::SRef;
+use crate::utils::VecSet;
+use std::path::PathBuf;
+use tex_engine::engine::filesystem::FileSystem;
+
+/// Which library renders the formulas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MathRenderer {
+    MathJax,
+    KaTeX,
+}
+impl MathRenderer {
+    /// `mathjax` or `katex`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "mathjax" => Some(Self::MathJax),
+            "katex" => Some(Self::KaTeX),
+            _ => None,
+        }
+    }
+    /// Adds the stylesheets and scripts loading (and configuring) the renderer.
+    pub(crate) fn add_loader(self, css: &mut VecSet<CSS>, scripts: &mut VecSet<Script>) {
+        match self {
+            Self::MathJax => {
+                scripts.insert(Script::Literal(MATHJAX_CONFIG.to_string()));
+                scripts.insert(Script::File(MATHJAX_URL.to_string()));
+            }
+            Self::KaTeX => {
+                css.insert(CSS::File(format!("{KATEX_URL}katex.min.css")));
+                scripts.insert(Script::File(format!("{KATEX_URL}katex.min.js")));
+                scripts.insert(Script::Literal(KATEX_RENDER.to_string()));
+            }
+        }
+    }
+}
+
+const MATHJAX_URL: &str = "https://cdn.jsdelivr.net/npm/mathjax@3/es5/tex-chtml.js";
+const MATHJAX_CONFIG: &str = r#"window.MathJax = {
+  tex: { inlineMath: [['\\(', '\\)']], displayMath: [['\\[', '\\]']] },
+  options: { ignoreHtmlClass: 'rustex-body', processHtmlClass: 'rustex-tex-math' }
+};
+"#;
+const KATEX_URL: &str = "https://cdn.jsdelivr.net/npm/katex@0.16/dist/";
+const KATEX_RENDER: &str = r#"document.addEventListener("DOMContentLoaded", () => {
+  for (const e of document.querySelectorAll(".rustex-tex-math")) {
+    katex.render(e.textContent.slice(2, -2), e, {
+      displayMode: e.classList.contains("rustex-tex-display"), throwOnError: false
+    });
+  }
+});
+"#;
+
+/// The HTML replacing a formula.
+pub(crate) fn html(tex: &str, display: bool) -> String {
+    let tex = tex
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    if display {
+        format!("<div class=\"rustex-display\"><span class=\"rustex-tex-math rustex-tex-display\">\\[{tex}\\]</span></div>")
+    } else {
+        format!("<span class=\"rustex-tex-math\">\\({tex}\\)</span>")
+    }
+}
+
+/// The contents of the input files, as far as needed for extracting formulas.
+#[derive(Debug, Default)]
+pub(crate) struct Sources {
+    files: Vec<(PathBuf, Option<Vec<String>>)>,
+}
+impl Sources {
+    /// The TeX source of the formula from `start` to `end`, without its delimiters.
+    pub(crate) fn formula(
+        &mut self,
+        start: SRef,
+        end: SRef,
+        fs: &RusTeXFileSystem,
+    ) -> Option<String> {
+        if start.file != end.file {
+            return None;
+        }
+        let path = fs.inner.kpse.pwd.join(fs.ref_str(start.file));
+        self.formula_at(path, (start.line, start.column), (end.line, end.column))
+    }
+    /// As [`formula`](Self::formula), with the file and the (line, column) positions given
+    /// directly.
+    pub(crate) fn formula_at(
+        &mut self,
+        path: PathBuf,
+        start: (usize, usize),
+        end: (usize, usize),
+    ) -> Option<String> {
+        if end <= start {
+            return None;
+        }
+        let lines = match self.files.iter().position(|(p, _)| *p == path) {
+            Some(i) => &self.files[i].1,
+            None => {
+                let lines = std::fs::read_to_string(&path)
+                    .ok()
+                    .map(|s| s.lines().map(str::to_string).collect());
+                self.files.push((path, lines));
+                &self.files[self.files.len() - 1].1
+            }
+        };
+        let lines = lines.as_ref()?;
+        let mut text = String::new();
+        for l in start.0..=end.0 {
+            let line = lines.get(l.checked_sub(1)?)?;
+            let from = if l == start.0 {
+                start.1.saturating_sub(1)
+            } else {
+                0
+            };
+            let to = if l == end.0 {
+                end.1.saturating_sub(1).min(line.len())
+            } else {
+                line.len()
+            };
+            text.push_str(line.get(from.min(to)..to)?);
+            if l != end.0 {
+                text.push('\n');
+            }
+        }
+        strip_delimiters(&text)
+    }
+}
+
+const MATH_ENVIRONMENTS: [&str; 4] = ["equation", "equation*", "displaymath", "math"];
+
+/// Removes the opening (and, if present, the closing) delimiters and `\label`s from a formula;
+/// `None` if it does not start with a delimiter or its braces are unbalanced.
+fn strip_delimiters(text: &str) -> Option<String> {
+    let text = text.trim();
+    let (body, close) = if let Some(rest) = text.strip_prefix("$$") {
+        (rest, "$$".to_string())
+    } else if let Some(rest) = text.strip_prefix('$') {
+        (rest, "$".to_string())
+    } else if let Some(rest) = text.strip_prefix("\\(") {
+        (rest, "\\)".to_string())
+    } else if let Some(rest) = text.strip_prefix("\\[") {
+        (rest, "\\]".to_string())
+    } else {
+        let rest = text
+            .strip_prefix("\\begin")?
+            .trim_start()
+            .strip_prefix('{')?;
+        let (env, body) = rest.split_once('}')?;
+        let close = format!("\\end{{{env}}}");
+        if MATH_ENVIRONMENTS.contains(&env) {
+            (body, close)
+        } else {
+            // e.g. `align`, which the renderers know as well
+            let body = body.trim_end();
+            let body = body.strip_suffix(close.as_str()).unwrap_or(body);
+            return balanced(&format!("\\begin{{{env}}}{}{close}", remove_labels(body)));
+        }
+    };
+    let body = body.trim_end();
+    let body = body.strip_suffix(close.as_str()).unwrap_or(body);
+    balanced(remove_labels(body).trim())
+}
+
+fn remove_labels(s: &str) -> String {
+    let mut ret = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find("\\label{") {
+        ret.push_str(&rest[..i]);
+        rest = &rest[i..];
+        match rest.find('}') {
+            Some(j) => rest = &rest[j + 1..],
+            None => break,
+        }
+    }
+    ret.push_str(rest);
+    ret
+}
+
+fn balanced(s: &str) -> Option<String> {
+    let mut depth = 0i32;
+    let mut escaped = false;
+    for c in s.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth < 0 {
+                    return None;
+                }
+            }
+            _ => (),
+        }
+    }
+    (depth == 0 && !s.is_empty()).then(|| s.to_string())
+}
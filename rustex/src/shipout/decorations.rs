@@ -0,0 +1,196 @@
+//! Page decorations (see [`Settings::decoration_filter`](crate::engine::Settings::decoration_filter)):
+//! a [`DecorationFilter`] is shown the boxes of every page shipped out - its header and footer,
+//! the material around its text (e.g. crop marks and watermarks added by shipout hooks) and the
+//! boxes in the text itself (e.g. lineno's line numbers) - together with the source range they
+//! were built from, and decides whether each of them is kept, dropped or marked with a class.
+
+use crate::engine::files::RusTeXFileSystem;
+use crate::engine::nodes::RusTeXNode;
+use crate::engine::Types;
+use crate::utils::{VecMap, VecSet};
+use tex_engine::engine::filesystem::FileSystem;
+use tex_engine::tex::nodes::boxes::TeXBox;
+use tex_engine::tex::nodes::horizontal::HNode;
+use tex_engine::tex::nodes::vertical::VNode;
+use tex_engine::tex::nodes::NodeTrait;
+use tex_engine::tex::numerics::Dim32;
+
+/// Where on the page a [`Decoration`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecorationKind {
+    /// above the box with the text of the page
+    Header,
+    /// below the box with the text of the page
+    Footer,
+    /// elsewhere outside of the text of the page, e.g. crop marks
+    Page,
+    /// any box in the text of the page
+    Text,
+}
+
+/// A box shown to a [`DecorationFilter`].
+#[derive(Debug, Clone)]
+pub struct Decoration<'a> {
+    pub kind: DecorationKind,
+    /// the file the box was started in
+    pub file: &'a str,
+    /// line and column of the start and end of the box
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    /// the dimensions of the box (in pt)
+    pub width: f32,
+    pub height: f32,
+    pub depth: f32,
+}
+
+/// What becomes of a [`Decoration`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Keep,
+    Drop,
+    /// keep the box, in an element with this class
+    Class(String),
+}
+
+/// Decides about the boxes of the pages during shipout (see [`Decoration`]). Implemented for
+/// all suitable closures, e.g.
+/// `|d: &Decoration| if d.file.ends_with("crop.sty") { Decision::Drop } else { Decision::Keep }`.
+pub trait DecorationFilter: Send {
+    fn filter(&mut self, decoration: &Decoration) -> Decision;
+}
+impl<F: FnMut(&Decoration) -> Decision + Send> DecorationFilter for F {
+    fn filter(&mut self, decoration: &Decoration) -> Decision {
+        self(decoration)
+    }
+}
+
+/// The [`DecorationFilter`] of a run, if any, while a page is shipped out.
+pub(crate) struct Decorations<'a> {
+    pub(crate) filter: &'a mut Option<Box<dyn DecorationFilter>>,
+    pub(crate) fs: &'a RusTeXFileSystem,
+}
+impl Decorations<'_> {
+    pub(crate) fn decide(&mut self, bx: &TeXBox<Types>, kind: DecorationKind) -> Decision {
+        let Some(filter) = self.filter.as_mut() else {
+            return Decision::Keep;
+        };
+        let (start, end) = match bx {
+            TeXBox::V { start, end, .. } | TeXBox::H { start, end, .. } => (start, end),
+        };
+        let pt = |d: Dim32| d.0 as f32 / 65536.0;
+        filter.filter(&Decoration {
+            kind,
+            file: self.fs.ref_str(start.file),
+            start: (start.line, start.column),
+            end: (end.line, end.column),
+            width: pt(bx.width()),
+            height: pt(bx.height()),
+            depth: pt(bx.depth()),
+        })
+    }
+
+    /// Applies the decisions about the boxes of kind `kind` in `nodes` - i.e. all of them, if
+    /// `nodes` is (part of) the text of a page, and otherwise those that do not contain it.
+    pub(crate) fn vlist(
+        &mut self,
+        nodes: Vec<VNode<Types>>,
+        kind: DecorationKind,
+    ) -> Vec<VNode<Types>> {
+        if self.filter.is_none() {
+            return nodes;
+        }
+        let mut ret = Vec::with_capacity(nodes.len());
+        for n in nodes {
+            match n {
+                VNode::Box(b) if kind != DecorationKind::Text && super::contains_page(&b) => {
+                    ret.push(VNode::Box(b))
+                }
+                VNode::Box(b) => match self.decide(&b, kind) {
+                    Decision::Keep => ret.push(VNode::Box(self.text(b, kind))),
+                    Decision::Drop => (),
+                    Decision::Class(cls) => {
+                        let b = self.text(b, kind);
+                        ret.extend(wrap(b, cls, VNode::Custom, VNode::Custom, VNode::Box))
+                    }
+                },
+                n => ret.push(n),
+            }
+        }
+        ret
+    }
+    fn hlist(&mut self, nodes: Vec<HNode<Types>>) -> Vec<HNode<Types>> {
+        let mut ret = Vec::with_capacity(nodes.len());
+        for n in nodes {
+            match n {
+                HNode::Box(b) => match self.decide(&b, DecorationKind::Text) {
+                    Decision::Keep => ret.push(HNode::Box(self.text(b, DecorationKind::Text))),
+                    Decision::Drop => (),
+                    Decision::Class(cls) => {
+                        let b = self.text(b, DecorationKind::Text);
+                        ret.extend(wrap(b, cls, HNode::Custom, HNode::Custom, HNode::Box))
+                    }
+                },
+                n => ret.push(n),
+            }
+        }
+        ret
+    }
+    /// Applies the decisions to the boxes in `bx`, if it is part of the text.
+    fn text(&mut self, bx: TeXBox<Types>, kind: DecorationKind) -> TeXBox<Types> {
+        if kind != DecorationKind::Text {
+            return bx;
+        }
+        match bx {
+            TeXBox::V {
+                info,
+                children,
+                start,
+                end,
+            } => TeXBox::V {
+                info,
+                children: self.vlist(children.into_vec(), kind).into(),
+                start,
+                end,
+            },
+            TeXBox::H {
+                info,
+                children,
+                start,
+                end,
+                preskip,
+            } => TeXBox::H {
+                info,
+                children: self.hlist(children.into_vec()).into(),
+                start,
+                end,
+                preskip,
+            },
+        }
+    }
+}
+
+/// `bx` in an annotation with the class `cls`.
+pub(crate) fn wrap<N>(
+    bx: TeXBox<Types>,
+    cls: String,
+    begin: impl FnOnce(RusTeXNode) -> N,
+    end: impl FnOnce(RusTeXNode) -> N,
+    node: impl FnOnce(TeXBox<Types>) -> N,
+) -> [N; 3] {
+    let (start, stop) = match &bx {
+        TeXBox::V { start, end, .. } | TeXBox::H { start, end, .. } => (*start, *end),
+    };
+    let mut classes = VecSet::default();
+    classes.insert(cls);
+    [
+        begin(RusTeXNode::AnnotBegin {
+            tag: None,
+            start,
+            attrs: VecMap::default(),
+            styles: VecMap::default(),
+            classes,
+        }),
+        node(bx),
+        end(RusTeXNode::AnnotEnd(stop)),
+    ]
+}
@@ -0,0 +1,249 @@
+//! Change highlighting between two versions of a document: the top-level blocks (paragraphs,
+//! tables, boxes... - color, font, link and annotation wrappers are looked through) of both
+//! outputs are identified by a hash of their HTML (without source references, which shift
+//! with every edit), aligned along their longest common subsequence, and the blocks of the new
+//! version that are not part of it are wrapped in a `div` with class `rustex-diff-inserted` or
+//! `rustex-diff-changed`. Where blocks of the old version were dropped, an empty
+//! `<div class="rustex-diff-deleted">` is inserted.
+
+use crate::shipout::state::{Common, ShipoutNodeT, ShipoutNodeV};
+use crate::utils::{VecMap, VecSet};
+use std::borrow::Cow;
+
+/// How a block of the new version differs from the old version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockChange {
+    /// the block takes the place of (at least) one block of the old version
+    Changed,
+    /// the block has no counterpart in the old version
+    Inserted,
+}
+impl BlockChange {
+    fn class(self) -> &'static str {
+        match self {
+            Self::Changed => "rustex-diff-changed",
+            Self::Inserted => "rustex-diff-inserted",
+        }
+    }
+}
+
+/// The number of blocks per kind of change.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiffSummary {
+    pub unchanged: usize,
+    pub changed: usize,
+    pub inserted: usize,
+    pub deleted: usize,
+}
+impl DiffSummary {
+    pub fn is_unchanged(&self) -> bool {
+        self.changed == 0 && self.inserted == 0 && self.deleted == 0
+    }
+}
+
+/// The result of aligning two sequences of block hashes.
+pub(crate) struct Alignment {
+    /// per block of the new version
+    pub(crate) changes: Vec<Option<BlockChange>>,
+    /// the number of blocks of the old version dropped before the block with the same
+    /// index in the new version; the last entry counts those at the very end
+    pub(crate) deleted: Vec<usize>,
+}
+impl Alignment {
+    pub(crate) fn summary(&self) -> DiffSummary {
+        let mut ret = DiffSummary {
+            deleted: self.deleted.iter().sum(),
+            ..DiffSummary::default()
+        };
+        for c in &self.changes {
+            match c {
+                None => ret.unchanged += 1,
+                Some(BlockChange::Changed) => ret.changed += 1,
+                Some(BlockChange::Inserted) => ret.inserted += 1,
+            }
+        }
+        ret
+    }
+}
+
+fn wrapper_children(n: &ShipoutNodeV) -> Option<&Vec<ShipoutNodeV>> {
+    match n {
+        ShipoutNodeV::Common(
+            Common::WithColor { children, .. }
+            | Common::WithFont { children, .. }
+            | Common::WithLink { children, .. }
+            | Common::WithAnnotation { children, .. }
+            | Common::WithMatrix { children, .. },
+        ) => Some(children),
+        _ => None,
+    }
+}
+
+/// Whether `n` is compared at all; vertical skips, literals and PDF destinations are not.
+fn is_block(n: &ShipoutNodeV) -> bool {
+    !matches!(
+        n,
+        ShipoutNodeV::KernSkip(_) | ShipoutNodeV::Common(Common::Literal(_) | Common::PDFDest(_))
+    )
+}
+
+/// Calls `f` on all blocks in `nodes`, in document order.
+pub(crate) fn blocks<'a>(nodes: &'a [ShipoutNodeV], f: &mut impl FnMut(&'a ShipoutNodeV)) {
+    for n in nodes {
+        match wrapper_children(n) {
+            Some(children) => blocks(children, f),
+            None if is_block(n) => f(n),
+            None => (),
+        }
+    }
+}
+
+/// Aligns the blocks of the new version with those of the old version.
+pub(crate) fn align(old: &[u64], new: &[u64]) -> Alignment {
+    let (prefix, suffix) = common_ends(old, new);
+    let o = &old[prefix..old.len() - suffix];
+    let n = &new[prefix..new.len() - suffix];
+    let lcs = lcs_table(o, n);
+    let width = n.len() + 1;
+
+    let mut changes = vec![None; new.len()];
+    let mut deleted = vec![0; new.len() + 1];
+    let (mut i, mut j) = (0, 0);
+    // blocks of the old and new version since the last common one
+    let (mut dropped, mut added) = (0, Vec::new());
+    let mut flush = |dropped: &mut usize, added: &mut Vec<usize>, at: usize| {
+        for (k, idx) in added.iter().enumerate() {
+            changes[prefix + idx] = Some(if k < *dropped {
+                BlockChange::Changed
+            } else {
+                BlockChange::Inserted
+            });
+        }
+        deleted[prefix + at] += dropped.saturating_sub(added.len());
+        *dropped = 0;
+        added.clear();
+    };
+    while i < o.len() || j < n.len() {
+        if i < o.len() && j < n.len() && o[i] == n[j] {
+            flush(&mut dropped, &mut added, j);
+            i += 1;
+            j += 1;
+        } else if j < n.len()
+            && (i == o.len() || lcs[i * width + j + 1] >= lcs[(i + 1) * width + j])
+        {
+            added.push(j);
+            j += 1;
+        } else {
+            dropped += 1;
+            i += 1;
+        }
+    }
+    flush(&mut dropped, &mut added, j);
+    Alignment { changes, deleted }
+}
+
+/// The lengths of the common prefix and (non-overlapping) suffix of `old` and `new`, which are
+/// part of every longest common subsequence; excluding them keeps the LCS table small for long
+/// documents with few changes.
+fn common_ends(old: &[u64], new: &[u64]) -> (usize, usize) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    (prefix, suffix)
+}
+
+/// `lcs[i * (n.len() + 1) + j]` = length of the longest common subsequence of `o[i..]` and
+/// `n[j..]`.
+fn lcs_table(o: &[u64], n: &[u64]) -> Vec<u32> {
+    let width = n.len() + 1;
+    let mut lcs = vec![0u32; (o.len() + 1) * width];
+    for i in (0..o.len()).rev() {
+        for j in (0..n.len()).rev() {
+            lcs[i * width + j] = if o[i] == n[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+    lcs
+}
+
+/// The pairs of indices of a longest common subsequence of `old` and `new`, in order.
+pub(crate) fn common(old: &[u64], new: &[u64]) -> Vec<(usize, usize)> {
+    let (prefix, suffix) = common_ends(old, new);
+    let o = &old[prefix..old.len() - suffix];
+    let n = &new[prefix..new.len() - suffix];
+    let lcs = lcs_table(o, n);
+    let width = n.len() + 1;
+    let mut ret = Vec::with_capacity(prefix + lcs[0] as usize + suffix);
+    ret.extend((0..prefix).map(|k| (k, k)));
+    let (mut i, mut j) = (0, 0);
+    while i < o.len() && j < n.len() {
+        if o[i] == n[j] {
+            ret.push((prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if lcs[i * width + j + 1] >= lcs[(i + 1) * width + j] {
+            j += 1;
+        } else {
+            i += 1;
+        }
+    }
+    ret.extend((0..suffix).map(|k| (old.len() - suffix + k, new.len() - suffix + k)));
+    ret
+}
+
+/// Wraps the blocks in `nodes` according to `alignment`; `idx` is the index of the next block.
+pub(crate) fn mark(nodes: &mut Vec<ShipoutNodeV>, alignment: &Alignment, idx: &mut usize) {
+    for mut n in std::mem::take(nodes) {
+        if let ShipoutNodeV::Common(
+            Common::WithColor { children, .. }
+            | Common::WithFont { children, .. }
+            | Common::WithLink { children, .. }
+            | Common::WithAnnotation { children, .. }
+            | Common::WithMatrix { children, .. },
+        ) = &mut n
+        {
+            mark(children, alignment, idx);
+            nodes.push(n);
+            continue;
+        }
+        if !is_block(&n) {
+            nodes.push(n);
+            continue;
+        }
+        if let Some(d) = alignment.deleted.get(*idx).filter(|d| **d > 0) {
+            nodes.push(deleted_marker(*d));
+        }
+        match alignment.changes.get(*idx).copied().flatten() {
+            Some(change) => {
+                let (uses_color, uses_font) = (n.uses_previous_color(), n.uses_previous_font());
+                let mut classes = VecSet::default();
+                classes.insert(Cow::Borrowed(change.class()));
+                nodes.push(ShipoutNodeV::Common(Common::WithAnnotation {
+                    attrs: VecMap::default(),
+                    styles: VecMap::default(),
+                    classes,
+                    tag: None,
+                    children: vec![n],
+                    uses_color,
+                    uses_font,
+                }));
+            }
+            None => nodes.push(n),
+        }
+        *idx += 1;
+    }
+}
+
+/// The marker for `num` blocks of the old version that have been dropped.
+pub(crate) fn deleted_marker(num: usize) -> ShipoutNodeV {
+    ShipoutNodeV::Common(Common::Literal(format!(
+        "<div class=\"rustex-diff-deleted\" data-rustex-deleted=\"{num}\"></div>"
+    )))
+}
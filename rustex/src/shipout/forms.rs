@@ -0,0 +1,348 @@
+//! Interactive form fields: hyperref's `\TextField`, `\CheckBox`, `\ChoiceMenu` and
+//! `\PushButton` end up as `\pdfannot`s with a `/Subtype/Widget` dictionary. Rather than being
+//! dropped like other annotations, they become the corresponding HTML form controls, with the
+//! field name and (default) value preserved.
+
+use crate::engine::Types;
+use crate::shipout::utils::{escape_html, px};
+use tex_engine::pdflatex::nodes::PDFAnnot;
+
+/// The field flags (`/Ff`) we care about; see section 12.7 of the PDF reference.
+const READ_ONLY: u32 = 1;
+const MULTILINE: u32 = 1 << 12;
+const PASSWORD: u32 = 1 << 13;
+const RADIO: u32 = 1 << 15;
+const PUSH_BUTTON: u32 = 1 << 16;
+const COMBO: u32 = 1 << 17;
+
+/// The HTML for the annotation, if it is a form field.
+pub(crate) fn field_html(annot: &PDFAnnot<Types>) -> Option<String> {
+    let dict = match Object::parse(&annot.content) {
+        Object::Dict(d) => d,
+        _ => return None,
+    };
+    let get = |key: &str| dict.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+    let kind = get("FT")?.as_name()?;
+    let flags = get("Ff").and_then(Object::as_num).unwrap_or(0);
+    let name = get("T").and_then(Object::as_str).unwrap_or_default();
+    let value = get("V").or(get("DV"));
+
+    let mut attrs = format!(
+        " class=\"rustex-form-field\" name=\"{}\"",
+        escape_html(name)
+    );
+    if let Some(tooltip) = get("TU").and_then(Object::as_str) {
+        attrs.push_str(&format!(" title=\"{}\"", escape_html(tooltip)));
+    }
+    if flags & READ_ONLY != 0 {
+        attrs.push_str(" disabled");
+    }
+    let mut style = String::new();
+    if let Some(w) = annot.width {
+        style.push_str(&format!("width:{}px;", px(w.0)));
+    }
+    if let Some(h) = annot.height {
+        let d = annot.depth.map(|d| d.0).unwrap_or(0);
+        style.push_str(&format!("height:{}px;", px(h.0 + d)));
+    }
+    if !style.is_empty() {
+        attrs.push_str(&format!(" style=\"{style}\""));
+    }
+
+    Some(match kind {
+        "Tx" => {
+            let value = value.and_then(Object::as_str).unwrap_or_default();
+            if flags & MULTILINE != 0 {
+                format!("<textarea{attrs}>{}</textarea>", escape_html(value))
+            } else {
+                let tp = if flags & PASSWORD != 0 {
+                    "password"
+                } else {
+                    "text"
+                };
+                if let Some(max) = get("MaxLen").and_then(Object::as_num) {
+                    attrs.push_str(&format!(" maxlength=\"{max}\""));
+                }
+                format!(
+                    "<input type=\"{tp}\"{attrs} value=\"{}\"/>",
+                    escape_html(value)
+                )
+            }
+        }
+        "Btn" if flags & PUSH_BUTTON != 0 => {
+            let caption = get("MK")
+                .and_then(|mk| mk.get("CA"))
+                .and_then(Object::as_str)
+                .unwrap_or(name);
+            format!(
+                "<input type=\"button\"{attrs} value=\"{}\"/>",
+                escape_html(caption)
+            )
+        }
+        "Btn" => {
+            let tp = if flags & RADIO != 0 {
+                "radio"
+            } else {
+                "checkbox"
+            };
+            let state = get("AS").or(value).and_then(Object::as_name);
+            if state.is_some_and(|s| s != "Off") {
+                attrs.push_str(" checked");
+            }
+            format!("<input type=\"{tp}\"{attrs}/>")
+        }
+        "Ch" => {
+            let selected = value.and_then(Object::as_str);
+            let options = get("Opt").and_then(Object::as_array).unwrap_or_default();
+            if flags & COMBO == 0 {
+                attrs.push_str(&format!(" size=\"{}\"", options.len().max(1)));
+            }
+            let mut ret = format!("<select{attrs}>");
+            for o in options {
+                // either `(value)` or `[(export value) (displayed value)]`
+                let (export, display) = match o {
+                    Object::Array(v) if v.len() == 2 => (v[0].as_str(), v[1].as_str()),
+                    o => (o.as_str(), o.as_str()),
+                };
+                let (Some(export), Some(display)) = (export, display) else {
+                    continue;
+                };
+                ret.push_str(&format!("<option value=\"{}\"", escape_html(export)));
+                if selected == Some(export) {
+                    ret.push_str(" selected");
+                }
+                ret.push_str(&format!(">{}</option>", escape_html(display)));
+            }
+            ret.push_str("</select>");
+            ret
+        }
+        _ => return None,
+    })
+}
+
+/// Just enough of PDF's object syntax for annotation dictionaries.
+#[derive(Debug, Clone, PartialEq)]
+enum Object {
+    Name(String),
+    String(String),
+    Number(f64),
+    Array(Vec<Object>),
+    Dict(Vec<(String, Object)>),
+    /// anything else (`true`, `null`, references, ...)
+    Other(String),
+}
+impl Object {
+    /// Parses the body of a dictionary (i.e. without the surrounding `<<`/`>>`), as given to
+    /// `\pdfannot`.
+    fn parse(s: &str) -> Self {
+        let mut chars = s.chars().peekable();
+        Self::Dict(Self::dict(&mut chars))
+    }
+    fn as_name(&self) -> Option<&str> {
+        match self {
+            Self::Name(s) => Some(s),
+            _ => None,
+        }
+    }
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+    fn as_num(&self) -> Option<u32> {
+        match self {
+            Self::Number(n) if *n >= 0.0 => Some(*n as u32),
+            _ => None,
+        }
+    }
+    fn as_array(&self) -> Option<&[Object]> {
+        match self {
+            Self::Array(v) => Some(v),
+            _ => None,
+        }
+    }
+    fn get(&self, key: &str) -> Option<&Object> {
+        match self {
+            Self::Dict(d) => d.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn ws(chars: &mut Chars) {
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '%' {
+                while chars.next_if(|c| *c != '\n' && *c != '\r').is_some() {}
+            } else {
+                break;
+            }
+        }
+    }
+    /// Key/value pairs up to `>>` or the end of the input.
+    fn dict(chars: &mut Chars) -> Vec<(String, Object)> {
+        let mut ret = Vec::new();
+        loop {
+            Self::ws(chars);
+            match chars.peek() {
+                None => return ret,
+                Some('>') => {
+                    chars.next();
+                    chars.next_if_eq(&'>');
+                    return ret;
+                }
+                _ => (),
+            }
+            match Self::object(chars) {
+                Some(Self::Name(key)) => {
+                    Self::ws(chars);
+                    match Self::object(chars) {
+                        Some(value) => ret.push((key, value)),
+                        None => return ret,
+                    }
+                }
+                Some(_) => (),
+                None => return ret,
+            }
+        }
+    }
+    fn object(chars: &mut Chars) -> Option<Self> {
+        Self::ws(chars);
+        match *chars.peek()? {
+            '/' => {
+                chars.next();
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !is_delimiter(*c)) {
+                    name.push(c);
+                }
+                Some(Self::Name(name))
+            }
+            '(' => {
+                chars.next();
+                Some(Self::String(decode(&Self::literal_string(chars))))
+            }
+            '<' => {
+                chars.next();
+                if chars.next_if_eq(&'<').is_some() {
+                    return Some(Self::Dict(Self::dict(chars)));
+                }
+                let mut digits = String::new();
+                for c in chars.by_ref() {
+                    match c {
+                        '>' => break,
+                        c if c.is_ascii_hexdigit() => digits.push(c),
+                        _ => (),
+                    }
+                }
+                if digits.len() % 2 == 1 {
+                    digits.push('0');
+                }
+                let bytes = (0..digits.len())
+                    .step_by(2)
+                    .filter_map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+                    .collect::<Vec<_>>();
+                Some(Self::String(decode(&bytes)))
+            }
+            '[' => {
+                chars.next();
+                let mut ret = Vec::new();
+                loop {
+                    Self::ws(chars);
+                    match chars.peek() {
+                        None => return Some(Self::Array(ret)),
+                        Some(']') => {
+                            chars.next();
+                            return Some(Self::Array(ret));
+                        }
+                        _ => ret.push(Self::object(chars)?),
+                    }
+                }
+            }
+            ']' | '>' | ')' | '{' | '}' => {
+                chars.next();
+                Some(Self::Other(String::new()))
+            }
+            _ => {
+                let mut s = String::new();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !is_delimiter(*c)) {
+                    s.push(c);
+                }
+                Some(match s.parse() {
+                    Ok(n) => Self::Number(n),
+                    Err(_) => Self::Other(s),
+                })
+            }
+        }
+    }
+    /// The bytes of a `(...)` string, after the opening parenthesis.
+    fn literal_string(chars: &mut Chars) -> Vec<u8> {
+        let mut ret = Vec::new();
+        let mut depth = 0;
+        let push = |ret: &mut Vec<u8>, c: char| {
+            let mut buf = [0; 4];
+            ret.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        };
+        while let Some(c) = chars.next() {
+            match c {
+                '(' => {
+                    depth += 1;
+                    ret.push(b'(');
+                }
+                ')' if depth == 0 => break,
+                ')' => {
+                    depth -= 1;
+                    ret.push(b')');
+                }
+                '\\' => match chars.next() {
+                    Some('n') => ret.push(b'\n'),
+                    Some('r') => ret.push(b'\r'),
+                    Some('t') => ret.push(b'\t'),
+                    Some('b') => ret.push(8),
+                    Some('f') => ret.push(12),
+                    Some('\r') => {
+                        chars.next_if_eq(&'\n');
+                    }
+                    Some('\n') => (),
+                    Some(c @ '0'..='7') => {
+                        let mut code = c.to_digit(8).unwrap();
+                        for _ in 0..2 {
+                            match chars.next_if(|c| c.is_digit(8)) {
+                                Some(c) => code = code * 8 + c.to_digit(8).unwrap(),
+                                None => break,
+                            }
+                        }
+                        ret.push(code as u8);
+                    }
+                    Some(c) => push(&mut ret, c),
+                    None => break,
+                },
+                c => push(&mut ret, c),
+            }
+        }
+        ret
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn is_delimiter(c: char) -> bool {
+    matches!(c, '(' | ')' | '<' | '>' | '[' | ']' | '{' | '}' | '/' | '%')
+}
+
+/// PDF text strings are either UTF-16BE with a byte order mark (as produced by hyperref's
+/// `unicode` option) or in PDFDocEncoding, which we treat as UTF-8 falling back to Latin-1.
+fn decode(bytes: &[u8]) -> String {
+    if let [0xFE, 0xFF, rest @ ..] = bytes {
+        let units = rest
+            .chunks(2)
+            .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]));
+        return char::decode_utf16(units)
+            .map(|c| c.unwrap_or('\u{FFFD}'))
+            .collect();
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.to_string(),
+        Err(_) => bytes.iter().map(|b| *b as char).collect(),
+    }
+}
@@ -1,9 +1,14 @@
-use crate::engine::extension::CSS;
+use crate::engine::extension::{Script, CSS};
+use crate::engine::nodes::BoxVisibility;
 use crate::engine::{Font, Types};
+use crate::shipout::captions::Caption;
+use crate::shipout::layout::{PageGeometry, PageLayout};
+use crate::shipout::positions::SourceMap;
 use crate::shipout::state::{
-    Alignment, CharOrStr, Common, FontData, ShipoutNodeH, ShipoutNodeHRow, ShipoutNodeM,
-    ShipoutNodeSVG, ShipoutNodeTable, ShipoutNodeV, SourceRef,
+    Alignment, CharOrStr, Common, FontData, LeadersBody, ShipoutNodeH, ShipoutNodeHRow,
+    ShipoutNodeM, ShipoutNodeSVG, ShipoutNodeTable, ShipoutNodeV, SourceRef,
 };
+use crate::shipout::utils::escape_html;
 use crate::utils::{Flex, Margin, VecMap, VecSet};
 use crate::RUSTEX_CSS_URL;
 use std::borrow::Cow;
@@ -11,9 +16,10 @@ use std::fmt::Write;
 use std::fmt::{Display, Formatter};
 use std::path::Path;
 use tex_engine::engine::fontsystem::Font as FontT;
-use tex_engine::pdflatex::nodes::{NumOrName, PDFColor, PDFImage};
+use tex_engine::pdflatex::nodes::{NumOrName, PDFColor, PDFImage, PDFXImage};
 use tex_engine::tex::nodes::boxes::{HBoxInfo, ToOrSpread, VBoxInfo};
 use tex_engine::tex::nodes::math::MathClass;
+use tex_engine::tex::nodes::LeaderType;
 use tex_engine::tex::numerics::TeXDimen;
 use tex_engine::utils::HMap;
 use tex_glyphs::fontstyles::FontModifier;
@@ -39,6 +45,13 @@ pub(crate) struct CompilationDisplay<'a, 'b> {
     pub(crate) image: &'a ImageOptions,
     pub(crate) f: &'a mut Formatter<'b>,
     pub(crate) font_info: bool,
+    pub(crate) script_level: u8,
+    /// see [`Settings::reflow`](crate::engine::Settings::reflow)
+    pub(crate) reflow: bool,
+    /// see [`Settings::fil_cap`](crate::engine::Settings::fil_cap), in sp
+    pub(crate) fil_cap: Option<i32>,
+    /// if set, rendered PDF images are collected here instead of being saved to disk
+    pub(crate) assets: Option<&'a mut Vec<(String, image::DynamicImage)>>,
 }
 
 macro_rules! node {
@@ -74,7 +87,10 @@ macro_rules! node {
         node!(@ATTRS $self;$tag; $($tk)*);
     };
     (@ATTRS $self:ident;$tag:expr; ref=$r:ident $($tk:tt)*) => {
-        if $self.sourcerefs { write!($self.f," data-rustex-sourceref=\"{}\"",$r)? }
+        if $self.sourcerefs {
+            if let Some(id) = $r.id { write!($self.f," id=\"{}\"",SourceMap::element_id(id))? }
+            write!($self.f," data-rustex-sourceref=\"{}\"",$r)?
+        }
         node!(@ATTRS $self;$tag; $($tk)*);
     };
     (@ATTRS $self:ident;$tag:expr; $a:literal=$v:expr; $($tk:tt)*) => {
@@ -194,15 +210,44 @@ macro_rules! node {
 }
 
 impl CompilationDisplay<'_, '_> {
+    /// The asset a PDF image is rendered to; one per page of the file.
+    fn raster_path(img: &PDFXImage<Types>) -> String {
+        match img.page {
+            Some(p) if p > 1 => format!("{}-{p}-rustex.png", img.filepath.display()),
+            _ => format!("{}-rustex.png", img.filepath.display()),
+        }
+    }
+    /// Saves a PDF image rendered to PNG next to the original file, unless it already
+    /// exists there, or records it in [`assets`](Self::assets).
+    fn pdf_image(&mut self, path: String, img: &image::DynamicImage) {
+        match &mut self.assets {
+            Some(assets) => {
+                if !assets.iter().any(|(p, _)| *p == path) {
+                    assets.push((path, img.clone()));
+                }
+            }
+            None => {
+                if !std::path::Path::new(&path).exists() {
+                    let _ = img.save_with_format(path, image::ImageFormat::Png);
+                }
+            }
+        }
+    }
     pub fn display(
         &mut self,
         metas: &[VecMap<String, String>],
         top: &VecMap<String, String>,
         css: &[CSS],
+        scripts: &[Script],
         page_width: i32,
+        geometry: PageGeometry,
+        layout: Option<&PageLayout>,
         out: &[ShipoutNodeV],
     ) -> std::fmt::Result {
-        self.f.write_str("<!DOCTYPE html>\n<html lang=\"en\"")?;
+        self.f.write_str("<!DOCTYPE html>\n<html")?;
+        if top.get("lang").is_none() {
+            self.f.write_str(" lang=\"en\"")?;
+        }
         for (k, v) in top.iter() {
             write!(self.f, " {}=\"{}\"", k, v)?;
         }
@@ -224,11 +269,21 @@ impl CompilationDisplay<'_, '_> {
                 CSS::File(s) => writeln!(
                     self.f,
                     "\t<link rel=\"stylesheet\" type=\"text/css\" href=\"{}\">",
-                    s
+                    escape_html(s)
                 )?,
                 CSS::Literal(s) => writeln!(self.f, "\t<style>\n{s}</style>")?,
             }
         }
+        for sc in scripts {
+            match sc {
+                Script::File(s) => writeln!(
+                    self.f,
+                    "\t<script src=\"{}\" defer></script>",
+                    escape_html(s)
+                )?,
+                Script::Literal(s) => writeln!(self.f, "\t<script>\n{s}</script>")?,
+            }
+        }
         let mut fonts = VecSet::default();
         for (name, d) in self.font_data.iter() {
             //.filter_map(|d| d.1.web.as_ref().map(|s| s.as_ref().ok()).flatten()) {
@@ -244,10 +299,18 @@ impl CompilationDisplay<'_, '_> {
         self.f.write_str("</head>")?;
         write!(
             self.f,
-            "<body class=\"rustex-body\" style=\"--rustex-text-width:{};--rustex-page-width:{};",
+            "<body class=\"rustex-body{}{}\" style=\"--rustex-text-width:{};--rustex-page-width:{};",
+            geometry
+                .body_class()
+                .map(|c| format!(" {c}"))
+                .unwrap_or_default(),
+            if self.reflow { " rustex-reflow" } else { "" },
             Self::dim_to_num(self.width),
             Self::dim_to_num(page_width)
         )?;
+        for (name, d) in layout.map(|l| l.0.as_slice()).unwrap_or_default() {
+            write!(self.f, "{name}:{};", Self::dim_to_num(*d))?;
+        }
         if let Some(font) = self.font_data.get(self.font.filename()) {
             if let Some((_, css)) = font.web.as_ref() {
                 write!(self.f, "font-family:{css};")?;
@@ -265,21 +328,34 @@ impl CompilationDisplay<'_, '_> {
         self.f.write_str("\n</body></html>")
     }
 
+    /// Displays a single top-level node, without the surrounding document.
+    pub(crate) fn display_fragment(&mut self, node: &ShipoutNodeV) -> std::fmt::Result {
+        self.do_v(node, true)
+    }
+
     #[inline(always)]
     fn dim_to_px(d: i32) -> f32 {
         d as f32 / 65536.0 * 1.5
     }
     #[inline(always)]
     fn dim_to_num(d: i32) -> String {
-        format!("{:.5}", Self::dim_to_px(d))
-            .trim_end_matches('0')
-            .trim_end_matches('.')
-            .to_string()
+        super::utils::px(d)
     }
     #[inline(always)]
     fn dim_to_string(d: i32) -> String {
         Self::dim_to_num(d) + "px"
     }
+    /// A horizontal dimension; with [`reflow`](Self::reflow) as a fraction of the current width.
+    fn hdim_to_string(&self, d: i32) -> String {
+        if self.reflow && self.width > 0 {
+            format!(
+                "calc(var(--rustex-curr-width) * {:.5})",
+                d as f32 / self.width as f32
+            )
+        } else {
+            Self::dim_to_string(d)
+        }
+    }
     #[inline(always)]
     fn mu_to_string(d: i32) -> String {
         format!("{:.5}", (d as f32) / 18.0 / 65536.0)
@@ -501,7 +577,7 @@ impl CompilationDisplay<'_, '_> {
                 children,
                 ..
             }) => {
-                node!(self <span class="rustex-pdfmatrix" style:"transform"=format_args!("matrix({scale},{rotate},{skewx},{skewy},0,0)"); {
+                node!(self <span class="rustex-pdfmatrix" style:"transform"=css_matrix(*scale,*rotate,*skewx,*skewy); {
                 for c in children { self.do_v(c,top)? }
             }/>);
                 Ok(())
@@ -515,31 +591,7 @@ impl CompilationDisplay<'_, '_> {
                 }
                 Ok(())
             }
-            ShipoutNodeV::KernSkip(m) => {
-                node!(self !<div class="rustex-vskip" style:{
-                if m.base.is_positive() {
-                    style!("height"=Self::dim_to_string(m.base))
-                } else {
-                    style!("margin-bottom"=Self::dim_to_string(m.base))
-                }
-                match m.stretch {
-                    Flex::Fil(_) | Flex::Fill(_) | Flex::Filll(_) =>
-                        style!("margin-top"="auto"),
-                    _ => ()
-                }
-            }/>);
-                match m.stretch {
-                    Flex::Fill(_) => {
-                        node!(self !<div class="rustex-vskip" style:"margin-top"="auto";/>);
-                    }
-                    Flex::Filll(_) => {
-                        node!(self !<div class="rustex-vskip" style:"margin-top"="auto";/>);
-                        node!(self !<div class="rustex-vskip" style:"margin-top"="auto";/>);
-                    }
-                    _ => (),
-                }
-                Ok(())
-            }
+            ShipoutNodeV::KernSkip(m) => self.do_vskip(m),
             ShipoutNodeV::Common(Common::VBox {
                 sref,
                 info: info @ VBoxInfo::VBox { .. },
@@ -559,10 +611,11 @@ impl CompilationDisplay<'_, '_> {
                 sref,
                 info: info @ HBoxInfo::HBox { .. },
                 children,
+                visibility,
                 ..
             }) => {
                 self.do_indent()?;
-                self.do_hbox(sref, info, children)
+                self.do_hbox(sref, info, children, *visibility)
             }
             ShipoutNodeV::HRule {
                 width,
@@ -599,7 +652,7 @@ impl CompilationDisplay<'_, '_> {
                 children,
                 alignment,
                 parskip: _,
-                line_skip: _,
+                line_skip,
                 left_skip,
                 right_skip,
                 width,
@@ -615,11 +668,12 @@ impl CompilationDisplay<'_, '_> {
                 };
                 node!(self <div class=cls;ref=sref style:{
                 if !left_skip.is_zero() {
-                    style!("margin-left"=Self::dim_to_string(left_skip.base))
+                    style!("margin-left"=self.hdim_to_string(left_skip.base))
                 }
                 if !right_skip.is_zero() {
-                    style!("margin-right"=Self::dim_to_string(right_skip.base))
+                    style!("margin-right"=self.hdim_to_string(right_skip.base))
                 }
+                style!("line-height"=Self::dim_to_string(line_skip.factor(&self.font) as i32));
                 match alignment {
                     Alignment::L => style!("text-align"="left"),
                     Alignment::C => style!("text-align"="center"),
@@ -636,8 +690,9 @@ impl CompilationDisplay<'_, '_> {
             ShipoutNodeV::HAlign {
                 children, num_cols, ..
             } => {
-                node!(self !<table class="rustex-halign" style:"--rustex-align-num"=num_cols;{
-                node!(self <tbody {
+                // `display:grid` drops the table semantics, so the roles are made explicit
+                node!(self !<table class="rustex-halign" "role"="table"; style:"--rustex-align-num"=num_cols;{
+                node!(self <tbody "role"="rowgroup"; {
                     for c in children {
                         self.do_row(c)?
                     }
@@ -649,6 +704,39 @@ impl CompilationDisplay<'_, '_> {
         }
     }
 
+    /// Vertical glue; all glue between two boxes has already been merged into one
+    /// [`KernSkip`](ShipoutNodeV::KernSkip), so that the spacers never collapse with each
+    /// other. The natural size becomes a spacer of that height (or a negative margin), infinite
+    /// stretch a spacer that grows into the free space of the enclosing box - weighted so that
+    /// `fill` all but suppresses `fil`, and `filll` `fill` - up to [`fil_cap`](Self::fil_cap).
+    fn do_vskip(&mut self, m: &Margin) -> std::fmt::Result {
+        let grow = match m.stretch {
+            Flex::Fil(_) => 1,
+            Flex::Fill(_) => 1_000,
+            Flex::Filll(_) => 1_000_000,
+            Flex::Fixed(_) => 0,
+        };
+        if m.base != 0 || grow == 0 {
+            node!(self !<div class="rustex-vskip" style:{
+                if m.base.is_positive() {
+                    style!("height"=Self::dim_to_string(m.base))
+                } else {
+                    style!("margin-bottom"=Self::dim_to_string(m.base))
+                }
+            }/>);
+        }
+        if grow != 0 {
+            let cap = self.fil_cap;
+            node!(self !<div class="rustex-vfil" style:{
+                style!("flex-grow"=grow);
+                if let Some(cap) = cap {
+                    style!("max-height"=Self::dim_to_string(cap))
+                }
+            }/>);
+        }
+        Ok(())
+    }
+
     fn do_h(&mut self, c: &ShipoutNodeH, escape: bool) -> std::fmt::Result {
         match c {
             ShipoutNodeH::Common(Common::WithColor {
@@ -695,7 +783,13 @@ impl CompilationDisplay<'_, '_> {
                 children,
                 ..
             }) => {
-                node!(self <span class="rustex-pdfmatrix" style:"transform"=format_args!("matrix({scale},{rotate},{skewx},{skewy},0,0)"); {
+                let depth = boxes_depth(children);
+                node!(self <span class="rustex-pdfmatrix" style:{
+                    style!("transform"=css_matrix(*scale,*rotate,*skewx,*skewy));
+                    if depth != 0 {
+                        style!("--rustex-depth"=Self::dim_to_string(depth))
+                    }
+                } {
                 for c in children { self.do_h(c,escape)? }
             }/>);
                 Ok(())
@@ -711,7 +805,7 @@ impl CompilationDisplay<'_, '_> {
             }
             ShipoutNodeH::KernSkip(m) => {
                 node!(self <div class="rustex-hskip" style:{
-                style!("margin-left"=Self::dim_to_string(m.base));
+                style!("margin-left"=self.hdim_to_string(m.base));
                 match m.stretch {
                     Flex::Fil(_) | Flex::Fill(_) | Flex::Filll(_) =>
                         style!("margin-right"="auto"),
@@ -737,14 +831,16 @@ impl CompilationDisplay<'_, '_> {
                 miny,
                 maxy,
                 children,
+                caption,
                 ..
-            }) => self.do_svg(sref, *minx, *maxx, *miny, *maxy, children),
+            }) => self.do_svg(sref, *minx, *maxx, *miny, *maxy, caption.as_ref(), children),
             ShipoutNodeH::Common(Common::HBox {
                 sref,
                 info: info @ HBoxInfo::HBox { .. },
                 children,
+                visibility,
                 ..
-            }) => self.do_hbox(sref, info, children),
+            }) => self.do_hbox(sref, info, children, *visibility),
             ShipoutNodeH::Common(Common::VBox {
                 sref,
                 info: info @ VBoxInfo::VBox { .. },
@@ -763,6 +859,16 @@ impl CompilationDisplay<'_, '_> {
                 depth,
             } => {
                 let wd = match width {
+                    // a strut: invisible, but its height and depth count for the line it is in
+                    Some(w) if w.0 <= 0 && (height.is_some() || depth.is_some()) => {
+                        let ht = height.map(|h| h.0).unwrap_or(0);
+                        let dp = depth.map(|d| d.0).unwrap_or(0);
+                        node!(self <div class="rustex-strut"
+                            style:"height"=Self::dim_to_string(ht + dp);
+                            {node!(self <div style:"margin-bottom"=Self::dim_to_string(-dp);{}/>)}
+                        />);
+                        return Ok(());
+                    }
                     Some(w) if w.0 <= 0 => return Ok(()),
                     Some(w) => w.0,
                     None => 26214,
@@ -825,24 +931,24 @@ impl CompilationDisplay<'_, '_> {
             }
             ShipoutNodeH::Math {
                 display,
+                fleqn,
+                axis_shift,
                 sref,
                 children,
                 ..
-            } => self.math_list(display, sref, children),
+            } => self.math_list(display, *fleqn, *axis_shift, sref, children),
 
             ShipoutNodeH::Img(img) => match (&self.image, &img.img) {
                 (ImageOptions::AsIs, PDFImage::PDF(imgfile)) => {
                     let width = img.width().0;
                     let height = img.height().0;
-                    let path = format!("{}-rustex.png", img.filepath.display());
+                    let path = Self::raster_path(img);
 
                     node!(self <img "src"=path;
                         "width"=Self::dim_to_string(width);
                         "height"=Self::dim_to_string(height);
                     />>);
-                    if !std::path::Path::new(&path).exists() {
-                        let _ = imgfile.save_with_format(path, image::ImageFormat::Png);
-                    }
+                    self.pdf_image(path, imgfile);
                     Ok(())
                 }
                 (ImageOptions::AsIs, _) => {
@@ -867,21 +973,79 @@ impl CompilationDisplay<'_, '_> {
             },
             ShipoutNodeH::Indent(i) => {
                 if *i != 0 {
-                    node!(self <div class="rustex-parindent" style:"margin-left"=Self::dim_to_string(*i);/>);
+                    node!(self <div class="rustex-parindent" style:"margin-left"=self.hdim_to_string(*i);/>);
                 }
                 Ok(())
             }
+            ShipoutNodeH::Leaders { skip, body } => self.do_leaders(skip, body, escape),
+            ShipoutNodeH::LineBreak if self.reflow => self.f.write_str(" "),
             ShipoutNodeH::LineBreak => self.f.write_str("<br/>"),
             ShipoutNodeH::MissingGlyph {
                 char, font_name, ..
             } => {
-                node!(self <span class="rustex-missing-glyph" "title"=format_args!("Missing Glyph {char} in {font_name}");/>);
+                node!(self <span class="rustex-missing-glyph" "title"=format_args!("Missing Glyph {char} in {font_name}"); {
+                    self.f.write_char('\u{FFFD}')?
+                }/>);
                 Ok(())
             }
             _ => todo!("{c:?}"),
         }
     }
 
+    /// Leaders as a fixed width container, clipping a rule or as many copies of their box as fit.
+    fn do_leaders(&mut self, skip: &Margin, body: &LeadersBody, escape: bool) -> std::fmt::Result {
+        if skip.base <= 0 {
+            return Ok(());
+        }
+        let color = self.color;
+        let justify = match body {
+            LeadersBody::Box {
+                tp: LeaderType::C, ..
+            } => Some("center"),
+            LeadersBody::Box {
+                tp: LeaderType::X, ..
+            } => Some("space-evenly"),
+            _ => None,
+        };
+        node!(self <div class="rustex-leaders" style:{
+            style!("--rustex-this-width"=self.hdim_to_string(skip.base));
+            if let Some(j) = justify {
+                style!("justify-content"=j);
+            }
+        } {
+            match body {
+                LeadersBody::Rule { height: None, depth } => {
+                    let font_size = self.font.get_at().0;
+                    node!(self <div class="rustex-leaders-rule" style:{
+                        style!("background"=color);
+                        style!("align-self"="stretch");
+                        style!("min-height"=Self::dim_to_string(font_size));
+                        if let Some(d) = depth {
+                            style!("margin-bottom"=Self::dim_to_string(-d.0));
+                        }
+                    }/>);
+                }
+                LeadersBody::Rule { height: Some(h), depth } => {
+                    let dp = depth.map_or(0, |d| d.0);
+                    node!(self <div class="rustex-leaders-rule" style:{
+                        style!("background"=color);
+                        style!("height"=Self::dim_to_string(h.0 + dp));
+                        style!("margin-bottom"=Self::dim_to_string(-dp));
+                    }/>);
+                }
+                LeadersBody::Box { width, children, .. } => {
+                    let copies = if *width > 0 { skip.base / *width } else { 0 };
+                    for _ in 0..copies {
+                        for c in children {
+                            self.do_h(c, escape)?
+                        }
+                    }
+                }
+            }
+        }/>);
+        Ok(())
+    }
+
     fn cls(cls: MathClass) -> &'static str {
         match cls {
             MathClass::Ord => "rustex-math-ord",
@@ -897,6 +1061,8 @@ impl CompilationDisplay<'_, '_> {
     fn math_list(
         &mut self,
         display: &Option<(Margin, Margin)>,
+        fleqn: Option<i32>,
+        axis_shift: Option<f32>,
         sref: &SourceRef,
         children: &[ShipoutNodeM],
     ) -> std::fmt::Result {
@@ -942,8 +1108,12 @@ impl CompilationDisplay<'_, '_> {
                 return Ok(());
             }
         }
-        let inner = move |s: &mut Self| {
-            node!(s <math class="rustex-math" ref=sref {
+        let inner = move |s: &mut Self, block: bool| {
+            node!(s <math class="rustex-math" ref=sref "display"=if block {"block"} else {"inline"}; style:{
+                if let Some(shift) = axis_shift {
+                    style!("vertical-align"=format_args!("{shift}em"))
+                }
+            } {
             node!(s !<mrow {
                 for c in children {
                    s.do_indent()?;s.do_math(c,None/*,false */)?
@@ -955,14 +1125,23 @@ impl CompilationDisplay<'_, '_> {
 
         match display {
             Some((above, below)) => {
-                node!(self <div class="rustex-display"
-                style:"margin-top"=Self::dim_to_string(above.base);
-                style:"margin-bottom"=Self::dim_to_string(below.base);{
-                inner(self)?
+                let cls = if fleqn.is_some() {
+                    "rustex-display rustex-display-fleqn"
+                } else {
+                    "rustex-display"
+                };
+                node!(self <div class=cls; style:{
+                    style!("margin-top"=Self::dim_to_string(above.base));
+                    style!("margin-bottom"=Self::dim_to_string(below.base));
+                    if let Some(indent) = fleqn {
+                        style!("padding-left"=Self::dim_to_string(indent))
+                    }
+                } {
+                inner(self,true)?
             }/>);
                 Ok(())
             }
-            None => inner(self),
+            None => inner(self, false),
         }
     }
 
@@ -1097,6 +1276,31 @@ impl CompilationDisplay<'_, '_> {
                     Ok(())
                 }
             }
+            ShipoutNodeM::Common(Common::HBox {
+                sref,
+                info: info @ HBoxInfo::HBox { .. },
+                children,
+                visibility: Some(visibility),
+                ..
+            }) => {
+                // hidden (or smashed) contents are still rendered, so that the browser reserves
+                // the space its own fonts need
+                match visibility {
+                    BoxVisibility::Phantom => node!(self !<mphantom {
+                        node!(self !<mtext class="rustex-math-escape" ref=sref {
+                            self.do_hbox(sref,info,children,None)?
+                        }/>);
+                    }/>),
+                    BoxVisibility::Smash => node!(self !<mpadded
+                        ?(info.assigned_height().map(|_| ("height", "0")))
+                        ?(info.assigned_depth().map(|_| ("depth", "0"))) {
+                        node!(self !<mtext class="rustex-math-escape" ref=sref {
+                            self.do_hbox(sref,info,children,Some(BoxVisibility::Smash))?
+                        }/>);
+                    }/>),
+                }
+                Ok(())
+            }
             ShipoutNodeM::Common(Common::HBox {
                 sref,
                 info: info @ HBoxInfo::HBox { .. },
@@ -1120,7 +1324,7 @@ impl CompilationDisplay<'_, '_> {
                     _ => ()
                 } */
             } {
-                self.do_hbox(sref,info,children)?
+                self.do_hbox(sref,info,children,None)?
             }/>);
                 //self.width = oldwd;
                 Ok(())
@@ -1218,6 +1422,15 @@ impl CompilationDisplay<'_, '_> {
                 depth,
             } => {
                 let wd = match width {
+                    Some(w) if w.0 <= 0 && (height.is_some() || depth.is_some()) => {
+                        self.do_indent()?;
+                        node!(self !<mspace
+                            "width"="0";
+                            "height"=Self::dim_to_string(height.unwrap_or_default().0);
+                            "depth"=Self::dim_to_string(depth.unwrap_or_default().0);
+                        />);
+                        return Ok(());
+                    }
                     Some(w) if w.0 <= 0 => return Ok(()),
                     Some(w) => w.0,
                     None => 26214,
@@ -1291,18 +1504,7 @@ impl CompilationDisplay<'_, '_> {
             ?(if *limits {Some(("displaystyle","true"))} else {None})
             {
                 self.do_math(base,None/*,cramped*/)?;
-                let at = self.font.get_at();
-                self.font.set_at(at.scale_float(0.7));
-                if sup.len() == 1 {
-                    self.do_math(sup.first().unwrap(),None/*,cramped*/)?;
-                } else {
-                    node!(self !<mrow {
-                        for c in sup.iter() {
-                            self.do_math(c,None/*,cramped*/)?;
-                        }
-                    }/>);
-                }
-                self.font.set_at(at);
+                self.do_script(sup)?;
             } />);
                 Ok(())
             }
@@ -1311,18 +1513,7 @@ impl CompilationDisplay<'_, '_> {
             ?(if *limits {Some(("displaystyle","true"))} else {None})
             {
                 self.do_math(base,None/*,cramped*/)?;
-                let at = self.font.get_at();
-                self.font.set_at(at.scale_float(0.7));
-                if sub.len() == 1 {
-                    self.do_math(sub.first().unwrap(),None/*,cramped*/)?;
-                } else {
-                    node!(self !<mrow {
-                        for c in sub.iter() {
-                            self.do_math(c,None/*,cramped*/)?;
-                        }
-                    }/>);
-                }
-                self.font.set_at(at);
+                self.do_script(sub)?;
             } />);
                 Ok(())
             }
@@ -1367,27 +1558,8 @@ impl CompilationDisplay<'_, '_> {
                 ?(if *limits {Some(("displaystyle","true"))} else {None})
             {
                 self.do_math(base,None/*,cramped*/)?;
-                let at = self.font.get_at();
-                self.font.set_at(at.scale_float(0.7));
-                if sub.len() == 1 {
-                    self.do_math(sub.first().unwrap(),None/*,cramped*/)?;
-                } else {
-                    node!(self !<mrow {
-                        for c in sub.iter() {
-                            self.do_math(c,None/*,cramped*/)?;
-                        }
-                    }/>);
-                }
-                if sup.len() == 1 {
-                    self.do_math(sup.first().unwrap(),None/*,cramped*/)?;
-                } else {
-                    node!(self !<mrow {
-                        for c in sup.iter() {
-                            self.do_math(c,None/*,cramped*/)?;
-                        }
-                    }/>);
-                }
-                self.font.set_at(at);
+                self.do_script(sub)?;
+                self.do_script(sup)?;
             } />);
                 Ok(())
             }
@@ -1509,15 +1681,13 @@ impl CompilationDisplay<'_, '_> {
                 (ImageOptions::AsIs, PDFImage::PDF(imgfile)) => {
                     let width = img.width().0;
                     let height = img.height().0;
-                    let path = format!("{}-rustex.png", img.filepath.display());
+                    let path = Self::raster_path(img);
 
                     node!(self <img "src"=path;
                         "width"=Self::dim_to_string(width);
                         "height"=Self::dim_to_string(height);
                     />>);
-                    if !std::path::Path::new(&path).exists() {
-                        let _ = imgfile.save_with_format(path, image::ImageFormat::Png);
-                    }
+                    self.pdf_image(path, imgfile);
                 }
                 (ImageOptions::AsIs, _) => {
                     let width = img.width().0;
@@ -1541,6 +1711,35 @@ impl CompilationDisplay<'_, '_> {
         }
     }
 
+    /// Sub-/superscripts: TeX only distinguishes script and scriptscript
+    /// style, so the font shrinks to 70% at level 1 and to 50% of the text
+    /// size at levels 2 and up; deeper nesting keeps the scriptscript size.
+    fn do_script(&mut self, children: &[ShipoutNodeM]) -> std::fmt::Result {
+        let at = self.font.get_at();
+        let old_level = self.script_level;
+        self.script_level = self.script_level.saturating_add(1);
+        let factor = match self.script_level {
+            1 => 0.7,
+            2 => 0.5 / 0.7,
+            _ => 1.0,
+        };
+        self.font.set_at(at.scale_float(factor));
+        let cls = if self.script_level == 1 {
+            "rustex-scriptlevel-1"
+        } else {
+            "rustex-scriptlevel-2"
+        };
+        let level = self.script_level.min(2);
+        node!(self !<mrow class=cls; "scriptlevel"=level; {
+            for c in children {
+                self.do_math(c,None/*,cramped*/)?;
+            }
+        }/>);
+        self.font.set_at(at);
+        self.script_level = old_level;
+        Ok(())
+    }
+
     fn do_row(&mut self, c: &ShipoutNodeTable) -> std::fmt::Result {
         match c {
             ShipoutNodeTable::Common(Common::Literal(s)) => self.f.write_str(s),
@@ -1572,7 +1771,7 @@ impl CompilationDisplay<'_, '_> {
                 Ok(())
             }
             ShipoutNodeTable::Row { children, .. } => {
-                node!(self !<tr {
+                node!(self !<tr "role"="row"; {
                 for c in children {
                     self.do_cell(c)?;
                 }
@@ -1580,7 +1779,7 @@ impl CompilationDisplay<'_, '_> {
                 Ok(())
             }
             ShipoutNodeTable::NoAlign { children, .. } => {
-                node!(self !<tr {node!(self <td class="rustex-noalign" {
+                node!(self !<tr "role"="row"; {node!(self <td class="rustex-noalign" "role"="cell"; {
                 for c in children {
                     self.do_v(c,false)?;
                 }
@@ -1614,7 +1813,7 @@ impl CompilationDisplay<'_, '_> {
             ShipoutNodeHRow::Cell {
                 children, spans, ..
             } => {
-                node!(self !<td class="rustex-halign-cell" style:{
+                node!(self !<td class="rustex-halign-cell" "role"="cell"; style:{
                 if *spans > 1 {
                     style!("grid-column"=format_args!("span {}",spans))
                 }
@@ -1636,8 +1835,15 @@ impl CompilationDisplay<'_, '_> {
         maxx: i32,
         miny: i32,
         maxy: i32,
+        caption: Option<&Caption>,
         children: &Vec<ShipoutNodeSVG>,
     ) -> std::fmt::Result {
+        if caption.is_some() {
+            self.attrs.insert("role".into(), "img".into());
+        }
+        if minx == maxx && miny == maxy {
+            return self.do_overlay(sref, caption, children);
+        }
         node!(self <div class="rustex-svg" {node!(self <svg ref=sref
             "width"=Self::dim_to_string(maxx - minx);
             "height"=Self::dim_to_string(maxy - miny);
@@ -1646,7 +1852,42 @@ impl CompilationDisplay<'_, '_> {
                 Self::dim_to_num(miny),
                 Self::dim_to_num(maxx - minx),
                 Self::dim_to_num(maxy - miny)
-            );{node!(self !<g "transform"=format_args!("translate(0,{})",Self::dim_to_num(maxy + miny)); {
+            );{
+            if let Some(caption) = caption {
+                let title = CharOrStr::Str(caption.title.as_str().into());
+                node!(self !<title {write!(self.f,"{}",Escaped(&title))?;}/>);
+                if let Some(desc) = &caption.desc {
+                    let desc = CharOrStr::Str(desc.as_str().into());
+                    node!(self !<desc {write!(self.f,"{}",Escaped(&desc))?;}/>);
+                }
+            }
+            node!(self !<g "transform"=format_args!("translate(0,{})",Self::dim_to_num(maxy + miny)); {
+                for c in children {
+                    self.do_svg_node(c)?
+                }
+            }/>);}
+        />)}/>);
+        Ok(())
+    }
+
+    /// A picture without a bounding box, i.e. one drawn entirely with `overlay` (typically
+    /// between positions remembered with `remember picture`): its origin is where it occurs, and
+    /// it is drawn on top of the surrounding elements without taking up any space. A `viewBox`
+    /// of size 0 would disable rendering, so coordinates are used as they are.
+    fn do_overlay(
+        &mut self,
+        sref: &SourceRef,
+        caption: Option<&Caption>,
+        children: &Vec<ShipoutNodeSVG>,
+    ) -> std::fmt::Result {
+        node!(self <div class="rustex-svg rustex-overlay" {node!(self <svg ref=sref
+            "width"="0";
+            "height"="0";{
+            if let Some(caption) = caption {
+                let title = CharOrStr::Str(caption.title.as_str().into());
+                node!(self !<title {write!(self.f,"{}",Escaped(&title))?;}/>);
+            }
+            node!(self !<g {
                 for c in children {
                     self.do_svg_node(c)?
                 }
@@ -1692,6 +1933,7 @@ impl CompilationDisplay<'_, '_> {
                 sref,
                 info: info @ HBoxInfo::HBox { .. },
                 children,
+                visibility,
                 ..
             }) => {
                 let wd = info.computed_width().map(|d| d.0).unwrap_or_default();
@@ -1703,7 +1945,7 @@ impl CompilationDisplay<'_, '_> {
                 style:"height"=Self::dim_to_string(ht);
                 style:"translate"=format_args!("0 {}",Self::dim_to_string(-ht));
                 {node!(self <div
-                    {self.do_hbox(sref,info,children)?;}
+                    {self.do_hbox(sref,info,children,*visibility)?;}
                 />)}
             />);
                 Ok(())
@@ -1717,6 +1959,7 @@ impl CompilationDisplay<'_, '_> {
         sref: &SourceRef,
         info: &HBoxInfo<Types>,
         children: &Vec<ShipoutNodeH>,
+        visibility: Option<BoxVisibility>,
     ) -> std::fmt::Result {
         let (wd, ht, bottom, to) = get_box_dims_h(info);
         let cls = match wd {
@@ -1725,6 +1968,10 @@ impl CompilationDisplay<'_, '_> {
             Some(i) if i == self.width => "rustex-hbox-container rustex-withwidth",
             _ => "rustex-hbox-container",
         };
+        let cls = match visibility {
+            Some(v) => Cow::Owned(format!("{cls} {}", v.class())),
+            None => Cow::Borrowed(cls),
+        };
         let inner = move |s: &mut Self| {
             node!(s <div class=cls; ref=sref style:{
                 if let Some(bottom) = bottom {
@@ -1757,7 +2004,7 @@ impl CompilationDisplay<'_, '_> {
                 }/>);
             }
             (None, Some(ml)) => {
-                node!(self <div class="rustex-moveleft" style:"--rustex-moveleft"=Self::dim_to_string(ml.0);{
+                node!(self <div class="rustex-moveleft" style:"--rustex-moveleft"=self.hdim_to_string(ml.0);{
                     inner(self)?
                 }/>);
             }
@@ -1819,7 +2066,7 @@ impl CompilationDisplay<'_, '_> {
                 }/>);
             }
             (None, Some(ml)) => {
-                node!(self <div class="rustex-moveleft" style:"--rustex-moveleft"=Self::dim_to_string(ml.0);{
+                node!(self <div class="rustex-moveleft" style:"--rustex-moveleft"=self.hdim_to_string(ml.0);{
                     inner(self)?
                 }/>);
             }
@@ -1886,7 +2133,7 @@ impl CompilationDisplay<'_, '_> {
                 }/>);
             }
             (None, Some(ml)) => {
-                node!(self <div class="rustex-moveleft" style:"--rustex-moveleft"=Self::dim_to_string(ml.0);{
+                node!(self <div class="rustex-moveleft" style:"--rustex-moveleft"=self.hdim_to_string(ml.0);{
                     inner(self)?
                 }/>);
             }
@@ -1971,6 +2218,36 @@ fn get_box_dims_v(
     (wd, ht, bottom, to)
 }
 
+/// The CSS `matrix(...)` for a `\pdfsetmatrix`; the y axis of PDF points up, the one of CSS down.
+fn css_matrix(scale: f32, rotate: f32, skewx: f32, skewy: f32) -> String {
+    // `0.0 - x` rather than `-x`, to avoid `-0`
+    format!(
+        "matrix({scale},{},{},{skewy},0,0)",
+        0.0 - rotate,
+        0.0 - skewx
+    )
+}
+
+/// The largest depth of the boxes among `children` - i.e. the distance between the bottom of
+/// a transformed `<span>` and the baseline that is the origin of the transformation.
+fn boxes_depth(children: &[ShipoutNodeH]) -> i32 {
+    children
+        .iter()
+        .filter_map(|c| match c {
+            ShipoutNodeH::Common(Common::HBox { info, .. }) => {
+                info.assigned_depth().or(info.computed_depth())
+            }
+            ShipoutNodeH::Common(Common::VBox { info, .. }) => {
+                info.assigned_depth().or(info.computed_depth())
+            }
+            _ => None,
+        })
+        .map(|d| d.0)
+        .max()
+        .unwrap_or(0)
+        .max(0)
+}
+
 fn get_box_dims_h(info: &HBoxInfo<Types>) -> (Option<i32>, Option<i32>, Option<i32>, Option<i32>) {
     let wd = match info.assigned_width() {
         Some(w) => Some(w.0),
@@ -2004,7 +2281,7 @@ fn get_box_dims_h(info: &HBoxInfo<Types>) -> (Option<i32>, Option<i32>, Option<i
     (wd, ht, bottom, to)
 }
 
-struct Escaped<'a>(&'a CharOrStr);
+pub(crate) struct Escaped<'a>(pub(crate) &'a CharOrStr);
 impl Display for Escaped<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         use CharOrStr::*;
@@ -0,0 +1,767 @@
+//! A structural export of the output (the "intermediate representation") and a structural diff
+//! of two such exports, to catch layout regressions between versions of RusTeX or of a document:
+//! [`CompilationResult::ir`](crate::engine::CompilationResult::ir) turns the shipped out nodes
+//! into a tree of [`IrNode`]s - paragraphs, boxes, tables, formulas, runs of text... - that can be
+//! saved with [`IrNode::to_json`] and read back with [`IrNode::from_json`], and [`diff`] reports
+//! the nodes that moved, changed, appeared or disappeared between two such trees, with their
+//! source references in both versions.
+//!
+//! Color and font wrappers are looked through; source references are not compared, since they
+//! shift with every edit.
+
+use crate::engine::nodes::BoxVisibility;
+use crate::json::{object, Json};
+use crate::shipout::state::{
+    Common, LeadersBody, ModifiedGlyph, ShipoutNodeH, ShipoutNodeHRow, ShipoutNodeM,
+    ShipoutNodeSVG, ShipoutNodeT, ShipoutNodeTable, ShipoutNodeV, SourceRef,
+};
+use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
+use tex_engine::tex::numerics::Dim32;
+
+/// A range in a source file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrSource {
+    pub file: String,
+    /// line and column of the start and end
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+impl Display for IrSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}#({};{}):({};{})",
+            self.file, self.start.0, self.start.1, self.end.0, self.end.1
+        )
+    }
+}
+impl From<&SourceRef> for IrSource {
+    fn from(s: &SourceRef) -> Self {
+        let (file, start, end) = s.span();
+        Self {
+            file: file.to_string(),
+            start,
+            end,
+        }
+    }
+}
+
+/// A node of the output tree.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IrNode {
+    /// e.g. `paragraph`, `vbox`, `hbox`, `table`, `row`, `cell`, `math`, `text`, `kern`, `rule`
+    pub kind: String,
+    /// the properties relevant for the layout, e.g. the width of a paragraph or the size of a kern
+    pub attrs: Vec<(String, String)>,
+    /// the characters of a `text` node, or the HTML of a `literal`
+    pub text: String,
+    pub source: Option<IrSource>,
+    pub children: Vec<IrNode>,
+}
+impl IrNode {
+    fn new(kind: &str, source: Option<&SourceRef>, children: Vec<IrNode>) -> Self {
+        Self {
+            kind: kind.to_string(),
+            source: source.map(IrSource::from),
+            children,
+            ..Self::default()
+        }
+    }
+    fn attr(mut self, key: &str, value: impl ToString) -> Self {
+        self.attrs.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// A hash of the node and its descendants, ignoring source references.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.kind.hash(&mut hasher);
+        self.attrs.hash(&mut hasher);
+        self.text.hash(&mut hasher);
+        for c in &self.children {
+            c.fingerprint().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    pub fn to_json(&self) -> String {
+        self.json().to_string()
+    }
+    fn json(&self) -> Json {
+        let mut ret = vec![("kind".to_string(), Json::from(self.kind.as_str()))];
+        if !self.attrs.is_empty() {
+            let attrs = self
+                .attrs
+                .iter()
+                .map(|(k, v)| (k.clone(), Json::from(v.as_str())))
+                .collect();
+            ret.push(("attrs".to_string(), Json::Object(attrs)));
+        }
+        if !self.text.is_empty() {
+            ret.push(("text".to_string(), self.text.as_str().into()));
+        }
+        if let Some(s) = &self.source {
+            let pos = |(l, c): (usize, usize)| Json::from(vec![l.into(), c.into()]);
+            let source = object([
+                ("file", s.file.as_str().into()),
+                ("start", pos(s.start)),
+                ("end", pos(s.end)),
+            ]);
+            ret.push(("source".to_string(), source));
+        }
+        if !self.children.is_empty() {
+            let children = self.children.iter().map(Self::json).collect::<Vec<_>>();
+            ret.push(("children".to_string(), children.into()));
+        }
+        Json::Object(ret)
+    }
+
+    /// Reads a tree written with [`IrNode::to_json`]; returns `None` if it is malformed.
+    pub fn from_json(s: &str) -> Option<Self> {
+        Self::from_json_value(&Json::parse(s)?)
+    }
+    fn from_json_value(j: &Json) -> Option<Self> {
+        let attrs = match j.get("attrs") {
+            None => Vec::new(),
+            Some(Json::Object(v)) => v
+                .iter()
+                .map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                .collect::<Option<_>>()?,
+            Some(_) => return None,
+        };
+        let text = match j.get("text") {
+            None => String::new(),
+            Some(t) => t.as_str()?.to_string(),
+        };
+        let source = match j.get("source") {
+            None => None,
+            Some(s) => {
+                let pos = |k: &str| {
+                    let a = s.get(k)?.as_array()?;
+                    Some((a.first()?.as_usize()?, a.get(1)?.as_usize()?))
+                };
+                Some(IrSource {
+                    file: s.get("file")?.as_str()?.to_string(),
+                    start: pos("start")?,
+                    end: pos("end")?,
+                })
+            }
+        };
+        let children = match j.get("children") {
+            None => Vec::new(),
+            Some(c) => c
+                .as_array()?
+                .iter()
+                .map(Self::from_json_value)
+                .collect::<Option<_>>()?,
+        };
+        Some(Self {
+            kind: j.get("kind")?.as_str()?.to_string(),
+            attrs,
+            text,
+            source,
+            children,
+        })
+    }
+}
+
+/// The tree of a whole document.
+pub(crate) fn document(nodes: &[ShipoutNodeV]) -> IrNode {
+    IrNode::new("document", None, list(nodes))
+}
+
+trait ToIr {
+    fn ir(&self, out: &mut Vec<IrNode>);
+}
+fn list<T: ToIr>(nodes: &[T]) -> Vec<IrNode> {
+    let mut ret = Vec::new();
+    for n in nodes {
+        n.ir(&mut ret);
+    }
+    ret
+}
+/// Appends `s` to the preceding `text` node, if any.
+fn text(out: &mut Vec<IrNode>, s: &str) {
+    match out.last_mut() {
+        Some(n) if n.kind == "text" => n.text.push_str(s),
+        _ => out.push(IrNode {
+            kind: "text".to_string(),
+            text: s.to_string(),
+            ..IrNode::default()
+        }),
+    }
+}
+fn dim(d: i32) -> String {
+    Dim32(d).to_string()
+}
+fn rule(width: Option<Dim32>, height: Option<Dim32>, depth: Option<Dim32>) -> IrNode {
+    let d = |d: Option<Dim32>| d.map_or_else(|| "*".to_string(), |d| d.to_string());
+    IrNode::new("rule", None, Vec::new())
+        .attr("width", d(width))
+        .attr("height", d(height))
+        .attr("depth", d(depth))
+}
+fn delimiter(out: &mut Vec<IrNode>, d: &Result<ModifiedGlyph, (Box<str>, u8, Box<str>)>) {
+    match d {
+        Ok(g) => text(out, &g.to_string()),
+        Err((name, ..)) => text(out, name),
+    }
+}
+
+impl<T: ShipoutNodeT + ToIr> ToIr for Common<T> {
+    fn ir(&self, out: &mut Vec<IrNode>) {
+        match self {
+            Common::Literal(s) => out.push(IrNode {
+                kind: "literal".to_string(),
+                text: s.clone(),
+                ..IrNode::default()
+            }),
+            Common::WithColor { children, .. } | Common::WithFont { children, .. } => {
+                for c in children {
+                    c.ir(out)
+                }
+            }
+            Common::WithLink { href, children, .. } => {
+                out.push(IrNode::new("link", None, list(children)).attr("href", href))
+            }
+            Common::WithAnnotation {
+                styles,
+                classes,
+                tag,
+                children,
+                ..
+            } => {
+                let mut n = IrNode::new("annotation", None, list(children));
+                if let Some(tag) = tag {
+                    n = n.attr("tag", tag);
+                }
+                if !classes.inner.is_empty() {
+                    n = n.attr("class", classes.inner.join(" "));
+                }
+                for (k, v) in styles.iter() {
+                    n = n.attr(&format!("style:{k}"), v);
+                }
+                out.push(n)
+            }
+            Common::WithMatrix {
+                scale,
+                rotate,
+                skewx,
+                skewy,
+                children,
+                ..
+            } => out.push(
+                IrNode::new("transform", None, list(children))
+                    .attr("matrix", format!("{scale} {rotate} {skewx} {skewy}")),
+            ),
+            Common::PDFDest(d) => {
+                out.push(IrNode::new("anchor", None, Vec::new()).attr("name", d.clone().as_name()))
+            }
+            Common::VBox { sref, children, .. } => {
+                out.push(IrNode::new("vbox", Some(sref), list(children)))
+            }
+            Common::HBox {
+                preskip,
+                sref,
+                children,
+                visibility,
+                ..
+            } => {
+                let mut n = IrNode::new("hbox", Some(sref), list(children));
+                if let Some(p) = preskip {
+                    n = n.attr("preskip", dim(p.base));
+                }
+                match visibility {
+                    Some(BoxVisibility::Phantom) => n = n.attr("visibility", "phantom"),
+                    Some(BoxVisibility::Smash) => n = n.attr("visibility", "smash"),
+                    None => (),
+                }
+                out.push(n)
+            }
+            Common::SVG {
+                sref,
+                minx,
+                maxx,
+                miny,
+                maxy,
+                children,
+                ..
+            } => out.push(
+                IrNode::new("svg", Some(sref), list(children))
+                    .attr("width", dim(maxx - minx))
+                    .attr("height", dim(maxy - miny)),
+            ),
+        }
+    }
+}
+
+impl ToIr for ShipoutNodeV {
+    fn ir(&self, out: &mut Vec<IrNode>) {
+        match self {
+            ShipoutNodeV::KernSkip(m) => {
+                out.push(IrNode::new("kern", None, Vec::new()).attr("size", dim(m.base)))
+            }
+            ShipoutNodeV::HRule {
+                width,
+                height,
+                depth,
+            } => out.push(rule(*width, *height, *depth)),
+            ShipoutNodeV::Paragraph {
+                children,
+                alignment,
+                width,
+                sref,
+                ..
+            } => out.push(
+                IrNode::new("paragraph", Some(sref), list(children))
+                    .attr("width", dim(*width))
+                    .attr("align", format!("{alignment:?}")),
+            ),
+            ShipoutNodeV::HAlign {
+                children, num_cols, ..
+            } => out.push(IrNode::new("table", None, list(children)).attr("columns", num_cols)),
+            ShipoutNodeV::Common(c) => c.ir(out),
+        }
+    }
+}
+
+impl ToIr for ShipoutNodeH {
+    fn ir(&self, out: &mut Vec<IrNode>) {
+        match self {
+            ShipoutNodeH::KernSkip(m) => {
+                out.push(IrNode::new("kern", None, Vec::new()).attr("size", dim(m.base)))
+            }
+            ShipoutNodeH::VRule {
+                width,
+                height,
+                depth,
+            } => out.push(rule(*width, *height, *depth)),
+            ShipoutNodeH::LineBreak => out.push(IrNode::new("linebreak", None, Vec::new())),
+            ShipoutNodeH::Common(c) => c.ir(out),
+            ShipoutNodeH::Char(c) => text(out, &c.to_string()),
+            ShipoutNodeH::MissingGlyph { name, .. } => text(out, name),
+            ShipoutNodeH::Space => text(out, " "),
+            ShipoutNodeH::Indent(i) => {
+                out.push(IrNode::new("indent", None, Vec::new()).attr("size", dim(*i)))
+            }
+            ShipoutNodeH::Math {
+                sref,
+                display,
+                children,
+                ..
+            } => {
+                let mut n = IrNode::new("math", Some(sref), list(children));
+                if display.is_some() {
+                    n = n.attr("display", true);
+                }
+                out.push(n)
+            }
+            ShipoutNodeH::Img(img) => out.push(
+                IrNode::new("image", None, Vec::new())
+                    .attr("file", img.filepath.display())
+                    .attr("width", dim(img.width().0))
+                    .attr("height", dim(img.height().0)),
+            ),
+            ShipoutNodeH::Leaders { skip, body } => {
+                let children = match body {
+                    LeadersBody::Rule { height, depth } => vec![rule(None, *height, *depth)],
+                    LeadersBody::Box { children, .. } => list(children),
+                };
+                out.push(IrNode::new("leaders", None, children).attr("width", dim(skip.base)))
+            }
+        }
+    }
+}
+
+impl ToIr for ShipoutNodeTable {
+    fn ir(&self, out: &mut Vec<IrNode>) {
+        match self {
+            ShipoutNodeTable::Common(c) => c.ir(out),
+            ShipoutNodeTable::Row { sref, children, .. } => {
+                out.push(IrNode::new("row", Some(sref), list(children)))
+            }
+            ShipoutNodeTable::NoAlign { children, .. } => {
+                out.push(IrNode::new("noalign", None, list(children)))
+            }
+        }
+    }
+}
+
+impl ToIr for ShipoutNodeHRow {
+    fn ir(&self, out: &mut Vec<IrNode>) {
+        match self {
+            ShipoutNodeHRow::Common(c) => c.ir(out),
+            ShipoutNodeHRow::Cell {
+                sref,
+                spans,
+                children,
+                ..
+            } => {
+                let mut n = IrNode::new("cell", Some(sref), list(children));
+                if *spans > 1 {
+                    n = n.attr("span", spans);
+                }
+                out.push(n)
+            }
+        }
+    }
+}
+
+impl ToIr for ShipoutNodeM {
+    fn ir(&self, out: &mut Vec<IrNode>) {
+        match self {
+            ShipoutNodeM::MissingGlyph { name, .. } => text(out, name),
+            ShipoutNodeM::Glyph { char, .. } => text(out, &char.to_string()),
+            ShipoutNodeM::Space => text(out, " "),
+            ShipoutNodeM::Phantom {
+                width,
+                height,
+                depth,
+            } => out.push(
+                IrNode::new("phantom", None, Vec::new())
+                    .attr("width", dim(*width))
+                    .attr("height", dim(*height))
+                    .attr("depth", dim(*depth)),
+            ),
+            ShipoutNodeM::VRule {
+                width,
+                height,
+                depth,
+            } => out.push(rule(*width, *height, *depth)),
+            ShipoutNodeM::MSkip { base, mu } => {
+                let size = if *mu {
+                    format!("{}mu", *base as f32 / 65536.0)
+                } else {
+                    dim(*base)
+                };
+                out.push(IrNode::new("kern", None, Vec::new()).attr("size", size))
+            }
+            ShipoutNodeM::Common(c) => c.ir(out),
+            ShipoutNodeM::Img(img) => out.push(
+                IrNode::new("image", None, Vec::new())
+                    .attr("file", img.filepath.display())
+                    .attr("width", dim(img.width().0))
+                    .attr("height", dim(img.height().0)),
+            ),
+            ShipoutNodeM::Over {
+                sref, top, bottom, ..
+            } => out.push(IrNode::new(
+                "fraction",
+                Some(sref),
+                vec![
+                    IrNode::new("numerator", None, list(top)),
+                    IrNode::new("denominator", None, list(bottom)),
+                ],
+            )),
+            ShipoutNodeM::Radical { children, .. } => {
+                out.push(IrNode::new("radical", None, list(children)))
+            }
+            ShipoutNodeM::WithClass { children, .. } => {
+                for c in children {
+                    c.ir(out)
+                }
+            }
+            ShipoutNodeM::Underline { children, .. } => {
+                out.push(IrNode::new("underline", None, list(children)))
+            }
+            ShipoutNodeM::Overline { children, .. } => {
+                out.push(IrNode::new("overline", None, list(children)))
+            }
+            ShipoutNodeM::LeftRight {
+                sref,
+                left,
+                right,
+                children,
+                ..
+            } => {
+                let mut ch = Vec::new();
+                if let Some(l) = left {
+                    delimiter(&mut ch, l);
+                }
+                for c in children {
+                    c.ir(&mut ch)
+                }
+                if let Some(r) = right {
+                    delimiter(&mut ch, r);
+                }
+                out.push(IrNode::new("delimited", Some(sref), ch))
+            }
+            ShipoutNodeM::Middle(d) => delimiter(out, d),
+            ShipoutNodeM::Accent {
+                accent, children, ..
+            } => {
+                let mut ch = list(children);
+                delimiter(&mut ch, accent);
+                out.push(IrNode::new("accent", None, ch))
+            }
+            ShipoutNodeM::Sub { base, sub, .. } => {
+                base.ir(out);
+                out.push(IrNode::new("sub", None, list(sub)))
+            }
+            ShipoutNodeM::Sup { base, sup, .. } => {
+                base.ir(out);
+                out.push(IrNode::new("sup", None, list(sup)))
+            }
+            ShipoutNodeM::SubSup { base, sub, sup, .. } => {
+                base.ir(out);
+                out.push(IrNode::new("sub", None, list(sub)));
+                out.push(IrNode::new("sup", None, list(sup)))
+            }
+            ShipoutNodeM::VCenter {
+                sref,
+                width,
+                children,
+                ..
+            } => out.push(
+                IrNode::new("vcenter", Some(sref), list(children)).attr("width", dim(*width)),
+            ),
+        }
+    }
+}
+
+impl ToIr for ShipoutNodeSVG {
+    fn ir(&self, out: &mut Vec<IrNode>) {
+        match self {
+            ShipoutNodeSVG::Common(c) => c.ir(out),
+            ShipoutNodeSVG::SVGNode {
+                tag,
+                attrs,
+                children,
+                ..
+            } => {
+                let mut n = IrNode::new(&format!("svg:{tag}"), None, list(children));
+                for (k, v) in attrs.iter() {
+                    n = n.attr(k, v);
+                }
+                out.push(n)
+            }
+        }
+    }
+}
+
+/// How a node differs between two versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrChangeKind {
+    /// the node is unchanged, but somewhere else
+    Moved,
+    /// the node takes the place of a node of the same kind with different properties or contents
+    Changed,
+    /// the node has no counterpart in the old version
+    Inserted,
+    /// the node has no counterpart in the new version
+    Deleted,
+}
+impl IrChangeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Moved => "moved",
+            Self::Changed => "changed",
+            Self::Inserted => "inserted",
+            Self::Deleted => "deleted",
+        }
+    }
+}
+
+/// Where a node is in one version of the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrLocation {
+    /// the kinds and positions among their siblings of the node and its ancestors, e.g.
+    /// `/vbox[0]/paragraph[3]`
+    pub path: String,
+    /// the source reference of the node or, if it has none, of its closest ancestor with one
+    pub source: Option<IrSource>,
+}
+impl IrLocation {
+    fn child(&self, node: &IrNode, idx: usize) -> Self {
+        Self {
+            path: format!("{}/{}[{idx}]", self.path, node.kind),
+            source: node.source.clone().or_else(|| self.source.clone()),
+        }
+    }
+    fn json(&self) -> Json {
+        let source = match &self.source {
+            Some(s) => s.to_string().into(),
+            None => Json::Null,
+        };
+        object([("path", self.path.as_str().into()), ("source", source)])
+    }
+}
+impl Display for IrLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            Some(s) => write!(f, "{} ({s})", self.path),
+            None => f.write_str(&self.path),
+        }
+    }
+}
+
+/// A difference between two trees, as found by [`diff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrChange {
+    pub kind: IrChangeKind,
+    /// the kind of the node
+    pub node: String,
+    /// where the node is in the old version; `None` if it was inserted
+    pub old: Option<IrLocation>,
+    /// where the node is in the new version; `None` if it was deleted
+    pub new: Option<IrLocation>,
+    fingerprint: u64,
+}
+impl IrChange {
+    pub fn to_json(&self) -> Json {
+        let loc = |l: &Option<IrLocation>| l.as_ref().map_or(Json::Null, IrLocation::json);
+        object([
+            ("kind", self.kind.name().into()),
+            ("node", self.node.as_str().into()),
+            ("old", loc(&self.old)),
+            ("new", loc(&self.new)),
+        ])
+    }
+}
+impl Display for IrChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.kind.name(), self.node)?;
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(f, ": {old} -> {new}"),
+            (Some(l), None) | (None, Some(l)) => write!(f, ": {l}"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// The differences between two trees, in document order (of the new version, where nodes were
+/// inserted or changed). The children of two nodes are aligned along the longest common
+/// subsequence of their fingerprints; of the remaining ones, nodes of the same kind are paired
+/// up and compared recursively (if they only differ in their children) or reported as changed,
+/// and a deleted node with the same fingerprint as an inserted one is reported as moved.
+pub fn diff(old: &IrNode, new: &IrNode) -> Vec<IrChange> {
+    let root = |n: &IrNode| IrLocation {
+        path: String::new(),
+        source: n.source.clone(),
+    };
+    let mut ret = Vec::new();
+    compare(old, root(old), new, root(new), &mut ret);
+    moves(ret)
+}
+
+fn compare(
+    old: &IrNode,
+    old_at: IrLocation,
+    new: &IrNode,
+    new_at: IrLocation,
+    ret: &mut Vec<IrChange>,
+) {
+    if old.fingerprint() == new.fingerprint() {
+        return;
+    }
+    if old.kind != new.kind
+        || old.attrs != new.attrs
+        || old.text != new.text
+        || old.children.is_empty()
+        || new.children.is_empty()
+    {
+        return ret.push(IrChange {
+            kind: IrChangeKind::Changed,
+            node: new.kind.clone(),
+            old: Some(old_at),
+            new: Some(new_at),
+            fingerprint: new.fingerprint(),
+        });
+    }
+    let fo = old
+        .children
+        .iter()
+        .map(IrNode::fingerprint)
+        .collect::<Vec<_>>();
+    let fn_ = new
+        .children
+        .iter()
+        .map(IrNode::fingerprint)
+        .collect::<Vec<_>>();
+    let mut common = super::diff::common(&fo, &fn_);
+    common.push((old.children.len(), new.children.len()));
+    let (mut i, mut j) = (0, 0);
+    for (ci, cj) in common {
+        // pair up the nodes in between by kind, in order
+        let mut next = j;
+        for (oi, o) in old.children[i..ci]
+            .iter()
+            .enumerate()
+            .map(|(k, o)| (i + k, o))
+        {
+            let old_child = old_at.child(o, oi);
+            match (next..cj).find(|nj| new.children[*nj].kind == o.kind) {
+                Some(nj) => {
+                    inserted(new, &new_at, next..nj, &fn_, ret);
+                    let n = &new.children[nj];
+                    compare(o, old_child, n, new_at.child(n, nj), ret);
+                    next = nj + 1;
+                }
+                None => ret.push(IrChange {
+                    kind: IrChangeKind::Deleted,
+                    node: o.kind.clone(),
+                    old: Some(old_child),
+                    new: None,
+                    fingerprint: fo[oi],
+                }),
+            }
+        }
+        inserted(new, &new_at, next..cj, &fn_, ret);
+        (i, j) = (ci + 1, cj + 1);
+    }
+}
+
+fn inserted(
+    new: &IrNode,
+    new_at: &IrLocation,
+    range: std::ops::Range<usize>,
+    fingerprints: &[u64],
+    ret: &mut Vec<IrChange>,
+) {
+    for nj in range {
+        let n = &new.children[nj];
+        ret.push(IrChange {
+            kind: IrChangeKind::Inserted,
+            node: n.kind.clone(),
+            old: None,
+            new: Some(new_at.child(n, nj)),
+            fingerprint: fingerprints[nj],
+        })
+    }
+}
+
+/// Merges deleted nodes with identical inserted ones (other than runs of text, kerns and the
+/// like, which are too common to be told apart) into moves.
+fn moves(mut changes: Vec<IrChange>) -> Vec<IrChange> {
+    let mut merged = vec![false; changes.len()];
+    for i in 0..changes.len() {
+        let c = &changes[i];
+        if c.kind != IrChangeKind::Deleted
+            || matches!(
+                c.node.as_str(),
+                "text" | "kern" | "linebreak" | "indent" | "anchor"
+            )
+        {
+            continue;
+        }
+        let fingerprint = c.fingerprint;
+        if let Some(j) = (0..changes.len()).find(|j| {
+            !merged[*j]
+                && changes[*j].kind == IrChangeKind::Inserted
+                && changes[*j].fingerprint == fingerprint
+        }) {
+            merged[j] = true;
+            changes[i].kind = IrChangeKind::Moved;
+            changes[i].new = changes[j].new.take();
+        }
+    }
+    let mut merged = merged.into_iter();
+    changes.retain(|_| !merged.next().unwrap_or_default());
+    changes
+}
+
+/// The changes as a JSON array (see [`IrChange::to_json`]).
+pub fn changes_to_json(changes: &[IrChange]) -> String {
+    Json::Array(changes.iter().map(IrChange::to_json).collect()).to_string()
+}
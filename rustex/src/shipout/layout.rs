@@ -0,0 +1,140 @@
+//! The layout registers in effect when a page is shipped out, exposed as CSS custom properties
+//! on the page container (`.rustex-body`), so that stylesheets can align custom elements with
+//! the TeX geometry - e.g. `margin-left: calc(var(--rustex-parindent) * 1px)`. As with
+//! `--rustex-text-width` and `--rustex-page-width`, values are unitless numbers of pixels.
+//!
+//! How the page geometry (as set up by e.g. the `geometry` package) maps to the HTML output is
+//! configured with [`PageGeometry`].
+
+use crate::engine::Refs;
+use crate::placement::{primitive_dim, ONE_INCH};
+use crate::shipout::utils::px;
+use crate::utils::VecMap;
+use tex_engine::commands::primitives::PRIMITIVES;
+use tex_engine::commands::TeXCommand;
+use tex_engine::engine::state::State;
+use tex_engine::tex::tokens::control_sequences::CSHandler;
+
+/// LaTeX's page layout parameters (which are plain `\dimen` registers), with their CSS names.
+const LATEX_DIMENS: &[(&str, &str)] = &[
+    ("textheight", "--rustex-text-height"),
+    ("paperwidth", "--rustex-paper-width"),
+    ("paperheight", "--rustex-paper-height"),
+    ("columnwidth", "--rustex-column-width"),
+    ("columnsep", "--rustex-column-sep"),
+    ("oddsidemargin", "--rustex-odd-side-margin"),
+    ("evensidemargin", "--rustex-even-side-margin"),
+    ("topmargin", "--rustex-top-margin"),
+    ("headheight", "--rustex-head-height"),
+    ("headsep", "--rustex-head-sep"),
+    ("footskip", "--rustex-foot-skip"),
+    ("marginparwidth", "--rustex-marginpar-width"),
+    ("marginparsep", "--rustex-marginpar-sep"),
+];
+
+/// How the page geometry maps to the HTML output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PageGeometry {
+    /// a single column of the width of the text of the first page, centered with the margins
+    /// scaled down to the window
+    #[default]
+    Text,
+    /// every shipped out page in a container as wide as the page (at most), with its left and
+    /// right margins as padding - so that changes of the geometry between pages are kept
+    Pages,
+    /// ignore the geometry and fill the window
+    Responsive,
+}
+impl PageGeometry {
+    /// `text`, `pages` or `responsive`
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "text" => Self::Text,
+            "pages" => Self::Pages,
+            "responsive" => Self::Responsive,
+            _ => return None,
+        })
+    }
+    /// The additional class of the `<body>`, if any.
+    pub(crate) fn body_class(self) -> Option<&'static str> {
+        match self {
+            Self::Text => None,
+            Self::Pages => Some("rustex-paged"),
+            Self::Responsive => Some("rustex-responsive"),
+        }
+    }
+}
+
+/// The CSS custom properties of one shipped out page.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PageLayout(pub(crate) Vec<(&'static str, i32)>);
+impl PageLayout {
+    pub(crate) fn capture(engine: Refs) -> Self {
+        let state = &engine.state;
+        let page_width = state.get_primitive_dim(PRIMITIVES.pdfpagewidth).0;
+        let mut text_width = state.get_primitive_dim(PRIMITIVES.hsize).0;
+        let mut margin_left = ONE_INCH + primitive_dim(state, "hoffset");
+        let mut ret = vec![
+            ("--rustex-page-width", page_width),
+            (
+                "--rustex-page-height",
+                primitive_dim(state, "pdfpageheight"),
+            ),
+            ("--rustex-hsize", text_width),
+            (
+                "--rustex-parindent",
+                state.get_primitive_dim(PRIMITIVES.parindent).0,
+            ),
+            (
+                "--rustex-parskip",
+                state.get_primitive_skip(PRIMITIVES.parskip).base.0,
+            ),
+            (
+                "--rustex-baselineskip",
+                state.get_primitive_skip(PRIMITIVES.baselineskip).base.0,
+            ),
+            (
+                "--rustex-vsize",
+                state.get_primitive_dim(PRIMITIVES.vsize).0,
+            ),
+        ];
+        for (name, css) in LATEX_DIMENS {
+            if let Some(d) = latex_dimen(engine, name) {
+                ret.push((css, d));
+            }
+        }
+        // LaTeX's `\textwidth` is the width of the page body, `\hsize` changes within e.g.
+        // minipages and lists
+        if let Some(d) = latex_dimen(engine, "textwidth") {
+            text_width = d;
+        }
+        if let Some(d) = latex_dimen(engine, "oddsidemargin") {
+            margin_left += d;
+        }
+        ret.push(("--rustex-text-width", text_width));
+        ret.push(("--rustex-margin-left", margin_left.max(0)));
+        ret.push((
+            "--rustex-margin-right",
+            (page_width - margin_left - text_width).max(0),
+        ));
+        Self(ret)
+    }
+
+    /// The properties as CSS values, for the container of the page with
+    /// [`PageGeometry::Pages`].
+    pub(crate) fn styles(&self) -> VecMap<String, String> {
+        let mut ret = VecMap::default();
+        for (name, d) in &self.0 {
+            ret.insert(name.to_string(), px(*d));
+        }
+        ret
+    }
+}
+
+fn latex_dimen(engine: Refs, name: &str) -> Option<i32> {
+    let cs = engine.aux.memory.cs_interner_mut().cs_from_str(name);
+    match engine.state.get_command(&cs) {
+        Some(TeXCommand::DimRegister(idx)) => Some(engine.state.get_dim_register(*idx).0),
+        _ => None,
+    }
+}
@@ -0,0 +1,462 @@
+//! Graphics drawn with raw PDF operators in `\pdfliteral`s (rules and boxes drawn by hand,
+//! `transparent`, simple diagrams). The operators of a single literal are interpreted and the
+//! paths it paints become an SVG anchored at the current position, like an `overlay` picture.
+//!
+//! Only the path construction and painting operators, clipping (`W`/`W*`), `q`/`Q`/`cm`, colors,
+//! line styles and opacities are supported; literals using text, images or shadings are dropped
+//! as before. Since every literal becomes an SVG of its own, a clipping path ends with the
+//! literal it is set in.
+//! Opacities are not content stream operators but set via ExtGStates, which we don't have
+//! access to; instead, `ca`/`CA` are accepted as if they were operators, and `/Name gs` is
+//! taken to set both opacities if the name ends in a number between 0 and 1 (as in the
+//! `transparent` package's `/TRP0.5`).
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tex_engine::pdflatex::nodes::{extgstate_opacity, PDFLiteral, PDFLiteralOption};
+
+/// px per bp, as in the HTML output (where 1pt is 1.5px)
+const SCALE: f32 = 1.5 * 72.27 / 72.0;
+
+/// for the ids of `<clipPath>`s, which need to be unique in the whole document
+static CLIP_PATHS: AtomicUsize = AtomicUsize::new(0);
+
+/// The HTML for the literal, if it paints anything we can draw.
+pub(crate) fn literal_svg(literal: &PDFLiteral) -> Option<String> {
+    if matches!(literal.option, PDFLiteralOption::Page) {
+        // relative to the page origin, which we have no position for
+        return None;
+    }
+    let mut interpreter = Interpreter::default();
+    for token in Tokens(literal.literal.as_bytes()) {
+        match token? {
+            Token::Operand(o) => interpreter.operands.push(o),
+            Token::Operator(op) => {
+                interpreter.operator(op)?;
+                interpreter.operands.clear();
+            }
+        }
+    }
+    if !interpreter.painted {
+        return None;
+    }
+    while interpreter.groups > 0 || !interpreter.stack.is_empty() {
+        interpreter.restore();
+    }
+    Some(format!(
+        "<div class=\"rustex-svg rustex-overlay\"><svg width=\"0\" height=\"0\"><g transform=\"scale({},{})\">{}</g></svg></div>",
+        num(SCALE),
+        num(-SCALE),
+        interpreter.out
+    ))
+}
+
+#[derive(Clone)]
+struct GState {
+    /// `None` is the current (text) color
+    fill: Option<String>,
+    stroke: Option<String>,
+    fill_opacity: f32,
+    stroke_opacity: f32,
+    line_width: f32,
+    cap: u8,
+    join: u8,
+    dash: Option<(Vec<f32>, f32)>,
+}
+impl Default for GState {
+    fn default() -> Self {
+        Self {
+            fill: None,
+            stroke: None,
+            fill_opacity: 1.0,
+            stroke_opacity: 1.0,
+            line_width: 1.0,
+            cap: 0,
+            join: 0,
+            dash: None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Interpreter {
+    operands: Vec<Operand>,
+    state: GState,
+    /// the states saved by `q`, with the number of `<g>`s open at that point
+    stack: Vec<(GState, usize)>,
+    /// the number of `<g>`s opened by `cm` since the last `q`
+    groups: usize,
+    path: String,
+    /// set by `W`/`W*` (with whether the even-odd rule applies), to clip to the current path
+    /// once it is painted
+    clip: Option<bool>,
+    current: (f32, f32),
+    start: (f32, f32),
+    painted: bool,
+    out: String,
+}
+impl Interpreter {
+    fn nums<const N: usize>(&self) -> Option<[f32; N]> {
+        let ops = self.operands.get(self.operands.len().checked_sub(N)?..)?;
+        let mut ret = [0.0; N];
+        for (r, o) in ret.iter_mut().zip(ops) {
+            match o {
+                Operand::Num(f) => *r = *f,
+                _ => return None,
+            }
+        }
+        Some(ret)
+    }
+    /// A gray, RGB or CMYK color, depending on the number of operands.
+    fn color(&self) -> Option<String> {
+        match self.operands.len() {
+            1 => self.nums::<1>().map(|[g]| rgb(g, g, g)),
+            3 => self.nums::<3>().map(|[r, g, b]| rgb(r, g, b)),
+            4 => self.nums::<4>().map(|[c, m, y, k]| {
+                rgb(
+                    (1.0 - c) * (1.0 - k),
+                    (1.0 - m) * (1.0 - k),
+                    (1.0 - y) * (1.0 - k),
+                )
+            }),
+            _ => None,
+        }
+    }
+    fn restore(&mut self) {
+        for _ in 0..self.groups {
+            self.out.push_str("</g>");
+        }
+        match self.stack.pop() {
+            Some((state, groups)) => {
+                self.state = state;
+                self.groups = groups;
+            }
+            None => self.groups = 0,
+        }
+    }
+    /// `None` if the literal does something we can't draw.
+    fn operator(&mut self, op: &str) -> Option<()> {
+        match op {
+            "q" => {
+                self.stack.push((self.state.clone(), self.groups));
+                self.groups = 0;
+            }
+            "Q" => self.restore(),
+            "cm" => {
+                let [a, b, c, d, e, f] = self.nums::<6>()?;
+                self.out.push_str(&format!(
+                    "<g transform=\"matrix({},{},{},{},{},{})\">",
+                    num(a),
+                    num(b),
+                    num(c),
+                    num(d),
+                    num(e),
+                    num(f)
+                ));
+                self.groups += 1;
+            }
+            "m" => {
+                let [x, y] = self.nums::<2>()?;
+                self.path.push_str(&format!("M{} {} ", num(x), num(y)));
+                self.current = (x, y);
+                self.start = (x, y);
+            }
+            "l" => {
+                let [x, y] = self.nums::<2>()?;
+                self.path.push_str(&format!("L{} {} ", num(x), num(y)));
+                self.current = (x, y);
+            }
+            "c" | "v" | "y" => {
+                let (c1, c2, end) = match op {
+                    "c" => {
+                        let [x1, y1, x2, y2, x3, y3] = self.nums::<6>()?;
+                        ((x1, y1), (x2, y2), (x3, y3))
+                    }
+                    "v" => {
+                        let [x2, y2, x3, y3] = self.nums::<4>()?;
+                        (self.current, (x2, y2), (x3, y3))
+                    }
+                    _ => {
+                        let [x1, y1, x3, y3] = self.nums::<4>()?;
+                        ((x1, y1), (x3, y3), (x3, y3))
+                    }
+                };
+                self.path.push_str(&format!(
+                    "C{} {} {} {} {} {} ",
+                    num(c1.0),
+                    num(c1.1),
+                    num(c2.0),
+                    num(c2.1),
+                    num(end.0),
+                    num(end.1)
+                ));
+                self.current = end;
+            }
+            "h" => {
+                self.path.push_str("Z ");
+                self.current = self.start;
+            }
+            "re" => {
+                let [x, y, w, h] = self.nums::<4>()?;
+                self.path.push_str(&format!(
+                    "M{} {} h{} v{} h{} Z ",
+                    num(x),
+                    num(y),
+                    num(w),
+                    num(h),
+                    num(-w)
+                ));
+                self.current = (x, y);
+                self.start = (x, y);
+            }
+            "S" => self.paint(false, true, false),
+            "s" => {
+                self.path.push_str("Z ");
+                self.paint(false, true, false)
+            }
+            "f" | "F" => self.paint(true, false, false),
+            "f*" => self.paint(true, false, true),
+            "B" => self.paint(true, true, false),
+            "B*" => self.paint(true, true, true),
+            "b" | "b*" => {
+                self.path.push_str("Z ");
+                self.paint(true, true, op == "b*")
+            }
+            "W" | "W*" => self.clip = Some(op == "W*"),
+            "n" => {
+                self.do_clip();
+                self.path.clear()
+            }
+            "w" => self.state.line_width = self.nums::<1>()?[0],
+            "J" => self.state.cap = self.nums::<1>()?[0] as u8,
+            "j" => self.state.join = self.nums::<1>()?[0] as u8,
+            "d" => match self.operands.as_slice() {
+                [Operand::Array(a), Operand::Num(phase)] => {
+                    self.state.dash = (!a.is_empty()).then(|| (a.clone(), *phase))
+                }
+                _ => return None,
+            },
+            "g" | "rg" | "k" | "sc" | "scn" => self.state.fill = Some(self.color()?),
+            "G" | "RG" | "K" | "SC" | "SCN" => self.state.stroke = Some(self.color()?),
+            "ca" => self.state.fill_opacity = self.nums::<1>()?[0],
+            "CA" => self.state.stroke_opacity = self.nums::<1>()?[0],
+            "gs" => {
+                if let Some(Operand::Name(name)) = self.operands.last() {
+                    if let Some(o) = extgstate_opacity(name) {
+                        self.state.fill_opacity = o;
+                        self.state.stroke_opacity = o;
+                    }
+                }
+            }
+            "BT" | "Do" | "sh" | "BI" | "EI" | "ID" => return None,
+            // color spaces, rendering intents, flatness, miter limits, marked content...
+            _ => (),
+        }
+        Some(())
+    }
+    /// Clips everything up to the next `Q` to the current path, if requested by `W`/`W*`.
+    fn do_clip(&mut self) {
+        let Some(evenodd) = self.clip.take() else {
+            return;
+        };
+        let id = CLIP_PATHS.fetch_add(1, Ordering::Relaxed);
+        self.out.push_str(&format!(
+            "<clipPath id=\"rustex-clip{id}\"><path d=\"{}\"{}/></clipPath><g clip-path=\"url(#rustex-clip{id})\">",
+            self.path.trim_end(),
+            if evenodd { " clip-rule=\"evenodd\"" } else { "" }
+        ));
+        self.groups += 1;
+    }
+    fn paint(&mut self, fill: bool, stroke: bool, evenodd: bool) {
+        if let Some(clip) = self.clip.take() {
+            // the clipping path only applies to what is painted afterwards
+            let path = self.path.clone();
+            self.paint(fill, stroke, evenodd);
+            self.path = path;
+            self.clip = Some(clip);
+            self.do_clip();
+            self.path.clear();
+            return;
+        }
+        let path = std::mem::take(&mut self.path);
+        if path.is_empty() {
+            return;
+        }
+        self.painted = true;
+        let state = &self.state;
+        self.out
+            .push_str(&format!("<path d=\"{}\"", path.trim_end()));
+        if fill {
+            let color = state.fill.as_deref().unwrap_or("currentColor");
+            self.out.push_str(&format!(" fill=\"{color}\""));
+            if evenodd {
+                self.out.push_str(" fill-rule=\"evenodd\"");
+            }
+            if state.fill_opacity < 1.0 {
+                self.out
+                    .push_str(&format!(" fill-opacity=\"{}\"", num(state.fill_opacity)));
+            }
+        } else {
+            self.out.push_str(" fill=\"none\"");
+        }
+        if stroke {
+            let color = state.stroke.as_deref().unwrap_or("currentColor");
+            self.out.push_str(&format!(
+                " stroke=\"{color}\" stroke-width=\"{}\"",
+                num(state.line_width.max(0.01))
+            ));
+            match state.cap {
+                1 => self.out.push_str(" stroke-linecap=\"round\""),
+                2 => self.out.push_str(" stroke-linecap=\"square\""),
+                _ => (),
+            }
+            match state.join {
+                1 => self.out.push_str(" stroke-linejoin=\"round\""),
+                2 => self.out.push_str(" stroke-linejoin=\"bevel\""),
+                _ => (),
+            }
+            if let Some((dashes, phase)) = &state.dash {
+                let dashes: Vec<_> = dashes.iter().map(|d| num(*d)).collect();
+                self.out.push_str(&format!(
+                    " stroke-dasharray=\"{}\" stroke-dashoffset=\"{}\"",
+                    dashes.join(" "),
+                    num(*phase)
+                ));
+            }
+            if state.stroke_opacity < 1.0 {
+                self.out.push_str(&format!(
+                    " stroke-opacity=\"{}\"",
+                    num(state.stroke_opacity)
+                ));
+            }
+        }
+        self.out.push_str("/>");
+    }
+}
+
+fn rgb(r: f32, g: f32, b: f32) -> String {
+    let c = |f: f32| (f.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", c(r), c(g), c(b))
+}
+
+fn num(f: f32) -> String {
+    let s = format!("{f:.3}");
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" {
+        "0".to_string()
+    } else {
+        s.to_string()
+    }
+}
+
+enum Operand {
+    Num(f32),
+    Name(String),
+    Array(Vec<f32>),
+    /// strings, dictionaries, ...
+    Other,
+}
+enum Token<'a> {
+    Operand(Operand),
+    Operator(&'a str),
+}
+
+/// The tokens of a content stream; `None` for anything malformed.
+struct Tokens<'a>(&'a [u8]);
+impl<'a> Tokens<'a> {
+    fn is_delimiter(b: u8) -> bool {
+        b.is_ascii_whitespace() || b"()<>[]{}/%".contains(&b)
+    }
+    fn regular(&mut self) -> &'a str {
+        let len = self
+            .0
+            .iter()
+            .position(|b| Self::is_delimiter(*b))
+            .unwrap_or(self.0.len());
+        let (word, rest) = self.0.split_at(len);
+        self.0 = rest;
+        std::str::from_utf8(word).unwrap_or_default()
+    }
+    fn skip_whitespace(&mut self) {
+        loop {
+            match self.0.first() {
+                Some(b) if b.is_ascii_whitespace() => self.0 = &self.0[1..],
+                Some(b'%') => {
+                    let len = self
+                        .0
+                        .iter()
+                        .position(|b| *b == b'\n')
+                        .unwrap_or(self.0.len());
+                    self.0 = &self.0[len..];
+                }
+                _ => return,
+            }
+        }
+    }
+    /// Skips a string or dictionary, up to the matching closing delimiter.
+    fn skip_nested(&mut self, open: u8, close: u8) -> Option<()> {
+        let mut depth = 0usize;
+        let mut escaped = false;
+        while let Some((b, rest)) = self.0.split_first() {
+            self.0 = rest;
+            match *b {
+                _ if escaped => escaped = false,
+                b'\\' if open == b'(' => escaped = true,
+                b if b == open => depth += 1,
+                b if b == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(());
+                    }
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+}
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Option<Token<'a>>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.skip_whitespace();
+        let first = *self.0.first()?;
+        Some(Some(match first {
+            b'/' => {
+                self.0 = &self.0[1..];
+                Token::Operand(Operand::Name(self.regular().to_string()))
+            }
+            b'(' => match self.skip_nested(b'(', b')') {
+                Some(()) => Token::Operand(Operand::Other),
+                None => return Some(None),
+            },
+            b'<' => match self.skip_nested(b'<', b'>') {
+                Some(()) => Token::Operand(Operand::Other),
+                None => return Some(None),
+            },
+            b'[' => {
+                self.0 = &self.0[1..];
+                let mut array = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    match self.0.first() {
+                        Some(b']') => {
+                            self.0 = &self.0[1..];
+                            break;
+                        }
+                        Some(_) => match self.regular().parse() {
+                            Ok(f) => array.push(f),
+                            Err(_) => return Some(None),
+                        },
+                        None => return Some(None),
+                    }
+                }
+                Token::Operand(Operand::Array(array))
+            }
+            b'+' | b'-' | b'.' | b'0'..=b'9' => match self.regular().parse() {
+                Ok(f) => Token::Operand(Operand::Num(f)),
+                Err(_) => return Some(None),
+            },
+            _ if Self::is_delimiter(first) => return Some(None),
+            _ => Token::Operator(self.regular()),
+        }))
+    }
+}
@@ -0,0 +1,211 @@
+//! Optional post-pass over the shipped-out nodes that merges adjacent inline
+//! formulas (e.g. `$a$-$b$`) into a single `<math>` node.
+//!
+//! Formulas split by color groups (e.g. `{\color{red}$a$}$=b$`) are merged as well, with the
+//! color moved into the formula, as long as the group contains nothing but the formula. Other
+//! groups (fonts, links, annotations), and separators inside a group, end a formula.
+
+use crate::shipout::html::Escaped;
+use crate::shipout::state::{
+    CharOrStr, Common, ShipoutNodeH, ShipoutNodeHRow, ShipoutNodeM, ShipoutNodeT, ShipoutNodeTable,
+    ShipoutNodeV,
+};
+
+/// Which text between two inline formulas allows them to be merged.
+/// Whitespace around a separator is ignored (but preserved as a math space);
+/// the empty string allows merging formulas that are only separated by
+/// whitespace (or nothing at all).
+#[derive(Debug, Clone)]
+pub struct MathMergeSettings {
+    pub separators: Vec<String>,
+}
+impl Default for MathMergeSettings {
+    fn default() -> Self {
+        Self {
+            separators: vec![
+                String::new(),
+                "-".to_string(),
+                "=".to_string(),
+                "+".to_string(),
+                "/".to_string(),
+            ],
+        }
+    }
+}
+
+pub(crate) fn merge_math(nodes: &mut Vec<ShipoutNodeV>, settings: &MathMergeSettings) {
+    for n in nodes {
+        match n {
+            ShipoutNodeV::Paragraph { children, .. } => merge_hlist(children, settings),
+            ShipoutNodeV::HAlign { children, .. } => merge_table(children, settings),
+            ShipoutNodeV::Common(c) => merge_common(c, settings, merge_math),
+            ShipoutNodeV::KernSkip(_) | ShipoutNodeV::HRule { .. } => (),
+        }
+    }
+}
+
+fn merge_table(nodes: &mut Vec<ShipoutNodeTable>, settings: &MathMergeSettings) {
+    for n in nodes {
+        match n {
+            ShipoutNodeTable::Row { children, .. } => merge_row(children, settings),
+            ShipoutNodeTable::NoAlign { children, .. } => merge_math(children, settings),
+            ShipoutNodeTable::Common(c) => merge_common(c, settings, merge_table),
+        }
+    }
+}
+
+fn merge_row(nodes: &mut Vec<ShipoutNodeHRow>, settings: &MathMergeSettings) {
+    for n in nodes {
+        match n {
+            ShipoutNodeHRow::Cell { children, .. } => merge_hlist(children, settings),
+            ShipoutNodeHRow::Common(c) => merge_common(c, settings, merge_row),
+        }
+    }
+}
+
+fn merge_common<T: ShipoutNodeT>(
+    node: &mut Common<T>,
+    settings: &MathMergeSettings,
+    f: fn(&mut Vec<T>, &MathMergeSettings),
+) {
+    match node {
+        Common::WithColor { children, .. }
+        | Common::WithFont { children, .. }
+        | Common::WithLink { children, .. }
+        | Common::WithAnnotation { children, .. }
+        | Common::WithMatrix { children, .. } => f(children, settings),
+        Common::VBox { children, .. } => merge_math(children, settings),
+        Common::HBox { children, .. } => merge_hlist(children, settings),
+        Common::Literal(_) | Common::PDFDest(_) | Common::SVG { .. } => (),
+    }
+}
+
+pub(crate) fn merge_hlist(nodes: &mut Vec<ShipoutNodeH>, settings: &MathMergeSettings) {
+    for n in nodes.iter_mut() {
+        if let ShipoutNodeH::Common(c) = n {
+            merge_common(c, settings, merge_hlist);
+        }
+    }
+    if !nodes.iter().any(is_inline_math) {
+        return;
+    }
+    let mut ret = Vec::with_capacity(nodes.len());
+    let mut iter = std::mem::take(nodes).into_iter().peekable();
+    while let Some(node) = iter.next() {
+        if !is_inline_math(&node) {
+            ret.push(node);
+            continue;
+        }
+        let mut current = node;
+        loop {
+            // collect the text up to the next non-character node
+            let mut between = Vec::new();
+            let mut text = String::new();
+            while let Some(ShipoutNodeH::Char(_) | ShipoutNodeH::Space) = iter.peek() {
+                match iter.next() {
+                    Some(ShipoutNodeH::Char(c)) => {
+                        text.push_str(&c.to_string());
+                        between.push(ShipoutNodeH::Char(c));
+                    }
+                    Some(n) => {
+                        text.push(' ');
+                        between.push(n);
+                    }
+                    None => unreachable!(),
+                }
+            }
+            let sep = text.trim();
+            let mergeable = iter.peek().is_some_and(is_inline_math)
+                && settings.separators.iter().any(|s| s == sep);
+            if !mergeable {
+                ret.push(current);
+                ret.extend(between);
+                break;
+            }
+            current = into_math(current);
+            let ShipoutNodeH::Math {
+                children,
+                uses_color,
+                uses_font,
+                ..
+            } = &mut current
+            else {
+                unreachable!()
+            };
+            let Some(ShipoutNodeH::Math {
+                children: next,
+                uses_color: next_color,
+                uses_font: next_font,
+                ..
+            }) = iter.next().map(into_math)
+            else {
+                unreachable!()
+            };
+            if text.starts_with(' ') {
+                children.push(ShipoutNodeM::Space);
+            }
+            if !sep.is_empty() {
+                children.push(ShipoutNodeM::Common(Common::Literal(format!(
+                    "<mo class=\"rustex-math-merged\">{}</mo>",
+                    Escaped(&CharOrStr::Str(sep.into()))
+                ))));
+                if text.ends_with(' ') {
+                    children.push(ShipoutNodeM::Space);
+                }
+            }
+            children.extend(next);
+            *uses_color = *uses_color || next_color;
+            *uses_font = *uses_font || next_font;
+        }
+    }
+    *nodes = ret;
+}
+
+/// Whether the node is an inline formula, possibly in a color group containing nothing else.
+fn is_inline_math(node: &ShipoutNodeH) -> bool {
+    match node {
+        ShipoutNodeH::Math { display: None, .. } => true,
+        ShipoutNodeH::Common(Common::WithColor { children, .. }) => {
+            matches!(children.as_slice(), [n] if is_inline_math(n))
+        }
+        _ => false,
+    }
+}
+
+/// Turns a node satisfying [`is_inline_math`] into a formula, moving color groups around it
+/// into the formula.
+fn into_math(node: ShipoutNodeH) -> ShipoutNodeH {
+    let ShipoutNodeH::Common(Common::WithColor {
+        color,
+        mut children,
+        ..
+    }) = node
+    else {
+        return node;
+    };
+    let Some(ShipoutNodeH::Math {
+        sref,
+        display,
+        fleqn,
+        axis_shift,
+        children,
+        uses_font,
+        ..
+    }) = children.pop().map(into_math)
+    else {
+        unreachable!()
+    };
+    ShipoutNodeH::Math {
+        sref,
+        display,
+        fleqn,
+        axis_shift,
+        children: vec![ShipoutNodeM::Common(Common::WithColor {
+            color,
+            children,
+            uses_font,
+        })],
+        uses_color: false,
+        uses_font,
+    }
+}
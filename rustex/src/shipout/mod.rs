@@ -1,19 +1,40 @@
 pub(crate) mod annotations;
+pub mod captions;
+pub mod client_math;
+pub mod decorations;
+pub mod diff;
+pub(crate) mod forms;
 pub(crate) mod html;
+pub mod ir;
+pub mod layout;
+pub(crate) mod literals;
+pub mod merge;
 pub(crate) mod nodes;
+pub(crate) mod pages;
+pub mod positions;
+pub(crate) mod punctuation;
+pub mod sections;
+pub mod split;
 pub(crate) mod state;
+pub mod text;
 pub(crate) mod utils;
+pub mod verbatim;
 
 use crate::engine::nodes::{LineSkip, RusTeXNode};
 use crate::engine::{Refs, Res, SRef, Types};
+use crate::shipout::decorations::{Decision, DecorationKind, Decorations};
+use crate::shipout::pages::RunningHeads;
 use crate::shipout::state::{
     Common, HLike, Math, ModeKind, Row, Shipout, ShipoutNodeH, ShipoutNodeM, ShipoutNodeT,
     ShipoutNodeTable, ShipoutNodeV, ShipoutState, VLike, SVG,
 };
 use crate::shipout::utils::{HNodes, MNode, MNodes, VNodes};
 use crate::utils::{Flex, Margin};
+use tex_engine::engine::fontsystem::Font as FontT;
+use tex_engine::engine::mouth::Mouth;
 use tex_engine::engine::stomach::methods::ParLineSpec;
-use tex_engine::pdflatex::nodes::{PDFDest, PDFNode};
+use tex_engine::engine::utils::approximations::ApproximationKind;
+use tex_engine::pdflatex::nodes::{PDFDest, PDFExtension, PDFNode};
 use tex_engine::tex::nodes::boxes::{HBoxInfo, TeXBox, ToOrSpread, VBoxInfo};
 use tex_engine::tex::nodes::horizontal::HNode;
 use tex_engine::tex::nodes::math::{
@@ -23,6 +44,7 @@ use tex_engine::tex::nodes::vertical::VNode;
 use tex_engine::tex::nodes::NodeTrait;
 use tex_engine::tex::numerics::{Dim32, Skip};
 use tex_engine::utils::errors::TeXError;
+use tex_glyphs::fontstyles::FontModifier;
 /*
 pub(crate) fn make_page<F:FnOnce(Refs,&mut ShipoutState) -> Res<()>>(engine:Refs,state:&mut ShipoutState,f:F) -> Res<HTMLNode> {
     let mut page = state.do_in_and(HTMLNode::page(),None,|state| {
@@ -42,15 +64,80 @@ pub(crate) fn make_page<F:FnOnce(Refs,&mut ShipoutState) -> Res<()>>(engine:Refs
 
 pub fn shipout(engine: Refs, n: VNode<Types>) -> Res<()> {
     //println!("Here: {}\n\n-------------------------------------------\n\n",n.display());
+    if let (Some(mut pdf), VNode::Box(page)) = (engine.aux.extension.pdf_output.take(), &n) {
+        pdf.add_page(engine, page);
+        engine.aux.extension.pdf_output = Some(pdf);
+    }
+    if let (Some(mut dvi), VNode::Box(page)) = (engine.aux.extension.dvi_output.take(), &n) {
+        dvi.add_page(engine, page);
+        engine.aux.extension.dvi_output = Some(dvi);
+    }
+    // only lay out the page if there may be `\pdfsavepos`es on it
+    if let (true, VNode::Box(page)) = (engine.aux.extension.positions().count > 0, &n) {
+        let index = engine.aux.extension.state.layouts.len();
+        let positions = crate::placement::saved_positions(engine.state, page, index);
+        engine.aux.extension.saved_positions.set_page(positions);
+    }
     match n {
-        VNode::Box(TeXBox::V { children, .. }) => {
-            let children = get_page_inner(children.into_vec());
+        VNode::Box(page @ TeXBox::V { .. }) => {
+            let mut heads = std::mem::take(&mut engine.aux.extension.running_heads);
+            let mut filter = engine.aux.extension.decoration_filter.take();
+            let mut decorations = Decorations {
+                filter: &mut filter,
+                fs: engine.filesystem,
+            };
+            let mut children = get_page_inner(vec![VNode::Box(page)], &mut heads, &mut decorations);
+            engine.aux.extension.running_heads = heads;
+            engine.aux.extension.decoration_filter = filter;
+            if let Some((frame, slide)) = engine.aux.extension.frame.take() {
+                let mut attrs = crate::utils::VecMap::default();
+                attrs.insert("data-frame".to_string(), frame.to_string());
+                attrs.insert("data-slide".to_string(), slide.to_string());
+                children.insert(
+                    0,
+                    VNode::Custom(RusTeXNode::AnnotBegin {
+                        tag: Some("section".to_string()),
+                        start: engine.mouth.start_ref(),
+                        attrs,
+                        styles: crate::utils::VecMap::default(),
+                        classes: crate::utils::VecSet::default(),
+                    }),
+                );
+                children.push(VNode::Custom(RusTeXNode::AnnotEnd(
+                    engine.mouth.current_sourceref(),
+                )));
+            }
+            let marker = engine.aux.extension.page_markers;
+            let layout = layout::PageLayout::capture(engine);
+            if engine.aux.extension.page_geometry == layout::PageGeometry::Pages {
+                let mut classes = crate::utils::VecSet::default();
+                classes.insert("rustex-page-geometry".to_string());
+                children.insert(
+                    0,
+                    VNode::Custom(RusTeXNode::AnnotBegin {
+                        tag: Some("div".to_string()),
+                        start: engine.mouth.start_ref(),
+                        attrs: crate::utils::VecMap::default(),
+                        styles: layout.styles(),
+                        classes,
+                    }),
+                );
+                children.push(VNode::Custom(RusTeXNode::AnnotEnd(
+                    engine.mouth.current_sourceref(),
+                )));
+            }
+            engine.aux.extension.state.layouts.push(layout);
             /*println!("--------------------------------------------");
             for c in &children {
                 println!("{}",c.display());
             }*/
-            ShipoutState::split_state(engine, |state| state.do_vlist(&mut children.into()))
-                .map_err(|e| TeXError::General(format!("Not allowed in V-Mode: {e:?}")))?;
+            ShipoutState::split_state(engine, |state| {
+                if marker {
+                    state.push(Common::Literal(pages::PAGE_MARKER.to_string()).into());
+                }
+                state.do_vlist(&mut children.into())
+            })
+            .map_err(|e| TeXError::General(format!("Not allowed in V-Mode: {e:?}")))?;
             /*
                         println!("--------------------------------------------");
                         println!("{:?}",engine.aux.extension.state.output);
@@ -67,21 +154,32 @@ impl<Mode: VLike> Shipout<'_, '_, Mode> {
         //let mut empty: bool = true;
         while let Some(c) = children.next() {
             match c {
+                VNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFAnnot(annot))) => {
+                    if let Some(html) = forms::field_html(&annot) {
+                        self.push(Common::Literal(html).into())
+                    }
+                }
+                VNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFLiteral(lit))) => {
+                    if let Some(svg) = literals::literal_svg(&lit) {
+                        self.push(Common::Literal(svg).into())
+                    }
+                }
                 VNode::Custom(RusTeXNode::PDFNode(
                     PDFNode::PDFOutline(_)
                     | PDFNode::PDFPageAttr(_)
                     | PDFNode::PDFPagesAttr(_)
                     | PDFNode::PDFCatalog(_)
                     | PDFNode::PDFSave
-                    | PDFNode::PDFAnnot(_)
-                    | PDFNode::PDFLiteral(_)
                     | PDFNode::XForm(_)
                     | PDFNode::Obj(_),
                 ))
                 | VNode::Penalty(_)
                 | VNode::Mark(..)
                 | VNode::Custom(
-                    RusTeXNode::PageBegin | RusTeXNode::PageEnd | RusTeXNode::HAlignEnd,
+                    RusTeXNode::PageBegin
+                    | RusTeXNode::PageEnd
+                    | RusTeXNode::HAlignEnd
+                    | RusTeXNode::BoxVisibility(_),
                 ) => (),
                 VNode::Custom(RusTeXNode::PGFEscape(bx)) => children.prefix(vec![VNode::Box(bx)]), // TODO?
                 VNode::Custom(RusTeXNode::PDFNode(PDFNode::Color(act))) => self.do_color(act),
@@ -105,6 +203,10 @@ impl<Mode: VLike> Shipout<'_, '_, Mode> {
                     tag,
                 ),
                 VNode::Custom(RusTeXNode::AnnotEnd(end)) => self.close_annot(end),
+                VNode::Custom(RusTeXNode::ProvenanceBegin { start, end, via }) => {
+                    self.open_provenance(start, end, via)
+                }
+                VNode::Custom(RusTeXNode::ProvenanceEnd) => self.close_provenance(),
                 VNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFMatrix {
                     scale,
                     rotate,
@@ -112,6 +214,7 @@ impl<Mode: VLike> Shipout<'_, '_, Mode> {
                     skewy,
                 })) => self.open_matrix(scale, rotate, skewx, skewy),
                 VNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFRestore)) => self.close_matrix(),
+                VNode::Custom(RusTeXNode::PDFNode(PDFNode::SavePos(id))) => self.save_pos(id),
                 VNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFDest(PDFDest { id, .. }))) => {
                     self.push(Common::PDFDest(id).into())
                 }
@@ -123,6 +226,7 @@ impl<Mode: VLike> Shipout<'_, '_, Mode> {
                     miny,
                     maxx,
                     maxy,
+                    picture,
                 }) => {
                     if let TeXBox::H {
                         children,
@@ -131,16 +235,26 @@ impl<Mode: VLike> Shipout<'_, '_, Mode> {
                         ..
                     } = bx
                     {
-                        self.in_svg(start, end, minx.0, miny.0, maxx.0, maxy.0, |state| {
-                            state.do_svglist(&mut children.into()).map_err(|_| None)
-                        })
+                        self.in_svg(
+                            start,
+                            end,
+                            minx.0,
+                            miny.0,
+                            maxx.0,
+                            maxy.0,
+                            picture,
+                            |state| state.do_svglist(&mut children.into()).map_err(|_| None),
+                        )
                         .map_err(|_| None)?
                     } else {
                         unreachable!()
                     }
                 }
-                VNode::Leaders(_) => (), // TODO?
+                VNode::Leaders(_) => self
+                    .engine
+                    .approximation(ApproximationKind::DroppedNode, "vertical leaders"), // TODO?
                 VNode::Custom(RusTeXNode::Literal(s)) => self.push(Common::Literal(s).into()),
+                VNode::Custom(RusTeXNode::External(n)) => self.do_external(n),
 
                 // ----------------------------------------------------------
                 VNode::VKern(d) => self.skipv(d.into()),
@@ -212,11 +326,17 @@ impl<Mode: VLike> Shipout<'_, '_, Mode> {
                             start,
                             end,
                             preskip,
-                        } if matches!(info, HBoxInfo::HBox { .. }) => self
-                            .in_h(start, end, info, preskip, |state| {
+                        } if matches!(info, HBoxInfo::HBox { .. }) => {
+                            // the interline glue is merged with the glue before it, so that
+                            // they end up as one spacer
+                            if let Some(s) = preskip {
+                                self.skipv(s.into())
+                            }
+                            self.in_h(start, end, info, preskip, |state| {
                                 state.do_hlist(&mut children.into())
                             })
-                            .map_err(|_| None)?,
+                            .map_err(|_| None)?
+                        }
                         TeXBox::H {
                             info: HBoxInfo::HAlignRow,
                             children,
@@ -263,8 +383,12 @@ impl<Mode: VLike> Shipout<'_, '_, Mode> {
                                 for r in redos.into_iter().rev() {
                                     self.push(r)
                                 }
+                                // TODO lost table row
+                                self.engine.approximation(
+                                    ApproximationKind::DroppedNode,
+                                    "table row outside of its alignment",
+                                );
                             }
-                            // TODO lost table row
                         }
                         _ => todo!("{tb:?}"),
                     }
@@ -344,11 +468,19 @@ impl<Mode: VLike> Shipout<'_, '_, Mode> {
         lineskip: LineSkip,
         parskip: Skip<Dim32>,
     ) -> Result<(), Option<VNode<Types>>> {
+        // a shaped paragraph (\parshape, \hangindent) keeps TeX's line breaks, and every line
+        // is indented relative to the last line spec, which determines the margins of the
+        // paragraph as a whole
+        let shaped = specs
+            .windows(2)
+            .any(|w| w[0].leftskip != w[1].leftskip || w[0].rightskip != w[1].rightskip);
+        let base_left = specs.last().map(|s| s.leftskip.base).unwrap_or_default();
         // hack for parlines that may have been \lastboxed
         let ret = self.in_par(specs, start, end, lineskip, parskip, |state| {
             let mut later = Vec::new();
             let mut emergency_break = false;
             let mut is_empty = true;
+            let mut broken = true;
             while let Some(c) = children.next() {
                 match c {
                     VNode::VSkip(_)
@@ -357,23 +489,38 @@ impl<Mode: VLike> Shipout<'_, '_, Mode> {
                     | VNode::VFilneg
                     | VNode::Vss
                     | VNode::Mark(..)
+                    | VNode::Penalty(_)
                     | VNode::VKern(_) => (),
-                    VNode::Custom(RusTeXNode::ParagraphEnd) if is_empty => emergency_break = true,
+                    VNode::Custom(RusTeXNode::ParagraphEnd) if is_empty => {
+                        state.engine.approximation(
+                            ApproximationKind::ParagraphSplit,
+                            "paragraph reassembled from boxes",
+                        );
+                        emergency_break = true
+                    }
                     VNode::Custom(RusTeXNode::ParagraphEnd) => return Ok(later),
                     VNode::Box(TeXBox::H {
                         info:
-                            HBoxInfo::ParLine {
+                            info @ HBoxInfo::ParLine {
                                 ends_with_line_break,
                                 ..
                             },
                         children,
                         ..
                     }) => {
+                        if let (true, HBoxInfo::ParLine { spec, .. }) = (shaped, &info) {
+                            if !broken {
+                                state.push(ShipoutNodeH::LineBreak)
+                            }
+                            state.skiph((spec.leftskip.base - base_left).into());
+                        }
+                        let children = crate::placement::set_glue(&info, children);
                         is_empty = false;
                         state.do_hlist(&mut children.into()).map_err(|_| None)?;
                         if ends_with_line_break {
                             state.push(ShipoutNodeH::LineBreak)
                         }
+                        broken = ends_with_line_break;
                     }
                     VNode::Box(TeXBox::H {
                         info:
@@ -481,16 +628,26 @@ impl Shipout<'_, '_, Row> {
 
 impl<Mode: HLike> Shipout<'_, '_, Mode> {
     fn do_hlist(&mut self, children: &mut HNodes) -> Result<(), Option<HNode<Types>>> {
+        let mut after_char = false;
         while let Some(c) = children.next() {
+            let adjacent = std::mem::replace(&mut after_char, matches!(c, HNode::Char { .. }));
             match c {
+                HNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFAnnot(annot))) => {
+                    if let Some(html) = forms::field_html(&annot) {
+                        self.push(Common::Literal(html).into())
+                    }
+                }
+                HNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFLiteral(lit))) => {
+                    if let Some(svg) = literals::literal_svg(&lit) {
+                        self.push(Common::Literal(svg).into())
+                    }
+                }
                 HNode::Custom(RusTeXNode::PDFNode(
                     PDFNode::PDFOutline(_)
                     | PDFNode::PDFPageAttr(_)
                     | PDFNode::PDFPagesAttr(_)
                     | PDFNode::PDFCatalog(_)
                     | PDFNode::PDFSave
-                    | PDFNode::PDFAnnot(_)
-                    | PDFNode::PDFLiteral(_)
                     | PDFNode::XForm(_)
                     | PDFNode::Obj(_),
                 ))
@@ -499,6 +656,9 @@ impl<Mode: HLike> Shipout<'_, '_, Mode> {
                 | HNode::Custom(
                     RusTeXNode::PageBegin | RusTeXNode::PageEnd | RusTeXNode::HAlignEnd,
                 ) => (),
+                HNode::Custom(RusTeXNode::BoxVisibility(v)) => {
+                    self.top_state.box_visibility = Some(v)
+                }
                 HNode::Custom(RusTeXNode::PGFEscape(bx)) => children.prefix(vec![HNode::Box(bx)]),
                 HNode::Custom(RusTeXNode::PDFNode(PDFNode::Color(act))) => self.do_color(act),
                 HNode::Custom(RusTeXNode::FontChange(font, global)) => self.open_font(font, global),
@@ -521,6 +681,10 @@ impl<Mode: HLike> Shipout<'_, '_, Mode> {
                     tag,
                 ),
                 HNode::Custom(RusTeXNode::AnnotEnd(end)) => self.close_annot(end),
+                HNode::Custom(RusTeXNode::ProvenanceBegin { start, end, via }) => {
+                    self.open_provenance(start, end, via)
+                }
+                HNode::Custom(RusTeXNode::ProvenanceEnd) => self.close_provenance(),
                 HNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFMatrix {
                     scale,
                     rotate,
@@ -528,11 +692,13 @@ impl<Mode: HLike> Shipout<'_, '_, Mode> {
                     skewy,
                 })) => self.open_matrix(scale, rotate, skewx, skewy),
                 HNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFRestore)) => self.close_matrix(),
+                HNode::Custom(RusTeXNode::PDFNode(PDFNode::SavePos(id))) => self.save_pos(id),
                 HNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFDest(PDFDest { id, .. }))) => {
                     self.push(Common::PDFDest(id).into())
                 }
                 HNode::Whatsit(wi) => wi.call(self.engine).map_err(|_| None)?,
                 HNode::Custom(RusTeXNode::Literal(s)) => self.push(Common::Literal(s).into()),
+                HNode::Custom(RusTeXNode::External(n)) => self.do_external(n),
                 HNode::Custom(RusTeXNode::PGFGBegin { .. } | RusTeXNode::PGFGEnd) => (), // TODO maybe? Only seems to happen in escape-boxes in svg
                 HNode::Custom(RusTeXNode::PGFSvg {
                     bx,
@@ -540,6 +706,7 @@ impl<Mode: HLike> Shipout<'_, '_, Mode> {
                     miny,
                     maxx,
                     maxy,
+                    picture,
                 }) => {
                     if let TeXBox::H {
                         children,
@@ -548,15 +715,22 @@ impl<Mode: HLike> Shipout<'_, '_, Mode> {
                         ..
                     } = bx
                     {
-                        self.in_svg(start, end, minx.0, miny.0, maxx.0, maxy.0, |state| {
-                            state.do_svglist(&mut children.into()).map_err(|_| None)
-                        })
+                        self.in_svg(
+                            start,
+                            end,
+                            minx.0,
+                            miny.0,
+                            maxx.0,
+                            maxy.0,
+                            picture,
+                            |state| state.do_svglist(&mut children.into()).map_err(|_| None),
+                        )
                         .map_err(|_| None)?
                     } else {
                         unreachable!()
                     }
                 }
-                HNode::Leaders(_) => (), // TODO?
+                HNode::Leaders(l) => self.leaders(l)?,
 
                 HNode::HSkip(sk) => self.skiph(sk.into()),
                 HNode::HKern(kn) => self.skiph(kn.into()),
@@ -610,11 +784,13 @@ impl<Mode: HLike> Shipout<'_, '_, Mode> {
                             start,
                             end,
                             preskip,
-                        } if matches!(info, HBoxInfo::HBox { .. }) => self
-                            .in_h(start, end, info, preskip, |state| {
+                        } if matches!(info, HBoxInfo::HBox { .. }) => {
+                            let children = crate::placement::set_glue(&info, children);
+                            self.in_h(start, end, info, preskip, |state| {
                                 state.do_hlist(&mut children.into())
                             })
-                            .map_err(|_| None)?,
+                            .map_err(|_| None)?
+                        }
                         TeXBox::H {
                             info: mut info @ HBoxInfo::ParLine { .. },
                             children,
@@ -622,6 +798,7 @@ impl<Mode: HLike> Shipout<'_, '_, Mode> {
                             end,
                             ..
                         } => {
+                            let children = crate::placement::set_glue(&info, children);
                             info.to_hbox();
                             self.in_h(start, end, info, None, |state| {
                                 state.do_hlist(&mut children.into())
@@ -657,11 +834,37 @@ impl<Mode: HLike> Shipout<'_, '_, Mode> {
                     depth,
                 }),
                 HNode::Char { char, font } => {
-                    let r =
-                        ShipoutNodeH::char(char, font, self.engine, &mut self.top_state.font_data);
-                    self.push(r)
+                    let r = ShipoutNodeH::char(
+                        char,
+                        font.clone(),
+                        self.engine,
+                        &mut self.top_state.font_data,
+                    );
+                    let monospaced = self
+                        .top_state
+                        .font_data
+                        .get(font.filename())
+                        .and_then(|d| d.modifiers)
+                        .is_some_and(|m| m.has(FontModifier::Monospaced));
+                    if self.engine.aux.extension.smart_punctuation && !monospaced {
+                        if let Some(r) = punctuation::smarten(self.nodes.last_mut(), adjacent, r) {
+                            self.push(r)
+                        }
+                    } else {
+                        self.push(r)
+                    }
                 }
                 HNode::Space => self.push(ShipoutNodeH::Space),
+                // the browser breaks the lines, so an (unbroken) hyphenation point becomes a
+                // soft hyphen and any other discretionary its no-break text
+                HNode::Discretionary { pre, post, nobreak }
+                    if post.is_empty() && nobreak.is_empty() =>
+                {
+                    if !pre.is_empty() {
+                        self.push(Common::Literal("\u{AD}".to_string()).into())
+                    }
+                }
+                HNode::Discretionary { nobreak, .. } => children.prefix(nobreak.into_vec()),
                 HNode::Custom(RusTeXNode::PDFNode(PDFNode::XImage(img))) => {
                     self.push(ShipoutNodeH::Img(img))
                 }
@@ -731,6 +934,11 @@ impl Shipout<'_, '_, Math> {
     ) -> Result<(), Option<MathNode<Types, MathFontStyle<Types>>>> {
         while let Some(c) = children.next() {
             match c {
+                MNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFLiteral(lit))) => {
+                    if let Some(svg) = literals::literal_svg(&lit) {
+                        self.push(Common::Literal(svg).into())
+                    }
+                }
                 MNode::Custom(RusTeXNode::PDFNode(
                     PDFNode::PDFOutline(_)
                     | PDFNode::PDFPageAttr(_)
@@ -738,14 +946,16 @@ impl Shipout<'_, '_, Math> {
                     | PDFNode::PDFCatalog(_)
                     | PDFNode::PDFSave
                     | PDFNode::PDFAnnot(_)
-                    | PDFNode::PDFLiteral(_)
                     | PDFNode::XForm(_)
                     | PDFNode::Obj(_),
                 ))
                 | MNode::Penalty(_)
                 | MNode::Mark(..)
                 | MNode::Custom(
-                    RusTeXNode::PageBegin | RusTeXNode::PageEnd | RusTeXNode::HAlignEnd,
+                    RusTeXNode::PageBegin
+                    | RusTeXNode::PageEnd
+                    | RusTeXNode::HAlignEnd
+                    | RusTeXNode::BoxVisibility(_),
                 ) => (),
                 MNode::Custom(RusTeXNode::PGFEscape(_bx)) => todo!(), // children.prefix(vec!(VNode::Box(bx))),
                 MNode::Custom(RusTeXNode::PDFNode(PDFNode::Color(act))) => self.do_color(act),
@@ -769,6 +979,10 @@ impl Shipout<'_, '_, Math> {
                     tag,
                 ),
                 MNode::Custom(RusTeXNode::AnnotEnd(end)) => self.close_annot(end),
+                MNode::Custom(RusTeXNode::ProvenanceBegin { start, end, via }) => {
+                    self.open_provenance(start, end, via)
+                }
+                MNode::Custom(RusTeXNode::ProvenanceEnd) => self.close_provenance(),
                 MNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFMatrix {
                     scale,
                     rotate,
@@ -776,11 +990,13 @@ impl Shipout<'_, '_, Math> {
                     skewy,
                 })) => self.open_matrix(scale, rotate, skewx, skewy),
                 MNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFRestore)) => self.close_matrix(),
+                MNode::Custom(RusTeXNode::PDFNode(PDFNode::SavePos(id))) => self.save_pos(id),
                 MNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFDest(PDFDest { id, .. }))) => {
                     self.push(Common::PDFDest(id).into())
                 }
                 MNode::Whatsit(wi) => wi.call(self.engine).map_err(|_| None)?,
                 MNode::Custom(RusTeXNode::Literal(s)) => self.push(Common::Literal(s).into()),
+                MNode::Custom(RusTeXNode::External(n)) => self.do_external(n),
                 MNode::Custom(RusTeXNode::PDFNode(PDFNode::XImage(img))) => {
                     self.push(ShipoutNodeM::Img(img))
                 }
@@ -804,7 +1020,9 @@ impl Shipout<'_, '_, Math> {
                 }),
                 MNode::HFil | MNode::HFill | MNode::Hss | MNode::HFilneg => (), // TODO maybe?
                 MNode::Choice(ls) => children.prefix(ls.0.into_vec()),
-                MNode::Leaders(..) => (), // TODO?
+                MNode::Leaders(..) => self
+                    .engine
+                    .approximation(ApproximationKind::DroppedNode, "leaders in math mode"), // TODO?
 
                 MNode::Atom(MathAtom { nucleus, sub, sup }) => {
                     let sub = sub.map(|inner| {
@@ -1011,7 +1229,10 @@ impl Shipout<'_, '_, SVG> {
                 | HNode::Penalty(_)
                 | HNode::Mark(..)
                 | HNode::Custom(
-                    RusTeXNode::PageBegin | RusTeXNode::PageEnd | RusTeXNode::HAlignEnd,
+                    RusTeXNode::PageBegin
+                    | RusTeXNode::PageEnd
+                    | RusTeXNode::HAlignEnd
+                    | RusTeXNode::BoxVisibility(_),
                 ) => (),
                 HNode::Custom(RusTeXNode::PDFNode(PDFNode::Color(act))) => self.do_color(act),
                 HNode::Custom(RusTeXNode::FontChange(font, global)) => self.open_font(font, global),
@@ -1034,6 +1255,7 @@ impl Shipout<'_, '_, SVG> {
                     tag,
                 ),
                 HNode::Custom(RusTeXNode::AnnotEnd(end)) => self.close_annot(end),
+                HNode::Custom(RusTeXNode::ProvenanceBegin { .. } | RusTeXNode::ProvenanceEnd) => (),
                 HNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFMatrix {
                     scale,
                     rotate,
@@ -1041,17 +1263,39 @@ impl Shipout<'_, '_, SVG> {
                     skewy,
                 })) => self.open_matrix(scale, rotate, skewx, skewy),
                 HNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFRestore)) => self.close_matrix(),
+                HNode::Custom(RusTeXNode::PDFNode(PDFNode::SavePos(id))) => self.save_pos(id),
                 HNode::Custom(RusTeXNode::PDFNode(PDFNode::PDFDest(PDFDest { id, .. }))) => {
                     self.push(Common::PDFDest(id).into())
                 }
 
                 HNode::Whatsit(wi) => wi.call(self.engine).map_err(|_| None)?,
-                HNode::Leaders(_) => (), // TODO?
+                HNode::Leaders(_) => self
+                    .engine
+                    .approximation(ApproximationKind::DroppedNode, "horizontal leaders"), // TODO?
                 HNode::Custom(RusTeXNode::Literal(s)) => self.push(Common::Literal(s).into()),
+                HNode::Custom(RusTeXNode::External(n)) => self.do_external(n),
 
                 HNode::Custom(RusTeXNode::PGFGBegin { attrs, tag }) => self.open_node(attrs, tag),
                 HNode::Custom(RusTeXNode::PGFGEnd) => self.close_node(),
-                HNode::Box(TeXBox::H { children: chs, .. }) => children.prefix(chs.into_vec()),
+                HNode::Box(TeXBox::H {
+                    children: chs,
+                    info,
+                    ..
+                }) => match info.raised() {
+                    // keep the shift of e.g. `\raise` as a translation
+                    Some(r) => {
+                        let mut attrs = crate::utils::VecMap::default();
+                        attrs.insert(
+                            "transform",
+                            format!("translate(0 {})", r.0 as f32 / 65536.0),
+                        );
+                        self.open_node(attrs, "g".to_string());
+                        self.do_svglist(&mut chs.into())?;
+                        self.close_node();
+                    }
+                    None => children.prefix(chs.into_vec()),
+                },
+                HNode::Discretionary { nobreak, .. } => children.prefix(nobreak.into_vec()),
                 HNode::Space | HNode::Hss => (),
                 HNode::Custom(RusTeXNode::PGFEscape(bx @ TeXBox::H { .. })) => {
                     let _ = bx.height();
@@ -1104,7 +1348,9 @@ impl Shipout<'_, '_, SVG> {
                     })
                     .map_err(|_| None)?
                 }
-                HNode::Char { .. } => (), // TODO?
+                HNode::Char { .. } => self
+                    .engine
+                    .approximation(ApproximationKind::DroppedNode, "character in SVG"), // TODO?
                 _ => todo!("{c:?}"),
             }
         }
@@ -1112,24 +1358,72 @@ impl Shipout<'_, '_, SVG> {
     }
 }
 
-fn get_page_inner(children: Vec<VNode<Types>>) -> Vec<VNode<Types>> {
+fn get_page_inner(
+    children: Vec<VNode<Types>>,
+    heads: &mut RunningHeads,
+    decorations: &mut Decorations,
+) -> Vec<VNode<Types>> {
     let mut ret = Vec::new();
     let mut list: VNodes = children.into();
     while let Some(c) = list.next() {
         match c {
+            // the box the output routine put the page's main vertical list in, with the header
+            // above and the footer below
+            VNode::Box(TeXBox::V { children, .. })
+                if children
+                    .iter()
+                    .any(|n| matches!(n, VNode::Box(b) if is_page_box(b))) =>
+            {
+                let mut foot = false;
+                let mut rest = Vec::new();
+                for c in children.into_vec() {
+                    match c {
+                        VNode::Box(b) if is_page_box(&b) => {
+                            foot = true;
+                            rest.push(VNode::Box(b))
+                        }
+                        VNode::Box(b) => {
+                            let kind = if foot {
+                                DecorationKind::Footer
+                            } else {
+                                DecorationKind::Header
+                            };
+                            match decorations.decide(&b, kind) {
+                                Decision::Keep => heads.push(b, foot, &mut rest),
+                                Decision::Drop => (),
+                                Decision::Class(cls) => rest.extend(decorations::wrap(
+                                    b,
+                                    cls,
+                                    VNode::Custom,
+                                    VNode::Custom,
+                                    VNode::Box,
+                                )),
+                            }
+                        }
+                        c => rest.push(c),
+                    }
+                }
+                list.prefix(rest)
+            }
             VNode::Box(TeXBox::V { children, .. })
                 if children
                     .iter()
                     .any(|n| matches!(n, VNode::Custom(RusTeXNode::PageBegin))) =>
             {
-                ret.extend(children.into_vec().into_iter().filter(|p| {
+                let text = children.into_vec().into_iter().filter(|p| {
                     !matches!(
                         p,
                         VNode::Custom(RusTeXNode::PageBegin | RusTeXNode::PageEnd)
                     )
-                }))
+                });
+                ret.extend(decorations.vlist(text.collect(), DecorationKind::Text))
+            }
+            VNode::Box(TeXBox::V { children, .. }) => {
+                list.prefix(decorations.vlist(children.into_vec(), DecorationKind::Page))
+            }
+            VNode::Box(bx @ TeXBox::H { .. }) if page_columns(&bx) > 1 => {
+                get_page_columns(bx, &mut ret, decorations)
             }
-            VNode::Box(TeXBox::V { children, .. }) => list.prefix(children.into_vec()),
             VNode::Box(TeXBox::H { children, .. }) if hbox_works(&children) => {
                 get_page_hbox(children, &mut ret, &mut list)
             }
@@ -1150,13 +1444,15 @@ fn get_page_inner(children: Vec<VNode<Types>>) -> Vec<VNode<Types>> {
                 | PDFNode::PDFOutline(_),
             )) => (),
             VNode::Custom(RusTeXNode::PageBegin) => {
+                let mut text = Vec::new();
                 for c in list.by_ref() {
                     if let VNode::Custom(RusTeXNode::PageEnd) = c {
                         break;
                     } else {
-                        ret.push(c)
+                        text.push(c)
                     }
                 }
+                ret.extend(decorations.vlist(text, DecorationKind::Text))
             }
             _ => ret.push(c),
         }
@@ -1164,6 +1460,91 @@ fn get_page_inner(children: Vec<VNode<Types>>) -> Vec<VNode<Types>> {
     ret
 }
 
+/// Whether the box directly contains the main vertical list, or is a row of
+/// [`page_columns`].
+fn is_page_box(bx: &TeXBox<Types>) -> bool {
+    match bx {
+        TeXBox::V { children, .. } => children
+            .iter()
+            .any(|n| matches!(n, VNode::Custom(RusTeXNode::PageBegin))),
+        TeXBox::H { .. } => page_columns(bx) > 1,
+    }
+}
+
+/// Whether the box contains (a part of) the main vertical list, i.e. is a column if it is
+/// next to others.
+pub(crate) fn contains_page(bx: &TeXBox<Types>) -> bool {
+    match bx {
+        TeXBox::H { children, .. } => children.iter().any(|n| match n {
+            HNode::Box(b) => contains_page(b),
+            _ => false,
+        }),
+        TeXBox::V { children, .. } => children.iter().any(|n| match n {
+            VNode::Custom(RusTeXNode::PageBegin) => true,
+            VNode::Box(b) => contains_page(b),
+            _ => false,
+        }),
+    }
+}
+
+/// The number of columns of an hbox, as put together by a multi-column output routine (e.g.
+/// LaTeX's for `\twocolumn`).
+fn page_columns(bx: &TeXBox<Types>) -> usize {
+    match bx {
+        TeXBox::H { children, .. } => children
+            .iter()
+            .filter(|n| matches!(n, HNode::Box(b) if contains_page(b)))
+            .count(),
+        TeXBox::V { .. } => 0,
+    }
+}
+
+/// Puts the contents of the columns of a page (see [`page_columns`]) one after the other into a
+/// CSS multi-column container, so that the browser distributes them again.
+fn get_page_columns(bx: TeXBox<Types>, ret: &mut Vec<VNode<Types>>, decorations: &mut Decorations) {
+    let width = bx.width().0;
+    let TeXBox::H {
+        children,
+        start,
+        end,
+        ..
+    } = bx
+    else {
+        unreachable!()
+    };
+    let columns: Vec<_> = children
+        .into_vec()
+        .into_iter()
+        .filter_map(|n| match n {
+            HNode::Box(b) if contains_page(&b) => Some(VNode::Box(b)),
+            _ => None,
+        })
+        .collect();
+    let num = columns.len() as i32;
+    let gap = (width - columns.iter().map(|c| c.width().0).sum::<i32>()).max(0) / (num - 1);
+    let mut styles = crate::utils::VecMap::default();
+    styles.insert("--rustex-columns".to_string(), num.to_string());
+    styles.insert(
+        "--rustex-column-gap".to_string(),
+        format!("{}px", gap as f32 / 65536.0 * 1.5),
+    );
+    let mut classes = crate::utils::VecSet::default();
+    classes.insert("rustex-multicol".to_string());
+    ret.push(VNode::Custom(RusTeXNode::AnnotBegin {
+        tag: Some("div".to_string()),
+        start,
+        attrs: crate::utils::VecMap::default(),
+        styles,
+        classes,
+    }));
+    ret.extend(get_page_inner(
+        columns,
+        &mut RunningHeads::default(),
+        decorations,
+    ));
+    ret.push(VNode::Custom(RusTeXNode::AnnotEnd(end)));
+}
+
 fn get_page_hbox(children: Box<[HNode<Types>]>, ret: &mut Vec<VNode<Types>>, list: &mut VNodes) {
     for c in children.into_vec().into_iter() {
         match c {
@@ -0,0 +1,177 @@
+//! Page boundaries: if enabled, every shipout starts with an (invisible) marker, which allows
+//! splitting the otherwise continuous output into one node list per page afterwards.
+//!
+//! Running heads: the boxes the output routine puts above and below the box with the main
+//! vertical list of a page are its header and footer (see [`RunningHeads`]).
+
+use crate::engine::nodes::RusTeXNode;
+use crate::engine::Types;
+use crate::shipout::state::{Common, ShipoutNodeV};
+use tex_engine::pdflatex::nodes::PDFNode;
+use tex_engine::tex::nodes::boxes::TeXBox;
+use tex_engine::tex::nodes::horizontal::HNode;
+use tex_engine::tex::nodes::vertical::VNode;
+use tex_engine::tex::nodes::NodeTrait;
+
+pub(crate) const PAGE_MARKER: &str = "<!--rustex-page-->";
+
+/// Splits `nodes` at the page markers. Annotations, colors, fonts etc. that span several
+/// pages are repeated on each of them.
+pub(crate) fn split_pages(nodes: Vec<ShipoutNodeV>) -> Vec<Vec<ShipoutNodeV>> {
+    let mut pages = split_at(nodes, &|s| (s == PAGE_MARKER).then_some(false));
+    if pages.len() > 1 && pages[0].is_empty() {
+        pages.remove(0);
+    }
+    pages
+}
+
+/// Splits `nodes` at the top-level literals for which `boundary` returns `Some(keep)`; the
+/// literal itself starts the next part if `keep` is set, and is dropped otherwise.
+pub(crate) fn split_at(
+    nodes: Vec<ShipoutNodeV>,
+    boundary: &dyn Fn(&str) -> Option<bool>,
+) -> Vec<Vec<ShipoutNodeV>> {
+    let mut ret = vec![Vec::new()];
+    for n in nodes {
+        match n {
+            ShipoutNodeV::Common(Common::Literal(s)) if boundary(&s).is_some() => {
+                let mut part = Vec::new();
+                if boundary(&s) == Some(true) {
+                    part.push(ShipoutNodeV::Common(Common::Literal(s)));
+                }
+                ret.push(part);
+            }
+            ShipoutNodeV::Common(mut c) => {
+                let Some(children) = wrapper_children(&mut c) else {
+                    ret.last_mut().unwrap().push(ShipoutNodeV::Common(c));
+                    continue;
+                };
+                let parts = split_at(std::mem::take(children), boundary);
+                for (i, part) in parts.into_iter().enumerate() {
+                    if i > 0 {
+                        ret.push(Vec::new());
+                    }
+                    if part.is_empty() {
+                        continue;
+                    }
+                    let mut c = c.clone();
+                    if let Some(children) = wrapper_children(&mut c) {
+                        *children = part;
+                    }
+                    ret.last_mut().unwrap().push(ShipoutNodeV::Common(c));
+                }
+            }
+            n => ret.last_mut().unwrap().push(n),
+        }
+    }
+    ret
+}
+
+fn wrapper_children(c: &mut Common<ShipoutNodeV>) -> Option<&mut Vec<ShipoutNodeV>> {
+    match c {
+        Common::WithColor { children, .. }
+        | Common::WithFont { children, .. }
+        | Common::WithLink { children, .. }
+        | Common::WithAnnotation { children, .. }
+        | Common::WithMatrix { children, .. } => Some(children),
+        _ => None,
+    }
+}
+
+/// The headers and footers of the pages (see [`Settings::headers`](crate::engine::Settings::headers)):
+/// dropped, or emitted as `<header>`/`<footer>` elements whenever they differ from the
+/// previous page's - i.e. once per section for running heads like LaTeX's.
+#[derive(Default)]
+pub(crate) struct RunningHeads {
+    pub(crate) emit: bool,
+    /// the last header and footer emitted
+    head: Option<String>,
+    foot: Option<String>,
+}
+impl RunningHeads {
+    pub(crate) fn new(emit: bool) -> Self {
+        Self {
+            emit,
+            ..Self::default()
+        }
+    }
+    /// Sorts the header or footer `bx` (depending on `foot`) into `ret`.
+    pub(crate) fn push(&mut self, bx: TeXBox<Types>, foot: bool, ret: &mut Vec<VNode<Types>>) {
+        if !self.emit || is_blank(&bx) {
+            return;
+        }
+        let key = bx.display().to_string();
+        let last = if foot { &mut self.foot } else { &mut self.head };
+        if last.as_ref() == Some(&key) {
+            return;
+        }
+        *last = Some(key);
+        let (start, end) = match &bx {
+            TeXBox::V { start, end, .. } | TeXBox::H { start, end, .. } => (*start, *end),
+        };
+        let mut classes = crate::utils::VecSet::default();
+        classes.insert(
+            if foot {
+                "rustex-running-foot"
+            } else {
+                "rustex-running-head"
+            }
+            .to_string(),
+        );
+        ret.push(VNode::Custom(RusTeXNode::AnnotBegin {
+            tag: Some(if foot { "footer" } else { "header" }.to_string()),
+            start,
+            attrs: crate::utils::VecMap::default(),
+            styles: crate::utils::VecMap::default(),
+            classes,
+        }));
+        ret.push(VNode::Box(bx));
+        ret.push(VNode::Custom(RusTeXNode::AnnotEnd(end)));
+    }
+}
+
+/// Whether `bx` contains nothing but glue, kerns, penalties, color and font changes and boxes
+/// of the same kind - e.g. the header of a page with an empty `\@oddhead`.
+fn is_blank(bx: &TeXBox<Types>) -> bool {
+    match bx {
+        TeXBox::V { children, .. } => children.iter().all(|n| match n {
+            VNode::Box(b) => is_blank(b),
+            VNode::Custom(n) => is_blank_custom(n),
+            n => matches!(
+                n,
+                VNode::VSkip(_)
+                    | VNode::VFil
+                    | VNode::VFill
+                    | VNode::VFilneg
+                    | VNode::Vss
+                    | VNode::VKern(_)
+                    | VNode::Penalty(_)
+                    | VNode::Mark(..)
+            ),
+        }),
+        TeXBox::H { children, .. } => children.iter().all(|n| match n {
+            HNode::Box(b) => is_blank(b),
+            HNode::Custom(n) => is_blank_custom(n),
+            n => matches!(
+                n,
+                HNode::HSkip(_)
+                    | HNode::Hss
+                    | HNode::Space
+                    | HNode::HKern(_)
+                    | HNode::HFil
+                    | HNode::HFill
+                    | HNode::HFilneg
+                    | HNode::Penalty(_)
+                    | HNode::Mark(..)
+            ),
+        }),
+    }
+}
+fn is_blank_custom(n: &RusTeXNode) -> bool {
+    matches!(
+        n,
+        RusTeXNode::PDFNode(PDFNode::Color(_))
+            | RusTeXNode::FontChange(..)
+            | RusTeXNode::FontChangeEnd
+    )
+}
@@ -0,0 +1,127 @@
+//! Mapping between source positions and the elements of the generated HTML. With
+//! [`Settings::sourcerefs`](crate::engine::Settings::sourcerefs), every element carrying a
+//! `data-rustex-sourceref` attribute also gets an `id` (see [`SourceMap::element_id`]), and the
+//! [`SourceMap`] of the [`CompilationResult`](crate::engine::CompilationResult) answers queries
+//! in both directions.
+
+use crate::engine::files::RusTeXFileSystem;
+use crate::engine::SRef;
+use std::path::{Path, PathBuf};
+use tex_engine::engine::filesystem::FileSystem;
+use tex_engine::utils::HMap;
+
+/// A position recorded with `\pdfsavepos`, in sp from the bottom left corner of the page (as in
+/// pdfTeX), on the page with the given index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SavedPosition {
+    pub page: usize,
+    pub x: i32,
+    pub y: i32,
+}
+
+/// The `\pdfsavepos` positions of a run: those on a page are computed when it is shipped out,
+/// and each one becomes `\pdflastxpos`/`\pdflastypos` when its node is reached while the page
+/// is converted to HTML (i.e. before the `\write`s that follow it are executed).
+#[derive(Debug, Default)]
+pub(crate) struct SavedPositions {
+    page: HMap<usize, SavedPosition>,
+    all: Vec<SavedPosition>,
+}
+impl SavedPositions {
+    pub(crate) fn set_page(&mut self, positions: HMap<usize, SavedPosition>) {
+        self.page = positions;
+    }
+    /// The position of the `\pdfsavepos` with the given number, if it is on the current page.
+    pub(crate) fn resolve(&mut self, id: usize) -> Option<SavedPosition> {
+        let pos = self.page.remove(&id)?;
+        self.all.push(pos);
+        Some(pos)
+    }
+    pub(crate) fn into_vec(self) -> Vec<SavedPosition> {
+        self.all
+    }
+}
+
+/// The source range an output element was produced from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceSpan {
+    pub file: PathBuf,
+    /// (line, column) of the start, both 1-based
+    pub start: (usize, usize),
+    /// (line, column) of the end; equal to `start` if the element ends in another file
+    pub end: (usize, usize),
+}
+impl SourceSpan {
+    pub fn contains_line(&self, line: usize) -> bool {
+        self.start.0 <= line && line <= self.end.0
+    }
+}
+
+/// All source spans of a document, indexed by the number in their element's id.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    spans: Vec<SourceSpan>,
+}
+impl SourceMap {
+    const PREFIX: &'static str = "rustex-src-";
+
+    /// The `id` attribute of the element with the given index.
+    pub fn element_id(idx: usize) -> String {
+        format!("{}{idx}", Self::PREFIX)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+    pub fn spans(&self) -> impl Iterator<Item = (String, &SourceSpan)> {
+        self.spans
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (Self::element_id(i), s))
+    }
+    /// The source span of the element with the given `id`.
+    pub fn source_of(&self, id: &str) -> Option<&SourceSpan> {
+        let idx: usize = id.strip_prefix(Self::PREFIX)?.parse().ok()?;
+        self.spans.get(idx)
+    }
+    /// The ids of all elements whose source covers the given line of `file`, innermost
+    /// (i.e. shortest span) first. Relative paths are taken relative to the working directory.
+    pub fn anchors_at(&self, file: &Path, line: usize) -> Vec<String> {
+        let file = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+        let mut ret: Vec<_> = self
+            .spans
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.file == file && s.contains_line(line))
+            .collect();
+        ret.sort_by_key(|(i, s)| (s.end.0 - s.start.0, s.end.1.abs_diff(s.start.1), *i));
+        ret.into_iter().map(|(i, _)| Self::element_id(i)).collect()
+    }
+
+    /// Resolves the recorded (start, end) references.
+    pub(crate) fn resolve(refs: Vec<(SRef, SRef)>, fs: &RusTeXFileSystem) -> Self {
+        let pwd = &fs.inner.kpse.pwd;
+        let mut files: Vec<(Option<_>, PathBuf)> = Vec::new();
+        let spans = refs
+            .into_iter()
+            .map(|(start, end)| {
+                let file = match files.iter().find(|(id, _)| *id == start.file) {
+                    Some((_, p)) => p.clone(),
+                    None => {
+                        let p = pwd.join(fs.ref_str(start.file));
+                        let p = p.canonicalize().unwrap_or(p);
+                        files.push((start.file, p.clone()));
+                        p
+                    }
+                };
+                let end = if end.file == start.file {
+                    (end.line, end.column).max((start.line, start.column))
+                } else {
+                    (start.line, start.column)
+                };
+                let start = (start.line, start.column);
+                SourceSpan { file, start, end }
+            })
+            .collect();
+        Self { spans }
+    }
+}
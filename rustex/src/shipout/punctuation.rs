@@ -0,0 +1,47 @@
+//! Optional replacement of the ASCII quote and dash conventions of TeX input (``` `` ```, `''`,
+//! `--`, `---`) by the corresponding Unicode punctuation, for fonts whose ligature tables or glyph
+//! maps do not take care of that already (see
+//! [`Settings::smart_punctuation`](crate::engine::Settings::smart_punctuation)).
+//!
+//! Only characters in the same font that directly follow each other are combined - the `\kern`s
+//! LaTeX inserts between characters in `verbatim`-like environments (`\@noligs`) thus prevent
+//! conversion, as do monospaced fonts.
+
+use crate::shipout::state::{CharOrStr, ShipoutNodeH};
+
+/// The character `next` turns into after `prev`, if the two form a ligature.
+fn combine(prev: char, next: char) -> Option<char> {
+    match (prev, next) {
+        ('-', '-') => Some('\u{2013}'),
+        ('\u{2013}', '-') => Some('\u{2014}'),
+        ('\u{2018}', '`') => Some('\u{201C}'),
+        ('\u{2019}', '\'') => Some('\u{201D}'),
+        _ => None,
+    }
+}
+
+/// Applies the conversion to `node`, which follows `last` in the output. Returns the node to
+/// push, or `None` if it has been merged into `last`.
+pub(crate) fn smarten(
+    last: Option<&mut ShipoutNodeH>,
+    adjacent: bool,
+    node: ShipoutNodeH,
+) -> Option<ShipoutNodeH> {
+    let ShipoutNodeH::Char(CharOrStr::Char(c)) = node else {
+        return Some(node);
+    };
+    if adjacent {
+        if let Some(ShipoutNodeH::Char(CharOrStr::Char(prev))) = last {
+            if let Some(r) = combine(*prev, c) {
+                *prev = r;
+                return None;
+            }
+        }
+    }
+    let c = match c {
+        '`' => '\u{2018}',
+        '\'' => '\u{2019}',
+        c => c,
+    };
+    Some(ShipoutNodeH::Char(CharOrStr::Char(c)))
+}
@@ -0,0 +1,211 @@
+//! Source spans of sectional units (`\part` to `\subparagraph`), emitted as empty anchors
+//! with `data-source-*` attributes in front of each heading, so that editors can offer
+//! "edit this section" links.
+
+use crate::engine::files::RusTeXFileSystem;
+use crate::engine::SRef;
+use crate::shipout::state::{
+    Common, ShipoutNodeH, ShipoutNodeHRow, ShipoutNodeT, ShipoutNodeTable, ShipoutNodeV,
+};
+use crate::shipout::utils::escape_html;
+use std::path::{Path, PathBuf};
+use tex_engine::engine::filesystem::FileSystem;
+
+/// The part of the sources a sectional unit spans: from its heading command up to (not
+/// including) the next heading of the same or a higher level in the same file, or to the end
+/// of the file.
+#[derive(Debug, Clone)]
+pub struct SectionSource {
+    /// as in `\@startsection`; `\chapter` is `0` and `\part` is `-1`
+    pub level: i32,
+    pub file: PathBuf,
+    /// first and last line (1-based, inclusive)
+    pub lines: (usize, usize),
+    /// byte range (end exclusive)
+    pub bytes: (usize, usize),
+}
+
+pub(crate) struct SectionStart {
+    pub(crate) level: i32,
+    pub(crate) sref: SRef,
+}
+
+const PLACEHOLDER: &str = "<!--rustex-section:";
+
+pub(crate) fn placeholder(idx: usize) -> String {
+    format!("{PLACEHOLDER}{idx}-->")
+}
+
+/// Computes the spans of the recorded section starts.
+pub(crate) fn resolve(starts: Vec<SectionStart>, fs: &RusTeXFileSystem) -> Vec<SectionSource> {
+    let pwd = fs.inner.kpse.pwd.clone();
+    let mut offsets: Vec<(PathBuf, Vec<usize>)> = Vec::new();
+    let files: Vec<PathBuf> = starts
+        .iter()
+        .map(|s| {
+            let p = pwd.join(fs.ref_str(s.sref.file));
+            p.canonicalize().unwrap_or(p)
+        })
+        .collect();
+    let mut ret = Vec::with_capacity(starts.len());
+    for (i, (start, file)) in starts.iter().zip(files.iter()).enumerate() {
+        let offs = match offsets.iter().position(|(p, _)| p == file) {
+            Some(j) => &offsets[j].1,
+            None => {
+                offsets.push((file.clone(), line_offsets(file)));
+                &offsets[offsets.len() - 1].1
+            }
+        };
+        let total_lines = offs.len().saturating_sub(1);
+        let end = starts[i + 1..]
+            .iter()
+            .zip(files[i + 1..].iter())
+            .find(|(s, f)| *f == file && s.level <= start.level && s.sref.line > start.sref.line)
+            .map_or(total_lines, |(s, _)| s.sref.line - 1)
+            .max(start.sref.line);
+        let byte = |line: usize| {
+            offs.get(line.saturating_sub(1))
+                .copied()
+                .unwrap_or_else(|| offs.last().copied().unwrap_or(0))
+        };
+        ret.push(SectionSource {
+            level: start.level,
+            file: file.clone(),
+            lines: (start.sref.line, end),
+            bytes: (byte(start.sref.line), byte(end + 1)),
+        });
+    }
+    ret
+}
+
+/// Byte offsets of the line starts, plus the file size as last entry.
+fn line_offsets(file: &std::path::Path) -> Vec<usize> {
+    let Ok(bytes) = std::fs::read(file) else {
+        return Vec::new();
+    };
+    std::iter::once(0)
+        .chain(
+            bytes
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| **b == b'\n')
+                .map(|(i, _)| i + 1),
+        )
+        .filter(|i| *i < bytes.len())
+        .chain(std::iter::once(bytes.len()))
+        .collect()
+}
+
+/// Replaces the placeholders emitted by `\rustex@sectionStart` by the anchors. Their
+/// `data-source-file` is relative to `base`, the directory of the main file.
+pub(crate) fn insert_anchors(nodes: &mut [ShipoutNodeV], sections: &[SectionSource], base: &Path) {
+    let base = base.canonicalize().unwrap_or_else(|_| base.to_path_buf());
+    let sections: Vec<SectionSource> = sections
+        .iter()
+        .map(|s| SectionSource {
+            file: relative_path(&s.file, &base),
+            ..s.clone()
+        })
+        .collect();
+    anchors_v(nodes, &sections)
+}
+
+/// `file` relative to `base`, going up with `..` if necessary.
+pub(crate) fn relative_path(file: &Path, base: &Path) -> PathBuf {
+    base.ancestors()
+        .enumerate()
+        .find_map(|(up, dir)| {
+            let rest = file.strip_prefix(dir).ok()?;
+            let mut ret: PathBuf = std::iter::repeat("..").take(up).collect();
+            ret.push(rest);
+            Some(ret)
+        })
+        .unwrap_or_else(|| file.to_path_buf())
+}
+
+fn anchors_v(nodes: &mut [ShipoutNodeV], sections: &[SectionSource]) {
+    for n in nodes {
+        match n {
+            ShipoutNodeV::Paragraph { children, .. } => anchors_h(children, sections),
+            ShipoutNodeV::HAlign { children, .. } => anchors_table(children, sections),
+            ShipoutNodeV::Common(c) => anchors_common(c, sections, anchors_v),
+            ShipoutNodeV::KernSkip(_) | ShipoutNodeV::HRule { .. } => (),
+        }
+    }
+}
+
+fn anchors_table(nodes: &mut [ShipoutNodeTable], sections: &[SectionSource]) {
+    for n in nodes {
+        match n {
+            ShipoutNodeTable::Row { children, .. } => anchors_row(children, sections),
+            ShipoutNodeTable::NoAlign { children, .. } => anchors_v(children, sections),
+            ShipoutNodeTable::Common(c) => anchors_common(c, sections, anchors_table),
+        }
+    }
+}
+
+fn anchors_row(nodes: &mut [ShipoutNodeHRow], sections: &[SectionSource]) {
+    for n in nodes {
+        match n {
+            ShipoutNodeHRow::Cell { children, .. } => anchors_h(children, sections),
+            ShipoutNodeHRow::Common(c) => anchors_common(c, sections, anchors_row),
+        }
+    }
+}
+
+fn anchors_h(nodes: &mut [ShipoutNodeH], sections: &[SectionSource]) {
+    for n in nodes {
+        if let ShipoutNodeH::Common(c) = n {
+            anchors_common(c, sections, anchors_h);
+        }
+    }
+}
+
+fn anchors_common<T: ShipoutNodeT>(
+    node: &mut Common<T>,
+    sections: &[SectionSource],
+    f: fn(&mut [T], &[SectionSource]),
+) {
+    match node {
+        Common::Literal(s) => {
+            if let Some(idx) = s
+                .strip_prefix(PLACEHOLDER)
+                .and_then(|r| r.strip_suffix("-->"))
+                .and_then(|i| i.parse::<usize>().ok())
+            {
+                *s = sections
+                    .get(idx)
+                    .map_or_else(String::new, |sec| anchor(idx, sec));
+            }
+        }
+        Common::WithColor { children, .. }
+        | Common::WithFont { children, .. }
+        | Common::WithLink { children, .. }
+        | Common::WithAnnotation { children, .. }
+        | Common::WithMatrix { children, .. } => f(children, sections),
+        Common::VBox { children, .. } => anchors_v(children, sections),
+        Common::HBox { children, .. } => anchors_h(children, sections),
+        Common::PDFDest(_) | Common::SVG { .. } => (),
+    }
+}
+
+const ANCHOR_PREFIX: &str = "<a class=\"rustex-section-source\" id=\"rustex-section-";
+
+/// The index of the section whose anchor is `literal`, if it is one.
+pub(crate) fn anchor_index(literal: &str) -> Option<usize> {
+    let rest = literal.strip_prefix(ANCHOR_PREFIX)?;
+    rest[..rest.find('"')?].parse().ok()
+}
+
+fn anchor(idx: usize, sec: &SectionSource) -> String {
+    format!(
+        "{ANCHOR_PREFIX}{idx}\" data-section-level=\"{}\" \
+         data-source-file=\"{}\" data-source-lines=\"{}-{}\" data-source-bytes=\"{}-{}\"></a>",
+        sec.level,
+        escape_html(&sec.file.display().to_string()),
+        sec.lines.0,
+        sec.lines.1,
+        sec.bytes.0,
+        sec.bytes.1
+    )
+}
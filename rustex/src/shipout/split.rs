@@ -0,0 +1,148 @@
+//! Splitting the output into several HTML files at sectioning boundaries (see
+//! [`Settings::split_at`](crate::engine::Settings::split_at)): everything before the first
+//! boundary goes into an index page together with a table of contents, every unit gets its own
+//! file with links to the previous and next unit and back to the index.
+
+use crate::shipout::pages::{self, PAGE_MARKER};
+use crate::shipout::sections::{self, SectionSource};
+use crate::shipout::state::{Common, ShipoutNodeV};
+
+/// Where to split the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitUnit {
+    Part,
+    Chapter,
+    Section,
+    Subsection,
+    /// every shipped out page, i.e. every `beamer` frame
+    Frame,
+}
+impl SplitUnit {
+    /// `part`, `chapter`, `section`, `subsection` or `frame`
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "part" => Self::Part,
+            "chapter" => Self::Chapter,
+            "section" => Self::Section,
+            "subsection" => Self::Subsection,
+            "frame" => Self::Frame,
+            _ => return None,
+        })
+    }
+    /// The sectioning level (as in [`SectionSource::level`]) up to which sectional units start
+    /// a new file; `None` for [`Frame`](Self::Frame), which splits at page boundaries instead.
+    pub fn level(self) -> Option<i32> {
+        match self {
+            Self::Part => Some(-1),
+            Self::Chapter => Some(0),
+            Self::Section => Some(1),
+            Self::Subsection => Some(2),
+            Self::Frame => None,
+        }
+    }
+}
+
+/// Splits `nodes` into the front matter and one node list per unit.
+pub(crate) fn split(
+    nodes: Vec<ShipoutNodeV>,
+    sections: &[SectionSource],
+    unit: SplitUnit,
+) -> (Vec<ShipoutNodeV>, Vec<Vec<ShipoutNodeV>>) {
+    let mut parts = match unit.level() {
+        None => pages::split_at(nodes, &|s| (s == PAGE_MARKER).then_some(false)),
+        Some(level) => pages::split_at(nodes, &|s| {
+            let sec = sections.get(sections::anchor_index(s)?)?;
+            (sec.level <= level).then_some(true)
+        }),
+    };
+    let front = parts.remove(0);
+    (front, parts)
+}
+
+/// The sectioning level of the unit, if it starts with a section anchor.
+pub(crate) fn level(part: &[ShipoutNodeV], sections: &[SectionSource]) -> Option<i32> {
+    match part.first() {
+        Some(ShipoutNodeV::Common(Common::Literal(s))) => {
+            sections.get(sections::anchor_index(s)?).map(|s| s.level)
+        }
+        _ => None,
+    }
+}
+
+/// `name` with `-<index>` inserted before the extension, e.g. `notes-3.html`.
+pub(crate) fn file_name(name: &str, index: usize) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) => format!("{stem}-{index}.{ext}"),
+        None => format!("{name}-{index}"),
+    }
+}
+
+/// The text content of an HTML fragment (still escaped), with whitespace collapsed and cut
+/// off after `max` characters.
+pub(crate) fn plain_text(html: &str, max: usize) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if in_tag => (),
+            c => text.push(c),
+        }
+    }
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut ret = String::new();
+    for w in words {
+        if ret.chars().count() + w.chars().count() > max {
+            ret.push('…');
+            break;
+        }
+        if !ret.is_empty() {
+            ret.push(' ');
+        }
+        ret.push_str(w);
+    }
+    ret
+}
+
+/// The navigation bar of a unit; `prev` and `next` are file names and titles.
+pub(crate) fn navigation(
+    prev: Option<(&str, &str)>,
+    index: &str,
+    next: Option<(&str, &str)>,
+) -> String {
+    let mut ret = String::from("<nav class=\"rustex-split-nav\">");
+    if let Some((file, title)) = prev {
+        ret.push_str(&format!(
+            "<a class=\"rustex-split-prev\" rel=\"prev\" href=\"{file}\">« {title}</a>"
+        ));
+    }
+    ret.push_str(&format!(
+        "<a class=\"rustex-split-index\" rel=\"index\" href=\"{index}\">Contents</a>"
+    ));
+    if let Some((file, title)) = next {
+        ret.push_str(&format!(
+            "<a class=\"rustex-split-next\" rel=\"next\" href=\"{file}\">{title} »</a>"
+        ));
+    }
+    ret.push_str("</nav>");
+    ret
+}
+
+/// The table of contents of the index page, from the file names, titles and levels of all
+/// units.
+pub(crate) fn table_of_contents(units: &[(String, String, Option<i32>)]) -> String {
+    let min = units.iter().filter_map(|u| u.2).min().unwrap_or(0);
+    let mut ret = String::from("<nav class=\"rustex-split-toc\"><ul>");
+    for (file, title, level) in units {
+        ret.push_str(&format!(
+            "<li style=\"margin-left:{}em\"><a href=\"{file}\">{title}</a></li>",
+            2 * (level.unwrap_or(min) - min)
+        ));
+    }
+    ret.push_str("</ul></nav>");
+    ret
+}
@@ -6,10 +6,12 @@ use std::collections::hash_map::Entry;
 use std::fmt::{Display, Formatter, Write};
 use std::ops::{Add, AddAssign};
 use tex_engine::commands::primitives::PRIMITIVES;
+use tex_engine::commands::TeXCommand;
 use tex_engine::engine::filesystem::FileSystem;
 use tex_engine::engine::fontsystem::{Font as FontTrait, FontSystem};
 use tex_engine::engine::state::State;
 use tex_engine::engine::stomach::methods::ParLineSpec;
+use tex_engine::engine::utils::approximations::ApproximationKind;
 use tex_engine::pdflatex::nodes::{
     ActionSpec, ColorStackAction, GotoAction, NumOrName, PDFColor, PDFExtension, PDFStartLink,
     PDFXImage,
@@ -17,14 +19,18 @@ use tex_engine::pdflatex::nodes::{
 use tex_engine::prelude::{HNode, MathNode, VNode};
 use tex_engine::tex::nodes::boxes::{HBoxInfo, ToOrSpread, VBoxInfo};
 use tex_engine::tex::nodes::math::{MathClass, MathFontStyle, MathStyleType};
+use tex_engine::tex::nodes::{LeaderBody, LeaderSkip, LeaderType, Leaders, NodeTrait};
 use tex_engine::tex::numerics::{Dim32, Skip, StretchShrink};
+use tex_engine::tex::tokens::control_sequences::CSHandler;
 use tex_engine::utils::{HMap, HSet};
 use tex_glyphs::fontstyles::ModifierSeq;
 use tex_glyphs::glyphs::{Glyph, GlyphName};
 //use crate::shipout::html::{HTMLChild, HTMLNode};
-use crate::engine::nodes::LineSkip;
+use crate::engine::nodes::{BoxVisibility, ExternalNode, LineSkip};
 use crate::shipout::annotations;
+use crate::shipout::positions::SourceMap;
 use crate::shipout::utils::VNodes;
+use crate::shipout::verbatim;
 use crate::utils::{Margin, VecMap, VecSet};
 
 mod sealed {
@@ -141,10 +147,26 @@ pub(crate) struct ShipoutState {
     pub(crate) top_font: Option<Font>,
     pub(crate) top_width: Option<i32>,
     pub(crate) page_width: Option<i32>,
+    /// the layout registers of every page shipped out so far
+    pub(crate) layouts: Vec<crate::shipout::layout::PageLayout>,
+    /// the input files formulas are taken from if
+    /// [`Settings::math_renderer`](crate::engine::Settings::math_renderer) is set
+    pub(crate) tex_sources: Option<crate::shipout::client_math::Sources>,
     nullfont: Option<Font>,
     pub(crate) previous: Vec<(ShipoutNodes, ShipoutWrapper)>,
     pub(crate) wrapper: ShipoutWrapper,
     //wrappers: Vec<ShipoutWrapper>,
+    /// the (start, end) of every [`SourceRef`] created so far, if recorded (see
+    /// [`SourceMap`](crate::shipout::positions::SourceMap))
+    pub(crate) sources: Option<Vec<(SRef, SRef)>>,
+    /// the places the currently open [`RusTeXNode::ProvenanceBegin`](crate::engine::nodes::RusTeXNode::ProvenanceBegin)s
+    /// were unpacked at, if recorded (see
+    /// [`Settings::provenance_via`](crate::engine::Settings::provenance_via))
+    pub(crate) via: Option<Vec<String>>,
+    /// the color stacks that carry ExtGState opacities rather than colors
+    pub(crate) opacity_stacks: HSet<usize>,
+    /// the [`BoxVisibility`] of the `\hbox` currently being shipped out, if any
+    pub(crate) box_visibility: Option<BoxVisibility>,
     pub(crate) font_data: HMap<Box<str>, FontData>, /*
                                                     pub(crate) output:Vec<HTMLChild>,
                                                     pub(crate) nodes:Vec<HTMLNode>,
@@ -162,6 +184,7 @@ pub(crate) struct ShipoutState {
 #[derive(Default, Clone, Debug)]
 pub(crate) enum ShipoutWrapper {
     Color(PDFColor),
+    Opacity(f32),
     Font(Font),
     Link(String),
     Annotation {
@@ -188,6 +211,7 @@ impl ShipoutWrapper {
     fn kind(&self) -> Option<WrapperKind> {
         match self {
             ShipoutWrapper::Color(..) => Some(WrapperKind::Color),
+            ShipoutWrapper::Opacity(..) => Some(WrapperKind::Opacity),
             ShipoutWrapper::Font(..) => Some(WrapperKind::Font),
             ShipoutWrapper::Link(..) => Some(WrapperKind::Link),
             ShipoutWrapper::Annotation { .. } => Some(WrapperKind::Annotation),
@@ -211,8 +235,31 @@ impl ShipoutWrapper {
     ) -> Result<Common<Node>, Vec<Node>> {
         match self {
             ShipoutWrapper::Color(c) => Common::with_color(c, nodes),
+            ShipoutWrapper::Opacity(o) => {
+                let mut styles = VecMap::default();
+                styles.insert("opacity".into(), o.to_string().into());
+                Ok(Common::with_annotation(
+                    VecMap::default(),
+                    styles,
+                    VecSet::default(),
+                    None,
+                    nodes,
+                ))
+            }
             ShipoutWrapper::Font(f) => Common::with_font(engine, fonts, f, nodes),
             ShipoutWrapper::Link(href) => Ok(Common::with_link(href, nodes)),
+            ShipoutWrapper::Annotation {
+                attrs,
+                styles,
+                classes,
+                ..
+            } if classes.contains(&verbatim::CLASS.into()) => Ok(Common::Literal(verbatim::html(
+                &attrs,
+                &styles,
+                classes,
+                Node::into_nodes(nodes),
+                &mut engine.aux.extension.highlighter,
+            ))),
             ShipoutWrapper::Annotation {
                 attrs,
                 styles,
@@ -238,6 +285,12 @@ impl ShipoutWrapper {
     ) -> Result<ShipoutNodeSVG, Vec<ShipoutNodeSVG>> {
         match self {
             ShipoutWrapper::SVG { tag, attrs } => Ok(ShipoutNodeSVG::svg_node(tag, attrs, nodes)),
+            ShipoutWrapper::Opacity(o) => {
+                let mut attrs = VecMap::default();
+                attrs.insert("fill-opacity", o.to_string());
+                attrs.insert("stroke-opacity", o.to_string());
+                Ok(ShipoutNodeSVG::svg_node("g".to_string(), attrs, nodes))
+            }
             _ => self.close_ii(engine, fonts, nodes).map(|e| e.into()),
         }
     }
@@ -257,6 +310,7 @@ impl ShipoutWrapper {
             match self.kind() {
                 Some(
                     WrapperKind::Color
+                    | WrapperKind::Opacity
                     | WrapperKind::Annotation
                     | WrapperKind::Font
                     | WrapperKind::Link,
@@ -308,6 +362,7 @@ impl ShipoutWrapper {
 #[derive(Copy, Clone, PartialEq, Eq)]
 enum WrapperKind {
     Color,
+    Opacity,
     Font,
     Link,
     Annotation,
@@ -317,7 +372,7 @@ enum WrapperKind {
 impl WrapperKind {
     fn prec(self) -> u8 {
         match self {
-            WrapperKind::Color | WrapperKind::Font => 1,
+            WrapperKind::Color | WrapperKind::Opacity | WrapperKind::Font => 1,
             WrapperKind::Link => 5,
             WrapperKind::Annotation => 10,
             WrapperKind::SVG => 15,
@@ -330,6 +385,7 @@ impl PartialEq<ShipoutWrapper> for WrapperKind {
     fn eq(&self, other: &ShipoutWrapper) -> bool {
         match self {
             WrapperKind::Color => matches!(other, ShipoutWrapper::Color(..)),
+            WrapperKind::Opacity => matches!(other, ShipoutWrapper::Opacity(..)),
             WrapperKind::Font => matches!(other, ShipoutWrapper::Font(..)),
             WrapperKind::Link => matches!(other, ShipoutWrapper::Link(..)),
             WrapperKind::Annotation => matches!(other, ShipoutWrapper::Annotation { .. }),
@@ -527,7 +583,7 @@ impl<'a, 'b, Mode: ShipoutModeT> Shipout<'a, 'b, Mode> {
         let (r, nodes, uses_color, uses_font) = self.do_in(|| V, f);
         self.nodes.push(
             Common::VBox {
-                sref: SourceRef::new(start, end, self.engine),
+                sref: self.top_state.sourceref(start, end, self.engine),
                 info,
                 children: nodes,
                 uses_color,
@@ -546,13 +602,16 @@ impl<'a, 'b, Mode: ShipoutModeT> Shipout<'a, 'b, Mode> {
         preskip: Option<Skip<Dim32>>,
         f: impl FnOnce(&mut Shipout<H>) -> Result<R, Option<HNode<Types>>>,
     ) -> Result<R, Option<HNode<Types>>> {
+        let outer = self.top_state.box_visibility.take();
         let (r, nodes, uses_color, uses_font) = self.do_in(|| H, f);
+        let visibility = std::mem::replace(&mut self.top_state.box_visibility, outer);
         self.nodes.push(
             Common::HBox {
-                sref: SourceRef::new(start, end, self.engine),
+                sref: self.top_state.sourceref(start, end, self.engine),
                 info,
                 children: nodes,
                 preskip: preskip.map(|r| r.into()),
+                visibility,
                 uses_color,
                 uses_font,
             }
@@ -569,6 +628,7 @@ impl<'a, 'b, Mode: ShipoutModeT> Shipout<'a, 'b, Mode> {
         miny: i32,
         maxx: i32,
         maxy: i32,
+        picture: usize,
         f: impl FnOnce(&mut Shipout<SVG>) -> Result<R, Option<VNode<Types>>>,
     ) -> Result<R, Option<VNode<Types>>> {
         let (r, nodes, uses_color, uses_font) = self.do_in(
@@ -586,7 +646,8 @@ impl<'a, 'b, Mode: ShipoutModeT> Shipout<'a, 'b, Mode> {
                 miny,
                 maxx,
                 maxy,
-                sref: SourceRef::new(start, end, self.engine),
+                sref: self.top_state.sourceref(start, end, self.engine),
+                caption: self.engine.aux.extension.captions.get(picture).cloned(),
                 uses_color,
                 uses_font,
             }
@@ -698,6 +759,11 @@ impl<'a, 'b, Mode: ShipoutModeT> Shipout<'a, 'b, Mode> {
     pub(crate) fn close_matrix(&mut self) {
         ShipoutWrapper::close(self, WrapperKind::Matrix)
     }
+    pub(crate) fn save_pos(&mut self, id: usize) {
+        if let Some(pos) = self.engine.aux.extension.saved_positions.resolve(id) {
+            self.engine.aux.extension.positions().last = (pos.x, pos.y);
+        }
+    }
     #[inline(always)]
     pub(crate) fn do_color(&mut self, act: ColorStackAction) {
         let stack = self.engine.aux.extension.colorstacks();
@@ -722,6 +788,29 @@ impl<'a, 'b, Mode: ShipoutModeT> Shipout<'a, 'b, Mode> {
                     ))
                 }
             }
+            // opacity stacks (e.g. from the transparent package) only keep their depth in the
+            // color stack; the opacities themselves live in the wrappers
+            ColorStackAction::SetOpacity(idx, o) => {
+                self.top_state.opacity_stacks.insert(idx);
+                let oldwrap = std::mem::replace(&mut self.wrapper, ShipoutWrapper::Opacity(o));
+                self.previous.push((
+                    Mode::NodeType::into_nodes(std::mem::take(&mut self.nodes)),
+                    oldwrap,
+                ))
+            }
+            ColorStackAction::PushOpacity(idx, o) => {
+                self.top_state.opacity_stacks.insert(idx);
+                stack[idx].push(PDFColor::black());
+                let oldwrap = std::mem::replace(&mut self.wrapper, ShipoutWrapper::Opacity(o));
+                self.previous.push((
+                    Mode::NodeType::into_nodes(std::mem::take(&mut self.nodes)),
+                    oldwrap,
+                ))
+            }
+            ColorStackAction::Pop(idx) if self.top_state.opacity_stacks.contains(&idx) => {
+                stack[idx].pop();
+                ShipoutWrapper::close(self, WrapperKind::Opacity)
+            }
             ColorStackAction::Pop(idx) => {
                 stack[idx].pop();
                 if *self.engine.aux.extension.current_colorstack() == idx {
@@ -729,12 +818,48 @@ impl<'a, 'b, Mode: ShipoutModeT> Shipout<'a, 'b, Mode> {
                 }
             }
             ColorStackAction::Current(idx) => {
-                if *self.engine.aux.extension.current_colorstack() != idx {
+                if *self.engine.aux.extension.current_colorstack() != idx
+                    && !self.top_state.opacity_stacks.contains(&idx)
+                {
                     todo!()
                 }
             }
         }
     }
+    /// Wraps the contents of an unpacked box in an element carrying the box's source reference
+    /// (and, if requested, the chain of places it was unpacked at, innermost first).
+    pub(crate) fn open_provenance(&mut self, start: SRef, end: SRef, via: SRef) {
+        let sr = self.top_state.sourceref(start, end, self.engine);
+        let mut attrs = VecMap::default();
+        if let Some(id) = sr.id {
+            attrs.insert("id".into(), SourceMap::element_id(id).into());
+        }
+        attrs.insert("data-rustex-sourceref".into(), sr.to_string().into());
+        if let Some(chain) = &mut self.top_state.via {
+            chain.push(SourceRef::new(via, via, self.engine).to_string());
+            let chain: Vec<_> = chain.iter().rev().map(String::as_str).collect();
+            attrs.insert("data-rustex-via".into(), chain.join(" ").into());
+        }
+        let mut classes = VecSet::default();
+        classes.insert("rustex-contents".into());
+        classes.insert("rustex-provenance".into());
+        self.open_annot(start, attrs, VecMap::default(), classes, None)
+    }
+    pub(crate) fn close_provenance(&mut self) {
+        if let Some(chain) = &mut self.top_state.via {
+            chain.pop();
+        }
+        ShipoutWrapper::close(self, WrapperKind::Annotation)
+    }
+    pub(crate) fn do_external(&mut self, node: ExternalNode) {
+        match self.engine.aux.extension.external_nodes.render(&node) {
+            Some(html) => self.push(Common::Literal(html).into()),
+            None => self.engine.approximation(
+                ApproximationKind::DroppedNode,
+                format!("{} without shipout handler", node.type_name()),
+            ),
+        }
+    }
 }
 impl<'a, 'b, Mode: VLike> Shipout<'a, 'b, Mode> {
     pub(crate) fn skipv(&mut self, skip: Margin) {
@@ -769,7 +894,7 @@ impl<'a, 'b, Mode: VLike> Shipout<'a, 'b, Mode> {
             },
         );
         *num_cols = (*num_cols).max(nc);
-        let sref = SourceRef::new(start, end, self.engine);
+        let sref = self.top_state.sourceref(start, end, self.engine);
         children.push(ShipoutNodeTable::Row {
             sref,
             num_cols: nc,
@@ -816,7 +941,7 @@ impl<'a, 'b, Mode: VLike> Shipout<'a, 'b, Mode> {
         let align: Alignment = Alignment::from(spec.leftskip, spec.rightskip);
 
         let p = ShipoutNodeV::Paragraph {
-            sref: SourceRef::new(start, end, self.engine),
+            sref: self.top_state.sourceref(start, end, self.engine),
             width: spec.target.0,
             left_skip: spec.leftskip.into(),
             right_skip: spec.rightskip.into(),
@@ -859,6 +984,33 @@ impl<'a, 'b, Mode: HLike> Shipout<'a, 'b, Mode> {
             self.push(ShipoutNodeH::KernSkip(skip))
         }
     }
+    /// Horizontal leaders; a box body is shipped out once and repeated in the output.
+    pub(crate) fn leaders(&mut self, leaders: Leaders<Types>) -> Result<(), Option<HNode<Types>>> {
+        let skip = match leaders.skip {
+            LeaderSkip::HSkip(s) => s.into(),
+            LeaderSkip::HFil => Margin::fil(),
+            LeaderSkip::HFill => Margin::fill(),
+            _ => return Ok(()),
+        };
+        let body = match leaders.body {
+            LeaderBody::Rule { height, depth, .. } => LeadersBody::Rule { height, depth },
+            LeaderBody::Box(bx) => {
+                let width = bx.width().0;
+                let (r, children, _, _) = self.do_in(
+                    || H,
+                    |state| state.do_hlist(&mut vec![HNode::Box(bx)].into()),
+                );
+                r?;
+                LeadersBody::Box {
+                    tp: leaders.tp,
+                    width,
+                    children,
+                }
+            }
+        };
+        self.push(ShipoutNodeH::Leaders { skip, body });
+        Ok(())
+    }
 
     #[inline(always)]
     pub(crate) fn in_math<R>(
@@ -869,10 +1021,24 @@ impl<'a, 'b, Mode: HLike> Shipout<'a, 'b, Mode> {
         f: impl FnOnce(&mut Shipout<Math>) -> Result<R, Option<MathNode<Types, MathFontStyle<Types>>>>,
     ) -> Result<R, Option<MathNode<Types, MathFontStyle<Types>>>> {
         let (r, nodes, uses_color, uses_font) = self.do_in(|| Math, f);
+        if let Some(sources) = &mut self.top_state.tex_sources {
+            if let Some(tex) = sources.formula(start, end, &self.engine.filesystem) {
+                let html = crate::shipout::client_math::html(&tex, display.is_some());
+                self.nodes.push(Common::Literal(html).into());
+                return r;
+            }
+        }
+        let (fleqn, axis_shift) = if display.is_some() {
+            (fleqn_indent(self.engine), None)
+        } else {
+            (None, axis_shift(self.engine))
+        };
         self.nodes.push(
             ShipoutNodeH::Math {
-                sref: SourceRef::new(start, end, self.engine),
+                sref: self.top_state.sourceref(start, end, self.engine),
                 display,
+                fleqn,
+                axis_shift,
                 children: nodes,
                 uses_color,
                 uses_font,
@@ -913,7 +1079,7 @@ impl<'a, 'b> Shipout<'a, 'b, Table> {
         );
         self.state.num_cols = self.state.num_cols.max(num_cols);
         self.nodes.push(ShipoutNodeTable::Row {
-            sref: SourceRef::new(start, end, self.engine),
+            sref: self.top_state.sourceref(start, end, self.engine),
             num_cols,
             children: nodes,
             uses_color,
@@ -935,7 +1101,7 @@ impl<'a, 'b> Shipout<'a, 'b, Row> {
         let (r, nodes, uses_color, uses_font) = self.do_in(|| H, f);
         self.state.num_cols += spans + 1;
         self.nodes.push(ShipoutNodeHRow::Cell {
-            sref: SourceRef::new(start, end, self.engine),
+            sref: self.top_state.sourceref(start, end, self.engine),
             spans: spans + 1,
             children: nodes,
             uses_color,
@@ -951,10 +1117,26 @@ impl<'a, 'b> Shipout<'a, 'b, SVG> {
                 *v = Self::strtonum(v)
             } else if *k == "d" {
                 *v = Self::parse_path(v)
-            } else if *k == "transform" {
+            } else if *k == "transform" || *k == "patternTransform" {
                 *v = Self::parse_transform(v)
             }
         }
+        // pattern tiles given in user space: the tile's contents are flipped like everything
+        // else, so the tile extends from its origin upwards
+        if tag == "pattern"
+            && attrs
+                .get(&"patternUnits")
+                .is_some_and(|u| u == "userSpaceOnUse")
+        {
+            let height = attrs.get(&"height").map(|h| Self::strtonum(h));
+            if let Some(width) = attrs.get_mut(&"width") {
+                *width = Self::strtonum(width)
+            }
+            if let Some(height) = height {
+                attrs.insert("y", format!("-{height}"));
+                attrs.insert("height", height);
+            }
+        }
         let oldwrap = std::mem::replace(&mut self.wrapper, ShipoutWrapper::SVG { tag, attrs });
         self.previous.push((
             ShipoutNodeSVG::into_nodes(std::mem::take(&mut self.nodes)),
@@ -1299,7 +1481,7 @@ impl<'a, 'b> Shipout<'a, 'b, Math> {
             Ok(cos) => Ok(cos),
             Err(glyph) => Err((glyph.to_string().into(), char, fs.font.filename().into())),
         });
-        let sref = SourceRef::new(start, end, self.engine);
+        let sref = self.top_state.sourceref(start, end, self.engine);
         self.push(ShipoutNodeM::Over {
             top,
             bottom,
@@ -1336,7 +1518,7 @@ impl<'a, 'b> Shipout<'a, 'b, Math> {
             Ok(cos) => Ok(cos),
             Err(glyph) => Err((glyph.to_string().into(), char, fs.font.filename().into())),
         });
-        let sref = SourceRef::new(start, end, self.engine);
+        let sref = self.top_state.sourceref(start, end, self.engine);
         self.do_sub_sup(
             ShipoutNodeM::LeftRight {
                 children,
@@ -1460,7 +1642,7 @@ impl<'a, 'b> Shipout<'a, 'b, Math> {
         } else {
             None
         };
-        let sref = SourceRef::new(start, end, self.engine);
+        let sref = self.top_state.sourceref(start, end, self.engine);
         self.do_sub_sup(
             ShipoutNodeM::VCenter {
                 sref,
@@ -1484,8 +1666,11 @@ impl<'a, 'b> Shipout<'a, 'b, Math> {
         };
         let glyphtable = fs.get_glyphlist(fontname);
         let glyph = glyphtable.get(char);
+        data.use_char(char);
         if !glyph.is_defined() {
-            data.missing_glyph(glyph.name(), char);
+            if data.missing_glyph(glyph.name(), char) {
+                missing_glyph_approximation(self.engine, glyph.name(), char, fontname);
+            }
             return Err(glyph.name().to_string());
         }
         let modifiers = data.modifiers;
@@ -1523,9 +1708,15 @@ impl Default for ShipoutState {
             top_font: None,
             top_width: None,
             page_width: None,
+            layouts: Vec::new(),
+            tex_sources: None,
             //wrappers: Vec::new(),
             wrapper: ShipoutWrapper::None,
             previous: Vec::new(),
+            sources: None,
+            via: None,
+            opacity_stacks: HSet::default(),
+            box_visibility: None,
             font_data: HMap::default(),
             /*
             output:Vec::new(),
@@ -1544,6 +1735,14 @@ impl Default for ShipoutState {
     }
 }
 impl ShipoutState {
+    pub(crate) fn sourceref(&mut self, start: SRef, end: SRef, engine: Refs) -> SourceRef {
+        let mut ret = SourceRef::new(start, end, engine);
+        if let Some(sources) = &mut self.sources {
+            ret.id = Some(sources.len());
+            sources.push((start, end));
+        }
+        ret
+    }
     /*
     #[inline]
     pub fn mode(&self) -> ShipoutMode {
@@ -1665,11 +1864,13 @@ impl ShipoutState {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub(crate) struct SourceRef {
     file: Box<str>,
     start: (usize, usize),
     end: (usize, usize),
+    /// index in the [`SourceMap`](crate::shipout::positions::SourceMap), if recorded
+    pub(crate) id: Option<usize>,
 }
 impl SourceRef {
     pub fn new(start: SRef, end: SRef, engine: Refs) -> Self {
@@ -1680,7 +1881,17 @@ impl SourceRef {
             .into_boxed_str();
         let start = (start.line, start.column);
         let end = (end.line, end.column);
-        Self { file, start, end }
+        Self {
+            file,
+            start,
+            end,
+            id: None,
+        }
+    }
+    /// The file (relative to the working directory) and the (line, column) positions of the
+    /// start and end.
+    pub(crate) fn span(&self) -> (&str, (usize, usize), (usize, usize)) {
+        (&self.file, self.start, self.end)
     }
 }
 impl Display for SourceRef {
@@ -1811,11 +2022,36 @@ pub(crate) enum ShipoutNodeH {
     Math {
         sref: SourceRef,
         display: Option<(Margin, Margin)>,
+        /// for display math under `fleqn`, the `\mathindent` to indent by instead of centering
+        fleqn: Option<i32>,
+        /// for inline math, how far (in em) to raise the formula so that the math axis of the
+        /// browser's math font matches the one of TeX's symbol font
+        axis_shift: Option<f32>,
         children: Vec<ShipoutNodeM>,
         uses_color: bool,
         uses_font: bool,
     },
     Img(PDFXImage<Types>),
+    Leaders {
+        skip: Margin,
+        body: LeadersBody,
+    },
+}
+
+/// What [`ShipoutNodeH::Leaders`] are filled with.
+#[derive(Clone)]
+pub(crate) enum LeadersBody {
+    /// A rule; missing dimensions are those of the surrounding box.
+    Rule {
+        height: Option<Dim32>,
+        depth: Option<Dim32>,
+    },
+    /// Copies of a box of the given width.
+    Box {
+        tp: LeaderType,
+        width: i32,
+        children: Vec<ShipoutNodeH>,
+    },
 }
 impl std::fmt::Debug for ShipoutNodeH {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -1834,9 +2070,58 @@ impl std::fmt::Debug for ShipoutNodeH {
             ShipoutNodeH::Indent(i) => write!(f, "Indent({})", i),
             ShipoutNodeH::Math { children, .. } => write!(f, "<{:?}>", children),
             ShipoutNodeH::Img(img) => write!(f, "Img({})", img.filepath.display()),
+            ShipoutNodeH::Leaders {
+                skip,
+                body: LeadersBody::Rule { .. },
+            } => write!(f, "Leaders({},rule)", skip.base),
+            ShipoutNodeH::Leaders {
+                skip,
+                body: LeadersBody::Box { children, .. },
+            } => write!(f, "Leaders({},{:?})", skip.base, children),
         }
     }
 }
+fn missing_glyph_approximation(engine: Refs, name: GlyphName, char: u8, font: &str) {
+    engine.approximation(
+        ApproximationKind::MissingGlyph,
+        format!("glyph {name} (position {char}) in font {font}"),
+    );
+}
+
+/// `Some(\mathindent)` if LaTeX's `fleqn` option is in effect, i.e. `\if@fleqn` is `\iftrue`.
+fn fleqn_indent(engine: Refs) -> Option<i32> {
+    let cs = engine.aux.memory.cs_interner_mut().cs_from_str("if@fleqn");
+    match engine.state.get_command(&cs) {
+        Some(TeXCommand::Primitive { name, .. }) if *name == PRIMITIVES.iftrue => (),
+        _ => return None,
+    }
+    let cs = engine
+        .aux
+        .memory
+        .cs_interner_mut()
+        .cs_from_str("mathindent");
+    match engine.state.get_command(&cs) {
+        Some(TeXCommand::DimRegister(idx)) => Some(engine.state.get_dim_register(*idx).0),
+        _ => Some(0),
+    }
+}
+
+/// The axis height of Latin Modern Math (the font `rustex.css` uses for formulas), in em.
+const MATH_FONT_AXIS: f32 = 0.25;
+
+/// The difference between the math axis of the symbol font (`\fontdimen22` of `\textfont2`)
+/// and [`MATH_FONT_AXIS`], in em; `None` if negligible.
+fn axis_shift(engine: Refs) -> Option<f32> {
+    let font = engine.state.get_textfont(2);
+    let size = font.get_at().0;
+    if size <= 0 {
+        return None;
+    }
+    let axis = font.get_dim(21).0 as f32 / size as f32;
+    let shift = ((axis - MATH_FONT_AXIS) * 1000.0).round() / 1000.0;
+    (shift.abs() >= 0.005).then_some(shift)
+}
+
 impl ShipoutNodeH {
     pub(crate) fn char(
         char: u8,
@@ -1851,8 +2136,11 @@ impl ShipoutNodeH {
             Entry::Occupied(e) => e.into_mut(),
             Entry::Vacant(e) => e.insert(FontData::new(font.filename(), fs)),
         };
+        data.use_char(char);
         if !glyph.is_defined() {
-            data.missing_glyph(glyph.name(), char);
+            if data.missing_glyph(glyph.name(), char) {
+                missing_glyph_approximation(engine, glyph.name(), char, font.filename());
+            }
             ShipoutNodeH::MissingGlyph {
                 font_name: font.filename().into(),
                 char,
@@ -1878,15 +2166,21 @@ impl ShipoutNodeH {
             Entry::Occupied(e) => e.into_mut(),
             Entry::Vacant(e) => e.insert(FontData::new(font.filename(), fs)),
         };
+        data.use_char(char);
+        data.use_char(accent);
         if !glyph.is_defined() {
-            data.missing_glyph(glyph.name(), char);
+            if data.missing_glyph(glyph.name(), char) {
+                missing_glyph_approximation(engine, glyph.name(), char, font.filename());
+            }
             ShipoutNodeH::MissingGlyph {
                 font_name: font.filename().into(),
                 char,
                 name: glyph.name().to_string().into(),
             }
         } else if !accentglyph.is_defined() {
-            data.missing_glyph(accentglyph.name(), accent);
+            if data.missing_glyph(accentglyph.name(), accent) {
+                missing_glyph_approximation(engine, accentglyph.name(), accent, font.filename());
+            }
             ShipoutNodeH::MissingGlyph {
                 font_name: font.filename().into(),
                 char: accent,
@@ -1920,6 +2214,14 @@ impl ShipoutNodeT for ShipoutNodeH {
             ShipoutNodeH::Img(_) => false,
             ShipoutNodeH::MissingGlyph { .. } => false,
             ShipoutNodeH::VRule { .. } => true,
+            ShipoutNodeH::Leaders {
+                body: LeadersBody::Rule { .. },
+                ..
+            } => true,
+            ShipoutNodeH::Leaders {
+                body: LeadersBody::Box { children, .. },
+                ..
+            } => children.iter().any(|c| c.uses_previous_color()),
             ShipoutNodeH::Math { uses_color, .. } => *uses_color,
             ShipoutNodeH::Common(c) => c.uses_previous_color(),
         }
@@ -1934,6 +2236,14 @@ impl ShipoutNodeT for ShipoutNodeH {
             ShipoutNodeH::Space => false,
             ShipoutNodeH::MissingGlyph { .. } => false,
             ShipoutNodeH::VRule { .. } => false,
+            ShipoutNodeH::Leaders {
+                body: LeadersBody::Rule { .. },
+                ..
+            } => false,
+            ShipoutNodeH::Leaders {
+                body: LeadersBody::Box { children, .. },
+                ..
+            } => children.iter().any(|c| c.uses_previous_font()),
             ShipoutNodeH::Math { uses_font, .. } => *uses_font,
             ShipoutNodeH::Common(c) => c.uses_previous_font(),
         }
@@ -2320,6 +2630,7 @@ pub(crate) enum Common<T: ShipoutNodeT> {
         sref: SourceRef,
         info: HBoxInfo<Types>,
         children: Vec<ShipoutNodeH>,
+        visibility: Option<BoxVisibility>,
         uses_color: bool,
         uses_font: bool,
     },
@@ -2330,6 +2641,7 @@ pub(crate) enum Common<T: ShipoutNodeT> {
         miny: i32,
         maxy: i32,
         children: Vec<ShipoutNodeSVG>,
+        caption: Option<crate::shipout::captions::Caption>,
         uses_color: bool,
         uses_font: bool,
     },
@@ -2563,6 +2875,8 @@ pub struct FontData {
     pub web: Option<(String, String)>,
     pub missing: VecSet<(String, u8)>,
     pub modifiers: Option<ModifierSeq>,
+    /// bit set of the character codes used
+    used: [u64; 4],
 }
 impl FontData {
     fn new(name: &str, store: &mut FontStore) -> Self {
@@ -2580,10 +2894,21 @@ impl FontData {
             web,
             missing: VecSet::default(),
             modifiers: info.map(|i| i.styles),
+            used: [0; 4],
         }
     }
-    fn missing_glyph(&mut self, name: GlyphName, char: u8) {
+    fn use_char(&mut self, char: u8) {
+        self.used[(char / 64) as usize] |= 1 << (char % 64);
+    }
+    /// The number of distinct character codes of this font used in the document.
+    pub fn chars_used(&self) -> u32 {
+        self.used.iter().map(|u| u.count_ones()).sum()
+    }
+    /// Returns whether the glyph was not already known to be missing.
+    fn missing_glyph(&mut self, name: GlyphName, char: u8) -> bool {
+        let new = !self.missing.contains(&(name.to_string(), char));
         self.missing.insert((name.to_string(), char));
+        new
         /*
             let s = match state.mode() {
             ShipoutMode::H{..} | ShipoutMode::Par => "span",
@@ -0,0 +1,573 @@
+//! Plain text and Markdown output (see
+//! [`CompilationResult::text`](crate::engine::CompilationResult::text)), for search indexes and
+//! accessibility pipelines that want the words rather than the layout.
+//!
+//! The shipped out node tree carries no logical markup, so the structure is recovered
+//! heuristically: paragraphs become blocks, headings are recognized by the anchors of
+//! [`Settings::section_spans`](crate::engine::Settings::section_spans), list items by a label
+//! box moved into the left margin (as LaTeX's `\item` does), and alignments become tables.
+
+use crate::shipout::client_math::Sources;
+use crate::shipout::sections::{self, SectionSource};
+use crate::shipout::state::{
+    Common, ModifiedGlyph, ShipoutNodeH, ShipoutNodeHRow, ShipoutNodeM, ShipoutNodeTable,
+    ShipoutNodeV, SourceRef,
+};
+use std::path::Path;
+
+/// The output syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextFormat {
+    #[default]
+    Plain,
+    /// headings, list items, links, images and tables in (CommonMark + pipe tables) Markdown
+    Markdown,
+}
+
+/// How formulas are rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextMath {
+    /// the characters of the formula, with fractions, roots and scripts linearized
+    #[default]
+    Unicode,
+    /// the TeX source of the formula in `$...$` (or `$$...$$`), if it can be found in the
+    /// input; [`Unicode`](Self::Unicode) otherwise
+    TeX,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TextOptions {
+    pub format: TextFormat,
+    pub math: TextMath,
+}
+
+/// The text of `nodes`, with blocks separated by blank lines; `dir` is the directory source
+/// references are relative to.
+pub(crate) fn text(
+    nodes: &[ShipoutNodeV],
+    sections: &[SectionSource],
+    dir: &Path,
+    options: TextOptions,
+) -> String {
+    let mut writer = TextWriter {
+        options,
+        sections,
+        min_level: sections.iter().map(|s| s.level).min().unwrap_or(0),
+        dir,
+        sources: Sources::default(),
+        out: String::new(),
+        heading: None,
+    };
+    writer.vlist(nodes);
+    let mut ret = String::new();
+    for block in writer.out.split("\n\n") {
+        let block = block.trim_matches('\n');
+        if !block.trim().is_empty() {
+            ret.push_str(block);
+            ret.push_str("\n\n");
+        }
+    }
+    ret.truncate(ret.trim_end().len());
+    ret.push('\n');
+    ret
+}
+
+struct TextWriter<'a> {
+    options: TextOptions,
+    sections: &'a [SectionSource],
+    min_level: i32,
+    dir: &'a Path,
+    sources: Sources,
+    out: String,
+    /// the level of the section anchor preceding the next paragraph
+    heading: Option<i32>,
+}
+impl TextWriter<'_> {
+    fn markdown(&self) -> bool {
+        self.options.format == TextFormat::Markdown
+    }
+    fn block(&mut self, s: &str) {
+        self.out.push_str("\n\n");
+        self.out.push_str(s);
+        self.out.push_str("\n\n");
+    }
+
+    fn vlist(&mut self, nodes: &[ShipoutNodeV]) {
+        for n in nodes {
+            match n {
+                ShipoutNodeV::Paragraph { children, .. } => self.paragraph(children),
+                ShipoutNodeV::HAlign { children, .. } => self.table(children),
+                ShipoutNodeV::HRule { .. } if self.markdown() => self.block("---"),
+                ShipoutNodeV::HRule { .. } | ShipoutNodeV::KernSkip(_) => (),
+                ShipoutNodeV::Common(c) => self.common_v(c),
+            }
+        }
+    }
+    fn common_v(&mut self, c: &Common<ShipoutNodeV>) {
+        match c {
+            Common::Literal(s) => {
+                if !self.anchor(s) {
+                    let text = literal_text(s);
+                    self.block(&text);
+                }
+            }
+            Common::WithColor { children, .. }
+            | Common::WithFont { children, .. }
+            | Common::WithLink { children, .. }
+            | Common::WithAnnotation { children, .. }
+            | Common::WithMatrix { children, .. }
+            | Common::VBox { children, .. } => self.vlist(children),
+            Common::HBox { children, .. } => self.paragraph(children),
+            Common::PDFDest(_) | Common::SVG { .. } => (),
+        }
+    }
+    /// Records the level of a section anchor; `false` if `s` is not one.
+    fn anchor(&mut self, s: &str) -> bool {
+        match sections::anchor_index(s).and_then(|i| self.sections.get(i)) {
+            Some(sec) => {
+                self.heading = Some(sec.level);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn paragraph(&mut self, children: &[ShipoutNodeH]) {
+        let mut rest = children;
+        let mut prefix = String::new();
+        if let Some((label, consumed)) = list_label(children) {
+            rest = &children[consumed..];
+            let label = self.inline(label).trim().to_string();
+            prefix = if !self.markdown() {
+                format!("{label} ")
+            } else if BULLETS.contains(&label.as_str()) {
+                "- ".to_string()
+            } else if is_number(&label) {
+                format!("{label} ")
+            } else {
+                format!("- {label} ")
+            };
+        }
+        let text = self.inline(rest);
+        let text = text.trim();
+        if text.is_empty() && prefix.is_empty() {
+            return;
+        }
+        match self.heading.take() {
+            Some(level) if self.markdown() => {
+                let hashes = "#".repeat((level - self.min_level + 1).clamp(1, 6) as usize);
+                self.block(&format!("{hashes} {}", text.replace('\n', " ")));
+            }
+            _ => self.block(&format!("{prefix}{text}")),
+        }
+    }
+    fn inline(&mut self, nodes: &[ShipoutNodeH]) -> String {
+        let mut s = String::new();
+        self.hlist(nodes, &mut s);
+        s
+    }
+    fn hlist(&mut self, nodes: &[ShipoutNodeH], s: &mut String) {
+        for n in nodes {
+            match n {
+                ShipoutNodeH::Char(c) => s.push_str(&c.to_string()),
+                ShipoutNodeH::Space => space(s),
+                ShipoutNodeH::KernSkip(m) if m.base >= WORD_SPACE => space(s),
+                ShipoutNodeH::Leaders { .. } => space(s),
+                ShipoutNodeH::LineBreak if self.markdown() => s.push_str("\\\n"),
+                ShipoutNodeH::LineBreak => s.push('\n'),
+                ShipoutNodeH::MissingGlyph { .. } => s.push('\u{FFFD}'),
+                ShipoutNodeH::Math {
+                    sref,
+                    display,
+                    children,
+                    ..
+                } => self.math(sref, display.is_some(), children, s),
+                ShipoutNodeH::Img(img) if self.markdown() => {
+                    s.push_str(&format!("![]({})", img.filepath.display()))
+                }
+                ShipoutNodeH::Img(_)
+                | ShipoutNodeH::KernSkip(_)
+                | ShipoutNodeH::VRule { .. }
+                | ShipoutNodeH::Indent(_) => (),
+                ShipoutNodeH::Common(c) => self.common_h(c, s),
+            }
+        }
+    }
+    fn common_h(&mut self, c: &Common<ShipoutNodeH>, s: &mut String) {
+        match c {
+            Common::Literal(l) => {
+                if !self.anchor(l) {
+                    s.push_str(&literal_text(l))
+                }
+            }
+            Common::WithLink { href, children, .. }
+                if self.markdown() && !href.starts_with('#') =>
+            {
+                let text = self.inline(children);
+                s.push_str(&format!("[{}]({href})", text.trim()));
+            }
+            Common::WithColor { children, .. }
+            | Common::WithFont { children, .. }
+            | Common::WithLink { children, .. }
+            | Common::WithAnnotation { children, .. }
+            | Common::WithMatrix { children, .. }
+            | Common::HBox { children, .. } => self.hlist(children, s),
+            Common::VBox { children, .. } => {
+                // the blocks of an inline box are run into the surrounding line
+                let mut inner = TextWriter {
+                    options: self.options,
+                    sections: self.sections,
+                    min_level: self.min_level,
+                    dir: self.dir,
+                    sources: std::mem::take(&mut self.sources),
+                    out: String::new(),
+                    heading: None,
+                };
+                inner.vlist(children);
+                self.sources = inner.sources;
+                space(s);
+                s.push_str(&inner.out.split_whitespace().collect::<Vec<_>>().join(" "));
+                space(s);
+            }
+            Common::PDFDest(_) | Common::SVG { .. } => (),
+        }
+    }
+
+    fn math(&mut self, sref: &SourceRef, display: bool, children: &[ShipoutNodeM], s: &mut String) {
+        let tex = match self.options.math {
+            TextMath::TeX => {
+                let (file, start, end) = sref.span();
+                self.sources.formula_at(self.dir.join(file), start, end)
+            }
+            TextMath::Unicode => None,
+        };
+        let formula = match tex {
+            Some(tex) if display => format!("$$\n{tex}\n$$"),
+            Some(tex) => format!("${tex}$"),
+            None => {
+                let mut m = String::new();
+                math_list(children, &mut m);
+                m.trim().to_string()
+            }
+        };
+        if display {
+            s.push_str("\n\n");
+            s.push_str(&formula);
+            s.push_str("\n\n");
+        } else {
+            s.push_str(&formula);
+        }
+    }
+
+    fn table(&mut self, rows: &[ShipoutNodeTable]) {
+        let mut cells: Vec<Vec<String>> = Vec::new();
+        self.rows(rows, &mut cells);
+        cells.retain(|r| r.iter().any(|c| !c.is_empty()));
+        if cells.is_empty() {
+            return;
+        }
+        let mut ret = String::new();
+        if self.markdown() {
+            let cols = cells.iter().map(Vec::len).max().unwrap_or(0);
+            for (i, row) in cells.iter().enumerate() {
+                ret.push('|');
+                for j in 0..cols {
+                    let cell = row.get(j).map_or("", String::as_str);
+                    ret.push_str(&format!(" {} |", cell.replace('|', "\\|")));
+                }
+                ret.push('\n');
+                if i == 0 {
+                    ret.push('|');
+                    ret.push_str(&" --- |".repeat(cols));
+                    ret.push('\n');
+                }
+            }
+        } else {
+            for row in &cells {
+                ret.push_str(&row.join("\t"));
+                ret.push('\n');
+            }
+        }
+        self.block(ret.trim_end());
+    }
+    fn rows(&mut self, rows: &[ShipoutNodeTable], cells: &mut Vec<Vec<String>>) {
+        for r in rows {
+            match r {
+                ShipoutNodeTable::Row { children, .. } => {
+                    let mut row = Vec::new();
+                    self.cells(children, &mut row);
+                    cells.push(row);
+                }
+                ShipoutNodeTable::NoAlign { .. } => (),
+                ShipoutNodeTable::Common(c) => match c {
+                    Common::WithColor { children, .. }
+                    | Common::WithFont { children, .. }
+                    | Common::WithLink { children, .. }
+                    | Common::WithAnnotation { children, .. }
+                    | Common::WithMatrix { children, .. } => self.rows(children, cells),
+                    _ => (),
+                },
+            }
+        }
+    }
+    fn cells(&mut self, nodes: &[ShipoutNodeHRow], row: &mut Vec<String>) {
+        for c in nodes {
+            match c {
+                ShipoutNodeHRow::Cell { children, .. } => {
+                    let text = self.inline(children);
+                    row.push(text.split_whitespace().collect::<Vec<_>>().join(" "));
+                }
+                ShipoutNodeHRow::Common(c) => match c {
+                    Common::WithColor { children, .. }
+                    | Common::WithFont { children, .. }
+                    | Common::WithLink { children, .. }
+                    | Common::WithAnnotation { children, .. }
+                    | Common::WithMatrix { children, .. } => self.cells(children, row),
+                    _ => (),
+                },
+            }
+        }
+    }
+}
+
+/// Horizontal kerns from this width on (3pt) separate words.
+const WORD_SPACE: i32 = 3 * 65536;
+
+const BULLETS: [&str; 8] = ["•", "◦", "∙", "·", "-", "–", "—", "∗"];
+
+fn space(s: &mut String) {
+    if !s.is_empty() && !s.ends_with([' ', '\n']) {
+        s.push(' ');
+    }
+}
+
+/// `1.`, `2)`, ... - which Markdown knows as ordered list markers.
+fn is_number(label: &str) -> bool {
+    label
+        .strip_suffix(['.', ')'])
+        .is_some_and(|n| !n.is_empty() && n.len() <= 9 && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// The label of a list item, and the number of leading nodes it spans: `\item` puts the label
+/// in a box preceded by a negative kern (inside or outside the box), i.e. into the left margin.
+fn list_label(children: &[ShipoutNodeH]) -> Option<(&[ShipoutNodeH], usize)> {
+    let (i, outdented) = match outdent(children) {
+        Some(i) => (i, true),
+        None => (lead(children), false),
+    };
+    match children.get(i)? {
+        ShipoutNodeH::Common(Common::HBox {
+            children: inner, ..
+        }) if outdented => Some((inner, i + 1)),
+        ShipoutNodeH::Common(Common::HBox {
+            children: inner, ..
+        }) => Some((&inner[outdent(inner)?..], i + 1)),
+        _ => None,
+    }
+}
+/// The number of leading indentation nodes.
+fn lead(children: &[ShipoutNodeH]) -> usize {
+    children
+        .iter()
+        .take_while(|n| matches!(n, ShipoutNodeH::Indent(_)))
+        .count()
+}
+/// The index after the leading negative kerns, if there are any.
+fn outdent(children: &[ShipoutNodeH]) -> Option<usize> {
+    let i = lead(children);
+    let kerns = children[i..]
+        .iter()
+        .take_while(|n| matches!(n, ShipoutNodeH::KernSkip(m) if m.base < 0))
+        .count();
+    (kerns > 0).then_some(i + kerns)
+}
+
+/// The text of an HTML literal, i.e. without tags and with the basic entities resolved.
+fn literal_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if in_tag => (),
+            c => text.push(c),
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&nbsp;", "\u{A0}")
+        .replace("&amp;", "&")
+}
+
+fn math_list(nodes: &[ShipoutNodeM], s: &mut String) {
+    for n in nodes {
+        math_node(n, s);
+    }
+}
+fn math_node(n: &ShipoutNodeM, s: &mut String) {
+    match n {
+        ShipoutNodeM::Glyph { char, .. } => s.push_str(&char.to_string()),
+        ShipoutNodeM::Middle(Ok(g)) => s.push_str(&g.to_string()),
+        ShipoutNodeM::MissingGlyph { .. } | ShipoutNodeM::Middle(Err(_)) => s.push('\u{FFFD}'),
+        ShipoutNodeM::Space => space(s),
+        ShipoutNodeM::MSkip { base, mu } => {
+            // `\;` and wider
+            let wide = if *mu {
+                *base >= 5 * 65536
+            } else {
+                *base >= WORD_SPACE
+            };
+            if wide {
+                space(s)
+            }
+        }
+        ShipoutNodeM::Over {
+            top,
+            bottom,
+            left,
+            right,
+            ..
+        } => {
+            delimiter(left, s);
+            s.push_str(&group(top));
+            s.push('/');
+            s.push_str(&group(bottom));
+            delimiter(right, s);
+        }
+        ShipoutNodeM::Radical { children, .. } => {
+            s.push('√');
+            s.push_str(&group(children));
+        }
+        ShipoutNodeM::LeftRight {
+            left,
+            right,
+            children,
+            ..
+        } => {
+            delimiter(left, s);
+            math_list(children, s);
+            delimiter(right, s);
+        }
+        ShipoutNodeM::WithClass { children, .. }
+        | ShipoutNodeM::Underline { children, .. }
+        | ShipoutNodeM::Overline { children, .. }
+        | ShipoutNodeM::Accent { children, .. } => math_list(children, s),
+        ShipoutNodeM::Sub { base, sub, .. } => {
+            math_node(base, s);
+            script(sub, SUBSCRIPTS, '_', s);
+        }
+        ShipoutNodeM::Sup { base, sup, .. } => {
+            math_node(base, s);
+            script(sup, SUPERSCRIPTS, '^', s);
+        }
+        ShipoutNodeM::SubSup { base, sub, sup, .. } => {
+            math_node(base, s);
+            script(sub, SUBSCRIPTS, '_', s);
+            script(sup, SUPERSCRIPTS, '^', s);
+        }
+        ShipoutNodeM::VCenter { children, .. } => {
+            let mut w = String::new();
+            for c in children {
+                if let ShipoutNodeV::Paragraph { children, .. } = c {
+                    for n in children {
+                        if let ShipoutNodeH::Char(c) = n {
+                            w.push_str(&c.to_string())
+                        }
+                    }
+                    space(&mut w);
+                }
+            }
+            s.push_str(w.trim());
+        }
+        ShipoutNodeM::Common(c) => match c {
+            Common::WithColor { children, .. }
+            | Common::WithFont { children, .. }
+            | Common::WithLink { children, .. }
+            | Common::WithAnnotation { children, .. }
+            | Common::WithMatrix { children, .. } => math_list(children, s),
+            Common::Literal(l) => s.push_str(&literal_text(l)),
+            _ => (),
+        },
+        ShipoutNodeM::Phantom { .. } | ShipoutNodeM::VRule { .. } | ShipoutNodeM::Img(_) => (),
+    }
+}
+
+type Delimiter = Option<Result<ModifiedGlyph, (Box<str>, u8, Box<str>)>>;
+fn delimiter(d: &Delimiter, s: &mut String) {
+    if let Some(Ok(g)) = d {
+        s.push_str(&g.to_string())
+    }
+}
+
+/// The linearized formula, parenthesized unless it is a single character.
+fn group(nodes: &[ShipoutNodeM]) -> String {
+    let mut s = String::new();
+    math_list(nodes, &mut s);
+    let s = s.trim();
+    if s.chars().count() > 1 {
+        format!("({s})")
+    } else {
+        s.to_string()
+    }
+}
+
+const SUPERSCRIPTS: &[(char, char)] = &[
+    ('0', '⁰'),
+    ('1', '¹'),
+    ('2', '²'),
+    ('3', '³'),
+    ('4', '⁴'),
+    ('5', '⁵'),
+    ('6', '⁶'),
+    ('7', '⁷'),
+    ('8', '⁸'),
+    ('9', '⁹'),
+    ('+', '⁺'),
+    ('-', '⁻'),
+    ('−', '⁻'),
+    ('=', '⁼'),
+    ('(', '⁽'),
+    (')', '⁾'),
+    ('n', 'ⁿ'),
+    ('i', 'ⁱ'),
+    ('′', '′'),
+];
+const SUBSCRIPTS: &[(char, char)] = &[
+    ('0', '₀'),
+    ('1', '₁'),
+    ('2', '₂'),
+    ('3', '₃'),
+    ('4', '₄'),
+    ('5', '₅'),
+    ('6', '₆'),
+    ('7', '₇'),
+    ('8', '₈'),
+    ('9', '₉'),
+    ('+', '₊'),
+    ('-', '₋'),
+    ('−', '₋'),
+    ('=', '₌'),
+    ('(', '₍'),
+    (')', '₎'),
+];
+
+/// A script in Unicode super-/subscript characters if there are ones for all of its
+/// characters, or as `^x`/`_(xy)` otherwise.
+fn script(nodes: &[ShipoutNodeM], table: &[(char, char)], marker: char, s: &mut String) {
+    let mut inner = String::new();
+    math_list(nodes, &mut inner);
+    let inner = inner.trim();
+    let mapped: Option<String> = inner
+        .chars()
+        .map(|c| table.iter().find(|(k, _)| *k == c).map(|(_, v)| *v))
+        .collect();
+    match mapped {
+        Some(m) if !m.is_empty() => s.push_str(&m),
+        _ => {
+            s.push(marker);
+            s.push_str(&group(nodes));
+        }
+    }
+}
@@ -50,3 +50,20 @@ pub(crate) type VNodes = ExtensibleIter<VNode<Types>>;
 pub(crate) type HNodes = ExtensibleIter<HNode<Types>>;
 pub(crate) type MNodes = ExtensibleIter<MNode>;
 pub(crate) type MNode = MathNode<Types, MathFontStyle<Types>>;
+
+/// A dimension (in scaled points) as a number of CSS pixels, without trailing zeros.
+pub(crate) fn px(d: i32) -> String {
+    format!("{:.5}", d as f32 / 65536.0 * 1.5)
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Escapes `&`, `<`, `>` and `"`, so that the result can be used both as text and as the
+/// value of a quoted attribute.
+pub(crate) fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,174 @@
+//! Verbatim blocks (see [`Settings::verbatim`](crate::engine::Settings::verbatim)): `patches.tex`
+//! wraps `verbatim`, fancyvrb's environments and listings' displayed listings in an annotation
+//! with the class [`CLASS`]. Instead of the paragraphs and boxes of monospaced text it contains,
+//! the annotation becomes a `<pre><code>` with the text of its lines, in which every space
+//! survives - including the kerns listings uses for them. A [`Highlighter`] can replace the
+//! plain text by highlighted HTML.
+
+use crate::engine::Font;
+use crate::shipout::state::{Common, ShipoutNodeH, ShipoutNodeV, ShipoutNodes};
+use crate::shipout::utils::escape_html;
+use crate::utils::{VecMap, VecSet};
+use std::borrow::Cow;
+use tex_engine::engine::fontsystem::Font as FontTrait;
+
+/// Highlights the code of verbatim blocks during shipout (see
+/// [`Settings::highlighter`](crate::engine::Settings::highlighter)), e.g. with syntect.
+/// Implemented for all suitable closures.
+pub trait Highlighter: Send {
+    /// The HTML for the plain text `code` inside the `<code>` element, or `None` to keep the
+    /// (escaped) text. `language` is the one of the listing (listings' `language` key, the
+    /// language argument of minted), if any.
+    fn highlight(&mut self, language: Option<&str>, code: &str) -> Option<String>;
+}
+impl<F: FnMut(Option<&str>, &str) -> Option<String> + Send> Highlighter for F {
+    fn highlight(&mut self, language: Option<&str>, code: &str) -> Option<String> {
+        self(language, code)
+    }
+}
+
+/// The class of the annotations that become verbatim blocks.
+pub(crate) const CLASS: &str = "rustex-verbatim";
+
+/// The width of a character of cmtt10 (5.25pt), for kerns in an unknown font.
+const DEFAULT_WIDTH: i32 = 344_064;
+
+/// The `<pre>` element for the contents of a verbatim annotation; classes starting with
+/// `language-` (as taken from the options of listings and minted) go on the `<code>` element,
+/// where client-side syntax highlighters look for them, and the first one is the language
+/// passed to the `highlighter`.
+pub(crate) fn html(
+    attrs: &VecMap<Cow<'static, str>, Cow<'static, str>>,
+    styles: &VecMap<Cow<'static, str>, Cow<'static, str>>,
+    classes: VecSet<Cow<'static, str>>,
+    nodes: ShipoutNodes,
+    highlighter: &mut Option<Box<dyn Highlighter>>,
+) -> String {
+    let (language, classes): (Vec<_>, Vec<_>) = classes
+        .into_iter()
+        .flat_map(|c| c.split_whitespace().map(str::to_string).collect::<Vec<_>>())
+        .partition(|c| c.starts_with("language-"));
+    let mut ret = format!("<pre class=\"{}\"", escape_html(&classes.join(" ")));
+    for (k, v) in attrs.iter() {
+        ret.push_str(&format!(" {k}=\"{}\"", escape_html(v)));
+    }
+    if !styles.is_empty() {
+        ret.push_str(" style=\"");
+        for (k, v) in styles.iter() {
+            ret.push_str(&format!("{k}:{};", escape_html(v)));
+        }
+        ret.push('"');
+    }
+    ret.push_str("><code");
+    if !language.is_empty() {
+        ret.push_str(&format!(" class=\"{}\"", escape_html(&language.join(" "))));
+    }
+    ret.push('>');
+    let code = text(nodes);
+    let lang = language.first().map(|l| &l["language-".len()..]);
+    match highlighter.as_mut().and_then(|h| h.highlight(lang, &code)) {
+        Some(html) => ret.push_str(&html),
+        None => ret.push_str(&escape_html(&code)),
+    }
+    ret.push_str("</code></pre>");
+    ret
+}
+
+/// The lines of `nodes`, without trailing whitespace.
+fn text(nodes: ShipoutNodes) -> String {
+    let mut lines = Lines {
+        lines: Vec::new(),
+        width: DEFAULT_WIDTH,
+    };
+    match nodes {
+        ShipoutNodes::V(v) => lines.vlist(&v),
+        ShipoutNodes::H(h) => {
+            lines.newline();
+            lines.hlist(&h)
+        }
+        _ => (),
+    }
+    let mut ret = String::new();
+    for l in lines.lines {
+        ret.push_str(l.trim_end());
+        ret.push('\n');
+    }
+    ret.truncate(ret.trim_end().len());
+    ret
+}
+
+struct Lines {
+    lines: Vec<String>,
+    /// the width of a character (in sp), i.e. of a space
+    width: i32,
+}
+impl Lines {
+    fn newline(&mut self) {
+        self.lines.push(String::new())
+    }
+    fn line(&mut self) -> &mut String {
+        if self.lines.is_empty() {
+            self.newline();
+        }
+        self.lines.last_mut().unwrap()
+    }
+    fn with_font<F: FnOnce(&mut Self)>(&mut self, font: &Font, f: F) {
+        let wd = font.get_wd(b'0').0;
+        let wd = if wd > 0 { wd } else { self.width };
+        let old = std::mem::replace(&mut self.width, wd);
+        f(self);
+        self.width = old;
+    }
+
+    fn vlist(&mut self, nodes: &[ShipoutNodeV]) {
+        for n in nodes {
+            match n {
+                ShipoutNodeV::Paragraph { children, .. } => {
+                    self.newline();
+                    self.hlist(children)
+                }
+                ShipoutNodeV::Common(Common::HBox { children, .. }) => {
+                    self.newline();
+                    self.hlist(children)
+                }
+                ShipoutNodeV::Common(Common::WithFont { font, children, .. }) => {
+                    self.with_font(font, |s| s.vlist(children))
+                }
+                ShipoutNodeV::Common(
+                    Common::WithColor { children, .. }
+                    | Common::WithLink { children, .. }
+                    | Common::WithAnnotation { children, .. }
+                    | Common::WithMatrix { children, .. }
+                    | Common::VBox { children, .. },
+                ) => self.vlist(children),
+                _ => (),
+            }
+        }
+    }
+    fn hlist(&mut self, nodes: &[ShipoutNodeH]) {
+        for n in nodes {
+            match n {
+                ShipoutNodeH::Char(c) => self.line().push_str(&c.to_string()),
+                ShipoutNodeH::Space => self.line().push(' '),
+                ShipoutNodeH::KernSkip(m) if m.base > 0 => {
+                    let n = (m.base as f32 / self.width as f32).round() as usize;
+                    self.line().push_str(&" ".repeat(n))
+                }
+                ShipoutNodeH::LineBreak => self.newline(),
+                ShipoutNodeH::MissingGlyph { .. } => self.line().push('\u{FFFD}'),
+                ShipoutNodeH::Common(Common::WithFont { font, children, .. }) => {
+                    self.with_font(font, |s| s.hlist(children))
+                }
+                ShipoutNodeH::Common(
+                    Common::WithColor { children, .. }
+                    | Common::WithLink { children, .. }
+                    | Common::WithAnnotation { children, .. }
+                    | Common::WithMatrix { children, .. }
+                    | Common::HBox { children, .. },
+                ) => self.hlist(children),
+                ShipoutNodeH::Common(Common::VBox { children, .. }) => self.vlist(children),
+                _ => (),
+            }
+        }
+    }
+}
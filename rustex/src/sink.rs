@@ -0,0 +1,164 @@
+//! Destinations for the files a compilation produces (HTML pages, rendered images, auxiliary
+//! files, thumbnails). Everything RusTeX writes goes through an [`OutputSink`], so embedders
+//! can store results in object storage or a database instead of the local file system.
+//!
+//! Names are `/`-separated paths relative to the sink's root; names of files that belong next
+//! to the sources (e.g. images rendered from PDFs, `.aux` files) may be absolute.
+
+use std::path::{Path, PathBuf};
+
+pub trait OutputSink {
+    /// Stores an asset, e.g. an image or an auxiliary file.
+    ///
+    /// #### Errors
+    /// If the asset can not be stored
+    fn write_asset(&mut self, name: &str, content: &[u8]) -> std::io::Result<()>;
+    /// Stores an HTML page.
+    ///
+    /// #### Errors
+    /// If the page can not be stored
+    fn write_page(&mut self, name: &str, html: &str) -> std::io::Result<()> {
+        self.write_asset(name, html.as_bytes())
+    }
+    /// Called once after all outputs have been written, e.g. to flush buffers or commit
+    /// a transaction.
+    ///
+    /// #### Errors
+    /// If finishing the output fails
+    fn finalize(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes to a directory of the local file system, creating subdirectories as needed.
+#[derive(Debug, Clone)]
+pub struct DirectorySink {
+    pub root: PathBuf,
+}
+impl DirectorySink {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+    /// The path a name is written to.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+}
+impl OutputSink for DirectorySink {
+    fn write_asset(&mut self, name: &str, content: &[u8]) -> std::io::Result<()> {
+        let path = self.path(name);
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, content)
+    }
+}
+
+/// Keeps all outputs in memory.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    pub pages: Vec<(String, String)>,
+    pub assets: Vec<(String, Vec<u8>)>,
+    pub finalized: bool,
+}
+impl OutputSink for MemorySink {
+    fn write_asset(&mut self, name: &str, content: &[u8]) -> std::io::Result<()> {
+        match self.assets.iter_mut().find(|(n, _)| n == name) {
+            Some((_, c)) => *c = content.to_vec(),
+            None => self.assets.push((name.to_string(), content.to_vec())),
+        }
+        Ok(())
+    }
+    fn write_page(&mut self, name: &str, html: &str) -> std::io::Result<()> {
+        match self.pages.iter_mut().find(|(n, _)| n == name) {
+            Some((_, c)) => *c = html.to_string(),
+            None => self.pages.push((name.to_string(), html.to_string())),
+        }
+        Ok(())
+    }
+    fn finalize(&mut self) -> std::io::Result<()> {
+        self.finalized = true;
+        Ok(())
+    }
+}
+
+/// The name of `path` relative to `root`, with `/` as separator; `path` itself if it is not
+/// below `root`.
+pub fn relative_name(path: &Path, root: &Path) -> String {
+    match path.strip_prefix(root) {
+        Ok(rel) => rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        Err(_) => path.to_string_lossy().into_owned(),
+    }
+}
+
+/// Which pre-compressed sidecars (`<name>.gz`, `<name>.br`) a [`CompressingSink`] emits,
+/// e.g. for static hosting with `gzip_static`/`brotli_static`.
+#[derive(Debug, Clone, Copy)]
+pub struct Compression {
+    pub gzip: bool,
+    pub brotli: bool,
+    /// smaller files are not worth compressing
+    pub min_size: usize,
+}
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            gzip: true,
+            brotli: false,
+            min_size: 1024,
+        }
+    }
+}
+
+/// Wraps another sink and additionally writes compressed versions of all text assets (HTML,
+/// CSS, SVG, JavaScript, JSON) to it, as soon as they are written.
+pub struct CompressingSink<S: OutputSink> {
+    pub inner: S,
+    pub compression: Compression,
+}
+impl<S: OutputSink> CompressingSink<S> {
+    const EXTENSIONS: [&'static str; 7] = ["html", "htm", "css", "svg", "js", "json", "xml"];
+
+    pub fn new(inner: S, compression: Compression) -> Self {
+        Self { inner, compression }
+    }
+    fn write_compressed(&mut self, name: &str, content: &[u8]) -> std::io::Result<()> {
+        use std::io::Write;
+        let compressible = name
+            .rsplit_once('.')
+            .is_some_and(|(_, e)| Self::EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()));
+        if !compressible || content.len() < self.compression.min_size {
+            return Ok(());
+        }
+        if self.compression.gzip {
+            let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+            enc.write_all(content)?;
+            self.inner
+                .write_asset(&format!("{name}.gz"), &enc.finish()?)?;
+        }
+        if self.compression.brotli {
+            let mut enc = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+            enc.write_all(content)?;
+            self.inner
+                .write_asset(&format!("{name}.br"), &enc.into_inner())?;
+        }
+        Ok(())
+    }
+}
+impl<S: OutputSink> OutputSink for CompressingSink<S> {
+    fn write_asset(&mut self, name: &str, content: &[u8]) -> std::io::Result<()> {
+        self.inner.write_asset(name, content)?;
+        self.write_compressed(name, content)
+    }
+    fn write_page(&mut self, name: &str, html: &str) -> std::io::Result<()> {
+        self.inner.write_page(name, html)?;
+        self.write_compressed(name, html.as_bytes())
+    }
+    fn finalize(&mut self) -> std::io::Result<()> {
+        self.inner.finalize()
+    }
+}
@@ -0,0 +1,228 @@
+//! A machine-readable summary of a compilation (see
+//! [`Settings::statistics`](crate::engine::Settings::statistics)): the fonts used and how many
+//! of their glyphs RusTeX could not map to Unicode, the files and packages loaded, the warnings
+//! the document produced (by category) and the unimplemented primitive that aborted the run, if
+//! any - to find out which gaps matter most for a given corpus.
+//!
+//! Files and warnings are collected by wrapping the engine's [`RusTeXOutput`] for the duration
+//! of a run.
+//!
+//! Independently of that, every result carries a few cheap resource counters ([`Metrics`]), for
+//! monitoring how much a document costs to convert.
+
+use crate::engine::output::{OutputCont, RusTeXOutput};
+use crate::json::{object, Json};
+use crate::shipout::state::FontData;
+use crate::utils::VecMap;
+use std::any::Any;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use tex_engine::engine::utils::outputs::Outputs;
+use tex_engine::utils::HMap;
+
+/// Usage of a single font.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontStatistics {
+    /// the file name of the font (e.g. `cmr10`)
+    pub name: String,
+    /// the number of distinct character codes used
+    pub chars_used: u32,
+    /// the names of the glyphs used but missing in RusTeX's glyph tables
+    pub missing_glyphs: Vec<String>,
+    /// whether a web font is known for the font
+    pub web_font: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Statistics {
+    /// sorted by name
+    pub fonts: Vec<FontStatistics>,
+    /// all files `\input` (including packages and classes), in the order they were first opened
+    pub files: Vec<PathBuf>,
+    /// the names of all `.sty` and `.cls` files loaded, in order
+    pub packages: Vec<String>,
+    /// the number of warnings per category, e.g. `LaTeX`, `LaTeX Font`, `Package hyperref`
+    pub warnings: VecMap<String, usize>,
+    /// primitives that are not implemented (yet) and were used
+    pub unsupported: Vec<String>,
+}
+impl Statistics {
+    pub(crate) fn new(
+        collected: Collected,
+        font_data: &HMap<Box<str>, FontData>,
+        error: Option<&str>,
+    ) -> Self {
+        let mut fonts: Vec<_> = font_data
+            .iter()
+            .map(|(name, d)| FontStatistics {
+                name: name.to_string(),
+                chars_used: d.chars_used(),
+                missing_glyphs: d.missing.inner.iter().map(|(g, _)| g.clone()).collect(),
+                web_font: d.web.is_some(),
+            })
+            .collect();
+        fonts.sort_by(|a, b| a.name.cmp(&b.name));
+        let packages = collected
+            .files
+            .iter()
+            .filter(|f| f.extension().is_some_and(|e| e == "sty" || e == "cls"))
+            .filter_map(|f| f.file_stem().map(|s| s.to_string_lossy().into_owned()))
+            .collect();
+        let unsupported = error
+            .and_then(|e| e.strip_prefix("Not yet implemented: "))
+            .map(|e| e.split(" at ").next().unwrap_or(e).to_string())
+            .into_iter()
+            .collect();
+        Self {
+            fonts,
+            files: collected.files,
+            packages,
+            warnings: collected.warnings,
+            unsupported,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        self.json().to_string()
+    }
+    fn json(&self) -> Json {
+        let strings = |v: &[String]| Json::Array(v.iter().map(|s| s.as_str().into()).collect());
+        let fonts = self.fonts.iter().map(|f| {
+            object([
+                ("name", f.name.as_str().into()),
+                ("chars_used", Json::Number(f.chars_used.into())),
+                ("web_font", f.web_font.into()),
+                ("missing_glyphs", strings(&f.missing_glyphs)),
+            ])
+        });
+        let files = self.files.iter().map(|f| f.display().to_string().into());
+        let warnings = self.warnings.iter().map(|(k, v)| (k.clone(), (*v).into()));
+        object([
+            ("fonts", Json::Array(fonts.collect())),
+            ("files", Json::Array(files.collect())),
+            ("packages", strings(&self.packages)),
+            ("warnings", Json::Object(warnings.collect())),
+            ("unsupported", strings(&self.unsupported)),
+        ])
+    }
+}
+
+/// Resource usage of a run (see [`CompilationResult::metrics`](crate::engine::CompilationResult::metrics));
+/// if the document needed several passes, of the last one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// the number of nodes added to lists
+    pub nodes: u64,
+    /// the largest number of node lists (paragraphs, boxes, formulas, ...) open at the same time
+    pub peak_list_depth: usize,
+    /// the number of macro expansions
+    pub expansions: u64,
+    /// the number of distinct `.tfm` files loaded, including those preloaded with the format
+    pub fonts_loaded: usize,
+    /// the bytes of HTML written so far by
+    /// [`write_to`](crate::engine::CompilationResult::write_to) (and hence
+    /// [`write_out`](crate::engine::CompilationResult::write_out))
+    pub html_bytes: usize,
+}
+impl Metrics {
+    pub fn to_json(&self) -> String {
+        object([
+            ("nodes", Json::Number(self.nodes as f64)),
+            ("peak_list_depth", self.peak_list_depth.into()),
+            ("expansions", Json::Number(self.expansions as f64)),
+            ("fonts_loaded", self.fonts_loaded.into()),
+            ("html_bytes", self.html_bytes.into()),
+        ])
+        .to_string()
+    }
+}
+
+/// What [`StatisticsOutput`] collected during a run.
+#[derive(Debug, Default)]
+pub(crate) struct Collected {
+    files: Vec<PathBuf>,
+    warnings: VecMap<String, usize>,
+}
+impl Collected {
+    fn text(&mut self, text: &str) {
+        for line in text.lines() {
+            let category = if line.starts_with("Missing character:") {
+                "Missing character"
+            } else if let Some((prefix, _)) = line.split_once("Warning:") {
+                match prefix.trim() {
+                    "" => "Warning",
+                    p if p.split_whitespace().count() <= 3 => p,
+                    _ => continue,
+                }
+            } else {
+                continue;
+            };
+            *self.warnings.get_or_insert_mut(category.to_string(), || 0) += 1;
+        }
+    }
+}
+
+/// Forwards everything to the wrapped output, recording opened files and warnings.
+pub(crate) struct StatisticsOutput {
+    inner: RusTeXOutput,
+    collected: RefCell<Collected>,
+}
+impl StatisticsOutput {
+    pub(crate) fn wrap(inner: RusTeXOutput) -> RusTeXOutput {
+        RusTeXOutput::Cont(Box::new(Self {
+            inner,
+            collected: RefCell::default(),
+        }))
+    }
+    /// Undoes [`wrap`](Self::wrap), returning the original output and what was collected.
+    pub(crate) fn unwrap(out: RusTeXOutput) -> (RusTeXOutput, Option<Collected>) {
+        match out {
+            RusTeXOutput::Cont(b) => match b.as_any().downcast::<Self>() {
+                Ok(s) => (s.inner, Some(s.collected.into_inner())),
+                Err(_) => (RusTeXOutput::None, None),
+            },
+            o => (o, None),
+        }
+    }
+}
+impl OutputCont for StatisticsOutput {
+    fn message(&self, text: String) {
+        self.collected.borrow_mut().text(&text);
+        self.inner.message(text)
+    }
+    fn errmessage(&self, text: String) {
+        self.inner.errmessage(text)
+    }
+    fn file_open(&self, text: String) {
+        let path = Path::new(&text).to_path_buf();
+        let mut collected = self.collected.borrow_mut();
+        if !collected.files.contains(&path) {
+            collected.files.push(path);
+        }
+        self.inner.file_open(text)
+    }
+    fn file_close(&self, text: String) {
+        self.inner.file_close(text)
+    }
+    fn write_18(&self, text: String) {
+        self.inner.write_18(text)
+    }
+    fn write_17(&self, text: String) {
+        self.collected.borrow_mut().text(&text);
+        self.inner.write_17(text)
+    }
+    fn write_16(&self, text: String) {
+        self.collected.borrow_mut().text(&text);
+        self.inner.write_16(text)
+    }
+    fn write_neg1(&self, text: String) {
+        self.collected.borrow_mut().text(&text);
+        self.inner.write_neg1(text)
+    }
+    fn write_other(&self, text: String) {
+        self.inner.write_other(text)
+    }
+    fn as_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
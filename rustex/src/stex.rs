@@ -0,0 +1,146 @@
+//! The module structure of sTeX documents (see
+//! [`Settings::stex_dependencies`](crate::engine::Settings::stex_dependencies)): which modules a
+//! document declares, and which modules and symbols they import, use or reference - so that
+//! MathHub can build its knowledge graph from the RusTeX run directly.
+//!
+//! The information is recorded by `\rustex@stexModule{<name>}`, `\rustex@stexModuleEnd` and
+//! `\rustex@stexDependency{<kind>}{<archive>}{<target>}`, which `patches.tex` inserts into
+//! `smodule`, `\importmodule`, `\usemodule`, `\requiremodule`, `\symdecl`, `\symdef` and
+//! `\symref`/`\sr`; packages can also call them directly.
+
+use crate::json::{object, Json};
+
+/// What a [`Dependency`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DependencyKind {
+    /// `\importmodule`: the module is imported (and re-exported)
+    Import,
+    /// `\usemodule`: the module is used, but not re-exported
+    Use,
+    /// `\requiremodule`
+    Require,
+    /// `\symdecl` or `\symdef`: the symbol is declared in the current module
+    Declaration,
+    /// `\symref` or `\sr`: the symbol is referenced
+    Reference,
+}
+impl DependencyKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "import" => Self::Import,
+            "use" => Self::Use,
+            "require" => Self::Require,
+            "declaration" => Self::Declaration,
+            "reference" => Self::Reference,
+            _ => return None,
+        })
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Import => "import",
+            Self::Use => "use",
+            Self::Require => "require",
+            Self::Declaration => "declaration",
+            Self::Reference => "reference",
+        }
+    }
+}
+
+/// A module declared by an `smodule` environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleDeclaration {
+    pub name: String,
+    /// the enclosing module, if nested
+    pub parent: Option<String>,
+    pub file: String,
+    pub line: usize,
+}
+
+/// A module or symbol a module depends on, at the place it was first mentioned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub kind: DependencyKind,
+    /// the math archive, if given explicitly
+    pub archive: Option<String>,
+    /// as written, e.g. `mod/path?Module` or `Module?symbol`
+    pub target: String,
+    /// the module in which the dependency occurs; `None` outside of modules
+    pub module: Option<String>,
+    pub file: String,
+    pub line: usize,
+}
+
+/// See the [module documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct StexDependencies {
+    /// in document order
+    pub modules: Vec<ModuleDeclaration>,
+    /// in document order; every dependency is only listed once per module
+    pub dependencies: Vec<Dependency>,
+    /// the currently open modules
+    open: Vec<String>,
+}
+impl StexDependencies {
+    pub(crate) fn open_module(&mut self, name: String, file: String, line: usize) {
+        self.modules.push(ModuleDeclaration {
+            name: name.clone(),
+            parent: self.open.last().cloned(),
+            file,
+            line,
+        });
+        self.open.push(name);
+    }
+    pub(crate) fn close_module(&mut self) {
+        self.open.pop();
+    }
+    pub(crate) fn add(
+        &mut self,
+        kind: DependencyKind,
+        archive: Option<String>,
+        target: String,
+        file: String,
+        line: usize,
+    ) {
+        let module = self.open.last().cloned();
+        if !self.dependencies.iter().any(|d| {
+            d.kind == kind && d.archive == archive && d.target == target && d.module == module
+        }) {
+            self.dependencies.push(Dependency {
+                kind,
+                archive,
+                target,
+                module,
+                file,
+                line,
+            });
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        self.json().to_string()
+    }
+    fn json(&self) -> Json {
+        let modules = self.modules.iter().map(|m| {
+            object([
+                ("name", m.name.as_str().into()),
+                ("parent", m.parent.as_deref().into()),
+                ("file", m.file.as_str().into()),
+                ("line", m.line.into()),
+            ])
+        });
+        let dependencies = self.dependencies.iter().map(|d| {
+            object([
+                ("kind", d.kind.name().into()),
+                ("archive", d.archive.as_deref().into()),
+                ("target", d.target.as_str().into()),
+                ("module", d.module.as_deref().into()),
+                ("file", d.file.as_str().into()),
+                ("line", d.line.into()),
+            ])
+        });
+        object([
+            ("modules", Json::Array(modules.collect())),
+            ("dependencies", Json::Array(dependencies.collect())),
+        ])
+    }
+}
@@ -0,0 +1,151 @@
+//! PNG thumbnails of the pages (e.g. slides) of a compiled document, plus a JSON manifest
+//! listing them. Pages are written as standalone HTML files (see
+//! [`CompilationResult::pages`]) and rendered by a [`ThumbnailRenderer`], by default a
+//! headless browser; the screenshots are then scaled down to the thumbnail width and
+//! written to an [`OutputSink`].
+
+use crate::engine::CompilationResult;
+use crate::json::{object, Json};
+use crate::sink::OutputSink;
+use std::path::Path;
+
+/// Renders an HTML file to a PNG image.
+pub trait ThumbnailRenderer {
+    /// Renders `html` with a viewport of `viewport` (width, height) pixels and writes the
+    /// result to `png`.
+    ///
+    /// #### Errors
+    /// If rendering fails or the image can not be written
+    fn render(&mut self, html: &Path, png: &Path, viewport: (u32, u32)) -> std::io::Result<()>;
+}
+
+/// Renders pages by taking screenshots with a Chromium-compatible browser in headless mode.
+#[derive(Debug, Clone)]
+pub struct HeadlessBrowser {
+    /// the browser executable, e.g. `chromium` or `google-chrome`
+    pub command: String,
+}
+impl ThumbnailRenderer for HeadlessBrowser {
+    fn render(&mut self, html: &Path, png: &Path, viewport: (u32, u32)) -> std::io::Result<()> {
+        let html = html.canonicalize()?;
+        let status = std::process::Command::new(&self.command)
+            .arg("--headless")
+            .arg("--disable-gpu")
+            .arg("--hide-scrollbars")
+            .arg(format!("--window-size={},{}", viewport.0, viewport.1))
+            .arg(format!("--screenshot={}", png.display()))
+            .arg(format!("file://{}", html.display()))
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()?;
+        if status.success() && png.is_file() {
+            Ok(())
+        } else {
+            Err(std::io::Error::other(format!(
+                "{} failed to render {}",
+                self.command,
+                html.display()
+            )))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ThumbnailOptions {
+    /// the width of the thumbnails in pixels; the height follows from the viewport
+    pub width: u32,
+    /// the size of the (virtual) screen pages are rendered on
+    pub viewport: (u32, u32),
+}
+impl Default for ThumbnailOptions {
+    fn default() -> Self {
+        Self {
+            width: 320,
+            viewport: (1280, 960),
+        }
+    }
+}
+
+/// A generated thumbnail.
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    /// 1-based
+    pub page: usize,
+    /// the name it was written to the [`OutputSink`] as
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Renders thumbnails of all pages of `result` and writes them to `sink` as
+/// `<prefix>-<page>.png`, together with the manifest `<prefix>.thumbnails.json`. The result
+/// should have been compiled with [`Settings::split_pages`](crate::engine::Settings::split_pages);
+/// otherwise the whole document counts as a single page.
+///
+/// Since the renderer needs files, the pages are temporarily written to `work_dir`, which
+/// should be where the HTML output lives, so that relative links to images etc. still work.
+///
+/// #### Errors
+/// If a file can not be written, or rendering a page fails
+pub fn generate(
+    result: &CompilationResult,
+    work_dir: &Path,
+    prefix: &str,
+    options: &ThumbnailOptions,
+    renderer: &mut dyn ThumbnailRenderer,
+    sink: &mut dyn OutputSink,
+) -> std::io::Result<Vec<Thumbnail>> {
+    std::fs::create_dir_all(work_dir)?;
+    let stem = prefix.rsplit('/').next().unwrap_or(prefix);
+    let mut ret = Vec::new();
+    for (i, page) in result.pages().into_iter().enumerate() {
+        let page_num = i + 1;
+        let html = work_dir.join(format!("{stem}-{page_num}.thumb.html"));
+        let png = work_dir.join(format!("{stem}-{page_num}.thumb.png"));
+        std::fs::write(&html, page)?;
+        let rendered = renderer.render(&html, &png, options.viewport);
+        let _ = std::fs::remove_file(&html);
+        rendered?;
+        let img = image::open(&png);
+        let _ = std::fs::remove_file(&png);
+        let img = img.map_err(std::io::Error::other)?.resize(
+            options.width,
+            u32::MAX,
+            image::imageops::FilterType::Triangle,
+        );
+        let mut bytes = Vec::new();
+        img.write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageFormat::Png,
+        )
+        .map_err(std::io::Error::other)?;
+        let name = format!("{prefix}-{page_num}.png");
+        sink.write_asset(&name, &bytes)?;
+        ret.push(Thumbnail {
+            page: page_num,
+            name,
+            width: img.width(),
+            height: img.height(),
+        });
+    }
+    sink.write_asset(
+        &format!("{prefix}.thumbnails.json"),
+        manifest(&ret).as_bytes(),
+    )?;
+    Ok(ret)
+}
+
+/// A JSON array of `{"page","file","width","height"}` objects; file names are relative to
+/// the directory of the manifest (i.e. without the directory part of the prefix).
+pub fn manifest(thumbnails: &[Thumbnail]) -> String {
+    let entries = thumbnails.iter().map(|t| {
+        let file = t.name.rsplit('/').next().unwrap_or(&t.name);
+        object([
+            ("page", t.page.into()),
+            ("file", file.into()),
+            ("width", Json::Number(t.width.into())),
+            ("height", Json::Number(t.height.into())),
+        ])
+    });
+    Json::Array(entries.collect()).to_string()
+}
@@ -0,0 +1,24 @@
+use rustex_lib::golden::{compare, hbox, vbox};
+
+fn check(snippet: &str) {
+    if let Err(e) = compare(snippet).check() {
+        panic!("{snippet}:\n{e}")
+    }
+}
+
+#[test]
+fn kerns_and_glue() {
+    check(&hbox(r"A\kern 2pt B\hskip 3pt plus 1fil C"))
+}
+
+#[test]
+fn set_glue() {
+    check(&hbox(r"\hbox to 50pt{a\hfil b}\vrule width 1pt"))
+}
+
+#[test]
+fn vertical() {
+    check(&vbox(
+        r"\hrule height 2pt\vskip 4pt\hbox{x}\penalty 100\kern 1pt",
+    ))
+}
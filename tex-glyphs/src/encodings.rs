@@ -88,6 +88,34 @@ impl<S: AsRef<str>, F: FnMut(&str) -> S> FontInfoStore<S, F> {
             Some(u) => self.glyph_lists.get(u).unwrap_or_else(|| unreachable!()),
         }
     }
+    /// Use the [`GlyphList`] of the font `fallback` for the font `name`, if `name` has none
+    /// (and `fallback` does). Returns whether the glyph list was substituted.
+    pub fn substitute_glyphlist<S2: AsRef<str>, S3: AsRef<str>>(
+        &mut self,
+        name: S2,
+        fallback: S3,
+    ) -> bool {
+        if self.get_glyphlist_i(&name).is_some() {
+            return false;
+        }
+        let Some(idx) = self.get_glyphlist_i(fallback) else {
+            return false;
+        };
+        let name = name.as_ref();
+        self.pdftex_map
+            .entry(name.into())
+            .or_insert_with(|| FontInfo {
+                tfm_name: name.into(),
+                enc_file: "".into(),
+                pfx_file: "".into(),
+                vf_file: false,
+                styles: ModifierSeq::empty(),
+                glyphlist: None,
+                weblink: None,
+            })
+            .glyphlist = Some(idx);
+        true
+    }
 
     fn add_list(e: &mut Vec<GlyphList>, list: GlyphList) -> usize {
         e.iter().position(|e| *e == list).unwrap_or_else(|| {
@@ -97,6 +125,27 @@ impl<S: AsRef<str>, F: FnMut(&str) -> S> FontInfoStore<S, F> {
         })
     }
 
+    /// The Unicode subfonts of the `CJK` package (e.g. `gbsnu4e`) split a TrueType/OpenType font
+    /// into 256-character planes; the `pdftex.map` only has an entry for the whole font
+    /// (`gbsnu@Unicode@`), and the last two hex digits of the name are the plane.
+    fn unicode_subfont(&self, name: &str, styles: &mut ModifierSeq) -> Option<GlyphList> {
+        let split = name.len().checked_sub(2)?;
+        let (prefix, plane) = (name.get(..split)?, name.get(split..)?);
+        if prefix.is_empty() || !plane.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let plane = u32::from_str_radix(plane, 16).ok()?;
+        let parent = self.pdftex_map.get(format!("{prefix}@Unicode@").as_str())?;
+        *styles = parent.styles;
+        let mut table = UNDEFINED_LIST.clone();
+        for (i, g) in table.0.iter_mut().enumerate() {
+            if let Some(c) = char::from_u32(plane << 8 | i as u32) {
+                *g = Glyph(GlyphI::Unicode(c));
+            }
+        }
+        Some(table)
+    }
+
     #[allow(clippy::too_many_lines)]
     fn get_glyphlist_i<S2: AsRef<str>>(&mut self, name: S2) -> Option<usize> {
         let mut enc = match self.pdftex_map.get(name.as_ref()) {
@@ -164,6 +213,12 @@ impl<S: AsRef<str>, F: FnMut(&str) -> S> FontInfoStore<S, F> {
             }
             enc.pfx_file = "".into();
         }
+        if let Some(ls) = self.unicode_subfont(name.as_ref(), &mut enc.styles) {
+            let idx = Self::add_list(&mut self.glyph_lists, ls);
+            enc.glyphlist = Some(idx);
+            self.pdftex_map.insert(name.as_ref().into(), enc);
+            return Some(idx);
+        }
         if enc.vf_file {
             self.pdftex_map.insert(name.as_ref().into(), enc);
             return None;
@@ -322,7 +377,11 @@ fn parse_pdftex_map_line(st: &str) -> FontInfo {
         }
         if s.drop("<[") || s.drop("<<") || s.drop("<") {
             let f: String = s.read_until_ws().into();
-            if f.ends_with(".pfb") || f.ends_with(".pfa") || f.ends_with(".ttf") {
+            if f.ends_with(".pfb")
+                || f.ends_with(".pfa")
+                || f.ends_with(".ttf")
+                || f.ends_with(".otf")
+            {
                 pfx_file = f;
                 modify!(pfx_file);
             } else if f.ends_with(".enc") {
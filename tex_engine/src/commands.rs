@@ -21,10 +21,13 @@ use crate::utils::errors::TeXResult;
 use either::Either;
 use std::fmt::Display;
 
+pub mod closures;
 pub mod etex;
+pub mod luatex;
 pub mod methods;
 pub mod primitives;
 pub mod tex;
+pub mod xetex;
 
 /// A [`Token`] that has been resolved to a [`TeXCommand`] or a character (if not a control sequence / active character).
 #[derive(Debug)]
@@ -43,24 +46,32 @@ pub enum CharOrPrimitive<ET: EngineTypes> {
     Primitive(PrimitiveIdentifier),
 }
 
-/// A currently active conditional, e.g. `\ifnum`, `\ifx`, etc.
+/// A currently active conditional, e.g. `\ifnum`, `\ifx`, etc. The `bool` is whether the
+/// conditional was prefixed with `\unless`.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum ActiveConditional<I: TeXInt> {
     /// An unfinished conditional, e.g. `\ifnum` before both numbers have been read.
-    Unfinished(PrimitiveIdentifier),
+    Unfinished(PrimitiveIdentifier, bool),
     /// `\ifcase` of the provided number
     Case(I),
     /// A conditional that has evaluated to true
-    True(PrimitiveIdentifier),
+    True(PrimitiveIdentifier, bool),
     /// A conditional that has evaluated to false after the matching `\else` branch
-    Else(PrimitiveIdentifier),
+    Else(PrimitiveIdentifier, bool),
 }
 impl<I: TeXInt> ActiveConditional<I> {
     /// The (original, primitive) name of the conditional.
     pub fn name(&self) -> PrimitiveIdentifier {
         match self {
             Self::Case(_) => PRIMITIVES.ifcase,
-            Self::True(n) | Self::Unfinished(n) | Self::Else(n) => *n,
+            Self::True(n, _) | Self::Unfinished(n, _) | Self::Else(n, _) => *n,
+        }
+    }
+    /// Whether the conditional was prefixed with `\unless`.
+    pub fn unless(&self) -> bool {
+        match self {
+            Self::Case(_) => false,
+            Self::True(_, u) | Self::Unfinished(_, u) | Self::Else(_, u) => *u,
         }
     }
 }
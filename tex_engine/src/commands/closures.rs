@@ -0,0 +1,281 @@
+/*! Primitives implemented by closures instead of `fn` pointers, so that embedders can add
+commands that capture their own state (e.g. to collect annotations) without implementing
+[`EngineTypes`] themselves.
+
+[`PrimitiveCommand`]s only hold `fn` pointers; a closure primitive is therefore registered
+with a generic trampoline, and the closure itself is stored in the [`ClosurePrimitives`] of the
+[`EngineAux`](crate::engine::EngineAux). When invoked, the trampoline looks up the closure by
+the [`PrimitiveIdentifier`] the triggering [`Token`](crate::tex::tokens::Token) currently
+resolves to - so `\let` copies of a closure primitive work as expected.
+*/
+use crate::commands::primitives::PrimitiveIdentifier;
+use crate::commands::{CommandScope, PrimitiveCommand, ResolvedToken, TeXCommand};
+use crate::engine::state::State;
+use crate::engine::{EngineReferences, EngineTypes, TeXEngine};
+use crate::tex::nodes::WhatsitFunction;
+use crate::tex::tokens::Token;
+use crate::utils::errors::TeXResult;
+use crate::utils::{HMap, Ptr};
+
+type ExpandableFn<ET> = dyn Fn(
+    &mut EngineReferences<ET>,
+    &mut Vec<<ET as EngineTypes>::Token>,
+    <ET as EngineTypes>::Token,
+) -> TeXResult<(), ET>;
+type SimpleFn<ET> =
+    dyn Fn(&mut EngineReferences<ET>, <ET as EngineTypes>::Token) -> TeXResult<(), ET>;
+type ConditionalFn<ET> =
+    dyn Fn(&mut EngineReferences<ET>, <ET as EngineTypes>::Token) -> TeXResult<bool, ET>;
+type AssignmentFn<ET> =
+    dyn Fn(&mut EngineReferences<ET>, <ET as EngineTypes>::Token, bool) -> TeXResult<(), ET>;
+type WhatsitGetFn<ET> = dyn Fn(
+    &mut EngineReferences<ET>,
+    <ET as EngineTypes>::Token,
+) -> TeXResult<Option<Box<WhatsitFunction<ET>>>, ET>;
+
+/// A closure implementing a primitive; see the `register_*_closure` functions.
+pub enum Closure<ET: EngineTypes> {
+    Expandable(Ptr<ExpandableFn<ET>>),
+    SimpleExpandable(Ptr<SimpleFn<ET>>),
+    Conditional(Ptr<ConditionalFn<ET>>),
+    Unexpandable(Ptr<SimpleFn<ET>>),
+    Assignment(Ptr<AssignmentFn<ET>>),
+    Whatsit {
+        get: Ptr<WhatsitGetFn<ET>>,
+        immediate: Ptr<SimpleFn<ET>>,
+    },
+}
+impl<ET: EngineTypes> Clone for Closure<ET> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Expandable(f) => Self::Expandable(f.clone()),
+            Self::SimpleExpandable(f) => Self::SimpleExpandable(f.clone()),
+            Self::Conditional(f) => Self::Conditional(f.clone()),
+            Self::Unexpandable(f) => Self::Unexpandable(f.clone()),
+            Self::Assignment(f) => Self::Assignment(f.clone()),
+            Self::Whatsit { get, immediate } => Self::Whatsit {
+                get: get.clone(),
+                immediate: immediate.clone(),
+            },
+        }
+    }
+}
+
+/// The closures of all closure primitives of an engine.
+pub struct ClosurePrimitives<ET: EngineTypes>(HMap<PrimitiveIdentifier, Closure<ET>>);
+impl<ET: EngineTypes> Default for ClosurePrimitives<ET> {
+    fn default() -> Self {
+        Self(HMap::default())
+    }
+}
+impl<ET: EngineTypes> ClosurePrimitives<ET> {
+    /// The closure registered for the given primitive, if any.
+    pub fn get(&self, id: PrimitiveIdentifier) -> Option<&Closure<ET>> {
+        self.0.get(&id)
+    }
+}
+
+/// The closure the primitive `token` resolves to; throws an error if there is none, e.g.
+/// because the closure primitive was registered with a different engine.
+fn closure<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    token: &ET::Token,
+) -> TeXResult<Option<Closure<ET>>, ET> {
+    let found = match engine.resolve(token) {
+        ResolvedToken::Cmd(Some(TeXCommand::Primitive { name, .. })) => {
+            engine.aux.closures.get(*name).cloned()
+        }
+        _ => None,
+    };
+    if found.is_none() {
+        let name = token
+            .display(
+                engine.aux.memory.cs_interner(),
+                engine.state.get_catcode_scheme(),
+                engine.state.get_escape_char(),
+            )
+            .to_string();
+        engine.general_error(format!("No closure registered for {name}"))?;
+    }
+    Ok(found)
+}
+
+fn expandable<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    exp: &mut Vec<ET::Token>,
+    token: ET::Token,
+) -> TeXResult<(), ET> {
+    match closure(engine, &token)? {
+        Some(Closure::Expandable(f)) => f(engine, exp, token),
+        _ => Ok(()),
+    }
+}
+fn simple<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    token: ET::Token,
+) -> TeXResult<(), ET> {
+    match closure(engine, &token)? {
+        Some(Closure::SimpleExpandable(f) | Closure::Unexpandable(f)) => f(engine, token),
+        _ => Ok(()),
+    }
+}
+fn conditional<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    token: ET::Token,
+) -> TeXResult<bool, ET> {
+    match closure(engine, &token)? {
+        Some(Closure::Conditional(f)) => f(engine, token),
+        _ => Ok(false),
+    }
+}
+fn assignment<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    token: ET::Token,
+    global: bool,
+) -> TeXResult<(), ET> {
+    match closure(engine, &token)? {
+        Some(Closure::Assignment(f)) => f(engine, token, global),
+        _ => Ok(()),
+    }
+}
+fn whatsit_get<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    token: ET::Token,
+) -> TeXResult<Option<Box<WhatsitFunction<ET>>>, ET> {
+    match closure(engine, &token)? {
+        Some(Closure::Whatsit { get, .. }) => get(engine, token),
+        _ => Ok(None),
+    }
+}
+fn whatsit_immediate<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    token: ET::Token,
+) -> TeXResult<(), ET> {
+    match closure(engine, &token)? {
+        Some(Closure::Whatsit { immediate, .. }) => immediate(engine, token),
+        _ => Ok(()),
+    }
+}
+
+fn register<E: TeXEngine>(
+    engine: &mut E,
+    name: &'static str,
+    cmd: PrimitiveCommand<E::Types>,
+    closure: Closure<E::Types>,
+) {
+    let refs = engine.get_engine_refs();
+    refs.state.register_primitive(refs.aux, name, cmd);
+    if let Some(id) = refs.state.primitives().get_name(name) {
+        refs.aux.closures.0.insert(id, closure);
+    }
+}
+
+/// Registers an expandable primitive implemented by a closure, which should push its expansion
+/// to the `Vec` argument. See [`PrimitiveCommand::Expandable`].
+pub fn register_expandable_closure<E: TeXEngine, F>(engine: &mut E, name: &'static str, f: F)
+where
+    F: Fn(
+            &mut EngineReferences<E::Types>,
+            &mut Vec<<E::Types as EngineTypes>::Token>,
+            <E::Types as EngineTypes>::Token,
+        ) -> TeXResult<(), E::Types>
+        + 'static,
+{
+    let cmd = PrimitiveCommand::Expandable(expandable::<E::Types>);
+    register(engine, name, cmd, Closure::Expandable(Ptr::new(f)));
+}
+
+/// Registers an expandable primitive implemented by a closure that inserts tokens (if any) by
+/// itself. See [`PrimitiveCommand::SimpleExpandable`].
+pub fn register_simple_expandable_closure<E: TeXEngine, F>(engine: &mut E, name: &'static str, f: F)
+where
+    F: Fn(
+            &mut EngineReferences<E::Types>,
+            <E::Types as EngineTypes>::Token,
+        ) -> TeXResult<(), E::Types>
+        + 'static,
+{
+    let cmd = PrimitiveCommand::SimpleExpandable(simple::<E::Types>);
+    register(engine, name, cmd, Closure::SimpleExpandable(Ptr::new(f)));
+}
+
+/// Registers a conditional implemented by a closure. See [`PrimitiveCommand::Conditional`].
+pub fn register_conditional_closure<E: TeXEngine, F>(engine: &mut E, name: &'static str, f: F)
+where
+    F: Fn(
+            &mut EngineReferences<E::Types>,
+            <E::Types as EngineTypes>::Token,
+        ) -> TeXResult<bool, E::Types>
+        + 'static,
+{
+    let cmd = PrimitiveCommand::Conditional(conditional::<E::Types>);
+    register(engine, name, cmd, Closure::Conditional(Ptr::new(f)));
+}
+
+/// Registers an unexpandable primitive implemented by a closure. See
+/// [`PrimitiveCommand::Unexpandable`].
+pub fn register_unexpandable_closure<E: TeXEngine, F>(
+    engine: &mut E,
+    name: &'static str,
+    scope: CommandScope,
+    f: F,
+) where
+    F: Fn(
+            &mut EngineReferences<E::Types>,
+            <E::Types as EngineTypes>::Token,
+        ) -> TeXResult<(), E::Types>
+        + 'static,
+{
+    let cmd = PrimitiveCommand::Unexpandable {
+        scope,
+        apply: simple::<E::Types>,
+    };
+    register(engine, name, cmd, Closure::Unexpandable(Ptr::new(f)));
+}
+
+/// Registers an assignment primitive implemented by a closure; the `bool` argument is `true` for
+/// global assignments. See [`PrimitiveCommand::Assignment`].
+pub fn register_assignment_closure<E: TeXEngine, F>(engine: &mut E, name: &'static str, f: F)
+where
+    F: Fn(
+            &mut EngineReferences<E::Types>,
+            <E::Types as EngineTypes>::Token,
+            bool,
+        ) -> TeXResult<(), E::Types>
+        + 'static,
+{
+    let cmd = PrimitiveCommand::Assignment(assignment::<E::Types>);
+    register(engine, name, cmd, Closure::Assignment(Ptr::new(f)));
+}
+
+/// Registers a whatsit primitive implemented by closures: `get` is called when the primitive
+/// occurs and may return a function to be called at shipout, `immediate` is called instead
+/// after `\immediate`. See [`PrimitiveCommand::Whatsit`].
+pub fn register_whatsit_closure<E: TeXEngine, G, I>(
+    engine: &mut E,
+    name: &'static str,
+    get: G,
+    immediate: I,
+) where
+    G: Fn(
+            &mut EngineReferences<E::Types>,
+            <E::Types as EngineTypes>::Token,
+        ) -> TeXResult<Option<Box<WhatsitFunction<E::Types>>>, E::Types>
+        + 'static,
+    I: Fn(
+            &mut EngineReferences<E::Types>,
+            <E::Types as EngineTypes>::Token,
+        ) -> TeXResult<(), E::Types>
+        + 'static,
+{
+    let cmd = PrimitiveCommand::Whatsit {
+        get: whatsit_get::<E::Types>,
+        immediate: whatsit_immediate::<E::Types>,
+        the: None,
+    };
+    let closure = Closure::Whatsit {
+        get: Ptr::new(get),
+        immediate: Ptr::new(immediate),
+    };
+    register(engine, name, cmd, closure);
+}
@@ -1,7 +1,7 @@
 use super::primitives::*;
 use crate::commands::{
-    CharOrPrimitive, CommandScope, Macro, MacroSignature, PrimitiveCommand, ResolvedToken,
-    TeXCommand,
+    ActiveConditional, CharOrPrimitive, CommandScope, Macro, MacroSignature, PrimitiveCommand,
+    ResolvedToken, TeXCommand,
 };
 use crate::engine::filesystem::FileSystem;
 use crate::engine::fontsystem::Font;
@@ -185,6 +185,80 @@ pub fn currentgrouplevel<ET: EngineTypes>(
 ) -> TeXResult<ET::Int, ET> {
     Ok((engine.state.get_group_level() as i32).into())
 }
+pub fn currentiflevel<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<ET::Int, ET> {
+    Ok((engine.gullet.get_conditionals().len() as i32).into())
+}
+
+/// The conditionals in the order of their `\currentiftype` codes (starting at 1). Conditionals
+/// not in this list (i.e. ones added by an embedder) get the subsequent codes, in the order they
+/// were registered. Conditionals prefixed with `\unless` report the negated code.
+const IF_TYPES: [&str; 24] = [
+    "if",
+    "ifcat",
+    "ifnum",
+    "ifdim",
+    "ifodd",
+    "ifvmode",
+    "ifhmode",
+    "ifmmode",
+    "ifinner",
+    "ifvoid",
+    "ifhbox",
+    "ifvbox",
+    "ifx",
+    "ifeof",
+    "iftrue",
+    "iffalse",
+    "ifcase",
+    "ifdefined",
+    "ifcsname",
+    "iffontchar",
+    "ifincsname",
+    "ifpdfprimitive",
+    "ifpdfabsnum",
+    "ifpdfabsdim",
+];
+pub fn currentiftype<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<ET::Int, ET> {
+    let Some(cond) = engine.gullet.get_conditionals().last() else {
+        return Ok(ET::Int::default());
+    };
+    let (name, unless) = (cond.name(), cond.unless());
+    let primitives = engine.state.primitives();
+    let builtin =
+        |id: &PrimitiveIdentifier| IF_TYPES.iter().any(|s| primitives.get_name(s) == Some(*id));
+    let idx = IF_TYPES
+        .iter()
+        .position(|s| primitives.get_name(s) == Some(name))
+        .unwrap_or_else(|| {
+            IF_TYPES.len()
+                + primitives
+                    .conditionals()
+                    .iter()
+                    .filter(|id| !builtin(id))
+                    .position(|id| *id == name)
+                    .unwrap_or_default()
+        });
+    let code = (idx + 1) as i32;
+    Ok((if unless { -code } else { code }).into())
+}
+pub fn currentifbranch<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<ET::Int, ET> {
+    Ok(match engine.gullet.get_conditionals().last() {
+        Some(ActiveConditional::True(..)) => 1,
+        Some(ActiveConditional::Else(..)) => -1,
+        _ => 0,
+    }
+    .into())
+}
+
 /*\currentgrouptype returns a number representing the type of the innermost
 group:
 0: bottom level (no group)
@@ -634,6 +708,12 @@ pub fn unless<ET: EngineTypes>(
 ) -> TeXResult<(), ET> {
     let t = engine.need_next(false, &tk)?;
     match engine.resolve(&t) {
+        ResolvedToken::Cmd(Some(TeXCommand::Primitive {
+            name,
+            cmd: PrimitiveCommand::Conditional(_),
+        })) if *name == PRIMITIVES.ifcase => {
+            engine.general_error("You can't use `\\unless` before `\\ifcase`".to_string())
+        }
         ResolvedToken::Cmd(Some(TeXCommand::Primitive {
             name,
             cmd: PrimitiveCommand::Conditional(cnd),
@@ -759,7 +839,7 @@ pub fn glueshrinkorder<ET: EngineTypes>(
         None | Some(StretchShrink::Dim(_)) => ET::Int::from(0),
         Some(StretchShrink::Fil(_)) => ET::Int::from(1),
         Some(StretchShrink::Fill(_)) => ET::Int::from(2),
-        Some(StretchShrink::Filll(_)) => ET::Int::from(2),
+        Some(StretchShrink::Filll(_)) => ET::Int::from(3),
     })
 }
 pub fn gluestretchorder<ET: EngineTypes>(
@@ -771,7 +851,7 @@ pub fn gluestretchorder<ET: EngineTypes>(
         None | Some(StretchShrink::Dim(_)) => ET::Int::from(0),
         Some(StretchShrink::Fil(_)) => ET::Int::from(1),
         Some(StretchShrink::Fill(_)) => ET::Int::from(2),
-        Some(StretchShrink::Filll(_)) => ET::Int::from(2),
+        Some(StretchShrink::Filll(_)) => ET::Int::from(3),
     })
 }
 pub fn glueshrink<ET: EngineTypes>(
@@ -803,6 +883,88 @@ pub fn gluestretch<ET: EngineTypes>(
     })
 }
 
+pub fn mutoglue<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: ET::Token,
+) -> TeXResult<Skip<ET::Dim>, ET> {
+    use crate::tex::numerics::TeXDimen;
+    let muskip = engine.read_muskip(false, &tk)?;
+    // 1mu becomes 1pt, i.e. as if 1em were 18pt
+    Ok(ET::Num::muskip_to_skip(
+        muskip,
+        ET::Dim::from_sp(18 * 65536),
+    ))
+}
+pub fn gluetomu<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: ET::Token,
+) -> TeXResult<MuSkip<ET::MuDim>, ET> {
+    use crate::tex::numerics::{MuDim, MuStretchShrink};
+    let skip = engine.read_skip(false, &tk)?;
+    let mu = |d: ET::Dim, e: &EngineReferences<ET>| {
+        let sp: i64 = ET::Num::dim_to_int(d).into();
+        ET::MuDim::from_float(e, sp as f64 / 65536.0, b"mu")
+    };
+    let conv = |s: StretchShrink<ET::Dim>, e: &EngineReferences<ET>| match s {
+        StretchShrink::Dim(d) => MuStretchShrink::Mu(mu(d, e)),
+        StretchShrink::Fil(i) => MuStretchShrink::Fil(i),
+        StretchShrink::Fill(i) => MuStretchShrink::Fill(i),
+        StretchShrink::Filll(i) => MuStretchShrink::Filll(i),
+    };
+    Ok(MuSkip::new(
+        mu(skip.base, engine),
+        skip.stretch.map(|s| conv(s, engine)),
+        skip.shrink.map(|s| conv(s, engine)),
+    ))
+}
+
+/// The `n`th line of the current `\parshape`, where the last one repeats; `None` if `n` is not
+/// positive or there is no `\parshape`.
+fn parshape_line<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: &ET::Token,
+) -> TeXResult<Option<(ET::Dim, ET::Dim)>, ET> {
+    let n: i64 = engine.read_int(false, tk)?.into();
+    let shape = engine.state.get_parshape();
+    if n <= 0 {
+        return Ok(None);
+    }
+    Ok(shape.get(n as usize - 1).or_else(|| shape.last()).copied())
+}
+pub fn parshapeindent<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: ET::Token,
+) -> TeXResult<ET::Dim, ET> {
+    Ok(parshape_line(engine, &tk)?
+        .map(|(i, _)| i)
+        .unwrap_or_default())
+}
+pub fn parshapelength<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: ET::Token,
+) -> TeXResult<ET::Dim, ET> {
+    Ok(parshape_line(engine, &tk)?
+        .map(|(_, l)| l)
+        .unwrap_or_default())
+}
+/// `\parshapedimen(2n-1)` is the indentation of line `n`, `\parshapedimen(2n)` its length.
+pub fn parshapedimen<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: ET::Token,
+) -> TeXResult<ET::Dim, ET> {
+    let n: i64 = engine.read_int(false, &tk)?.into();
+    let shape = engine.state.get_parshape();
+    if n <= 0 {
+        return Ok(ET::Dim::default());
+    }
+    let line = ((n + 1) / 2) as usize;
+    Ok(match shape.get(line - 1).or_else(|| shape.last()) {
+        None => ET::Dim::default(),
+        Some((i, _)) if n % 2 == 1 => *i,
+        Some((_, l)) => *l,
+    })
+}
+
 const PRIMITIVE_INTS: &[&str] = &[
     "savinghyphcodes",
     "tracingassigns",
@@ -828,6 +990,9 @@ pub fn register_etex_primitives<E: TeXEngine>(engine: &mut E) {
 
     register_int(engine, "currentgrouplevel", currentgrouplevel, None);
     register_int(engine, "currentgrouptype", currentgrouptype, None);
+    register_int(engine, "currentiflevel", currentiflevel, None);
+    register_int(engine, "currentiftype", currentiftype, None);
+    register_int(engine, "currentifbranch", currentifbranch, None);
     register_int(engine, "lastnodetype", lastnodetype, None);
     register_int(engine, "eTeXversion", eTeXversion, None);
     register_int(engine, "glueshrinkorder", glueshrinkorder, None);
@@ -839,6 +1004,11 @@ pub fn register_etex_primitives<E: TeXEngine>(engine: &mut E) {
     register_dim(engine, "fontcharic", fontcharic, None);
     register_dim(engine, "glueshrink", glueshrink, None);
     register_dim(engine, "gluestretch", gluestretch, None);
+    register_skip(engine, "mutoglue", mutoglue, None);
+    register_muskip(engine, "gluetomu", gluetomu, None);
+    register_dim(engine, "parshapeindent", parshapeindent, None);
+    register_dim(engine, "parshapelength", parshapelength, None);
+    register_dim(engine, "parshapedimen", parshapedimen, None);
 
     register_assignment(engine, "protected", |e, cmd, g| {
         protected(e, cmd, false, false, false, g)
@@ -869,20 +1039,12 @@ pub fn register_etex_primitives<E: TeXEngine>(engine: &mut E) {
     cmtodo!(engine, beginL);
     cmtodo!(engine, beginR);
     cmtodo!(engine, clubpenalties);
-    cmtodo!(engine, currentifbranch);
-    cmtodo!(engine, currentiflevel);
-    cmtodo!(engine, currentiftype);
     cmtodo!(engine, displaywidowpenalties);
     cmtodo!(engine, endL);
     cmtodo!(engine, endR);
-    cmtodo!(engine, gluetomu);
     cmtodo!(engine, interlinepenalties);
     cmtodo!(engine, lastlinefit);
-    cmtodo!(engine, mutoglue);
     cmtodo!(engine, pagediscards);
-    cmtodo!(engine, parshapedimen);
-    cmtodo!(engine, parshapeindent);
-    cmtodo!(engine, parshapelength);
     cmtodo!(engine, showgroups);
     cmtodo!(engine, showifs);
     cmtodo!(engine, showtokens);
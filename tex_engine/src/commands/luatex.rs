@@ -0,0 +1,101 @@
+/*! The LuaTeX catcode table primitives (`\catcodetable`, `\savecatcodetable`,
+`\initcatcodetable`). These are not part of any of the engines implemented here
+and need to be registered explicitly via [`register_catcode_table_primitives`].
+*/
+use super::primitives::*;
+use crate::commands::CommandScope;
+use crate::engine::mouth::Mouth;
+use crate::engine::state::State;
+use crate::engine::utils::trace::CatcodeCause;
+use crate::engine::{EngineReferences, EngineTypes, TeXEngine};
+use crate::utils::errors::TeXResult;
+
+/// The largest valid catcode table index.
+pub const MAX_CATCODE_TABLE: u16 = 0x7FFF;
+
+fn read_table_index<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    skip_eq: bool,
+    tk: &ET::Token,
+) -> TeXResult<Option<usize>, ET> {
+    let i: i64 = engine.read_int(skip_eq, tk)?.into();
+    if !(0..=i64::from(MAX_CATCODE_TABLE)).contains(&i) {
+        engine.general_error(format!("Invalid \\catcode table {i}"))?;
+        return Ok(None);
+    }
+    Ok(Some(i as usize))
+}
+
+pub fn catcodetable_get<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<ET::Int, ET> {
+    Ok(ET::Int::from(engine.state.get_catcode_table() as i32))
+}
+pub fn catcodetable_set<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: ET::Token,
+    globally: bool,
+) -> TeXResult<(), ET> {
+    if let Some(idx) = read_table_index(engine, true, &tk)? {
+        let before = engine
+            .aux
+            .catcode_trace
+            .is_some()
+            .then(|| engine.state.get_catcode_scheme().clone());
+        if !engine.state.set_catcode_table(engine.aux, idx, globally) {
+            return engine.general_error(format!("Invalid \\catcode table {idx}"));
+        }
+        if let (Some(trace), Some(before)) = (engine.aux.catcode_trace.as_mut(), before) {
+            let at = engine.mouth.current_sourceref();
+            trace.changed_scheme::<ET::Char>(
+                &before,
+                engine.state.get_catcode_scheme(),
+                CatcodeCause::Table,
+                at,
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn savecatcodetable<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: ET::Token,
+) -> TeXResult<(), ET> {
+    if let Some(idx) = read_table_index(engine, false, &tk)? {
+        engine.state.save_catcode_table(idx);
+    }
+    Ok(())
+}
+
+pub fn initcatcodetable<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: ET::Token,
+) -> TeXResult<(), ET> {
+    if let Some(idx) = read_table_index(engine, false, &tk)? {
+        engine.state.init_catcode_table(idx);
+    }
+    Ok(())
+}
+
+pub fn register_catcode_table_primitives<E: TeXEngine>(engine: &mut E) {
+    register_int(
+        engine,
+        "catcodetable",
+        catcodetable_get,
+        Some(catcodetable_set),
+    );
+    register_unexpandable(
+        engine,
+        "savecatcodetable",
+        CommandScope::Any,
+        savecatcodetable,
+    );
+    register_unexpandable(
+        engine,
+        "initcatcodetable",
+        CommandScope::Any,
+        initcatcodetable,
+    );
+}
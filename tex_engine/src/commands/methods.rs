@@ -26,7 +26,7 @@ use crate::tex::nodes::NodeTrait;
 use crate::tex::nodes::{
     BoxTarget, LeaderBody, LeaderSkip, LeaderType, Leaders, ListTarget, NodeList,
 };
-use crate::tex::numerics::Skip;
+use crate::tex::numerics::{Skip, TeXDimen};
 use crate::tex::tokens::control_sequences::CSHandler;
 use crate::tex::tokens::token_lists::TokenList;
 use crate::tex::tokens::Token;
@@ -995,9 +995,12 @@ pub(crate) fn do_leaders<ET: EngineTypes>(
                 }
             }
             ResolvedToken::Cmd(Some(TeXCommand::Primitive {name,..})) if *name == PRIMITIVES.hrule || *name == PRIMITIVES.vrule => {
-                let mut width = None;
-                let mut height = None;
-                let mut depth = None;
+                // the dimensions a rule does not "run" along; `None` takes them from the enclosing box
+                let (mut width,mut height,mut depth) = if *name == PRIMITIVES.hrule {
+                    (None,Some(ET::Dim::from_sp(26214)),Some(ET::Dim::default()))
+                } else {
+                    (Some(ET::Dim::from_sp(26214)),None,None)
+                };
                 loop {
                     match engine.read_keywords(&[b"width",b"height",b"depth"])? {
                         Some(b"width") => {
@@ -25,6 +25,10 @@ macro_rules! cmstodos {
 macro_rules! cmtodo {
     ($engine:ident,$name:ident) => {{
         let command = $crate::commands::PrimitiveCommand::SimpleExpandable(|e, _| {
+            e.approximation(
+                $crate::engine::utils::approximations::ApproximationKind::StubbedPrimitive,
+                concat!("\\", stringify!($name)),
+            );
             e.general_error(format!(
                 "Not yet implemented: \\{} at {}",
                 stringify!($name),
@@ -42,6 +46,10 @@ macro_rules! cmstodo {
         let command = $crate::commands::PrimitiveCommand::Unexpandable {
             scope: $crate::commands::CommandScope::Any,
             apply: |e, _| {
+                e.approximation(
+                    $crate::engine::utils::approximations::ApproximationKind::StubbedPrimitive,
+                    concat!("\\", stringify!($name)),
+                );
                 e.general_error(format!(
                     "Not yet implemented: \\{} at {}",
                     stringify!($name),
@@ -338,12 +346,14 @@ pub fn register_whatsit<E: TeXEngine>(
 pub struct PrimitiveCommands<ET: EngineTypes> {
     commands: Vec<TeXCommand<ET>>,
     names: HMap<&'static str, u16>,
+    conditionals: Vec<PrimitiveIdentifier>,
 }
 impl<ET: EngineTypes> Default for PrimitiveCommands<ET> {
     fn default() -> Self {
         Self {
             commands: Vec::new(),
             names: HMap::default(),
+            conditionals: Vec::new(),
         }
     }
 }
@@ -365,10 +375,17 @@ impl<ET: EngineTypes> PrimitiveCommands<ET> {
                 },
             );
         }
+        if matches!(cmd, PrimitiveCommand::Conditional(_)) && !self.conditionals.contains(&id) {
+            self.conditionals.push(id);
+        }
         self.commands[idx] = TeXCommand::Primitive { name: id, cmd };
         self.names.insert(name, idx as u16);
         id
     }
+    /// The conditionals, in the order they were first registered.
+    pub fn conditionals(&self) -> &[PrimitiveIdentifier] {
+        &self.conditionals
+    }
     /// Return the primitive command with the given identifier.
     pub fn get_id(&self, id: PrimitiveIdentifier) -> Option<&TeXCommand<ET>> {
         let idx = id.as_u16() as usize;
@@ -403,6 +420,8 @@ pub struct PrimitiveInterner {
     pub tracingcommands: PrimitiveIdentifier,
     pub tracinggroups: PrimitiveIdentifier,
     pub tracingrestores: PrimitiveIdentifier,
+    pub showboxdepth: PrimitiveIdentifier,
+    pub showboxbreadth: PrimitiveIdentifier,
     pub r#else: PrimitiveIdentifier,
     pub fi: PrimitiveIdentifier,
     pub or: PrimitiveIdentifier,
@@ -472,6 +491,17 @@ pub struct PrimitiveInterner {
     pub day: PrimitiveIdentifier,
     pub time: PrimitiveIdentifier,
     pub mathchar: PrimitiveIdentifier,
+    pub interchartokenstate: PrimitiveIdentifier,
+    pub spaceskip: PrimitiveIdentifier,
+    pub xspaceskip: PrimitiveIdentifier,
+    pub discretionary: PrimitiveIdentifier,
+    pub looseness: PrimitiveIdentifier,
+    pub interlinepenalty: PrimitiveIdentifier,
+    pub clubpenalty: PrimitiveIdentifier,
+    pub widowpenalty: PrimitiveIdentifier,
+    pub brokenpenalty: PrimitiveIdentifier,
+    pub tracingparagraphs: PrimitiveIdentifier,
+    pub interactionmode: PrimitiveIdentifier,
 }
 impl PrimitiveInterner {
     fn new() -> Self {
@@ -489,6 +519,8 @@ impl PrimitiveInterner {
         let tracingcommands = PrimitiveIdentifier(interner.get_or_intern_static("tracingcommands"));
         let tracinggroups = PrimitiveIdentifier(interner.get_or_intern_static("tracinggroups"));
         let tracingrestores = PrimitiveIdentifier(interner.get_or_intern_static("tracingrestores"));
+        let showboxdepth = PrimitiveIdentifier(interner.get_or_intern_static("showboxdepth"));
+        let showboxbreadth = PrimitiveIdentifier(interner.get_or_intern_static("showboxbreadth"));
         let r#else = PrimitiveIdentifier(interner.get_or_intern_static("else"));
         let fi = PrimitiveIdentifier(interner.get_or_intern_static("fi"));
         let or = PrimitiveIdentifier(interner.get_or_intern_static("or"));
@@ -560,6 +592,20 @@ impl PrimitiveInterner {
         let day = PrimitiveIdentifier(interner.get_or_intern_static("day"));
         let time = PrimitiveIdentifier(interner.get_or_intern_static("time"));
         let mathchar = PrimitiveIdentifier(interner.get_or_intern_static("mathchar"));
+        let interchartokenstate =
+            PrimitiveIdentifier(interner.get_or_intern_static("XeTeXinterchartokenstate"));
+        let spaceskip = PrimitiveIdentifier(interner.get_or_intern_static("spaceskip"));
+        let xspaceskip = PrimitiveIdentifier(interner.get_or_intern_static("xspaceskip"));
+        let discretionary = PrimitiveIdentifier(interner.get_or_intern_static("discretionary"));
+        let looseness = PrimitiveIdentifier(interner.get_or_intern_static("looseness"));
+        let interlinepenalty =
+            PrimitiveIdentifier(interner.get_or_intern_static("interlinepenalty"));
+        let clubpenalty = PrimitiveIdentifier(interner.get_or_intern_static("clubpenalty"));
+        let widowpenalty = PrimitiveIdentifier(interner.get_or_intern_static("widowpenalty"));
+        let brokenpenalty = PrimitiveIdentifier(interner.get_or_intern_static("brokenpenalty"));
+        let tracingparagraphs =
+            PrimitiveIdentifier(interner.get_or_intern_static("tracingparagraphs"));
+        let interactionmode = PrimitiveIdentifier(interner.get_or_intern_static("interactionmode"));
         PrimitiveInterner {
             interner: RwLock::new(interner),
             globaldefs,
@@ -585,6 +631,8 @@ impl PrimitiveInterner {
             everyeof,
             count,
             tracingrestores,
+            showboxdepth,
+            showboxbreadth,
             noexpand,
             endcsname,
             unexpanded,
@@ -640,7 +688,18 @@ impl PrimitiveInterner {
             month,
             day,
             time,
-            mathchar
+            mathchar,
+            interchartokenstate,
+            spaceskip,
+            xspaceskip,
+            discretionary,
+            looseness,
+            interlinepenalty,
+            clubpenalty,
+            widowpenalty,
+            brokenpenalty,
+            tracingparagraphs,
+            interactionmode,
         }
     }
 
@@ -13,7 +13,9 @@ use crate::engine::mouth::Mouth;
 use crate::engine::state::{GroupType, State};
 use crate::engine::stomach::methods::SplitResult;
 use crate::engine::stomach::{Stomach, TeXMode};
+use crate::engine::utils::approximations::ApproximationKind;
 use crate::engine::utils::outputs::Outputs;
+use crate::engine::utils::trace::CatcodeCause;
 use crate::engine::{EngineReferences, EngineTypes, TeXEngine};
 use crate::tex::catcodes::{CategoryCode, CommandCode};
 use crate::tex::characters::{Character, CharacterMap};
@@ -25,7 +27,9 @@ use crate::tex::nodes::math::{
     UnresolvedMathFontStyle,
 };
 use crate::tex::nodes::vertical::{VNode, VerticalNodeListType};
-use crate::tex::nodes::{BoxTarget, LeaderType, ListTarget, NodeList, NodeTrait, WhatsitFunction};
+use crate::tex::nodes::{
+    BoxTarget, CustomNodeTrait, LeaderType, ListTarget, NodeList, NodeTrait, WhatsitFunction,
+};
 use crate::tex::numerics::TeXDimen;
 use crate::tex::numerics::{MuSkip, NumSet, Skip};
 use crate::tex::tokens::control_sequences::{CSHandler, ResolvedCSName};
@@ -120,10 +124,13 @@ pub fn discretionary<ET: EngineTypes>(
     engine: &mut EngineReferences<ET>,
     tk: ET::Token,
 ) -> TeXResult<(), ET> {
-    engine.skip_argument(&tk)?;
-    engine.skip_argument(&tk)?;
-    engine.skip_argument(&tk)
-    // TODO
+    if engine.stomach.data_mut().mode().is_math() {
+        // TODO discretionaries in math mode
+        engine.skip_argument(&tk)?;
+        engine.skip_argument(&tk)?;
+        return engine.skip_argument(&tk);
+    }
+    crate::engine::stomach::methods::open_discretionary(engine, vec![], &tk)
 }
 
 pub fn endinput<ET: EngineTypes>(
@@ -134,10 +141,39 @@ pub fn endinput<ET: EngineTypes>(
     Ok(())
 }
 
+/// Sets `\interactionmode` (globally, as in TeX); since we never prompt the user, the mode
+/// has no further effect.
+fn set_interaction<ET: EngineTypes>(engine: &mut EngineReferences<ET>, mode: i32) {
+    engine
+        .state
+        .set_primitive_int(engine.aux, PRIMITIVES.interactionmode, mode.into(), true);
+}
+pub fn batchmode<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<(), ET> {
+    set_interaction(engine, 0);
+    Ok(())
+}
+pub fn nonstopmode<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<(), ET> {
+    set_interaction(engine, 1);
+    Ok(())
+}
+pub fn scrollmode<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<(), ET> {
+    set_interaction(engine, 2);
+    Ok(())
+}
 pub fn errorstopmode<ET: EngineTypes>(
-    _engine: &mut EngineReferences<ET>,
+    engine: &mut EngineReferences<ET>,
     _tk: ET::Token,
 ) -> TeXResult<(), ET> {
+    set_interaction(engine, 3);
     Ok(())
 }
 
@@ -171,6 +207,10 @@ pub fn catcode_set<ET: EngineTypes>(
     }
     let cc: CategoryCode = (val as u8).try_into().unwrap();
     engine.state.set_catcode(engine.aux, char, cc, globally);
+    if let Some(trace) = engine.aux.catcode_trace.as_mut() {
+        let at = engine.mouth.current_sourceref();
+        trace.changed(char.into(), cc, CatcodeCause::Assignment, at);
+    }
     Ok(())
 }
 
@@ -949,22 +989,22 @@ pub fn r#else<ET: EngineTypes>(
 ) -> TeXResult<(), ET> {
     let conds = engine.gullet.get_conditionals();
     let name = match conds.pop() {
-        Some(ActiveConditional::True(id)) => {
-            conds.push(ActiveConditional::Else(id));
+        Some(ActiveConditional::True(id, unless)) => {
+            conds.push(ActiveConditional::Else(id, unless));
             id
         }
         Some(c @ ActiveConditional::Case(_)) => {
             conds.push(c);
             PRIMITIVES.ifcase
         }
-        Some(u @ ActiveConditional::Unfinished(_)) => {
+        Some(u @ ActiveConditional::Unfinished(..)) => {
             conds.push(u);
             engine.mouth.requeue(tk);
             let relax = engine.aux.memory.cs_interner_mut().cs_from_str("relax");
             engine.mouth.requeue(ET::Token::from_cs(relax));
             return Ok(());
         }
-        Some(ActiveConditional::Else(_)) => {
+        Some(ActiveConditional::Else(..)) => {
             return Err(TeXError::General(
                 "Unexpected `\\else` in `\\else`-branch".to_string(),
             ))
@@ -1003,9 +1043,9 @@ pub fn or<ET: EngineTypes>(engine: &mut EngineReferences<ET>, tk: ET::Token) ->
     let conds = engine.gullet.get_conditionals();
     match conds.pop() {
         Some(ActiveConditional::Case(_)) => {
-            conds.push(ActiveConditional::Else(PRIMITIVES.ifcase));
+            conds.push(ActiveConditional::Else(PRIMITIVES.ifcase, false));
         }
-        Some(u @ ActiveConditional::Unfinished(_)) => {
+        Some(u @ ActiveConditional::Unfinished(..)) => {
             conds.push(u);
             engine.mouth.requeue(tk);
             let relax = engine.aux.memory.cs_interner_mut().cs_from_str("relax");
@@ -1248,16 +1288,38 @@ pub fn unbox<ET: EngineTypes>(
     } else {
         engine.state.take_box_register(idx)
     };
+    let provenance = match &bx {
+        Some(TeXBox::V { start, end, .. } | TeXBox::H { start, end, .. }) => {
+            <ET::CustomNode as CustomNodeTrait<ET>>::provenance(engine, *start, *end)
+        }
+        None => None,
+    };
     match bx {
         None => (),
-        Some(TeXBox::V { children, .. }) if tp == BoxType::Vertical => {
-            for c in children.into_vec() {
-                ET::Stomach::add_node_v(engine, c)?
+        Some(TeXBox::V { children, .. }) if tp == BoxType::Vertical => match provenance {
+            None => {
+                for c in children.into_vec() {
+                    ET::Stomach::add_node_v(engine, c)?
+                }
             }
-        }
+            Some((begin, end)) => {
+                ET::Stomach::add_node_v(engine, VNode::Custom(begin))?;
+                for c in children.into_vec() {
+                    ET::Stomach::add_node_v(engine, c)?
+                }
+                ET::Stomach::add_node_v(engine, VNode::Custom(end))?
+            }
+        },
         Some(TeXBox::H { children, .. }) if tp == BoxType::Horizontal => {
             match engine.stomach.data_mut().open_lists.last_mut() {
-                Some(NodeList::Horizontal { children: ls, .. }) => ls.extend(children.into_vec()),
+                Some(NodeList::Horizontal { children: ls, .. }) => match provenance {
+                    None => ls.extend(children.into_vec()),
+                    Some((begin, end)) => {
+                        ls.push(HNode::Custom(begin));
+                        ls.extend(children.into_vec());
+                        ls.push(HNode::Custom(end));
+                    }
+                },
                 _ => {
                     return engine.general_error(
                         "Cannot unbox \\hbox outside of horizontal mode".to_string(),
@@ -1653,6 +1715,10 @@ pub fn input<ET: EngineTypes>(
     if is_file && !file.exists() {
         return engine.general_error(format!("File does not exist: {}", file.path().display()));
     }
+    engine
+        .aux
+        .limits
+        .check_input_depth(engine.mouth.file_trace().count() + 1)?;
     engine.aux.outputs.file_open(&file);
     engine.push_file(file);
     Ok(())
@@ -1661,9 +1727,9 @@ pub fn input<ET: EngineTypes>(
 pub fn fi<ET: EngineTypes>(engine: &mut EngineReferences<ET>, tk: ET::Token) -> TeXResult<(), ET> {
     let conds = engine.gullet.get_conditionals();
     let name = match conds.pop() {
-        Some(ActiveConditional::True(id) | ActiveConditional::Else(id)) => id,
+        Some(ActiveConditional::True(id, _) | ActiveConditional::Else(id, _)) => id,
         Some(ActiveConditional::Case(_)) => PRIMITIVES.ifcase,
-        Some(u @ ActiveConditional::Unfinished(_)) => {
+        Some(u @ ActiveConditional::Unfinished(..)) => {
             conds.push(u);
             engine.mouth.requeue(tk);
             let relax = engine.aux.memory.cs_interner_mut().cs_from_str("relax");
@@ -1924,7 +1990,11 @@ pub fn right<ET: EngineTypes>(
         }) => {
             engine.state.pop(engine.aux, engine.mouth);
             let (children, None) = children.close(start, engine.mouth.current_sourceref()) else {
-                unreachable!()
+                return Err(engine
+                    .aux
+                    .hardening
+                    .violation("\\eqno in a \\left\\right group")
+                    .into());
             };
             ET::Stomach::add_node_m(
                 engine,
@@ -1996,6 +2066,36 @@ pub fn cleaders<ET: EngineTypes>(
     super::methods::do_leaders(engine, LeaderType::C, &tk)
 }
 
+/// Writes the contents of a box register to the log, in TeX's format (see
+/// [`ShowBox`](crate::tex::nodes::show::ShowBox)).
+pub fn showbox<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: ET::Token,
+) -> TeXResult<(), ET> {
+    let idx = engine.read_register_index(false, &tk)?;
+    let mut out = format!("> \\box{idx}=");
+    match engine.state.get_box_register(idx) {
+        None => out.push_str("void"),
+        Some(bx) => crate::tex::nodes::show::ShowBox {
+            int: engine.aux.memory.cs_interner(),
+            cc: engine.state.get_catcode_scheme(),
+            escapechar: engine.state.get_escape_char(),
+            font: engine.state.get_current_font().clone(),
+            depth: engine
+                .state
+                .get_primitive_int(PRIMITIVES.showboxdepth)
+                .into(),
+            breadth: engine
+                .state
+                .get_primitive_int(PRIMITIVES.showboxbreadth)
+                .into(),
+        }
+        .write(bx, &mut out),
+    }
+    engine.aux.outputs.write_neg1(out);
+    Ok(())
+}
+
 pub fn message<ET: EngineTypes>(
     engine: &mut EngineReferences<ET>,
     tk: ET::Token,
@@ -4177,10 +4277,31 @@ pub fn char_slash<ET: EngineTypes>(
     Ok(())
 }
 pub fn char_dash<ET: EngineTypes>(
-    _engine: &mut EngineReferences<ET>,
+    engine: &mut EngineReferences<ET>,
     _tk: ET::Token,
 ) -> TeXResult<(), ET> {
-    // TODO
+    if !engine.stomach.data_mut().mode().is_horizontal() {
+        // TODO discretionaries in math mode
+        return Ok(());
+    }
+    // `\-` is `\discretionary{\char\hyphenchar\font}{}{}`
+    let font = engine.state.get_current_font().clone();
+    let hyphenchar: i64 = font.get_hyphenchar().into();
+    let pre = match u64::try_from(hyphenchar)
+        .ok()
+        .and_then(|c| ET::Char::try_from(c).ok())
+    {
+        Some(char) if font.has_char(char) => vec![HNode::Char { char, font }],
+        _ => vec![],
+    };
+    ET::Stomach::add_node_h(
+        engine,
+        HNode::Discretionary {
+            pre: pre.into(),
+            post: Box::default(),
+            nobreak: Box::default(),
+        },
+    );
     Ok(())
 }
 
@@ -4473,11 +4594,19 @@ pub fn register_tex_primitives<E: TeXEngine>(engine: &mut E) {
         CommandScope::SwitchesToHorizontalOrMath,
         r#char,
     );
-    register_unexpandable(engine, "discretionary", CommandScope::Any, discretionary);
+    register_unexpandable(
+        engine,
+        "discretionary",
+        CommandScope::SwitchesToHorizontalOrMath,
+        discretionary,
+    );
     register_unexpandable(engine, "dump", CommandScope::Any, |_, _| Ok(()));
     register_unexpandable(engine, "endcsname", CommandScope::Any, endcsname);
     register_unexpandable(engine, "endgroup", CommandScope::Any, endgroup);
     register_unexpandable(engine, "end", CommandScope::Any, end);
+    register_unexpandable(engine, "batchmode", CommandScope::Any, batchmode);
+    register_unexpandable(engine, "nonstopmode", CommandScope::Any, nonstopmode);
+    register_unexpandable(engine, "scrollmode", CommandScope::Any, scrollmode);
     register_unexpandable(engine, "errorstopmode", CommandScope::Any, errorstopmode);
     register_unexpandable(engine, "halign", CommandScope::Any, halign);
     register_unexpandable(engine, "valign", CommandScope::SwitchesToHorizontal, valign);
@@ -4570,8 +4699,17 @@ pub fn register_tex_primitives<E: TeXEngine>(engine: &mut E) {
     }
     register_unexpandable(engine, "mark", CommandScope::Any, mark);
     register_unexpandable(engine, "/", CommandScope::Any, char_slash);
-    register_unexpandable(engine, "-", CommandScope::Any, char_dash);
-    register_unexpandable(engine, "showlists", CommandScope::Any, |_, _| Ok(())); // TODO
+    register_unexpandable(
+        engine,
+        "-",
+        CommandScope::SwitchesToHorizontalOrMath,
+        char_dash,
+    );
+    register_unexpandable(engine, "showbox", CommandScope::Any, showbox);
+    register_unexpandable(engine, "showlists", CommandScope::Any, |e, _| {
+        e.approximation(ApproximationKind::StubbedPrimitive, "\\showlists");
+        Ok(())
+    }); // TODO
     register_unexpandable(engine, "crcr", CommandScope::Any, |_, _| Ok(()));
     register_unexpandable(engine, "cr", CommandScope::Any, |_, _| {
         Err(TeXError::General("Unexpected \\cr".to_string()))
@@ -4695,7 +4833,10 @@ pub fn register_tex_primitives<E: TeXEngine>(engine: &mut E) {
         scriptscriptstyle,
     );
 
-    register_unexpandable(engine, "nonscript", CommandScope::MathOnly, |_, _| Ok(())); // TODO
+    register_unexpandable(engine, "nonscript", CommandScope::MathOnly, |e, _| {
+        e.approximation(ApproximationKind::StubbedPrimitive, "\\nonscript");
+        Ok(())
+    }); // TODO
 
     register_whatsit(engine, "closeout", closeout, closeout_immediate, None);
     register_whatsit(engine, "openout", openout, openout_immediate, None);
@@ -4713,11 +4854,7 @@ pub fn register_tex_primitives<E: TeXEngine>(engine: &mut E) {
 
     cmtodos!(
         engine,
-        scrollmode,
-        nonstopmode,
-        batchmode,
         show,
-        showbox,
         showthe,
         noboundary,
         setlanguage,
@@ -0,0 +1,72 @@
+/*! The XeTeX inter-character token primitives (`\XeTeXinterchartokenstate`, `\XeTeXcharclass`,
+`\XeTeXinterchartoks`), used by packages that insert glue or annotations between characters of
+different classes (e.g. CJK-Latin spacing or French punctuation). Every character has a class
+(default `0`); if `\XeTeXinterchartokenstate` is positive, the tokens `\XeTeXinterchartoks a b`
+are inserted between two characters of classes `a` and `b`, where the start and end of a word
+count as class `4095`, and characters of class `4096` are skipped. These are not part of any of
+the engines implemented here and need to be registered explicitly via
+[`register_interchar_primitives`].
+*/
+use super::primitives::*;
+use crate::engine::state::State;
+use crate::engine::stomach::methods::{assign_toks, IGNORED_CLASS};
+use crate::engine::{EngineReferences, EngineTypes, TeXEngine};
+use crate::utils::errors::TeXResult;
+
+fn read_class<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    skip_eq: bool,
+    tk: &ET::Token,
+) -> TeXResult<Option<u16>, ET> {
+    let i: i64 = engine.read_int(skip_eq, tk)?.into();
+    if !(0..=IGNORED_CLASS as i64).contains(&i) {
+        engine.general_error(format!("Bad character class ({i})"))?;
+        return Ok(None);
+    }
+    Ok(Some(i as u16))
+}
+
+pub fn charclass_get<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: ET::Token,
+) -> TeXResult<ET::Int, ET> {
+    let char = engine.read_charcode(false, &tk)?;
+    Ok(ET::Int::from(engine.state.get_charclass(char) as i32))
+}
+pub fn charclass_set<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: ET::Token,
+    globally: bool,
+) -> TeXResult<(), ET> {
+    let char = engine.read_charcode(false, &tk)?;
+    if let Some(class) = read_class(engine, true, &tk)? {
+        engine
+            .state
+            .set_charclass(engine.aux, char, class, globally);
+    }
+    Ok(())
+}
+
+pub fn interchartoks<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: ET::Token,
+    globally: bool,
+) -> TeXResult<(), ET> {
+    let Some(from) = read_class(engine, false, &tk)? else {
+        return Ok(());
+    };
+    let Some(to) = read_class(engine, false, &tk)? else {
+        return Ok(());
+    };
+    assign_toks(engine, tk, |engine, ls| {
+        engine
+            .state
+            .set_interchar_toks(engine.aux, from, to, ls, globally)
+    })
+}
+
+pub fn register_interchar_primitives<E: TeXEngine>(engine: &mut E) {
+    register_primitive_int(engine, &["XeTeXinterchartokenstate"]);
+    register_int(engine, "XeTeXcharclass", charclass_get, Some(charclass_set));
+    register_assignment(engine, "XeTeXinterchartoks", interchartoks);
+}
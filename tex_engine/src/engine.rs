@@ -2,16 +2,24 @@
 /*! A TeX engine combines all the necessary components into a struct capable of compiling a TeX file into
     some output format.
 */
+use crate::commands::closures::ClosurePrimitives;
 use crate::commands::primitives::PRIMITIVES;
 use crate::commands::{ResolvedToken, TeXCommand};
 use crate::engine::filesystem::{File, FileSystem, VirtualFile};
 use crate::engine::fontsystem::{Font, FontSystem, TfmFont, TfmFontSystem};
 use crate::engine::gullet::{DefaultGullet, Gullet};
 use crate::engine::mouth::{DefaultMouth, Mouth};
+use crate::engine::state::snapshot::DefinitionSites;
 use crate::engine::state::State;
 use crate::engine::stomach::{DefaultStomach, Stomach};
+use crate::engine::utils::approximations::Approximations;
+use crate::engine::utils::debugger::Debugger;
+use crate::engine::utils::hardening::Hardening;
+use crate::engine::utils::limits::ResourceGuard;
 use crate::engine::utils::memory::MemoryManager;
 use crate::engine::utils::outputs::{LogOutputs, Outputs};
+use crate::engine::utils::progress::ProgressReporter;
+use crate::engine::utils::trace::{CatcodeTrace, ConditionalTrace, MacroTrace};
 use crate::tex;
 use crate::tex::catcodes::CommandCode;
 use crate::tex::characters::Character;
@@ -79,11 +87,75 @@ pub struct EngineAux<ET: EngineTypes> {
     /// printing to logs or the terminal
     pub outputs: ET::Outputs,
     /// start time of the current job
-    pub start_time: chrono::DateTime<chrono::Local>,
+    pub start_time: chrono::DateTime<chrono::FixedOffset>,
+    /// if set, the time every job is assumed to start at, rather than the current time (or
+    /// `SOURCE_DATE_EPOCH`); for reproducible output
+    pub clock: Option<chrono::DateTime<chrono::FixedOffset>>,
     /// `\jobname`
     pub jobname: String,
     /// extension components
     pub extension: ET::Extension,
+    /// resource limits and usage counters
+    pub limits: ResourceGuard,
+    /// progress reports for long compilations
+    pub progress: ProgressReporter,
+    /// if set, all macro expansions are recorded here
+    pub macro_trace: Option<MacroTrace>,
+    /// if set, the branches taken by all conditionals are recorded here
+    pub conditional_trace: Option<ConditionalTrace>,
+    /// if set, all changes of category codes are recorded here
+    pub catcode_trace: Option<CatcodeTrace<<ET::File as File>::SourceRefID>>,
+    /// the closures implementing primitives registered via [`closures`](crate::commands::closures)
+    pub closures: ClosurePrimitives<ET>,
+    /// approximations taken so far, if in strict mode
+    pub approximations: Approximations,
+    /// whether broken internal invariants panic or fail
+    pub hardening: Hardening,
+    /// if set, where every macro was last defined is recorded here
+    pub definitions: Option<DefinitionSites>,
+    /// if set, consulted for every token in the top-level loop
+    pub debugger: Option<Debugger<ET>>,
+}
+
+/// The time given by the `SOURCE_DATE_EPOCH` environment variable (seconds since 1970, as
+/// used for reproducible builds), if set.
+pub fn source_date_epoch() -> Option<chrono::DateTime<chrono::Utc>> {
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    chrono::DateTime::from_timestamp(secs, 0)
+}
+
+/// Sets [`EngineAux::start_time`] to [`EngineAux::clock`], `SOURCE_DATE_EPOCH` (in UTC) or
+/// the current time, in that order, and `\year`, `\month`, `\day` and `\time` accordingly.
+#[allow(clippy::cast_possible_wrap)]
+fn set_start_time<ET: EngineTypes>(comps: &mut EngineReferences<ET>) {
+    let time = comps
+        .aux
+        .clock
+        .or_else(|| source_date_epoch().map(|t| t.fixed_offset()))
+        .unwrap_or_else(|| chrono::Local::now().fixed_offset());
+    comps.aux.start_time = time;
+    comps
+        .state
+        .set_primitive_int(comps.aux, PRIMITIVES.year, time.year().into(), true);
+    comps.state.set_primitive_int(
+        comps.aux,
+        PRIMITIVES.month,
+        (time.month() as i32).into(),
+        true,
+    );
+    comps
+        .state
+        .set_primitive_int(comps.aux, PRIMITIVES.day, (time.day() as i32).into(), true);
+    comps.state.set_primitive_int(
+        comps.aux,
+        PRIMITIVES.time,
+        (((time.hour() * 60) + time.minute()) as i32).into(),
+        true,
+    );
 }
 
 struct Colon<'c, ET: EngineTypes> {
@@ -113,6 +185,10 @@ impl<ET: EngineTypes> EngineReferences<'_, ET> {
         let mut colon = std::mem::take(&mut self.colon);
         let r = colon.out(self, n);
         self.colon = colon;
+        if self.aux.progress.is_active() {
+            let line = self.mouth.file_trace().last().map(|r| r.line);
+            self.aux.progress.page_shipped(line);
+        }
         r
     }
 }
@@ -171,31 +247,7 @@ pub trait TeXEngine: Sized {
     fn init_file(&mut self, s: &str) -> TeXResult<(), Self::Types> {
         log::debug!("Initializing with file {}", s);
         let mut comps = self.get_engine_refs();
-        comps.aux.start_time = chrono::Local::now();
-        comps.state.set_primitive_int(
-            comps.aux,
-            PRIMITIVES.year,
-            comps.aux.start_time.year().into(),
-            true,
-        );
-        comps.state.set_primitive_int(
-            comps.aux,
-            PRIMITIVES.month,
-            (comps.aux.start_time.month() as i32).into(),
-            true,
-        );
-        comps.state.set_primitive_int(
-            comps.aux,
-            PRIMITIVES.day,
-            (comps.aux.start_time.day() as i32).into(),
-            true,
-        );
-        comps.state.set_primitive_int(
-            comps.aux,
-            PRIMITIVES.time,
-            (((comps.aux.start_time.hour() * 60) + comps.aux.start_time.minute()) as i32).into(),
-            true,
-        );
+        set_start_time(&mut comps);
         let file = comps.filesystem.get(s);
         let Some(filename) = file
             .path()
@@ -206,7 +258,9 @@ pub trait TeXEngine: Sized {
         };
         comps.aux.jobname = filename;
         comps.push_file(file);
-        comps.top_loop()
+        comps.top_loop()?;
+        comps.aux.progress.format_loaded(s);
+        Ok(())
     }
 
     /// #### Errors
@@ -219,34 +273,15 @@ pub trait TeXEngine: Sized {
         f: F,
     ) -> TeXResult<(), Self::Types> {
         let mut comps = self.get_engine_refs();
-        comps.aux.start_time = chrono::Local::now();
-        comps.state.set_primitive_int(
-            comps.aux,
-            PRIMITIVES.year,
-            comps.aux.start_time.year().into(),
-            true,
-        );
-        comps.state.set_primitive_int(
-            comps.aux,
-            PRIMITIVES.month,
-            (comps.aux.start_time.month() as i32).into(),
-            true,
-        );
-        comps.state.set_primitive_int(
-            comps.aux,
-            PRIMITIVES.day,
-            (comps.aux.start_time.day() as i32).into(),
-            true,
-        );
-        comps.state.set_primitive_int(
-            comps.aux,
-            PRIMITIVES.time,
-            (((comps.aux.start_time.hour() * 60) + comps.aux.start_time.minute()) as i32).into(),
-            true,
-        );
+        set_start_time(&mut comps);
         comps.push_every(PRIMITIVES.everyjob);
         comps.colon = Colon::new(f);
-        comps.top_loop()
+        let mut ret = comps.top_loop();
+        if ret.is_ok() {
+            ret = comps.aux.approximations.check().map_err(Into::into);
+        }
+        comps.filesystem.close_all_out();
+        ret
     }
 
     /// Compile a `.tex` file. All finished pages are passed to the provided continuation.
@@ -280,16 +315,50 @@ pub trait TeXEngine: Sized {
         }
         self.run(f)
     }
+    /// Fully expands `input` like the body of an `\edef` - without typesetting anything or
+    /// executing unexpandable primitives (assignments, boxes, ...), which are kept as they are -
+    /// and returns the resulting token list as a string. Useful to employ the engine as a plain
+    /// macro expander, e.g. for preprocessing. `input` must have balanced braces.
+    ///
+    /// #### Errors
+    /// On TeX errors during expansion
+    fn expand_to_string(&mut self, input: &str) -> TeXResult<String, Self::Types> {
+        let mut comps = self.get_engine_refs();
+        let end =
+            <Self::Types as EngineTypes>::Token::from_char_cat(b'}'.into(), CommandCode::EndGroup);
+        comps.mouth.requeue(end.clone());
+        comps.mouth.push_string(input.into());
+        let mut ret = Vec::new();
+        if let Err(e) = comps.expand_until_endgroup(false, false, &end, |_, _, t| {
+            ret.push(t);
+            Ok(())
+        }) {
+            comps.mouth.finish();
+            return Err(e);
+        }
+        Ok(tex::tokens::token_lists::TokenListDisplay::from_vec(
+            &ret,
+            comps.aux.memory.cs_interner(),
+            comps.state.get_catcode_scheme(),
+            comps.state.get_escape_char(),
+            false,
+        )
+        .to_string())
+    }
     /// Registers all primitives of plain TeX and sets the default variables.
     fn initialize_tex_primitives(&mut self) {
         super::commands::tex::register_tex_primitives(self);
         let mag = PRIMITIVES.mag;
         let fam = PRIMITIVES.fam;
+        let interactionmode = PRIMITIVES.interactionmode;
         let refs = self.get_engine_refs();
         refs.state
             .set_primitive_int(refs.aux, mag, (1000).into(), true);
         refs.state
             .set_primitive_int(refs.aux, fam, (-1).into(), true);
+        // we never stop to prompt the user, so `\nonstopmode`
+        refs.state
+            .set_primitive_int(refs.aux, interactionmode, (1).into(), true);
     }
 
     /// Initialize the engine by processing `plain.tex`.
@@ -321,6 +390,21 @@ pub trait TeXEngine: Sized {
     fn load_latex(&mut self) -> TeXResult<(), Self::Types> {
         self.init_file("latex.ltx")
     }
+
+    /// Registers a custom conditional `\name`. It behaves like the builtin ones, i.e. it can be
+    /// prefixed with `\unless`, is skipped correctly in false branches and shows up in
+    /// `\tracingifs` and `\currentiftype`. `cond` is called with the token that triggered
+    /// the conditional and can read arguments from the engine as usual.
+    fn register_conditional(
+        &mut self,
+        name: &'static str,
+        cond: fn(
+            &mut EngineReferences<Self::Types>,
+            <Self::Types as EngineTypes>::Token,
+        ) -> TeXResult<bool, Self::Types>,
+    ) {
+        crate::commands::primitives::register_conditional(self, name, cond);
+    }
 }
 
 /// Default implementation of a [`TeXEngine`] for the provided [`EngineTypes`].
@@ -339,10 +423,21 @@ impl<ET: EngineTypes> Default for DefaultEngine<ET> {
         let mut aux = EngineAux {
             outputs: ET::Outputs::new(),
             error_handler: ET::ErrorHandler::new(),
-            start_time: chrono::Local::now(),
+            start_time: chrono::Local::now().fixed_offset(),
+            clock: None,
             extension: ET::Extension::new(&mut memory),
             memory,
             jobname: String::new(),
+            limits: ResourceGuard::default(),
+            progress: ProgressReporter::default(),
+            macro_trace: None,
+            conditional_trace: None,
+            catcode_trace: None,
+            closures: ClosurePrimitives::default(),
+            approximations: Approximations::default(),
+            hardening: Hardening::default(),
+            definitions: None,
+            debugger: None,
         };
         let fontsystem = ET::FontSystem::new(&mut aux);
         let mut state = ET::State::new(fontsystem.null(), &mut aux);
@@ -407,12 +502,24 @@ impl<ET: EngineTypes> EngineReferences<'_, ET> {
                 let _ = self.get_next(false);
                 continue
             }
+            self.aux.hardening.check()?;
+            if self.stomach.data_mut().orphaned_math_list() {
+                return Err(self
+                    .aux
+                    .hardening
+                    .violation("math list outside of math mode")
+                    .into());
+            }
+            if self.aux.debugger.is_some() {
+                self.debug_token(&token)?;
+            }
         }; self,
             ResolvedToken::Tk { char, code } => ET::Stomach::do_char(self, token, char, code)?,
             ResolvedToken::Cmd(Some(TeXCommand::Char {char, code})) => ET::Stomach::do_char(self, token, *char, *code)?,
             ResolvedToken::Cmd(None) => TeXError::undefined(self.aux,self.state,self.mouth,&token)?,
             ResolvedToken::Cmd(Some(cmd)) => crate::do_cmd!(self,token,cmd)
         );
+        self.aux.hardening.check()?;
         Ok(())
     }
 }
@@ -34,6 +34,12 @@ pub trait FileSystem: Clone {
     fn close_in(&mut self, idx: u8);
     /// Closes the file with the given index (`\closeout`).
     fn close_out(&mut self, idx: u8);
+    /// Closes all files still open for writing; called at the end of a job, like TeX does.
+    fn close_all_out(&mut self) {
+        for idx in 0..=u8::MAX {
+            self.close_out(idx);
+        }
+    }
     /// Ehether the file with the given index is at its end (`\ifeof`).
     fn eof(&self, idx: u8) -> bool;
     /// Writes the given string to the file with the given index (`\write`).
@@ -103,6 +109,7 @@ pub struct NoOutputFileSystem<C: Character> {
     pub envs: HMap<String, String>,
     write_files: Vec<Option<WritableVirtualFile<C>>>,
     read_files: Vec<Option<InputTokenizer<C, VirtualFileLineSource<C>>>>,
+    written: Vec<PathBuf>,
     pub interner: string_interner::StringInterner<
         string_interner::backend::StringBackend<string_interner::symbol::SymbolU32>,
         rustc_hash::FxBuildHasher,
@@ -116,6 +123,7 @@ impl<C: Character> Clone for NoOutputFileSystem<C> {
             write_files: self.write_files.clone(),
             envs: self.envs.clone(),
             read_files: Vec::new(),
+            written: self.written.clone(),
             interner: self.interner.clone(),
         }
     }
@@ -137,6 +145,10 @@ impl<C: Character> NoOutputFileSystem<C> {
         };
         self.files.insert(f.path.clone(), f.clone());
     }
+    /// The files written (via `\openout`) and closed so far, in the order they were first opened.
+    pub fn written_files(&self) -> impl Iterator<Item = &VirtualFile<C>> {
+        self.written.iter().filter_map(|p| self.files.get(p))
+    }
 }
 impl<C: Character> FileSystem for NoOutputFileSystem<C> {
     type File = VirtualFile<C>;
@@ -151,6 +163,7 @@ impl<C: Character> FileSystem for NoOutputFileSystem<C> {
             files: HMap::default(),
             write_files: Vec::new(),
             read_files: Vec::new(),
+            written: Vec::new(),
             interner: string_interner::StringInterner::new(),
         }
     }
@@ -262,22 +275,26 @@ impl<C: Character> FileSystem for NoOutputFileSystem<C> {
                     Err(e) => Err(e.into()),
                 }
             }
-            _ => Err(TeXError::EmergencyStop),
+            _ => Err(TeXError::General(
+                "*** (cannot \\read from terminal in nonstop modes)".to_string(),
+            )),
         }
     }
     fn readline<ET: EngineTypes<Char = <Self::File as File>::Char>, F: FnMut(ET::Token)>(
         &mut self,
         idx: u8,
-        _state: &ET::State,
+        state: &ET::State,
         cont: F,
     ) -> TeXResult<(), ET> {
         match self.read_files.get_mut(idx as usize) {
             Some(Some(f)) => {
                 //debug_log!(debug => "readline: {}",f.source.path.display());
-                f.readline(cont);
+                f.readline(state.get_endline_char(), cont);
                 Ok(())
             }
-            _ => Err(TeXError::EmergencyStop),
+            _ => Err(TeXError::General(
+                "*** (cannot \\readline from terminal in nonstop modes)".to_string(),
+            )),
         }
     }
     fn eof(&self, idx: u8) -> bool {
@@ -293,6 +310,8 @@ impl<C: Character> FileSystem for NoOutputFileSystem<C> {
         }
     }
     fn open_out(&mut self, idx: u8, file: Self::File) {
+        // reopening a stream closes (and thus keeps) the previous file first
+        self.close_out(idx);
         if idx as usize >= self.write_files.len() {
             self.write_files.resize((idx + 1) as usize, None);
         }
@@ -308,10 +327,18 @@ impl<C: Character> FileSystem for NoOutputFileSystem<C> {
                     id: Some(f.2),
                     pipe: false,
                 };
+                if !self.written.contains(&vf.path) {
+                    self.written.push(vf.path.clone());
+                }
                 self.files.insert(vf.path.clone(), vf);
             }
         }
     }
+    fn close_all_out(&mut self) {
+        for idx in 0..self.write_files.len() {
+            self.close_out(idx as u8);
+        }
+    }
     fn write<ET: EngineTypes, D: std::fmt::Display>(
         &mut self,
         idx: i64,
@@ -11,7 +11,7 @@ use crate::tex::numerics::{Numeric, TeXDimen, TeXInt};
 use crate::tex::tokens::control_sequences::{CSHandler, CSName};
 use crate::utils::errors::{TeXError, TeXResult};
 use crate::utils::{HMap, Ptr};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::RwLock;
 
 /// A font system provides [`Font`]s, which in turn provide various information about [`Character`]s (or, rather, glyphs)
@@ -100,6 +100,19 @@ pub trait Font: Clone + std::fmt::Debug {
     /// Returns the ligature of the given [`Character`]s in this font, if any; e.g. most fonts
     /// combine `-` and `-` into an endash.
     fn ligature(&self, c1: Self::Char, c2: Self::Char) -> Option<Self::Char>;
+    /// Returns the implicit kern between the given [`Character`]s in this font, if any; e.g.
+    /// most fonts move `A` and `V` closer together.
+    fn kern(&self, c1: Self::Char, c2: Self::Char) -> Option<Self::Dim>;
+}
+
+/// A `.tfm`-file that could not be found, and the font loaded in its place.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TfmSubstitution {
+    /// the file name as given to `\font`
+    pub requested: String,
+    /// the [fallback](TfmFontSystem::fallback), or `None` if it could not be found either
+    /// and a font without any characters was used instead
+    pub substitute: Option<String>,
 }
 
 /// A font system for `.tfm`-files, as used by plain TeX, eTeX and pdfTeX for [`Character`]`=u8`
@@ -107,6 +120,57 @@ pub trait Font: Clone + std::fmt::Debug {
 pub struct TfmFontSystem<I: TeXInt, D: TeXDimen + Numeric<I>, CS: CSName<u8>> {
     files: HMap<PathBuf, Ptr<TfmFile>>,
     null: Ptr<TfmFontI<I, D, CS>>,
+    /// the font (e.g. `cmr10`) whose metrics are used for fonts whose `.tfm`-file can not be
+    /// found
+    pub fallback: Option<String>,
+    substitutions: Vec<TfmSubstitution>,
+}
+
+impl<I: TeXInt, D: TeXDimen + Numeric<I>, CS: CSName<u8>> TfmFontSystem<I, D, CS> {
+    /// The number of distinct `.tfm`-files loaded so far.
+    pub fn loaded_files(&self) -> usize {
+        self.files.len()
+    }
+    /// The `.tfm`-files that could not be found since the last call, in order.
+    pub fn take_substitutions(&mut self) -> Vec<TfmSubstitution> {
+        std::mem::take(&mut self.substitutions)
+    }
+    fn tfm_file<F: FileSystem>(path: &str, fs: &mut F) -> F::File {
+        if path.ends_with(".tfm") {
+            fs.get(path)
+        } else {
+            fs.get(format!("{path}.tfm"))
+        }
+    }
+    fn load(&mut self, path: &Path) -> Ptr<TfmFile> {
+        match self.files.get(path) {
+            Some(ff) => ff.clone(),
+            None => {
+                let ff = Ptr::new(TfmFile::new(path.to_path_buf()));
+                self.files.insert(path.to_path_buf(), ff.clone());
+                ff
+            }
+        }
+    }
+    /// The metrics to use for the missing `.tfm`-file `path`.
+    fn substitute<F: FileSystem>(&mut self, path: &str, fs: &mut F) -> Ptr<TfmFile> {
+        let fallback = self
+            .fallback
+            .as_deref()
+            .map(|name| (name.to_string(), Self::tfm_file(name, fs)))
+            .filter(|(_, f)| f.exists());
+        let substitution = TfmSubstitution {
+            requested: path.to_string(),
+            substitute: fallback.as_ref().map(|(name, _)| name.clone()),
+        };
+        if !self.substitutions.contains(&substitution) {
+            self.substitutions.push(substitution);
+        }
+        match fallback {
+            Some((_, f)) => self.load(f.path()),
+            None => Ptr::new(TfmFile::empty(PathBuf::from(path))),
+        }
+    }
 }
 
 impl<I: TeXInt, D: TeXDimen + Numeric<I>, CS: CSName<u8>> FontSystem for TfmFontSystem<I, D, CS> {
@@ -117,20 +181,7 @@ impl<I: TeXInt, D: TeXDimen + Numeric<I>, CS: CSName<u8>> FontSystem for TfmFont
     type CS = CS;
 
     fn new<ET: EngineTypes<Char = Self::Char, CSName = Self::CS>>(aux: &mut EngineAux<ET>) -> Self {
-        let null_file = TfmFile {
-            hyphenchar: 45,
-            skewchar: 255,
-            dimen: vec![],
-            size: 0,
-            //typestr:"nullfont".to_string(),
-            widths: [0.0; 256],
-            defined: [false; 256],
-            heights: [0.0; 256],
-            depths: [0.0; 256],
-            ics: [0.0; 256],
-            ligs: HMap::default(),
-            filepath: std::path::PathBuf::from("/nullfont"),
-        };
+        let null_file = TfmFile::empty(PathBuf::from("/nullfont"));
         let muts = Mutables::default();
         let null = Ptr::new(TfmFontI {
             file: Ptr::new(null_file),
@@ -140,6 +191,8 @@ impl<I: TeXInt, D: TeXDimen + Numeric<I>, CS: CSName<u8>> FontSystem for TfmFont
         TfmFontSystem {
             files: HMap::default(),
             null,
+            fallback: None,
+            substitutions: Vec::new(),
         }
     }
 
@@ -150,18 +203,11 @@ impl<I: TeXInt, D: TeXDimen + Numeric<I>, CS: CSName<u8>> FontSystem for TfmFont
         fs: &mut F,
     ) -> Self::Font {
         let path = path.as_ref();
-        let f = if path.ends_with(".tfm") {
-            fs.get(path)
+        let f = Self::tfm_file(path, fs);
+        let ff = if f.exists() {
+            self.load(f.path())
         } else {
-            fs.get(format!("{path}.tfm"))
-        };
-        let ff = match self.files.get(f.path()) {
-            Some(ff) => ff.clone(),
-            None => {
-                let ff = Ptr::new(TfmFile::new(f.path().to_path_buf()));
-                self.files.insert(f.path().to_path_buf(), ff.clone());
-                ff
-            }
+            self.substitute(path, fs)
         };
         let muts = Mutables::default();
         let font = TfmFontI {
@@ -320,6 +366,13 @@ impl<I: TeXInt, D: TeXDimen + Numeric<I>, CS: CSName<u8>> Font for TfmFont<I, D,
     fn ligature(&self, char1: Self::Char, char2: Self::Char) -> Option<Self::Char> {
         self.file.ligs.get(&(char1, char2)).copied()
     }
+
+    fn kern(&self, char1: Self::Char, char2: Self::Char) -> Option<Self::Dim> {
+        self.file
+            .kerns
+            .get(&(char1, char2))
+            .map(|k| self.get_at().scale_float(*k as f64))
+    }
 }
 
 impl<ET: EngineTypes> EngineReferences<'_, ET> {
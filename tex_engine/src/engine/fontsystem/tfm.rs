@@ -27,6 +27,8 @@ pub struct TfmFile {
     pub ics: [f32; 256],
     /// The ligatures of the font.
     pub ligs: HMap<(u8, u8), u8>,
+    /// The implicit kerns of the font (as (originally) fixed-point numbers); to be scaled by `size`.
+    pub kerns: HMap<(u8, u8), f32>,
     /// The path to the `.tfm`-file.
     pub filepath: PathBuf,
 }
@@ -41,6 +43,23 @@ impl TfmFile {
     pub fn name(&self) -> &str {
         self.filepath.file_stem().unwrap().to_str().unwrap()
     }
+    /// A font without any characters, e.g. `\nullfont`.
+    pub fn empty(filepath: PathBuf) -> TfmFile {
+        TfmFile {
+            hyphenchar: 45,
+            skewchar: 255,
+            dimen: vec![],
+            size: 0,
+            widths: [0.0; 256],
+            defined: [false; 256],
+            heights: [0.0; 256],
+            depths: [0.0; 256],
+            ics: [0.0; 256],
+            ligs: HMap::default(),
+            kerns: HMap::default(),
+            filepath,
+        }
+    }
     pub fn new(pb: PathBuf) -> TfmFile {
         let filepath = pb; // ec-lmr10: 102+41 = 5???
         let mut state = FontState::new(&filepath);
@@ -57,6 +76,7 @@ impl TfmFile {
         let mut ics: [f32; 256] = [0.0; 256];
         let mut defined = [false; 256];
         let mut ligs: HMap<(u8, u8), u8> = HMap::default();
+        let mut kerns: HMap<(u8, u8), f32> = HMap::default();
 
         let (lf, lh) = state.read_int();
         let (bc, ec) = state.read_int();
@@ -115,7 +135,8 @@ impl TfmFile {
             let (a, b, c, d) = state.pop();
             lig_kerns.push((a, b, c, d));
         }
-        state.skip(nk + ne);
+        let kernls: Vec<f32> = (0..nk).map(|_| state.read_float()).collect();
+        state.skip(ne);
         assert_eq!(
             state.i,
             lh + 6 + (ec - bc + 1) + nw + nh + nd + ni + nl + nk + ne
@@ -163,7 +184,7 @@ impl TfmFile {
                     ics[t.char as usize] = factor * f;
                 }
             }
-            t.ligature(&lig_kerns, &mut ligs)
+            t.lig_kern(&lig_kerns, &kernls, factor, &mut ligs, &mut kerns)
         }
         assert_eq!(state.i, lf);
 
@@ -177,6 +198,7 @@ impl TfmFile {
             depths,
             ics,
             ligs,
+            kerns,
             filepath,
             defined,
         }
@@ -270,7 +292,16 @@ struct FInfoEntry {
     remainder: u8,
 }
 impl FInfoEntry {
-    pub fn ligature(&self, ligs: &[(u8, u8, u8, u8)], map: &mut HMap<(u8, u8), u8>) {
+    /// Runs the lig/kern program of this character; only the first instruction for a following
+    /// character counts.
+    pub fn lig_kern(
+        &self,
+        ligs: &[(u8, u8, u8, u8)],
+        kernls: &[f32],
+        factor: f32,
+        map: &mut HMap<(u8, u8), u8>,
+        kerns: &mut HMap<(u8, u8), f32>,
+    ) {
         if self.tag_field == 1 {
             let mut i = self.remainder as usize;
             match ligs.get(i).copied() {
@@ -290,6 +321,11 @@ impl FInfoEntry {
                     loop {
                         if op < 128 {
                             map.insert((self.char, next), rem);
+                        } else if !map.contains_key(&(self.char, next)) {
+                            let idx = 256 * (op as usize - 128) + (rem as usize);
+                            if let Some(k) = kernls.get(idx) {
+                                kerns.entry((self.char, next)).or_insert(factor * k);
+                            }
                         }
                         if skip >= 128 {
                             return;
@@ -6,10 +6,12 @@ pub mod methods;
 
 use crate::commands::primitives::{PrimitiveIdentifier, PRIMITIVES};
 use crate::commands::{ActiveConditional, CharOrPrimitive, Macro, ResolvedToken, TeXCommand};
+use crate::engine::filesystem::FileSystem;
 use crate::engine::gullet::hvalign::AlignData;
 use crate::engine::mouth::Mouth;
 use crate::engine::state::State;
 use crate::engine::utils::outputs::Outputs;
+use crate::engine::utils::trace::{Branch, ConditionalEvent, MacroEvent};
 use crate::engine::{EngineAux, EngineReferences, EngineTypes};
 use crate::tex::catcodes::CommandCode;
 use crate::tex::characters::Character;
@@ -388,7 +390,7 @@ pub trait Gullet<ET: EngineTypes> {
         engine
             .gullet
             .get_conditionals()
-            .push(ActiveConditional::Unfinished(name));
+            .push(ActiveConditional::Unfinished(name, unless));
         if trace {
             //crate::debug_log!(error => "Here: {}",engine.preview());
             engine.aux.outputs.write_neg1(format_args!(
@@ -399,18 +401,28 @@ pub trait Gullet<ET: EngineTypes> {
             ));
             //engine.aux.outputs.write_neg1(format_args!("Here: {}",engine.preview()));
         }
+        let start = engine
+            .aux
+            .conditional_trace
+            .as_ref()
+            .filter(|t| t.wants())
+            .map(|_| engine.mouth.current_sourceref());
         let mut ret = f(engine, token.clone())?;
         if unless {
             ret = !ret
         }
+        let case = match engine.gullet.get_conditionals().get(index) {
+            Some(ActiveConditional::Case(n)) if start.is_some() => Some((*n).into()),
+            _ => None,
+        };
         if ret {
             if trace {
                 engine.aux.outputs.write_neg1("{true}");
             }
             if name != PRIMITIVES.ifcase {
                 match engine.gullet.get_conditionals().get_mut(index) {
-                    Some(u @ ActiveConditional::Unfinished(_)) => {
-                        *u = ActiveConditional::True(name)
+                    Some(u @ ActiveConditional::Unfinished(..)) => {
+                        *u = ActiveConditional::True(name, unless)
                     }
                     _ => unreachable!(),
                 }
@@ -444,6 +456,30 @@ pub trait Gullet<ET: EngineTypes> {
                 }
             }
         }
+        if let Some(start) = start {
+            let branch = match case {
+                Some(n) => Branch::Case(n),
+                None if ret => Branch::True,
+                None if engine.gullet.get_conditionals().len() > index => Branch::Else,
+                None => Branch::Skipped,
+            };
+            let esc = engine.state.get_escape_char();
+            let event = ConditionalEvent {
+                name: if unless {
+                    format!("{}unless {}", ET::Char::display_opt(esc), name.display(esc))
+                } else {
+                    name.display(esc).to_string()
+                },
+                branch,
+                file: engine.filesystem.ref_str(start.file).to_string(),
+                line: start.line,
+                column: start.column,
+                level: index + 1,
+            };
+            if let Some(trace) = engine.aux.conditional_trace.as_mut() {
+                trace.record(event);
+            }
+        }
         Ok(())
     }
 
@@ -493,6 +529,13 @@ impl<ET: EngineTypes> Gullet<ET> for DefaultGullet<ET> {
         m: Macro<ET::Token>,
         token: ET::Token,
     ) -> TeXResult<(), ET> {
+        engine.aux.limits.expansion()?;
+        engine.aux.hardening.check()?;
+        let event = if engine.aux.macro_trace.is_some() {
+            macro_event(engine, &m, &token)
+        } else {
+            None
+        };
         let trace = engine.state.get_primitive_int(PRIMITIVES.tracingcommands) > ET::Int::default();
         if trace {
             match token.to_enum() {
@@ -540,11 +583,17 @@ impl<ET: EngineTypes> Gullet<ET> for DefaultGullet<ET> {
             */
         }
         if m.signature.params.is_empty() {
+            if engine.aux.macro_trace.is_some() {
+                record_macro_event(engine, event, &[]);
+            }
             engine.mouth.push_exp(&m.expansion);
             return Ok(());
         }
         let mut args = engine.mouth.get_args();
         methods::read_arguments(engine, &mut args, m.signature.params, m.long, &token)?;
+        if engine.aux.macro_trace.is_some() {
+            record_macro_event(engine, event, &args[..m.signature.arity as usize]);
+        }
         if trace {
             for i in 0..m.signature.arity {
                 engine.aux.outputs.write_neg1(format_args!(
@@ -581,6 +630,83 @@ impl<ET: EngineTypes> Gullet<ET> for DefaultGullet<ET> {
     }
 }
 
+/// Starts a [`MacroEvent`] for the expansion of `m`, if it is to be recorded.
+fn macro_event<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    m: &Macro<ET::Token>,
+    token: &ET::Token,
+) -> Option<MacroEvent> {
+    let trace = engine.aux.macro_trace.as_mut()?;
+    let depth = trace.enter(engine.mouth.pending_tokens());
+    let (bare, name) = match token.to_enum() {
+        StandardToken::ControlSequence(cs) => {
+            let bare = engine.aux.memory.cs_interner().resolve(&cs).to_string();
+            let name = format!(
+                "{}{}",
+                ET::Char::display_opt(engine.state.get_escape_char()),
+                bare
+            );
+            (bare, name)
+        }
+        StandardToken::Character(c, _) => (c.display().to_string(), c.display().to_string()),
+        StandardToken::Primitive(p) => {
+            let name = p.display(engine.state.get_escape_char()).to_string();
+            (name.trim_start_matches('\\').to_string(), name)
+        }
+    };
+    if !trace.wants(&bare) {
+        return None;
+    }
+    let sr = engine.mouth.current_sourceref();
+    Some(MacroEvent {
+        name,
+        arguments: Vec::new(),
+        expansion: m
+            .expansion
+            .display(
+                engine.aux.memory.cs_interner(),
+                engine.state.get_catcode_scheme(),
+                engine.state.get_escape_char(),
+                false,
+            )
+            .to_string(),
+        file: engine.filesystem.ref_str(sr.file).to_string(),
+        line: sr.line,
+        column: sr.column,
+        depth,
+    })
+}
+
+/// Completes and records `event` (if any) once the arguments have been read, and marks the
+/// begin of the expansion.
+fn record_macro_event<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    event: Option<MacroEvent>,
+    args: &[Vec<ET::Token>],
+) {
+    let pending = engine.mouth.pending_tokens();
+    let Some(trace) = engine.aux.macro_trace.as_mut() else {
+        return;
+    };
+    if let Some(mut event) = event {
+        event.arguments = args
+            .iter()
+            .map(|a| {
+                crate::tex::tokens::token_lists::TokenListDisplay::from_vec(
+                    a,
+                    engine.aux.memory.cs_interner(),
+                    engine.state.get_catcode_scheme(),
+                    engine.state.get_escape_char(),
+                    false,
+                )
+                .to_string()
+            })
+            .collect();
+        trace.record(event);
+    }
+    trace.open(pending);
+}
+
 impl<ET: EngineTypes> EngineReferences<'_, ET> {
     /// Yields [`Token`]s from the input stream until and passes them on to `cont` until `cont` returns `false`.
     #[inline]
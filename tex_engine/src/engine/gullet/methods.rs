@@ -338,7 +338,7 @@ pub(crate) fn case_loop<ET: EngineTypes>(
                 Some(CharOrPrimitive::Primitive(name))
                     if name == PRIMITIVES.r#else && incond == 0 =>
                 {
-                    conds.push(ActiveConditional::Else(PRIMITIVES.ifcase));
+                    conds.push(ActiveConditional::Else(PRIMITIVES.ifcase, false));
                     Ok(Some(()))
                 }
                 Some(CharOrPrimitive::Primitive(name)) if name == PRIMITIVES.fi => {
@@ -386,13 +386,14 @@ pub fn false_loop<ET: EngineTypes>(
     allowelse: bool,
     mut skipelse: bool,
 ) -> TeXResult<(), ET> {
-    let (mut incond, cond) = {
+    let (mut incond, cond, unless) = {
         let conds = engine.gullet.get_conditionals();
         let ic = conds.len() - idx;
         for _ in 0..ic {
             conds.pop();
         }
-        (ic, conds.pop().unwrap().name())
+        let cond = conds.pop().unwrap();
+        (ic, cond.name(), cond.unless())
     };
     let mut conds = std::mem::take(engine.gullet.get_conditionals());
     engine.iterate(
@@ -405,7 +406,7 @@ pub fn false_loop<ET: EngineTypes>(
                     if name == PRIMITIVES.r#else && incond == 0 =>
                 {
                     if allowelse {
-                        conds.push(ActiveConditional::Else(cond));
+                        conds.push(ActiveConditional::Else(cond, unless));
                         Ok(Some(()))
                     } else if skipelse {
                         skipelse = false;
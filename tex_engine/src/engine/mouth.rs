@@ -164,6 +164,13 @@ pub trait Mouth<ET: EngineTypes> {
     fn file_trace(
         &self,
     ) -> impl Iterator<Item = SourceReference<<ET::File as File>::SourceRefID>> + '_;
+
+    /// The number of [`Token`]s pushed to the [`Mouth`] (expansions, token lists, requeued tokens)
+    /// that have not been read yet; unread parts of files and strings do not count. Used to
+    /// reconstruct expansion depths for [`MacroTrace`](crate::engine::utils::trace::MacroTrace)s.
+    fn pending_tokens(&self) -> usize {
+        0
+    }
 }
 
 enum TokenSource<T: Token, F: File<Char = T::Char>> {
@@ -205,6 +212,16 @@ impl<ET: EngineTypes> Mouth<ET> for DefaultMouth<ET> {
         self.start_ref.clear();
     }
 
+    fn pending_tokens(&self) -> usize {
+        self.inputs
+            .iter()
+            .map(|s| match s {
+                TokenSource::Vec(v) => v.len(),
+                _ => 0,
+            })
+            .sum()
+    }
+
     fn file_trace(
         &self,
     ) -> impl Iterator<Item = SourceReference<<ET::File as File>::SourceRefID>> + '_ {
@@ -282,27 +299,47 @@ impl<ET: EngineTypes> Mouth<ET> for DefaultMouth<ET> {
         state: &ET::State,
     ) -> Result<(), InvalidCharacter<ET::Char>> {
         for (i, s) in self.inputs.iter().enumerate().rev() {
-            if let TokenSource::File(f, _) = s {
-                aux.outputs.file_close(f.source.path().display());
-                let TokenSource::File(mut r, _) = self.inputs.remove(i) else {
-                    unreachable!()
-                };
-                self.start_ref.pop();
-                if r.state != MouthState::NewLine {
-                    let mut ret = Vec::new();
-                    match r.read(
+            let mut ret = Vec::new();
+            // the pseudo file of a \scantokens is ended just like a real one
+            let res = match s {
+                TokenSource::File(f, _) => {
+                    aux.outputs.file_close(f.source.path().display());
+                    let TokenSource::File(mut r, _) = self.inputs.remove(i) else {
+                        unreachable!()
+                    };
+                    self.start_ref.pop();
+                    if r.state == MouthState::NewLine {
+                        return Ok(());
+                    }
+                    r.read(
                         aux.memory.cs_interner_mut(),
                         state.get_catcode_scheme(),
                         state.get_endline_char(),
                         |t| ret.push(t),
-                    ) {
-                        Ok(_) => (),
-                        Err(e) => e.recover(aux, state, self)?,
+                    )
+                }
+                TokenSource::String(_) => {
+                    let TokenSource::String(mut r) = self.inputs.remove(i) else {
+                        unreachable!()
+                    };
+                    if r.state == MouthState::NewLine {
+                        return Ok(());
                     }
-                    self.with_list(|ls| ls.extend(ret.into_iter().rev()));
+                    r.read(
+                        aux.memory.cs_interner_mut(),
+                        state.get_catcode_scheme(),
+                        state.get_endline_char(),
+                        |t| ret.push(t),
+                    )
                 }
-                return Ok(());
+                TokenSource::Vec(_) => continue,
+            };
+            match res {
+                Ok(_) => (),
+                Err(e) => e.recover(aux, state, self)?,
             }
+            self.with_list(|ls| ls.extend(ret.into_iter().rev()));
+            return Ok(());
         }
         Ok(())
     }
@@ -601,8 +638,35 @@ impl<ET: EngineTypes> DefaultMouth<ET> {
 impl<ET: EngineTypes> EngineReferences<'_, ET> {
     /// Push a file to the [`Mouth`] (see [`Mouth::push_file`]).
     pub fn push_file(&mut self, f: ET::File) {
+        if self.aux.progress.is_active() {
+            let depth = self.mouth.file_trace().count();
+            self.aux.progress.file_opened(f.path(), depth);
+        }
         self.mouth.push_file(f);
     }
+    /// Push a string to the [`Mouth`] as a pseudo file, exactly like `\scantokens` does: it is
+    /// tokenized lazily under the category codes in effect when its characters are read, and
+    /// `\everyeof` is inserted at its end.
+    pub fn push_pseudo_file(&mut self, s: &str) {
+        self.mouth.push_string(s.into());
+    }
+    /// Tokenize a string under the current category codes and `\endlinechar` (as if it were read
+    /// from a file) and return the [`Token`]s, without inserting them into the [`Mouth`].
+    /// Category code changes within the string do not take effect until afterwards.
+    /// #### Errors
+    /// if the string contains an invalid character
+    pub fn tokenize_str(&mut self, s: &str) -> TeXResult<Vec<ET::Token>, ET> {
+        let mut tokenizer = InputTokenizer::new(StringLineSource::from(s));
+        let mut ret = Vec::new();
+        while let Some(t) = tokenizer.get_next(
+            self.aux.memory.cs_interner_mut(),
+            self.state.get_catcode_scheme(),
+            self.state.get_endline_char(),
+        )? {
+            ret.push(t);
+        }
+        Ok(ret)
+    }
     /// Insert the value of a primitive token list (e.g. `\everypar`) into the [`Mouth`].
     pub fn push_every(&mut self, every: PrimitiveIdentifier) {
         let tks = self.state.get_primitive_tokens(every);
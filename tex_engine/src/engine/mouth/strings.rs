@@ -108,16 +108,17 @@ impl<C: Character, S: TextLineSource<C>> InputTokenizer<C, S> {
     }
 
     /// `\readline` - read a line of input as [`Character`]s of [`CategoryCode::Other`] (except for ` `, which has
-    /// [`Space`](CategoryCode::Space)) and passing each token to the given function.
-    pub fn readline<T: Token<Char = C>, F: FnMut(T)>(&mut self, mut f: F) {
-        while self.col < self.current_line.len() {
-            let next = self.current_line[self.col];
-            self.col += 1;
+    /// [`Space`](CategoryCode::Space)) and passing each token to the given function. As in e-TeX, the
+    /// end-of-line character (if any) is part of the line.
+    pub fn readline<T: Token<Char = C>, F: FnMut(T)>(&mut self, endline: Option<C>, mut f: F) {
+        let chars = self.current_line[self.col..].iter().copied();
+        for next in chars.chain(endline) {
             match next.try_into() {
                 Ok(b' ') => f(T::space()),
                 _ => f(T::from_char_cat(next, CommandCode::Other)),
             }
         }
+        self.col = self.current_line.len();
         self.next_line();
     }
 
@@ -1,5 +1,6 @@
 /*! The [`State`] of a TeX engine keeps track of scoped (by TeX groups)
 values. */
+pub mod snapshot;
 pub mod tex_state;
 
 use crate::commands::primitives::{PrimitiveCommands, PrimitiveIdentifier, PRIMITIVES};
@@ -7,9 +8,10 @@ use crate::commands::{PrimitiveCommand, TeXCommand};
 use crate::engine::gullet::methods::CSOrActiveChar;
 use crate::engine::{EngineAux, EngineReferences, EngineTypes};
 use crate::tex::catcodes::{CategoryCode, CategoryCodeScheme};
+use crate::tex::characters::Character;
 use crate::tex::nodes::boxes::TeXBox;
 use crate::tex::numerics::{MuSkip, Skip};
-use crate::tex::tokens::control_sequences::CSName;
+use crate::tex::tokens::control_sequences::{CSHandler, CSName};
 use crate::tex::tokens::token_lists::TokenList;
 use std::fmt::Formatter;
 
@@ -171,6 +173,18 @@ pub trait State<ET: EngineTypes>: Sized + Clone {
     fn get_catcode_scheme(&self) -> &CategoryCodeScheme<ET::Char>;
     /// Set the current [`CategoryCode`] for a character
     fn set_catcode(&mut self, aux: &EngineAux<ET>, c: ET::Char, cc: CategoryCode, globally: bool);
+    /// Get the index of the currently active catcode table (`\catcodetable`)
+    fn get_catcode_table(&self) -> usize;
+    /// Switch to the catcode table with the given index (`\catcodetable`); catcode
+    /// assignments afterwards modify that table. Returns `false` if the table is undefined.
+    fn set_catcode_table(&mut self, aux: &EngineAux<ET>, idx: usize, globally: bool) -> bool;
+    /// Store the current [`CategoryCodeScheme`] in the catcode table with the given index
+    /// (`\savecatcodetable`). Catcode tables are always global, and their indices at most
+    /// [`MAX_CATCODE_TABLE`](crate::commands::luatex::MAX_CATCODE_TABLE).
+    fn save_catcode_table(&mut self, idx: usize);
+    /// Initialize the catcode table with the given index with the iniTeX [`CategoryCodeScheme`]
+    /// (`\initcatcodetable`)
+    fn init_catcode_table(&mut self, idx: usize);
     /// Get the current space factor code `\sfcode` for a character
     fn get_sfcode(&self, c: ET::Char) -> u16;
     /// Set the current space factor code `\sfcode` for a character
@@ -191,6 +205,23 @@ pub trait State<ET: EngineTypes>: Sized + Clone {
     fn get_mathcode(&self, c: ET::Char) -> u32;
     /// Set the current math code `\mathcode` for a character
     fn set_mathcode(&mut self, aux: &EngineAux<ET>, c: ET::Char, mathcode: u32, globally: bool);
+    /// Get the current inter-character class `\XeTeXcharclass` for a character
+    fn get_charclass(&self, c: ET::Char) -> u16;
+    /// Set the current inter-character class `\XeTeXcharclass` for a character
+    fn set_charclass(&mut self, aux: &EngineAux<ET>, c: ET::Char, class: u16, globally: bool);
+    /// Get the tokens to be inserted between characters of the given classes
+    /// (`\XeTeXinterchartoks`)
+    fn get_interchar_toks(&self, from: u16, to: u16) -> &TokenList<ET::Token>;
+    /// Set the tokens to be inserted between characters of the given classes
+    /// (`\XeTeXinterchartoks`)
+    fn set_interchar_toks(
+        &mut self,
+        aux: &EngineAux<ET>,
+        from: u16,
+        to: u16,
+        v: TokenList<ET::Token>,
+        globally: bool,
+    );
     /// Get the current endline character
     fn get_endline_char(&self) -> Option<ET::Char>;
     /// Set the current endline character
@@ -326,6 +357,9 @@ pub trait State<ET: EngineTypes>: Sized + Clone {
         cmd: Option<TeXCommand<ET>>,
         globally: bool,
     );
+    /// Iterate over all control sequence names that currently have a definition, in no particular
+    /// order
+    fn iter_commands(&self) -> impl Iterator<Item = (ET::CSName, &TeXCommand<ET>)>;
     /// Get the current definition for the active character
     fn get_ac_command(&self, c: ET::Char) -> Option<&TeXCommand<ET>>;
     /// Set the current definition for the active character
@@ -374,9 +408,14 @@ pub trait StateChangeTracker<ET: EngineTypes>: State<ET> {
 #[derive(Clone, Debug)]
 pub enum StateChange<ET: EngineTypes> {
     Catcode {
+        /// the catcode table that was active when the catcode was changed
+        table: usize,
         char: ET::Char,
         old: CategoryCode,
     },
+    CatcodeTable {
+        old: usize,
+    },
     SfCode {
         char: ET::Char,
         old: u16,
@@ -397,6 +436,14 @@ pub enum StateChange<ET: EngineTypes> {
         char: ET::Char,
         old: u32,
     },
+    CharClass {
+        char: ET::Char,
+        old: u16,
+    },
+    InterCharToks {
+        classes: (u16, u16),
+        old: TokenList<ET::Token>,
+    },
     ParShape {
         old: Vec<(ET::Dim, ET::Dim)>,
     },
@@ -595,14 +642,20 @@ impl<ET: EngineTypes> StateChangeI<ET> {
 impl<ET: EngineTypes> From<StateChange<ET>> for StateChangeI<ET> {
     fn from(value: StateChange<ET>) -> Self {
         let u = match &value {
-            StateChange::Catcode { char, .. } => (*char).into() as usize,
+            StateChange::Catcode { table, char, .. } => (*table << 21) | (*char).into() as usize,
             StateChange::SfCode { char, .. } => (*char).into() as usize,
             StateChange::LcCode { char, .. } => (*char).into() as usize,
             StateChange::UcCode { char, .. } => (*char).into() as usize,
             StateChange::MathCode { char, .. } => (*char).into() as usize,
             StateChange::DelCode { char, .. } => (*char).into() as usize,
+            StateChange::CharClass { char, .. } => (*char).into() as usize,
+            StateChange::InterCharToks {
+                classes: (from, to),
+                ..
+            } => ((*from as usize) << 16) | *to as usize,
             StateChange::AcCommand { char, .. } => (*char).into() as usize,
             StateChange::CurrentFont(_) => 0,
+            StateChange::CatcodeTable { .. } => 0,
             StateChange::EndlineChar { .. } => 0,
             StateChange::EscapeChar { .. } => 0,
             StateChange::NewlineChar { .. } => 0,
@@ -640,8 +693,19 @@ impl<ET: EngineTypes> EngineReferences<'_, ET> {
         globally: bool,
     ) {
         match name {
-            CSOrActiveChar::Active(c) => self.state.set_ac_command(self.aux, *c, cmd, globally),
-            CSOrActiveChar::Name(cs) => self.state.set_command(self.aux, cs.clone(), cmd, globally),
+            CSOrActiveChar::Active(c) => {
+                if let Some(TeXCommand::Macro(_)) = &cmd {
+                    self.record_definition(&c.display().to_string());
+                }
+                self.state.set_ac_command(self.aux, *c, cmd, globally)
+            }
+            CSOrActiveChar::Name(cs) => {
+                if let Some(TeXCommand::Macro(_)) = &cmd {
+                    let name = format!("\\{}", self.aux.memory.cs_interner().resolve(cs));
+                    self.record_definition(&name);
+                }
+                self.state.set_command(self.aux, cs.clone(), cmd, globally)
+            }
         }
     }
 }
@@ -0,0 +1,222 @@
+/*! A read-only view of a [`State`] at some point of a run (e.g. at its end, or from within a
+primitive acting as a breakpoint) - the defined macros with their parameter texts and bodies,
+non-default register values, the current fonts and catcodes - for editor tooling that wants to
+offer completion or "go to definition" for user macros.
+
+Where macros were defined is only known if [`EngineAux::definitions`] was set before the run;
+the [`State`] itself does not keep track of that.
+
+All names are given with a backslash as escape character, regardless of `\escapechar`.
+*/
+use crate::commands::TeXCommand;
+use crate::engine::filesystem::FileSystem;
+use crate::engine::fontsystem::Font;
+use crate::engine::mouth::Mouth;
+use crate::engine::state::State;
+use crate::engine::{EngineAux, EngineReferences, EngineTypes};
+use crate::tex::catcodes::CategoryCode;
+use crate::tex::characters::{Character, CharacterMap};
+use crate::tex::tokens::control_sequences::CSHandler;
+use crate::utils::HMap;
+
+/// Where a macro was (last) defined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefinitionSite {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The [`DefinitionSite`]s of all macros defined during a run, by name.
+#[derive(Debug, Clone, Default)]
+pub struct DefinitionSites(HMap<String, DefinitionSite>);
+impl DefinitionSites {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Where the macro with the given name (e.g. `\foo` or `~`) was last defined.
+    pub fn get(&self, name: &str) -> Option<&DefinitionSite> {
+        self.0.get(name)
+    }
+}
+
+impl<ET: EngineTypes> EngineReferences<'_, ET> {
+    /// Records the current position of the mouth as the [`DefinitionSite`] of the macro `name`
+    /// (if [`EngineAux::definitions`] is set).
+    pub(crate) fn record_definition(&mut self, name: &str) {
+        if let Some(sites) = self.aux.definitions.as_mut() {
+            let sr = self.mouth.current_sourceref();
+            sites.0.insert(
+                name.to_string(),
+                DefinitionSite {
+                    file: self.filesystem.ref_str(sr.file).to_string(),
+                    line: sr.line,
+                    column: sr.column,
+                },
+            );
+        }
+    }
+    /// Takes a [`StateSnapshot`] of the current state.
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot::new(self.state, self.aux)
+    }
+}
+
+/// A defined macro.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroDefinition {
+    /// e.g. `\foo`, or `~` for an active character
+    pub name: String,
+    pub protected: bool,
+    pub long: bool,
+    pub outer: bool,
+    /// the parameter text, e.g. `#1#2` or `#1\to#2`
+    pub parameters: String,
+    /// the replacement text (with `##` for parameter tokens)
+    pub body: String,
+    /// `None` if definition sites were not recorded
+    pub defined_at: Option<DefinitionSite>,
+}
+
+/// A font as selected by a font identifier.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FontInfo {
+    /// the font identifier, e.g. `\tenrm`
+    pub name: String,
+    /// the font file, e.g. `cmr10`
+    pub file: String,
+    /// the size, if loaded with `at` or `scaled`
+    pub at: Option<String>,
+}
+impl FontInfo {
+    fn new<ET: EngineTypes>(font: &ET::Font, aux: &EngineAux<ET>) -> Self {
+        Self {
+            name: format!("\\{}", aux.memory.cs_interner().resolve(font.name())),
+            file: font.filename().to_string(),
+            at: font.has_at_set().then(|| font.get_at().to_string()),
+        }
+    }
+}
+
+/// See the [module documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct StateSnapshot {
+    /// all defined macros, sorted by name
+    pub macros: Vec<MacroDefinition>,
+    /// all other defined control sequences and active characters with their `\meaning`, sorted
+    /// by name (e.g. `\tenrm` and `select font cmr10`, or `\z@` and `\dimen0`); primitives
+    /// with their original meaning are omitted
+    pub commands: Vec<(String, String)>,
+    /// all `\count` registers with a non-zero value
+    pub int_registers: Vec<(usize, i64)>,
+    /// all `\dimen` registers with a non-zero value
+    pub dim_registers: Vec<(usize, String)>,
+    /// all `\skip` registers with a non-zero value
+    pub skip_registers: Vec<(usize, String)>,
+    /// all `\muskip` registers with a non-zero value
+    pub muskip_registers: Vec<(usize, String)>,
+    /// all non-empty `\toks` registers
+    pub toks_registers: Vec<(usize, String)>,
+    /// the indices of all non-void `\box` registers
+    pub box_registers: Vec<usize>,
+    pub current_font: FontInfo,
+    /// `\textfont`, `\scriptfont` and `\scriptscriptfont` for every family
+    pub math_fonts: Vec<(u8, FontInfo, FontInfo, FontInfo)>,
+    /// the category codes differing from their initial values, sorted by character
+    pub catcodes: Vec<(char, CategoryCode)>,
+    pub group_level: usize,
+}
+impl StateSnapshot {
+    pub fn new<ET: EngineTypes>(state: &ET::State, aux: &EngineAux<ET>) -> Self {
+        let int = aux.memory.cs_interner();
+        let cc = state.get_catcode_scheme();
+        let esc = Some(ET::Char::from(b'\\'));
+        let mut ret = Self {
+            group_level: state.get_group_level(),
+            current_font: FontInfo::new(state.get_current_font(), aux),
+            ..Self::default()
+        };
+        let mut add = |name: String, cmd: &TeXCommand<ET>| match cmd {
+            TeXCommand::Macro(m) => ret.macros.push(MacroDefinition {
+                protected: m.protected,
+                long: m.long,
+                outer: m.outer,
+                parameters: m.signature.params.display(int, cc, esc, false).to_string(),
+                body: m.expansion.display(int, cc, esc, true).to_string(),
+                defined_at: aux.definitions.as_ref().and_then(|d| d.get(&name)).cloned(),
+                name,
+            }),
+            cmd => {
+                let meaning = cmd.meaning(int, cc, esc).to_string();
+                if meaning != name {
+                    ret.commands.push((name, meaning));
+                }
+            }
+        };
+        for (cs, cmd) in state.iter_commands() {
+            add(format!("\\{}", int.resolve(&cs)), cmd);
+        }
+        let (min, max): (u64, u64) = (ET::Char::MIN.into(), ET::Char::MAX.into());
+        let initial = ET::Char::starting_catcode_scheme();
+        for c in (min..=max).filter_map(|c| ET::Char::try_from(c).ok()) {
+            if let Some(cmd) = state.get_ac_command(c) {
+                add(c.display().to_string(), cmd);
+            }
+            if cc.get(c) != initial.get(c) {
+                ret.catcodes.push((c.to_char(), *cc.get(c)));
+            }
+        }
+        ret.macros.sort_by(|a, b| a.name.cmp(&b.name));
+        ret.commands.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for idx in 0..=u16::MAX as usize {
+            let i: i64 = state.get_int_register(idx).into();
+            if i != 0 {
+                ret.int_registers.push((idx, i));
+            }
+            let d = state.get_dim_register(idx);
+            if d != ET::Dim::default() {
+                ret.dim_registers.push((idx, d.to_string()));
+            }
+            let s = state.get_skip_register(idx);
+            if s != Default::default() {
+                ret.skip_registers.push((idx, s.to_string()));
+            }
+            let m = state.get_muskip_register(idx);
+            if m != Default::default() {
+                ret.muskip_registers.push((idx, m.to_string()));
+            }
+            let t = state.get_toks_register(idx);
+            if !t.is_empty() {
+                ret.toks_registers
+                    .push((idx, t.display(int, cc, esc, false).to_string()));
+            }
+            if state.get_box_register(idx).is_some() {
+                ret.box_registers.push(idx);
+            }
+        }
+        for fam in 0..16 {
+            ret.math_fonts.push((
+                fam,
+                FontInfo::new(state.get_textfont(fam), aux),
+                FontInfo::new(state.get_scriptfont(fam), aux),
+                FontInfo::new(state.get_scriptscriptfont(fam), aux),
+            ));
+        }
+        ret
+    }
+
+    /// The macro with the given name (e.g. `\foo`), if defined.
+    pub fn get_macro(&self, name: &str) -> Option<&MacroDefinition> {
+        self.macros
+            .binary_search_by(|m| m.name.as_str().cmp(name))
+            .ok()
+            .map(|i| &self.macros[i])
+    }
+    /// All macros whose name starts with `prefix` (e.g. `\sec`), for completion.
+    pub fn complete<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a MacroDefinition> {
+        self.macros
+            .iter()
+            .filter(move |m| m.name.starts_with(prefix))
+    }
+}
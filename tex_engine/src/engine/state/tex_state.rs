@@ -5,6 +5,7 @@ use crate::engine::fontsystem::Font;
 use crate::engine::mouth::Mouth;
 use crate::engine::state::{GroupType, State, StateChange, StateChangeTracker, StateStack};
 use crate::engine::utils::outputs::Outputs;
+use crate::engine::utils::trace::CatcodeCause;
 use crate::engine::{EngineAux, EngineTypes};
 use crate::tex::catcodes::{CategoryCode, CategoryCodeScheme};
 use crate::tex::characters::Character;
@@ -22,11 +23,15 @@ pub struct DefaultState<ET: EngineTypes> {
     stack: StateStack<ET>,
     primitives: PrimitiveCommands<ET>,
     catcodes: CategoryCodeScheme<ET::Char>,
+    catcode_table: usize,
+    catcode_tables: Vec<Option<CategoryCodeScheme<ET::Char>>>,
     sfcodes: <ET::Char as Character>::CharMap<u16>,
     lccodes: <ET::Char as Character>::CharMap<ET::Char>,
     uccodes: <ET::Char as Character>::CharMap<ET::Char>,
     mathcodes: <ET::Char as Character>::CharMap<u32>,
     delcodes: <ET::Char as Character>::CharMap<ET::Int>,
+    charclasses: <ET::Char as Character>::CharMap<u16>,
+    interchar_toks: HMap<(u16, u16), TokenList<ET::Token>>,
     primitive_ints: HMap<PrimitiveIdentifier, ET::Int>,
     primitive_dims: HMap<PrimitiveIdentifier, ET::Dim>,
     primitive_skips: HMap<PrimitiveIdentifier, Skip<ET::Dim>>,
@@ -57,6 +62,15 @@ impl<ET: EngineTypes> DefaultState<ET> {
     fn tracing_restores(&self) -> bool {
         matches!(self.primitive_ints.get(&PRIMITIVES.tracingrestores), Some(v) if *v > ET::Int::default())
     }
+    /// Writes the current catcodes back to the active table and loads table `idx`.
+    fn switch_catcode_table(&mut self, idx: usize) -> bool {
+        switch_catcode_table::<ET::Char>(
+            &mut self.catcodes,
+            &mut self.catcode_table,
+            &mut self.catcode_tables,
+            idx,
+        )
+    }
     pub fn set_command_direct(&mut self, name: ET::CSName, cmd: Option<TeXCommand<ET>>) {
         match cmd {
             None => self.commands.remove(&name),
@@ -65,6 +79,27 @@ impl<ET: EngineTypes> DefaultState<ET> {
     }
 }
 
+fn switch_catcode_table<C: Character>(
+    catcodes: &mut CategoryCodeScheme<C>,
+    current: &mut usize,
+    tables: &mut Vec<Option<CategoryCodeScheme<C>>>,
+    idx: usize,
+) -> bool {
+    if idx == *current {
+        return true;
+    }
+    let Some(Some(new)) = tables.get(idx) else {
+        return false;
+    };
+    let old = std::mem::replace(catcodes, new.clone());
+    if *current >= tables.len() {
+        tables.resize(*current + 1, None);
+    }
+    tables[*current] = Some(old);
+    *current = idx;
+    true
+}
+
 impl<ET: EngineTypes> StateChangeTracker<ET> for DefaultState<ET> {
     fn stack(&mut self) -> &mut StateStack<ET> {
         &mut self.stack
@@ -92,8 +127,12 @@ impl<ET: EngineTypes> State<ET> for DefaultState<ET> {
             stack: StateStack::default(),
             primitives: PrimitiveCommands::default(),
             catcodes: ET::Char::starting_catcode_scheme(),
+            catcode_table: 0,
+            catcode_tables: vec![None],
             sfcodes: CharacterMap::default(),
             delcodes: CharacterMap::default(),
+            charclasses: CharacterMap::default(),
+            interchar_toks: HMap::default(),
             lccodes,
             uccodes,
             mathcodes,
@@ -184,7 +223,7 @@ impl<ET: EngineTypes> State<ET> for DefaultState<ET> {
         ch.close(|c| {
             match c {
                 //StateChange::Custom { change } => change.restore(aux,self,trace),
-                StateChange::Catcode { char, old } => {
+                StateChange::Catcode { table, char, old } => {
                     if trace {
                         aux.outputs.write_neg1(format_args!(
                             "{{restoring {}catcode{}={}}}",
@@ -193,7 +232,46 @@ impl<ET: EngineTypes> State<ET> for DefaultState<ET> {
                             old
                         ));
                     }
-                    *self.catcodes.get_mut(char) = old;
+                    if table != self.catcode_table {
+                        // the table has been switched since; restore the saved one
+                        if let Some(Some(t)) = self.catcode_tables.get_mut(table) {
+                            *t.get_mut(char) = old;
+                        }
+                    } else {
+                        *self.catcodes.get_mut(char) = old;
+                        if let Some(t) = aux.catcode_trace.as_mut() {
+                            t.changed(
+                                char.into(),
+                                old,
+                                CatcodeCause::GroupEnd,
+                                mouth.current_sourceref(),
+                            );
+                        }
+                    }
+                }
+                StateChange::CatcodeTable { old } => {
+                    if trace {
+                        aux.outputs.write_neg1(format_args!(
+                            "{{restoring {}catcodetable={}}}",
+                            <ET::Char as Character>::display_opt(self.escape_char),
+                            old
+                        ));
+                    }
+                    let before = aux.catcode_trace.is_some().then(|| self.catcodes.clone());
+                    switch_catcode_table::<ET::Char>(
+                        &mut self.catcodes,
+                        &mut self.catcode_table,
+                        &mut self.catcode_tables,
+                        old,
+                    );
+                    if let (Some(t), Some(before)) = (aux.catcode_trace.as_mut(), before) {
+                        t.changed_scheme::<ET::Char>(
+                            &before,
+                            &self.catcodes,
+                            CatcodeCause::GroupEnd,
+                            mouth.current_sourceref(),
+                        );
+                    }
                 }
                 StateChange::CurrentFont(font) => {
                     if trace {
@@ -299,6 +377,41 @@ impl<ET: EngineTypes> State<ET> for DefaultState<ET> {
                     }
                     *self.mathcodes.get_mut(char) = old;
                 }
+                StateChange::CharClass { char, old } => {
+                    if trace {
+                        aux.outputs.write_neg1(format_args!(
+                            "{{restoring {}XeTeXcharclass{}={}}}",
+                            <ET::Char as Character>::display_opt(self.escape_char),
+                            char.into(),
+                            old
+                        ));
+                    }
+                    *self.charclasses.get_mut(char) = old;
+                }
+                StateChange::InterCharToks {
+                    classes: (from, to),
+                    old,
+                } => {
+                    if trace {
+                        aux.outputs.write_neg1(format_args!(
+                            "{{restoring {}XeTeXinterchartoks{} {}={}}}",
+                            <ET::Char as Character>::display_opt(self.escape_char),
+                            from,
+                            to,
+                            old.display(
+                                aux.memory.cs_interner(),
+                                &self.catcodes,
+                                self.escape_char,
+                                false
+                            )
+                        ));
+                    }
+                    if old.is_empty() {
+                        self.interchar_toks.remove(&(from, to));
+                    } else {
+                        self.interchar_toks.insert((from, to), old);
+                    }
+                }
                 StateChange::EndlineChar { old } => {
                     if trace {
                         aux.outputs.write_neg1(format_args!(
@@ -705,9 +818,58 @@ impl<ET: EngineTypes> State<ET> for DefaultState<ET> {
                 ));
             }
             let old = std::mem::replace(s.catcodes.get_mut(c), cc);
-            StateChange::Catcode { char: c, old }
+            StateChange::Catcode {
+                table: s.catcode_table,
+                char: c,
+                old,
+            }
         })
     }
+    fn get_catcode_table(&self) -> usize {
+        self.catcode_table
+    }
+    fn set_catcode_table(&mut self, aux: &EngineAux<ET>, idx: usize, globally: bool) -> bool {
+        if idx == self.catcode_table {
+            return true;
+        }
+        if !matches!(self.catcode_tables.get(idx), Some(Some(_))) {
+            return false;
+        }
+        self.change_field(globally, |s, g| {
+            if s.tracing_assigns() {
+                aux.outputs.write_neg1(format_args!(
+                    "{{{} {}catcodetable={}}}",
+                    if g {
+                        "globally changing"
+                    } else {
+                        "reassigning"
+                    },
+                    <ET::Char as Character>::display_opt(s.escape_char),
+                    idx
+                ));
+            }
+            let old = s.catcode_table;
+            s.switch_catcode_table(idx);
+            StateChange::CatcodeTable { old }
+        });
+        true
+    }
+    fn save_catcode_table(&mut self, idx: usize) {
+        if idx >= self.catcode_tables.len() {
+            self.catcode_tables.resize(idx + 1, None);
+        }
+        self.catcode_tables[idx] = Some(self.catcodes.clone());
+    }
+    fn init_catcode_table(&mut self, idx: usize) {
+        if idx == self.catcode_table {
+            self.catcodes = ET::Char::starting_catcode_scheme();
+            return;
+        }
+        if idx >= self.catcode_tables.len() {
+            self.catcode_tables.resize(idx + 1, None);
+        }
+        self.catcode_tables[idx] = Some(ET::Char::starting_catcode_scheme());
+    }
 
     fn get_sfcode(&self, c: ET::Char) -> u16 {
         *self.sfcodes.get(c)
@@ -839,6 +1001,83 @@ impl<ET: EngineTypes> State<ET> for DefaultState<ET> {
         })
     }
 
+    fn get_charclass(&self, c: ET::Char) -> u16 {
+        *self.charclasses.get(c)
+    }
+    fn set_charclass(&mut self, aux: &EngineAux<ET>, c: ET::Char, val: u16, globally: bool) {
+        self.change_field(globally, |s, g| {
+            let old = std::mem::replace(s.charclasses.get_mut(c), val);
+            if s.tracing_assigns() {
+                let num = c.into();
+                aux.outputs.write_neg1(format_args!(
+                    "{{{}changing {}XeTeXcharclass{}={}}}",
+                    if g { "globally " } else { "" },
+                    <ET::Char as Character>::display_opt(s.escape_char),
+                    num,
+                    old
+                ));
+                aux.outputs.write_neg1(format_args!(
+                    "{{into {}XeTeXcharclass{}={}}}",
+                    <ET::Char as Character>::display_opt(s.escape_char),
+                    num,
+                    val
+                ));
+            }
+            StateChange::CharClass { char: c, old }
+        })
+    }
+
+    fn get_interchar_toks(&self, from: u16, to: u16) -> &TokenList<ET::Token> {
+        match self.interchar_toks.get(&(from, to)) {
+            Some(t) => t,
+            _ => &self.empty_list,
+        }
+    }
+    fn set_interchar_toks(
+        &mut self,
+        aux: &EngineAux<ET>,
+        from: u16,
+        to: u16,
+        v: TokenList<ET::Token>,
+        globally: bool,
+    ) {
+        self.change_field(globally, |s, g| {
+            let old = if v.is_empty() {
+                s.interchar_toks.remove(&(from, to))
+            } else {
+                s.interchar_toks.insert((from, to), v)
+            }
+            .unwrap_or_else(|| s.empty_list.clone());
+            if s.tracing_assigns() {
+                let esc = ET::Char::display_opt(s.escape_char);
+                aux.outputs.write_neg1(format_args!(
+                    "{{{}changing {}XeTeXinterchartoks{} {}={}}}",
+                    if g { "globally " } else { "" },
+                    esc,
+                    from,
+                    to,
+                    old.display(aux.memory.cs_interner(), &s.catcodes, s.escape_char, false)
+                ));
+                aux.outputs.write_neg1(format_args!(
+                    "{{into {}XeTeXinterchartoks{} {}={}}}",
+                    esc,
+                    from,
+                    to,
+                    s.get_interchar_toks(from, to).display(
+                        aux.memory.cs_interner(),
+                        &s.catcodes,
+                        s.escape_char,
+                        false
+                    )
+                ));
+            }
+            StateChange::InterCharToks {
+                classes: (from, to),
+                old,
+            }
+        })
+    }
+
     fn get_endline_char(&self) -> Option<ET::Char> {
         self.endline_char
     }
@@ -1321,6 +1560,9 @@ impl<ET: EngineTypes> State<ET> for DefaultState<ET> {
     fn get_command(&self, name: &ET::CSName) -> Option<&TeXCommand<ET>> {
         self.commands.get(name)
     }
+    fn iter_commands(&self) -> impl Iterator<Item = (ET::CSName, &TeXCommand<ET>)> {
+        self.commands.iter()
+    }
     fn set_command(
         &mut self,
         aux: &EngineAux<ET>,
@@ -363,6 +363,14 @@ pub trait Stomach<ET: EngineTypes /*<Stomach = Self>*/> {
     ) -> TeXResult<(), ET> {
         methods::do_char(engine, token, char, code)
     }
+    /// Adds the interword glue for a space in horizontal mode, depending on the current
+    /// [`spacefactor`](StomachData::spacefactor) (see [`methods::space_glue`])
+    fn add_space(engine: &mut EngineReferences<ET>) {
+        match methods::space_glue(engine) {
+            None => Self::add_node_h(engine, HNode::Space),
+            Some(glue) => Self::add_node_h(engine, HNode::HSkip(glue)),
+        }
+    }
     fn do_char_in_math(engine: &mut EngineReferences<ET>, char: ET::Char) -> TeXResult<(), ET> {
         ET::Stomach::add_node_m(
             engine,
@@ -515,7 +523,13 @@ pub trait Stomach<ET: EngineTypes /*<Stomach = Self>*/> {
                     Self::add_node_h(engine, c);
                 }
             }
-            _ => unreachable!("Stomach::close_align called outside of an align"),
+            _ => {
+                return Err(engine
+                    .aux
+                    .hardening
+                    .violation("Stomach::close_align called outside of an align")
+                    .into())
+            }
         };
         Ok(())
     }
@@ -525,24 +539,40 @@ pub trait Stomach<ET: EngineTypes /*<Stomach = Self>*/> {
         engine: &mut EngineReferences<ET>,
         node: MathNode<ET, UnresolvedMathFontStyle<ET>>,
     ) {
+        engine.aux.limits.node();
+        let depth = engine.stomach.data_mut().open_lists.len();
+        engine.aux.limits.list_depth(depth);
         match engine.stomach.data_mut().open_lists.last_mut() {
             Some(NodeList::Math { children, .. }) => {
                 children.push(node);
             }
-            _ => unreachable!("Stomach::add_node_m called outside of math mode"),
+            _ => engine
+                .aux
+                .hardening
+                .defer("Stomach::add_node_m called outside of math mode"),
         }
     }
 
     /// Adds a node to the current horizontal list (i.e. assumes we're in (restricted) horizontal mode)
     fn add_node_h(engine: &mut EngineReferences<ET>, node: HNode<ET>) {
-        if let HNode::Penalty(i) = node {
-            engine.stomach.data_mut().lastpenalty = i;
+        engine.aux.limits.node();
+        let depth = engine.stomach.data_mut().open_lists.len();
+        engine.aux.limits.list_depth(depth);
+        match node {
+            HNode::Penalty(i) => engine.stomach.data_mut().lastpenalty = i,
+            HNode::Box(_) | HNode::VRule { .. } | HNode::MathGroup(_) => {
+                engine.stomach.data_mut().spacefactor = 1000
+            }
+            _ => (),
         }
         match engine.stomach.data_mut().open_lists.last_mut() {
             Some(NodeList::Horizontal { children, .. }) => {
                 children.push(node);
             }
-            _ => unreachable!("Stomach::add_node_h called outside of horizontal mode"),
+            _ => engine
+                .aux
+                .hardening
+                .defer("Stomach::add_node_h called outside of horizontal mode"),
         }
     }
 
@@ -593,6 +623,7 @@ pub trait Stomach<ET: EngineTypes /*<Stomach = Self>*/> {
         let sref = engine.mouth.start_ref();
         let data = engine.stomach.data_mut();
         data.prevgraf = 0;
+        data.spacefactor = 1000;
         data.open_lists.push(NodeList::Horizontal {
             tp: HorizontalNodeListType::Paragraph(sref),
             children: vec![],
@@ -659,7 +690,13 @@ pub trait Stomach<ET: EngineTypes /*<Stomach = Self>*/> {
                 let spec = ParLineSpec::make(engine.state, engine.aux);
                 Self::split_paragraph(engine, spec, children, sourceref)?;
             }
-            _ => unreachable!("Stomach::close_paragraph called outside of horizontal mode"),
+            _ => {
+                return Err(engine
+                    .aux
+                    .hardening
+                    .violation("Stomach::close_paragraph called outside of horizontal mode")
+                    .into())
+            }
         }
         Ok(())
     }
@@ -683,6 +720,7 @@ pub trait Stomach<ET: EngineTypes /*<Stomach = Self>*/> {
             match line {
                 ParLine::Adjust(n) => Self::add_node_v(engine, n)?,
                 ParLine::Line(bx) => Self::add_node_v(engine, VNode::Box(bx))?,
+                ParLine::Penalty(p) => Self::add_node_v(engine, VNode::Penalty(p))?,
             }
         }
         Ok(())
@@ -719,6 +757,14 @@ pub struct StomachData<ET: EngineTypes> {
     pub deadcycles: usize,
     pub vadjusts: Vec<VNode<ET>>,
     pub inserts: Vec<(usize, Box<[VNode<ET>]>)>,
+    /// The `\XeTeXcharclass` of the previous character, or the boundary class `4095`
+    pub charclass: u16,
+    /// Whether the `\XeTeXinterchartoks` in front of the next character have been inserted
+    /// already
+    pub interchar_inserted: bool,
+    /// Whether to insert the implicit kerns of the fonts' lig/kern programs between characters
+    /// (as [`HNode::HKern`]s), as TeX does
+    pub implicit_kerns: bool,
 }
 impl<ET: EngineTypes> StomachData<ET> {
     /// The current [`TeXMode`] (indicating the type of node list currently open)
@@ -744,11 +790,27 @@ impl<ET: EngineTypes> StomachData<ET> {
                         }
                     }
                 }
-                unreachable!()
+                // a broken stomach; reported by the next check in the top loop (see
+                // `orphaned_math_list`)
+                TeXMode::InlineMath
             }
             None => TeXMode::Vertical,
         }
     }
+    /// Whether the innermost open list is a math list without an enclosing
+    /// [`Top`](MathNodeListType::Top) math list, which only a broken stomach can produce.
+    pub fn orphaned_math_list(&self) -> bool {
+        matches!(self.open_lists.last(), Some(NodeList::Math { .. }))
+            && !self.open_lists.iter().any(|ls| {
+                matches!(
+                    ls,
+                    NodeList::Math {
+                        tp: MathNodeListType::Top { .. },
+                        ..
+                    }
+                )
+            })
+    }
 }
 
 impl<ET: EngineTypes> Default for StomachData<ET> {
@@ -778,6 +840,9 @@ impl<ET: EngineTypes> Default for StomachData<ET> {
             deadcycles: 0,
             vadjusts: vec![],
             inserts: vec![],
+            charclass: methods::BOUNDARY_CLASS,
+            interchar_inserted: false,
+            implicit_kerns: true,
         }
     }
 }
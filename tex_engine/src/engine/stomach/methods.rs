@@ -6,6 +6,8 @@ use crate::engine::gullet::Gullet;
 use crate::engine::mouth::Mouth;
 use crate::engine::state::{GroupType, State};
 use crate::engine::stomach::{Stomach, TeXMode};
+use crate::engine::utils::hardening::{Hardening, InvariantViolation};
+use crate::engine::utils::outputs::Outputs;
 use crate::engine::{EngineAux, EngineReferences, EngineTypes};
 use crate::prelude::{Character, CommandCode, TokenList};
 use crate::tex::nodes::boxes::{BoxType, HBoxInfo, TeXBox, ToOrSpread, VBoxInfo};
@@ -16,8 +18,8 @@ use crate::tex::nodes::math::{
 };
 use crate::tex::nodes::vertical::{VNode, VerticalNodeListType};
 use crate::tex::nodes::{BoxTarget, ListTarget, NodeList, NodeTrait};
-use crate::tex::numerics::Skip;
 use crate::tex::numerics::TeXDimen;
+use crate::tex::numerics::{Numeric, Skip, StretchShrink};
 use crate::tex::tokens::Token;
 use crate::utils::errors::{TeXError, TeXResult};
 
@@ -61,11 +63,24 @@ pub fn assign_toks_register<ET: EngineTypes>(
     register: usize,
     global: bool,
 ) -> TeXResult<(), ET> {
-    let mut had_eq = false;
-    let cont = |engine: &mut EngineReferences<ET>, ls| {
+    assign_toks(engine, token, |engine, ls| {
         engine
             .state
-            .set_toks_register(engine.aux, register, ls, global);
+            .set_toks_register(engine.aux, register, ls, global)
+    })
+}
+
+/// Reads the right-hand side of a token list assignment (an optional `=` followed by a braced
+/// token list or a token list parameter) and passes it to `set`. Used for `\toks` registers and
+/// e.g. `\XeTeXinterchartoks`.
+pub fn assign_toks<ET: EngineTypes, F: FnOnce(&mut EngineReferences<ET>, TokenList<ET::Token>)>(
+    engine: &mut EngineReferences<ET>,
+    token: ET::Token,
+    set: F,
+) -> TeXResult<(), ET> {
+    let mut had_eq = false;
+    let cont = |engine: &mut EngineReferences<ET>, ls| {
+        set(engine, ls);
         insert_afterassignment(engine);
     };
     crate::expand_loop!(ET; engine,tk,
@@ -233,7 +248,7 @@ pub fn do_char<ET: EngineTypes>(
     match code {
         CommandCode::EOF => (),
         CommandCode::Space if engine.stomach.data_mut().mode().is_horizontal() => {
-            ET::Stomach::add_node_h(engine, HNode::Space)
+            ET::Stomach::add_space(engine)
         }
         CommandCode::Space => (),
         CommandCode::BeginGroup if engine.stomach.data_mut().mode().is_math() => {
@@ -257,7 +272,11 @@ pub fn do_char<ET: EngineTypes>(
         CommandCode::EndGroup => match engine.state.get_group_type() {
             Some(GroupType::Simple) => engine.state.pop(engine.aux, engine.mouth),
             Some(
-                GroupType::HBox | GroupType::Math | GroupType::MathChoice | GroupType::LeftRight,
+                GroupType::HBox
+                | GroupType::Math
+                | GroupType::MathChoice
+                | GroupType::LeftRight
+                | GroupType::Disc,
             ) => ET::Stomach::close_box(engine, BoxType::Horizontal)?,
             Some(
                 GroupType::VBox
@@ -298,7 +317,13 @@ pub fn do_char<ET: EngineTypes>(
         CommandCode::Escape
         | CommandCode::Primitive
         | CommandCode::Active
-        | CommandCode::Argument => unreachable!(),
+        | CommandCode::Argument => {
+            return Err(engine
+                .aux
+                .hardening
+                .violation("do_char called with a non-character category code")
+                .into())
+        }
         CommandCode::AlignmentTab => engine.general_error(format!(
             "Misplaced alignment tab character {}",
             char.display()
@@ -323,34 +348,132 @@ pub fn do_char<ET: EngineTypes>(
     Ok(())
 }
 
+/// The `\XeTeXcharclass` of the start and end of a word.
+pub const BOUNDARY_CLASS: u16 = 4095;
+/// The `\XeTeXcharclass` of characters that are transparent for `\XeTeXinterchartoks`.
+pub const IGNORED_CLASS: u16 = 4096;
+
+/// If `\XeTeXinterchartokenstate` is positive, inserts the `\XeTeXinterchartoks` between the
+/// class of the previous character and the class of `char` (or the boundary class, if `None`),
+/// followed by `next`; returns whether anything was inserted.
+fn insert_interchar_toks<ET: EngineTypes>(
+    state: &ET::State,
+    stomach: &mut ET::Stomach,
+    mouth: &mut ET::Mouth,
+    char: Option<ET::Char>,
+    next: ET::Token,
+) -> bool {
+    if state.get_primitive_int(PRIMITIVES.interchartokenstate) <= ET::Int::default() {
+        return false;
+    }
+    let class = char.map_or(BOUNDARY_CLASS, |c| state.get_charclass(c));
+    let data = stomach.data_mut();
+    if class == IGNORED_CLASS || std::mem::take(&mut data.interchar_inserted) {
+        return false;
+    }
+    let prev = std::mem::replace(&mut data.charclass, class);
+    let toks = state.get_interchar_toks(prev, class);
+    if toks.is_empty() || (prev == BOUNDARY_CLASS && class == BOUNDARY_CLASS) {
+        return false;
+    }
+    // the next character's check is done; the one at the end of a word never comes back here
+    data.interchar_inserted = char.is_some();
+    mouth.requeue(next);
+    mouth.push_exp(toks);
+    true
+}
+
 #[allow(clippy::no_effect)]
 fn do_word<ET: EngineTypes>(
     engine: &mut EngineReferences<ET>,
     char: ET::Char,
 ) -> TeXResult<(), ET> {
     // TODO trace
+    if insert_interchar_toks::<ET>(
+        engine.state,
+        engine.stomach,
+        engine.mouth,
+        Some(char),
+        ET::Token::from_char_cat(char, CommandCode::Other),
+    ) {
+        return Ok(());
+    }
     let mut current = char;
     macro_rules! char {
         ($c:expr) => {{
+            let c = $c;
+            if insert_interchar_toks::<ET>(
+                engine.state,
+                engine.stomach,
+                engine.mouth,
+                Some(c),
+                ET::Token::from_char_cat(c, CommandCode::Other),
+            ) {
+                let font = engine.state.get_current_font().clone();
+                add_char::<ET>(
+                    engine.stomach,
+                    engine.state,
+                    &engine.aux.hardening,
+                    current,
+                    font,
+                )?;
+                return Ok(());
+            }
             let font = engine.state.get_current_font().clone();
-            match font.ligature(current, $c) {
-                Some(c) => {
-                    current = c;
+            match font.ligature(current, c) {
+                Some(lig) => {
+                    current = lig;
                 }
                 None => {
-                    add_char::<ET>(engine.stomach, engine.state, current, font);
-                    current = $c;
+                    add_char::<ET>(
+                        engine.stomach,
+                        engine.state,
+                        &engine.aux.hardening,
+                        current,
+                        font.clone(),
+                    )?;
+                    if engine.stomach.data_mut().implicit_kerns {
+                        if let Some(k) = font.kern(current, c) {
+                            ET::Stomach::add_node_h(engine, HNode::HKern(k));
+                        }
+                    }
+                    current = c;
                 }
             }
         }};
     }
 
     macro_rules! end {
+        ($tk:ident => $e:expr) => {{
+            if insert_interchar_toks::<ET>(
+                engine.state,
+                engine.stomach,
+                engine.mouth,
+                None,
+                $tk.clone(),
+            ) {
+                let font = engine.state.get_current_font().clone();
+                add_char::<ET>(
+                    engine.stomach,
+                    engine.state,
+                    &engine.aux.hardening,
+                    current,
+                    font,
+                )?;
+                return Ok(());
+            }
+            end!($e)
+        }};
         ($e:expr) => {{
             let font = engine.state.get_current_font().clone();
-            add_char::<ET>(engine.stomach, engine.state, current, font);
+            add_char::<ET>(
+                engine.stomach,
+                engine.state,
+                &engine.aux.hardening,
+                current,
+                font,
+            )?;
             $e;
-            engine.stomach.data_mut().spacefactor = 1000;
             return Ok(());
         }};
     }
@@ -367,17 +490,17 @@ fn do_word<ET: EngineTypes>(
         }
         ResolvedToken::Tk { code:CommandCode::Space, .. } |
         ResolvedToken::Cmd(Some(TeXCommand::Char {code:CommandCode::Space,..})) =>
-            end!(ET::Stomach::add_node_h(engine,HNode::Space)),
+            end!(token => ET::Stomach::add_space(engine)),
         ResolvedToken::Tk { char, code } =>
-            end!(ET::Stomach::do_char(engine,token,char,code)?),
+            end!(token => ET::Stomach::do_char(engine,token,char,code)?),
         ResolvedToken::Cmd(Some(TeXCommand::Char {char, code})) =>
-            end!(ET::Stomach::do_char(engine,token,*char,*code)?),
+            end!(token => ET::Stomach::do_char(engine,token,*char,*code)?),
         ResolvedToken::Cmd(None) => {
             TeXError::undefined(engine.aux,engine.state,engine.mouth,&token)?;
             end!(())
         }
         ResolvedToken::Cmd(Some(cmd)) => {
-            end!(crate::do_cmd!(ET;engine,token,cmd))
+            end!(token => crate::do_cmd!(ET;engine,token,cmd))
         }
     );
     end!(())
@@ -386,22 +509,68 @@ fn do_word<ET: EngineTypes>(
 fn add_char<ET: EngineTypes>(
     slf: &mut ET::Stomach,
     state: &ET::State,
+    hardening: &Hardening,
     char: ET::Char,
     font: ET::Font,
-) {
+) -> Result<(), InvariantViolation> {
     let sf = state.get_sfcode(char);
     let data = slf.data_mut();
+    // characters with `\sfcode` 0 (e.g. `)` in plain TeX) leave the space factor alone
     if sf > 1000 && data.spacefactor < 1000 {
         data.spacefactor = 1000;
-    } else {
+    } else if sf > 0 {
         data.spacefactor = sf as i32;
     }
     match slf.data_mut().open_lists.last_mut() {
         Some(NodeList::Horizontal { children, .. }) => {
             children.push(HNode::Char { char, font });
+            Ok(())
+        }
+        _ => Err(hardening.violation("add_char called outside of horizontal mode")),
+    }
+}
+
+/// The interword glue for a space, as TeX computes it from the current
+/// [`spacefactor`](crate::engine::stomach::StomachData::spacefactor): `\xspaceskip` after the end
+/// of a sentence (space factor >= 2000) if it is nonzero, otherwise `\spaceskip` or the font's
+/// interword glue, with the stretch scaled by `f/1000`, the shrink by `1000/f` and the font's extra
+/// space (`\fontdimen7`) added for a space factor `f >= 2000`. `None` if this is just the font's
+/// normal interword glue, i.e. an [`HNode::Space`].
+pub fn space_glue<ET: EngineTypes>(engine: &mut EngineReferences<ET>) -> Option<Skip<ET::Dim>> {
+    let sf = engine.stomach.data_mut().spacefactor;
+    let spaceskip = engine.state.get_primitive_skip(PRIMITIVES.spaceskip);
+    if sf == 1000 && spaceskip == Skip::default() {
+        return None;
+    }
+    if sf >= 2000 {
+        let xspaceskip = engine.state.get_primitive_skip(PRIMITIVES.xspaceskip);
+        if xspaceskip != Skip::default() {
+            return Some(xspaceskip);
+        }
+    }
+    let font = engine.state.get_current_font();
+    let mut glue = if spaceskip == Skip::default() {
+        Skip {
+            base: font.get_dim(1),
+            stretch: Some(StretchShrink::Dim(font.get_dim(2))),
+            shrink: Some(StretchShrink::Dim(font.get_dim(3))),
+        }
+    } else {
+        spaceskip
+    };
+    if sf > 0 && sf != 1000 {
+        let (f, thousand) = (ET::Int::from(sf), ET::Int::from(1000));
+        if sf >= 2000 {
+            glue.base = glue.base + font.get_dim(6);
+        }
+        if let Some(StretchShrink::Dim(d)) = &mut glue.stretch {
+            *d = d.scale(f, thousand);
+        }
+        if let Some(StretchShrink::Dim(d)) = &mut glue.shrink {
+            *d = d.scale(thousand, f);
         }
-        _ => unreachable!(),
     }
+    Some(glue)
 }
 
 fn open_math<ET: EngineTypes>(engine: &mut EngineReferences<ET>) -> TeXResult<(), ET> {
@@ -490,7 +659,11 @@ fn close_group_in_m<ET: EngineTypes>(engine: &mut EngineReferences<ET>) -> TeXRe
         }) if t.is_some() => {
             engine.state.pop(engine.aux, engine.mouth);
             let (children, None) = children.close(start, engine.mouth.current_sourceref()) else {
-                unreachable!()
+                return Err(engine
+                    .aux
+                    .hardening
+                    .violation("\\eqno in a math group")
+                    .into());
             };
             t.call(engine, children, start)
         }
@@ -542,7 +715,11 @@ fn close_group_in_m<ET: EngineTypes>(engine: &mut EngineReferences<ET>) -> TeXRe
             engine.state.pop(engine.aux, engine.mouth);
             Ok(())
         }
-        _ => unreachable!(),
+        _ => Err(engine
+            .aux
+            .hardening
+            .violation("math group closed outside of a math list")
+            .into()),
     }
 }
 
@@ -589,13 +766,14 @@ impl Script {
                 {
                     if let Some(MathNode::Atom(a)) = ch.list_mut().last_mut() {
                         a.sup = Some(children.into());
-                        Ok(())
-                    } else {
-                        unreachable!()
+                        return Ok(());
                     }
-                } else {
-                    unreachable!()
                 }
+                Err(engine
+                    .aux
+                    .hardening
+                    .violation("superscript outside of a math list")
+                    .into())
             }),
             _ => ListTarget::<ET, _>::new(|engine, children, _| {
                 if let Some(NodeList::Math { children: ch, .. }) =
@@ -603,13 +781,14 @@ impl Script {
                 {
                     if let Some(MathNode::Atom(a)) = ch.list_mut().last_mut() {
                         a.sub = Some(children.into());
-                        Ok(())
-                    } else {
-                        unreachable!()
+                        return Ok(());
                     }
-                } else {
-                    unreachable!()
                 }
+                Err(engine
+                    .aux
+                    .hardening
+                    .violation("subscript outside of a math list")
+                    .into())
             }),
         }
     }
@@ -635,19 +814,30 @@ fn do_xscript<ET: EngineTypes>(
             Some(MathNode::Atom(_)) => (),
             _ => children.push(MathNode::Atom(MathAtom::empty())),
         },
-        _ => unreachable!(),
+        _ => {
+            return Err(engine
+                .aux
+                .hardening
+                .violation("script outside of a math list")
+                .into())
+        }
     }
     engine.read_char_or_math_group(
         in_token,
         |script, engine, c| {
-            match engine.stomach.data_mut().open_lists.last_mut() {
-                Some(NodeList::Math { children, .. }) => match children.list_mut().last_mut() {
-                    Some(MathNode::Atom(a)) => script.merge(MathNode::Atom(c.to_atom()), a),
-                    _ => unreachable!(),
-                },
-                _ => unreachable!(),
+            if let Some(NodeList::Math { children, .. }) =
+                engine.stomach.data_mut().open_lists.last_mut()
+            {
+                if let Some(MathNode::Atom(a)) = children.list_mut().last_mut() {
+                    script.merge(MathNode::Atom(c.to_atom()), a);
+                    return Ok(());
+                }
             }
-            Ok(())
+            Err(engine
+                .aux
+                .hardening
+                .violation("script outside of a math list")
+                .into())
         },
         |script| script.tp(),
         script,
@@ -741,7 +931,29 @@ pub fn close_box<ET: EngineTypes>(
             bx.depth();
             add_box(engine, bx, target)?
         }
-        _ => unreachable!(),
+        Some(NodeList::Horizontal {
+            children,
+            tp: HorizontalNodeListType::Discretionary(mut parts),
+        }) if bt == BoxType::Horizontal => {
+            engine.state.pop(engine.aux, engine.mouth);
+            parts.push(children.into());
+            if parts.len() < 3 {
+                let tk = ET::Token::primitive(PRIMITIVES.discretionary);
+                return open_discretionary(engine, parts, &tk);
+            }
+            let nobreak = parts.pop().unwrap_or_default();
+            let post = parts.pop().unwrap_or_default();
+            let pre = parts.pop().unwrap_or_default();
+            ET::Stomach::add_node_h(engine, HNode::Discretionary { pre, post, nobreak });
+            return Ok(());
+        }
+        _ => {
+            return Err(engine
+                .aux
+                .hardening
+                .violation("Stomach::close_box called outside of a box")
+                .into())
+        }
     }
     match engine.stomach.data_mut().mode() {
         TeXMode::Vertical => {
@@ -761,12 +973,36 @@ pub fn close_box<ET: EngineTypes>(
     Ok(())
 }
 
+/// Opens the next part of a `\discretionary`, after the already completed `parts`.
+pub fn open_discretionary<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    parts: Vec<Box<[HNode<ET>]>>,
+    token: &ET::Token,
+) -> TeXResult<(), ET> {
+    engine.expand_until_bgroup(true, token)?;
+    engine
+        .stomach
+        .data_mut()
+        .open_lists
+        .push(NodeList::Horizontal {
+            children: vec![],
+            tp: HorizontalNodeListType::Discretionary(parts),
+        });
+    engine
+        .state
+        .push(engine.aux, GroupType::Disc, engine.mouth.line_number());
+    Ok(())
+}
+
 /// Default implementation for [`Stomach::add_node_v`].
 pub fn add_node_v<ET: EngineTypes>(
     engine: &mut EngineReferences<ET>,
     mut node: VNode<ET>,
 ) -> TeXResult<(), ET> {
+    engine.aux.limits.node();
+    engine.aux.limits.check_nodes()?;
     let data = engine.stomach.data_mut();
+    engine.aux.limits.list_depth(data.open_lists.len());
     let prevdepth = data.prevdepth;
 
     if let VNode::HRule { .. } = node {
@@ -812,7 +1048,13 @@ pub fn add_node_v<ET: EngineTypes>(
             children.push(node);
             return Ok(());
         }
-        Some(_) => unreachable!("add_node_v in non-vertical mode"),
+        Some(_) => {
+            return Err(engine
+                .aux
+                .hardening
+                .violation("add_node_v in non-vertical mode")
+                .into())
+        }
         _ => (),
     }
     if !data.page_contains_boxes
@@ -961,7 +1203,7 @@ pub fn do_output<ET: EngineTypes>(
         .set_box_register(engine.aux, 255, Some(bx), false);
 
     engine.push_every(PRIMITIVES.output);
-    engine.get_next(false).unwrap(); // '{':BeginGroup
+    engine.get_next(false)?; // '{':BeginGroup
 
     //crate::debug_log!(debug => "Here: {} at {}",engine.mouth.display_position(),engine.preview());
 
@@ -969,7 +1211,13 @@ pub fn do_output<ET: EngineTypes>(
     loop {
         let next = match engine.get_next(false)? {
             Some(t) => t,
-            None => unreachable!(),
+            None => {
+                return Err(engine
+                    .aux
+                    .hardening
+                    .violation("file ended in the output routine")
+                    .into())
+            }
         };
         //println!("HERE: {}",engine.preview());
         if engine.state.get_group_level() == depth && next.command_code() == CommandCode::EndGroup {
@@ -988,13 +1236,19 @@ pub fn do_output<ET: EngineTypes>(
                         ET::Stomach::add_node_v(engine, r)?
                     }
                 }
-                _ => unreachable!(),
+                _ => {
+                    return Err(engine
+                        .aux
+                        .hardening
+                        .violation("output routine ended outside of the page")
+                        .into())
+                }
             }
             engine.stomach.data_mut().in_output = false;
             return Ok(());
         }
         if next.is_primitive() == Some(PRIMITIVES.noexpand) {
-            engine.get_next(false).unwrap();
+            engine.get_next(false)?;
             continue;
         }
         crate::expand!(ET;engine,next;
@@ -1157,32 +1411,141 @@ impl<ET: EngineTypes> ParLineSpec<ET> {
     }
 }
 
-/// The result of breaking a paragraph into lines - either an actual line (horizontal box)
-/// or vertical material inserted via `\vadjust`.
+/// The result of breaking a paragraph into lines - either an actual line (horizontal box),
+/// vertical material inserted via `\vadjust`, or the penalty between two lines.
 pub enum ParLine<ET: EngineTypes> {
     Line(TeXBox<ET>), //{contents:Vec<TeXNode<ET>>, broken_early:bool },
     Adjust(VNode<ET>),
+    /// `\interlinepenalty`, plus `\clubpenalty` after the first line, `\widowpenalty` before
+    /// the last one and `\brokenpenalty` after a line that ends at a discretionary
+    Penalty(i32),
 }
 
-/// Rough implementation of paragraph breaking
+/// Rough implementation of paragraph breaking. A nonzero `\looseness` is approximated by
+/// breaking the paragraph again with the line widths decreased (or increased) by a few
+/// percent, until it has the requested number of lines more (or fewer); `\looseness` is
+/// reset afterwards. With `\tracingparagraphs` positive, the number of lines is logged.
 pub fn split_paragraph_roughly<ET: EngineTypes>(
     engine: &mut EngineReferences<ET>,
     specs: Vec<ParLineSpec<ET>>,
     children: Vec<HNode<ET>>,
     start: SourceReference<<ET::File as File>::SourceRefID>,
 ) -> Vec<ParLine<ET>> {
+    let looseness: i64 = engine.state.get_primitive_int(PRIMITIVES.looseness).into();
+    let (lines, hyphenated) = if looseness == 0 {
+        break_lines(engine, specs, children, start, 1.0)
+    } else {
+        engine
+            .state
+            .set_primitive_int(engine.aux, PRIMITIVES.looseness, ET::Int::default(), false);
+        let natural = break_lines(engine, specs.clone(), children.clone(), start, 1.0);
+        let goal = natural.1.len() as i64 + looseness;
+        let mut best = natural;
+        for step in 1..=10 {
+            let factor = if looseness > 0 {
+                1.0 - 0.02 * step as f64
+            } else {
+                1.0 + 0.01 * step as f64
+            };
+            let candidate = break_lines(engine, specs.clone(), children.clone(), start, factor);
+            if (candidate.1.len() as i64 - goal).abs() < (best.1.len() as i64 - goal).abs() {
+                best = candidate;
+            }
+            if best.1.len() as i64 == goal {
+                break;
+            }
+        }
+        best
+    };
+    if engine.state.get_primitive_int(PRIMITIVES.tracingparagraphs) > ET::Int::default() {
+        if looseness == 0 {
+            engine
+                .aux
+                .outputs
+                .write_neg1(format_args!("{{@paragraph: {} lines}}", hyphenated.len()));
+        } else {
+            engine.aux.outputs.write_neg1(format_args!(
+                "{{@paragraph: {} lines with looseness {}}}",
+                hyphenated.len(),
+                looseness
+            ));
+        }
+    }
+    interline_penalties(engine, lines, &hyphenated)
+}
+
+/// Inserts the [`ParLine::Penalty`]s in front of all lines but the first; `hyphenated` has
+/// one entry per line.
+fn interline_penalties<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    lines: Vec<ParLine<ET>>,
+    hyphenated: &[bool],
+) -> Vec<ParLine<ET>> {
+    let get =
+        |engine: &EngineReferences<ET>, p| -> i64 { engine.state.get_primitive_int(p).into() };
+    let interline = get(engine, PRIMITIVES.interlinepenalty);
+    let club = get(engine, PRIMITIVES.clubpenalty);
+    let widow = get(engine, PRIMITIVES.widowpenalty);
+    let broken = get(engine, PRIMITIVES.brokenpenalty);
+    let n = hyphenated.len();
+    let mut ret = Vec::with_capacity(lines.len() + n);
+    let mut i = 0;
+    for line in lines {
+        if let ParLine::Line(_) = line {
+            if i > 0 {
+                let mut p = interline;
+                if i == 1 {
+                    p += club;
+                }
+                if i == n - 1 {
+                    p += widow;
+                }
+                if hyphenated[i - 1] {
+                    p += broken;
+                }
+                if p != 0 {
+                    ret.push(ParLine::Penalty(p.clamp(-10000, 10000) as i32));
+                }
+            }
+            i += 1;
+        }
+        ret.push(line);
+    }
+    ret
+}
+
+/// Breaks the paragraph into lines, greedily, with all line widths multiplied by `factor`;
+/// also returns whether each line ends at a discretionary.
+fn break_lines<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    specs: Vec<ParLineSpec<ET>>,
+    children: Vec<HNode<ET>>,
+    start: SourceReference<<ET::File as File>::SourceRefID>,
+    factor: f64,
+) -> (Vec<ParLine<ET>>, Vec<bool>) {
     let mut ret: Vec<ParLine<ET>> = Vec::new();
+    let mut hyphenated: Vec<bool> = Vec::new();
     let mut hgoals = specs.into_iter();
     let mut nodes = children.into_iter();
     let mut line_spec = hgoals.next().unwrap();
-    let mut target = line_spec.target;
+    let mut target = line_spec.target.scale_float(factor);
     let mut currstart = start;
     let mut currend = currstart;
     let mut curr_height = ET::Dim::default();
     let mut curr_depth = ET::Dim::default();
+    // whether the previous node was a `\penalty10000`, which makes the following glue unbreakable
+    let mut nobreak = false;
+    // the post-break text of a discretionary the previous line was broken at
+    let mut post_break: Vec<HNode<ET>> = vec![];
     'A: loop {
         let mut line = vec![];
         let mut reinserts = vec![];
+        for node in std::mem::take(&mut post_break) {
+            target = target + (-node.width());
+            curr_height = curr_height.max(node.height());
+            curr_depth = curr_depth.max(node.depth());
+            line.push(node);
+        }
 
         macro_rules! next_line {
             ($b:literal) => {
@@ -1200,6 +1563,7 @@ pub fn split_paragraph_roughly<ET: EngineTypes>(
                         },
                         preskip: None,
                     }));
+                    hyphenated.push(!post_break.is_empty());
                 }
                 for c in reinserts {
                     ret.push(ParLine::Adjust(c));
@@ -1208,7 +1572,7 @@ pub fn split_paragraph_roughly<ET: EngineTypes>(
                     None => (),
                     Some(e) => line_spec = e,
                 }
-                target = line_spec.target;
+                target = line_spec.target.scale_float(factor);
             };
         }
 
@@ -1229,6 +1593,7 @@ pub fn split_paragraph_roughly<ET: EngineTypes>(
                             },
                             preskip: None,
                         }));
+                        hyphenated.push(false);
                     }
                     for c in reinserts {
                         ret.push(ParLine::Adjust(c));
@@ -1259,24 +1624,37 @@ pub fn split_paragraph_roughly<ET: EngineTypes>(
                         },
                         preskip: None,
                     }));
+                    hyphenated.push(false);
                     continue 'A;
                 }
                 Some(HNode::Penalty(i)) if i <= -10000 => {
                     next_line!(true); // TODO mark somehow
                     continue 'A;
                 }
-                Some(HNode::Penalty(_)) => (),
+                Some(HNode::Penalty(i)) => nobreak = i >= 10000,
                 Some(
                     n @ HNode::Space
                     | n @ HNode::Hss
                     | n @ HNode::HSkip(_)
                     | n @ HNode::HFil
                     | n @ HNode::HFill,
-                ) if target <= ET::Dim::default() => {
+                ) if target <= ET::Dim::default() && !nobreak => {
                     line.push(n);
                     break;
                 }
+                Some(HNode::Discretionary { pre, post, .. })
+                    if target <= ET::Dim::default() && !nobreak =>
+                {
+                    for node in pre.into_vec() {
+                        curr_height = curr_height.max(node.height());
+                        curr_depth = curr_depth.max(node.depth());
+                        line.push(node);
+                    }
+                    post_break = post.into_vec();
+                    break;
+                }
                 Some(node) => {
+                    nobreak = false;
                     if let Some((_, b)) = node.sourceref() {
                         currend = *b
                     }
@@ -1289,5 +1667,5 @@ pub fn split_paragraph_roughly<ET: EngineTypes>(
         }
         next_line!(false);
     }
-    ret
+    (ret, hyphenated)
 }
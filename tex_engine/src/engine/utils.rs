@@ -1,2 +1,8 @@
+pub mod approximations;
+pub mod debugger;
+pub mod hardening;
+pub mod limits;
 pub mod memory;
 pub mod outputs;
+pub mod progress;
+pub mod trace;
@@ -0,0 +1,114 @@
+/*! Places where the engine (or whatever consumes its output) knowingly deviates from what
+TeX would do - e.g. a primitive that is only stubbed, or a node that is dropped because
+there is no way to represent it. By default these are silently accepted; in strict mode (see
+[`Approximations::strict`]) they are recorded, and a run that took any of them fails with a
+[`StrictModeViolation`] listing all of them.
+*/
+use crate::engine::mouth::Mouth;
+use crate::engine::{EngineReferences, EngineTypes};
+use std::fmt::{Display, Formatter};
+
+/// The kind of an [`Approximation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApproximationKind {
+    /// a primitive that is accepted, but does nothing (or not the right thing)
+    StubbedPrimitive,
+    /// a paragraph that had to be reassembled from boxes rather than its lines
+    ParagraphSplit,
+    /// a character without known Unicode equivalent in its font's encoding
+    MissingGlyph,
+    /// a node that was dropped from the output
+    DroppedNode,
+}
+impl Display for ApproximationKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::StubbedPrimitive => "stubbed primitive",
+            Self::ParagraphSplit => "rough paragraph split",
+            Self::MissingGlyph => "guessed encoding",
+            Self::DroppedNode => "dropped node",
+        })
+    }
+}
+
+/// A single approximation taken during a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Approximation {
+    pub kind: ApproximationKind,
+    /// what was approximated, e.g. `\pdfsavepos`
+    pub what: String,
+    /// the position of the mouth at the time, as displayed by the filesystem
+    pub at: String,
+}
+impl Display for Approximation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} at {}", self.kind, self.what, self.at)
+    }
+}
+
+/// The approximations recorded during a run; only populated if [`strict`](Self::strict) is set.
+#[derive(Debug, Clone, Default)]
+pub struct Approximations {
+    pub strict: bool,
+    list: Vec<Approximation>,
+}
+impl Approximations {
+    pub fn new(strict: bool) -> Self {
+        Self {
+            strict,
+            list: Vec::new(),
+        }
+    }
+    /// The approximations recorded so far, in order.
+    pub fn list(&self) -> &[Approximation] {
+        &self.list
+    }
+    /// Records an approximation, unless the same one has already been recorded at the same
+    /// position (e.g. in a loop).
+    pub fn record(&mut self, approximation: Approximation) {
+        if self.strict && !self.list.contains(&approximation) {
+            self.list.push(approximation);
+        }
+    }
+    /// #### Errors
+    /// If any approximations have been recorded
+    pub fn check(&self) -> Result<(), StrictModeViolation> {
+        if self.list.is_empty() {
+            Ok(())
+        } else {
+            Err(StrictModeViolation(self.list.clone()))
+        }
+    }
+}
+
+/// Returned as [`TeXError::Strict`](crate::utils::errors::TeXError::Strict) at the end of a run in
+/// strict mode that took approximations.
+#[derive(Debug, Clone, thiserror::Error)]
+pub struct StrictModeViolation(pub Vec<Approximation>);
+impl Display for StrictModeViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "! Strict mode: {} approximation(s) taken", self.0.len())?;
+        for a in &self.0 {
+            write!(f, "\n  {a}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<ET: EngineTypes> EngineReferences<'_, ET> {
+    /// Records an [`Approximation`] at the current position of the mouth (if in strict mode).
+    pub fn approximation<S: Into<String>>(&mut self, kind: ApproximationKind, what: S) {
+        if self.aux.approximations.strict {
+            let at = self
+                .mouth
+                .current_sourceref()
+                .display(self.filesystem)
+                .to_string();
+            self.aux.approximations.record(Approximation {
+                kind,
+                what: what.into(),
+                at,
+            });
+        }
+    }
+}
@@ -0,0 +1,161 @@
+/*! A breakpoint/step debugger for the top-level loop, e.g. for a debugger frontend: the
+[`Debugger`] in [`EngineAux::debugger`](crate::engine::EngineAux::debugger) is consulted for
+every token the top-level loop processes (i.e. unexpandable commands, characters and macros
+occurring in the main vertical/horizontal lists - expansions *within* macro arguments or
+`\edef`s are not seen). If a [`Breakpoint`] is hit (or the debugger is stepping), the
+controller is called with the full [`EngineReferences`] - so it can e.g. take a
+[`StateSnapshot`](crate::engine::state::snapshot::StateSnapshot) or inspect the current lists -
+and decides how to continue via the returned [`DebugAction`].
+*/
+use crate::commands::{ResolvedToken, TeXCommand};
+use crate::engine::filesystem::FileSystem;
+use crate::engine::mouth::Mouth;
+use crate::engine::state::State;
+use crate::engine::{EngineReferences, EngineTypes};
+use crate::tex::catcodes::CommandCode;
+use crate::tex::characters::Character;
+use crate::tex::tokens::control_sequences::CSHandler;
+use crate::tex::tokens::{StandardToken, Token};
+use crate::utils::errors::{TeXError, TeXResult};
+
+/// A condition to pause on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// the first token processed in the given line of a file whose name ends with `file`
+    Line { file: String, line: usize },
+    /// a control sequence with the given name (without escape character), regardless of its
+    /// meaning - or an active character, if `name` is a single character
+    Token(String),
+    /// a control sequence that currently means the primitive with the given name (without
+    /// escape character), including `\let` copies
+    Primitive(String),
+}
+
+/// How to continue after a pause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugAction {
+    /// run until the next breakpoint
+    Continue,
+    /// pause again at the next token
+    Step,
+    /// stop the run with an [`EmergencyStop`](TeXError::EmergencyStop)
+    Abort,
+}
+
+/// Why the run was paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseReason {
+    /// the previous pause returned [`DebugAction::Step`]
+    Step,
+    /// the breakpoint with the given index in [`Debugger::breakpoints`] was hit
+    Breakpoint(usize),
+}
+
+/// Passed to the controller at every pause.
+#[derive(Debug, Clone)]
+pub struct DebugEvent {
+    pub reason: PauseReason,
+    /// the token about to be processed, as displayed by TeX
+    pub token: String,
+    /// file, line and column of the mouth
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+type Controller<ET> = dyn FnMut(&mut EngineReferences<ET>, &DebugEvent) -> DebugAction;
+
+/// See the [module documentation](self).
+pub struct Debugger<ET: EngineTypes> {
+    pub breakpoints: Vec<Breakpoint>,
+    stepping: bool,
+    last_line: Option<(String, usize)>,
+    controller: Box<Controller<ET>>,
+}
+impl<ET: EngineTypes> Debugger<ET> {
+    /// A debugger calling `controller` at every pause.
+    pub fn new<F: FnMut(&mut EngineReferences<ET>, &DebugEvent) -> DebugAction + 'static>(
+        controller: F,
+    ) -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            stepping: false,
+            last_line: None,
+            controller: Box::new(controller),
+        }
+    }
+    /// Pause at the very first token.
+    pub fn step(mut self) -> Self {
+        self.stepping = true;
+        self
+    }
+    pub fn with_breakpoint(mut self, breakpoint: Breakpoint) -> Self {
+        self.breakpoints.push(breakpoint);
+        self
+    }
+}
+
+impl<ET: EngineTypes> EngineReferences<'_, ET> {
+    /// Checks whether the [`Debugger`] (if any) should pause at `token`, and calls its controller
+    /// if so. Called by [`top_loop`](Self::top_loop).
+    /// #### Errors
+    /// If the controller aborts the run
+    pub fn debug_token(&mut self, token: &ET::Token) -> TeXResult<(), ET> {
+        let Some(mut debugger) = self.aux.debugger.take() else {
+            return Ok(());
+        };
+        let sr = self.mouth.current_sourceref();
+        let file = self.filesystem.ref_str(sr.file).to_string();
+        let new_line = !matches!(&debugger.last_line, Some((f, l)) if *l == sr.line && *f == file);
+        if new_line {
+            debugger.last_line = Some((file.clone(), sr.line));
+        }
+        let name = match token.to_enum() {
+            StandardToken::ControlSequence(cs) => {
+                Some(self.aux.memory.cs_interner().resolve(&cs).to_string())
+            }
+            StandardToken::Character(c, CommandCode::Active) => Some(c.display().to_string()),
+            _ => None,
+        };
+        let primitive = match self.resolve(token) {
+            ResolvedToken::Cmd(Some(TeXCommand::Primitive { name, .. })) => {
+                Some(name.display::<ET::Char>(None).to_string())
+            }
+            _ => None,
+        };
+        let hit = debugger.breakpoints.iter().position(|b| match b {
+            Breakpoint::Line { file: f, line } => new_line && *line == sr.line && file.ends_with(f),
+            Breakpoint::Token(n) => name.as_ref() == Some(n),
+            Breakpoint::Primitive(n) => primitive.as_ref() == Some(n),
+        });
+        let reason = match hit {
+            Some(i) => PauseReason::Breakpoint(i),
+            None if debugger.stepping => PauseReason::Step,
+            None => {
+                self.aux.debugger = Some(debugger);
+                return Ok(());
+            }
+        };
+        let event = DebugEvent {
+            reason,
+            token: token
+                .display(
+                    self.aux.memory.cs_interner(),
+                    self.state.get_catcode_scheme(),
+                    self.state.get_escape_char(),
+                )
+                .to_string(),
+            file,
+            line: sr.line,
+            column: sr.column,
+        };
+        let action = (debugger.controller)(self, &event);
+        debugger.stepping = action == DebugAction::Step;
+        self.aux.debugger = Some(debugger);
+        if action == DebugAction::Abort {
+            Err(TeXError::EmergencyStop)
+        } else {
+            Ok(())
+        }
+    }
+}
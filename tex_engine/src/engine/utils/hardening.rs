@@ -0,0 +1,54 @@
+/*! Places where the engine relies on an invariant that malformed input (or an earlier,
+recovered error) may still break - e.g. that [`add_node_h`](crate::engine::stomach::Stomach::add_node_h)
+is only called in horizontal mode. By default these panic; in hardened mode (see
+[`Hardening::enabled`]) they fail with an [`InvariantViolation`] instead, so that a process
+embedding the engine (e.g. a server) never aborts because of a document.
+*/
+
+/// Whether broken invariants panic or fail; in the latter case, also the first violation in a
+/// place that can not return an error, until it is [`check`](Self::check)ed.
+#[derive(Debug, Clone, Default)]
+pub struct Hardening {
+    pub enabled: bool,
+    pending: Option<InvariantViolation>,
+}
+impl Hardening {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            pending: None,
+        }
+    }
+    /// The error to return from a place that assumes `what`, which does not hold.
+    /// #### Panics
+    /// If not in hardened mode
+    pub fn violation(&self, what: &str) -> InvariantViolation {
+        if !self.enabled {
+            unreachable!("{what}")
+        }
+        InvariantViolation(what.to_string())
+    }
+    /// Like [`violation`](Self::violation), but for places that can not return an error: the
+    /// violation is returned by the next [`check`](Self::check) instead.
+    /// #### Panics
+    /// If not in hardened mode
+    pub fn defer(&mut self, what: &str) {
+        let violation = self.violation(what);
+        self.pending.get_or_insert(violation);
+    }
+    /// Called regularly, e.g. on every macro expansion.
+    /// #### Errors
+    /// If a violation was [`defer`](Self::defer)red since the last check
+    #[inline]
+    pub fn check(&mut self) -> Result<(), InvariantViolation> {
+        match self.pending.take() {
+            Some(v) => Err(v),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returned as [`TeXError::Internal`](crate::utils::errors::TeXError::Internal) in hardened mode.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("! Internal error: {0}")]
+pub struct InvariantViolation(pub String);
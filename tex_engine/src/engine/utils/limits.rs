@@ -0,0 +1,132 @@
+/*! Resource limits that abort a compilation instead of letting e.g. a runaway macro loop
+hang forever. All limits are disabled by default.
+*/
+use std::time::{Duration, Instant};
+
+/// Configurable resource limits; `None` means unlimited.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// maximum wall-clock time of a run
+    pub timeout: Option<Duration>,
+    /// maximum number of macro expansions
+    pub max_expansions: Option<u64>,
+    /// maximum number of nodes added to lists
+    pub max_nodes: Option<u64>,
+    /// maximum nesting depth of `\input` files
+    pub max_input_depth: Option<usize>,
+}
+
+/// The limit that was exceeded; returned as [`TeXError::LimitExceeded`](crate::utils::errors::TeXError::LimitExceeded).
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum LimitExceeded {
+    #[error("! TeX capacity exceeded: compilation took longer than {0:?}")]
+    Timeout(Duration),
+    #[error("! TeX capacity exceeded: more than {0} macro expansions")]
+    Expansions(u64),
+    #[error("! TeX capacity exceeded: more than {0} nodes")]
+    Nodes(u64),
+    #[error("! TeX capacity exceeded: \\input nesting deeper than {0}")]
+    InputDepth(usize),
+}
+
+/// Keeps track of the resources used so far and checks them against the [`Limits`].
+#[derive(Debug, Clone)]
+pub struct ResourceGuard {
+    pub limits: Limits,
+    start: Instant,
+    expansions: u64,
+    nodes: u64,
+    peak_list_depth: usize,
+}
+impl Default for ResourceGuard {
+    fn default() -> Self {
+        Self::new(Limits::default())
+    }
+}
+impl ResourceGuard {
+    /// Checking the clock on every expansion is too expensive; we do it every so many instead.
+    const TIME_CHECK_INTERVAL: u64 = 1024;
+
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            start: Instant::now(),
+            expansions: 0,
+            nodes: 0,
+            peak_list_depth: 0,
+        }
+    }
+    /// Resets the counters and restarts the clock.
+    pub fn reset(&mut self) {
+        self.start = Instant::now();
+        self.expansions = 0;
+        self.nodes = 0;
+        self.peak_list_depth = 0;
+    }
+    /// The number of macro expansions so far
+    pub fn expansions(&self) -> u64 {
+        self.expansions
+    }
+    /// The number of nodes created so far
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+    /// The largest number of simultaneously open node lists (boxes, paragraphs, formulas...) so
+    /// far
+    pub fn peak_list_depth(&self) -> usize {
+        self.peak_list_depth
+    }
+    /// To be called on every macro expansion. Also checks the timeout and the node count
+    /// (which is counted in places that can not fail; see [`node`](Self::node)).
+    /// #### Errors
+    /// If any limit was exceeded
+    #[inline]
+    pub fn expansion(&mut self) -> Result<(), LimitExceeded> {
+        self.expansions += 1;
+        if let Some(max) = self.limits.max_expansions {
+            if self.expansions > max {
+                return Err(LimitExceeded::Expansions(max));
+            }
+        }
+        if self.expansions % Self::TIME_CHECK_INTERVAL == 0 {
+            self.check_time()?;
+        }
+        self.check_nodes()
+    }
+    /// To be called whenever a node is added to a list.
+    #[inline]
+    pub fn node(&mut self) {
+        self.nodes += 1;
+    }
+    /// To be called whenever a node is added to a list, with the number of currently open
+    /// lists.
+    #[inline]
+    pub fn list_depth(&mut self, depth: usize) {
+        self.peak_list_depth = self.peak_list_depth.max(depth);
+    }
+    /// #### Errors
+    /// If the maximum number of nodes was exceeded
+    #[inline]
+    pub fn check_nodes(&self) -> Result<(), LimitExceeded> {
+        match self.limits.max_nodes {
+            Some(max) if self.nodes > max => Err(LimitExceeded::Nodes(max)),
+            _ => Ok(()),
+        }
+    }
+    /// #### Errors
+    /// If the timeout has passed
+    pub fn check_time(&self) -> Result<(), LimitExceeded> {
+        match self.limits.timeout {
+            Some(t) if self.start.elapsed() > t => Err(LimitExceeded::Timeout(t)),
+            _ => Ok(()),
+        }
+    }
+    /// #### Errors
+    /// If `depth` exceeds the maximum `\input` nesting depth
+    pub fn check_input_depth(&self, depth: usize) -> Result<(), LimitExceeded> {
+        match self.limits.max_input_depth {
+            Some(max) if depth > max => Err(LimitExceeded::InputDepth(max)),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+/*! Progress reporting for long-running compilations, e.g. to drive a progress bar in a GUI
+or to print periodic status lines in CI logs.
+*/
+use std::path::Path;
+
+/// A phase of a compilation reported to a [`ProgressHandler`].
+#[derive(Debug, Clone, Copy)]
+pub enum Progress<'a> {
+    /// An initialization file (e.g. `latex.ltx`) has been fully processed.
+    FormatLoaded(&'a str),
+    /// A file has been opened for input; `depth` is the number of files that were already open
+    /// (i.e. `0` for the main file).
+    FileOpened { path: &'a Path, depth: usize },
+    /// A page has been shipped out. `percent` estimates how much of the main file has been
+    /// processed, by byte offset of the current line; `None` if the main file could not be measured.
+    PageShipped { page: usize, percent: Option<u8> },
+}
+
+/// Receives [`Progress`] reports. Implemented for all suitable closures.
+pub trait ProgressHandler: Send {
+    fn progress(&mut self, progress: Progress<'_>);
+}
+impl<F: FnMut(Progress<'_>) + Send> ProgressHandler for F {
+    fn progress(&mut self, progress: Progress<'_>) {
+        self(progress)
+    }
+}
+
+/// Holds the (optional) [`ProgressHandler`] and the bookkeeping needed to compute page counts
+/// and percentages.
+#[derive(Default)]
+pub struct ProgressReporter {
+    handler: Option<Box<dyn ProgressHandler>>,
+    pages: usize,
+    /// byte offsets of the line starts of the main file, plus its total size as last entry
+    line_offsets: Vec<usize>,
+}
+impl ProgressReporter {
+    pub fn new(handler: Box<dyn ProgressHandler>) -> Self {
+        Self {
+            handler: Some(handler),
+            ..Self::default()
+        }
+    }
+    /// Removes and returns the handler, e.g. to reuse it for another run.
+    pub fn take_handler(&mut self) -> Option<Box<dyn ProgressHandler>> {
+        self.handler.take()
+    }
+    /// Whether a handler is installed; if not, all reports are no-ops.
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        self.handler.is_some()
+    }
+    /// Number of pages shipped out so far.
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+    pub fn format_loaded(&mut self, name: &str) {
+        if let Some(h) = &mut self.handler {
+            h.progress(Progress::FormatLoaded(name));
+        }
+    }
+    /// Reports an opened file; if it is the main file (`depth == 0`), its line offsets are
+    /// recorded for later percentage estimates.
+    pub fn file_opened(&mut self, path: &Path, depth: usize) {
+        let Some(h) = &mut self.handler else { return };
+        if depth == 0 {
+            self.pages = 0;
+            self.line_offsets = match std::fs::read(path) {
+                Ok(bytes) => std::iter::once(0)
+                    .chain(
+                        bytes
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, b)| **b == b'\n')
+                            .map(|(i, _)| i + 1),
+                    )
+                    .chain(std::iter::once(bytes.len()))
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+        }
+        h.progress(Progress::FileOpened { path, depth });
+    }
+    /// Reports a shipped page; `main_line` is the current (zero-based) line in the main file,
+    /// if it is still open.
+    pub fn page_shipped(&mut self, main_line: Option<usize>) {
+        let Some(h) = &mut self.handler else { return };
+        self.pages += 1;
+        let percent = match (main_line, self.line_offsets.last()) {
+            (_, Some(0)) | (_, None) => None,
+            (None, Some(_)) => Some(100),
+            (Some(line), Some(&total)) => {
+                let offset = self.line_offsets.get(line).copied().unwrap_or(total);
+                Some(((offset.min(total) * 100) / total) as u8)
+            }
+        };
+        h.progress(Progress::PageShipped {
+            page: self.pages,
+            percent,
+        });
+    }
+}
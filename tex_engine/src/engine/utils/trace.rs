@@ -0,0 +1,525 @@
+/*! A structured alternative to `\tracingmacros`: every macro expansion is recorded as a
+[`MacroEvent`] in a [`MacroTrace`], which can be queried or exported as JSON or in the
+[Chrome trace event format](https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU)
+(e.g. for `chrome://tracing` or Perfetto).
+
+Since the [`DefaultMouth`](crate::engine::mouth::DefaultMouth) does not keep track of
+expansion levels, the nesting depth is reconstructed from the number of pending tokens: an
+expansion is considered finished once all tokens it pushed have been consumed.
+
+Similarly, a [`ConditionalTrace`] records which branch every `\if...` took (cf.
+`\tracingifs`), e.g. to find out why a package's feature detection went a different way.
+
+A [`CatcodeTrace`] records every change of a category code together with the source position
+it took effect at, so that the regime in force for any part of the input can be reconstructed
+afterwards - in particular the [`VerbatimRegion`]s, in which `\` was not an escape character.
+*/
+use crate::engine::filesystem::SourceReference;
+use crate::tex::catcodes::{CategoryCode, CategoryCodeScheme};
+use crate::tex::characters::{Character, CharacterMap};
+use std::fmt::Write;
+
+/// A single macro expansion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroEvent {
+    /// the expanded control sequence or active character, as displayed by TeX
+    pub name: String,
+    /// the arguments the macro was applied to
+    pub arguments: Vec<String>,
+    /// the replacement text of the macro (with `#1`, `#2`... for the arguments)
+    pub expansion: String,
+    /// file, line and column of the mouth when the macro was expanded
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    /// the number of enclosing expansions that had not finished yet
+    pub depth: usize,
+}
+
+/// The recorded [`MacroEvent`]s, in the order they happened.
+#[derive(Debug, Clone, Default)]
+pub struct MacroTrace {
+    pub events: Vec<MacroEvent>,
+    /// if non-empty, only macros with one of these names (without escape character) are recorded;
+    /// the depth is tracked for all of them regardless
+    pub only: Vec<String>,
+    /// at most this many events are recorded (`0` for no limit)
+    pub max_events: usize,
+    /// pending tokens at the end of each unfinished expansion
+    levels: Vec<usize>,
+}
+impl MacroTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// A trace that only records expansions of the given macros.
+    pub fn restricted_to<I: IntoIterator<Item = String>>(names: I) -> Self {
+        Self {
+            only: names.into_iter().collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Determines the depth of an expansion starting with `pending` tokens left in the mouth.
+    pub fn enter(&mut self, pending: usize) -> usize {
+        while self.levels.last().is_some_and(|l| *l >= pending) {
+            self.levels.pop();
+        }
+        self.levels.len()
+    }
+    /// Marks the begin of an expansion, after its arguments have been read; `pending` is the
+    /// number of tokens left in the mouth before the replacement text is inserted.
+    pub fn open(&mut self, pending: usize) {
+        self.levels.push(pending);
+    }
+    /// Whether an expansion of `name` should be recorded.
+    pub fn wants(&self, name: &str) -> bool {
+        (self.max_events == 0 || self.events.len() < self.max_events)
+            && (self.only.is_empty() || self.only.iter().any(|n| n == name))
+    }
+    pub fn record(&mut self, event: MacroEvent) {
+        self.events.push(event);
+    }
+
+    /// All expansions of the macro with the given name (without escape character).
+    pub fn expansions_of<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a MacroEvent> {
+        self.events
+            .iter()
+            .filter(move |e| e.name.trim_start_matches('\\') == name)
+    }
+    /// All expansions that happened in the given line of a file whose name ends with `file`.
+    pub fn at_line<'a>(
+        &'a self,
+        file: &'a str,
+        line: usize,
+    ) -> impl Iterator<Item = &'a MacroEvent> {
+        self.events
+            .iter()
+            .filter(move |e| e.line == line && e.file.ends_with(file))
+    }
+    /// The index one past the last event that happened during the expansion of event `idx`,
+    /// i.e. the events `idx+1..end` are nested in it.
+    pub fn end_of(&self, idx: usize) -> usize {
+        let Some(depth) = self.events.get(idx).map(|e| e.depth) else {
+            return idx;
+        };
+        self.events[idx + 1..]
+            .iter()
+            .position(|e| e.depth <= depth)
+            .map_or(self.events.len(), |p| idx + 1 + p)
+    }
+    /// The events directly nested in the expansion of event `idx`.
+    pub fn children(&self, idx: usize) -> impl Iterator<Item = (usize, &MacroEvent)> {
+        let depth = self.events.get(idx).map_or(0, |e| e.depth + 1);
+        let end = self.end_of(idx);
+        self.events
+            .iter()
+            .enumerate()
+            .take(end)
+            .skip(idx + 1)
+            .filter(move |(_, e)| e.depth == depth)
+    }
+
+    /// Exports the trace as a JSON array of events.
+    pub fn to_json(&self) -> String {
+        let mut ret = String::from("[");
+        for (i, e) in self.events.iter().enumerate() {
+            if i > 0 {
+                ret.push(',');
+            }
+            ret.push_str("\n  {\"name\":");
+            json_string(&mut ret, &e.name);
+            ret.push_str(",\"arguments\":[");
+            for (j, a) in e.arguments.iter().enumerate() {
+                if j > 0 {
+                    ret.push(',');
+                }
+                json_string(&mut ret, a);
+            }
+            ret.push_str("],\"expansion\":");
+            json_string(&mut ret, &e.expansion);
+            ret.push_str(",\"file\":");
+            json_string(&mut ret, &e.file);
+            let _ = write!(
+                ret,
+                ",\"line\":{},\"column\":{},\"depth\":{}}}",
+                e.line, e.column, e.depth
+            );
+        }
+        ret.push_str("\n]\n");
+        ret
+    }
+
+    /// Exports the trace in the Chrome trace event format. There is no meaningful time in a
+    /// macro expansion, so every expansion is one "microsecond" long plus the length of all
+    /// expansions nested in it, which yields a flame graph of the expansion structure.
+    pub fn to_chrome_trace(&self) -> String {
+        let mut ret = String::from("{\"traceEvents\":[");
+        for (i, e) in self.events.iter().enumerate() {
+            if i > 0 {
+                ret.push(',');
+            }
+            ret.push_str("\n  {\"name\":");
+            json_string(&mut ret, &e.name);
+            let _ = write!(
+                ret,
+                ",\"cat\":\"macro\",\"ph\":\"X\",\"ts\":{i},\"dur\":{},\"pid\":1,\"tid\":1,\"args\":{{\"source\":",
+                self.end_of(i) - i
+            );
+            json_string(&mut ret, &format!("{}:{}:{}", e.file, e.line, e.column));
+            for (j, a) in e.arguments.iter().enumerate() {
+                let _ = write!(ret, ",\"#{}\":", j + 1);
+                json_string(&mut ret, a);
+            }
+            ret.push_str(",\"expansion\":");
+            json_string(&mut ret, &e.expansion);
+            ret.push_str("}}");
+        }
+        ret.push_str("\n]}\n");
+        ret
+    }
+}
+
+/// The branch a conditional took.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Branch {
+    /// the condition was true
+    True,
+    /// the condition was false and the `\else` branch was entered
+    Else,
+    /// the condition was false and there was no `\else` branch
+    Skipped,
+    /// the case selected by `\ifcase`
+    Case(i64),
+}
+impl Branch {
+    fn as_str(&self) -> std::borrow::Cow<'static, str> {
+        match self {
+            Self::True => "true".into(),
+            Self::Else => "else".into(),
+            Self::Skipped => "skipped".into(),
+            Self::Case(i) => i.to_string().into(),
+        }
+    }
+}
+
+/// A single evaluated conditional.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConditionalEvent {
+    /// the conditional, as displayed by TeX (including `\unless`, if present)
+    pub name: String,
+    pub branch: Branch,
+    /// file, line and column of the mouth when the conditional was entered
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    /// the nesting level of the conditional, starting at 1
+    pub level: usize,
+}
+
+/// The recorded [`ConditionalEvent`]s, in the order they happened.
+#[derive(Debug, Clone, Default)]
+pub struct ConditionalTrace {
+    pub events: Vec<ConditionalEvent>,
+    /// at most this many events are recorded (`0` for no limit)
+    pub max_events: usize,
+}
+impl ConditionalTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Whether another event should be recorded.
+    pub fn wants(&self) -> bool {
+        self.max_events == 0 || self.events.len() < self.max_events
+    }
+    pub fn record(&mut self, event: ConditionalEvent) {
+        self.events.push(event);
+    }
+    /// All evaluations of the given conditional (without escape character).
+    pub fn evaluations_of<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a ConditionalEvent> {
+        self.events
+            .iter()
+            .filter(move |e| e.name.trim_start_matches('\\') == name)
+    }
+    /// All conditionals evaluated in the given line of a file whose name ends with `file`.
+    pub fn at_line<'a>(
+        &'a self,
+        file: &'a str,
+        line: usize,
+    ) -> impl Iterator<Item = &'a ConditionalEvent> {
+        self.events
+            .iter()
+            .filter(move |e| e.line == line && e.file.ends_with(file))
+    }
+
+    /// Exports the trace as a JSON array of events.
+    pub fn to_json(&self) -> String {
+        let mut ret = String::from("[");
+        for (i, e) in self.events.iter().enumerate() {
+            if i > 0 {
+                ret.push(',');
+            }
+            ret.push_str("\n  {\"name\":");
+            json_string(&mut ret, &e.name);
+            ret.push_str(",\"branch\":");
+            json_string(&mut ret, &e.branch.as_str());
+            ret.push_str(",\"file\":");
+            json_string(&mut ret, &e.file);
+            let _ = write!(
+                ret,
+                ",\"line\":{},\"column\":{},\"level\":{}}}",
+                e.line, e.column, e.level
+            );
+        }
+        ret.push_str("\n]\n");
+        ret
+    }
+}
+
+/// Why a category code changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatcodeCause {
+    /// a `\catcode` assignment
+    Assignment,
+    /// the end of the group the code was assigned in
+    GroupEnd,
+    /// switching to another `\catcodetable`
+    Table,
+}
+impl CatcodeCause {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Assignment => "assignment",
+            Self::GroupEnd => "group end",
+            Self::Table => "table",
+        }
+    }
+}
+
+/// A single change of a category code. `F` identifies the file; the trace kept in the
+/// [`EngineAux`](crate::engine::EngineAux) uses the
+/// [`SourceRefID`](crate::engine::filesystem::File::SourceRefID)s of the engine, which
+/// [`CatcodeTrace::map_files`] turns into file names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatcodeEvent<F = String> {
+    /// the character code
+    pub char: u64,
+    /// the new category code
+    pub catcode: CategoryCode,
+    pub cause: CatcodeCause,
+    /// file, line and column of the mouth when the change happened, i.e. where the new
+    /// category code starts to apply
+    pub file: F,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A stretch of the input read while `\` did not have category code
+/// [`Escape`](CategoryCode::Escape), e.g. the body of a `verbatim` environment or the argument
+/// of `\verb`. Both ends are positions in `file`; if the regime ended in another file, the region
+/// extends to the end of `file` (`end` is `None`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerbatimRegion {
+    pub file: String,
+    /// line and column
+    pub start: (usize, usize),
+    /// line and column
+    pub end: Option<(usize, usize)>,
+}
+impl VerbatimRegion {
+    /// Whether the given position of a file whose name ends with `file` lies in this region.
+    pub fn contains(&self, file: &str, line: usize, column: usize) -> bool {
+        self.file.ends_with(file)
+            && self.start <= (line, column)
+            && self.end.is_none_or(|e| (line, column) < e)
+    }
+}
+
+/// The recorded [`CatcodeEvent`]s, in the order they happened.
+#[derive(Debug, Clone)]
+pub struct CatcodeTrace<F = String> {
+    pub events: Vec<CatcodeEvent<F>>,
+    /// at most this many events are recorded (`0` for no limit)
+    pub max_events: usize,
+}
+impl<F> Default for CatcodeTrace<F> {
+    fn default() -> Self {
+        Self {
+            events: Vec::new(),
+            max_events: 0,
+        }
+    }
+}
+impl<F> CatcodeTrace<F> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Whether another event should be recorded.
+    pub fn wants(&self) -> bool {
+        self.max_events == 0 || self.events.len() < self.max_events
+    }
+    pub fn record(&mut self, event: CatcodeEvent<F>) {
+        if self.wants() {
+            self.events.push(event);
+        }
+    }
+    /// Replaces the file identifiers of all events, e.g. by their names.
+    pub fn map_files<G, M: FnMut(F) -> G>(self, mut f: M) -> CatcodeTrace<G> {
+        CatcodeTrace {
+            events: self
+                .events
+                .into_iter()
+                .map(|e| CatcodeEvent {
+                    char: e.char,
+                    catcode: e.catcode,
+                    cause: e.cause,
+                    file: f(e.file),
+                    line: e.line,
+                    column: e.column,
+                })
+                .collect(),
+            max_events: self.max_events,
+        }
+    }
+}
+impl<F: Copy + Default> CatcodeTrace<F> {
+    /// Records that `char` has category code `catcode` from `at` on.
+    pub fn changed(
+        &mut self,
+        char: u64,
+        catcode: CategoryCode,
+        cause: CatcodeCause,
+        at: SourceReference<F>,
+    ) {
+        self.record(CatcodeEvent {
+            char,
+            catcode,
+            cause,
+            file: at.file,
+            line: at.line,
+            column: at.column,
+        })
+    }
+    /// Records the differences between two [`CategoryCodeScheme`]s (for the first 256
+    /// characters), e.g. after switching the `\catcodetable`.
+    pub fn changed_scheme<C: Character>(
+        &mut self,
+        old: &CategoryCodeScheme<C>,
+        new: &CategoryCodeScheme<C>,
+        cause: CatcodeCause,
+        at: SourceReference<F>,
+    ) {
+        for c in 0..=255u8 {
+            let c = C::from(c);
+            if old.get(c) != new.get(c) {
+                self.changed(c.into(), *new.get(c), cause, at);
+            }
+        }
+    }
+}
+impl CatcodeTrace {
+    /// All changes of the category code of `char`.
+    pub fn changes_of(&self, char: u64) -> impl Iterator<Item = &CatcodeEvent> {
+        self.events.iter().filter(move |e| e.char == char)
+    }
+    /// All changes in the given lines (inclusive) of a file whose name ends with `file`.
+    pub fn in_lines<'a>(
+        &'a self,
+        file: &'a str,
+        lines: std::ops::RangeInclusive<usize>,
+    ) -> impl Iterator<Item = &'a CatcodeEvent> {
+        self.events
+            .iter()
+            .filter(move |e| lines.contains(&e.line) && e.file.ends_with(file))
+    }
+    /// The stretches of the input read while `\` was not an escape character, reconstructed
+    /// from the changes of its category code (starting out as [`Escape`](CategoryCode::Escape)).
+    pub fn verbatim_regions(&self) -> Vec<VerbatimRegion> {
+        let mut ret = Vec::new();
+        let mut open: Option<&CatcodeEvent> = None;
+        for e in self.changes_of(b'\\' as u64) {
+            match (open, e.catcode) {
+                (None, cc) if cc != CategoryCode::Escape => open = Some(e),
+                (Some(start), CategoryCode::Escape) => {
+                    ret.push(VerbatimRegion {
+                        file: start.file.clone(),
+                        start: (start.line, start.column),
+                        end: (e.file == start.file).then_some((e.line, e.column)),
+                    });
+                    open = None;
+                }
+                _ => (),
+            }
+        }
+        if let Some(start) = open {
+            ret.push(VerbatimRegion {
+                file: start.file.clone(),
+                start: (start.line, start.column),
+                end: None,
+            });
+        }
+        ret
+    }
+    /// Whether the given position of a file whose name ends with `file` was read verbatim (see
+    /// [`verbatim_regions`](Self::verbatim_regions)).
+    pub fn is_verbatim(&self, file: &str, line: usize, column: usize) -> bool {
+        self.verbatim_regions()
+            .iter()
+            .any(|r| r.contains(file, line, column))
+    }
+
+    /// Exports the trace as a JSON object with the array of `events` and the
+    /// `verbatim` regions derived from them.
+    pub fn to_json(&self) -> String {
+        let mut ret = String::from("{\"events\":[");
+        for (i, e) in self.events.iter().enumerate() {
+            if i > 0 {
+                ret.push(',');
+            }
+            let cc: u8 = e.catcode.into();
+            let _ = write!(ret, "\n  {{\"char\":{},\"catcode\":{cc},\"cause\":", e.char);
+            json_string(&mut ret, e.cause.as_str());
+            ret.push_str(",\"file\":");
+            json_string(&mut ret, &e.file);
+            let _ = write!(ret, ",\"line\":{},\"column\":{}}}", e.line, e.column);
+        }
+        ret.push_str("\n],\"verbatim\":[");
+        for (i, r) in self.verbatim_regions().iter().enumerate() {
+            if i > 0 {
+                ret.push(',');
+            }
+            ret.push_str("\n  {\"file\":");
+            json_string(&mut ret, &r.file);
+            let _ = write!(ret, ",\"start\":[{},{}],\"end\":", r.start.0, r.start.1);
+            match r.end {
+                Some((l, c)) => {
+                    let _ = write!(ret, "[{l},{c}]}}");
+                }
+                None => ret.push_str("null}"),
+            }
+        }
+        ret.push_str("\n]}\n");
+        ret
+    }
+}
+
+fn json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
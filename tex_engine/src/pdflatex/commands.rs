@@ -2,8 +2,8 @@ use md5::Digest;
 
 use super::nodes::{
     ColorStackAction, NumOrName, PDFAnnot, PDFBoxSpec, PDFCatalog, PDFColor, PDFDest, PDFExtension,
-    PDFImage, PDFLiteral, PDFLiteralOption, PDFNode, PDFObj, PDFOutline, PDFStartLink, PDFXForm,
-    PDFXImage,
+    PDFImage, PDFImageInfo, PDFLiteral, PDFLiteralOption, PDFNode, PDFObj, PDFOutline, PDFRandom,
+    PDFStartLink, PDFXForm, PDFXImage,
 };
 use crate::commands::primitives::*;
 use crate::commands::CommandScope;
@@ -21,7 +21,7 @@ use crate::tex::nodes::horizontal::HNode;
 use crate::tex::nodes::math::MathNode;
 use crate::tex::nodes::vertical::VNode;
 use crate::tex::nodes::WhatsitFunction;
-use crate::tex::numerics::NumSet;
+use crate::tex::numerics::{NumSet, TeXDimen};
 use crate::tex::tokens::token_lists::Otherize;
 use crate::tex::tokens::{StandardToken, Token};
 use crate::utils::errors::{TeXError, TeXResult};
@@ -101,21 +101,27 @@ where
         Some(b"set") => {
             let mut color = String::new();
             engine.read_braced_string(true, true, &tk, &mut color)?;
-            let color = PDFColor::parse(color);
+            let action = match gs_opacity(&color) {
+                Some(o) => ColorStackAction::SetOpacity(index, o),
+                None => ColorStackAction::Set(index, PDFColor::parse(color)),
+            };
             crate::add_node!(ET::Stomach;engine,
-                                     VNode::Custom(PDFNode::Color(ColorStackAction::Set(index,color)).into()),
-                                     HNode::Custom(PDFNode::Color(ColorStackAction::Set(index,color)).into()),
-                                     MathNode::Custom(PDFNode::Color(ColorStackAction::Set(index,color)).into())
+                                     VNode::Custom(PDFNode::Color(action).into()),
+                                     HNode::Custom(PDFNode::Color(action).into()),
+                                     MathNode::Custom(PDFNode::Color(action).into())
             )
         }
         Some(b"push") => {
             let mut color = String::new();
             engine.read_braced_string(true, true, &tk, &mut color)?;
-            let color = PDFColor::parse(color);
+            let action = match gs_opacity(&color) {
+                Some(o) => ColorStackAction::PushOpacity(index, o),
+                None => ColorStackAction::Push(index, PDFColor::parse(color)),
+            };
             crate::add_node!(ET::Stomach;engine,
-                                     VNode::Custom(PDFNode::Color(ColorStackAction::Push(index,color)).into()),
-                                     HNode::Custom(PDFNode::Color(ColorStackAction::Push(index,color)).into()),
-                                     MathNode::Custom(PDFNode::Color(ColorStackAction::Push(index,color)).into())
+                                     VNode::Custom(PDFNode::Color(action).into()),
+                                     HNode::Custom(PDFNode::Color(action).into()),
+                                     MathNode::Custom(PDFNode::Color(action).into())
             )
         }
         _ => TeXError::missing_keyword(
@@ -128,6 +134,12 @@ where
     Ok(())
 }
 
+/// The opacity set by a color stack entry of the form `/Name gs`.
+fn gs_opacity(s: &str) -> Option<f32> {
+    let name = s.trim().strip_suffix("gs")?.trim_end().strip_prefix('/')?;
+    super::nodes::extgstate_opacity(name)
+}
+
 pub fn pdfcolorstackinit<ET: EngineTypes>(
     engine: &mut EngineReferences<ET>,
     tk: ET::Token,
@@ -260,6 +272,45 @@ where
     Ok(())
 }
 
+/// Inserts a [`PDFNode::SavePos`]; its position is determined at shipout, where it becomes
+/// `\pdflastxpos` and `\pdflastypos` for the `\write`s that follow it.
+pub fn pdfsavepos<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<(), ET>
+where
+    ET::Extension: PDFExtension<ET>,
+    ET::CustomNode: From<PDFNode<ET>>,
+{
+    let positions = engine.aux.extension.positions();
+    let id = positions.count;
+    positions.count += 1;
+    crate::add_node!(ET::Stomach;engine,
+        VNode::Custom(PDFNode::SavePos(id).into()),
+        HNode::Custom(PDFNode::SavePos(id).into()),
+        MathNode::Custom(PDFNode::SavePos(id).into())
+    );
+    Ok(())
+}
+pub fn pdflastxpos<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<ET::Int, ET>
+where
+    ET::Extension: PDFExtension<ET>,
+{
+    Ok(engine.aux.extension.positions().last.0.into())
+}
+pub fn pdflastypos<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<ET::Int, ET>
+where
+    ET::Extension: PDFExtension<ET>,
+{
+    Ok(engine.aux.extension.positions().last.1.into())
+}
+
 pub fn pdfsetmatrix<ET: EngineTypes>(
     engine: &mut EngineReferences<ET>,
     tk: ET::Token,
@@ -460,26 +511,37 @@ pub fn rightmarginkern<ET: EngineTypes>(
     Ok(())
 }
 
-pub fn pdfcreationdate<ET: EngineTypes>(
-    engine: &mut EngineReferences<ET>,
-    exp: &mut Vec<ET::Token>,
-    _tk: ET::Token,
-) -> TeXResult<(), ET> {
+/// Writes a date in the PDF format `D:YYYYMMDDHHmmSS+HH'mm'` (with `Z` for UTC).
+fn write_pdf_date<W: Write>(
+    w: &mut W,
+    dt: chrono::DateTime<chrono::FixedOffset>,
+) -> std::fmt::Result {
     use chrono::{Datelike, Timelike};
-    let dt = engine.aux.start_time;
-    let mut f = |t| exp.push(t);
-    let mut tk = Otherize::new(&mut f);
     write!(
-        tk,
-        "D:{}{:02}{:02}{:02}{:02}{:02}{}'",
+        w,
+        "D:{}{:02}{:02}{:02}{:02}{:02}",
         dt.year(),
         dt.month(),
         dt.day(),
         dt.hour(),
         dt.minute(),
-        dt.second(),
-        dt.offset().to_string().replace(':', "'")
+        dt.second()
     )?;
+    match dt.offset().local_minus_utc() {
+        0 => w.write_char('Z'),
+        _ => write!(w, "{}'", dt.offset().to_string().replace(':', "'")),
+    }
+}
+
+pub fn pdfcreationdate<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    exp: &mut Vec<ET::Token>,
+    _tk: ET::Token,
+) -> TeXResult<(), ET> {
+    let dt = engine.aux.start_time;
+    let mut f = |t| exp.push(t);
+    let mut tk = Otherize::new(&mut f);
+    write_pdf_date(&mut tk, dt)?;
     Ok(())
 }
 
@@ -488,26 +550,17 @@ pub fn pdffilemoddate<ET: EngineTypes>(
     exp: &mut Vec<ET::Token>,
     tk: ET::Token,
 ) -> TeXResult<(), ET> {
-    use chrono::{Datelike, Timelike};
     let mut filename = engine.aux.memory.get_string();
     engine.read_braced_string(true, false, &tk, &mut filename)?;
     let f = engine.filesystem.get(&filename);
     engine.aux.memory.return_string(filename);
     let path = f.path();
     if let Ok(Ok(st)) = std::fs::metadata(path).map(|md| md.modified()) {
-        let dt: chrono::DateTime<chrono::Local> = chrono::DateTime::from(st);
         let mut f = |t| exp.push(t);
         let mut tk = Otherize::new(&mut f);
-        write!(
-            tk,
-            "D:{}{:02}{:02}{:02}{:02}{:02}{}'",
-            dt.year(),
-            dt.month(),
-            dt.day(),
-            dt.hour(),
-            dt.minute(),
-            dt.second(),
-            dt.offset().to_string().replace(':', "'")
+        write_pdf_date(
+            &mut tk,
+            chrono::DateTime::<chrono::Local>::from(st).fixed_offset(),
         )?;
     }
     Ok(())
@@ -675,6 +728,42 @@ pub fn pdffilesize<ET: EngineTypes>(
     Ok(())
 }
 
+/// `\pdffiledump [offset n] [length n] {file}`: the given bytes of the file in hexadecimal;
+/// empty if `length` is missing or the file does not exist.
+pub fn pdffiledump<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    exp: &mut Vec<ET::Token>,
+    tk: ET::Token,
+) -> TeXResult<(), ET> {
+    let offset: i64 = if engine.read_keyword(b"offset")? {
+        engine.read_int(false, &tk)?.into()
+    } else {
+        0
+    };
+    let length: i64 = if engine.read_keyword(b"length")? {
+        engine.read_int(false, &tk)?.into()
+    } else {
+        0
+    };
+    let mut filename = engine.aux.memory.get_string();
+    engine.read_braced_string(false, true, &tk, &mut filename)?;
+    let file = engine.filesystem.get(&filename);
+    engine.aux.memory.return_string(filename);
+    if length <= 0 || offset < 0 {
+        return Ok(());
+    }
+    if let Ok(bytes) = std::fs::read(file.path()) {
+        let start = (offset as usize).min(bytes.len());
+        let end = start.saturating_add(length as usize).min(bytes.len());
+        let mut f = |t| exp.push(t);
+        let mut t = Otherize::new(&mut f);
+        for b in &bytes[start..end] {
+            write!(t, "{b:02X}")?;
+        }
+    }
+    Ok(())
+}
+
 pub fn pdfglyphtounicode<ET: EngineTypes>(
     engine: &mut EngineReferences<ET>,
     tk: ET::Token,
@@ -693,11 +782,12 @@ where
     ET::Extension: PDFExtension<ET>,
 {
     let icase = engine.read_keyword(b"icase")?;
-    let _subcount = if engine.read_keyword(b"subcount")? {
+    // the maximal number of entries (the whole match and its groups) to record
+    let subcount: i64 = if engine.read_keyword(b"subcount")? {
         engine.read_int(false, &tk)?.into()
     } else {
         -1
-    }; // TODO use subcount
+    };
     let mut pattern_string = String::new();
     let mut target_string = String::new();
     if icase {
@@ -718,7 +808,11 @@ where
             Some(capture) => {
                 let cap = capture.get(0).unwrap();
                 pdfmatches.push(format!("{}->{}", cap.start(), cap.as_str()));
-                for cap in capture.iter().skip(1) {
+                let groups = match subcount {
+                    n if n < 0 => usize::MAX,
+                    n => (n as usize).saturating_sub(1),
+                };
+                for cap in capture.iter().skip(1).take(groups) {
                     match cap {
                         None => pdfmatches.push("-1".to_string()),
                         Some(cap) => {
@@ -770,9 +864,11 @@ where
         let mut filename = String::new();
         engine.read_braced_string(true, true, &tk, &mut filename)?;
         let file = engine.filesystem.get(&filename);
-        let mut t = Otherize::new(&mut f);
-        for i in file.md5() {
-            write!(t, "{i:02X}")?;
+        if file.exists() {
+            let mut t = Otherize::new(&mut f);
+            for i in file.md5() {
+                write!(t, "{i:02X}")?;
+            }
         }
     } else {
         let mut str = String::new();
@@ -1135,8 +1231,13 @@ where
     engine.read_braced_string(true, true, &tk, &mut filename)?;
     let file = engine.filesystem.get(&filename);
 
-    let img = if file.path().extension().is_some_and(|ext| ext == "pdf") {
-        super::nodes::pdf_as_image(file.path(), &mut engine.aux.extension)
+    let (img, info) = if file.path().extension().is_some_and(|ext| ext == "pdf") {
+        super::nodes::pdf_as_image(
+            file.path(),
+            &mut engine.aux.extension,
+            page.unwrap_or(1),
+            boxspec,
+        )
     } else {
         let Ok(img) = image::ImageReader::open(file.path()) else {
             engine.general_error("Unknown type of image".into())?;
@@ -1146,8 +1247,21 @@ where
             engine.general_error("Unknown type of image".into())?;
             return Ok(());
         };
-        PDFImage::Img(img)
+        let resolution = engine
+            .state
+            .primitives()
+            .get_name("pdfimageresolution")
+            .map_or(0, |id| engine.state.get_primitive_int(id).into() as i32);
+        let info = PDFImageInfo::bitmap(file.path(), img.width(), img.height(), resolution);
+        (PDFImage::Img(img), Some(info))
     };
+    let info = info.unwrap_or_else(|| {
+        let (w, h) = (img.width() as i32, img.height() as i32);
+        PDFImageInfo {
+            bbox: [0, 0, 65536 * w, 65536 * h],
+            pages: 1,
+        }
+    });
     let img = PDFXImage {
         width,
         height,
@@ -1158,6 +1272,7 @@ where
         boxspec,
         img,
         filepath: file.path().to_path_buf(),
+        info,
     };
     engine.aux.extension.pdfximages().push(img);
     Ok(())
@@ -1253,6 +1368,49 @@ where
     Ok((engine.aux.extension.pdfximages().len() as i32 - 1).into())
 }
 
+pub fn pdflastximagepages<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<ET::Int, ET>
+where
+    ET::Extension: PDFExtension<ET>,
+{
+    let pages = engine
+        .aux
+        .extension
+        .pdfximages()
+        .last()
+        .map_or(0, |img| img.info.pages);
+    Ok((pages as i32).into())
+}
+
+/// `\pdfximagebbox <image> <n>`: the `n`th coordinate (1-4: lower left x and y, upper right
+/// x and y) of the bounding box of an image.
+pub fn pdfximagebbox<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    exp: &mut Vec<ET::Token>,
+    tk: ET::Token,
+) -> TeXResult<(), ET>
+where
+    ET::Extension: PDFExtension<ET>,
+{
+    let num: i64 = engine.read_int(false, &tk)?.into();
+    let idx: i64 = engine.read_int(false, &tk)?.into();
+    let Some(info) = usize::try_from(num)
+        .ok()
+        .and_then(|n| engine.aux.extension.pdfximages().get(n))
+        .map(|img| img.info)
+    else {
+        return engine.general_error("pdfTeX error (ext1): invalid image number.".to_string());
+    };
+    let Some(v) = usize::try_from(idx - 1).ok().and_then(|i| info.bbox.get(i)) else {
+        return engine.general_error("pdfTeX error (ext1): invalid parameter.".to_string());
+    };
+    let mut f = |t| exp.push(t);
+    write!(Otherize::new(&mut f), "{}", ET::Dim::from_sp(*v))?;
+    Ok(())
+}
+
 pub fn pdfliteral<ET: EngineTypes>(
     engine: &mut EngineReferences<ET>,
     tk: ET::Token,
@@ -1305,6 +1463,77 @@ pub fn pdfstrcmp<ET: EngineTypes>(
     Ok(())
 }
 
+/// The time since the start of the run (or the last `\pdfresettimer`) in scaled seconds, i.e.
+/// in units of 1/65536 seconds; at most `2^31-1`.
+pub fn pdfelapsedtime<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<ET::Int, ET>
+where
+    ET::Extension: PDFExtension<ET>,
+{
+    let secs = engine.aux.extension.elapsed().elapsed().as_secs_f64();
+    let scaled = ((secs * 65536.0) as i64).min(i32::MAX as i64) as i32;
+    Ok(<ET::Num as NumSet>::Int::from(scaled))
+}
+pub fn pdfresettimer<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<(), ET>
+where
+    ET::Extension: PDFExtension<ET>,
+{
+    *engine.aux.extension.elapsed() = std::time::Instant::now();
+    Ok(())
+}
+
+pub fn pdfrandomseed<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<ET::Int, ET>
+where
+    ET::Extension: PDFExtension<ET>,
+{
+    Ok(<ET::Num as NumSet>::Int::from(
+        engine.aux.extension.random().seed(),
+    ))
+}
+pub fn pdfsetrandomseed<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: ET::Token,
+) -> TeXResult<(), ET>
+where
+    ET::Extension: PDFExtension<ET>,
+{
+    let seed: i64 = engine.read_int(false, &tk)?.into();
+    *engine.aux.extension.random() = PDFRandom::new(seed as i32);
+    Ok(())
+}
+pub fn pdfnormaldeviate<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    _tk: ET::Token,
+) -> TeXResult<ET::Int, ET>
+where
+    ET::Extension: PDFExtension<ET>,
+{
+    Ok(<ET::Num as NumSet>::Int::from(
+        engine.aux.extension.random().normal(),
+    ))
+}
+pub fn pdfuniformdeviate<ET: EngineTypes>(
+    engine: &mut EngineReferences<ET>,
+    tk: ET::Token,
+) -> TeXResult<ET::Int, ET>
+where
+    ET::Extension: PDFExtension<ET>,
+{
+    let n: i64 = engine.read_int(false, &tk)?.into();
+    let n = n.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+    Ok(<ET::Num as NumSet>::Int::from(
+        engine.aux.extension.random().uniform(n),
+    ))
+}
+
 pub fn pdffontsize<ET: EngineTypes>(
     engine: &mut EngineReferences<ET>,
     exp: &mut Vec<ET::Token>,
@@ -1344,6 +1573,7 @@ const PRIMITIVE_INTS: &[&str] = &[
     "tracingstacklevels",
     "pdfprependkern",
     "pdfappendkern",
+    "pdfimageresolution",
 ];
 
 const PRIMITIVE_DIMS: &[&str] = &[
@@ -1372,6 +1602,7 @@ where
     register_expandable(engine, "pdfescapehex", pdfescapehex);
     register_expandable(engine, "pdfunescapehex", pdfunescapehex);
     register_expandable(engine, "pdffilesize", pdffilesize);
+    register_expandable(engine, "pdffiledump", pdffiledump);
     register_expandable(engine, "pdfmatch", pdfmatch);
     register_expandable(engine, "pdflastmatch", pdflastmatch);
     register_expandable(engine, "pdfstrcmp", pdfstrcmp);
@@ -1383,6 +1614,10 @@ where
     register_int(engine, "pdfmajorversion", pdfmajorversion, None);
     register_int(engine, "pdfshellescape", pdfshellescape, None);
     register_int(engine, "pdfcolorstackinit", pdfcolorstackinit, None);
+    register_int(engine, "pdfelapsedtime", pdfelapsedtime, None);
+    register_int(engine, "pdfrandomseed", pdfrandomseed, None);
+    register_int(engine, "pdfuniformdeviate", pdfuniformdeviate, None);
+    register_int(engine, "pdfnormaldeviate", pdfnormaldeviate, None);
     register_int(engine, "lpcode", lpcode_get, Some(lpcode_set));
     register_int(engine, "rpcode", rpcode_get, Some(rpcode_set));
 
@@ -1410,6 +1645,13 @@ where
     register_unexpandable(engine, "pdfrestore", CommandScope::Any, pdfrestore);
     register_unexpandable(engine, "pdfsetmatrix", CommandScope::Any, pdfsetmatrix);
     register_unexpandable(engine, "pdfannot", CommandScope::Any, pdfannot);
+    register_unexpandable(engine, "pdfresettimer", CommandScope::Any, pdfresettimer);
+    register_unexpandable(
+        engine,
+        "pdfsetrandomseed",
+        CommandScope::Any,
+        pdfsetrandomseed,
+    );
 
     register_whatsit(engine, "pdfobj", pdfobj, pdfobj_immediate, None);
     register_whatsit(engine, "pdfxform", pdfxform, pdfxform_immediate, None);
@@ -1441,27 +1683,17 @@ where
     register_unexpandable(engine, "pdfrefximage", CommandScope::Any, pdfrefximage);
     register_simple_expandable(engine, "pdfprimitive", pdfprimitive);
     register_int(engine, "pdflastximage", pdflastximage, None);
+    register_int(engine, "pdflastximagepages", pdflastximagepages, None);
+    register_expandable(engine, "pdfximagebbox", pdfximagebbox);
     register_int(engine, "pdflastannot", pdflastannot, None);
-    register_simple_expandable(engine, "pdfsavepos", |_, _| Ok(()));
-    register_int(
-        engine,
-        "pdflastxpos",
-        |_, _| Ok(<E::Types as EngineTypes>::Int::default()),
-        None,
-    );
-    register_int(
-        engine,
-        "pdflastypos",
-        |_, _| Ok(<E::Types as EngineTypes>::Int::default()),
-        None,
-    );
+    register_unexpandable(engine, "pdfsavepos", CommandScope::Any, pdfsavepos);
+    register_int(engine, "pdflastxpos", pdflastxpos, None);
+    register_int(engine, "pdflastypos", pdflastypos, None);
 
     register_primitive_int(engine, PRIMITIVE_INTS);
     register_primitive_dim(engine, PRIMITIVE_DIMS);
     register_primitive_toks(engine, PRIMITIVE_TOKS);
 
-    cmtodos!(engine, pdfelapsedtime, pdfresettimer);
-
     cmtodo!(engine, efcode);
     cmtodo!(engine, knaccode);
     cmtodo!(engine, knbccode);
@@ -1472,7 +1704,6 @@ where
     cmtodo!(engine, pdfimageapplygamma);
     cmtodo!(engine, pdfimagegamma);
     cmtodo!(engine, pdfimagehicolor);
-    cmtodo!(engine, pdfimageresolution);
     cmtodo!(engine, pdfinclusioncopyfonts);
     cmtodo!(engine, pdfinclusionerrorlevel);
     cmtodo!(engine, pdfinfoomitdate);
@@ -1492,8 +1723,6 @@ where
     cmtodo!(engine, tagcode);
     cmtodo!(engine, pdflastlink);
     cmtodo!(engine, pdflastximagecolordepth);
-    cmtodo!(engine, pdflastximagepages);
-    cmtodo!(engine, pdfrandomseed);
     cmtodo!(engine, pdfretval);
     cmtodo!(engine, pdfdestmargin);
     cmtodo!(engine, pdfeachlinedepth);
@@ -1504,17 +1733,13 @@ where
     cmtodo!(engine, pdfpxdimen);
     cmtodo!(engine, pdfthreadmargin);
     cmtodo!(engine, pdfpkmode);
-    cmtodo!(engine, pdffiledump);
     cmtodo!(engine, pdffontname);
     cmtodo!(engine, pdffontobjnum);
     cmtodo!(engine, pdfincludechars);
     cmtodo!(engine, pdfinsertht);
-    cmtodo!(engine, pdfnormaldeviate);
     cmtodo!(engine, pdfpageref);
     cmtodo!(engine, pdftexbanner);
-    cmtodo!(engine, pdfuniformdeviate);
     cmtodo!(engine, pdfxformname);
-    cmtodo!(engine, pdfximagebbox);
 
     cmtodo!(engine, letterspacefont);
     cmtodo!(engine, partokenname);
@@ -1531,7 +1756,6 @@ where
     cmtodo!(engine, pdfnoligatures);
     cmtodo!(engine, pdfrunninglinkoff);
     cmtodo!(engine, pdfrunninglinkon);
-    cmtodo!(engine, pdfsetrandomseed);
     cmtodo!(engine, pdfspacefont);
     cmtodo!(engine, pdfthread);
     cmtodo!(engine, pdftrailer);
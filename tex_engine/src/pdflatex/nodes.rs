@@ -25,6 +25,8 @@ pub enum PDFNode<ET: EngineTypes> {
     PDFEndLink,
     PDFSave,
     PDFRestore,
+    /// `\pdfsavepos`, numbered in the order of the document
+    SavePos(usize),
     PDFMatrix {
         scale: f32,
         rotate: f32,
@@ -304,6 +306,21 @@ pub enum ColorStackAction {
     Push(usize, PDFColor),
     Pop(usize),
     Set(usize, PDFColor),
+    /// a push of an ExtGState setting an opacity (see [`extgstate_opacity`]), e.g. by the
+    /// `transparent` package, which keeps its own color stack for that
+    PushOpacity(usize, f32),
+    SetOpacity(usize, f32),
+}
+
+/// The opacity set by the ExtGState with the given name (without the `/`). Color stacks and
+/// literals only refer to ExtGStates by name, so this is a guess: packages name them after
+/// the opacity they set, e.g. the `transparent` package's `/TRP0.5`.
+pub fn extgstate_opacity(name: &str) -> Option<f32> {
+    let value = name.trim_start_matches(|c: char| !c.is_ascii_digit() && c != '.');
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|o| (0.0..=1.0).contains(o))
 }
 
 #[derive(Clone, Debug)]
@@ -413,6 +430,7 @@ where
             PDFNode::PDFPagesAttr(_) => write!(f, "<pdfpagesattr/>"),
             PDFNode::PDFSave => write!(f, "<pdfsave>"),
             PDFNode::PDFRestore => write!(f, "<pdfrestore>"),
+            PDFNode::SavePos(i) => write!(f, "<pdfsavepos {i}>"),
             PDFNode::PDFLiteral(PDFLiteral { option, literal }) => write!(
                 f,
                 "<pdfliteral option=\"{:?}\", literal=\"{:?}\">",
@@ -427,6 +445,12 @@ where
                 ColorStackAction::Set(i, c) => {
                     write!(f, "<pdfcolorstack set=\"{}\", color=\"{:?}\">", i, c)
                 }
+                ColorStackAction::PushOpacity(i, o) => {
+                    write!(f, "<pdfcolorstack push=\"{i}\", opacity=\"{o}\">")
+                }
+                ColorStackAction::SetOpacity(i, o) => {
+                    write!(f, "<pdfcolorstack set=\"{i}\", opacity=\"{o}\">")
+                }
             },
             PDFNode::PDFStartLink(PDFStartLink {
                 width,
@@ -532,6 +556,8 @@ fn download_pdfium(lib_dir: &std::path::Path) {
 pub trait PDFExtension<ET: EngineTypes>: EngineExtension<ET> {
     fn pdfmatches(&mut self) -> &mut Vec<String>;
     fn elapsed(&mut self) -> &mut std::time::Instant;
+    fn random(&mut self) -> &mut PDFRandom;
+    fn positions(&mut self) -> &mut PDFPositions;
     fn colorstacks(&mut self) -> &mut Vec<Vec<PDFColor>>;
     fn current_colorstack(&mut self) -> &mut usize;
     fn pdfobjs(&mut self) -> &mut Vec<PDFObj>;
@@ -571,9 +597,73 @@ pub trait PDFExtension<ET: EngineTypes>: EngineExtension<ET> {
     }
 }
 
+/// The random number generator behind `\pdfuniformdeviate` and `\pdfnormaldeviate`. Like
+/// pdfTeX, it is seeded from the current time unless `\pdfsetrandomseed` (or the engine) sets
+/// the seed; `\pdfrandomseed` returns it. The same seed always yields the same numbers.
+#[derive(Clone, Debug)]
+pub struct PDFRandom {
+    seed: i32,
+    state: u64,
+}
+impl Default for PDFRandom {
+    fn default() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() ^ (d.as_secs() as u32))
+            .unwrap_or_default();
+        Self::new((nanos & 0x7FFF_FFFF) as i32)
+    }
+}
+impl PDFRandom {
+    /// A new generator with the given seed.
+    pub fn new(seed: i32) -> Self {
+        Self {
+            seed,
+            state: seed as u64,
+        }
+    }
+    /// The seed this generator was initialized with.
+    pub fn seed(&self) -> i32 {
+        self.seed
+    }
+    /// The next number in `[0,1)` (SplitMix64).
+    pub fn next_float(&mut self) -> f64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+    /// A uniformly distributed integer between `0` (inclusive) and `n` (exclusive; or between
+    /// `n` and `0` if `n` is negative), as for `\pdfuniformdeviate n`.
+    pub fn uniform(&mut self, n: i32) -> i32 {
+        (self.next_float() * n as f64).floor() as i32
+    }
+    /// A normally distributed number with mean 0 and standard deviation 65536 (i.e. 1.0 as a
+    /// scaled number), as for `\pdfnormaldeviate` (Box-Muller).
+    pub fn normal(&mut self) -> i32 {
+        let u1 = 1.0 - self.next_float();
+        let u2 = self.next_float();
+        let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+        (z * 65536.0).round() as i32
+    }
+}
+
+/// The state of `\pdfsavepos`: the number of positions saved so far, and the last position
+/// resolved at shipout (in sp from the bottom left corner of the page), as returned by
+/// `\pdflastxpos` and `\pdflastypos`.
+#[derive(Clone, Debug, Default)]
+pub struct PDFPositions {
+    pub count: usize,
+    pub last: (i32, i32),
+}
+
 pub struct MinimalPDFExtension<ET: EngineTypes> {
     matches: Vec<String>,
     elapsed: std::time::Instant, //chrono::DateTime<chrono::Local>,
+    random: PDFRandom,
+    positions: PDFPositions,
     colorstacks: Vec<Vec<PDFColor>>,
     current_colorstack: usize,
     pdfobjs: Vec<PDFObj>,
@@ -588,6 +678,8 @@ impl<ET: EngineTypes> EngineExtension<ET> for MinimalPDFExtension<ET> {
         Self {
             matches: Vec::new(),
             elapsed: std::time::Instant::now(),
+            random: PDFRandom::default(),
+            positions: PDFPositions::default(),
             colorstacks: vec![vec![PDFColor::black()]],
             current_colorstack: 0,
             pdfobjs: Vec::new(),
@@ -608,6 +700,14 @@ impl<ET: EngineTypes> PDFExtension<ET> for MinimalPDFExtension<ET> {
         &mut self.elapsed
     }
 
+    fn random(&mut self) -> &mut PDFRandom {
+        &mut self.random
+    }
+
+    fn positions(&mut self) -> &mut PDFPositions {
+        &mut self.positions
+    }
+
     fn colorstacks(&mut self) -> &mut Vec<Vec<PDFColor>> {
         &mut self.colorstacks
     }
@@ -692,60 +792,187 @@ pub struct PDFXImage<ET: EngineTypes> {
     pub boxspec: Option<PDFBoxSpec>,
     pub filepath: PathBuf,
     pub img: PDFImage,
+    pub info: PDFImageInfo,
 }
 impl<ET: EngineTypes> PDFXImage<ET> {
-    pub fn height(&self) -> ET::Dim {
-        match (self.height, self.width) {
-            (None, Some(w)) => {
-                let scale = self.img.width() as f32 / (w.into() as f32);
-                ET::Dim::from_sp((self.img.height() as f32 / scale).round() as i32)
+    /// The size the image is set at, as in pdfTeX: the natural size if neither `width` nor
+    /// `height` is given, and scaled preserving the aspect ratio if only one of them is (where
+    /// the natural height is the height plus the depth).
+    fn size(&self) -> (i32, i32) {
+        let [llx, lly, urx, ury] = self.info.bbox;
+        let (nat_w, nat_h) = (urx - llx, ury - lly);
+        let sp = |d: ET::Dim| d.into() as i32;
+        let d = self.depth.map_or(0, sp);
+        let scale = |v: i32, num: i32, den: i32| {
+            if den == 0 {
+                v
+            } else {
+                (i64::from(v) * i64::from(num) / i64::from(den)) as i32
             }
-            (Some(h), _) => h,
-            _ => ET::Dim::from_sp(65536 * (self.img.height() as i32)),
+        };
+        match (self.width.map(sp), self.height.map(sp)) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, scale(w, nat_h, nat_w) - d),
+            (None, Some(h)) => (scale(h + d, nat_w, nat_h), h),
+            (None, None) => (nat_w, nat_h - d),
         }
     }
+    pub fn height(&self) -> ET::Dim {
+        ET::Dim::from_sp(self.size().1)
+    }
 
     pub fn width(&self) -> ET::Dim {
-        match (self.height, self.width) {
-            (Some(h), None) => {
-                let scale = self.img.height() as f32 / (h.into() as f32);
-                ET::Dim::from_sp((self.img.width() as f32 / scale).round() as i32)
-            }
-            (_, Some(w)) => w,
-            _ => ET::Dim::from_sp(65536 * (self.img.width() as i32)),
-        }
+        ET::Dim::from_sp(self.size().0)
     }
     pub fn depth(&self) -> ET::Dim {
         self.depth.unwrap_or_default()
     }
 }
 
+/// What pdfTeX determines about an image file when it is loaded: its bounding box in sp (the
+/// selected page box of a PDF, or the pixels at the image's resolution for bitmaps) and its
+/// number of pages (for `\pdflastximagepages`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PDFImageInfo {
+    pub bbox: [i32; 4],
+    pub pages: usize,
+}
+impl PDFImageInfo {
+    /// sp per inch
+    const INCH: f64 = 72.27 * 65536.0;
+    /// A bitmap of the given size in pixels, at the resolution stored in the file if there is
+    /// one, otherwise at `default_dpi` (i.e. `\pdfimageresolution`) or 72dpi.
+    pub fn bitmap(path: &Path, width: u32, height: u32, default_dpi: i32) -> Self {
+        let fallback = if default_dpi > 0 {
+            f64::from(default_dpi)
+        } else {
+            72.0
+        };
+        let (xres, yres) = std::fs::read(path)
+            .ok()
+            .and_then(|bytes| bitmap_resolution(&bytes))
+            .unwrap_or((fallback, fallback));
+        PDFImageInfo {
+            bbox: [
+                0,
+                0,
+                (f64::from(width) * Self::INCH / xres).round() as i32,
+                (f64::from(height) * Self::INCH / yres).round() as i32,
+            ],
+            pages: 1,
+        }
+    }
+    /// A page box of a PDF, in bp.
+    pub fn pdf(bbox: [f32; 4], pages: usize) -> Self {
+        PDFImageInfo {
+            bbox: bbox.map(|bp| (f64::from(bp) * Self::INCH / 72.0).round() as i32),
+            pages,
+        }
+    }
+}
+
+/// The resolution (in dpi) of a PNG (`pHYs`) or JPEG (JFIF) file, if it specifies one.
+fn bitmap_resolution(bytes: &[u8]) -> Option<(f64, f64)> {
+    let be16 = |b: &[u8], i: usize| Some(u16::from_be_bytes([*b.get(i)?, *b.get(i + 1)?]));
+    let be32 = |b: &[u8], i: usize| Some(u32::from_be_bytes(b.get(i..i + 4)?.try_into().ok()?));
+    let res = if let Some(mut chunks) = bytes.strip_prefix(b"\x89PNG\r\n\x1a\n") {
+        loop {
+            let len = be32(chunks, 0)? as usize;
+            match chunks.get(4..8)? {
+                b"pHYs" if chunks.get(16) == Some(&1) => {
+                    // pixels per meter
+                    break (
+                        f64::from(be32(chunks, 8)?) * 0.0254,
+                        f64::from(be32(chunks, 12)?) * 0.0254,
+                    );
+                }
+                b"pHYs" | b"IDAT" | b"IEND" => return None,
+                _ => chunks = chunks.get(12 + len..)?,
+            }
+        }
+    } else if let Some(mut segments) = bytes.strip_prefix(&[0xFF, 0xD8]) {
+        loop {
+            if *segments.first()? != 0xFF {
+                return None;
+            }
+            let len = be16(segments, 2)? as usize;
+            match segments[1] {
+                0xE0 if segments.get(4..9) == Some(b"JFIF\0") => {
+                    let x = f64::from(be16(segments, 12)?);
+                    let y = f64::from(be16(segments, 14)?);
+                    break match segments.get(11)? {
+                        1 => (x, y),
+                        2 => (x * 2.54, y * 2.54),
+                        _ => return None,
+                    };
+                }
+                0xDA => return None,
+                _ => segments = segments.get(2 + len..)?,
+            }
+        }
+    } else {
+        return None;
+    };
+    (res.0 > 0.0 && res.1 > 0.0).then_some(res)
+}
+
+/// Renders page `page` (starting at 1) of a PDF file, and determines its box `boxspec`
+/// (defaulting to the crop box, which in turn defaults to the media box).
 #[cfg(feature = "pdfium")]
-pub fn pdf_as_image<ET: EngineTypes, E: PDFExtension<ET>>(path: &Path, ext: &mut E) -> PDFImage {
+pub fn pdf_as_image<ET: EngineTypes, E: PDFExtension<ET>>(
+    path: &Path,
+    ext: &mut E,
+    page: i64,
+    boxspec: Option<PDFBoxSpec>,
+) -> (PDFImage, Option<PDFImageInfo>) {
     use pdfium_render::prelude::PdfRenderConfig;
     let Some(pdfium) = ext.pdfium() else {
         log::warn!("PDFium not loaded");
-        return PDFImage::None;
+        return (PDFImage::None, None);
     };
     let Ok(pdf) = pdfium.load_pdf_from_file(&path, None) else {
-        log::warn!("Failed to load PDF file {}",path.display());
-        return PDFImage::None;
+        log::warn!("Failed to load PDF file {}", path.display());
+        return (PDFImage::None, None);
     };
     let cfg = PdfRenderConfig::new().scale_page_by_factor(5.0);
     let pages = pdf.pages();
-    let r = if let Ok(bmp) = pages.iter().next().unwrap().render_with_config(&cfg) {
+    let Some(pdfpage) = u16::try_from(page - 1).ok().and_then(|i| pages.get(i).ok()) else {
+        log::warn!("PDF file {} has no page {page}", path.display());
+        return (PDFImage::None, None);
+    };
+    let boundaries = pdfpage.boundaries();
+    let bounds = match boxspec {
+        Some(PDFBoxSpec::MediaBox) => boundaries.media(),
+        Some(PDFBoxSpec::BleedBox) => boundaries.bleed(),
+        Some(PDFBoxSpec::TrimBox) => boundaries.trim(),
+        Some(PDFBoxSpec::ArtBox) => boundaries.art(),
+        Some(PDFBoxSpec::CropBox) | None => boundaries.crop(),
+    }
+    .or_else(|_| boundaries.crop())
+    .or_else(|_| boundaries.media())
+    .map(|b| b.bounds);
+    let info = bounds.ok().map(|r| {
+        let bbox = [r.left(), r.bottom(), r.right(), r.top()].map(|p| p.value);
+        PDFImageInfo::pdf(bbox, pages.len() as usize)
+    });
+    let r = if let Ok(bmp) = pdfpage.render_with_config(&cfg) {
         let img = bmp.as_image();
         PDFImage::PDF(img)
     } else {
-        log::warn!("Failed to render PDF file {}",path.display());
+        log::warn!("Failed to render PDF file {}", path.display());
         PDFImage::None
     };
-    r
+    (r, info)
 }
 
 #[cfg(not(feature = "pdfium"))]
-pub fn pdf_as_image<ET: EngineTypes, E: PDFExtension<ET>>(_path: &Path, _ext: &mut E) -> PDFImage {
-    PDFImage::None
+pub fn pdf_as_image<ET: EngineTypes, E: PDFExtension<ET>>(
+    _path: &Path,
+    _ext: &mut E,
+    _page: i64,
+    _boxspec: Option<PDFBoxSpec>,
+) -> (PDFImage, Option<PDFImageInfo>) {
+    (PDFImage::None, None)
 }
 
 #[derive(Debug, Clone)]
@@ -156,6 +156,119 @@ fn carlisle() {
     }
 }
 
+#[test]
+fn catcode_tables() {
+    use crate::commands::luatex::register_catcode_table_primitives;
+    use crate::engine::state::{GroupType, State};
+    use crate::tex::catcodes::CategoryCode;
+    debug();
+    let mut engine = PlainTeXEngine::default();
+    engine.initialize_tex_primitives();
+    register_catcode_table_primitives(&mut engine);
+    // the catcode change belongs to table 0, even though table 1 is active when the group ends
+    engine.mouth.push_string(
+        r"\initcatcodetable1 \begingroup\catcode`\A=12 \catcodetable1 \endgroup\end".into(),
+    );
+    engine.run(|_, _| Ok(())).unwrap();
+    assert_eq!(engine.state.get_catcode_table(), 0);
+    assert_eq!(
+        *engine.state.get_catcode_scheme().get(b'A'),
+        CategoryCode::Letter
+    );
+    engine.state.set_catcode_table(&engine.aux, 1, true);
+    assert_eq!(
+        *engine.state.get_catcode_scheme().get(b'A'),
+        CategoryCode::Letter
+    );
+
+    let mut engine = PlainTeXEngine::default();
+    engine.initialize_tex_primitives();
+    register_catcode_table_primitives(&mut engine);
+    engine
+        .mouth
+        .push_string(r"\savecatcodetable 2147483647 \end".into());
+    assert!(engine.run(|_, _| Ok(())).is_err());
+}
+
+#[test]
+fn currentiftype() {
+    use crate::engine::state::{GroupType, State};
+    debug();
+    let mut engine = PlainTeXEngine::default();
+    engine.initialize_etex_primitives();
+    engine.register_conditional("ifcustom", |_, _| Ok(true));
+    engine.mouth.push_string(
+        r"\ifnum1=1 \count1=\currentiftype\fi
+        \unless\ifnum1=2 \count2=\currentiftype\fi
+        \unless\ifnum1=1 \else\count3=\currentiftype\fi
+        \ifcustom\count4=\currentiftype\fi
+        \unless\ifcustom\else\count5=\currentiftype\fi\end"
+            .into(),
+    );
+    engine.run(|_, _| Ok(())).unwrap();
+    let codes: Vec<i32> = (1..=5).map(|i| engine.state.get_int_register(i)).collect();
+    assert_eq!(codes, [3, -3, -3, 25, -25]);
+}
+
+#[test]
+fn hardened() {
+    use crate::engine::state::{GroupType, State};
+    use crate::engine::stomach::Stomach;
+    use crate::engine::utils::hardening::Hardening;
+    use crate::tex::nodes::math::{EqNoPosition, MathNodeList, MathNodeListType};
+    use crate::tex::nodes::NodeList;
+    use crate::utils::errors::TeXError;
+    debug();
+    let hardened = || {
+        let mut engine = PlainTeXEngine::default();
+        engine.initialize_tex_primitives();
+        engine.aux.hardening = Hardening::new(true);
+        engine
+    };
+    let mut engine = hardened();
+    // a math list without enclosing math mode used to panic in `StomachData::mode`
+    engine.stomach.data_mut().open_lists.push(NodeList::Math {
+        children: MathNodeList::default(),
+        start: engine.mouth.start_ref(),
+        tp: MathNodeListType::LeftRight(None),
+    });
+    engine.mouth.push_string(r"x\end".into());
+    assert!(matches!(
+        engine.run(|_, _| Ok(())),
+        Err(TeXError::Internal(_))
+    ));
+    // a `\left` list with an equation number used to hit an `unreachable!` in `\right`
+    let mut engine = hardened();
+    let start = engine.mouth.start_ref();
+    let lists = &mut engine.stomach.data_mut().open_lists;
+    lists.push(NodeList::Math {
+        children: MathNodeList::default(),
+        start,
+        tp: MathNodeListType::Top { display: true },
+    });
+    lists.push(NodeList::Math {
+        children: MathNodeList::EqNo {
+            pos: EqNoPosition::Right,
+            main: vec![],
+            eqno: vec![],
+        },
+        start,
+        tp: MathNodeListType::LeftRight(None),
+    });
+    engine.state.push(&mut engine.aux, GroupType::LeftRight, 0);
+    engine.mouth.push_string(r"\right.\end".into());
+    assert!(matches!(
+        engine.run(|_, _| Ok(())),
+        Err(TeXError::Internal(_))
+    ));
+    // malformed input after `\noexpand` in the output routine is an error, not a panic
+    let mut engine = hardened();
+    engine
+        .mouth
+        .push_string("\\catcode123=1 \\catcode125=2 \\catcode127=15 \\output={\\noexpand}\\hbox{}\\penalty-10000 \\noexpand\x7f\\end".into());
+    assert!(engine.run(|_, _| Ok(())).is_err());
+}
+
 #[cfg(feature = "pdflatex")]
 #[test]
 fn pdflatex_init() {
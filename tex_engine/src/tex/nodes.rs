@@ -6,6 +6,7 @@ nodes, each with a corresponding [`NodeList`] type.
 pub mod boxes;
 pub mod horizontal;
 pub mod math;
+pub mod show;
 pub mod vertical;
 
 use crate::commands::primitives::PrimitiveIdentifier;
@@ -91,6 +92,16 @@ where
     fn into_math(self) -> MathNode<ET, UnresolvedMathFontStyle<ET>> {
         MathNode::Custom(self.into())
     }
+    /// Called when the contents of a box spanning `start` to `end` are unpacked into the current
+    /// list (`\unhbox`, `\unvcopy`...). May return a pair of (opaque) nodes to insert before and
+    /// after the contents, so that their provenance is not lost; by default, nothing is inserted.
+    fn provenance(
+        _engine: &mut EngineReferences<ET>,
+        _start: SourceRef<ET>,
+        _end: SourceRef<ET>,
+    ) -> Option<(ET::CustomNode, ET::CustomNode)> {
+        None
+    }
 }
 
 impl<ET: EngineTypes<CustomNode = Infallible>> NodeTrait<ET> for Infallible {
@@ -145,6 +156,10 @@ impl<ET: EngineTypes> WhatsitNode<ET> {
         let i = std::cell::RefCell::new(Some(f));
         WhatsitNode(name.display::<ET::Char>(None).to_string(), Ptr::new(i))
     }
+    /// The name of the primitive that produced this node.
+    pub fn name(&self) -> &str {
+        &self.0
+    }
     /// Run this Whatsit node's function at shipout, if it has not been run yet.
     /// If it has been run already, this is a no-op.
 
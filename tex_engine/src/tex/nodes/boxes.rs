@@ -1013,6 +1013,20 @@ impl<ET: EngineTypes> TeXBox<ET> {
             _ => ToOrSpread::None,
         }
     }
+    /// How far this box is raised (by `\raise` or `\lower`), if at all
+    pub fn raised(&self) -> Option<ET::Dim> {
+        match self {
+            TeXBox::H { info, .. } => info.raised(),
+            TeXBox::V { info, .. } => info.raised(),
+        }
+    }
+    /// How far this box is moved left (by `\moveleft` or `\moveright`), if at all
+    pub fn moved_left(&self) -> Option<ET::Dim> {
+        match self {
+            TeXBox::H { info, .. } => info.moved_left(),
+            TeXBox::V { info, .. } => info.moved_left(),
+        }
+    }
 }
 
 impl<ET: EngineTypes> NodeTrait<ET> for TeXBox<ET> {
@@ -73,6 +73,16 @@ pub enum HNode<ET: EngineTypes> {
         /// The current font
         font: <ET::FontSystem as FontSystem>::Font,
     },
+    /// A discretionary break, as produced by `\discretionary` or `\-`. If a line is broken here,
+    /// `pre` ends the line and `post` starts the next one; otherwise, `nobreak` is typeset.
+    Discretionary {
+        /// The pre-break text.
+        pre: Box<[HNode<ET>]>,
+        /// The post-break text.
+        post: Box<[HNode<ET>]>,
+        /// The no-break text.
+        nobreak: Box<[HNode<ET>]>,
+    },
     /// A custom node.
     Custom(ET::CustomNode),
 }
@@ -150,12 +160,27 @@ impl<ET: EngineTypes> NodeTrait<ET> for HNode<ET> {
             HNode::Hss => write!(f, "<hss>"),
             HNode::Space => write!(f, "<space>"),
             HNode::HKern(d) => write!(f, "<hkern:{}>", d),
+            HNode::Discretionary { pre, post, nobreak } => {
+                display_do_indent(indent, f)?;
+                f.write_str("<discretionary>")?;
+                for (name, ls) in [("pre", pre), ("post", post), ("nobreak", nobreak)] {
+                    display_do_indent(indent + 2, f)?;
+                    write!(f, "<{}>", name)?;
+                    for c in ls.iter() {
+                        c.display_fmt(indent + 4, f)?;
+                    }
+                    display_do_indent(indent + 2, f)?;
+                    write!(f, "</{}>", name)?;
+                }
+                display_do_indent(indent, f)?;
+                f.write_str("</discretionary>")
+            }
             HNode::Custom(n) => n.display_fmt(indent, f),
         }
     }
     fn height(&self) -> ET::Dim {
         match self {
-            HNode::Box(b) => b.height(),
+            HNode::Box(b) => b.height() + b.raised().unwrap_or_default(),
             HNode::VRule { height, .. } => height.unwrap_or_default(),
             HNode::Char { char, font } => font.get_ht(*char),
             HNode::Leaders(l) => l.height(),
@@ -164,6 +189,9 @@ impl<ET: EngineTypes> NodeTrait<ET> for HNode<ET> {
             HNode::Accent { char, font, .. } => {
                 font.get_ht(*char) // TODO
             }
+            HNode::Discretionary { nobreak, .. } => {
+                nobreak.iter().map(|n| n.height()).max().unwrap_or_default()
+            }
             _ => ET::Dim::default(),
         }
     }
@@ -179,18 +207,22 @@ impl<ET: EngineTypes> NodeTrait<ET> for HNode<ET> {
             HNode::HSkip(s) => s.base,
             HNode::Accent { char, font, .. } => font.get_wd(*char),
             HNode::Space => ET::Dim::from_sp(65536 * 5), // TODO heuristic; use spacefactor instead
+            HNode::Discretionary { nobreak, .. } => nobreak.iter().map(|n| n.width()).sum(),
             _ => ET::Dim::default(),
         }
     }
     fn depth(&self) -> ET::Dim {
         match self {
-            HNode::Box(b) => b.depth(),
+            HNode::Box(b) => b.depth() - b.raised().unwrap_or_default(),
             HNode::Char { char, font } => font.get_dp(*char),
             HNode::Accent { char, font, .. } => font.get_dp(*char),
             HNode::VRule { depth, .. } => depth.unwrap_or_default(),
             HNode::Leaders(l) => l.depth(),
             HNode::MathGroup(mg) => mg.depth(),
             HNode::Custom(n) => n.depth(),
+            HNode::Discretionary { nobreak, .. } => {
+                nobreak.iter().map(|n| n.depth()).max().unwrap_or_default()
+            }
             _ => ET::Dim::default(),
         }
     }
@@ -207,6 +239,7 @@ impl<ET: EngineTypes> NodeTrait<ET> for HNode<ET> {
             HNode::Mark(_, _) => NodeType::Mark,
             HNode::Whatsit(_) => NodeType::WhatsIt,
             HNode::Accent { .. } => NodeType::Char,
+            HNode::Discretionary { .. } => NodeType::Discretionary,
             HNode::Leaders(_) => NodeType::Glue,
             HNode::HSkip(_)
             | HNode::Space
@@ -251,4 +284,7 @@ pub enum HorizontalNodeListType<ET: EngineTypes> {
     /// The `u8` indicates the number of *additional* columns spanned by this cell
     /// (so by default 0).
     HAlignCell(SourceRef<ET>, u8),
+    /// A part of a `\discretionary`; contains the parts that have already been completed
+    /// (the pre-break and post-break texts, in that order).
+    Discretionary(Vec<Box<[HNode<ET>]>>),
 }
@@ -0,0 +1,515 @@
+/*! TeX's own format for displaying boxes, as written to the log by `\showbox` - as opposed to the
+more compact [`NodeTrait::display`](super::NodeTrait::display) format. Used to compare the boxes
+built by this engine with those of pdfTeX.
+
+Since nodes here are not quite the same as TeX's, some information is reconstructed or missing:
+interword glue ([`HNode::Space`]) is taken from the font of the preceding character (ignoring the
+space factor), glue set ratios are recomputed from the contents of a box, math lists are shown as
+just `\mathon` and `\mathoff`, and [custom nodes](HNode::Custom) are not shown at all.
+*/
+use crate::engine::fontsystem::Font;
+use crate::engine::EngineTypes;
+use crate::tex::catcodes::CategoryCodeScheme;
+use crate::tex::characters::Character;
+use crate::tex::nodes::boxes::{HBoxInfo, TeXBox, ToOrSpread, VBoxInfo};
+use crate::tex::nodes::horizontal::HNode;
+use crate::tex::nodes::vertical::VNode;
+use crate::tex::nodes::{LeaderBody, LeaderSkip, LeaderType, Leaders, NodeTrait};
+use crate::tex::numerics::{Dim32, Skip, StretchShrink, TeXDimen};
+use crate::tex::tokens::control_sequences::{CSHandler, CSName};
+use std::fmt::{Display, Formatter, Write};
+
+/// The parameters for displaying boxes like `\showbox` does.
+pub struct ShowBox<'a, ET: EngineTypes> {
+    /// for the names of fonts and the control sequences in marks
+    pub int: &'a <ET::CSName as CSName<ET::Char>>::Handler,
+    /// for the contents of marks
+    pub cc: &'a CategoryCodeScheme<ET::Char>,
+    pub escapechar: Option<ET::Char>,
+    /// the font interword glue is taken from before the first character of a list
+    pub font: ET::Font,
+    /// `\showboxdepth`: lists nested deeper than this are shown as ` []`
+    pub depth: i64,
+    /// `\showboxbreadth`: lists are cut off with `etc.` after this many nodes; as in TeX,
+    /// non-positive values mean 5
+    pub breadth: i64,
+}
+impl<ET: EngineTypes> ShowBox<'_, ET> {
+    /// Writes `bx` to `out`, every node on a new line (including the first).
+    pub fn write(&self, bx: &TeXBox<ET>, out: &mut String) {
+        let mut w = Writer {
+            show: self,
+            breadth: if self.breadth <= 0 { 5 } else { self.breadth },
+            out,
+            prefix: String::new(),
+            font: self.font.clone(),
+        };
+        w.line();
+        w.tex_box(bx);
+    }
+}
+
+/// A number of scaled points as TeX's `print_scaled` writes it, i.e. without a unit.
+struct Scaled(i64);
+impl Display for Scaled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Dim32::display_num(self.0 as i32, "", f)
+    }
+}
+fn scaled<D: TeXDimen>(d: D) -> Scaled {
+    Scaled(d.into())
+}
+
+fn is_zero<D: TeXDimen>(s: &StretchShrink<D>) -> bool {
+    match s {
+        StretchShrink::Dim(d) => *d == D::default(),
+        StretchShrink::Fil(i) | StretchShrink::Fill(i) | StretchShrink::Filll(i) => *i == 0,
+    }
+}
+fn write_stretch<D: TeXDimen>(out: &mut String, s: &StretchShrink<D>) {
+    let _ = match s {
+        StretchShrink::Dim(d) => write!(out, "{}", scaled(*d)),
+        StretchShrink::Fil(i) => write!(out, "{}fil", Scaled(*i as i64)),
+        StretchShrink::Fill(i) => write!(out, "{}fill", Scaled(*i as i64)),
+        StretchShrink::Filll(i) => write!(out, "{}filll", Scaled(*i as i64)),
+    };
+}
+/// B-Book §178 (`print_spec`)
+fn write_spec<D: TeXDimen>(out: &mut String, s: &Skip<D>) {
+    let _ = write!(out, "{}", scaled(s.base));
+    if let Some(st) = s.stretch.as_ref().filter(|s| !is_zero(*s)) {
+        out.push_str(" plus ");
+        write_stretch(out, st);
+    }
+    if let Some(sh) = s.shrink.as_ref().filter(|s| !is_zero(*s)) {
+        out.push_str(" minus ");
+        write_stretch(out, sh);
+    }
+}
+fn fil<D: TeXDimen>(
+    stretch: Option<StretchShrink<D>>,
+    shrink: Option<StretchShrink<D>>,
+) -> Skip<D> {
+    Skip {
+        base: D::default(),
+        stretch,
+        shrink,
+    }
+}
+const ONE: i32 = 65536;
+
+struct Writer<'a, 'b, ET: EngineTypes> {
+    show: &'a ShowBox<'a, ET>,
+    breadth: i64,
+    out: &'b mut String,
+    /// the `.`s (and `|`s) in front of every line
+    prefix: String,
+    /// the font of the last character
+    font: ET::Font,
+}
+impl<ET: EngineTypes> Writer<'_, '_, ET> {
+    fn line(&mut self) {
+        self.out.push('\n');
+        self.out.push_str(&self.prefix);
+    }
+    /// B-Book §182 (`show_box`): the nodes of a list, one level deeper.
+    fn list<N>(&mut self, prefix: char, nodes: &[N], mut f: impl FnMut(&mut Self, &N)) {
+        self.prefix.push(prefix);
+        if self.prefix.len() as i64 > self.show.depth {
+            if !nodes.is_empty() {
+                self.out.push_str(" []");
+            }
+        } else {
+            for (i, n) in nodes.iter().enumerate() {
+                self.line();
+                if i as i64 >= self.breadth {
+                    self.out.push_str("etc.");
+                    break;
+                }
+                f(self, n);
+            }
+        }
+        self.prefix.pop();
+    }
+    fn dims(&mut self, height: ET::Dim, depth: ET::Dim, width: ET::Dim) {
+        let _ = write!(
+            self.out,
+            "({}+{})x{}",
+            scaled(height),
+            scaled(depth),
+            scaled(width)
+        );
+    }
+    fn rule(&mut self, height: Option<ET::Dim>, depth: Option<ET::Dim>, width: Option<ET::Dim>) {
+        let d = |d: Option<ET::Dim>| d.map_or_else(|| "*".to_string(), |d| scaled(d).to_string());
+        let _ = write!(self.out, "\\rule({}+{})x{}", d(height), d(depth), d(width));
+    }
+    fn font_name(&mut self, font: &ET::Font) {
+        let _ = write!(self.out, "\\{}", self.show.int.resolve(font.name()));
+    }
+    /// B-Book §49 (`print_ASCII`)
+    fn char(&mut self, c: ET::Char) {
+        match c.to_char() {
+            c if (c as u32) < 32 => {
+                let _ = write!(self.out, "^^{}", ((c as u8) + 64) as char);
+            }
+            '\u{7f}' => self.out.push_str("^^?"),
+            c if (c as u32) < 128 => self.out.push(c),
+            c if (c as u32) < 256 => {
+                let _ = write!(self.out, "^^{:02x}", c as u32);
+            }
+            c => self.out.push(c),
+        }
+    }
+    fn space(&self) -> Skip<ET::Dim> {
+        Self::space_of(&self.font)
+    }
+    /// The interword glue of `font`, at space factor 1000.
+    fn space_of(font: &ET::Font) -> Skip<ET::Dim> {
+        Skip {
+            base: font.get_dim(1),
+            stretch: Some(StretchShrink::Dim(font.get_dim(2))),
+            shrink: Some(StretchShrink::Dim(font.get_dim(3))),
+        }
+    }
+    fn glue(&mut self, s: &Skip<ET::Dim>) {
+        self.out.push_str("\\glue ");
+        write_spec(self.out, s);
+    }
+
+    /// The glue of a horizontal node, if any.
+    fn hglue(&self, n: &HNode<ET>) -> Option<Skip<ET::Dim>> {
+        Some(match n {
+            HNode::HSkip(s) => *s,
+            HNode::HFil => fil(Some(StretchShrink::Fil(ONE)), None),
+            HNode::HFill => fil(Some(StretchShrink::Fill(ONE)), None),
+            HNode::HFilneg => fil(Some(StretchShrink::Fil(-ONE)), None),
+            HNode::Hss => fil(Some(StretchShrink::Fil(ONE)), Some(StretchShrink::Fil(ONE))),
+            HNode::Space => self.space(),
+            HNode::Leaders(l) => Self::leaders_glue(l),
+            _ => return None,
+        })
+    }
+    fn vglue(n: &VNode<ET>) -> Option<Skip<ET::Dim>> {
+        Some(match n {
+            VNode::VSkip(s) => *s,
+            VNode::VFil => fil(Some(StretchShrink::Fil(ONE)), None),
+            VNode::VFill => fil(Some(StretchShrink::Fill(ONE)), None),
+            VNode::VFilneg => fil(Some(StretchShrink::Fil(-ONE)), None),
+            VNode::Vss => fil(Some(StretchShrink::Fil(ONE)), Some(StretchShrink::Fil(ONE))),
+            VNode::Leaders(l) => Self::leaders_glue(l),
+            _ => return None,
+        })
+    }
+    fn leaders_glue(l: &Leaders<ET>) -> Skip<ET::Dim> {
+        match &l.skip {
+            LeaderSkip::HSkip(s) | LeaderSkip::VSkip(s) => *s,
+            LeaderSkip::HFil | LeaderSkip::VFil => fil(Some(StretchShrink::Fil(ONE)), None),
+            LeaderSkip::HFill | LeaderSkip::VFill => fil(Some(StretchShrink::Fill(ONE)), None),
+        }
+    }
+
+    /// B-Book §186: `, glue set ...` for a box of size `size` whose contents have the natural
+    /// size `natural` and the total glue `total`.
+    fn glue_set(&mut self, size: ET::Dim, natural: ET::Dim, total: Skip<ET::Dim>) {
+        let x: i64 = (size - natural).into();
+        let (available, shrinking) = match x {
+            0 => return,
+            x if x > 0 => (total.stretch, false),
+            _ => (total.shrink, true),
+        };
+        let (amount, unit) = match available {
+            None => return,
+            Some(StretchShrink::Dim(d)) => (d.into(), ""),
+            Some(StretchShrink::Fil(i)) => (i as i64, "fil"),
+            Some(StretchShrink::Fill(i)) => (i as i64, "fill"),
+            Some(StretchShrink::Filll(i)) => (i as i64, "filll"),
+        };
+        if amount == 0 {
+            return;
+        }
+        let mut ratio = x.abs() as f64 / amount as f64;
+        // finite shrinkability is never exceeded (§664)
+        if shrinking && unit.is_empty() && ratio > 1.0 {
+            ratio = 1.0;
+        }
+        self.out.push_str(", glue set ");
+        if shrinking {
+            self.out.push_str("- ");
+        }
+        if ratio > 20000.0 {
+            let _ = write!(self.out, ">20000.0{unit}");
+        } else {
+            let _ = write!(
+                self.out,
+                "{}{unit}",
+                Scaled((ratio * 65536.0).round() as i64)
+            );
+        }
+    }
+    fn shifted(&mut self, bx: &TeXBox<ET>) {
+        let shift = bx
+            .raised()
+            .or_else(|| bx.moved_left())
+            .filter(|d| *d != ET::Dim::default());
+        if let Some(d) = shift {
+            let _ = write!(self.out, ", shifted {}", scaled(-d));
+        }
+    }
+
+    fn tex_box(&mut self, bx: &TeXBox<ET>) {
+        match bx {
+            TeXBox::H { info, children, .. } => {
+                self.out.push_str("\\hbox");
+                self.dims(bx.height(), bx.depth(), bx.width());
+                let target = match info {
+                    HBoxInfo::HBox { scaled, .. } => Some(scaled),
+                    _ => None,
+                };
+                if let Some(target) = target {
+                    let (natural, total) = self.hsums(children);
+                    match target {
+                        ToOrSpread::None => (),
+                        ToOrSpread::To(w) => self.glue_set(*w, natural, total),
+                        ToOrSpread::Spread(s) => self.glue_set(natural + *s, natural, total),
+                    }
+                }
+                self.shifted(bx);
+                self.hlist(children);
+            }
+            TeXBox::V { info, children, .. } => {
+                self.out.push_str("\\vbox");
+                self.dims(bx.height(), bx.depth(), bx.width());
+                let target = match info {
+                    VBoxInfo::VBox { scaled, .. } | VBoxInfo::VTop { scaled, .. } => Some(scaled),
+                    _ => None,
+                };
+                if let Some(target) = target {
+                    let (natural, total) = Self::vsums(children);
+                    match target {
+                        ToOrSpread::None => (),
+                        ToOrSpread::To(h) => self.glue_set(*h, natural, total),
+                        ToOrSpread::Spread(s) => self.glue_set(natural + *s, natural, total),
+                    }
+                }
+                self.shifted(bx);
+                self.vlist(children);
+            }
+        }
+    }
+    /// The natural width and the total glue of a horizontal list.
+    fn hsums(&self, nodes: &[HNode<ET>]) -> (ET::Dim, Skip<ET::Dim>) {
+        let mut font = self.font.clone();
+        let mut natural = ET::Dim::default();
+        let mut total = Skip::default();
+        for n in nodes {
+            match n {
+                HNode::Char { font: f, .. } => font = f.clone(),
+                HNode::Space => {
+                    let space = Self::space_of(&font);
+                    natural = natural + space.base;
+                    total = total + space;
+                    continue;
+                }
+                _ => (),
+            }
+            match self.hglue(n) {
+                Some(s) => {
+                    natural = natural + s.base;
+                    total = total + s;
+                }
+                None => natural = natural + n.width(),
+            }
+        }
+        (natural, total)
+    }
+    /// The natural height (without the depth of the last node) and the total glue of a
+    /// vertical list.
+    fn vsums(nodes: &[VNode<ET>]) -> (ET::Dim, Skip<ET::Dim>) {
+        let mut natural = ET::Dim::default();
+        let mut total = Skip::default();
+        let mut last_depth = ET::Dim::default();
+        for n in nodes {
+            match Self::vglue(n) {
+                Some(s) => {
+                    natural = natural + last_depth + s.base;
+                    total = total + s;
+                    last_depth = ET::Dim::default();
+                }
+                None => {
+                    natural = natural + last_depth + n.height();
+                    last_depth = n.depth();
+                }
+            }
+        }
+        (natural, total)
+    }
+
+    fn hlist(&mut self, nodes: &[HNode<ET>]) {
+        // discretionaries are followed by the nodes they replace, as in TeX
+        fn flatten<'n, ET: EngineTypes>(nodes: &'n [HNode<ET>], ret: &mut Vec<&'n HNode<ET>>) {
+            for n in nodes {
+                match n {
+                    HNode::Custom(_) => (),
+                    HNode::Discretionary { nobreak, .. } => {
+                        ret.push(n);
+                        flatten(nobreak, ret);
+                    }
+                    n => ret.push(n),
+                }
+            }
+        }
+        let mut flat = Vec::new();
+        flatten(nodes, &mut flat);
+        self.list('.', &flat, |w, n| w.hnode(n));
+    }
+    fn vlist(&mut self, nodes: &[VNode<ET>]) {
+        let nodes = nodes
+            .iter()
+            .filter(|n| !matches!(n, VNode::Custom(_)))
+            .collect::<Vec<_>>();
+        self.list('.', &nodes, |w, n| w.vnode(n));
+    }
+
+    /// B-Book §183 (`display`)
+    fn hnode(&mut self, n: &HNode<ET>) {
+        match n {
+            HNode::Char { char, font } => {
+                self.font = font.clone();
+                self.font_name(font);
+                self.out.push(' ');
+                self.char(*char);
+            }
+            HNode::Accent { accent, char, font } => {
+                self.font = font.clone();
+                self.font_name(font);
+                self.out.push(' ');
+                self.char(*accent);
+                self.line();
+                self.font_name(font);
+                self.out.push(' ');
+                self.char(*char);
+            }
+            HNode::Box(b) => self.tex_box(b),
+            HNode::VRule {
+                width,
+                height,
+                depth,
+                ..
+            } => self.rule(
+                *height,
+                *depth,
+                Some(width.unwrap_or(ET::Dim::from_sp(26214))),
+            ),
+            HNode::HKern(d) => {
+                let _ = write!(self.out, "\\kern {}", scaled(*d));
+            }
+            HNode::Penalty(p) => {
+                let _ = write!(self.out, "\\penalty {p}");
+            }
+            HNode::Mark(_, tl) => self.mark(tl),
+            HNode::Whatsit(w) => {
+                let _ = write!(self.out, "\\{}", w.name());
+            }
+            HNode::Leaders(l) => self.leaders(l),
+            HNode::Insert(n, ch) => {
+                let _ = write!(self.out, "\\insert{n}");
+                self.vlist(ch);
+            }
+            HNode::VAdjust(ch) => {
+                self.out.push_str("\\vadjust");
+                self.vlist(ch);
+            }
+            HNode::MathGroup(_) => {
+                self.out.push_str("\\mathon");
+                self.line();
+                self.out.push_str("\\mathoff");
+            }
+            HNode::Discretionary {
+                pre, post, nobreak, ..
+            } => {
+                self.out.push_str("\\discretionary");
+                if !nobreak.is_empty() {
+                    let _ = write!(self.out, " replacing {}", nobreak.len());
+                }
+                let mut flat = Vec::new();
+                flat.extend(pre.iter().filter(|n| !matches!(n, HNode::Custom(_))));
+                self.list('.', &flat, |w, n| w.hnode(n));
+                flat.clear();
+                flat.extend(post.iter().filter(|n| !matches!(n, HNode::Custom(_))));
+                self.list('|', &flat, |w, n| w.hnode(n));
+            }
+            HNode::Custom(_) => (),
+            n => {
+                if let Some(s) = self.hglue(n) {
+                    self.glue(&s)
+                }
+            }
+        }
+    }
+    fn vnode(&mut self, n: &VNode<ET>) {
+        match n {
+            VNode::Box(b) => self.tex_box(b),
+            VNode::HRule {
+                width,
+                height,
+                depth,
+                ..
+            } => self.rule(
+                Some(height.unwrap_or(ET::Dim::from_sp(26214))),
+                Some(depth.unwrap_or_default()),
+                *width,
+            ),
+            VNode::VKern(d) => {
+                let _ = write!(self.out, "\\kern {}", scaled(*d));
+            }
+            VNode::Penalty(p) => {
+                let _ = write!(self.out, "\\penalty {p}");
+            }
+            VNode::Mark(_, tl) => self.mark(tl),
+            VNode::Whatsit(w) => {
+                let _ = write!(self.out, "\\{}", w.name());
+            }
+            VNode::Leaders(l) => self.leaders(l),
+            VNode::Insert(n, ch) => {
+                let _ = write!(self.out, "\\insert{n}");
+                self.vlist(ch);
+            }
+            VNode::Custom(_) => (),
+            n => {
+                if let Some(s) = Self::vglue(n) {
+                    self.glue(&s)
+                }
+            }
+        }
+    }
+    fn mark(&mut self, tl: &crate::tex::tokens::token_lists::TokenList<ET::Token>) {
+        let _ = write!(
+            self.out,
+            "\\mark{{{}}}",
+            tl.display(self.show.int, self.show.cc, self.show.escapechar, false)
+        );
+    }
+    /// B-Book §190
+    fn leaders(&mut self, l: &Leaders<ET>) {
+        self.out.push_str(match l.tp {
+            LeaderType::Normal => "\\leaders ",
+            LeaderType::C => "\\cleaders ",
+            LeaderType::X => "\\xleaders ",
+        });
+        write_spec(self.out, &Self::leaders_glue(l));
+        self.prefix.push('.');
+        if self.prefix.len() as i64 <= self.show.depth {
+            self.line();
+            match &l.body {
+                LeaderBody::Box(b) => self.tex_box(b),
+                LeaderBody::Rule {
+                    width,
+                    height,
+                    depth,
+                } => self.rule(*height, *depth, *width),
+            }
+        }
+        self.prefix.pop();
+    }
+}
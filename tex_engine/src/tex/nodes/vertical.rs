@@ -128,7 +128,7 @@ impl<ET: EngineTypes> NodeTrait<ET> for VNode<ET> {
     }
     fn width(&self) -> ET::Dim {
         match self {
-            VNode::Box(b) => b.width(),
+            VNode::Box(b) => b.width() - b.moved_left().unwrap_or_default(),
             VNode::HRule { width, .. } => width.unwrap_or_default(),
             VNode::Custom(n) => n.width(),
             VNode::Leaders(l) => l.width(),
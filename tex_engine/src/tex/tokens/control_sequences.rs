@@ -54,6 +54,10 @@ pub trait CSNameMap<C: Character, CS: CSName<C>, A: Clone>: Clone + Default {
     /// Removes the value associated with the given control sequence name, returning it if any.
     fn remove(&mut self, cs: &CS) -> Option<A>;
     fn into_iter(self) -> impl Iterator<Item = (CS, A)>;
+    /// Iterates over all control sequence names that have a value, in no particular order.
+    fn iter<'a>(&'a self) -> impl Iterator<Item = (CS, &'a A)>
+    where
+        A: 'a;
 }
 
 impl<C: Character, CS: CSName<C>, A: Clone> CSNameMap<C, CS, A> for HMap<CS, A> {
@@ -71,6 +75,12 @@ impl<C: Character, CS: CSName<C>, A: Clone> CSNameMap<C, CS, A> for HMap<CS, A>
     fn into_iter(self) -> impl Iterator<Item = (CS, A)> {
         <HMap<CS, A> as IntoIterator>::into_iter(self)
     }
+    fn iter<'a>(&'a self) -> impl Iterator<Item = (CS, &'a A)>
+    where
+        A: 'a,
+    {
+        HMap::iter(self).map(|(k, v)| (k.clone(), v))
+    }
 }
 
 impl<C: Character> CSName<C> for Ptr<str> {
@@ -134,6 +144,15 @@ impl<C: Character, A: Clone> CSNameMap<C, InternedCSName<C>, A> for CSNameVec<C,
             x.map(|x| ((NonZeroU32::new((i + 1) as u32).unwrap(), PhantomData), x))
         })
     }
+    fn iter<'a>(&'a self) -> impl Iterator<Item = (InternedCSName<C>, &'a A)>
+    where
+        A: 'a,
+    {
+        self.0.iter().enumerate().filter_map(|(i, x)| {
+            x.as_ref()
+                .map(|x| ((NonZeroU32::new((i + 1) as u32).unwrap(), PhantomData), x))
+        })
+    }
 }
 
 /// A control sequence name that has been interned needs to be resolved again to
@@ -8,6 +8,9 @@
 
 use crate::commands::primitives::PrimitiveIdentifier;
 use crate::engine::state::State;
+use crate::engine::utils::approximations::StrictModeViolation;
+use crate::engine::utils::hardening::InvariantViolation;
+use crate::engine::utils::limits::LimitExceeded;
 use crate::engine::utils::memory::MemoryManager;
 use crate::engine::{EngineAux, EngineReferences, EngineTypes};
 use crate::prelude::{Mouth, TeXMode};
@@ -61,6 +64,12 @@ pub enum TeXError<ET: EngineTypes> {
     General(String),
     #[error(transparent)]
     Fmt(#[from] std::fmt::Error),
+    #[error(transparent)]
+    LimitExceeded(#[from] LimitExceeded),
+    #[error(transparent)]
+    Strict(#[from] StrictModeViolation),
+    #[error(transparent)]
+    Internal(#[from] InvariantViolation),
     /*
     FileEndWhileScanningTextOf(ET::Token),
     MissingEndgroup,